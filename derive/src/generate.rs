@@ -14,7 +14,6 @@ const EBNF_INLINE_ATTR: &str = "ebnf_inline";
 
 pub fn generate(ast: DeriveInput) -> TokenStream {
     let grammar = grammar_from_ast(&ast).unwrap();
-    println!("grammar: {}", grammar);
     let name = ast.ident;
     let generics = ast.generics;
 
@@ -54,7 +53,7 @@ fn grammar_from_ast(ast: &DeriveInput) -> Result<Grammar> {
             Lit::Str(s) => {
                 if val.path.is_ident(EBNF_FILE_ATTR) {
                     let root = env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".into());
-                    let path = Path::new(&root).join(&s.value());
+                    let path = Path::new(&root).join(s.value());
                     let data = match fs::read_to_string(path.clone()) {
                         Ok(s) => s,
                         Err(e) => {
@@ -138,7 +137,6 @@ fn generate_rule_function(rule: Production) -> TokenStream {
     let gen_expr = generate_rhs_expression(&rule.rhs);
     let gen = quote! {
         pub fn #name(state: parsegen::State<super::Rule>) -> parsegen::StateResult<parsegen::State<super::Rule>> {
-            println!("name: {:?}", super::Rule::#name);
             state.tokenize(super::Rule::#name, |state| {
                 #gen_expr
             })
@@ -161,6 +159,14 @@ fn generate_rhs_expression(rhs: &Rhs) -> TokenStream {
                 state.match_str(#str)
             }
         }
+        Rhs::Class(class) => {
+            let los = class.ranges.iter().map(|(lo, _)| lo);
+            let his = class.ranges.iter().map(|(_, hi)| hi);
+            let negated = class.negated;
+            quote! {
+                state.match_class(&[ #( (#los, #his) ),* ], #negated)
+            }
+        }
         Rhs::Optional(rhs) => {
             let rhs_expr = generate_rhs_expression(rhs);
             quote! {
@@ -181,10 +187,12 @@ fn generate_rhs_expression(rhs: &Rhs) -> TokenStream {
             }
         }
         Rhs::Concatenation(rhs1, rhs2) => {
+            // Applied as a unit so that a failure part way through doesn't
+            // leave the first half consumed.
             let rhs1_expr = generate_rhs_expression(rhs1);
             let rhs2_expr = generate_rhs_expression(rhs2);
             quote! {
-                #rhs1_expr.and_then(|state| #rhs2_expr)
+                state.apply(|state| #rhs1_expr.and_then(|state| #rhs2_expr))
             }
         }
         Rhs::Group(rhs) => {
@@ -193,7 +201,13 @@ fn generate_rhs_expression(rhs: &Rhs) -> TokenStream {
                 state.apply(|state| #rhs_expr)
             }
         }
-        _ => unimplemented!("exception"),
+        Rhs::Exception(rhs1, rhs2) => {
+            let rhs1_expr = generate_rhs_expression(rhs1);
+            let rhs2_expr = generate_rhs_expression(rhs2);
+            quote! {
+                state.except(|state| #rhs1_expr, |state| #rhs2_expr)
+            }
+        }
     }
 }
 
@@ -232,7 +246,7 @@ mod tests {
         let generics = ast.generics;
         let g: Grammar = "a = 'b' ;".parse().unwrap();
         let ts = generate_impl(name, &generics, g);
-        println!("Generated:\n{}", ts.to_string());
+        println!("Generated:\n{}", ts);
     }
 
     #[test]
@@ -275,4 +289,24 @@ mod tests {
         let ts = generate_rule_enum(&g);
         assert_eq!(ts.to_string(), expected.to_string());
     }
+
+    #[test]
+    fn exception_expression() {
+        let rhs: Rhs = "a - 'b'".parse().unwrap();
+        let expected = quote! {
+            state.except(|state| a(state), |state| state.match_str("b"))
+        };
+        let ts = generate_rhs_expression(&rhs);
+        assert_eq!(ts.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn class_expression() {
+        let rhs: Rhs = "? [^a-z_] ?".parse().unwrap();
+        let expected = quote! {
+            state.match_class(&[('a', 'z'), ('_', '_')], true)
+        };
+        let ts = generate_rhs_expression(&rhs);
+        assert_eq!(ts.to_string(), expected.to_string());
+    }
 }
@@ -0,0 +1,92 @@
+//! Property tests for derived parsers, using sentences generated from the same
+//! grammar the parser was derived from.
+
+use derive::Parser;
+use ebnf::generate::{recognize, Generator, Options};
+use ebnf::Grammar;
+use parsegen::{Parser, Token};
+
+#[derive(Parser)]
+#[ebnf_file = "tests/grammars/expr.ebnf"]
+struct ExprParser;
+
+const EXPR_GRAMMAR: &str = include_str!("grammars/expr.ebnf");
+
+fn generator(grammar: &Grammar, seed: u64) -> Generator<'_> {
+    Generator::new(
+        grammar,
+        Options {
+            seed,
+            max_depth: 6,
+            ..Options::default()
+        },
+    )
+}
+
+/// Parse `input`, returning the tokens only if the root token spans all of
+/// it.
+fn parse_all(input: &str) -> Option<Vec<Token<Rule>>> {
+    let toks: Vec<_> = ExprParser::parse(Rule::expr, input)
+        .ok()?
+        .into_iter()
+        .collect();
+    if toks[0].as_str(input) == input {
+        Some(toks)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn generated_sentences_parse() {
+    let grammar: Grammar = EXPR_GRAMMAR.parse().unwrap();
+    for seed in 0..20 {
+        let mut gen = generator(&grammar, seed);
+        for _ in 0..50 {
+            let input = gen.sentence("expr").unwrap();
+            let toks = match parse_all(&input) {
+                Some(toks) => toks,
+                None => panic!("failed to parse generated sentence {:?}", input),
+            };
+
+            assert_eq!(toks[0].rule(), Rule::expr);
+            // Tokens are in depth first order, so starts never go backwards,
+            // and everything lies within the root.
+            for pair in toks.windows(2) {
+                assert!(pair[0].span.start <= pair[1].span.start, "{:?}", toks);
+            }
+            for tok in &toks {
+                assert!(tok.span.end <= input.len(), "{:?}", tok);
+            }
+        }
+    }
+}
+
+#[test]
+fn keywords_are_not_identifiers() {
+    assert!(parse_all("let x = 1 in x").is_some());
+    assert!(parse_all("letter + inner").is_some());
+    assert!(parse_all("let + 1").is_none());
+    assert!(parse_all("in").is_none());
+}
+
+#[test]
+fn near_misses_agree_with_grammar() {
+    let grammar: Grammar = EXPR_GRAMMAR.parse().unwrap();
+    let mut rejected = 0;
+    for seed in 0..20 {
+        let mut gen = generator(&grammar, seed);
+        for _ in 0..50 {
+            let input = gen.near_miss("expr").unwrap();
+            match parse_all(&input) {
+                Some(_) => assert!(
+                    recognize(&grammar, "expr", &input).unwrap(),
+                    "parser accepted {:?}, which isn't in the grammar",
+                    input
+                ),
+                None => rejected += 1,
+            }
+        }
+    }
+    assert!(rejected > 0);
+}
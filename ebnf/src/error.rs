@@ -4,12 +4,19 @@ use std::fmt::{self, Display};
 #[derive(Debug)]
 pub enum Error {
     ParseError(String),
+    /// The grammar uses a construct that can't be expressed in the target
+    /// format.
+    Unsupported(String),
+    /// A sentence couldn't be generated from the grammar.
+    Generate(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::ParseError(ref s) => write!(f, "failed to parse: {}", s),
+            Error::Unsupported(ref s) => write!(f, "unsupported: {}", s),
+            Error::Generate(ref s) => write!(f, "failed to generate: {}", s),
         }
     }
 }
@@ -0,0 +1,614 @@
+//! Augmented BNF, as described in RFC 5234.
+//!
+//! ```text
+//! ; comment
+//! rule = "a" / %x62-63 *DIGIT
+//! rule =/ [ other ] 2*3( x y )
+//! ```
+//!
+//! Quoted strings in ABNF are case insensitive, and are converted to a
+//! concatenation of character classes where they contain letters. The RFC 7405
+//! `%s` (case sensitive) and `%i` (case insensitive) prefixes are accepted.
+//! Rule names are converted to identifiers by replacing `-` with `_`, and the
+//! other way around when writing.
+//!
+//! Bounded repetitions (`n*m`) are expanded into concatenations of the
+//! repeated element and nested options.
+
+use std::fmt::Write as _;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until, take_while, take_while1},
+    character::complete::{char, digit0, digit1, one_of},
+    combinator::{map, opt, recognize},
+    error::ErrorKind,
+    multi::many0,
+    sequence::{delimited, pair, preceded, tuple},
+    IResult,
+};
+
+use crate::parser::{expect_end, fold_right};
+use crate::{CharClass, Error, Grammar, Identifier, Lhs, Production, Rhs, Terminal};
+
+use super::as_one_or_more;
+
+/// The core rules from appendix B of RFC 5234.
+const CORE_RULES: &str = "\
+ALPHA = %x41-5A / %x61-7A
+BIT = \"0\" / \"1\"
+CHAR = %x01-7F
+CR = %x0D
+CRLF = CR LF
+CTL = %x00-1F / %x7F
+DIGIT = %x30-39
+DQUOTE = %x22
+HEXDIG = DIGIT / \"A\" / \"B\" / \"C\" / \"D\" / \"E\" / \"F\"
+HTAB = %x09
+LF = %x0A
+LWSP = *(WSP / CRLF WSP)
+OCTET = %x00-FF
+SP = %x20
+VCHAR = %x21-7E
+WSP = SP / HTAB
+";
+
+/// Parse a grammar written in ABNF.
+///
+/// Incremental alternatives (`=/`) are merged into the rule they extend.
+pub fn parse(input: &str) -> Result<Grammar, Error> {
+    let (rem, rules) = many0(preceded(blank_lines, rule))(input)?;
+    let (rem, _) = blank_lines(rem)?;
+    expect_end(rem)?;
+
+    let mut grammar = Grammar { rules: Vec::new() };
+    for (production, incremental) in rules {
+        let existing = grammar
+            .rules
+            .iter_mut()
+            .find(|r| r.lhs.0 .0.eq_ignore_ascii_case(&production.lhs.0 .0));
+        match (existing, incremental) {
+            (Some(existing), true) => {
+                let prev = std::mem::replace(&mut existing.rhs, Rhs::Terminal("".into()));
+                existing.rhs = Rhs::Alternation(Box::new(prev), Box::new(production.rhs));
+            }
+            (None, true) => {
+                return Err(Error::ParseError(format!(
+                    "incremental alternative for undefined rule: {}",
+                    production.lhs
+                )))
+            }
+            (Some(_), false) => {
+                return Err(Error::ParseError(format!(
+                    "rule defined more than once: {}",
+                    production.lhs
+                )))
+            }
+            (None, false) => grammar.rules.push(production),
+        }
+    }
+    Ok(grammar)
+}
+
+/// The core rules (`ALPHA`, `DIGIT`, `CRLF`, etc.) that ABNF grammars may
+/// reference without defining.
+pub fn core_rules() -> Grammar {
+    parse(CORE_RULES).expect("core rules to parse")
+}
+
+/// Write a grammar as ABNF.
+///
+/// Exceptions and negated character classes can't be expressed in ABNF.
+pub fn write(grammar: &Grammar) -> Result<String, Error> {
+    let mut out = String::new();
+    for rule in &grammar.rules {
+        out.push_str(&rule_name(&rule.lhs.0)?);
+        out.push_str(" = ");
+        write_rhs(&rule.rhs, &mut out)?;
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn rule_name(iden: &Identifier) -> Result<String, Error> {
+    if iden.0.starts_with('_') {
+        return Err(Error::Unsupported(format!(
+            "ABNF rule names must start with a letter: {}",
+            iden
+        )));
+    }
+    Ok(iden.0.replace('_', "-"))
+}
+
+/// Whitespace within a rule. Line breaks are only allowed when followed by
+/// more whitespace.
+fn c_wsp(input: &str) -> IResult<&str, &str> {
+    recognize(many0(alt((
+        take_while1(|c| c == ' ' || c == '\t'),
+        comment,
+        recognize(pair(
+            alt((tag("\r\n"), tag("\n"))),
+            take_while1(|c| c == ' ' || c == '\t'),
+        )),
+    ))))(input)
+}
+
+fn comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(char(';'), take_while(|c| c != '\n' && c != '\r')))(input)
+}
+
+/// Empty lines, comments, and whitespace between rules.
+fn blank_lines(input: &str) -> IResult<&str, &str> {
+    recognize(many0(alt((
+        take_while1(|c: char| c.is_ascii_whitespace()),
+        comment,
+    ))))(input)
+}
+
+fn rulename(input: &str) -> IResult<&str, Identifier> {
+    let (rem, matched) = recognize(pair(
+        take_while1(|c: char| c.is_ascii_alphabetic()),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '-'),
+    ))(input)?;
+    Ok((rem, Identifier(matched.replace('-', "_"))))
+}
+
+/// Parse a rule, returning whether it's an incremental alternative.
+fn rule(input: &str) -> IResult<&str, (Production, bool)> {
+    let (rem, name) = rulename(input)?;
+    let (rem, defined_as) = delimited(c_wsp, alt((tag("=/"), tag("="))), c_wsp)(rem)?;
+    let (rem, rhs) = alternation(rem)?;
+    let (rem, _) = c_wsp(rem)?;
+    // Rules end at a line break, or the end of input.
+    let rem = if rem.is_empty() {
+        rem
+    } else {
+        alt((tag("\r\n"), tag("\n")))(rem)?.0
+    };
+    Ok((
+        rem,
+        (
+            Production {
+                lhs: Lhs(name),
+                rhs,
+            },
+            defined_as == "=/",
+        ),
+    ))
+}
+
+fn alternation(input: &str) -> IResult<&str, Rhs> {
+    let (mut rem, first) = concatenation(input)?;
+    let mut items = vec![first];
+    while let Ok((r, _)) = delimited(c_wsp, char('/'), c_wsp)(rem) {
+        let (r, item) = concatenation(r)?;
+        items.push(item);
+        rem = r;
+    }
+    Ok((rem, fold_right(items, Rhs::Alternation)))
+}
+
+fn concatenation(input: &str) -> IResult<&str, Rhs> {
+    let (mut rem, first) = repetition(input)?;
+    let mut items = vec![first];
+    loop {
+        let (r, ws) = c_wsp(rem)?;
+        if ws.is_empty() {
+            break;
+        }
+        match repetition(r) {
+            Ok((r, item)) => {
+                items.push(item);
+                rem = r;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((rem, fold_right(items, Rhs::Concatenation)))
+}
+
+fn repetition(input: &str) -> IResult<&str, Rhs> {
+    let (rem, repeat) = opt(alt((
+        map(tuple((digit0, char('*'), digit0)), |(min, _, max)| {
+            (parse_count(min).unwrap_or(0), parse_count(max))
+        }),
+        map(digit1, |n| {
+            let n = parse_count(n);
+            (n.unwrap_or(0), n)
+        }),
+    )))(input)?;
+    let (rem, elem) = element(rem)?;
+    match repeat {
+        None => Ok((rem, elem)),
+        Some((min, max)) => Ok((rem, expand_repetition(elem, min, max))),
+    }
+}
+
+fn parse_count(s: &str) -> Option<usize> {
+    if s.is_empty() {
+        None
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Expand `min*max elem` into concatenations and options.
+fn expand_repetition(elem: Rhs, min: usize, max: Option<usize>) -> Rhs {
+    if min == 1 && max.is_none() {
+        return Rhs::Concatenation(
+            Box::new(elem.clone()),
+            Box::new(Rhs::Repeat(Box::new(elem))),
+        );
+    }
+
+    let mut items: Vec<Rhs> = (0..min).map(|_| elem.clone()).collect();
+    match max {
+        None => items.push(Rhs::Repeat(Box::new(elem))),
+        Some(max) if max > min => {
+            // `*3 x` becomes `[ x , [ x , [ x ] ] ]`.
+            let mut optional = Rhs::Optional(Box::new(elem.clone()));
+            for _ in min + 1..max {
+                optional = Rhs::Optional(Box::new(Rhs::Concatenation(
+                    Box::new(elem.clone()),
+                    Box::new(optional),
+                )));
+            }
+            items.push(optional);
+        }
+        Some(_) => (),
+    }
+    if items.is_empty() {
+        Rhs::Terminal("".into())
+    } else {
+        fold_right(items, Rhs::Concatenation)
+    }
+}
+
+fn element(input: &str) -> IResult<&str, Rhs> {
+    alt((
+        map(rulename, Rhs::Identifier),
+        map(
+            delimited(pair(char('('), c_wsp), alternation, pair(c_wsp, char(')'))),
+            |rhs| Rhs::Group(Box::new(rhs)),
+        ),
+        map(
+            delimited(pair(char('['), c_wsp), alternation, pair(c_wsp, char(']'))),
+            |rhs| Rhs::Optional(Box::new(rhs)),
+        ),
+        char_val,
+        num_val,
+        prose_val,
+    ))(input)
+}
+
+fn quoted(input: &str) -> IResult<&str, &str> {
+    delimited(char('"'), take_while(|c| c != '"'), char('"'))(input)
+}
+
+fn char_val(input: &str) -> IResult<&str, Rhs> {
+    alt((
+        map(preceded(tag("%s"), quoted), |s| Rhs::Terminal(s.into())),
+        map(preceded(tag("%i"), quoted), case_insensitive),
+        map(quoted, case_insensitive),
+    ))(input)
+}
+
+/// Convert a case insensitive string into a concatenation of terminals and
+/// character classes matching either case.
+fn case_insensitive(s: &str) -> Rhs {
+    let mut items = Vec::new();
+    let mut run = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphabetic() {
+            if !run.is_empty() {
+                items.push(Rhs::Terminal(Terminal(std::mem::take(&mut run))));
+            }
+            let (upper, lower) = (c.to_ascii_uppercase(), c.to_ascii_lowercase());
+            items.push(Rhs::Class(CharClass {
+                negated: false,
+                ranges: vec![(upper, upper), (lower, lower)],
+            }));
+        } else {
+            run.push(c);
+        }
+    }
+    if !run.is_empty() || items.is_empty() {
+        items.push(Rhs::Terminal(Terminal(run)));
+    }
+    fold_right(items, Rhs::Concatenation)
+}
+
+fn num_val(input: &str) -> IResult<&str, Rhs> {
+    let (rem, _) = char('%')(input)?;
+    let (rem, base) = one_of("xdbXDB")(rem)?;
+    let (radix, digits): (u32, fn(char) -> bool) = match base.to_ascii_lowercase() {
+        'x' => (16, |c: char| c.is_ascii_hexdigit()),
+        'd' => (10, |c: char| c.is_ascii_digit()),
+        _ => (2, |c: char| c == '0' || c == '1'),
+    };
+    let number = |input| -> IResult<&str, char> {
+        let (rem, digits) = take_while1(digits)(input)?;
+        match u32::from_str_radix(digits, radix)
+            .ok()
+            .and_then(std::char::from_u32)
+        {
+            Some(c) => Ok((rem, c)),
+            None => Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                ErrorKind::Digit,
+            ))),
+        }
+    };
+
+    let (rem, first) = number(rem)?;
+    if let Ok((rem, last)) = preceded(char('-'), number)(rem) {
+        return Ok((
+            rem,
+            Rhs::Class(CharClass {
+                negated: false,
+                ranges: vec![(first, last)],
+            }),
+        ));
+    }
+    let (rem, rest) = many0(preceded(char('.'), number))(rem)?;
+    let s: String = std::iter::once(first).chain(rest).collect();
+    Ok((rem, Rhs::Terminal(Terminal(s))))
+}
+
+/// Prose is a free-form description of a rule, there's nothing sensible to
+/// convert it to.
+fn prose_val(input: &str) -> IResult<&str, Rhs> {
+    delimited(char('<'), take_until(">"), char('>'))(input)?;
+    Err(nom::Err::Failure(nom::error::Error::new(
+        input,
+        ErrorKind::Verify,
+    )))
+}
+
+fn write_operand(rhs: &Rhs, min: u8, out: &mut String) -> Result<(), Error> {
+    let prec = match rhs {
+        _ if as_case_insensitive(rhs).is_some() => 3,
+        // Classes with multiple ranges are written as an alternation.
+        Rhs::Class(class) if class.ranges.len() > 1 => 0,
+        _ if as_one_or_more(rhs).is_some() => 3,
+        _ => rhs.precedence(),
+    };
+    if prec < min {
+        out.push_str("( ");
+        write_rhs(rhs, out)?;
+        out.push_str(" )");
+        Ok(())
+    } else {
+        write_rhs(rhs, out)
+    }
+}
+
+fn write_rhs(rhs: &Rhs, out: &mut String) -> Result<(), Error> {
+    if let Some(item) = as_one_or_more(rhs) {
+        out.push_str("1*");
+        return write_operand(item, 3, out);
+    }
+    if let Some(s) = as_case_insensitive(rhs) {
+        let _ = write!(out, "\"{}\"", s);
+        return Ok(());
+    }
+
+    match rhs {
+        Rhs::Identifier(iden) => out.push_str(&rule_name(iden)?),
+        Rhs::Terminal(term) => write_terminal(&term.0, out),
+        Rhs::Class(class) => {
+            if class.negated {
+                return Err(Error::Unsupported(format!(
+                    "negated character class in ABNF: {}",
+                    class
+                )));
+            }
+            for (i, (lo, hi)) in class.ranges.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(" / ");
+                }
+                if lo == hi {
+                    let _ = write!(out, "%x{:02X}", *lo as u32);
+                } else {
+                    let _ = write!(out, "%x{:02X}-{:02X}", *lo as u32, *hi as u32);
+                }
+            }
+        }
+        Rhs::Optional(inner) => {
+            out.push_str("[ ");
+            write_rhs(inner, out)?;
+            out.push_str(" ]");
+        }
+        Rhs::Repeat(inner) => {
+            out.push('*');
+            write_operand(inner, 3, out)?;
+        }
+        Rhs::Group(inner) => {
+            out.push_str("( ");
+            write_rhs(inner, out)?;
+            out.push_str(" )");
+        }
+        Rhs::Exception(_, _) => {
+            return Err(Error::Unsupported(format!("exception in ABNF: {}", rhs)));
+        }
+        Rhs::Alternation(rhs1, rhs2) => {
+            write_operand(rhs1, 1, out)?;
+            out.push_str(" / ");
+            write_operand(rhs2, 0, out)?;
+        }
+        Rhs::Concatenation(rhs1, rhs2) => {
+            write_operand(rhs1, 2, out)?;
+            out.push(' ');
+            write_operand(rhs2, 1, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// The quoted string a rule was parsed from, if it's the concatenation of
+/// terminals and either-case letter classes that `case_insensitive` makes.
+fn as_case_insensitive(rhs: &Rhs) -> Option<String> {
+    let mut s = String::new();
+    let mut rest = rhs;
+    loop {
+        let (item, next) = match rest {
+            Rhs::Concatenation(item, next) => (&**item, Some(&**next)),
+            item => (item, None),
+        };
+        match item {
+            Rhs::Terminal(term) => s.push_str(&term.0),
+            Rhs::Class(class) => match class.ranges[..] {
+                [(upper, _), (lower, _)] if upper.is_ascii_uppercase() => {
+                    s.push(lower.to_ascii_lowercase())
+                }
+                _ => return None,
+            },
+            _ => return None,
+        }
+        match next {
+            Some(next) => rest = next,
+            None => break,
+        }
+    }
+    let quotable = s.chars().all(|c| (' '..='~').contains(&c) && c != '"');
+    (quotable && case_insensitive(&s) == *rhs).then_some(s)
+}
+
+/// Quoted strings are case insensitive, so anything containing letters is
+/// written as a sequence of character codes.
+fn write_terminal(s: &str, out: &mut String) {
+    let plain = |c: char| (' '..='~').contains(&c) && c != '"' && !c.is_ascii_alphabetic();
+    if s.chars().all(plain) {
+        let _ = write!(out, "\"{}\"", s);
+    } else {
+        let codes: Vec<String> = s.chars().map(|c| format!("{:02X}", c as u32)).collect();
+        let _ = write!(out, "%x{}", codes.join("."));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::tests::sample_grammar;
+
+    #[test]
+    fn lossless_abnf() {
+        let g = sample_grammar();
+        let s = write(&g).unwrap();
+        assert_eq!(parse(&s).unwrap(), g, "written:\n{}", s);
+    }
+
+    #[test]
+    fn lossless_rfc_abnf() {
+        let input = "\
+; From RFC 7230.
+Transfer-Encoding = *( \",\" OWS ) transfer-coding *( OWS \",\" [ OWS transfer-coding ] )
+transfer-coding   = \"chunked\" / \"compress\" / \"deflate\" / \"gzip\" / transfer-extension
+HTTP-version      = HTTP-name \"/\" DIGIT \".\" DIGIT
+HTTP-name         = %x48.54.54.50
+chunk-ext         = *( \";\" chunk-ext-name [ \"=\" chunk-ext-val ] )
+last-chunk        = 1*(\"0\") [ chunk-ext ] CRLF
+OWS               = *( SP / HTAB )
+Host              = uri-host [ \":\" port ] \"x-\" %x58 / \"q=0\" *DIGIT \"e\"
+";
+        let g = parse(input).unwrap();
+        let s = write(&g).unwrap();
+        assert!(s.contains("\"chunked\" / \"compress\""), "written:\n{}", s);
+        assert_eq!(parse(&s).unwrap(), g, "written:\n{}", s);
+        let core = core_rules();
+        assert_eq!(parse(&write(&core).unwrap()).unwrap(), core);
+    }
+
+    #[test]
+    fn parse_rfc_excerpt() {
+        let input = "\
+; From RFC 3986.
+URI-reference = URI / relative-ref
+scheme        = ALPHA *( ALPHA / DIGIT / \"+\" / \"-\" / \".\" )
+dec-octet     = DIGIT                 ; 0-9
+              / %x31-39 DIGIT         ; 10-99
+h16           = 1*4HEXDIG
+IPv4address   = dec-octet \".\" dec-octet \".\" dec-octet \".\" dec-octet
+dec-octet     =/ \"25\" %x30-35
+";
+        let g = parse(input).unwrap();
+        let names: Vec<_> = g.rules.iter().map(|r| r.lhs.to_string()).collect();
+        assert_eq!(
+            names,
+            vec!["URI_reference", "scheme", "dec_octet", "h16", "IPv4address"]
+        );
+
+        // The incremental alternative is appended to the original rule.
+        match &g.rules[2].rhs {
+            Rhs::Alternation(_, last) => assert_eq!(
+                **last,
+                Rhs::Concatenation(
+                    Box::new(Rhs::Terminal("25".into())),
+                    Box::new(Rhs::Class(CharClass {
+                        negated: false,
+                        ranges: vec![('0', '5')],
+                    })),
+                )
+            ),
+            rhs => panic!("unexpected rhs: {:?}", rhs),
+        }
+
+        // 1*4 expands to one required and three optional elements.
+        let hexdig = || Box::new(Rhs::Identifier("HEXDIG".into()));
+        let expected = Rhs::Concatenation(
+            hexdig(),
+            Box::new(Rhs::Optional(Box::new(Rhs::Concatenation(
+                hexdig(),
+                Box::new(Rhs::Optional(Box::new(Rhs::Concatenation(
+                    hexdig(),
+                    Box::new(Rhs::Optional(hexdig())),
+                )))),
+            )))),
+        );
+        assert_eq!(g.rules[3].rhs, expected);
+    }
+
+    #[test]
+    fn case_insensitive_strings() {
+        let g = parse("a = \"x1\"\nb = %s\"x1\"\n").unwrap();
+        assert_eq!(
+            g.rules[0].rhs,
+            Rhs::Concatenation(
+                Box::new(Rhs::Class(CharClass {
+                    negated: false,
+                    ranges: vec![('X', 'X'), ('x', 'x')],
+                })),
+                Box::new(Rhs::Terminal("1".into())),
+            )
+        );
+        assert_eq!(g.rules[1].rhs, Rhs::Terminal("x1".into()));
+    }
+
+    #[test]
+    fn terminals_with_letters_use_codes() {
+        let g = Grammar {
+            rules: vec![Production {
+                lhs: "kw_let".into(),
+                rhs: Rhs::Terminal("let".into()),
+            }],
+        };
+        let s = write(&g).unwrap();
+        assert_eq!(s, "kw-let = %x6C.65.74\n");
+        assert_eq!(parse(&s).unwrap(), g);
+    }
+
+    #[test]
+    fn core_rules_parse() {
+        let g = core_rules();
+        assert_eq!(g.rules.len(), 16);
+        assert!(g.rules.iter().any(|r| r.lhs.to_string() == "DIGIT"));
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        let g: Grammar = "a = b - c ;".parse().unwrap();
+        assert!(matches!(write(&g), Err(Error::Unsupported(_))));
+        assert!(parse("a = <prose description>\n").is_err());
+    }
+}
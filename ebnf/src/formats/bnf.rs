@@ -0,0 +1,331 @@
+//! Plain BNF.
+//!
+//! ```text
+//! <postal-address> ::= <name-part> <street-address> <zip-part>
+//! <opt-suffix-part> ::= "Sr." | "Jr." | ""
+//! ```
+//!
+//! Nonterminals are written in angle brackets, sequences by juxtaposition, and
+//! the empty string as `""`. Rules end where the next `<name> ::=` begins.
+//! Characters in rule names that aren't valid in identifiers are replaced with
+//! underscores.
+//!
+//! BNF has no options, repetitions or groups, so writing a grammar introduces
+//! helper rules for them.
+
+use std::fmt::Write as _;
+
+use nom::{
+    bytes::complete::{tag, take_until},
+    character::complete::{char, multispace0},
+    combinator::map,
+    multi::many0,
+    sequence::{delimited, preceded, tuple},
+    IResult,
+};
+
+use crate::parser::{expect_end, fold_right, terminal};
+use crate::{Error, Grammar, Identifier, Lhs, Production, Rhs, Terminal};
+
+use super::sanitize_name;
+
+/// Parse a grammar written in BNF.
+pub fn parse(input: &str) -> Result<Grammar, Error> {
+    let (rem, rules) = many0(production)(input)?;
+    let (rem, _) = multispace0(rem)?;
+    expect_end(rem)?;
+    Ok(Grammar { rules })
+}
+
+/// Write a grammar as BNF.
+///
+/// Options, repetitions and groups that can't be written inline are moved
+/// into helper rules named after the rule they came from. Character classes
+/// are expanded into alternations of their characters. Exceptions and negated
+/// classes can't be expressed.
+pub fn write(grammar: &Grammar) -> Result<String, Error> {
+    let mut rules = Vec::new();
+    for rule in &grammar.rules {
+        let mut helpers = Helpers {
+            base: rule.lhs.to_string(),
+            count: 0,
+            rules: Vec::new(),
+        };
+        let alts = helpers.alternatives(&rule.rhs)?;
+        rules.push((rule.lhs.to_string(), alts));
+        rules.append(&mut helpers.rules);
+    }
+
+    let mut out = String::new();
+    for (name, alts) in rules {
+        let _ = write!(out, "<{}> ::=", name);
+        for (i, seq) in alts.iter().enumerate() {
+            if i > 0 {
+                out.push_str(" |");
+            }
+            if seq.is_empty() {
+                out.push_str(" \"\"");
+            }
+            for sym in seq {
+                match sym {
+                    Symbol::NonTerminal(name) => {
+                        let _ = write!(out, " <{}>", name);
+                    }
+                    Symbol::Terminal(s) => {
+                        if s.contains('"') && s.contains('\'') {
+                            return Err(Error::Unsupported(format!(
+                                "terminal containing both quote characters: {}",
+                                s
+                            )));
+                        }
+                        let _ = write!(out, " {}", Terminal(s.clone()));
+                    }
+                }
+            }
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn ws(input: &str) -> IResult<&str, &str> {
+    multispace0(input)
+}
+
+fn production_start(input: &str) -> IResult<&str, Identifier> {
+    let (rem, (_, name, _, _)) = tuple((multispace0, nonterminal, multispace0, tag("::=")))(input)?;
+    Ok((rem, name))
+}
+
+fn nonterminal(input: &str) -> IResult<&str, Identifier> {
+    map(delimited(char('<'), take_until(">"), char('>')), |s| {
+        Identifier(sanitize_name(s))
+    })(input)
+}
+
+fn production(input: &str) -> IResult<&str, Production> {
+    let (rem, name) = production_start(input)?;
+    let (mut rem, first) = sequence(rem)?;
+    let mut alts = vec![first];
+    while let Ok((r, _)) = preceded(ws, char('|'))(rem) {
+        let (r, seq) = sequence(r)?;
+        alts.push(seq);
+        rem = r;
+    }
+    Ok((
+        rem,
+        Production {
+            lhs: Lhs(name),
+            rhs: fold_right(alts, Rhs::Alternation),
+        },
+    ))
+}
+
+fn sequence(input: &str) -> IResult<&str, Rhs> {
+    let mut rem = input;
+    let mut items = Vec::new();
+    while production_start(rem).is_err() {
+        let term = preceded(
+            multispace0,
+            nom::branch::alt((
+                map(terminal, Rhs::Terminal),
+                map(nonterminal, Rhs::Identifier),
+            )),
+        )(rem);
+        match term {
+            Ok((r, item)) => {
+                items.push(item);
+                rem = r;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    if items.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Many1,
+        )));
+    }
+    Ok((rem, fold_right(items, Rhs::Concatenation)))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Symbol {
+    Terminal(String),
+    NonTerminal(String),
+}
+
+/// Collects helper rules while converting a single production.
+struct Helpers {
+    base: String,
+    count: usize,
+    rules: Vec<(String, Vec<Vec<Symbol>>)>,
+}
+
+impl Helpers {
+    /// Reserve a helper rule, returning its index and name. Reserving before
+    /// converting the helper's body keeps helpers in the order they appear,
+    /// and allows the body to refer to the helper itself.
+    fn reserve(&mut self, kind: &str) -> (usize, String) {
+        self.count += 1;
+        let name = format!("{}_{}{}", self.base, kind, self.count);
+        self.rules.push((name.clone(), Vec::new()));
+        (self.rules.len() - 1, name)
+    }
+
+    /// Convert an rhs into a list of alternative sequences.
+    fn alternatives(&mut self, rhs: &Rhs) -> Result<Vec<Vec<Symbol>>, Error> {
+        match rhs {
+            Rhs::Alternation(rhs1, rhs2) => {
+                let mut alts = self.alternatives(rhs1)?;
+                alts.append(&mut self.alternatives(rhs2)?);
+                Ok(alts)
+            }
+            Rhs::Group(inner) => self.alternatives(inner),
+            Rhs::Class(class) if !class.negated => {
+                let count: u32 = class
+                    .ranges
+                    .iter()
+                    .map(|&(lo, hi)| (hi as u32).saturating_sub(lo as u32) + 1)
+                    .sum();
+                if count > 256 {
+                    return Err(Error::Unsupported(format!(
+                        "character class too large to expand: {}",
+                        class
+                    )));
+                }
+                Ok(class
+                    .ranges
+                    .iter()
+                    .flat_map(|&(lo, hi)| lo..=hi)
+                    .map(|c| vec![Symbol::Terminal(c.to_string())])
+                    .collect())
+            }
+            Rhs::Class(class) => Err(Error::Unsupported(format!(
+                "negated character class in BNF: {}",
+                class
+            ))),
+            _ => Ok(vec![self.sequence(rhs)?]),
+        }
+    }
+
+    /// Convert an rhs into a single sequence, introducing helper rules for
+    /// anything that would need more than one alternative.
+    fn sequence(&mut self, rhs: &Rhs) -> Result<Vec<Symbol>, Error> {
+        match rhs {
+            Rhs::Identifier(iden) => Ok(vec![Symbol::NonTerminal(iden.0.clone())]),
+            Rhs::Terminal(term) if term.0.is_empty() => Ok(Vec::new()),
+            Rhs::Terminal(term) => Ok(vec![Symbol::Terminal(term.0.clone())]),
+            Rhs::Concatenation(rhs1, rhs2) => {
+                let mut seq = self.sequence(rhs1)?;
+                seq.append(&mut self.sequence(rhs2)?);
+                Ok(seq)
+            }
+            Rhs::Optional(inner) => {
+                let (idx, name) = self.reserve("opt");
+                let mut alts = vec![Vec::new()];
+                alts.append(&mut self.alternatives(inner)?);
+                self.rules[idx].1 = alts;
+                Ok(vec![Symbol::NonTerminal(name)])
+            }
+            Rhs::Repeat(inner) => {
+                let (idx, name) = self.reserve("rep");
+                let mut alts = vec![Vec::new()];
+                for mut alt in self.alternatives(inner)? {
+                    alt.push(Symbol::NonTerminal(name.clone()));
+                    alts.push(alt);
+                }
+                self.rules[idx].1 = alts;
+                Ok(vec![Symbol::NonTerminal(name)])
+            }
+            Rhs::Group(_) | Rhs::Alternation(_, _) | Rhs::Class(_) => {
+                let (idx, name) = self.reserve("group");
+                let mut alts = self.alternatives(rhs)?;
+                if alts.len() == 1 {
+                    // No helper needed after all.
+                    self.rules.remove(idx);
+                    Ok(alts.remove(0))
+                } else {
+                    self.rules[idx].1 = alts;
+                    Ok(vec![Symbol::NonTerminal(name)])
+                }
+            }
+            Rhs::Exception(_, _) => Err(Error::Unsupported(format!("exception in BNF: {}", rhs))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lossless_bnf() {
+        let iden = |s: &str| Box::new(Rhs::Identifier(s.into()));
+        let term = |s: &str| Box::new(Rhs::Terminal(s.into()));
+        let g = Grammar {
+            rules: vec![
+                Production {
+                    lhs: "expr".into(),
+                    rhs: Rhs::Alternation(
+                        Box::new(Rhs::Concatenation(
+                            iden("term"),
+                            Box::new(Rhs::Concatenation(term("+"), iden("expr"))),
+                        )),
+                        iden("term"),
+                    ),
+                },
+                Production {
+                    lhs: "term".into(),
+                    rhs: Rhs::Alternation(term("1"), Box::new(Rhs::Terminal("\"".into()))),
+                },
+            ],
+        };
+        let s = write(&g).unwrap();
+        assert_eq!(parse(&s).unwrap(), g, "written:\n{}", s);
+    }
+
+    #[test]
+    fn parse_postal_address() {
+        let input = r#"
+            <postal-address> ::= <name-part> <street-address> <zip-part>
+            <opt-suffix-part> ::= "Sr." | "Jr." | <roman-numeral> | ""
+        "#;
+        let g = parse(input).unwrap();
+        assert_eq!(g.rules.len(), 2);
+        assert_eq!(g.rules[0].lhs.to_string(), "postal_address");
+        assert_eq!(
+            g.rules[1].rhs.to_string(),
+            r#""Sr." | "Jr." | roman_numeral | """#
+        );
+    }
+
+    #[test]
+    fn helper_rules() {
+        let g: Grammar = "list = '[' , [ item , { ',' , item } ] , ']' ;"
+            .parse()
+            .unwrap();
+        let s = write(&g).unwrap();
+        let expected = "\
+<list> ::= \"[\" <list_opt1> \"]\"
+<list_opt1> ::= \"\" | <item> <list_rep2>
+<list_rep2> ::= \"\" | \",\" <item> <list_rep2>
+";
+        assert_eq!(s, expected);
+        // The output must be valid BNF.
+        assert_eq!(parse(&s).unwrap().rules.len(), 3);
+    }
+
+    #[test]
+    fn classes_expand() {
+        let g: Grammar = "bit = ? [0-1] ? ;".parse().unwrap();
+        assert_eq!(write(&g).unwrap(), "<bit> ::= \"0\" | \"1\"\n");
+    }
+
+    #[test]
+    fn exceptions_unsupported() {
+        let g: Grammar = "a = b - c ;".parse().unwrap();
+        assert!(matches!(write(&g), Err(Error::Unsupported(_))));
+    }
+}
@@ -0,0 +1,182 @@
+//! Conversion between grammars and other grammar notations.
+//!
+//! Each notation lives in its own module exposing `parse` and/or `write`.
+//! Writers return [`Error::Unsupported`] for constructs the target notation
+//! can't express.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use crate::{Error, Grammar};
+
+pub mod abnf;
+pub mod bnf;
+pub mod pest;
+pub mod w3c;
+
+/// A grammar notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// ISO/IEC 14977 EBNF, the native format of this crate.
+    Iso,
+    /// The EBNF notation used by W3C specifications (e.g. XML).
+    W3c,
+    /// Augmented BNF as described in RFC 5234.
+    Abnf,
+    /// Plain BNF.
+    Bnf,
+    /// Grammars for the pest parser generator. Write only.
+    Pest,
+}
+
+impl Format {
+    /// Parse a grammar written in this notation.
+    pub fn parse(self, input: &str) -> Result<Grammar, Error> {
+        match self {
+            Format::Iso => input.parse(),
+            Format::W3c => w3c::parse(input),
+            Format::Abnf => abnf::parse(input),
+            Format::Bnf => bnf::parse(input),
+            Format::Pest => Err(Error::Unsupported("parsing pest grammars".to_owned())),
+        }
+    }
+
+    /// Write a grammar in this notation.
+    pub fn write(self, grammar: &Grammar) -> Result<String, Error> {
+        match self {
+            Format::Iso => Ok(grammar.to_string()),
+            Format::W3c => w3c::write(grammar),
+            Format::Abnf => abnf::write(grammar),
+            Format::Bnf => bnf::write(grammar),
+            Format::Pest => pest::write(grammar),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Format::Iso => "iso",
+            Format::W3c => "w3c",
+            Format::Abnf => "abnf",
+            Format::Bnf => "bnf",
+            Format::Pest => "pest",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "iso" | "ebnf" => Ok(Format::Iso),
+            "w3c" => Ok(Format::W3c),
+            "abnf" => Ok(Format::Abnf),
+            "bnf" => Ok(Format::Bnf),
+            "pest" => Ok(Format::Pest),
+            _ => Err(Error::ParseError(format!("unknown grammar format: {}", s))),
+        }
+    }
+}
+
+/// Replace characters that aren't valid in identifiers with underscores.
+pub(crate) fn sanitize_name(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Check if the rhs is `x , { x }`, which several notations can write as a
+/// single "one or more" operator.
+pub(crate) fn as_one_or_more(rhs: &crate::Rhs) -> Option<&crate::Rhs> {
+    match rhs {
+        crate::Rhs::Concatenation(first, rest) => match rest.as_ref() {
+            crate::Rhs::Repeat(repeated) if repeated == first => Some(first),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{CharClass, Production, Rhs};
+
+    /// A grammar using only constructs every importable format supports,
+    /// with groups wherever a format would need parentheses.
+    pub fn sample_grammar() -> Grammar {
+        let iden = |s: &str| Box::new(Rhs::Identifier(s.into()));
+        let term = |s: &str| Box::new(Rhs::Terminal(s.into()));
+        Grammar {
+            rules: vec![
+                Production {
+                    lhs: "list".into(),
+                    rhs: Rhs::Concatenation(
+                        term("["),
+                        Box::new(Rhs::Concatenation(
+                            Box::new(Rhs::Optional(Box::new(Rhs::Group(Box::new(
+                                Rhs::Concatenation(
+                                    iden("item"),
+                                    Box::new(Rhs::Repeat(Box::new(Rhs::Group(Box::new(
+                                        Rhs::Concatenation(term(","), iden("item")),
+                                    ))))),
+                                ),
+                            ))))),
+                            term("]"),
+                        )),
+                    ),
+                },
+                Production {
+                    lhs: "item".into(),
+                    rhs: Rhs::Alternation(
+                        iden("list"),
+                        Box::new(Rhs::Alternation(
+                            iden("number"),
+                            Box::new(Rhs::Group(Box::new(Rhs::Alternation(
+                                term("true"),
+                                term("false"),
+                            )))),
+                        )),
+                    ),
+                },
+                Production {
+                    lhs: "number".into(),
+                    rhs: Rhs::Concatenation(iden("digit"), Box::new(Rhs::Repeat(iden("digit")))),
+                },
+                Production {
+                    lhs: "digit".into(),
+                    rhs: Rhs::Class(CharClass {
+                        negated: false,
+                        ranges: vec![('0', '9')],
+                    }),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn format_from_str() {
+        for format in &[
+            Format::Iso,
+            Format::W3c,
+            Format::Abnf,
+            Format::Bnf,
+            Format::Pest,
+        ] {
+            let parsed: Format = format.to_string().parse().unwrap();
+            assert_eq!(*format, parsed);
+        }
+        assert!("yacc".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn lossless_iso() {
+        let g = sample_grammar();
+        let s = Format::Iso.write(&g).unwrap();
+        assert_eq!(Format::Iso.parse(&s).unwrap(), g, "written:\n{}", s);
+    }
+}
@@ -0,0 +1,227 @@
+//! Grammars for the [pest](https://pest.rs) parser generator.
+//!
+//! ```text
+//! list = { "[" ~ (item ~ ("," ~ item)*)? ~ "]" }
+//! digit = { '0'..'9' }
+//! ```
+//!
+//! pest grammars are PEGs, so alternation is ordered and exceptions are
+//! written as a negative lookahead (`a - b` becomes `!b ~ a`). This is exact
+//! when `b` can only match strings that `a` could match in full, which covers
+//! the common "identifier minus keyword" pattern.
+//!
+//! Rule names that clash with Rust keywords or pest's builtin rules get an
+//! underscore appended.
+
+use std::fmt::Write as _;
+
+use crate::{CharClass, Error, Grammar, Rhs};
+
+use super::as_one_or_more;
+
+/// Rust keywords, since pest generates an enum variant per rule.
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+const PEST_BUILTINS: &[&str] = &[
+    "ANY",
+    "SOI",
+    "EOI",
+    "PUSH",
+    "POP",
+    "POP_ALL",
+    "PEEK",
+    "PEEK_ALL",
+    "DROP",
+    "WHITESPACE",
+    "COMMENT",
+    "ASCII",
+    "ASCII_DIGIT",
+    "ASCII_ALPHA",
+    "ASCII_ALPHANUMERIC",
+    "NEWLINE",
+];
+
+/// Write a grammar as a pest grammar.
+pub fn write(grammar: &Grammar) -> Result<String, Error> {
+    let mut out = String::new();
+    for rule in &grammar.rules {
+        let _ = write!(out, "{} = {{ ", rule_name(&rule.lhs.0 .0));
+        write_rhs(&rule.rhs, &mut out);
+        out.push_str(" }\n");
+    }
+    Ok(out)
+}
+
+fn rule_name(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) || PEST_BUILTINS.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+fn write_operand(rhs: &Rhs, min: u8, out: &mut String) {
+    let prec = match rhs {
+        Rhs::Class(class) if class.ranges.len() > 1 || class.negated => 0,
+        // Exceptions are written as a parenthesized lookahead.
+        Rhs::Exception(_, _) => 3,
+        _ if as_one_or_more(rhs).is_some() => 3,
+        _ => rhs.precedence(),
+    };
+    if prec < min {
+        out.push('(');
+        write_rhs(rhs, out);
+        out.push(')');
+    } else {
+        write_rhs(rhs, out);
+    }
+}
+
+fn write_rhs(rhs: &Rhs, out: &mut String) {
+    if let Some(item) = as_one_or_more(rhs) {
+        write_operand(item, 3, out);
+        out.push('+');
+        return;
+    }
+
+    match rhs {
+        Rhs::Identifier(iden) => out.push_str(&rule_name(&iden.0)),
+        Rhs::Terminal(term) => write_string(&term.0, out),
+        Rhs::Class(class) => write_class(class, out),
+        Rhs::Optional(inner) => {
+            write_operand(inner, 3, out);
+            out.push('?');
+        }
+        Rhs::Repeat(inner) => {
+            write_operand(inner, 3, out);
+            out.push('*');
+        }
+        Rhs::Group(inner) => {
+            out.push('(');
+            write_rhs(inner, out);
+            out.push(')');
+        }
+        Rhs::Exception(rhs1, rhs2) => {
+            out.push_str("(!");
+            write_operand(rhs2, 3, out);
+            out.push_str(" ~ ");
+            write_operand(rhs1, 2, out);
+            out.push(')');
+        }
+        Rhs::Alternation(rhs1, rhs2) => {
+            write_operand(rhs1, 1, out);
+            out.push_str(" | ");
+            write_operand(rhs2, 0, out);
+        }
+        Rhs::Concatenation(rhs1, rhs2) => {
+            write_operand(rhs1, 2, out);
+            out.push_str(" ~ ");
+            write_operand(rhs2, 1, out);
+        }
+    }
+}
+
+fn write_class(class: &CharClass, out: &mut String) {
+    let ranges: Vec<String> = class
+        .ranges
+        .iter()
+        .map(|&(lo, hi)| {
+            if lo == hi {
+                let mut s = String::new();
+                write_string(&lo.to_string(), &mut s);
+                s
+            } else {
+                format!("{}..{}", char_literal(lo), char_literal(hi))
+            }
+        })
+        .collect();
+    let alts = if ranges.is_empty() {
+        // An empty class never matches.
+        "!ANY ~ ANY".to_owned()
+    } else {
+        ranges.join(" | ")
+    };
+    if class.negated {
+        let _ = write!(out, "!({}) ~ ANY", alts);
+    } else {
+        out.push_str(&alts);
+    }
+}
+
+fn escape(c: char, quote: char, out: &mut String) {
+    match c {
+        '\\' => out.push_str("\\\\"),
+        '\n' => out.push_str("\\n"),
+        '\r' => out.push_str("\\r"),
+        '\t' => out.push_str("\\t"),
+        c if c == quote => {
+            out.push('\\');
+            out.push(c);
+        }
+        c if c.is_control() => {
+            let _ = write!(out, "\\u{{{:X}}}", c as u32);
+        }
+        c => out.push(c),
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        escape(c, '"', out);
+    }
+    out.push('"');
+}
+
+fn char_literal(c: char) -> String {
+    let mut s = String::from("'");
+    escape(c, '\'', &mut s);
+    s.push('\'');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::tests::sample_grammar;
+
+    #[test]
+    fn write_sample() {
+        let s = write(&sample_grammar()).unwrap();
+        let expected = r#"list = { "[" ~ (item ~ ("," ~ item)*)? ~ "]" }
+item = { list | number | ("true" | "false") }
+number = { digit+ }
+digit = { '0'..'9' }
+"#;
+        assert_eq!(s, expected);
+    }
+
+    #[test]
+    fn exceptions_and_classes() {
+        let g: Grammar = r#"
+            ident = ( letter , { letter } ) - "let" ;
+            letter = ? [a-zA-Z_] ? ;
+            not_quote = ? [^#x22#xA] ? ;
+        "#
+        .parse()
+        .unwrap();
+        let s = write(&g).unwrap();
+        let expected = r#"ident = { (!"let" ~ (letter+)) }
+letter = { 'a'..'z' | 'A'..'Z' | "_" }
+not_quote = { !("\"" | "\n") ~ ANY }
+"#;
+        assert_eq!(s, expected);
+    }
+
+    #[test]
+    fn reserved_names() {
+        let g: Grammar = "type = fn ; fn = 'x' ;".parse().unwrap();
+        let s = write(&g).unwrap();
+        assert_eq!(s, "type_ = { fn_ }\nfn_ = { \"x\" }\n");
+    }
+}
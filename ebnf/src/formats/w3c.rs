@@ -0,0 +1,328 @@
+//! The EBNF notation used by W3C specifications.
+//!
+//! ```text
+//! [1] document ::= prolog element Misc*
+//! Char ::= #x9 | #xA | [#x20-#xD7FF]
+//! ```
+//!
+//! Rules are written `name ::= expression`, without a terminator. A rule may
+//! optionally be preceded by a bracketed production number, which is ignored.
+//! Sequences are written by juxtaposition, and `?`, `*` and `+` are postfix
+//! operators. Comments are written `/* ... */`.
+
+use std::fmt::Write as _;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until},
+    character::complete::{char, digit1, multispace1},
+    combinator::{map, opt, recognize},
+    multi::many0,
+    sequence::{delimited, pair, preceded, tuple},
+    IResult,
+};
+
+use crate::parser::{char_class, expect_end, fold_right, hex_char, identifier, terminal};
+use crate::{Error, Grammar, Lhs, Production, Rhs, Terminal};
+
+use super::as_one_or_more;
+
+/// Parse a grammar written in W3C EBNF.
+pub fn parse(input: &str) -> Result<Grammar, Error> {
+    let (rem, rules) = many0(production)(input)?;
+    expect_end_w3c(rem)?;
+    Ok(Grammar { rules })
+}
+
+/// Write a grammar as W3C EBNF.
+pub fn write(grammar: &Grammar) -> Result<String, Error> {
+    let mut out = String::new();
+    for rule in &grammar.rules {
+        let _ = write!(out, "{} ::= ", rule.lhs);
+        write_rhs(&rule.rhs, &mut out)?;
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn expect_end_w3c(rem: &str) -> Result<(), Error> {
+    let (rem, _) = ws(rem)?;
+    expect_end(rem)
+}
+
+fn ws(input: &str) -> IResult<&str, &str> {
+    recognize(many0(alt((
+        multispace1,
+        delimited(tag("/*"), take_until("*/"), tag("*/")),
+    ))))(input)
+}
+
+fn label(input: &str) -> IResult<&str, &str> {
+    delimited(char('['), digit1, char(']'))(input)
+}
+
+/// The start of a production, used to find where the previous one ends.
+fn production_start(input: &str) -> IResult<&str, Lhs> {
+    let (rem, (_, _, _, name, _, _)) =
+        tuple((ws, opt(label), ws, identifier, ws, tag("::=")))(input)?;
+    Ok((rem, Lhs(name)))
+}
+
+fn production(input: &str) -> IResult<&str, Production> {
+    let (rem, lhs) = production_start(input)?;
+    let (rem, rhs) = choice(rem)?;
+    Ok((rem, Production { lhs, rhs }))
+}
+
+fn choice(input: &str) -> IResult<&str, Rhs> {
+    let (mut rem, first) = sequence(input)?;
+    let mut items = vec![first];
+    while let Ok((r, _)) = preceded(ws, char('|'))(rem) {
+        let (r, item) = sequence(r)?;
+        items.push(item);
+        rem = r;
+    }
+    Ok((rem, fold_right(items, Rhs::Alternation)))
+}
+
+fn sequence(input: &str) -> IResult<&str, Rhs> {
+    let (mut rem, first) = difference(input)?;
+    let mut items = vec![first];
+    // Items continue until something that isn't an item, or the start of the
+    // next production.
+    while production_start(rem).is_err() {
+        match difference(rem) {
+            Ok((r, item)) => {
+                items.push(item);
+                rem = r;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((rem, fold_right(items, Rhs::Concatenation)))
+}
+
+fn difference(input: &str) -> IResult<&str, Rhs> {
+    let (rem, item1) = postfix(input)?;
+    match preceded(pair(ws, char('-')), postfix)(rem) {
+        Ok((rem, item2)) => Ok((rem, Rhs::Exception(Box::new(item1), Box::new(item2)))),
+        Err(nom::Err::Error(_)) => Ok((rem, item1)),
+        Err(e) => Err(e),
+    }
+}
+
+fn postfix(input: &str) -> IResult<&str, Rhs> {
+    let (mut rem, mut item) = primary(input)?;
+    while let Ok((r, op)) = preceded(ws, alt((char('?'), char('*'), char('+'))))(rem) {
+        item = match op {
+            '?' => Rhs::Optional(Box::new(item)),
+            '*' => Rhs::Repeat(Box::new(item)),
+            _ => Rhs::Concatenation(
+                Box::new(item.clone()),
+                Box::new(Rhs::Repeat(Box::new(item))),
+            ),
+        };
+        rem = r;
+    }
+    Ok((rem, item))
+}
+
+fn primary(input: &str) -> IResult<&str, Rhs> {
+    preceded(
+        ws,
+        alt((
+            map(
+                delimited(char('('), choice, preceded(ws, char(')'))),
+                |rhs| Rhs::Group(Box::new(rhs)),
+            ),
+            map(terminal, Rhs::Terminal),
+            map(char_class, Rhs::Class),
+            map(hex_char, |c| Rhs::Terminal(Terminal(c.to_string()))),
+            map(identifier, Rhs::Identifier),
+        )),
+    )(input)
+}
+
+fn write_operand(rhs: &Rhs, min: u8, out: &mut String) -> Result<(), Error> {
+    let prec = if as_one_or_more(rhs).is_some() {
+        3
+    } else {
+        rhs.precedence()
+    };
+    if prec < min {
+        out.push_str("( ");
+        write_rhs(rhs, out)?;
+        out.push_str(" )");
+        Ok(())
+    } else {
+        write_rhs(rhs, out)
+    }
+}
+
+fn write_rhs(rhs: &Rhs, out: &mut String) -> Result<(), Error> {
+    if let Some(item) = as_one_or_more(rhs) {
+        write_operand(item, 3, out)?;
+        out.push('+');
+        return Ok(());
+    }
+
+    match rhs {
+        Rhs::Identifier(iden) => out.push_str(&iden.0),
+        Rhs::Terminal(term) => write_terminal(&term.0, out)?,
+        Rhs::Class(class) => {
+            let _ = write!(out, "{}", class);
+        }
+        Rhs::Optional(inner) => {
+            write_operand(inner, 3, out)?;
+            out.push('?');
+        }
+        Rhs::Repeat(inner) => {
+            write_operand(inner, 3, out)?;
+            out.push('*');
+        }
+        Rhs::Group(inner) => {
+            out.push_str("( ");
+            write_rhs(inner, out)?;
+            out.push_str(" )");
+        }
+        Rhs::Exception(rhs1, rhs2) => {
+            write_operand(rhs1, 3, out)?;
+            out.push_str(" - ");
+            write_operand(rhs2, 3, out)?;
+        }
+        Rhs::Alternation(rhs1, rhs2) => {
+            write_operand(rhs1, 1, out)?;
+            out.push_str(" | ");
+            write_operand(rhs2, 0, out)?;
+        }
+        Rhs::Concatenation(rhs1, rhs2) => {
+            write_operand(rhs1, 2, out)?;
+            out.push(' ');
+            write_operand(rhs2, 1, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Strings can't contain escapes, so characters that can't be written in a
+/// quoted string are written using `#x` notation.
+fn write_terminal(s: &str, out: &mut String) -> Result<(), Error> {
+    let printable = |c: char| !c.is_control();
+    if s.chars().all(printable) && !(s.contains('"') && s.contains('\'')) {
+        let _ = write!(out, "{}", Terminal(s.to_owned()));
+        return Ok(());
+    }
+    if s.chars().count() == 1 {
+        let c = s.chars().next().unwrap();
+        let _ = write!(out, "#x{:X}", c as u32);
+        return Ok(());
+    }
+    Err(Error::Unsupported(format!(
+        "terminal '{}' can't be written as a single W3C string",
+        s.escape_debug()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::tests::sample_grammar;
+    use crate::CharClass;
+
+    #[test]
+    fn lossless_w3c() {
+        let g = sample_grammar();
+        let s = write(&g).unwrap();
+        assert_eq!(parse(&s).unwrap(), g, "written:\n{}", s);
+    }
+
+    #[test]
+    fn parse_xml_spec_excerpt() {
+        let input = r#"
+            /* From the XML 1.0 specification. */
+            [1] document ::= prolog element Misc*
+            [2] Char ::= #x9 | #xA | #xD | [#x20-#xD7FF]
+            [3] S ::= (#x20 | #x9 | #xD | #xA)+
+            [10] AttValue ::= '"' ([^<&"] | Reference)* '"'
+            Name ::= NameStartChar (NameChar)*
+            PITarget ::= Name - (('X' | 'x') ('M' | 'm') ('L' | 'l'))
+        "#;
+        let g = parse(input).unwrap();
+        let names: Vec<_> = g.rules.iter().map(|r| r.lhs.to_string()).collect();
+        assert_eq!(
+            names,
+            vec!["document", "Char", "S", "AttValue", "Name", "PITarget"]
+        );
+
+        assert_eq!(
+            g.rules[0].rhs,
+            Rhs::Concatenation(
+                Box::new(Rhs::Identifier("prolog".into())),
+                Box::new(Rhs::Concatenation(
+                    Box::new(Rhs::Identifier("element".into())),
+                    Box::new(Rhs::Repeat(Box::new(Rhs::Identifier("Misc".into())))),
+                )),
+            )
+        );
+
+        match &g.rules[1].rhs {
+            Rhs::Alternation(tab, _) => assert_eq!(**tab, Rhs::Terminal("\t".into())),
+            rhs => panic!("unexpected rhs: {:?}", rhs),
+        }
+
+        match &g.rules[3].rhs {
+            Rhs::Concatenation(_, rest) => match rest.as_ref() {
+                Rhs::Concatenation(repeat, _) => match repeat.as_ref() {
+                    Rhs::Repeat(group) => match group.as_ref() {
+                        Rhs::Group(inner) => match inner.as_ref() {
+                            Rhs::Alternation(class, _) => assert_eq!(
+                                **class,
+                                Rhs::Class(CharClass {
+                                    negated: true,
+                                    ranges: vec![('<', '<'), ('&', '&'), ('"', '"')],
+                                })
+                            ),
+                            rhs => panic!("unexpected rhs: {:?}", rhs),
+                        },
+                        rhs => panic!("unexpected rhs: {:?}", rhs),
+                    },
+                    rhs => panic!("unexpected rhs: {:?}", rhs),
+                },
+                rhs => panic!("unexpected rhs: {:?}", rhs),
+            },
+            rhs => panic!("unexpected rhs: {:?}", rhs),
+        }
+
+        assert!(matches!(g.rules[5].rhs, Rhs::Exception(_, _)));
+    }
+
+    #[test]
+    fn one_or_more() {
+        let g = parse("a ::= b+").unwrap();
+        let expected = Rhs::Concatenation(
+            Box::new(Rhs::Identifier("b".into())),
+            Box::new(Rhs::Repeat(Box::new(Rhs::Identifier("b".into())))),
+        );
+        assert_eq!(g.rules[0].rhs, expected);
+        assert_eq!(write(&g).unwrap(), "a ::= b+\n");
+    }
+
+    #[test]
+    fn control_characters() {
+        let g = Grammar {
+            rules: vec![Production {
+                lhs: "nl".into(),
+                rhs: Rhs::Terminal("\n".into()),
+            }],
+        };
+        let s = write(&g).unwrap();
+        assert_eq!(s, "nl ::= #xA\n");
+        assert_eq!(parse(&s).unwrap(), g);
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        assert!(parse("a ::= b\n)").is_err());
+    }
+}
@@ -0,0 +1,674 @@
+//! Random sentences from grammars.
+//!
+//! A `Generator` produces random strings matching a rule, which makes it easy
+//! to property test parsers built from a grammar: every generated sentence
+//! should parse. `Generator::mutate` produces near misses of those sentences,
+//! which a parser should reject or accept without falling over.
+//!
+//! ```
+//! use ebnf::generate::{recognize, Generator, Options};
+//! use ebnf::Grammar;
+//!
+//! let grammar: Grammar = "
+//!     list = '[' , [ digit , { ',' , digit } ] , ']' ;
+//!     digit = ? [0-9] ? ;
+//! "
+//! .parse()
+//! .unwrap();
+//!
+//! let mut gen = Generator::new(&grammar, Options::default());
+//! for _ in 0..10 {
+//!     let s = gen.sentence("list").unwrap();
+//!     assert!(recognize(&grammar, "list", &s).unwrap());
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{CharClass, Error, Grammar, Rhs};
+
+/// Depth of rules that can never produce a finite sentence.
+const UNPRODUCTIVE: usize = usize::MAX;
+
+/// How many times the lhs of an exception is regenerated before giving up.
+const EXCEPTION_ATTEMPTS: usize = 32;
+
+/// Controls the sentences produced by a `Generator`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Seed for the random number generator. The same grammar, options and
+    /// seed always produce the same sentences.
+    pub seed: u64,
+    /// How deeply rules may nest before the generator starts taking the
+    /// shortest way out. Rules that need deeper nesting to produce anything at
+    /// all still get it.
+    pub max_depth: usize,
+    /// Maximum number of times to repeat the contents of `{ ... }`.
+    pub max_repeat: usize,
+    /// Relative weights for the top level alternatives of a rule, in the
+    /// order they're written. Alternatives without a weight get a weight of 1.
+    /// Alternatives with a weight of 0 are only picked if nothing else can be.
+    pub weights: HashMap<String, Vec<u32>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            seed: 0,
+            max_depth: 12,
+            max_repeat: 3,
+            weights: HashMap::new(),
+        }
+    }
+}
+
+/// Generates random sentences from a grammar.
+pub struct Generator<'g> {
+    rules: HashMap<&'g str, &'g Rhs>,
+    /// The least rule nesting needed to finish each rule.
+    depths: HashMap<&'g str, usize>,
+    options: Options,
+    rng: Rng,
+}
+
+impl<'g> Generator<'g> {
+    pub fn new(grammar: &'g Grammar, options: Options) -> Self {
+        let rules = rule_map(grammar);
+        let depths = min_depths(&rules);
+        let rng = Rng::new(options.seed);
+        Generator {
+            rules,
+            depths,
+            options,
+            rng,
+        }
+    }
+
+    /// Generate a random sentence matching `rule`.
+    pub fn sentence(&mut self, rule: &str) -> Result<String, Error> {
+        let mut out = String::new();
+        self.expand_rule(rule, 0, &mut out)?;
+        Ok(out)
+    }
+
+    /// Generate a random sentence matching `rule`, then mutate it.
+    pub fn near_miss(&mut self, rule: &str) -> Result<String, Error> {
+        let sentence = self.sentence(rule)?;
+        Ok(self.mutate(&sentence))
+    }
+
+    /// Make a small random edit to a sentence: deleting, inserting, replacing,
+    /// duplicating or swapping characters, or truncating it.
+    ///
+    /// The result is usually, but not always, no longer a valid sentence.
+    pub fn mutate(&mut self, sentence: &str) -> String {
+        let mut chars: Vec<char> = sentence.chars().collect();
+        let len = chars.len() as u64;
+        let op = if chars.is_empty() {
+            1
+        } else {
+            self.rng.below(6)
+        };
+        match op {
+            0 => {
+                chars.remove(self.rng.below(len) as usize);
+            }
+            1 => {
+                let c = self.mutation_char(&chars);
+                chars.insert(self.rng.below(len + 1) as usize, c);
+            }
+            2 => {
+                let c = self.mutation_char(&chars);
+                chars[self.rng.below(len) as usize] = c;
+            }
+            3 => {
+                let start = self.rng.below(len) as usize;
+                let max = (chars.len() - start).min(4) as u64;
+                let end = start + 1 + self.rng.below(max) as usize;
+                let dup: Vec<char> = chars[start..end].to_vec();
+                chars.splice(end..end, dup);
+            }
+            4 if chars.len() > 1 => {
+                let idx = self.rng.below(len - 1) as usize;
+                chars.swap(idx, idx + 1);
+            }
+            _ => chars.truncate(self.rng.below(len) as usize),
+        }
+        chars.into_iter().collect()
+    }
+
+    /// Pick a character to insert, preferring characters already in the
+    /// sentence since they're more likely to be meaningful to the grammar.
+    fn mutation_char(&mut self, chars: &[char]) -> char {
+        if !chars.is_empty() && self.rng.below(2) == 0 {
+            chars[self.rng.below(chars.len() as u64) as usize]
+        } else {
+            printable(self.rng.below(95))
+        }
+    }
+
+    fn expand_rule(&mut self, name: &str, depth: usize, out: &mut String) -> Result<(), Error> {
+        let (name, rhs) = match self.rules.get_key_value(name) {
+            Some((&name, &rhs)) => (name, rhs),
+            None => return Err(undefined(name)),
+        };
+        let mut alts = Vec::new();
+        alternatives(rhs, &mut alts);
+        let weights = self.options.weights.get(name).cloned().unwrap_or_default();
+        let alt = self.choose(name, &alts, &weights, depth)?;
+        self.expand(alt, depth + 1, out)
+    }
+
+    /// Choose an alternative, preferring weighted alternatives that fit within
+    /// the remaining depth.
+    fn choose(
+        &mut self,
+        context: &str,
+        alts: &[&'g Rhs],
+        weights: &[u32],
+        depth: usize,
+    ) -> Result<&'g Rhs, Error> {
+        let remaining = self.options.max_depth.saturating_sub(depth);
+        let depths: Vec<usize> = alts.iter().map(|alt| self.depth(alt)).collect();
+        let weight = |i: usize| weights.get(i).copied().unwrap_or(1);
+
+        let mut candidates: Vec<(usize, u32)> = (0..alts.len())
+            .filter(|&i| depths[i] <= remaining && weight(i) > 0)
+            .map(|i| (i, weight(i)))
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..alts.len())
+                .filter(|&i| depths[i] <= remaining)
+                .map(|i| (i, 1))
+                .collect();
+        }
+        if candidates.is_empty() {
+            let min = depths.iter().copied().min().unwrap_or(UNPRODUCTIVE);
+            if min == UNPRODUCTIVE {
+                return Err(Error::Generate(format!(
+                    "'{}' can't produce a finite sentence",
+                    context
+                )));
+            }
+            candidates = (0..alts.len())
+                .filter(|&i| depths[i] == min)
+                .map(|i| (i, 1))
+                .collect();
+        }
+
+        let total: u64 = candidates.iter().map(|&(_, w)| w as u64).sum();
+        let mut pick = self.rng.below(total);
+        for (i, w) in candidates {
+            if pick < w as u64 {
+                return Ok(alts[i]);
+            }
+            pick -= w as u64;
+        }
+        unreachable!("pick is less than the total weight")
+    }
+
+    fn expand(&mut self, rhs: &'g Rhs, depth: usize, out: &mut String) -> Result<(), Error> {
+        let remaining = self.options.max_depth.saturating_sub(depth);
+        match rhs {
+            Rhs::Identifier(iden) => self.expand_rule(&iden.0, depth, out)?,
+            Rhs::Terminal(term) => out.push_str(&term.0),
+            Rhs::Class(class) => out.push(self.class_char(class)?),
+            Rhs::Optional(inner) => {
+                if self.depth(inner) <= remaining && self.rng.below(2) == 0 {
+                    self.expand(inner, depth, out)?;
+                }
+            }
+            Rhs::Repeat(inner) => {
+                if self.depth(inner) <= remaining {
+                    let count = self.rng.below(self.options.max_repeat as u64 + 1);
+                    for _ in 0..count {
+                        self.expand(inner, depth, out)?;
+                    }
+                }
+            }
+            Rhs::Group(inner) => self.expand(inner, depth, out)?,
+            Rhs::Alternation(_, _) => {
+                let mut alts = Vec::new();
+                alternatives(rhs, &mut alts);
+                let alt = self.choose(&rhs.to_string(), &alts, &[], depth)?;
+                self.expand(alt, depth, out)?;
+            }
+            Rhs::Concatenation(rhs1, rhs2) => {
+                self.expand(rhs1, depth, out)?;
+                self.expand(rhs2, depth, out)?;
+            }
+            Rhs::Exception(rhs1, rhs2) => {
+                for _ in 0..EXCEPTION_ATTEMPTS {
+                    let mut s = String::new();
+                    self.expand(rhs1, depth, &mut s)?;
+                    let mut matcher = Matcher::new(&self.rules, &s);
+                    if !matcher.ends(rhs2, 0).contains(&s.len()) {
+                        out.push_str(&s);
+                        return Ok(());
+                    }
+                }
+                return Err(Error::Generate(format!(
+                    "couldn't generate a sentence for '{}'",
+                    rhs
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn class_char(&mut self, class: &CharClass) -> Result<char, Error> {
+        if class.negated {
+            for _ in 0..64 {
+                let c = printable(self.rng.below(95));
+                if class.contains(c) {
+                    return Ok(c);
+                }
+            }
+            return (0..=0x10FFFF)
+                .filter_map(std::char::from_u32)
+                .find(|&c| class.contains(c))
+                .ok_or_else(|| Error::Generate(format!("empty class '{}'", class)));
+        }
+
+        let size = |&(lo, hi): &(char, char)| (hi as u64 + 1).saturating_sub(lo as u64);
+        let total: u64 = class.ranges.iter().map(size).sum();
+        if total == 0 {
+            return Err(Error::Generate(format!("empty class '{}'", class)));
+        }
+        let mut pick = self.rng.below(total);
+        for range in &class.ranges {
+            if pick < size(range) {
+                // Ranges spanning surrogates can land on invalid chars.
+                let c = std::char::from_u32(range.0 as u32 + pick as u32);
+                return Ok(c.unwrap_or(range.0));
+            }
+            pick -= size(range);
+        }
+        unreachable!("pick is less than the class size")
+    }
+
+    fn depth(&self, rhs: &Rhs) -> usize {
+        depth(rhs, &self.depths)
+    }
+}
+
+/// Check if `input` is a sentence of `rule`.
+///
+/// Unlike a derived parser, this considers every way the grammar could match
+/// the input, so it can be used to check a parser's answers. Left recursive
+/// rules aren't supported.
+pub fn recognize(grammar: &Grammar, rule: &str, input: &str) -> Result<bool, Error> {
+    let rules = rule_map(grammar);
+    let name = match rules.get_key_value(rule) {
+        Some((&name, _)) => name,
+        None => return Err(undefined(rule)),
+    };
+    let mut matcher = Matcher::new(&rules, input);
+    Ok(matcher.rule(name, 0).contains(&input.len()))
+}
+
+fn rule_map(grammar: &Grammar) -> HashMap<&str, &Rhs> {
+    grammar
+        .rules
+        .iter()
+        .map(|rule| (rule.lhs.0 .0.as_str(), &rule.rhs))
+        .collect()
+}
+
+fn undefined(rule: &str) -> Error {
+    Error::Generate(format!("undefined rule '{}'", rule))
+}
+
+fn alternatives<'g>(rhs: &'g Rhs, out: &mut Vec<&'g Rhs>) {
+    match rhs {
+        Rhs::Alternation(rhs1, rhs2) => {
+            alternatives(rhs1, out);
+            alternatives(rhs2, out);
+        }
+        rhs => out.push(rhs),
+    }
+}
+
+/// Map 0..95 onto printable ASCII characters.
+fn printable(n: u64) -> char {
+    (b' ' + n as u8) as char
+}
+
+/// The least rule nesting needed to produce a sentence from `rhs`.
+fn depth(rhs: &Rhs, depths: &HashMap<&str, usize>) -> usize {
+    match rhs {
+        Rhs::Identifier(iden) => depths
+            .get(iden.0.as_str())
+            .map_or(UNPRODUCTIVE, |d| d.saturating_add(1)),
+        Rhs::Terminal(_) => 0,
+        Rhs::Class(class) if !class.negated && class.ranges.iter().all(|(lo, hi)| lo > hi) => {
+            UNPRODUCTIVE
+        }
+        Rhs::Class(_) | Rhs::Optional(_) | Rhs::Repeat(_) => 0,
+        Rhs::Group(inner) | Rhs::Exception(inner, _) => depth(inner, depths),
+        Rhs::Alternation(rhs1, rhs2) => depth(rhs1, depths).min(depth(rhs2, depths)),
+        Rhs::Concatenation(rhs1, rhs2) => depth(rhs1, depths).max(depth(rhs2, depths)),
+    }
+}
+
+/// Compute the least nesting needed for every rule by iterating to a fixed
+/// point.
+fn min_depths<'g>(rules: &HashMap<&'g str, &'g Rhs>) -> HashMap<&'g str, usize> {
+    let mut depths: HashMap<&str, usize> = rules.keys().map(|&name| (name, UNPRODUCTIVE)).collect();
+    loop {
+        let mut changed = false;
+        for (&name, rhs) in rules {
+            let d = depth(rhs, &depths);
+            if d < depths[name] {
+                depths.insert(name, d);
+                changed = true;
+            }
+        }
+        if !changed {
+            return depths;
+        }
+    }
+}
+
+/// Finds every position a rhs can match up to.
+struct Matcher<'a, 'g> {
+    rules: &'a HashMap<&'g str, &'g Rhs>,
+    input: &'a str,
+    memo: HashMap<(&'g str, usize), Vec<usize>>,
+    /// Rules currently being matched, used to cut off left recursion.
+    active: HashSet<(&'g str, usize)>,
+}
+
+impl<'a, 'g> Matcher<'a, 'g> {
+    fn new(rules: &'a HashMap<&'g str, &'g Rhs>, input: &'a str) -> Self {
+        Matcher {
+            rules,
+            input,
+            memo: HashMap::new(),
+            active: HashSet::new(),
+        }
+    }
+
+    fn rule(&mut self, name: &'g str, pos: usize) -> Vec<usize> {
+        if let Some(ends) = self.memo.get(&(name, pos)) {
+            return ends.clone();
+        }
+        if !self.active.insert((name, pos)) {
+            return Vec::new();
+        }
+        let ends = match self.rules.get(name) {
+            Some(rhs) => self.ends(rhs, pos),
+            None => Vec::new(),
+        };
+        self.active.remove(&(name, pos));
+        self.memo.insert((name, pos), ends.clone());
+        ends
+    }
+
+    /// Sorted end positions of every way `rhs` can match starting at `pos`.
+    fn ends(&mut self, rhs: &'g Rhs, pos: usize) -> Vec<usize> {
+        let rest = &self.input[pos..];
+        let mut ends = match rhs {
+            Rhs::Identifier(iden) => match self.rules.get_key_value(iden.0.as_str()) {
+                Some((&name, _)) => self.rule(name, pos),
+                None => Vec::new(),
+            },
+            Rhs::Terminal(term) if rest.starts_with(&term.0) => vec![pos + term.0.len()],
+            Rhs::Terminal(_) => Vec::new(),
+            Rhs::Class(class) => match rest.chars().next() {
+                Some(c) if class.contains(c) => vec![pos + c.len_utf8()],
+                _ => Vec::new(),
+            },
+            Rhs::Optional(inner) => {
+                let mut ends = self.ends(inner, pos);
+                ends.push(pos);
+                ends
+            }
+            Rhs::Repeat(inner) => {
+                let mut seen = vec![pos];
+                let mut todo = vec![pos];
+                while let Some(p) = todo.pop() {
+                    for end in self.ends(inner, p) {
+                        if !seen.contains(&end) {
+                            seen.push(end);
+                            todo.push(end);
+                        }
+                    }
+                }
+                seen
+            }
+            Rhs::Group(inner) => self.ends(inner, pos),
+            Rhs::Alternation(rhs1, rhs2) => {
+                let mut ends = self.ends(rhs1, pos);
+                ends.append(&mut self.ends(rhs2, pos));
+                ends
+            }
+            Rhs::Concatenation(rhs1, rhs2) => {
+                let mut ends = Vec::new();
+                for mid in self.ends(rhs1, pos) {
+                    ends.append(&mut self.ends(rhs2, mid));
+                }
+                ends
+            }
+            Rhs::Exception(rhs1, rhs2) => {
+                let excluded = self.ends(rhs2, pos);
+                let mut ends = self.ends(rhs1, pos);
+                ends.retain(|end| !excluded.contains(end));
+                ends
+            }
+        };
+        ends.sort_unstable();
+        ends.dedup();
+        ends
+    }
+}
+
+/// A small seedable random number generator (splitmix64). Sentences only need
+/// to be varied and reproducible, not cryptographically random.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A random number in `0..n`. `n` must not be 0.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EBNF written in EBNF, used to fuzz our own parser.
+    const EBNF_GRAMMAR: &str = r##"
+        grammar = ws , { production , ws } ;
+        production = identifier , ws , "=" , ws , rhs , ws , ";" ;
+        rhs = concatenation , { ws , "|" , ws , concatenation } ;
+        concatenation = exception , { ws , "," , ws , exception } ;
+        exception = primary , [ ws , "-" , ws , primary ] ;
+        primary = identifier
+                | terminal
+                | class
+                | "(" , ws , rhs , ws , ")"
+                | "[" , ws , rhs , ws , "]"
+                | "{" , ws , rhs , ws , "}" ;
+        identifier = letter , { letter | digit | "_" } ;
+        terminal = '"' , { ? [^"] ? } , '"' | "'" , { ? [^'] ? } , "'" ;
+        class = "?" , ws , "[" , [ "^" ] , range , { range } , "]" , ws , "?" ;
+        range = class_char , [ "-" , class_char ] ;
+        class_char = ? [a-zA-Z0-9?!.(*)] ? | "#x" , hex , [ hex ] ;
+        ws = { ? [#x20#xA] ? | comment } ;
+        comment = "(*" , { ? [a-z ] ? } , "*)" ;
+        letter = ? [a-zA-Z] ? ;
+        digit = ? [0-9] ? ;
+        hex = ? [0-9A-F] ? ;
+    "##;
+
+    fn grammar(s: &str) -> Grammar {
+        s.parse().unwrap()
+    }
+
+    fn options(seed: u64) -> Options {
+        Options {
+            seed,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn sentences_are_recognized() {
+        let tests = vec![
+            (
+                "list",
+                "list = '[' , [ item , { ',' , item } ] , ']' ; item = list | ? [0-9] ? ;",
+            ),
+            (
+                "e",
+                "e = t , { ( '+' | '-' ) , t } ; t = ? [a-z] ? | '(' , e , ')' ;",
+            ),
+            ("s", "s = ( 'a' | 'ab' ) , ( 'c' | 'bc' ) ;"),
+            ("w", "w = ? [^a-z] ? , { ? [#x3B1-#x3C9] ? } ;"),
+        ];
+        for (rule, g) in tests {
+            let g = grammar(g);
+            let mut gen = Generator::new(&g, options(1));
+            for _ in 0..100 {
+                let s = gen.sentence(rule).unwrap();
+                assert!(recognize(&g, rule, &s).unwrap(), "{}: {:?}", rule, s);
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_are_reproducible() {
+        let g = grammar("a = { ? [a-z] ? | b } ; b = '(' , a , ')' ;");
+        let sentences = |seed| {
+            let mut gen = Generator::new(&g, options(seed));
+            (0..20)
+                .map(|_| gen.sentence("a").unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sentences(3), sentences(3));
+        assert_ne!(sentences(3), sentences(4));
+    }
+
+    #[test]
+    fn depth_and_weights() {
+        let g = grammar("e = '(' , e , ')' | 'x' ;");
+        let mut weights = HashMap::new();
+        weights.insert("e".to_owned(), vec![1, 0]);
+        let mut gen = Generator::new(
+            &g,
+            Options {
+                max_depth: 4,
+                weights,
+                ..Options::default()
+            },
+        );
+        // Always recurse, until the depth runs out.
+        assert_eq!(gen.sentence("e").unwrap(), "((((x))))");
+
+        let mut weights = HashMap::new();
+        weights.insert("e".to_owned(), vec![0, 1]);
+        let mut gen = Generator::new(
+            &g,
+            Options {
+                weights,
+                ..Options::default()
+            },
+        );
+        assert_eq!(gen.sentence("e").unwrap(), "x");
+    }
+
+    #[test]
+    fn deep_rules_still_finish() {
+        let g = grammar("a = b ; b = c ; c = d ; d = 'd' ;");
+        let mut gen = Generator::new(
+            &g,
+            Options {
+                max_depth: 1,
+                ..Options::default()
+            },
+        );
+        assert_eq!(gen.sentence("a").unwrap(), "d");
+    }
+
+    #[test]
+    fn exceptions_are_avoided() {
+        let g = grammar("ident = ( letter , { letter } ) - ( 'if' | 'in' ) ; letter = ? [fin] ? ;");
+        let mut gen = Generator::new(&g, options(9));
+        for _ in 0..200 {
+            let s = gen.sentence("ident").unwrap();
+            assert!(s != "if" && s != "in", "generated keyword");
+            assert!(recognize(&g, "ident", &s).unwrap());
+        }
+        assert!(!recognize(&g, "ident", "if").unwrap());
+    }
+
+    #[test]
+    fn unproductive_and_undefined_rules() {
+        let g = grammar("a = 'x' , a ; b = c ;");
+        let mut gen = Generator::new(&g, Options::default());
+        assert!(matches!(gen.sentence("a"), Err(Error::Generate(_))));
+        assert!(matches!(gen.sentence("b"), Err(Error::Generate(_))));
+        assert!(matches!(gen.sentence("z"), Err(Error::Generate(_))));
+        assert!(recognize(&g, "z", "").is_err());
+    }
+
+    #[test]
+    fn recognize_considers_every_match() {
+        // An ordered choice parser would stop after the repetition.
+        let g = grammar("a = { 'x' } , 'x' ; b = ( 'a' | 'ab' ) , 'c' ;");
+        assert!(recognize(&g, "a", "xxx").unwrap());
+        assert!(!recognize(&g, "a", "").unwrap());
+        assert!(recognize(&g, "b", "abc").unwrap());
+        assert!(!recognize(&g, "b", "ab").unwrap());
+    }
+
+    #[test]
+    fn near_misses() {
+        let g = grammar("list = '[' , [ item , { ',' , item } ] , ']' ; item = ? [0-9] ? ;");
+        let mut gen = Generator::new(&g, options(5));
+        let invalid = (0..100)
+            .map(|_| gen.near_miss("list").unwrap())
+            .filter(|s| !recognize(&g, "list", s).unwrap())
+            .count();
+        assert!(invalid > 50, "only {} near misses were invalid", invalid);
+        assert_eq!(gen.mutate("").chars().count(), 1);
+    }
+
+    #[test]
+    fn fuzz_ebnf_parser() {
+        let ebnf = grammar(EBNF_GRAMMAR);
+        let mut gen = Generator::new(
+            &ebnf,
+            Options {
+                max_depth: 8,
+                ..options(42)
+            },
+        );
+        for _ in 0..300 {
+            let s = gen.sentence("grammar").unwrap();
+            let parsed: Grammar = match s.parse() {
+                Ok(g) => g,
+                Err(e) => panic!("failed to parse generated grammar {:?}: {}", s, e),
+            };
+            let reparsed: Grammar = parsed.to_string().parse().unwrap();
+            assert_eq!(parsed, reparsed, "generated: {:?}", s);
+
+            // Near misses only need to not panic.
+            let _ = gen.mutate(&s).parse::<Grammar>();
+        }
+    }
+}
@@ -2,11 +2,15 @@ use std::fmt::{self, Display};
 use std::str::FromStr;
 
 mod error;
-use error::Error;
+pub use error::Error;
+pub mod formats;
+pub mod generate;
 mod parser;
+pub mod railroad;
+pub mod transform;
 
 /// A constant identifying production rules.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Identifier(pub String);
 
 impl Display for Identifier {
@@ -22,12 +26,44 @@ impl From<&str> for Identifier {
 }
 
 /// A literal string.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Terminal(pub String);
 
+impl Terminal {
+    /// Write one piece of a terminal, quoted with whichever quote character
+    /// doesn't show up in it.
+    fn fmt_piece(f: &mut fmt::Formatter, piece: &str) -> fmt::Result {
+        if piece.contains('"') {
+            write!(f, "'{}'", piece)
+        } else {
+            write!(f, "\"{}\"", piece)
+        }
+    }
+}
+
+/// Terminals cannot contain escapes. One containing both quote characters is
+/// written as a parenthesized concatenation of pieces that each lack one of
+/// them, e.g. `( "it's " , '"' )`, which matches the same text.
 impl Display for Terminal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "\"{}\"", self.0)
+        if !(self.0.contains('"') && self.0.contains('\'')) {
+            return Terminal::fmt_piece(f, &self.0);
+        }
+        write!(f, "( ")?;
+        let (mut start, mut single, mut double) = (0, false, false);
+        for (i, c) in self.0.char_indices() {
+            single |= c == '\'';
+            double |= c == '"';
+            if single && double {
+                Terminal::fmt_piece(f, &self.0[start..i])?;
+                write!(f, " , ")?;
+                start = i;
+                single = c == '\'';
+                double = c == '"';
+            }
+        }
+        Terminal::fmt_piece(f, &self.0[start..])?;
+        write!(f, " )")
     }
 }
 
@@ -37,8 +73,62 @@ impl From<&str> for Terminal {
     }
 }
 
+/// A set of characters matching exactly one character of input.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CharClass {
+    /// Match characters *not* contained in `ranges`.
+    pub negated: bool,
+    /// Inclusive character ranges.
+    pub ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// Check if a character is matched by this class.
+    pub fn contains(&self, c: char) -> bool {
+        let in_ranges = self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        in_ranges != self.negated
+    }
+
+    /// Write a single character of a class, using `#x` escapes for anything
+    /// that could be confused with class or grammar syntax.
+    fn fmt_char(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+        if c.is_ascii_alphanumeric() || "!$%&*+./:;<=>@_~|(){},'\"`".contains(c) {
+            write!(f, "{}", c)
+        } else {
+            write!(f, "#x{:X}", c as u32)
+        }
+    }
+}
+
+/// Classes are written as bracket expressions, e.g. `[a-zA-Z_]` or `[^#x22]`.
+impl Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        if self.negated {
+            write!(f, "^")?;
+        }
+        for &(lo, hi) in &self.ranges {
+            CharClass::fmt_char(f, lo)?;
+            if lo != hi {
+                write!(f, "-")?;
+                CharClass::fmt_char(f, hi)?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl FromStr for CharClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (_, class) = parser::char_class(s)?;
+        Ok(class)
+    }
+}
+
 /// The lhs of a production rule.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Lhs(pub Identifier);
 
 impl From<&str> for Lhs {
@@ -54,10 +144,11 @@ impl Display for Lhs {
 }
 
 /// The rhs of a production rule.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Rhs {
     Identifier(Identifier),
     Terminal(Terminal),
+    Class(CharClass),
     Optional(Box<Rhs>),
     Repeat(Box<Rhs>),
     Group(Box<Rhs>),
@@ -66,17 +157,55 @@ pub enum Rhs {
     Concatenation(Box<Rhs>, Box<Rhs>),
 }
 
+impl Rhs {
+    /// How tightly this rhs binds. Writers use this to decide when an operand
+    /// needs parenthesizing.
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Rhs::Alternation(_, _) => 0,
+            Rhs::Concatenation(_, _) => 1,
+            Rhs::Exception(_, _) => 2,
+            _ => 3,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, min: u8) -> fmt::Result {
+        if self.precedence() < min {
+            write!(f, "( {} )", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+/// Operators are written right associative. Operands binding looser than
+/// their operator are parenthesized.
 impl Display for Rhs {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Rhs::Identifier(iden) => write!(f, "{}", iden),
             Rhs::Terminal(term) => write!(f, "{}", term),
+            // Classes aren't part of ISO EBNF, and are written as special
+            // sequences instead.
+            Rhs::Class(class) => write!(f, "? {} ?", class),
             Rhs::Optional(rhs) => write!(f, "[ {} ]", rhs),
             Rhs::Repeat(rhs) => write!(f, "{{ {} }}", rhs),
             Rhs::Group(rhs) => write!(f, "( {} )", rhs),
-            Rhs::Exception(rhs1, rhs2) => write!(f, "{} - {}", rhs1, rhs2),
-            Rhs::Alternation(rhs1, rhs2) => write!(f, "{} | {}", rhs1, rhs2),
-            Rhs::Concatenation(rhs1, rhs2) => write!(f, "{} , {}", rhs1, rhs2),
+            Rhs::Exception(rhs1, rhs2) => {
+                rhs1.fmt_operand(f, 3)?;
+                write!(f, " - ")?;
+                rhs2.fmt_operand(f, 2)
+            }
+            Rhs::Alternation(rhs1, rhs2) => {
+                rhs1.fmt_operand(f, 1)?;
+                write!(f, " | ")?;
+                rhs2.fmt_operand(f, 0)
+            }
+            Rhs::Concatenation(rhs1, rhs2) => {
+                rhs1.fmt_operand(f, 2)?;
+                write!(f, " , ")?;
+                rhs2.fmt_operand(f, 1)
+            }
         }
     }
 }
@@ -85,13 +214,14 @@ impl FromStr for Rhs {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (_, rhs) = parser::rhs(s)?;
+        let (rem, rhs) = parser::rhs(s)?;
+        parser::expect_end(rem)?;
         Ok(rhs)
     }
 }
 
 /// A production rule.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Production {
     pub lhs: Lhs,
     pub rhs: Rhs,
@@ -107,13 +237,14 @@ impl FromStr for Production {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (_, rule) = parser::production(s)?;
+        let (rem, rule) = parser::production(s)?;
+        parser::expect_end(rem)?;
         Ok(rule)
     }
 }
 
 /// A set of rules.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Grammar {
     pub rules: Vec<Production>,
 }
@@ -131,7 +262,8 @@ impl FromStr for Grammar {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (_, grammar) = parser::grammar(s)?;
+        let (rem, grammar) = parser::grammar(s)?;
+        parser::expect_end(rem)?;
         Ok(grammar)
     }
 }
@@ -155,6 +287,19 @@ mod tests {
     #[test]
     fn lossless_rhs() {
         let tests = vec![
+            Rhs::Class(CharClass {
+                negated: true,
+                ranges: vec![('a', 'z'), ('?', '?'), (']', ']'), ('\n', '\n')],
+            }),
+            Rhs::Concatenation(
+                Box::new(Rhs::Group(Box::new(Rhs::Alternation(
+                    Box::new(Rhs::Terminal("a\"".into())),
+                    Box::new(Rhs::Identifier("b".into())),
+                )))),
+                Box::new(Rhs::Optional(Box::new(Rhs::Repeat(Box::new(
+                    Rhs::Identifier("c".into()),
+                ))))),
+            ),
             Rhs::Exception(
                 Box::new(Rhs::Terminal("hello".into())),
                 Box::new(Rhs::Identifier("world".into())),
@@ -176,6 +321,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn lossless_terminal_quotes() {
+        for text in &["it's", "say \"hi\"", "'", "\""] {
+            assert_lossless_conversion(Rhs::Terminal((*text).into()));
+        }
+    }
+
+    /// Collect the text matched by a concatenation of terminals.
+    fn concatenated_text(rhs: &Rhs) -> String {
+        match rhs {
+            Rhs::Terminal(Terminal(text)) => text.clone(),
+            Rhs::Group(rhs) => concatenated_text(rhs),
+            Rhs::Concatenation(rhs1, rhs2) => concatenated_text(rhs1) + &concatenated_text(rhs2),
+            _ => panic!("not a concatenation of terminals: {:?}", rhs),
+        }
+    }
+
+    #[test]
+    fn terminal_with_both_quotes() {
+        for text in &["it's \"hi\"", "'\"", "\"'\"'", "a'b\"c'd"] {
+            let rhs = Rhs::Exception(
+                Box::new(Rhs::Identifier("a".into())),
+                Box::new(Rhs::Terminal((*text).into())),
+            );
+            let printed = rhs.to_string();
+            match printed.parse::<Rhs>() {
+                Ok(Rhs::Exception(_, term)) => {
+                    assert_eq!(concatenated_text(&term), *text, "To string:\n{}\n", printed)
+                }
+                other => panic!("{} parsed as {:?}", printed, other),
+            }
+        }
+    }
+
     #[test]
     fn lossless_rule() {
         let rule = Production {
@@ -3,20 +3,20 @@
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_until, take_while},
-    character::complete::{alpha1, alphanumeric1, space0},
+    character::complete::{alpha1, alphanumeric1, char, hex_digit1, multispace0, none_of},
     combinator::{opt, recognize},
-    multi::many0,
-    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    error::ErrorKind,
+    multi::{many0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
 
-use crate::{Grammar, Identifier, Lhs, Production, Rhs, Terminal};
+use crate::{CharClass, Error, Grammar, Identifier, Lhs, Production, Rhs, Terminal};
 
 /// Parse a string literal.
 ///
 /// Terminals may use either single or double quotes.
 pub fn terminal(input: &str) -> IResult<&str, Terminal> {
-    // TODO: Ensure parsing with balanced parens.
     let (rem, matched) = alt((
         delimited(tag("\""), take_until("\""), tag("\"")),
         delimited(tag("\'"), take_until("\'"), tag("\'")),
@@ -36,6 +36,24 @@ pub fn identifier(input: &str) -> IResult<&str, Identifier> {
     Ok((rem, Identifier(matched.to_owned())))
 }
 
+/// Parse a character class written as a bracket expression.
+///
+/// Characters may be written literally, or as `#x` followed by the hex code
+/// point. A leading `^` negates the class.
+pub fn char_class(input: &str) -> IResult<&str, CharClass> {
+    let (rem, _) = char('[')(input)?;
+    let (rem, negated) = opt(char('^'))(rem)?;
+    let (rem, ranges) = many0(class_range)(rem)?;
+    let (rem, _) = char(']')(rem)?;
+    Ok((
+        rem,
+        CharClass {
+            negated: negated.is_some(),
+            ranges,
+        },
+    ))
+}
+
 /// Parse the left hand side of a rule.
 pub fn lhs(input: &str) -> IResult<&str, Lhs> {
     let (rem, matched) = identifier(input)?;
@@ -43,22 +61,12 @@ pub fn lhs(input: &str) -> IResult<&str, Lhs> {
 }
 
 /// Parse the right hand side of a rule.
+///
+/// From loosest to tightest binding, the operators are alternation (`|`),
+/// concatenation (`,`) and exception (`-`). All are right associative.
 pub fn rhs(input: &str) -> IResult<&str, Rhs> {
-    let (rem, matched) = preceded(
-        space0,
-        alt((
-            rhs_group,
-            rhs_repetition,
-            rhs_optional,
-            rhs_alternation,
-            rhs_concatenation,
-            rhs_exception,
-            rhs_terminal,
-            rhs_identifier,
-        )),
-    )(input)?;
-
-    Ok((rem, matched))
+    let (rem, items) = separated_list1(preceded(whitespace, char('|')), rhs_concatenation)(input)?;
+    Ok((rem, fold_right(items, Rhs::Alternation)))
 }
 
 /// Parse a rule.
@@ -66,13 +74,10 @@ pub fn rhs(input: &str) -> IResult<&str, Rhs> {
 /// Rules must contain an lhs and rhs seperated by '='. Rules are terminated by
 /// ';'.
 pub fn production(input: &str) -> IResult<&str, Production> {
-    // TODO: Take until non-terminal ';'
-    let (rem, (matched_lhs, matched_rhs)) = terminated(
-        separated_pair(take_until("="), tag("="), take_until(";")),
-        tag(";"),
-    )(input)?;
-    let (_, rule_lhs) = lhs(matched_lhs)?;
-    let (_, rule_rhs) = rhs(matched_rhs)?;
+    let (rem, rule_lhs) = preceded(whitespace, lhs)(input)?;
+    let (rem, _) = preceded(whitespace, char('='))(rem)?;
+    let (rem, rule_rhs) = rhs(rem)?;
+    let (rem, _) = preceded(whitespace, char(';'))(rem)?;
     Ok((
         rem,
         Production {
@@ -89,30 +94,123 @@ pub fn production(input: &str) -> IResult<&str, Production> {
 ///
 /// Comments and whitespace around rules are discarded.
 pub fn grammar(input: &str) -> IResult<&str, Grammar> {
-    // Strip out comments and whitespace from before and after each production
-    // rule.
-    let (rem, rules) = many0(terminated(
-        preceded(comment_and_whitespace, production),
-        comment_and_whitespace,
-    ))(input)?;
+    let (rem, rules) = terminated(many0(production), whitespace)(input)?;
     Ok((rem, Grammar { rules }))
 }
 
-fn comment_and_whitespace(input: &str) -> IResult<&str, &str> {
-    alt((
-        terminated(preceded(whitespace, comment), whitespace),
-        whitespace,
-    ))(input)
+/// Ensure nothing but whitespace or comments remains after parsing.
+pub(crate) fn expect_end(rem: &str) -> Result<(), Error> {
+    let (rem, _) = whitespace(rem)?;
+    if rem.is_empty() {
+        Ok(())
+    } else {
+        let context: String = rem.chars().take(40).collect();
+        Err(Error::ParseError(format!(
+            "unexpected input: '{}'",
+            context
+        )))
+    }
 }
 
-fn comment(input: &str) -> IResult<&str, &str> {
-    let (rem, matched) = delimited(tag("(*"), take_until("*)"), tag("*)"))(input)?;
-    Ok((rem, matched))
+/// Fold a non-empty list of items into a right associative tree using `f`.
+pub(crate) fn fold_right<F>(mut items: Vec<Rhs>, f: F) -> Rhs
+where
+    F: Fn(Box<Rhs>, Box<Rhs>) -> Rhs,
+{
+    let mut acc = items.pop().expect("at least one item to fold");
+    while let Some(item) = items.pop() {
+        acc = f(Box::new(item), Box::new(acc));
+    }
+    acc
+}
+
+/// Parse a single character written as `#x` followed by hex digits.
+pub(crate) fn hex_char(input: &str) -> IResult<&str, char> {
+    let (rem, digits) = preceded(tag("#x"), hex_digit1)(input)?;
+    match u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(std::char::from_u32)
+    {
+        Some(c) => Ok((rem, c)),
+        None => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            ErrorKind::HexDigit,
+        ))),
+    }
+}
+
+fn class_range(input: &str) -> IResult<&str, (char, char)> {
+    let (rem, lo) = class_char(input)?;
+    let (rem, hi) = opt(preceded(char('-'), class_char))(rem)?;
+    Ok((rem, (lo, hi.unwrap_or(lo))))
+}
+
+fn class_char(input: &str) -> IResult<&str, char> {
+    alt((hex_char, none_of("]-")))(input)
 }
 
+/// Skip whitespace and (possibly nested) comments.
 fn whitespace(input: &str) -> IResult<&str, &str> {
-    let (rem, matched) = take_while(move |c| " \t\r\n".contains(c))(input)?;
-    Ok((rem, matched))
+    recognize(many0(alt((
+        take_while_nonempty(|c| " \t\r\n".contains(c)),
+        comment,
+    ))))(input)
+}
+
+fn take_while_nonempty<F>(f: F) -> impl Fn(&str) -> IResult<&str, &str>
+where
+    F: Fn(char) -> bool + Copy,
+{
+    move |input| {
+        let (rem, matched) = take_while(f)(input)?;
+        if matched.is_empty() {
+            Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                ErrorKind::TakeWhile1,
+            )))
+        } else {
+            Ok((rem, matched))
+        }
+    }
+}
+
+fn comment(input: &str) -> IResult<&str, &str> {
+    let (mut rem, _) = tag("(*")(input)?;
+    let mut depth = 1;
+    while depth > 0 {
+        if rem.starts_with("*)") {
+            depth -= 1;
+            rem = &rem[2..];
+        } else if rem.starts_with("(*") {
+            depth += 1;
+            rem = &rem[2..];
+        } else {
+            match rem.chars().next() {
+                Some(c) => rem = &rem[c.len_utf8()..],
+                None => {
+                    return Err(nom::Err::Error(nom::error::Error::new(
+                        input,
+                        ErrorKind::TakeUntil,
+                    )))
+                }
+            }
+        }
+    }
+    Ok((rem, &input[..input.len() - rem.len()]))
+}
+
+fn rhs_primary(input: &str) -> IResult<&str, Rhs> {
+    preceded(
+        whitespace,
+        alt((
+            rhs_group,
+            rhs_repetition,
+            rhs_optional,
+            rhs_special,
+            rhs_terminal,
+            rhs_identifier,
+        )),
+    )(input)
 }
 
 fn rhs_identifier(input: &str) -> IResult<&str, Rhs> {
@@ -125,43 +223,44 @@ fn rhs_terminal(input: &str) -> IResult<&str, Rhs> {
     Ok((rem, Rhs::Terminal(matched)))
 }
 
-fn rhs_exception(input: &str) -> IResult<&str, Rhs> {
-    let (rem, (matched1, matched2)) = separated_pair(take_until("-"), tag("-"), rhs)(input)?;
-    let (_, inner1) = rhs(matched1)?;
-    Ok((rem, Rhs::Exception(Box::new(inner1), Box::new(matched2))))
+/// Special sequences are used for character classes, e.g. `? [a-z] ?`. Any
+/// other special sequence is an error.
+fn rhs_special(input: &str) -> IResult<&str, Rhs> {
+    let (rem, _) = char('?')(input)?;
+    // Classes are parsed directly rather than looking for the closing `?`,
+    // since the class itself may contain one.
+    let class = delimited(multispace0, char_class, pair(multispace0, char('?')))(rem);
+    match class {
+        Ok((rem, class)) => Ok((rem, Rhs::Class(class))),
+        Err(_) => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            ErrorKind::Verify,
+        ))),
+    }
 }
 
-fn rhs_alternation(input: &str) -> IResult<&str, Rhs> {
-    let (rem, (matched1, matched2)) = separated_pair(take_until("|"), tag("|"), rhs)(input)?;
-    let (_, inner1) = rhs(matched1)?;
-    Ok((rem, Rhs::Alternation(Box::new(inner1), Box::new(matched2))))
+fn rhs_exception(input: &str) -> IResult<&str, Rhs> {
+    let (rem, items) = separated_list1(preceded(whitespace, char('-')), rhs_primary)(input)?;
+    Ok((rem, fold_right(items, Rhs::Exception)))
 }
 
 fn rhs_concatenation(input: &str) -> IResult<&str, Rhs> {
-    // TODO: Doesn't handle continued concats.
-    let (rem, (matched1, matched2)) = separated_pair(take_until(","), tag(","), rhs)(input)?;
-    let (_, inner1) = rhs(matched1)?;
-    Ok((
-        rem,
-        Rhs::Concatenation(Box::new(inner1), Box::new(matched2)),
-    ))
+    let (rem, items) = separated_list1(preceded(whitespace, char(',')), rhs_exception)(input)?;
+    Ok((rem, fold_right(items, Rhs::Concatenation)))
 }
 
 fn rhs_group(input: &str) -> IResult<&str, Rhs> {
-    let (rem, matched) = delimited(tag("("), take_until(")"), tag(")"))(input)?;
-    let (_, inner_rhs) = rhs(matched)?;
+    let (rem, inner_rhs) = delimited(char('('), rhs, preceded(whitespace, char(')')))(input)?;
     Ok((rem, Rhs::Group(Box::new(inner_rhs))))
 }
 
 fn rhs_repetition(input: &str) -> IResult<&str, Rhs> {
-    let (rem, matched) = delimited(tag("{"), take_until("}"), tag("}"))(input)?;
-    let (_, inner_rhs) = rhs(matched)?;
+    let (rem, inner_rhs) = delimited(char('{'), rhs, preceded(whitespace, char('}')))(input)?;
     Ok((rem, Rhs::Repeat(Box::new(inner_rhs))))
 }
 
 fn rhs_optional(input: &str) -> IResult<&str, Rhs> {
-    let (rem, matched) = delimited(tag("["), take_until("]"), tag("]"))(input)?;
-    let (_, inner_rhs) = rhs(matched)?;
+    let (rem, inner_rhs) = delimited(char('['), rhs, preceded(whitespace, char(']')))(input)?;
     Ok((rem, Rhs::Optional(Box::new(inner_rhs))))
 }
 
@@ -318,6 +417,19 @@ mod tests {
                     ),
                 ))),
             },
+            TestCase {
+                input: "? [?!] ? , a",
+                out: Some(Ok((
+                    "",
+                    Rhs::Concatenation(
+                        Box::new(Rhs::Class(CharClass {
+                            negated: false,
+                            ranges: vec![('?', '?'), ('!', '!')],
+                        })),
+                        Box::new(Rhs::Identifier(Identifier("a".to_owned()))),
+                    ),
+                ))),
+            },
         ];
 
         assert_test_cases(rhs, tests);
@@ -0,0 +1,601 @@
+//! Railroad (syntax) diagram export for grammars.
+//!
+//! Each production is rendered as a standalone SVG document. An HTML index
+//! embedding every diagram can be generated for the whole grammar, with
+//! nonterminals linking to the diagram of the rule they reference.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{Grammar, Production, Rhs};
+
+/// Approximate width of a single character of diagram text.
+const CHAR_WIDTH: usize = 8;
+/// Horizontal padding inside terminal and nonterminal boxes.
+const BOX_PADDING: usize = 10;
+/// Height of terminal and nonterminal boxes.
+const BOX_HEIGHT: usize = 22;
+/// Radius of the arcs used for rails.
+const ARC: usize = 10;
+/// Horizontal gap between items in a sequence.
+const H_GAP: usize = 10;
+/// Vertical gap between stacked items (choices, loops).
+const V_GAP: usize = 8;
+/// Margin around the whole diagram.
+const MARGIN: usize = 20;
+
+const STYLE: &str = "\
+svg.railroad { background-color: #fff; }
+svg.railroad path { stroke-width: 2; stroke: #333; fill: none; }
+svg.railroad text { font: 13px monospace; text-anchor: middle; fill: #000; }
+svg.railroad text.label { font-size: 11px; font-style: italic; fill: #666; text-anchor: start; }
+svg.railroad rect { stroke-width: 2; stroke: #333; }
+svg.railroad rect.terminal { fill: #e6f2ff; }
+svg.railroad rect.class { fill: #e6ffe6; }
+svg.railroad rect.nonterminal { fill: #fff5e0; }
+svg.railroad rect.group { stroke-width: 1; stroke-dasharray: 4 3; fill: none; stroke: #888; }
+svg.railroad rect.exception { stroke-width: 1; stroke-dasharray: 4 3; fill: #fde8e8; stroke: #a33; }
+";
+
+/// How references to other rules are linked.
+#[derive(Debug, Clone, Copy)]
+enum Links {
+    /// Link to an anchor in the same document (`#rule`).
+    Anchor,
+    /// Link to a sibling svg file (`rule.svg`).
+    File,
+}
+
+impl Links {
+    fn href(self, rule: &str) -> String {
+        match self {
+            Links::Anchor => format!("#{}", rule),
+            Links::File => format!("{}.svg", rule),
+        }
+    }
+}
+
+/// A laid out diagram element.
+///
+/// Every element has a single entry point on the left and a single exit point
+/// on the right, both on the element's baseline. `up` and `down` describe how
+/// far the element extends above and below the baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagram {
+    /// A literal string, drawn in a rounded box.
+    Terminal(String),
+    /// A reference to another rule, drawn in a square box.
+    NonTerminal(String),
+    /// A character class, drawn like a terminal.
+    Class(String),
+    /// An empty path.
+    Skip,
+    /// Items following one after another.
+    Sequence(Vec<Diagram>),
+    /// Exactly one of the items. The first item is drawn on the baseline.
+    Choice(Vec<Diagram>),
+    /// One or more repetitions of an item.
+    OneOrMore(Box<Diagram>),
+    /// A dashed frame around an item.
+    Group(Box<Diagram>),
+    /// An item, minus whatever the second item matches.
+    Exception(Box<Diagram>, Box<Diagram>),
+}
+
+impl Diagram {
+    /// Build a diagram for the rhs of a production.
+    ///
+    /// Nested alternations and concatenations are flattened so that `a | b |
+    /// c` is drawn as a single three-way choice.
+    pub fn from_rhs(rhs: &Rhs) -> Self {
+        match rhs {
+            Rhs::Identifier(iden) => Diagram::NonTerminal(iden.0.clone()),
+            Rhs::Terminal(term) => Diagram::Terminal(term.0.clone()),
+            Rhs::Class(class) => Diagram::Class(class.to_string()),
+            Rhs::Optional(inner) => Diagram::Choice(vec![Diagram::Skip, Diagram::from_rhs(inner)]),
+            Rhs::Repeat(inner) => Diagram::Choice(vec![
+                Diagram::Skip,
+                Diagram::OneOrMore(Box::new(Diagram::from_rhs(inner))),
+            ]),
+            Rhs::Group(inner) => Diagram::Group(Box::new(Diagram::from_rhs(inner))),
+            Rhs::Exception(rhs1, rhs2) => Diagram::Exception(
+                Box::new(Diagram::from_rhs(rhs1)),
+                Box::new(Diagram::from_rhs(rhs2)),
+            ),
+            Rhs::Alternation(_, _) => {
+                let mut items = Vec::new();
+                flatten_alternation(rhs, &mut items);
+                Diagram::Choice(items.into_iter().map(Diagram::from_rhs).collect())
+            }
+            Rhs::Concatenation(_, _) => {
+                let mut items = Vec::new();
+                flatten_concatenation(rhs, &mut items);
+                Diagram::Sequence(items.into_iter().map(Diagram::from_rhs).collect())
+            }
+        }
+    }
+
+    /// Width of the element.
+    pub fn width(&self) -> usize {
+        match self {
+            Diagram::Terminal(s) | Diagram::NonTerminal(s) | Diagram::Class(s) => {
+                text_width(s) + 2 * BOX_PADDING
+            }
+            Diagram::Skip => 0,
+            Diagram::Sequence(items) => {
+                let sum: usize = items.iter().map(Diagram::width).sum();
+                sum + H_GAP * items.len().saturating_sub(1)
+            }
+            Diagram::Choice(items) => {
+                let max = items.iter().map(Diagram::width).max().unwrap_or(0);
+                max + 4 * ARC
+            }
+            Diagram::OneOrMore(item) => item.width() + 2 * ARC,
+            Diagram::Group(item) => item.width() + 2 * H_GAP,
+            Diagram::Exception(item, except) => {
+                let label = text_width("except") + H_GAP;
+                item.width().max(except.width() + label) + 2 * H_GAP
+            }
+        }
+    }
+
+    /// Distance the element extends above its baseline.
+    pub fn up(&self) -> usize {
+        match self {
+            Diagram::Terminal(_) | Diagram::NonTerminal(_) | Diagram::Class(_) => BOX_HEIGHT / 2,
+            Diagram::Skip => 0,
+            Diagram::Sequence(items) => items.iter().map(Diagram::up).max().unwrap_or(0),
+            Diagram::Choice(items) => items.first().map(Diagram::up).unwrap_or(0),
+            Diagram::OneOrMore(item) => item.up(),
+            Diagram::Group(item) | Diagram::Exception(item, _) => item.up() + V_GAP,
+        }
+    }
+
+    /// Distance the element extends below its baseline.
+    pub fn down(&self) -> usize {
+        match self {
+            Diagram::Terminal(_) | Diagram::NonTerminal(_) | Diagram::Class(_) => BOX_HEIGHT / 2,
+            Diagram::Skip => 0,
+            Diagram::Sequence(items) => items.iter().map(Diagram::down).max().unwrap_or(0),
+            Diagram::Choice(items) => {
+                let mut down = items.first().map(Diagram::down).unwrap_or(0);
+                for item in items.iter().skip(1) {
+                    down += V_GAP.max(ARC) + item.up() + item.down();
+                }
+                down
+            }
+            Diagram::OneOrMore(item) => item.down() + V_GAP + ARC,
+            Diagram::Group(item) => item.down() + V_GAP,
+            Diagram::Exception(item, except) => {
+                item.down() + V_GAP + except.up() + except.down() + V_GAP
+            }
+        }
+    }
+
+    /// Render the element with its entry point at (`x`, `y`).
+    fn render(&self, x: usize, y: usize, links: Links, out: &mut String) {
+        match self {
+            Diagram::Terminal(s) => render_box(s, x, y, "terminal", None, out),
+            Diagram::Class(s) => render_box(s, x, y, "terminal class", None, out),
+            Diagram::NonTerminal(s) => {
+                render_box(s, x, y, "nonterminal", Some(&links.href(s)), out)
+            }
+            Diagram::Skip => {}
+            Diagram::Sequence(items) => {
+                let mut x = x;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        line(x, y, x + H_GAP, y, out);
+                        x += H_GAP;
+                    }
+                    item.render(x, y, links, out);
+                    x += item.width();
+                }
+            }
+            Diagram::Choice(items) => self.render_choice(items, x, y, links, out),
+            Diagram::OneOrMore(item) => {
+                let w = item.width();
+                line(x, y, x + ARC, y, out);
+                item.render(x + ARC, y, links, out);
+                line(x + ARC + w, y, x + 2 * ARC + w, y, out);
+                // Loop back from the exit to the entry, below the item.
+                let (left, right) = (x, x + 2 * ARC + w);
+                let loop_y = y + item.down() + V_GAP + ARC;
+                let _ = writeln!(
+                    out,
+                    r#"<path d="M{r} {y} Q{r2} {y} {r2} {y1} V{ly1} Q{r2} {ly} {r} {ly} H{l} Q{l0} {ly} {l0} {ly1} V{y1} Q{l0} {y} {l} {y}"/>"#,
+                    r = right - ARC,
+                    r2 = right,
+                    l = left + ARC,
+                    l0 = left,
+                    y = y,
+                    y1 = y + ARC,
+                    ly = loop_y,
+                    ly1 = loop_y - ARC,
+                );
+            }
+            Diagram::Group(item) => {
+                let w = self.width();
+                frame(x, y - self.up(), w, self.up() + self.down(), "group", out);
+                line(x, y, x + H_GAP, y, out);
+                item.render(x + H_GAP, y, links, out);
+                line(x + H_GAP + item.width(), y, x + w, y, out);
+            }
+            Diagram::Exception(item, except) => {
+                let w = self.width();
+                frame(
+                    x,
+                    y - self.up(),
+                    w,
+                    self.up() + self.down(),
+                    "exception",
+                    out,
+                );
+                line(x, y, x + H_GAP, y, out);
+                item.render(x + H_GAP, y, links, out);
+                line(x + H_GAP + item.width(), y, x + w, y, out);
+
+                // The excluded item is drawn unconnected beneath a label.
+                let ey = y + item.down() + V_GAP + except.up();
+                let _ = writeln!(
+                    out,
+                    r#"<text class="label" x="{}" y="{}">except</text>"#,
+                    x + H_GAP,
+                    ey + 4
+                );
+                except.render(x + 2 * H_GAP + text_width("except"), ey, links, out);
+            }
+        }
+    }
+
+    fn render_choice(&self, items: &[Diagram], x: usize, y: usize, links: Links, out: &mut String) {
+        let w = self.width();
+        let inner = w - 4 * ARC;
+        let mut child_y = y;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                child_y += items[i - 1].down() + V_GAP.max(ARC) + item.up();
+            }
+            let iw = item.width();
+            let pad = inner - iw;
+            if i == 0 {
+                line(x, y, x + 2 * ARC, y, out);
+            } else {
+                // Branch down from the entry rail, and back up to the exit.
+                let _ = writeln!(
+                    out,
+                    r#"<path d="M{} {} a{} {} 0 0 1 {} {} V{} a{} {} 0 0 0 {} {}"/>"#,
+                    x,
+                    y,
+                    ARC,
+                    ARC,
+                    ARC,
+                    ARC,
+                    child_y - ARC,
+                    ARC,
+                    ARC,
+                    ARC,
+                    ARC
+                );
+                let _ = writeln!(
+                    out,
+                    r#"<path d="M{} {} a{} {} 0 0 0 {} -{} V{} a{} {} 0 0 1 {} -{}"/>"#,
+                    x + w - 2 * ARC,
+                    child_y,
+                    ARC,
+                    ARC,
+                    ARC,
+                    ARC,
+                    y + ARC,
+                    ARC,
+                    ARC,
+                    ARC,
+                    ARC
+                );
+            }
+            item.render(x + 2 * ARC, child_y, links, out);
+            line(
+                x + 2 * ARC + iw,
+                child_y,
+                x + 2 * ARC + iw + pad,
+                child_y,
+                out,
+            );
+            if i == 0 {
+                line(x + w - 2 * ARC, y, x + w, y, out);
+            }
+        }
+    }
+}
+
+/// Render a single production as a standalone SVG document.
+///
+/// References to other rules link to `<rule>.svg`, matching the files written
+/// by [`write_diagrams`].
+///
+/// # Examples
+///
+/// ```
+/// use ebnf::{railroad, Production};
+///
+/// let rule: Production = "digits = digit , { digit } ;".parse().unwrap();
+/// let svg = railroad::production_svg(&rule);
+/// assert!(svg.starts_with("<svg"));
+/// assert!(svg.contains(">digit</text>"));
+/// ```
+pub fn production_svg(rule: &Production) -> String {
+    render_svg(rule, Links::File)
+}
+
+fn render_svg(rule: &Production, links: Links) -> String {
+    let diagram = Diagram::from_rhs(&rule.rhs);
+    let name = rule.lhs.to_string();
+
+    // Room for the rule name above the diagram.
+    let title = BOX_HEIGHT;
+    let width = diagram.width() + 2 * MARGIN + 2 * H_GAP;
+    let height = title + diagram.up() + diagram.down() + 2 * MARGIN;
+    let y = MARGIN + title + diagram.up();
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" class="railroad" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+        width, height, width, height
+    );
+    let _ = writeln!(out, "<style>\n{}</style>", STYLE);
+    let _ = writeln!(
+        out,
+        r#"<text class="label" x="{}" y="{}">{}</text>"#,
+        MARGIN,
+        MARGIN + 4,
+        escape(&name)
+    );
+
+    // Entry and exit markers.
+    let _ = writeln!(
+        out,
+        r#"<path d="M{} {} v20 m0 -10 h{}"/>"#,
+        MARGIN,
+        y - 10,
+        H_GAP
+    );
+    diagram.render(MARGIN + H_GAP, y, links, &mut out);
+    let end = MARGIN + H_GAP + diagram.width();
+    let _ = writeln!(out, r#"<path d="M{} {} h{} m0 -10 v20"/>"#, end, y, H_GAP);
+
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Render an HTML page containing a diagram for every production in the
+/// grammar.
+///
+/// Each diagram is preceded by an anchor named after the rule, so references
+/// to other rules can be followed.
+pub fn grammar_html(grammar: &Grammar) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>Grammar</title>\n</head>\n<body>\n");
+
+    out.push_str("<ul>\n");
+    for rule in &grammar.rules {
+        let name = escape(&rule.lhs.to_string());
+        let _ = writeln!(out, "<li><a href=\"#{}\">{}</a></li>", name, name);
+    }
+    out.push_str("</ul>\n");
+
+    for rule in &grammar.rules {
+        let name = escape(&rule.lhs.to_string());
+        let _ = writeln!(out, "<h2 id=\"{}\">{}</h2>", name, name);
+        let _ = writeln!(out, "<pre>{}</pre>", escape(&rule.to_string()));
+        out.push_str(&render_svg(rule, Links::Anchor));
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Write one `<rule>.svg` file per production and an `index.html` to `dir`.
+///
+/// The directory is created if it does not exist.
+pub fn write_diagrams<P: AsRef<Path>>(grammar: &Grammar, dir: P) -> io::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    for rule in &grammar.rules {
+        let path = dir.join(format!("{}.svg", rule.lhs));
+        fs::write(path, production_svg(rule))?;
+    }
+    fs::write(dir.join("index.html"), grammar_html(grammar))
+}
+
+fn flatten_alternation<'a>(rhs: &'a Rhs, out: &mut Vec<&'a Rhs>) {
+    match rhs {
+        Rhs::Alternation(rhs1, rhs2) => {
+            flatten_alternation(rhs1, out);
+            flatten_alternation(rhs2, out);
+        }
+        _ => out.push(rhs),
+    }
+}
+
+fn flatten_concatenation<'a>(rhs: &'a Rhs, out: &mut Vec<&'a Rhs>) {
+    match rhs {
+        Rhs::Concatenation(rhs1, rhs2) => {
+            flatten_concatenation(rhs1, out);
+            flatten_concatenation(rhs2, out);
+        }
+        _ => out.push(rhs),
+    }
+}
+
+fn text_width(s: &str) -> usize {
+    s.chars().count() * CHAR_WIDTH
+}
+
+fn line(x1: usize, y1: usize, x2: usize, y2: usize, out: &mut String) {
+    if x1 != x2 || y1 != y2 {
+        let _ = writeln!(out, r#"<path d="M{} {} L{} {}"/>"#, x1, y1, x2, y2);
+    }
+}
+
+fn frame(x: usize, y: usize, w: usize, h: usize, class: &str, out: &mut String) {
+    let _ = writeln!(
+        out,
+        r#"<rect class="{}" x="{}" y="{}" width="{}" height="{}" rx="4"/>"#,
+        class, x, y, w, h
+    );
+}
+
+fn render_box(text: &str, x: usize, y: usize, class: &str, href: Option<&str>, out: &mut String) {
+    let w = text_width(text) + 2 * BOX_PADDING;
+    let rx = if class == "terminal" {
+        BOX_HEIGHT / 2
+    } else {
+        0
+    };
+    if let Some(href) = href {
+        let _ = writeln!(out, r#"<a href="{}">"#, escape(href));
+    }
+    let _ = writeln!(
+        out,
+        r#"<rect class="{}" x="{}" y="{}" width="{}" height="{}" rx="{}"/>"#,
+        class,
+        x,
+        y - BOX_HEIGHT / 2,
+        w,
+        BOX_HEIGHT,
+        rx
+    );
+    let _ = writeln!(
+        out,
+        r#"<text x="{}" y="{}">{}</text>"#,
+        x + w / 2,
+        y + 4,
+        escape(text)
+    );
+    if href.is_some() {
+        out.push_str("</a>\n");
+    }
+}
+
+/// Escape text for inclusion in XML/HTML.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iden(s: &str) -> Box<Rhs> {
+        Box::new(Rhs::Identifier(s.into()))
+    }
+
+    fn term(s: &str) -> Box<Rhs> {
+        Box::new(Rhs::Terminal(s.into()))
+    }
+
+    #[test]
+    fn flattens_alternation_and_concatenation() {
+        let rhs = Rhs::Alternation(
+            iden("a"),
+            Box::new(Rhs::Alternation(
+                Box::new(Rhs::Concatenation(term("b"), iden("c"))),
+                iden("d"),
+            )),
+        );
+        let got = Diagram::from_rhs(&rhs);
+        let expected = Diagram::Choice(vec![
+            Diagram::NonTerminal("a".into()),
+            Diagram::Sequence(vec![
+                Diagram::Terminal("b".into()),
+                Diagram::NonTerminal("c".into()),
+            ]),
+            Diagram::NonTerminal("d".into()),
+        ]);
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn every_rhs_variant_renders() {
+        let rules = vec![
+            Production {
+                lhs: "optional".into(),
+                rhs: Rhs::Optional(term("a")),
+            },
+            Production {
+                lhs: "repeat".into(),
+                rhs: Rhs::Repeat(iden("b")),
+            },
+            Production {
+                lhs: "group".into(),
+                rhs: Rhs::Group(Box::new(Rhs::Alternation(term("c"), term("d")))),
+            },
+            Production {
+                lhs: "exception".into(),
+                rhs: Rhs::Exception(iden("letter"), term("x")),
+            },
+            Production {
+                lhs: "concat".into(),
+                rhs: Rhs::Concatenation(term("e"), iden("f")),
+            },
+        ];
+
+        for rule in &rules {
+            let svg = production_svg(rule);
+            assert!(svg.starts_with("<svg"), "{}", svg);
+            assert!(svg.trim_end().ends_with("</svg>"), "{}", svg);
+            assert!(svg.contains(&format!(">{}</text>", rule.lhs)), "{}", svg);
+        }
+
+        let svg = production_svg(&rules[3]);
+        assert!(svg.contains(r#"class="exception""#));
+        assert!(svg.contains(">except</text>"));
+        let svg = production_svg(&rules[2]);
+        assert!(svg.contains(r#"class="group""#));
+    }
+
+    #[test]
+    fn terminals_are_escaped() {
+        let rule = Production {
+            lhs: "lt".into(),
+            rhs: Rhs::Terminal("<&>".into()),
+        };
+        let svg = production_svg(&rule);
+        assert!(svg.contains(">&lt;&amp;&gt;</text>"), "{}", svg);
+    }
+
+    #[test]
+    fn choice_grows_downwards() {
+        let single = Diagram::from_rhs(&Rhs::Terminal("a".into()));
+        let choice = Diagram::from_rhs(&Rhs::Alternation(term("a"), term("b")));
+        assert_eq!(single.up(), choice.up());
+        assert!(choice.down() > single.down() + single.up());
+        assert!(choice.width() > single.width());
+    }
+
+    #[test]
+    fn html_index_links_rules() {
+        let g: Grammar = "a = b ; b = 'x' ;".parse().unwrap();
+        let html = grammar_html(&g);
+        assert!(html.contains(r##"<a href="#a">a</a>"##));
+        assert!(html.contains(r#"<h2 id="b">b</h2>"#));
+        // The reference to `b` inside `a` links to its diagram.
+        assert!(html.contains(r##"<a href="#b">"##));
+        assert_eq!(html.matches("<svg").count(), 2);
+    }
+}
@@ -0,0 +1,698 @@
+//! Semantics preserving rewrites of grammars.
+//!
+//! Every pass takes a grammar and returns a new grammar matching the same
+//! language. Passes that change the set of rules (`inline_productions`,
+//! `desugar`) will change the shape of trees produced by parsers derived from
+//! the grammar.
+//!
+//! Note that `left_factor` preserves the language described by the grammar,
+//! but not necessarily the behavior of an ordered choice (PEG) parser: `a | a ,
+//! b` becomes `a , [ b ]`, which will match `ab` where a PEG parser would stop
+//! after `a`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::fold_right;
+use crate::{CharClass, Grammar, Identifier, Lhs, Production, Rhs};
+
+/// Run the passes that keep every rule intact: `flatten_groups`,
+/// `collapse_char_sets` and `left_factor`.
+pub fn simplify(grammar: Grammar) -> Grammar {
+    let grammar = flatten_groups(grammar);
+    let grammar = collapse_char_sets(grammar);
+    let grammar = left_factor(grammar);
+    flatten_groups(grammar)
+}
+
+/// Remove groups that don't affect how a rule is parsed, and flatten nested
+/// alternations and concatenations into right associative chains.
+///
+/// The remaining groups are exactly those needed to write the grammar, so the
+/// result parses back to itself.
+pub fn flatten_groups(grammar: Grammar) -> Grammar {
+    map_rules(grammar, flatten)
+}
+
+/// Inline rules that are only referenced once, or whose rhs is a single
+/// identifier, terminal, or character class.
+///
+/// Rules that aren't referenced by any other rule are treated as entry points
+/// and kept. Recursive rules are never inlined into themselves.
+pub fn inline_productions(mut grammar: Grammar) -> Grammar {
+    loop {
+        let counts = reference_counts(&grammar);
+        let candidate = grammar.rules.iter().position(|rule| {
+            let name = &rule.lhs.0 .0;
+            let uses = counts.get(name.as_str()).copied().unwrap_or(0);
+            let trivial = matches!(
+                rule.rhs,
+                Rhs::Identifier(_) | Rhs::Terminal(_) | Rhs::Class(_)
+            );
+            uses > 0 && (uses == 1 || trivial) && !references(&rule.rhs, name)
+        });
+
+        let idx = match candidate {
+            Some(idx) => idx,
+            None => return flatten_groups(grammar),
+        };
+        let rule = grammar.rules.remove(idx);
+        for other in grammar.rules.iter_mut() {
+            let rhs = std::mem::replace(&mut other.rhs, Rhs::Terminal("".into()));
+            other.rhs = substitute(rhs, &rule.lhs.0, &rule.rhs);
+        }
+    }
+}
+
+/// Factor common prefixes out of adjacent alternatives.
+///
+/// `a , b | a , c` becomes `a , ( b | c )`, and `a | a , b` becomes `a , [ b
+/// ]`.
+pub fn left_factor(grammar: Grammar) -> Grammar {
+    map_rules(grammar, |rhs| flatten(factor(flatten(rhs))))
+}
+
+/// Merge adjacent alternatives that each match a single character into one
+/// character class.
+///
+/// `"a" | "b" | ? [c-z] ?` becomes `? [a-z] ?`.
+pub fn collapse_char_sets(grammar: Grammar) -> Grammar {
+    map_rules(grammar, |rhs| flatten(collapse(flatten(rhs))))
+}
+
+/// Rewrite options, repetitions and groups into helper rules, leaving each
+/// rule as an alternation of concatenations.
+///
+/// Helper rules are named after the rule they're created for, and placed
+/// directly after it:
+///
+/// ```text
+/// list = item , { "," , item } ;
+/// ```
+///
+/// becomes
+///
+/// ```text
+/// list = item , list_rep1 ;
+/// list_rep1 = "," , item , list_rep1 | "" ;
+/// ```
+///
+/// The empty alternative always comes last, so the result can also be used
+/// with ordered choice parsers. Exceptions have no plain BNF equivalent and
+/// are kept, with their operands moved into helper rules where needed.
+pub fn desugar(grammar: Grammar) -> Grammar {
+    let mut names: HashSet<String> = grammar.rules.iter().map(|r| r.lhs.to_string()).collect();
+    let mut rules = Vec::new();
+    for rule in grammar.rules {
+        let mut helpers = Helpers {
+            base: rule.lhs.to_string(),
+            count: 0,
+            names: &mut names,
+            rules: Vec::new(),
+        };
+        let alts = helpers.alternatives(rule.rhs);
+        let helper_rules = helpers.rules;
+        rules.push(Production {
+            lhs: rule.lhs,
+            rhs: build(alts),
+        });
+        for (name, alts) in helper_rules {
+            rules.push(Production {
+                lhs: Lhs(Identifier(name)),
+                rhs: build(alts),
+            });
+        }
+    }
+    Grammar { rules }
+}
+
+fn map_rules<F>(grammar: Grammar, f: F) -> Grammar
+where
+    F: Fn(Rhs) -> Rhs,
+{
+    Grammar {
+        rules: grammar
+            .rules
+            .into_iter()
+            .map(|rule| Production {
+                lhs: rule.lhs,
+                rhs: f(rule.rhs),
+            })
+            .collect(),
+    }
+}
+
+/// Wrap `rhs` in a group if it binds looser than `min`.
+fn regroup(rhs: Rhs, min: u8) -> Rhs {
+    if rhs.precedence() < min {
+        Rhs::Group(Box::new(rhs))
+    } else {
+        rhs
+    }
+}
+
+/// Collect the operands of an alternation, looking through groups.
+fn alternatives(rhs: Rhs, out: &mut Vec<Rhs>) {
+    match rhs {
+        Rhs::Alternation(rhs1, rhs2) => {
+            alternatives(*rhs1, out);
+            alternatives(*rhs2, out);
+        }
+        Rhs::Group(inner) => match *inner {
+            inner @ Rhs::Alternation(_, _) | inner @ Rhs::Group(_) => alternatives(inner, out),
+            inner => out.push(inner),
+        },
+        rhs => out.push(rhs),
+    }
+}
+
+/// Collect the operands of a concatenation, looking through groups.
+fn sequence(rhs: Rhs, out: &mut Vec<Rhs>) {
+    match rhs {
+        Rhs::Concatenation(rhs1, rhs2) => {
+            sequence(*rhs1, out);
+            sequence(*rhs2, out);
+        }
+        Rhs::Group(inner) => match *inner {
+            inner @ Rhs::Concatenation(_, _) | inner @ Rhs::Group(_) => sequence(inner, out),
+            inner => out.push(inner),
+        },
+        rhs => out.push(rhs),
+    }
+}
+
+/// Alternatives never need grouping, since nested alternations are always
+/// flattened.
+fn alternation_of(items: Vec<Rhs>) -> Rhs {
+    fold_right(items, Rhs::Alternation)
+}
+
+fn concatenation_of(items: Vec<Rhs>) -> Rhs {
+    let items: Vec<Rhs> = items.into_iter().map(|r| regroup(r, 2)).collect();
+    fold_right(items, Rhs::Concatenation)
+}
+
+fn flatten(rhs: Rhs) -> Rhs {
+    match rhs {
+        Rhs::Group(inner) => flatten(*inner),
+        Rhs::Optional(inner) => Rhs::Optional(Box::new(flatten(*inner))),
+        Rhs::Repeat(inner) => Rhs::Repeat(Box::new(flatten(*inner))),
+        Rhs::Exception(rhs1, rhs2) => Rhs::Exception(
+            Box::new(regroup(flatten(*rhs1), 3)),
+            Box::new(regroup(flatten(*rhs2), 2)),
+        ),
+        rhs @ Rhs::Alternation(_, _) => {
+            let mut items = Vec::new();
+            alternatives(rhs, &mut items);
+            let items: Vec<Rhs> = items.into_iter().map(flatten).collect();
+            // Flattening an item may expose a nested alternation.
+            let mut flat = Vec::new();
+            for item in items {
+                alternatives(item, &mut flat);
+            }
+            alternation_of(flat)
+        }
+        rhs @ Rhs::Concatenation(_, _) => {
+            let mut items = Vec::new();
+            sequence(rhs, &mut items);
+            let mut flat = Vec::new();
+            for item in items {
+                sequence(flatten(item), &mut flat);
+            }
+            concatenation_of(flat)
+        }
+        rhs => rhs,
+    }
+}
+
+fn factor(rhs: Rhs) -> Rhs {
+    match rhs {
+        Rhs::Group(inner) => Rhs::Group(Box::new(factor(*inner))),
+        Rhs::Optional(inner) => Rhs::Optional(Box::new(factor(*inner))),
+        Rhs::Repeat(inner) => Rhs::Repeat(Box::new(factor(*inner))),
+        Rhs::Exception(rhs1, rhs2) => {
+            Rhs::Exception(Box::new(factor(*rhs1)), Box::new(factor(*rhs2)))
+        }
+        Rhs::Concatenation(rhs1, rhs2) => {
+            Rhs::Concatenation(Box::new(factor(*rhs1)), Box::new(factor(*rhs2)))
+        }
+        rhs @ Rhs::Alternation(_, _) => {
+            let mut alts = Vec::new();
+            alternatives(rhs, &mut alts);
+            let seqs: Vec<Vec<Rhs>> = alts
+                .into_iter()
+                .map(|alt| {
+                    let mut seq = Vec::new();
+                    sequence(factor(alt), &mut seq);
+                    seq
+                })
+                .collect();
+            alternation_of(factor_sequences(seqs))
+        }
+        rhs => rhs,
+    }
+}
+
+/// Factor common prefixes out of runs of adjacent sequences with the same
+/// first item.
+fn factor_sequences(seqs: Vec<Vec<Rhs>>) -> Vec<Rhs> {
+    let mut out = Vec::new();
+    let mut seqs = seqs.into_iter().peekable();
+    while let Some(first) = seqs.next() {
+        let mut run = vec![first];
+        while let Some(next) = seqs.peek() {
+            if next.first() == run[0].first() {
+                run.push(seqs.next().unwrap());
+            } else {
+                break;
+            }
+        }
+
+        if run.len() == 1 {
+            out.push(concatenation_of(run.remove(0)));
+            continue;
+        }
+
+        // Longest prefix shared by every sequence in the run.
+        let mut prefix_len = run.iter().map(Vec::len).min().unwrap_or(0);
+        for seq in &run[1..] {
+            let shared = run[0].iter().zip(seq).take_while(|(a, b)| a == b).count();
+            prefix_len = prefix_len.min(shared);
+        }
+
+        let prefix: Vec<Rhs> = run[0][..prefix_len].to_vec();
+        let mut empty = false;
+        let mut rests = Vec::new();
+        for seq in run {
+            let rest: Vec<Rhs> = seq.into_iter().skip(prefix_len).collect();
+            if rest.is_empty() {
+                empty = true;
+            } else if !rests.contains(&rest) {
+                rests.push(rest);
+            }
+        }
+
+        let mut items = prefix;
+        if !rests.is_empty() {
+            let tail = alternation_of(factor_sequences(rests));
+            if empty {
+                items.push(Rhs::Optional(Box::new(tail)));
+            } else {
+                items.push(tail);
+            }
+        }
+        out.push(concatenation_of(items));
+    }
+    out
+}
+
+/// The character class matching the same single characters as `rhs`, if
+/// there is one.
+fn as_char_class(rhs: &Rhs) -> Option<CharClass> {
+    match rhs {
+        Rhs::Terminal(term) => {
+            let mut chars = term.0.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(CharClass {
+                    negated: false,
+                    ranges: vec![(c, c)],
+                }),
+                _ => None,
+            }
+        }
+        Rhs::Class(class) if !class.negated => Some(class.clone()),
+        _ => None,
+    }
+}
+
+/// Sort and merge overlapping or adjacent ranges.
+fn merge_ranges(mut ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(char, char)> = Vec::new();
+    for (lo, hi) in ranges {
+        match merged.last_mut() {
+            Some(last) if (lo as u32) <= (last.1 as u32).saturating_add(1) => {
+                if hi > last.1 {
+                    last.1 = hi;
+                }
+            }
+            _ => merged.push((lo, hi)),
+        }
+    }
+    merged
+}
+
+fn collapse(rhs: Rhs) -> Rhs {
+    match rhs {
+        Rhs::Group(inner) => Rhs::Group(Box::new(collapse(*inner))),
+        Rhs::Optional(inner) => Rhs::Optional(Box::new(collapse(*inner))),
+        Rhs::Repeat(inner) => Rhs::Repeat(Box::new(collapse(*inner))),
+        Rhs::Exception(rhs1, rhs2) => {
+            Rhs::Exception(Box::new(collapse(*rhs1)), Box::new(collapse(*rhs2)))
+        }
+        Rhs::Concatenation(rhs1, rhs2) => {
+            Rhs::Concatenation(Box::new(collapse(*rhs1)), Box::new(collapse(*rhs2)))
+        }
+        rhs @ Rhs::Alternation(_, _) => {
+            let mut alts = Vec::new();
+            alternatives(rhs, &mut alts);
+
+            let mut out = Vec::new();
+            let mut run = Vec::new();
+            for alt in alts {
+                let alt = collapse(alt);
+                if as_char_class(&alt).is_some() {
+                    run.push(alt);
+                } else {
+                    merge_run(&mut run, &mut out);
+                    out.push(alt);
+                }
+            }
+            merge_run(&mut run, &mut out);
+            alternation_of(out)
+        }
+        rhs => rhs,
+    }
+}
+
+/// Move a run of single character alternatives into `out`, merging them into
+/// one class if there's more than one.
+fn merge_run(run: &mut Vec<Rhs>, out: &mut Vec<Rhs>) {
+    if run.len() < 2 {
+        out.append(run);
+        return;
+    }
+    let ranges = run
+        .drain(..)
+        .filter_map(|rhs| as_char_class(&rhs))
+        .flat_map(|class| class.ranges)
+        .collect();
+    out.push(Rhs::Class(CharClass {
+        negated: false,
+        ranges: merge_ranges(ranges),
+    }));
+}
+
+/// Count references to each rule, ignoring references a rule makes to itself.
+fn reference_counts(grammar: &Grammar) -> HashMap<&str, usize> {
+    fn walk<'a>(rhs: &'a Rhs, owner: &str, counts: &mut HashMap<&'a str, usize>) {
+        match rhs {
+            Rhs::Identifier(iden) => {
+                if iden.0 != owner {
+                    *counts.entry(iden.0.as_str()).or_insert(0) += 1;
+                }
+            }
+            Rhs::Terminal(_) | Rhs::Class(_) => (),
+            Rhs::Optional(inner) | Rhs::Repeat(inner) | Rhs::Group(inner) => {
+                walk(inner, owner, counts)
+            }
+            Rhs::Exception(rhs1, rhs2)
+            | Rhs::Alternation(rhs1, rhs2)
+            | Rhs::Concatenation(rhs1, rhs2) => {
+                walk(rhs1, owner, counts);
+                walk(rhs2, owner, counts);
+            }
+        }
+    }
+
+    let mut counts = HashMap::new();
+    for rule in &grammar.rules {
+        walk(&rule.rhs, &rule.lhs.0 .0, &mut counts);
+    }
+    counts
+}
+
+fn references(rhs: &Rhs, name: &str) -> bool {
+    match rhs {
+        Rhs::Identifier(iden) => iden.0 == name,
+        Rhs::Terminal(_) | Rhs::Class(_) => false,
+        Rhs::Optional(inner) | Rhs::Repeat(inner) | Rhs::Group(inner) => references(inner, name),
+        Rhs::Exception(rhs1, rhs2)
+        | Rhs::Alternation(rhs1, rhs2)
+        | Rhs::Concatenation(rhs1, rhs2) => references(rhs1, name) || references(rhs2, name),
+    }
+}
+
+/// Replace references to `name` with a group containing `body`.
+fn substitute(rhs: Rhs, name: &Identifier, body: &Rhs) -> Rhs {
+    let sub = |rhs: Box<Rhs>| Box::new(substitute(*rhs, name, body));
+    match rhs {
+        Rhs::Identifier(ref iden) if iden == name => Rhs::Group(Box::new(body.clone())),
+        Rhs::Optional(inner) => Rhs::Optional(sub(inner)),
+        Rhs::Repeat(inner) => Rhs::Repeat(sub(inner)),
+        Rhs::Group(inner) => Rhs::Group(sub(inner)),
+        Rhs::Exception(rhs1, rhs2) => Rhs::Exception(sub(rhs1), sub(rhs2)),
+        Rhs::Alternation(rhs1, rhs2) => Rhs::Alternation(sub(rhs1), sub(rhs2)),
+        Rhs::Concatenation(rhs1, rhs2) => Rhs::Concatenation(sub(rhs1), sub(rhs2)),
+        rhs => rhs,
+    }
+}
+
+/// Build an alternation of concatenations, using an empty terminal for empty
+/// sequences.
+fn build(alts: Vec<Vec<Rhs>>) -> Rhs {
+    let alts: Vec<Rhs> = alts
+        .into_iter()
+        .map(|seq| {
+            if seq.is_empty() {
+                Rhs::Terminal("".into())
+            } else {
+                fold_right(seq, Rhs::Concatenation)
+            }
+        })
+        .collect();
+    fold_right(alts, Rhs::Alternation)
+}
+
+/// Collects helper rules while desugaring a single rule.
+struct Helpers<'a> {
+    base: String,
+    count: usize,
+    names: &'a mut HashSet<String>,
+    rules: Vec<(String, Vec<Vec<Rhs>>)>,
+}
+
+impl<'a> Helpers<'a> {
+    /// Reserve a uniquely named helper rule, returning its index and name.
+    fn reserve(&mut self, kind: &str) -> (usize, String) {
+        let name = loop {
+            self.count += 1;
+            let name = format!("{}_{}{}", self.base, kind, self.count);
+            if !self.names.contains(&name) {
+                break name;
+            }
+        };
+        self.names.insert(name.clone());
+        self.rules.push((name.clone(), Vec::new()));
+        (self.rules.len() - 1, name)
+    }
+
+    fn helper(&mut self, kind: &str, rhs: Rhs) -> Rhs {
+        let (idx, name) = self.reserve(kind);
+        self.rules[idx].1 = self.alternatives(rhs);
+        Rhs::Identifier(Identifier(name))
+    }
+
+    fn alternatives(&mut self, rhs: Rhs) -> Vec<Vec<Rhs>> {
+        let mut alts = Vec::new();
+        alternatives(rhs, &mut alts);
+        alts.into_iter().map(|alt| self.sequence(alt)).collect()
+    }
+
+    fn sequence(&mut self, rhs: Rhs) -> Vec<Rhs> {
+        let mut items = Vec::new();
+        sequence(rhs, &mut items);
+        let mut seq = Vec::new();
+        for item in items {
+            match item {
+                Rhs::Terminal(ref term) if term.0.is_empty() => (),
+                rhs @ Rhs::Identifier(_) | rhs @ Rhs::Terminal(_) | rhs @ Rhs::Class(_) => {
+                    seq.push(rhs)
+                }
+                Rhs::Optional(inner) => {
+                    let (idx, name) = self.reserve("opt");
+                    let mut alts = self.alternatives(*inner);
+                    alts.push(Vec::new());
+                    self.rules[idx].1 = alts;
+                    seq.push(Rhs::Identifier(Identifier(name)));
+                }
+                Rhs::Repeat(inner) => {
+                    let (idx, name) = self.reserve("rep");
+                    let mut alts = self.alternatives(*inner);
+                    for alt in alts.iter_mut() {
+                        alt.push(Rhs::Identifier(Identifier(name.clone())));
+                    }
+                    alts.push(Vec::new());
+                    self.rules[idx].1 = alts;
+                    seq.push(Rhs::Identifier(Identifier(name)));
+                }
+                Rhs::Exception(rhs1, rhs2) => {
+                    let rhs1 = self.atom(*rhs1);
+                    let rhs2 = self.atom(*rhs2);
+                    seq.push(Rhs::Exception(Box::new(rhs1), Box::new(rhs2)));
+                }
+                rhs => {
+                    // An alternation (possibly grouped) within a sequence.
+                    let mut alts = Vec::new();
+                    alternatives(rhs, &mut alts);
+                    if alts.len() == 1 {
+                        seq.append(&mut self.sequence(alts.remove(0)));
+                    } else {
+                        seq.push(self.helper("group", fold_right(alts, Rhs::Alternation)));
+                    }
+                }
+            }
+        }
+        seq
+    }
+
+    /// Reduce an exception operand to a single item.
+    fn atom(&mut self, rhs: Rhs) -> Rhs {
+        match rhs {
+            rhs @ Rhs::Identifier(_) | rhs @ Rhs::Terminal(_) | rhs @ Rhs::Class(_) => rhs,
+            Rhs::Group(inner) => self.atom(*inner),
+            rhs => self.helper("group", rhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_pass<F>(pass: F, input: &str, expected: &str)
+    where
+        F: Fn(Grammar) -> Grammar,
+    {
+        let g: Grammar = input.parse().unwrap();
+        let got = pass(g);
+        assert_eq!(got.to_string(), expected, "input:\n{}", input);
+        // Passes must always produce something that can be written and read
+        // back.
+        let reparsed: Grammar = got.to_string().parse().unwrap();
+        assert_eq!(reparsed.to_string(), expected);
+    }
+
+    #[test]
+    fn flatten_nested_groups() {
+        assert_pass(
+            flatten_groups,
+            "a = ( ( b ) ) | ( c , ( d , e ) ) | [ ( f ) ] ;",
+            "a = b | c , d , e | [ f ] ;\n",
+        );
+    }
+
+    #[test]
+    fn flatten_keeps_needed_groups() {
+        assert_pass(
+            flatten_groups,
+            "a = ( b | ( c ) ) , d ;",
+            "a = ( b | c ) , d ;\n",
+        );
+        assert_pass(
+            flatten_groups,
+            "a = ( b , c ) - ( d ) ;",
+            "a = ( b , c ) - d ;\n",
+        );
+    }
+
+    #[test]
+    fn flatten_is_lossless() {
+        let g: Grammar = "a = ( ( b | c ) | d ) , ( e , f ) ;".parse().unwrap();
+        let flat = flatten_groups(g);
+        let reparsed: Grammar = flat.to_string().parse().unwrap();
+        assert_eq!(flat, reparsed);
+    }
+
+    #[test]
+    fn inline_single_use() {
+        assert_pass(
+            inline_productions,
+            "start = a , b , b ; a = 'x' | 'y' ; b = c ; c = 'z' , { 'z' } ;",
+            "start = ( \"x\" | \"y\" ) , c , c ;\nc = \"z\" , { \"z\" } ;\n",
+        );
+    }
+
+    #[test]
+    fn inline_skips_recursive() {
+        assert_pass(
+            inline_productions,
+            "start = list ; list = 'x' , [ list ] ;",
+            "start = list ;\nlist = \"x\" , [ list ] ;\n",
+        );
+    }
+
+    #[test]
+    fn left_factor_common_prefix() {
+        assert_pass(
+            left_factor,
+            "a = 'if' , e , 'then' , e | 'if' , e , 'then' , e , 'else' , e | x ;",
+            "a = \"if\" , e , \"then\" , e , [ \"else\" , e ] | x ;\n",
+        );
+        assert_pass(
+            left_factor,
+            "a = b , c | b , d | e | b ;",
+            "a = b , ( c | d ) | e | b ;\n",
+        );
+    }
+
+    #[test]
+    fn collapse_single_characters() {
+        assert_pass(
+            collapse_char_sets,
+            "d = '0' | '1' | '2' | ? [3-9] ? | 'ab' | 'c' ;",
+            "d = ? [0-9] ? | \"ab\" | \"c\" ;\n",
+        );
+        assert_pass(
+            collapse_char_sets,
+            "l = 'a' | 'c' | 'b' | 'x' ;",
+            "l = ? [a-cx] ? ;\n",
+        );
+    }
+
+    #[test]
+    fn desugar_options_and_repetitions() {
+        assert_pass(
+            desugar,
+            "list = '[' , [ item , { ',' , item } ] , ']' ;",
+            "list = \"[\" , list_opt1 , \"]\" ;\n\
+             list_opt1 = item , list_rep2 | \"\" ;\n\
+             list_rep2 = \",\" , item , list_rep2 | \"\" ;\n",
+        );
+    }
+
+    #[test]
+    fn desugar_groups_and_exceptions() {
+        assert_pass(
+            desugar,
+            "a = b , ( c | d ) , ( e , f ) | ( g | h ) ; b = ( x , y ) - z ;",
+            "a = b , a_group1 , e , f | g | h ;\n\
+             a_group1 = c | d ;\n\
+             b = b_group1 - z ;\n\
+             b_group1 = x , y ;\n",
+        );
+    }
+
+    #[test]
+    fn desugar_avoids_name_clashes() {
+        assert_pass(
+            desugar,
+            "a = [ b ] ; a_opt1 = 'x' ;",
+            "a = a_opt2 ;\na_opt2 = b | \"\" ;\na_opt1 = \"x\" ;\n",
+        );
+    }
+
+    #[test]
+    fn simplify_sml_style_rules() {
+        let g: Grammar = "
+            digit = '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' ;
+            exp = ( ( atexp ) ) , ':' , ty | atexp ;
+        "
+        .parse()
+        .unwrap();
+        let got = simplify(g);
+        assert_eq!(
+            got.to_string(),
+            "digit = ? [0-9] ? ;\nexp = atexp , [ \":\" , ty ] ;\n"
+        );
+    }
+}
@@ -3,17 +3,33 @@ use std::fmt::Debug;
 
 mod position;
 mod reserve;
+mod source;
 mod span;
 mod state;
 mod tokens;
+mod tree;
 
+pub use source::{FileId, Location, SourceFile, SourceMap};
+pub use span::{RelativeLocation, Span};
 pub use state::{DfsParseTreeIterator, State, StateResult};
 pub use tokens::Token;
+pub use tree::Node;
 
 pub trait ParserRule: Copy + Debug + Eq {}
 
 impl<T: Copy + Debug + Eq> ParserRule for T {}
 
 pub trait Parser<R: ParserRule> {
+    /// Parse `input` starting from `rule`. Token spans belong to
+    /// `FileId::ANON`.
     fn parse(rule: R, input: &str) -> Result<DfsParseTreeIterator<R>>;
+
+    /// Parse a file from a source map starting from `rule`.
+    fn parse_file(rule: R, sources: &SourceMap, file: FileId) -> Result<Vec<Token<R>>> {
+        let toks = Self::parse(rule, sources.source(file))?;
+        Ok(toks
+            .into_iter()
+            .map(|tok| Token::new(tok.rule, Span { file, ..tok.span }))
+            .collect())
+    }
 }
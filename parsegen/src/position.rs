@@ -34,7 +34,22 @@ impl<'a> Position<'a> {
         }
     }
 
+    /// Check if the next character satisfies `f`, moving past it on match.
+    pub fn match_char<F>(&mut self, f: F) -> bool
+    where
+        F: Fn(char) -> bool,
+    {
+        match self.input[self.idx..].chars().next() {
+            Some(c) if f(c) => {
+                self.idx += c.len_utf8();
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Move current index forward some amount.
+    #[allow(dead_code)]
     fn skip(&mut self, n: usize) -> bool {
         if self.idx + n < self.input.len() {
             self.idx += n;
@@ -65,6 +80,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn position_match_char() {
+        let mut c = Position::new("éa", 0).unwrap();
+        assert!(!c.match_char(|c| c == 'a'));
+        assert!(c.match_char(|c| c == 'é'));
+        assert_eq!(c.idx, 2);
+        assert!(c.match_char(|c| c == 'a'));
+        assert!(!c.match_char(|_| true));
+    }
+
     #[test]
     fn position_match_str_idx_multiple() {
         let mut c = Position::new("hello", 0).unwrap();
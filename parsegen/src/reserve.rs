@@ -19,10 +19,21 @@ impl<T> ReserveVec<T> {
     }
 
     /// Identical to a regular vector push.
+    #[allow(dead_code)]
     pub fn push(&mut self, value: T) {
         self.vec.push(VecItem::Value(value));
     }
 
+    /// Number of values and reserved positions in the vector.
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    /// Drop everything pushed or reserved after the first `len` items.
+    pub fn truncate(&mut self, len: usize) {
+        self.vec.truncate(len);
+    }
+
     /// Reserve the next position in the vector.
     pub fn reserve_next(&mut self) -> ReservePos {
         let idx = self.vec.len();
@@ -36,9 +47,9 @@ impl<T> ReserveVec<T> {
     }
 }
 
-impl<T> Into<Vec<T>> for ReserveVec<T> {
-    fn into(self) -> Vec<T> {
-        self.vec
+impl<T> From<ReserveVec<T>> for Vec<T> {
+    fn from(r: ReserveVec<T>) -> Vec<T> {
+        r.vec
             .into_iter()
             .filter_map(|v| match v {
                 VecItem::Value(v) => Some(v),
@@ -89,4 +100,18 @@ mod tests {
         let v: Vec<_> = r.into();
         assert_eq!(v, vec![3, 1, 4, 5, 2, 6]);
     }
+
+    #[test]
+    fn truncate_drops_later_reservations() {
+        let mut r = ReserveVec::new();
+        let p1 = r.reserve_next();
+        r.push(1);
+        let len = r.len();
+        r.reserve_next();
+        r.push(2);
+        r.truncate(len);
+        r.insert_at_reserved(p1, 3);
+        let v: Vec<_> = r.into();
+        assert_eq!(v, vec![3, 1]);
+    }
 }
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+use crate::span::Span;
+
+/// Identifies a file in a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+impl FileId {
+    /// The file of input that was parsed without a source map.
+    pub const ANON: FileId = FileId(u32::MAX);
+}
+
+/// A file added to a `SourceMap`.
+#[derive(Debug)]
+pub struct SourceFile {
+    name: String,
+    src: Rc<str>,
+    /// Byte offsets of the start of each line.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    /// The 1-based line and column of a byte offset. Columns count
+    /// characters, not bytes.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.line_starts[line];
+        let col = self.src[start..offset].chars().count();
+        (line + 1, col + 1)
+    }
+}
+
+/// A human readable location, e.g. `src/main.sml:3:14`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl<'a> Display for Location<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// The set of files making up a program.
+///
+/// File contents are interned, so adding the same file twice returns the same
+/// id, and files with identical contents share storage.
+///
+/// # Examples
+///
+/// ```
+/// use parsegen::{SourceMap, Span};
+///
+/// let mut sources = SourceMap::new();
+/// let file = sources.add("main.sml", "val x = 1\nval y = x");
+/// let span = Span::new(file, 14, 15);
+/// assert_eq!(sources.slice(span), "y");
+/// assert_eq!(sources.location(span).to_string(), "main.sml:2:5");
+/// ```
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    contents: HashSet<Rc<str>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file, returning its id.
+    pub fn add(&mut self, name: &str, src: &str) -> FileId {
+        let src = match self.contents.get(src) {
+            Some(src) => src.clone(),
+            None => {
+                let src: Rc<str> = Rc::from(src);
+                self.contents.insert(src.clone());
+                src
+            }
+        };
+
+        if let Some(idx) = self
+            .files
+            .iter()
+            .position(|f| f.name == name && Rc::ptr_eq(&f.src, &src))
+        {
+            return FileId(idx as u32);
+        }
+
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        self.files.push(SourceFile {
+            name: name.to_owned(),
+            src,
+            line_starts,
+        });
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    /// Get a file. Panics if the id isn't from this source map.
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    /// Get the contents of a file.
+    pub fn source(&self, id: FileId) -> &str {
+        self.file(id).src()
+    }
+
+    /// Get the text covered by a span.
+    pub fn slice(&self, span: Span) -> &str {
+        span.as_str(self.source(span.file))
+    }
+
+    /// Get the location of the start of a span.
+    pub fn location(&self, span: Span) -> Location<'_> {
+        let file = self.file(span.file);
+        let (line, col) = file.line_col(span.start);
+        Location {
+            file: file.name(),
+            line,
+            col,
+        }
+    }
+
+    /// Iterate over all files and their ids, in the order they were added.
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(idx, file)| (FileId(idx as u32), file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let mut sources = SourceMap::new();
+        let a = sources.add("a.sml", "val x = 1");
+        let b = sources.add("b.sml", "val x = 1");
+        let a2 = sources.add("a.sml", "val x = 1");
+        assert_eq!(a, a2);
+        assert_ne!(a, b);
+        assert!(Rc::ptr_eq(&sources.file(a).src, &sources.file(b).src));
+        assert_eq!(sources.files().count(), 2);
+    }
+
+    #[test]
+    fn line_col() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("x.sml", "ab\n\nλc\n");
+        let f = sources.file(file);
+        assert_eq!(f.line_col(0), (1, 1));
+        assert_eq!(f.line_col(2), (1, 3));
+        assert_eq!(f.line_col(3), (2, 1));
+        assert_eq!(f.line_col(4), (3, 1));
+        // 'λ' is two bytes, but one column.
+        assert_eq!(f.line_col(6), (3, 2));
+        assert_eq!(f.line_col(8), (4, 1));
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::anyhow;
 
 use crate::position::Position;
+use crate::source::FileId;
 
 /// Describes the location of a span relative to another span.
 #[derive(Debug, PartialEq)]
@@ -14,17 +15,30 @@ pub enum RelativeLocation {
     Encompasses,
 }
 
-/// A region over a string.
-#[derive(Debug)]
-pub struct Span<'a> {
-    pub s: &'a str,
+/// A byte range within a file.
+///
+/// Spans don't borrow the text they refer to, so they can be freely copied
+/// and kept around after parsing. Use `as_str` or a `SourceMap` to get the
+/// text back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
     pub start: usize,
     pub end: usize,
 }
 
-impl<'a> Span<'a> {
-    pub fn from_positions(start: &Position<'a>, end: &Position<'a>) -> Result<Self, anyhow::Error> {
-        if start.input != end.input {
+impl Span {
+    pub fn new(file: FileId, start: usize, end: usize) -> Self {
+        debug_assert!(start <= end, "start {} after end {}", start, end);
+        Span { file, start, end }
+    }
+
+    pub fn from_positions(
+        file: FileId,
+        start: &Position<'_>,
+        end: &Position<'_>,
+    ) -> Result<Self, anyhow::Error> {
+        if !std::ptr::eq(start.input, end.input) {
             Err(anyhow!(
                 "positions on different strings: '{}', '{}'",
                 start.input,
@@ -37,30 +51,45 @@ impl<'a> Span<'a> {
                 end.idx
             ))
         } else {
-            Ok(Self {
-                s: start.input,
-                start: start.idx,
-                end: end.idx,
-            })
+            Ok(Span::new(file, start.idx, end.idx))
         }
     }
 
-    pub fn as_str(&self) -> &'a str {
-        &self.s[self.start..self.end]
+    /// Returns the text covered by this span. `input` must be the contents of
+    /// the span's file.
+    pub fn as_str<'a>(&self, input: &'a str) -> &'a str {
+        &input[self.start..self.end]
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The smallest span covering both this span and `other`. Both spans must
+    /// be in the same file.
+    pub fn to(&self, other: Span) -> Span {
+        debug_assert_eq!(self.file, other.file);
+        Span::new(
+            self.file,
+            self.start.min(other.start),
+            self.end.max(other.end),
+        )
     }
 
     /// Describes this span's location relative to `other`.
     ///
-    /// Spans must be referencing the same input. Spans must not partially
+    /// Spans must be referencing the same file. Spans must not partially
     /// overlap.
-    ///
-    /// TODO: Might be helpful using this in the parse tree.
     pub fn relative_location(&self, other: &Self) -> Result<RelativeLocation, anyhow::Error> {
-        if self.s != other.s {
+        if self.file != other.file {
             return Err(anyhow!(
-                "span string references differ, self: '{}', other: '{}'",
-                self.s,
-                other.s
+                "spans in different files, self: {:?}, other: {:?}",
+                self.file,
+                other.file
             ));
         }
 
@@ -82,50 +111,32 @@ impl<'a> Span<'a> {
     }
 
     /// Check if this span contains the entirety of the other span. Both spans
-    /// should be referencing the same input.
+    /// should be referencing the same file.
     pub fn contains(&self, other: &Self) -> Result<bool, anyhow::Error> {
-        if self.s != other.s {
+        if self.file != other.file {
             return Err(anyhow!(
-                "span inputs differ, self: '{}', other: '{}'",
-                self.s,
-                other.s
+                "spans in different files, self: {:?}, other: {:?}",
+                self.file,
+                other.file
             ));
         }
         Ok(self.start <= other.start && self.end >= other.end)
     }
 }
 
-impl<'a> PartialEq for Span<'a> {
-    fn eq(&self, other: &Span<'a>) -> bool {
-        self.as_str() == other.as_str()
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn span_relative_location() {
-        let input = "hello world";
+        let file = FileId::ANON;
         // "hello"
-        let a = Span {
-            s: input,
-            start: 0,
-            end: 5,
-        };
+        let a = Span::new(file, 0, 5);
         // " world"
-        let b = Span {
-            s: input,
-            start: 5,
-            end: 11,
-        };
+        let b = Span::new(file, 5, 11);
         // "hello world"
-        let c = Span {
-            s: input,
-            start: 0,
-            end: 11,
-        };
+        let c = Span::new(file, 0, 11);
 
         assert_eq!(RelativeLocation::Before, a.relative_location(&b).unwrap());
         assert_eq!(RelativeLocation::After, b.relative_location(&a).unwrap());
@@ -139,5 +150,20 @@ mod tests {
             RelativeLocation::Encompasses,
             c.relative_location(&b).unwrap()
         );
+        assert_eq!(a.to(b), c);
+        assert_eq!(c.as_str("hello world"), "hello world");
+    }
+
+    #[test]
+    fn spans_in_different_files() {
+        let mut sources = crate::SourceMap::new();
+        let f1 = sources.add("a.sml", "val x = 1");
+        let f2 = sources.add("b.sml", "val x = 1");
+        let a = Span::new(f1, 0, 3);
+        let b = Span::new(f2, 0, 3);
+        // Same text, but not the same span.
+        assert_ne!(a, b);
+        assert!(a.relative_location(&b).is_err());
+        assert!(a.contains(&b).is_err());
     }
 }
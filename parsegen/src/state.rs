@@ -1,24 +1,36 @@
-use anyhow::anyhow; // TODO: Proper errors
-use std::cmp::Ordering;
-
-use crate::{position::Position, reserve::ReserveVec, span::Span, ParserRule, Token};
+use crate::{
+    position::Position, reserve::ReserveVec, source::FileId, span::Span, tree::Node, ParserRule,
+    Token,
+};
 
 pub type StateResult<T> = Result<T, T>;
 
 /// Parser state.
 #[derive(Debug)]
 pub struct State<'a, R: ParserRule> {
-    /// A list of tokens that have been matched.
-    tokens: ReserveVec<Token<'a, R>>,
+    /// A list of tokens that have been matched, along with how deeply they're
+    /// nested.
+    tokens: ReserveVec<(Token<R>, usize)>,
+    /// The number of rules currently being tokenized.
+    depth: usize,
     cursor: Position<'a>,
+    /// The file spans are attributed to.
+    file: FileId,
 }
 
 impl<'a, R: ParserRule> State<'a, R> {
     pub fn new(input: &'a str) -> Result<Self, anyhow::Error> {
+        Self::with_file(input, FileId::ANON)
+    }
+
+    /// Create a state for parsing `input`, which is the contents of `file`.
+    pub fn with_file(input: &'a str, file: FileId) -> Result<Self, anyhow::Error> {
         let cursor = Position::new(input, 0)?;
         Ok(State {
             tokens: ReserveVec::new(),
+            depth: 0,
             cursor,
+            file,
         })
     }
 
@@ -63,7 +75,7 @@ impl<'a, R: ParserRule> State<'a, R> {
     /// assert_eq!(toks[6].rule(), Rule::b);
     /// assert_eq!(toks[7].rule(), Rule::a);
     /// ```
-    pub fn into_parse_tree_iter(self) -> DfsParseTreeIterator<'a, R> {
+    pub fn into_parse_tree_iter(self) -> DfsParseTreeIterator<R> {
         DfsParseTreeIterator { vec: self.tokens }
     }
 
@@ -71,73 +83,145 @@ impl<'a, R: ParserRule> State<'a, R> {
     /// from the function will result in an unmodified state.
     ///
     /// Internally this tracks tokens in a DFS-like fashion.
-    pub fn tokenize<F>(mut self: Self, rule: R, f: F) -> StateResult<Self>
+    pub fn tokenize<F>(self, rule: R, f: F) -> StateResult<Self>
     where
         F: Fn(Self) -> StateResult<Self>,
     {
         // Keep track of starting position so we can keep an accurate span for
         // the rule.
         let start = self.cursor.clone();
+        let file = self.file;
 
-        // Reserve position for token we're currently parsing.
-        let pos = self.tokens.reserve_next();
+        self.checkpoint(|mut state| {
+            // Reserve position for token we're currently parsing.
+            let pos = state.tokens.reserve_next();
+            let depth = state.depth;
 
-        match f(self) {
-            Ok(mut state) => {
-                let end = state.cursor.clone();
-                // TODO: Figure out good way to preserve state. Unwrapping to
-                // avoid thinking about for now.
-                let span = Span::from_positions(&start, &end).unwrap();
-                let token = Token::new(rule, span);
-
-                // Inserting at the reserved position gurantees that 'parent'
-                // tokens come before their children. And since we're parsing
-                // left to right, sibling tokens are ordered left to right.
-                state.tokens.insert_at_reserved(pos, token);
-
-                Ok(state)
-            }
-            Err(state) => Err(state),
-        }
+            state.depth += 1;
+            let mut state = f(state).map_err(|mut state| {
+                state.depth = depth;
+                state
+            })?;
+            state.depth = depth;
+            let end = state.cursor.clone();
+            // Both positions come from the same input, and the cursor only
+            // moves forward.
+            let span = Span::from_positions(file, &start, &end).unwrap();
+            let token = Token::new(rule, span);
+
+            // Inserting at the reserved position gurantees that 'parent'
+            // tokens come before their children. And since we're parsing
+            // left to right, sibling tokens are ordered left to right.
+            state.tokens.insert_at_reserved(pos, (token, depth));
+
+            Ok(state)
+        })
     }
 
-    /// Apply a function to state, returning the result vebatim.
-    pub fn apply<F>(self: Self, f: F) -> StateResult<Self>
+    /// Apply a function to state. If the function fails, any input it
+    /// consumed and tokens it produced are discarded.
+    pub fn apply<F>(self, f: F) -> StateResult<Self>
     where
         F: FnOnce(Self) -> StateResult<Self>,
     {
-        f(self)
+        self.checkpoint(f)
     }
 
-    /// Repeatedly applies some func to state until the first error.
-    pub fn repeat<F>(self: Self, f: F) -> StateResult<Self>
+    /// Repeatedly applies some func to state until the first error, or until
+    /// an application stops consuming input.
+    pub fn repeat<F>(self, f: F) -> StateResult<Self>
     where
         F: Fn(Self) -> StateResult<Self>,
     {
-        let mut result = f(self);
+        let mut state = self;
         loop {
-            match result {
-                Ok(state) => result = f(state),
-                Err(state) => return Ok(state),
+            let idx = state.cursor.idx;
+            match state.checkpoint(&f) {
+                Ok(next) if next.cursor.idx == idx => return Ok(next),
+                Ok(next) => state = next,
+                Err(next) => return Ok(next),
             }
         }
     }
 
     /// Attempt to apply some func to state, returning Ok regardless of what the
     /// function returns.
-    pub fn optional<F>(self: Self, f: F) -> StateResult<Self>
+    pub fn optional<F>(self, f: F) -> StateResult<Self>
     where
         F: FnOnce(Self) -> StateResult<Self>,
     {
-        match f(self) {
+        match self.checkpoint(f) {
             Ok(state) => Ok(state),
             Err(state) => Ok(state),
         }
     }
 
+    /// Apply `f`, failing if `g` matches exactly the same input as `f` does.
+    ///
+    /// This is how exceptions (`a - b`) are parsed. Anything matched by `g` is
+    /// discarded.
+    pub fn except<F, G>(self, f: F, g: G) -> StateResult<Self>
+    where
+        F: FnOnce(Self) -> StateResult<Self>,
+        G: FnOnce(Self) -> StateResult<Self>,
+    {
+        let start = self.cursor.clone();
+        let len = self.tokens.len();
+
+        let (state, excluded) = match self.checkpoint(g) {
+            Ok(mut state) => {
+                let end = state.cursor.idx;
+                state.restore(start.clone(), len);
+                (state, Some(end))
+            }
+            Err(state) => (state, None),
+        };
+
+        match state.checkpoint(f) {
+            Ok(mut state) if Some(state.cursor.idx) == excluded => {
+                state.restore(start, len);
+                Err(state)
+            }
+            res => res,
+        }
+    }
+
+    /// Apply `f`, restoring the cursor and tokens to how they were before if
+    /// it fails.
+    fn checkpoint<F>(self, f: F) -> StateResult<Self>
+    where
+        F: FnOnce(Self) -> StateResult<Self>,
+    {
+        let cursor = self.cursor.clone();
+        let len = self.tokens.len();
+        f(self).map_err(|mut state| {
+            state.restore(cursor, len);
+            state
+        })
+    }
+
+    fn restore(&mut self, cursor: Position<'a>, len: usize) {
+        self.cursor = cursor;
+        self.tokens.truncate(len);
+    }
+
+    /// Attempt to match a single character falling within (or, if `negated`,
+    /// outside of) the inclusive `ranges`.
+    pub fn match_class(mut self, ranges: &[(char, char)], negated: bool) -> StateResult<Self> {
+        let matched = self.cursor.match_char(|c| {
+            let in_ranges = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+            in_ranges != negated
+        });
+        if matched {
+            Ok(self)
+        } else {
+            Err(self)
+        }
+    }
+
     /// Attempt to match the given string on input. State is updated only if the
     /// string successfully matches.
-    pub fn match_str(mut self: Self, s: &str) -> StateResult<Self> {
+    pub fn match_str(mut self, s: &str) -> StateResult<Self> {
         if self.cursor.match_str(s) {
             Ok(self)
         } else {
@@ -147,16 +231,46 @@ impl<'a, R: ParserRule> State<'a, R> {
 }
 
 /// An iterator over the generated parse tree. Iteration is done via DFS.
-pub struct DfsParseTreeIterator<'a, R: ParserRule> {
-    vec: ReserveVec<Token<'a, R>>,
+pub struct DfsParseTreeIterator<R: ParserRule> {
+    vec: ReserveVec<(Token<R>, usize)>,
+}
+
+impl<R: ParserRule> DfsParseTreeIterator<R> {
+    /// Build the parse tree, returning the top level nodes.
+    pub fn into_tree(self) -> Vec<Node<R>> {
+        let toks: Vec<_> = self.vec.into();
+        // Nodes that are still waiting for children, outermost first.
+        let mut stack: Vec<Node<R>> = Vec::new();
+        let mut roots = Vec::new();
+
+        for (token, depth) in toks {
+            while stack.len() > depth {
+                let node = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => roots.push(node),
+                }
+            }
+            stack.push(Node::new(token));
+        }
+        while let Some(node) = stack.pop() {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => roots.push(node),
+            }
+        }
+        roots
+    }
 }
 
-impl<'a, R: ParserRule> IntoIterator for DfsParseTreeIterator<'a, R> {
-    type Item = Token<'a, R>;
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+impl<R: ParserRule> IntoIterator for DfsParseTreeIterator<R> {
+    type Item = Token<R>;
+    #[allow(clippy::type_complexity)]
+    type IntoIter =
+        std::iter::Map<std::vec::IntoIter<(Token<R>, usize)>, fn((Token<R>, usize)) -> Token<R>>;
 
     fn into_iter(self) -> Self::IntoIter {
         let v: Vec<_> = self.vec.into();
-        v.into_iter()
+        v.into_iter().map(|(token, _)| token)
     }
 }
@@ -1,22 +1,18 @@
-use anyhow::anyhow;
-use std::cmp::Ordering;
+use crate::{span::Span, ParserRule};
 
-use crate::{
-    reserve::ReserveVec,
-    span::{RelativeLocation, Span},
-    ParserRule,
-};
-
-/// A token represents a span over some test that satisifies some parser rule.
-#[derive(Debug)]
-pub struct Token<'a, R: ParserRule> {
+/// A token represents a span over some text that satisifies some parser rule.
+///
+/// Tokens don't borrow the input, so they can be kept after the input is
+/// gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<R: ParserRule> {
     pub rule: R,
-    pub span: Span<'a>,
+    pub span: Span,
 }
 
-impl<'a, R: ParserRule> Token<'a, R> {
+impl<R: ParserRule> Token<R> {
     /// Create a new token using the provided rule and span.
-    pub fn new(rule: R, span: Span<'a>) -> Self {
+    pub fn new(rule: R, span: Span) -> Self {
         Token { rule, span }
     }
 
@@ -25,8 +21,14 @@ impl<'a, R: ParserRule> Token<'a, R> {
         self.rule
     }
 
-    /// Returns the string representation of this token.
-    pub fn as_str(&self) -> &'a str {
-        self.span.as_str()
+    /// Returns the span of input covered by this token.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the string representation of this token. `input` must be the
+    /// input the token was parsed from.
+    pub fn as_str<'a>(&self, input: &'a str) -> &'a str {
+        self.span.as_str(input)
     }
 }
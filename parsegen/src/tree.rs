@@ -0,0 +1,82 @@
+use crate::{span::Span, ParserRule, Token};
+
+/// A node in a parse tree, built with `DfsParseTreeIterator::into_tree`.
+///
+/// # Examples
+///
+/// ```
+/// use parsegen::{State, StateResult};
+/// #[allow(non_camel_case_types)]
+/// #[derive(Copy, Debug, Eq, Clone, PartialEq)]
+/// enum Rule {
+///     a,
+///     b,
+///     ab,
+/// }
+///
+/// fn a(state: State<Rule>) -> StateResult<State<Rule>> {
+///     state.tokenize(Rule::a, |s| s.match_str("a"))
+/// }
+/// fn b(state: State<Rule>) -> StateResult<State<Rule>> {
+///     state.tokenize(Rule::b, |s| s.optional(|s| s.match_str("b")))
+/// }
+/// fn ab(state: State<Rule>) -> StateResult<State<Rule>> {
+///     state.tokenize(Rule::ab, |s| a(s).and_then(b).and_then(b))
+/// }
+///
+/// let state = State::new("ab").unwrap();
+/// let tree = ab(state).unwrap().into_parse_tree_iter().into_tree();
+/// assert_eq!(tree.len(), 1);
+/// let rules: Vec<_> = tree[0].children.iter().map(|n| n.rule()).collect();
+/// assert_eq!(rules, vec![Rule::a, Rule::b, Rule::b]);
+/// // The second `b` matched nothing, but is still a child of `ab`.
+/// assert!(tree[0].children[2].span().is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node<R: ParserRule> {
+    pub token: Token<R>,
+    pub children: Vec<Node<R>>,
+}
+
+impl<R: ParserRule> Node<R> {
+    pub fn new(token: Token<R>) -> Self {
+        Node {
+            token,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn rule(&self) -> R {
+        self.token.rule
+    }
+
+    pub fn span(&self) -> Span {
+        self.token.span
+    }
+
+    /// Returns the text covered by this node. `input` must be the input the
+    /// tree was parsed from.
+    pub fn as_str<'a>(&self, input: &'a str) -> &'a str {
+        self.token.as_str(input)
+    }
+
+    /// The first child matching `rule`.
+    pub fn child(&self, rule: R) -> Option<&Node<R>> {
+        self.children.iter().find(|n| n.rule() == rule)
+    }
+
+    /// All children matching `rule`.
+    pub fn children_of(&self, rule: R) -> impl Iterator<Item = &Node<R>> {
+        self.children.iter().filter(move |n| n.rule() == rule)
+    }
+
+    /// Iterate over this node and all its descendants, depth first.
+    pub fn descendants(&self) -> impl Iterator<Item = &Node<R>> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
@@ -2,7 +2,7 @@
 //! for generating the minimal parser.
 
 use anyhow::{anyhow, Result};
-use parsegen::{DfsParseTreeIterator, Parser, State, StateResult, Token};
+use parsegen::{DfsParseTreeIterator, FileId, Parser, SourceMap, Span, State, StateResult, Token};
 
 /// A simplified set of parsing rules for our simple csv parser.
 #[allow(non_camel_case_types)]
@@ -47,7 +47,7 @@ impl Parser<Rule> for CsvParser {
                     .or_else(|s| s.match_str("8"))
                     .or_else(|s| s.match_str("9"))
             })
-        };
+        }
 
         fn field(state: State<Rule>) -> StateResult<State<Rule>> {
             state.tokenize(Rule::field, |s| {
@@ -96,7 +96,7 @@ fn digit() {
 
     assert_eq!(toks.len(), 1, "unexpected number of tokens: {:?}", toks);
     assert_eq!(toks[0].rule(), Rule::digit);
-    assert_eq!(toks[0].as_str(), input);
+    assert_eq!(toks[0].as_str(input), input);
 }
 
 #[test]
@@ -117,7 +117,7 @@ fn field() {
         "unexpected number of tokens: {:?}",
         field_toks
     );
-    assert_eq!(field_toks[0].as_str(), input);
+    assert_eq!(field_toks[0].as_str(input), input);
 }
 
 #[test]
@@ -129,8 +129,8 @@ fn fields() {
         .collect();
 
     let field_toks: Vec<&Token<Rule>> = toks.iter().filter(|t| t.rule() == Rule::field).collect();
-    assert_eq!(field_toks[0].as_str(), "123");
-    assert_eq!(field_toks[1].as_str(), "789");
+    assert_eq!(field_toks[0].as_str(input), "123");
+    assert_eq!(field_toks[1].as_str(input), "789");
 
     let fields_toks: Vec<&Token<Rule>> = toks.iter().filter(|t| t.rule() == Rule::fields).collect();
     assert_eq!(fields_toks.len(), 1);
@@ -159,3 +159,37 @@ fn csv() {
     let record_toks: Vec<&Token<Rule>> = toks.iter().filter(|t| t.rule() == Rule::record).collect();
     assert_eq!(record_toks.len(), 2, "tokens: {:?}", toks);
 }
+
+#[test]
+fn tokens_outlive_input() {
+    let toks: Vec<Token<Rule>> = {
+        let input = String::from("12\n");
+        CsvParser::parse(Rule::csv, &input)
+            .unwrap()
+            .into_iter()
+            .collect()
+    };
+    assert_eq!(toks[0].span(), Span::new(FileId::ANON, 0, 3));
+}
+
+#[test]
+fn parse_files() {
+    let mut sources = SourceMap::new();
+    let a = sources.add("a.csv", "1,2\n");
+    let b = sources.add("b.csv", "3\n4\n");
+
+    let toks = CsvParser::parse_file(Rule::csv, &sources, b).unwrap();
+    assert!(toks.iter().all(|t| t.span.file == b));
+    let records: Vec<&str> = toks
+        .iter()
+        .filter(|t| t.rule() == Rule::record)
+        .map(|t| sources.slice(t.span))
+        .collect();
+    assert_eq!(records, vec!["3\n", "4\n"]);
+    assert_eq!(sources.location(toks[2].span).to_string(), "b.csv:1:1");
+
+    let toks = CsvParser::parse_file(Rule::csv, &sources, a).unwrap();
+    let last = toks.last().unwrap();
+    assert_eq!(sources.slice(last.span), "2");
+    assert_eq!(sources.location(last.span).to_string(), "a.csv:1:3");
+}
@@ -0,0 +1,269 @@
+//! The heap, and its copying collector.
+//!
+//! Objects are bump allocated in one space. When it fills up, the live
+//! objects, those reachable from the roots, are copied to a new space with
+//! Cheney's algorithm, and the old space is freed. Copying leaves a
+//! forwarding header behind, so objects reachable in more than one way are
+//! copied once, and cycles through refs and arrays are fine.
+//!
+//! Objects outside the heap, like the constants compiled code keeps in
+//! static memory, are left where they are. They never point into the heap,
+//! since they're never mutated.
+
+use crate::value::*;
+
+/// The low byte of a forwarding header, which is the new address shifted
+/// up past it. It isn't a kind of object.
+const FORWARDED: u64 = 0xff;
+
+/// The smallest space, in words.
+pub const MIN_WORDS: usize = 1 << 10;
+
+pub struct Heap {
+    space: Vec<u64>,
+    /// The next free word in the space.
+    top: usize,
+    /// The size of the next space, which grows when little of it is free
+    /// after a collection.
+    target: usize,
+    pub stats: Stats,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub collections: usize,
+    /// The most words live after a collection.
+    pub max_live: usize,
+    /// All the words allocated.
+    pub allocated: usize,
+}
+
+impl Heap {
+    /// A heap whose first space has room for `words`.
+    pub fn new(words: usize) -> Heap {
+        let words = words.max(MIN_WORDS);
+        Heap {
+            space: vec![0; words],
+            top: 0,
+            target: words,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Allocate an object with `len` fields, or bytes for strings, whose
+    /// payload is zeroed. If there isn't room, this collects first, with the
+    /// roots given, and objects move.
+    pub fn alloc(&mut self, header: u64, roots: &mut [&mut [Value]]) -> *mut u64 {
+        let words = 1 + payload(header);
+        if self.top + words > self.space.len() {
+            self.collect(roots, words);
+        }
+        let object = &mut self.space[self.top];
+        *object = header;
+        let object: *mut u64 = object;
+        self.top += words;
+        self.stats.allocated += words;
+        object
+    }
+
+    /// Whether a value points into the heap.
+    pub fn contains(&self, value: Value) -> bool {
+        let start = self.space.as_ptr() as usize;
+        let end = start + self.top * 8;
+        is_object(value) && (start..end).contains(&(value as usize))
+    }
+
+    /// The words in use.
+    pub fn used(&self) -> usize {
+        self.top
+    }
+
+    /// Copy the objects reachable from the roots to a new space, leaving
+    /// room for `words` more, and update the roots to point to them.
+    pub fn collect(&mut self, roots: &mut [&mut [Value]], words: usize) {
+        let from = (self.space.as_ptr() as usize, self.top);
+        let size = self.target.max(self.top + words);
+        let mut to = Collection {
+            from,
+            space: vec![0; size],
+            top: 0,
+        };
+        for roots in roots.iter_mut() {
+            for root in roots.iter_mut() {
+                *root = to.forward(*root);
+            }
+        }
+        let mut scan = 0;
+        while scan < to.top {
+            let header = to.space[scan];
+            let (first, len) = match header_kind(header) {
+                kind::STRING | kind::REAL | kind::WORD => (0, 0),
+                // The code pointer isn't a value.
+                kind::CLOSURE => (1, header_len(header).saturating_sub(1)),
+                _ => (0, header_len(header)),
+            };
+            for i in scan + 1 + first..scan + 1 + first + len {
+                to.space[i] = to.forward(to.space[i] as Value) as u64;
+            }
+            scan += 1 + payload(header);
+        }
+
+        let live = to.top;
+        self.space = to.space;
+        self.top = live;
+        if (live + words) * 2 > self.target {
+            self.target = (live + words) * 2;
+        }
+        self.stats.collections += 1;
+        self.stats.max_live = self.stats.max_live.max(live);
+    }
+}
+
+/// The state of a collection.
+struct Collection {
+    /// The start and used words of the space being collected.
+    from: (usize, usize),
+    space: Vec<u64>,
+    top: usize,
+}
+
+impl Collection {
+    /// The new value of a value, copying what it points to if it's in the
+    /// space being collected and hasn't been copied yet.
+    fn forward(&mut self, value: Value) -> Value {
+        let (start, used) = self.from;
+        let address = value as usize;
+        if !is_object(value) || address < start || address >= start + used * 8 {
+            return value;
+        }
+        let object = address as *mut u64;
+        // Safety: the value points into the space being collected, which is
+        // live until the collection's done.
+        unsafe {
+            let header = *object;
+            if header & 0xff == FORWARDED {
+                return (header >> 8) as Value;
+            }
+            let words = 1 + payload(header);
+            let copy = self.top;
+            self.space[copy..copy + words]
+                .copy_from_slice(std::slice::from_raw_parts(object, words));
+            self.top += words;
+            let new = &self.space[copy] as *const u64 as u64;
+            *object = new << 8 | FORWARDED;
+            new as Value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Allocate an object with fields, which must be rooted if they're in the
+    /// heap.
+    fn object(heap: &mut Heap, kind: u8, tag: u32, fields: &[Value]) -> Value {
+        let object = heap.alloc(header(kind, tag, crate::object_len(fields.len())), &mut []);
+        for (i, field) in fields.iter().enumerate() {
+            unsafe { *object.add(i + 1) = *field as u64 };
+        }
+        object as Value
+    }
+
+    fn list(heap: &mut Heap, items: &[i64]) -> Value {
+        items.iter().rev().fold(NIL, |tail, n| {
+            let cell = object(heap, kind::RECORD, 0, &[tagged(*n), tail]);
+            object(heap, kind::CON, 1, &[cell])
+        })
+    }
+
+    fn ints(value: Value) -> Vec<i64> {
+        unsafe {
+            crate::value::list(value)
+                .into_iter()
+                .map(untagged)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn live_objects_survive() {
+        let mut heap = Heap::new(MIN_WORDS);
+        let mut roots = [list(&mut heap, &[1, 2, 3]), tagged(7), 0];
+        list(&mut heap, &[4, 5, 6]);
+        let before = heap.used();
+        heap.collect(&mut [&mut roots], 0);
+        assert_eq!(heap.used(), before / 2);
+        assert!(heap.contains(roots[0]));
+        assert_eq!(ints(roots[0]), vec![1, 2, 3]);
+        assert_eq!(roots[1..], [tagged(7), 0]);
+        assert_eq!(heap.stats.collections, 1);
+    }
+
+    #[test]
+    fn sharing_and_cycles() {
+        let mut heap = Heap::new(MIN_WORDS);
+        let shared = object(&mut heap, kind::RECORD, 0, &[tagged(1)]);
+        let pair = object(&mut heap, kind::RECORD, 0, &[shared, shared]);
+        let r = object(&mut heap, kind::REF, 0, &[0]);
+        unsafe { set_field(r, 0, r) };
+        let mut roots = [pair, r];
+        heap.collect(&mut [&mut roots], 0);
+        unsafe {
+            assert_eq!(field(roots[0], 0), field(roots[0], 1));
+            assert_eq!(field(field(roots[0], 0), 0), tagged(1));
+            assert_eq!(field(roots[1], 0), roots[1]);
+        }
+        assert_eq!(heap.used(), 2 + 3 + 2);
+    }
+
+    #[test]
+    fn raw_fields_and_statics() {
+        static CONSTANT: [u64; 2] = [0x1_0000_0008, 0x4000_0000_0000_0000];
+        let constant = CONSTANT.as_ptr() as Value;
+        let mut heap = Heap::new(MIN_WORDS);
+        let s = heap.alloc(header(kind::STRING, 0, 11), &mut []);
+        unsafe { std::ptr::copy_nonoverlapping(b"hello world".as_ptr(), s.add(1) as *mut u8, 11) };
+        // A closure's code pointer looks like an object, but isn't one.
+        let closure = object(&mut heap, kind::CLOSURE, 0, &[0x1000, s as Value, constant]);
+        let mut roots = [closure];
+        heap.collect(&mut [&mut roots], 0);
+        unsafe {
+            assert_eq!(field(roots[0], 0), 0x1000);
+            assert_eq!(bytes(field(roots[0], 1)), b"hello world");
+            assert_eq!(field(roots[0], 2), constant);
+            assert_eq!(real(constant), 2.0);
+        }
+    }
+
+    #[test]
+    fn growth() {
+        let mut heap = Heap::new(MIN_WORDS);
+        // The list, and a cell while its cons is allocated.
+        let mut roots = [NIL, 0];
+        // Keep everything live, so the heap has to grow.
+        for n in 0..10_000 {
+            let cell = heap.alloc(header(kind::RECORD, 0, 2), &mut [&mut roots]);
+            unsafe {
+                *cell.add(1) = tagged(n) as u64;
+                *cell.add(2) = roots[0] as u64;
+            }
+            roots[1] = cell as Value;
+            let cons = heap.alloc(header(kind::CON, 1, 1), &mut [&mut roots]);
+            unsafe { *cons.add(1) = roots[1] as u64 };
+            roots = [cons as Value, 0];
+        }
+        let items = ints(roots[0]);
+        assert_eq!(items.len(), 10_000);
+        assert!(items.iter().rev().copied().eq(0..10_000));
+        assert!(heap.stats.collections > 1);
+    }
+
+    #[test]
+    fn big_objects() {
+        let mut heap = Heap::new(MIN_WORDS);
+        let array = heap.alloc(header(kind::ARRAY, 0, 5000), &mut []);
+        assert_eq!(unsafe { *array.add(5000) }, 0);
+        assert!(heap.used() > MIN_WORDS);
+    }
+}
@@ -0,0 +1,173 @@
+//! Streams, for `print` and `TextIO`, as the interpreter has them.
+//!
+//! Streams are ints, indexing the open streams. Standard input, output and
+//! error are 0, 1 and 2. Characters are Unicode scalar values, and input is
+//! decoded as UTF-8, with invalid input reading as U+FFFD.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+pub enum Stream {
+    In(Input),
+    Out(Box<dyn Write>),
+}
+
+pub struct Streams {
+    streams: Vec<Option<Stream>>,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Streams {
+            streams: vec![
+                Some(Stream::In(Input::new(Box::new(
+                    BufReader::new(io::stdin()),
+                )))),
+                Some(Stream::Out(Box::new(BufWriter::new(io::stdout())))),
+                Some(Stream::Out(Box::new(io::stderr()))),
+            ],
+        }
+    }
+}
+
+impl Streams {
+    pub fn open_in(&mut self, path: &str) -> Result<usize, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        Ok(self.add(Stream::In(Input::new(Box::new(BufReader::new(file))))))
+    }
+
+    pub fn open_out(&mut self, path: &str, append: bool) -> Result<usize, String> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .map_err(|err| err.to_string())?;
+        Ok(self.add(Stream::Out(Box::new(BufWriter::new(file)))))
+    }
+
+    fn add(&mut self, stream: Stream) -> usize {
+        self.streams.push(Some(stream));
+        self.streams.len() - 1
+    }
+
+    pub fn close(&mut self, id: usize) {
+        self.flush(id);
+        if let Some(stream) = self.streams.get_mut(id) {
+            *stream = None;
+        }
+    }
+
+    /// Write to an output stream, returning whether it worked.
+    pub fn output(&mut self, id: usize, bytes: &[u8]) -> bool {
+        match self.streams.get_mut(id) {
+            Some(Some(Stream::Out(out))) => out.write_all(bytes).is_ok(),
+            _ => false,
+        }
+    }
+
+    pub fn flush(&mut self, id: usize) -> bool {
+        match self.streams.get_mut(id) {
+            Some(Some(Stream::Out(out))) => out.flush().is_ok(),
+            _ => false,
+        }
+    }
+
+    pub fn flush_all(&mut self) {
+        for id in 0..self.streams.len() {
+            self.flush(id);
+        }
+    }
+
+    /// An input stream, unless it's closed.
+    pub fn input(&mut self, id: usize) -> Option<&mut Input> {
+        match self.streams.get_mut(id) {
+            Some(Some(Stream::In(input))) => Some(input),
+            _ => None,
+        }
+    }
+}
+
+/// An input stream, with the characters that have been looked at but not
+/// read yet.
+pub struct Input {
+    reader: Box<dyn BufRead>,
+    ahead: VecDeque<char>,
+}
+
+impl Input {
+    pub fn new(reader: Box<dyn BufRead>) -> Self {
+        Input {
+            reader,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    pub fn read_char(&mut self) -> Option<char> {
+        self.ahead.pop_front().or_else(|| self.decode())
+    }
+
+    /// Look at the nth character from here, without reading it.
+    pub fn peek(&mut self, n: usize) -> Option<char> {
+        while self.ahead.len() <= n {
+            let c = self.decode()?;
+            self.ahead.push_back(c);
+        }
+        Some(self.ahead[n])
+    }
+
+    /// Everything left.
+    pub fn read_all(&mut self) -> String {
+        let mut s: String = self.ahead.drain(..).collect();
+        let mut bytes = Vec::new();
+        let _ = self.reader.read_to_end(&mut bytes);
+        s += &String::from_utf8_lossy(&bytes);
+        s
+    }
+
+    fn decode(&mut self) -> Option<char> {
+        let first = *self.reader.fill_buf().ok()?.first()?;
+        self.reader.consume(1);
+        let width = match first {
+            0x00..=0x7f => return Some(first as char),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        let mut bytes = vec![first];
+        for _ in 1..width {
+            match self
+                .reader
+                .fill_buf()
+                .ok()
+                .and_then(|buf| buf.first().copied())
+            {
+                Some(b) if b & 0xc0 == 0x80 => {
+                    bytes.push(b);
+                    self.reader.consume(1);
+                }
+                _ => break,
+            }
+        }
+        String::from_utf8_lossy(&bytes).chars().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding() {
+        let mut input = Input::new(Box::new(&b"a\xc3\xa9\xffb"[..]));
+        assert_eq!(input.peek(1), Some('\u{e9}'));
+        assert_eq!(input.read_char(), Some('a'));
+        assert_eq!(input.read_char(), Some('\u{e9}'));
+        assert_eq!(input.read_char(), Some(char::REPLACEMENT_CHARACTER));
+        assert_eq!(input.read_all(), "b");
+        assert_eq!(input.read_char(), None);
+    }
+}
@@ -0,0 +1,496 @@
+//! The runtime that programs compiled by `smol` link against.
+//!
+//! It provides allocation and the garbage collector, structural equality,
+//! the string operations compiled code calls, streams, and the primitives
+//! of the Basis' structure `Prim`, as `smol_prim_<name>`. The representation
+//! of values is in `value`, and must agree with `smol::codegen`.
+//!
+//! Compiled code keeps every value in a frame on a shadow stack, from the
+//! stack's base up to `smol_sp`, and in its globals, so the collector can
+//! find all the roots precisely and update them when it moves objects. The
+//! runtime's own functions push the values they hold onto the same stack
+//! while they allocate (see `Roots`). A raised exception is left in
+//! `smol_exn`, which is a root too, and functions that raise return 0.
+//!
+//! The runtime is single threaded: its state is global, set up by
+//! `smol_init`, and the program runs on one thread, started by `smol_run`.
+//! Two environment variables tune it: `SMOL_HEAP_WORDS` is the size of the
+//! first heap space in words, and `SMOL_GC_STATS`, if set, makes the program
+//! print how much it collected when it finishes.
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+use std::thread;
+
+pub mod heap;
+pub mod io;
+pub mod natives;
+pub mod value;
+
+use heap::Heap;
+use io::Streams;
+use value::*;
+
+/// The top of the shadow stack.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut smol_sp: *mut Value = ptr::null_mut();
+
+/// How far `smol_sp` can go. Every function checks its frame fits below
+/// it, and raises `Overflow` if it doesn't.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut smol_sp_limit: *mut Value = ptr::null_mut();
+
+/// The exception being raised, or 0.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut smol_exn: Value = 0;
+
+/// The size of the shadow stack, in words. It's reserved up front, but only
+/// the pages that are used are touched.
+const STACK_WORDS: usize = 1 << 24;
+
+/// Words past `smol_sp_limit` kept for the runtime's own roots.
+const STACK_SLACK: usize = 1 << 10;
+
+/// The size of the C stack programs run on, in bytes. Non-tail calls use it
+/// as well as the shadow stack, so it's big enough that the shadow stack
+/// runs out first, and a deep recursion raises `Overflow`.
+const C_STACK_BYTES: usize = 1 << 30;
+
+const HEAP_WORDS: usize = 1 << 20;
+
+pub struct Runtime {
+    pub heap: Heap,
+    stack: Vec<Value>,
+    globals: *mut Value,
+    globals_len: usize,
+    /// The names of exceptions, by id.
+    names: Vec<Option<String>>,
+    pub streams: Streams,
+}
+
+static mut RUNTIME: Option<Runtime> = None;
+
+/// The runtime's state.
+///
+/// # Safety
+///
+/// The borrow must be the only one: one this gave before must not be used
+/// after it's called again, including by a runtime function called while
+/// the borrow is held. The runtime is single threaded, so this is about
+/// nesting, not threads.
+///
+/// # Panics
+///
+/// If `smol_init` hasn't been called.
+pub unsafe fn runtime() -> &'static mut Runtime {
+    (*ptr::addr_of_mut!(RUNTIME))
+        .as_mut()
+        .expect("smol_init wasn't called")
+}
+
+/// An object's length, as its header holds it.
+///
+/// # Panics
+///
+/// If it's too long for the header, rather than making an object whose
+/// header is shorter than what's written after it.
+pub fn object_len(len: usize) -> u32 {
+    u32::try_from(len).unwrap_or_else(|_| panic!("smol: an object of length {} is too big", len))
+}
+
+impl Runtime {
+    /// Set up the runtime, with the globals and exception names compiled
+    /// code gives it.
+    pub fn install(
+        heap_words: usize,
+        globals: *mut Value,
+        globals_len: usize,
+        names: Vec<Option<String>>,
+    ) {
+        let mut stack = vec![0; STACK_WORDS];
+        // Safety: see `runtime`.
+        unsafe {
+            smol_sp = stack.as_mut_ptr();
+            smol_sp_limit = smol_sp.add(STACK_WORDS - STACK_SLACK);
+            smol_exn = 0;
+            *ptr::addr_of_mut!(RUNTIME) = Some(Runtime {
+                heap: Heap::new(heap_words),
+                stack,
+                globals,
+                globals_len,
+                names,
+                streams: Streams::default(),
+            });
+        }
+    }
+
+    /// Allocate an object, whose payload is zeroed. Objects can move, so
+    /// values not on the shadow stack or in globals can't be used after this.
+    pub fn alloc(&mut self, header: u64) -> *mut u64 {
+        // Safety: everything from the base of the stack up to `smol_sp` is
+        // a frame, and the globals are compiled code's.
+        unsafe {
+            let base = self.stack.as_mut_ptr();
+            let depth = smol_sp.offset_from(base) as usize;
+            if depth > self.stack.len() {
+                eprintln!("smol: stack overflow");
+                std::process::abort();
+            }
+            let stack = slice::from_raw_parts_mut(base, depth);
+            let globals = slice::from_raw_parts_mut(self.globals, self.globals_len);
+            let exn = slice::from_mut(&mut *ptr::addr_of_mut!(smol_exn));
+            self.heap.alloc(header, &mut [stack, globals, exn])
+        }
+    }
+
+    /// Allocate an object with fields.
+    pub fn object(&mut self, kind: u8, tag: u32, fields: &[Value]) -> Value {
+        let roots = Roots::new(fields);
+        let object = self.alloc(header(kind, tag, object_len(fields.len())));
+        for i in 0..fields.len() {
+            // Safety: the object has the fields.
+            unsafe { *object.add(i + 1) = roots.get(i) as u64 };
+        }
+        object as Value
+    }
+
+    pub fn string(&mut self, bytes: &[u8]) -> Value {
+        let object = self.alloc(header(kind::STRING, 0, object_len(bytes.len())));
+        // Safety: the object has room for the bytes.
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), object.add(1) as *mut u8, bytes.len()) };
+        object as Value
+    }
+
+    pub fn word(&mut self, w: u64) -> Value {
+        let object = self.alloc(header(kind::WORD, 0, 1));
+        unsafe { *object.add(1) = w };
+        object as Value
+    }
+
+    pub fn real(&mut self, x: f64) -> Value {
+        let object = self.alloc(header(kind::REAL, 0, 1));
+        unsafe { *object.add(1) = x.to_bits() };
+        object as Value
+    }
+
+    pub fn some(&mut self, value: Value) -> Value {
+        self.object(kind::CON, 1, &[value])
+    }
+
+    /// A list of immediate values.
+    pub fn list(&mut self, items: impl DoubleEndedIterator<Item = Value>) -> Value {
+        let roots = Roots::new(&[NIL]);
+        for item in items.rev() {
+            let cell = self.object(kind::RECORD, 0, &[item, roots.get(0)]);
+            let cons = self.object(kind::CON, 1, &[cell]);
+            roots.set(0, cons);
+        }
+        roots.get(0)
+    }
+
+    /// Raise a built in exception, giving the value to return to compiled
+    /// code.
+    pub fn raise(&mut self, id: u32) -> Value {
+        let exn = self.object(kind::EXN, 0, &[tagged(id as i64), UNIT]);
+        // Safety: see `runtime`.
+        unsafe { smol_exn = exn };
+        0
+    }
+
+    /// The name of an exception.
+    pub fn exn_name(&self, exn: Value) -> &str {
+        // Safety: exceptions are objects with their id first.
+        let id = untagged(unsafe { field(exn, 0) }) as usize;
+        match self.names.get(id) {
+            Some(Some(name)) => name,
+            _ => "?",
+        }
+    }
+}
+
+/// Values a runtime function holds while it allocates, kept on the shadow
+/// stack, where the collector updates them. They're popped when this is
+/// dropped, so these must be dropped in the order they're made.
+pub struct Roots {
+    base: *mut Value,
+    len: usize,
+}
+
+impl Roots {
+    /// Push the values, past `smol_sp_limit` if need be, into the words
+    /// kept for them.
+    ///
+    /// # Panics
+    ///
+    /// If they don't fit on the stack, or `smol_init` hasn't been called.
+    pub fn new(values: &[Value]) -> Roots {
+        // Safety: the runtime is single threaded, and the values are
+        // copied only once there's room for them.
+        unsafe {
+            assert!(!smol_sp_limit.is_null(), "smol_init wasn't called");
+            let base = smol_sp;
+            let end = smol_sp_limit.add(STACK_SLACK);
+            if values.len() > end.offset_from(base) as usize {
+                eprintln!("smol: stack overflow");
+                std::process::abort();
+            }
+            ptr::copy_nonoverlapping(values.as_ptr(), base, values.len());
+            smol_sp = base.add(values.len());
+            Roots {
+                base,
+                len: values.len(),
+            }
+        }
+    }
+
+    pub fn get(&self, i: usize) -> Value {
+        assert!(i < self.len, "root {} of {}", i, self.len);
+        // Safety: the root was pushed, and is still there until this is
+        // dropped.
+        unsafe { *self.base.add(i) }
+    }
+
+    pub fn set(&self, i: usize, value: Value) {
+        assert!(i < self.len, "root {} of {}", i, self.len);
+        // Safety: as for `get`.
+        unsafe { *self.base.add(i) = value }
+    }
+}
+
+impl Drop for Roots {
+    fn drop(&mut self) {
+        unsafe { smol_sp = self.base }
+    }
+}
+
+/// Set up the runtime. Compiled programs' `main` calls this first.
+///
+/// # Safety
+///
+/// `globals` must point to `globals_len` values, and `names` to `names_len`
+/// pointers, each null or a C string.
+#[no_mangle]
+pub unsafe extern "C" fn smol_init(
+    _argc: i32,
+    _argv: *const *const c_char,
+    globals: *mut Value,
+    globals_len: i64,
+    names: *const *const c_char,
+    names_len: i64,
+) {
+    let names = (0..names_len as usize)
+        .map(|i| {
+            let name = *names.add(i);
+            if name.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+            }
+        })
+        .collect();
+    let heap_words = env::var("SMOL_HEAP_WORDS")
+        .ok()
+        .and_then(|words| words.parse().ok())
+        .unwrap_or(HEAP_WORDS);
+    Runtime::install(heap_words, globals, globals_len as usize, names);
+}
+
+/// Run a program's top level, `start`, on a thread with a C stack of
+/// `C_STACK_BYTES`, and wait for it.
+#[no_mangle]
+pub extern "C" fn smol_run(start: extern "C" fn()) {
+    let thread = thread::Builder::new()
+        .stack_size(C_STACK_BYTES)
+        .spawn(move || start());
+    match thread.map(|thread| thread.join()) {
+        Ok(Ok(())) => (),
+        Ok(Err(_)) => std::process::abort(),
+        Err(err) => {
+            eprintln!("smol: can't start the program: {}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Finish running a program, flushing its output and reporting an uncaught
+/// exception, and give the status to exit with.
+#[no_mangle]
+pub extern "C" fn smol_finish() -> i32 {
+    // Safety: nothing else is running, and nothing here calls back into the
+    // runtime.
+    let rt = unsafe { runtime() };
+    rt.streams.flush_all();
+    if env::var_os("SMOL_GC_STATS").is_some() {
+        let stats = rt.heap.stats;
+        eprintln!(
+            "smol: {} collections, {} words allocated, at most {} live",
+            stats.collections, stats.allocated, stats.max_live
+        );
+    }
+    // Safety: see `runtime`.
+    let exn = unsafe { smol_exn };
+    if exn == 0 {
+        0
+    } else {
+        eprintln!("uncaught exception {}", rt.exn_name(exn));
+        1
+    }
+}
+
+/// Allocate an object with zeroed fields.
+#[no_mangle]
+pub extern "C" fn smol_alloc(_len: i64, header: i64) -> *mut u64 {
+    // Safety: the borrow ends with the call.
+    unsafe { runtime() }.alloc(header as u64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_equal(a: Value, b: Value) -> Value {
+    // Safety: compiled code only compares values of the same equality type.
+    bool(unsafe { equal(a, b) })
+}
+
+/// Compare two strings, giving -1, 0 or 1.
+#[no_mangle]
+pub extern "C" fn smol_string_compare(a: Value, b: Value) -> i64 {
+    // Safety: compiled code only compares strings.
+    let (a, b) = unsafe { (bytes(a), bytes(b)) };
+    a.cmp(b) as i64
+}
+
+#[no_mangle]
+pub extern "C" fn smol_string_concat(a: Value, b: Value) -> Value {
+    let roots = Roots::new(&[a, b]);
+    let (a_len, b_len) = unsafe { (len(a), len(b)) };
+    let header = header(kind::STRING, 0, object_len(a_len + b_len));
+    // Safety: the borrow ends with the call.
+    let object = unsafe { runtime() }.alloc(header);
+    // Safety: the strings are where the roots say, after allocating.
+    unsafe {
+        let bytes_at = object.add(1) as *mut u8;
+        ptr::copy_nonoverlapping(bytes(roots.get(0)).as_ptr(), bytes_at, a_len);
+        ptr::copy_nonoverlapping(bytes(roots.get(1)).as_ptr(), bytes_at.add(a_len), b_len);
+    }
+    object as Value
+}
+
+/// `print`, to standard output.
+#[no_mangle]
+pub extern "C" fn smol_print(s: Value) {
+    // Safety: compiled code only prints strings, and nothing here calls back
+    // into the runtime.
+    let streams = unsafe { &mut runtime().streams };
+    streams.output(1, unsafe { bytes(s) });
+    streams.flush(1);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    /// Set up the runtime for a test, with a small heap so that it collects
+    /// a lot. Tests share the runtime's state, so they take turns.
+    pub(crate) fn install(globals: &mut [Value]) -> MutexGuard<'static, ()> {
+        let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let names = [
+            "Bind",
+            "Match",
+            "Div",
+            "Overflow",
+            "Size",
+            "Subscript",
+            "Chr",
+            "Domain",
+        ];
+        Runtime::install(
+            heap::MIN_WORDS,
+            globals.as_mut_ptr(),
+            globals.len(),
+            names.iter().map(|name| Some(name.to_string())).collect(),
+        );
+        guard
+    }
+
+    /// The runtime, for one call, as in `natives`.
+    fn rt() -> &'static mut Runtime {
+        // Safety: each borrow is used within the expression that asks for it.
+        unsafe { runtime() }
+    }
+
+    pub(crate) fn string(value: Value) -> String {
+        String::from_utf8(unsafe { bytes(value) }.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn roots_survive_collections() {
+        let mut globals = [0];
+        let _guard = install(&mut globals);
+        globals[0] = rt().string(b"global");
+        let roots = Roots::new(&[rt().list((0..100).map(tagged))]);
+        for i in 0..10_000 {
+            let s = rt().string(i.to_string().as_bytes());
+            let s = smol_string_concat(s, globals[0]);
+            assert_eq!(string(s), format!("{}global", i));
+        }
+        assert!(rt().heap.stats.collections > 10);
+        let items: Vec<_> = unsafe { list(roots.get(0)) };
+        assert!(items.into_iter().map(untagged).eq(0..100));
+        assert_eq!(string(globals[0]), "global");
+    }
+
+    #[test]
+    fn equality_and_comparison() {
+        let _guard = install(&mut []);
+        let a = rt().list([tagged(1), tagged(2)].iter().copied());
+        let roots = Roots::new(&[a]);
+        let b = rt().list([tagged(1), tagged(2)].iter().copied());
+        let a = roots.get(0);
+        assert_eq!(smol_equal(a, b), TRUE);
+        assert_eq!(smol_equal(a, NIL), FALSE);
+        let r = rt().object(kind::REF, 0, &[tagged(1)]);
+        let roots = Roots::new(&[r]);
+        let s = rt().object(kind::REF, 0, &[tagged(1)]);
+        assert_eq!(smol_equal(roots.get(0), s), FALSE);
+        assert_eq!(smol_equal(s, s), TRUE);
+        let roots = Roots::new(&[rt().string(b"ab")]);
+        let y = rt().string(b"abc");
+        let x = roots.get(0);
+        assert_eq!(smol_string_compare(x, y), -1);
+        assert_eq!(smol_string_compare(y, x), 1);
+        assert_eq!(smol_string_compare(x, x), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "root 1 of 1")]
+    fn roots_are_checked() {
+        let _guard = install(&mut []);
+        let roots = Roots::new(&[tagged(1)]);
+        assert_eq!(roots.get(0), tagged(1));
+        roots.get(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "too big")]
+    fn lengths_are_checked() {
+        object_len(1 << 32);
+    }
+
+    #[test]
+    fn raising() {
+        let _guard = install(&mut []);
+        assert_eq!(rt().raise(exn::DIV), 0);
+        let exn = unsafe { smol_exn };
+        assert_eq!(rt().exn_name(exn), "Div");
+        assert_eq!(smol_finish(), 1);
+    }
+}
@@ -0,0 +1,720 @@
+//! The primitives of the Basis' structure `Prim`, as the interpreter has them
+//! (see `smol::eval::NATIVES` and `basis/prim.sml`), for compiled code.
+//!
+//! Each takes its argument, a tuple if it has more than one, and gives its
+//! result, or raises by leaving an exception in `smol_exn` and giving 0.
+//! Compiled code is type checked, so arguments are always the values the
+//! primitive's type says, and they aren't checked again here.
+
+#![allow(non_snake_case)]
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use crate::value::*;
+use crate::{object_len, runtime, Roots, Runtime};
+
+/// The primitives' names, without their `smol_prim_` prefix.
+pub const NAMES: &[&str] = &[
+    "exnName",
+    "intToString",
+    "intQuot",
+    "intRem",
+    "wordFromInt",
+    "wordToInt",
+    "wordToIntX",
+    "wordAndb",
+    "wordOrb",
+    "wordXorb",
+    "wordNotb",
+    "wordShl",
+    "wordShr",
+    "wordAshr",
+    "realFromInt",
+    "realToString",
+    "realScan",
+    "realFloor",
+    "realCeil",
+    "realRound",
+    "realTrunc",
+    "charOrd",
+    "charChr",
+    "stringSize",
+    "stringSub",
+    "stringExtract",
+    "stringConcat",
+    "stringImplode",
+    "stringExplode",
+    "vectorFromList",
+    "vectorLength",
+    "vectorSub",
+    "arrayArray",
+    "arrayFromList",
+    "arrayLength",
+    "arraySub",
+    "arrayUpdate",
+    "arrayVector",
+    "ioOpenIn",
+    "ioOpenOut",
+    "ioOpenAppend",
+    "ioClose",
+    "ioOutput",
+    "ioFlush",
+    "ioInput",
+    "ioPeek",
+    "ioInputLine",
+    "ioInputAll",
+    "ioEndOfStream",
+];
+
+/// The runtime, for one call: a borrow this gives is used within the
+/// expression that asks for it, and never kept across another call to `rt`.
+fn rt() -> &'static mut Runtime {
+    // Safety: as above, so no two borrows are live at once.
+    unsafe { runtime() }
+}
+
+/// The fields of a tuple argument.
+fn args<'a>(arg: Value) -> &'a [Value] {
+    unsafe { fields(arg) }
+}
+
+fn text<'a>(s: Value) -> Cow<'a, str> {
+    String::from_utf8_lossy(unsafe { bytes(s) })
+}
+
+fn int(n: Value) -> i64 {
+    untagged(n)
+}
+
+/// The number of characters in a string.
+fn size(s: &str) -> usize {
+    if s.is_ascii() {
+        s.len()
+    } else {
+        s.chars().count()
+    }
+}
+
+fn char_value(c: char) -> Value {
+    tagged(c as i64)
+}
+
+/// An int, or Overflow if it doesn't fit.
+fn checked(n: i64) -> Value {
+    if fits(n) {
+        tagged(n)
+    } else {
+        rt().raise(exn::OVERFLOW)
+    }
+}
+
+fn bool_value(b: bool) -> Value {
+    bool(b)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_exnName(exn: Value) -> Value {
+    let name = rt().exn_name(exn).to_owned();
+    rt().string(name.as_bytes())
+}
+
+// Int
+
+#[no_mangle]
+pub extern "C" fn smol_prim_intToString(n: Value) -> Value {
+    rt().string(int(n).to_string().replace('-', "~").as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_intQuot(arg: Value) -> Value {
+    let (a, b) = (int(args(arg)[0]), int(args(arg)[1]));
+    if b == 0 {
+        return rt().raise(exn::DIV);
+    }
+    checked(a / b)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_intRem(arg: Value) -> Value {
+    let (a, b) = (int(args(arg)[0]), int(args(arg)[1]));
+    if b == 0 {
+        return rt().raise(exn::DIV);
+    }
+    tagged(a % b)
+}
+
+// Word
+
+fn word_of(w: Value) -> u64 {
+    unsafe { word(w) }
+}
+
+fn words(arg: Value, f: fn(u64, u64) -> u64) -> Value {
+    let (a, b) = (word_of(args(arg)[0]), word_of(args(arg)[1]));
+    rt().word(f(a, b))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordFromInt(n: Value) -> Value {
+    rt().word(int(n) as u64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordToInt(w: Value) -> Value {
+    match i64::try_from(word_of(w)) {
+        Ok(n) => checked(n),
+        Err(_) => rt().raise(exn::OVERFLOW),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordToIntX(w: Value) -> Value {
+    checked(word_of(w) as i64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordAndb(arg: Value) -> Value {
+    words(arg, |a, b| a & b)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordOrb(arg: Value) -> Value {
+    words(arg, |a, b| a | b)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordXorb(arg: Value) -> Value {
+    words(arg, |a, b| a ^ b)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordNotb(w: Value) -> Value {
+    rt().word(!word_of(w))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordShl(arg: Value) -> Value {
+    words(arg, |a, b| {
+        a.checked_shl(u32::try_from(b).unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordShr(arg: Value) -> Value {
+    words(arg, |a, b| {
+        a.checked_shr(u32::try_from(b).unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_wordAshr(arg: Value) -> Value {
+    // Shifting by the word size or more fills with the sign bit.
+    words(arg, |a, b| (a as i64 >> b.min(63)) as u64)
+}
+
+// Real
+
+fn real_of(x: Value) -> f64 {
+    unsafe { real(x) }
+}
+
+/// A real as the interpreter shows it.
+fn show_real(x: f64) -> String {
+    let s = if x.is_infinite() {
+        if x > 0.0 {
+            "inf".to_owned()
+        } else {
+            "-inf".to_owned()
+        }
+    } else if x.fract() == 0.0 && x.abs() < 1e16 {
+        format!("{:.1}", x)
+    } else {
+        x.to_string()
+    };
+    s.replace('-', "~")
+}
+
+/// Scan a real in SML's syntax from the start of a string, after any
+/// whitespace, giving it and the number of characters read.
+fn scan_real(s: &str) -> Option<(f64, usize)> {
+    let skipped = s.len() - s.trim_start().len();
+    let bytes = &s.as_bytes()[skipped..];
+    let digits = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        i
+    };
+    let mut i = 0;
+    if matches!(bytes.first(), Some(b'~' | b'-' | b'+')) {
+        i += 1;
+    }
+    let start = i;
+    i = digits(i);
+    let mut mantissa = i - start;
+    if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+        let end = digits(i + 1);
+        mantissa += end - i - 1;
+        i = end;
+    }
+    if mantissa == 0 {
+        return None;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(i + 1), Some(b'~' | b'-' | b'+')));
+        if bytes.get(i + 1 + sign).is_some_and(u8::is_ascii_digit) {
+            i = digits(i + 1 + sign);
+        }
+    }
+    let x = s[skipped..skipped + i].replace('~', "-").parse().ok()?;
+    Some((x, s[..skipped].chars().count() + i))
+}
+
+fn to_int(x: f64) -> Value {
+    if x.is_nan() {
+        rt().raise(exn::DOMAIN)
+    } else if x < MIN_INT as f64 || x >= -(MIN_INT as f64) {
+        rt().raise(exn::OVERFLOW)
+    } else {
+        tagged(x as i64)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realFromInt(n: Value) -> Value {
+    rt().real(int(n) as f64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realToString(x: Value) -> Value {
+    rt().string(show_real(real_of(x)).as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realScan(s: Value) -> Value {
+    match scan_real(&text(s)) {
+        Some((x, len)) => {
+            let x = rt().real(x);
+            let pair = rt().object(kind::RECORD, 0, &[x, tagged(len as i64)]);
+            rt().some(pair)
+        }
+        None => NIL,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realFloor(x: Value) -> Value {
+    to_int(real_of(x).floor())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realCeil(x: Value) -> Value {
+    to_int(real_of(x).ceil())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realRound(x: Value) -> Value {
+    to_int(real_of(x).round_ties_even())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_realTrunc(x: Value) -> Value {
+    to_int(real_of(x).trunc())
+}
+
+// Char
+
+/// Chars and ints are both tagged, so this is the identity.
+#[no_mangle]
+pub extern "C" fn smol_prim_charOrd(c: Value) -> Value {
+    c
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_charChr(n: Value) -> Value {
+    match u8::try_from(int(n)) {
+        Ok(c) => char_value(c as char),
+        Err(_) => rt().raise(exn::CHR),
+    }
+}
+
+// String
+
+#[no_mangle]
+pub extern "C" fn smol_prim_stringSize(s: Value) -> Value {
+    tagged(size(&text(s)) as i64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_stringSub(arg: Value) -> Value {
+    let (s, i) = (text(args(arg)[0]), int(args(arg)[1]));
+    let c = usize::try_from(i).ok().and_then(|i| {
+        if s.is_ascii() {
+            s.as_bytes().get(i).map(|&b| b as char)
+        } else {
+            s.chars().nth(i)
+        }
+    });
+    match c {
+        Some(c) => char_value(c),
+        None => rt().raise(exn::SUBSCRIPT),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_stringExtract(arg: Value) -> Value {
+    let fields = args(arg);
+    let (s, i, n) = (text(fields[0]), int(fields[1]), int(fields[2]));
+    let len = size(&s) as i64;
+    if i < 0 || n < 0 || i > len - n {
+        return rt().raise(exn::SUBSCRIPT);
+    }
+    let (i, n) = (i as usize, n as usize);
+    let extract = if s.is_ascii() {
+        s.as_bytes()[i..i + n].to_vec()
+    } else {
+        s.chars().skip(i).take(n).collect::<String>().into_bytes()
+    };
+    rt().string(&extract)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_stringConcat(ss: Value) -> Value {
+    let mut concat = Vec::new();
+    for s in unsafe { list(ss) } {
+        concat.extend_from_slice(unsafe { bytes(s) });
+    }
+    rt().string(&concat)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_stringImplode(cs: Value) -> Value {
+    let s: String = unsafe { list(cs) }
+        .into_iter()
+        .map(|c| char::from_u32(int(c) as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    rt().string(s.as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_stringExplode(s: Value) -> Value {
+    let chars: Vec<Value> = text(s).chars().map(char_value).collect();
+    rt().list(chars.into_iter())
+}
+
+// Vector and Array
+
+/// A vector or array of the elements of a list.
+fn from_list(kind: u8, xs: Value) -> Value {
+    let n = unsafe { list(xs) }.len();
+    let roots = Roots::new(&[xs]);
+    let object = rt().alloc(header(kind, 0, object_len(n))) as Value;
+    for (i, x) in unsafe { list(roots.get(0)) }.into_iter().enumerate() {
+        unsafe { set_field(object, i, x) };
+    }
+    object
+}
+
+fn index(items: Value, i: Value) -> Value {
+    let items = unsafe { fields(items) };
+    match usize::try_from(int(i)).ok().and_then(|i| items.get(i)) {
+        Some(x) => *x,
+        None => rt().raise(exn::SUBSCRIPT),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_vectorFromList(xs: Value) -> Value {
+    from_list(kind::VECTOR, xs)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_vectorLength(v: Value) -> Value {
+    tagged(unsafe { len(v) } as i64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_vectorSub(arg: Value) -> Value {
+    index(args(arg)[0], args(arg)[1])
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_arrayArray(arg: Value) -> Value {
+    let (n, x) = (int(args(arg)[0]), args(arg)[1]);
+    let n = match u32::try_from(n) {
+        Ok(n) => n,
+        Err(_) => return rt().raise(exn::SIZE),
+    };
+    let roots = Roots::new(&[x]);
+    let array = rt().alloc(header(kind::ARRAY, 0, n)) as Value;
+    let x = roots.get(0);
+    for i in 0..n as usize {
+        unsafe { set_field(array, i, x) };
+    }
+    array
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_arrayFromList(xs: Value) -> Value {
+    from_list(kind::ARRAY, xs)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_arrayLength(a: Value) -> Value {
+    tagged(unsafe { len(a) } as i64)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_arraySub(arg: Value) -> Value {
+    index(args(arg)[0], args(arg)[1])
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_arrayUpdate(arg: Value) -> Value {
+    let fields = args(arg);
+    let (a, i, x) = (fields[0], int(fields[1]), fields[2]);
+    match usize::try_from(i) {
+        Ok(i) if i < unsafe { len(a) } => {
+            unsafe { set_field(a, i, x) };
+            UNIT
+        }
+        _ => rt().raise(exn::SUBSCRIPT),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_arrayVector(a: Value) -> Value {
+    let n = unsafe { len(a) };
+    let roots = Roots::new(&[a]);
+    let vector = rt().alloc(header(kind::VECTOR, 0, object_len(n))) as Value;
+    for (i, x) in unsafe { fields(roots.get(0)) }.iter().enumerate() {
+        unsafe { set_field(vector, i, *x) };
+    }
+    vector
+}
+
+// TextIO
+
+/// A stream, and an empty error, or ~1 and the error opening it.
+fn opened(res: Result<usize, String>) -> Value {
+    let (id, error) = match res {
+        Ok(id) => (id as i64, String::new()),
+        Err(error) => (-1, error),
+    };
+    let error = rt().string(error.as_bytes());
+    rt().object(kind::RECORD, 0, &[tagged(id), error])
+}
+
+fn stream(id: Value) -> usize {
+    int(id) as usize
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioOpenIn(name: Value) -> Value {
+    let name = text(name).into_owned();
+    opened(rt().streams.open_in(&name))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioOpenOut(name: Value) -> Value {
+    let name = text(name).into_owned();
+    opened(rt().streams.open_out(&name, false))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioOpenAppend(name: Value) -> Value {
+    let name = text(name).into_owned();
+    opened(rt().streams.open_out(&name, true))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioClose(id: Value) -> Value {
+    rt().streams.close(stream(id));
+    UNIT
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioOutput(arg: Value) -> Value {
+    let (id, s) = (stream(args(arg)[0]), args(arg)[1]);
+    bool_value(rt().streams.output(id, unsafe { bytes(s) }))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioFlush(id: Value) -> Value {
+    bool_value(rt().streams.flush(stream(id)))
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioInput(arg: Value) -> Value {
+    let (id, n) = (stream(args(arg)[0]), int(args(arg)[1]));
+    let mut s = String::new();
+    if let Some(input) = rt().streams.input(id) {
+        for _ in 0..n {
+            match input.read_char() {
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+    }
+    rt().string(s.as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioPeek(arg: Value) -> Value {
+    let (id, n) = (stream(args(arg)[0]), int(args(arg)[1]));
+    let n = usize::try_from(n).unwrap_or(usize::MAX);
+    let c = rt().streams.input(id).and_then(|input| input.peek(n));
+    match c {
+        Some(c) => rt().some(char_value(c)),
+        None => NIL,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioInputLine(id: Value) -> Value {
+    let mut line = String::new();
+    if let Some(input) = rt().streams.input(stream(id)) {
+        while let Some(c) = input.read_char() {
+            line.push(c);
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+    if line.is_empty() {
+        return NIL;
+    }
+    if !line.ends_with('\n') {
+        line.push('\n');
+    }
+    let line = rt().string(line.as_bytes());
+    rt().some(line)
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioInputAll(id: Value) -> Value {
+    let s = match rt().streams.input(stream(id)) {
+        Some(input) => input.read_all(),
+        None => String::new(),
+    };
+    rt().string(s.as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn smol_prim_ioEndOfStream(id: Value) -> Value {
+    let input = rt().streams.input(stream(id));
+    bool_value(input.and_then(|input| input.peek(0)).is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smol_exn;
+    use crate::tests::{install, string};
+
+    fn tuple(fields: &[Value]) -> Value {
+        rt().object(kind::RECORD, 0, fields)
+    }
+
+    fn raised() -> Option<String> {
+        let exn = unsafe { std::mem::replace(&mut *std::ptr::addr_of_mut!(smol_exn), 0) };
+        if exn == 0 {
+            None
+        } else {
+            Some(rt().exn_name(exn).to_owned())
+        }
+    }
+
+    #[test]
+    fn ints_and_words() {
+        let _guard = install(&mut []);
+        let n = smol_prim_intQuot(tuple(&[tagged(-7), tagged(2)]));
+        assert_eq!(untagged(n), -3);
+        assert_eq!(
+            untagged(smol_prim_intRem(tuple(&[tagged(-7), tagged(2)]))),
+            -1
+        );
+        assert_eq!(smol_prim_intQuot(tuple(&[tagged(1), tagged(0)])), 0);
+        assert_eq!(raised().as_deref(), Some("Div"));
+        let s = smol_prim_intToString(tagged(-12));
+        assert_eq!(string(s), "~12");
+
+        let w = smol_prim_wordFromInt(tagged(-1));
+        assert_eq!(smol_prim_wordToInt(w), 0);
+        assert_eq!(raised().as_deref(), Some("Overflow"));
+        let w = smol_prim_wordFromInt(tagged(12));
+        let roots = Roots::new(&[w]);
+        let three = smol_prim_wordFromInt(tagged(3));
+        let shifted = smol_prim_wordShl(tuple(&[roots.get(0), three]));
+        assert_eq!(untagged(smol_prim_wordToInt(shifted)), 96);
+    }
+
+    #[test]
+    fn reals() {
+        let _guard = install(&mut []);
+        let x = smol_prim_realFromInt(tagged(-3));
+        assert_eq!(string(smol_prim_realToString(x)), "~3.0");
+        let s = rt().string(b"  1.5e2xyz");
+        let scanned = smol_prim_realScan(s);
+        let pair = unsafe { field(scanned, 0) };
+        assert_eq!(unsafe { real(field(pair, 0)) }, 150.0);
+        assert_eq!(untagged(unsafe { field(pair, 1) }), 7);
+        let nan = rt().real(f64::NAN);
+        assert_eq!(smol_prim_realFloor(nan), 0);
+        assert_eq!(raised().as_deref(), Some("Domain"));
+        let x = rt().real(2.5);
+        assert_eq!(untagged(smol_prim_realRound(x)), 2);
+    }
+
+    #[test]
+    fn strings() {
+        let _guard = install(&mut []);
+        let s = rt().string("h\u{e9}llo".as_bytes());
+        let roots = Roots::new(&[s]);
+        assert_eq!(untagged(smol_prim_stringSize(s)), 5);
+        let sub = smol_prim_stringSub(tuple(&[roots.get(0), tagged(1)]));
+        assert_eq!(untagged(sub), 0xe9);
+        let extract = smol_prim_stringExtract(tuple(&[roots.get(0), tagged(1), tagged(3)]));
+        assert_eq!(string(extract), "\u{e9}ll");
+        smol_prim_stringExtract(tuple(&[roots.get(0), tagged(4), tagged(2)]));
+        assert_eq!(raised().as_deref(), Some("Subscript"));
+
+        let chars = smol_prim_stringExplode(roots.get(0));
+        assert_eq!(unsafe { list(chars) }.len(), 5);
+        let imploded = smol_prim_stringImplode(chars);
+        assert_eq!(string(imploded), "h\u{e9}llo");
+        let a = rt().string(b"ab");
+        let ss = rt().object(kind::RECORD, 0, &[a, NIL]);
+        let ss = rt().object(kind::CON, 1, &[ss]);
+        let roots = Roots::new(&[ss]);
+        let cell = rt().object(kind::RECORD, 0, &[a, roots.get(0)]);
+        assert_eq!(string(smol_prim_stringConcat(rt().some(cell))), "abab");
+    }
+
+    #[test]
+    fn vectors_and_arrays() {
+        let _guard = install(&mut []);
+        let xs = rt().list((0..2000).map(tagged));
+        let v = smol_prim_vectorFromList(xs);
+        assert_eq!(untagged(smol_prim_vectorLength(v)), 2000);
+        assert_eq!(
+            untagged(smol_prim_vectorSub(tuple(&[v, tagged(1999)]))),
+            1999
+        );
+        let a = smol_prim_arrayArray(tuple(&[tagged(3), tagged(7)]));
+        let roots = Roots::new(&[a]);
+        smol_prim_arrayUpdate(tuple(&[roots.get(0), tagged(1), tagged(8)]));
+        assert_eq!(
+            smol_prim_arrayUpdate(tuple(&[roots.get(0), tagged(3), UNIT])),
+            0
+        );
+        assert_eq!(raised().as_deref(), Some("Subscript"));
+        let v = smol_prim_arrayVector(roots.get(0));
+        assert_eq!(unsafe { fields(v) }, [tagged(7), tagged(8), tagged(7)]);
+        smol_prim_arrayArray(tuple(&[tagged(-1), UNIT]));
+        assert_eq!(raised().as_deref(), Some("Size"));
+    }
+}
@@ -0,0 +1,230 @@
+//! The representation of values, which must agree with `smol::codegen`.
+//!
+//! A value is a word. Immediate values have a low bit of 1, and anything
+//! else is a pointer to an object: a header word, then the object's fields.
+//! A value of 0 is neither, and is what frame slots hold before they're
+//! bound.
+
+use std::slice;
+
+pub type Value = i64;
+
+/// Kinds of object, the low byte of their header.
+pub mod kind {
+    pub const RECORD: u8 = 0;
+    pub const CON: u8 = 1;
+    pub const EXN: u8 = 2;
+    pub const REF: u8 = 3;
+    pub const ARRAY: u8 = 4;
+    pub const VECTOR: u8 = 5;
+    pub const CLOSURE: u8 = 6;
+    pub const STRING: u8 = 7;
+    pub const REAL: u8 = 8;
+    pub const WORD: u8 = 9;
+}
+
+/// The ids of the built in exceptions, in the order `smol::types` declares
+/// them.
+pub mod exn {
+    pub const BIND: u32 = 0;
+    pub const MATCH: u32 = 1;
+    pub const DIV: u32 = 2;
+    pub const OVERFLOW: u32 = 3;
+    pub const SIZE: u32 = 4;
+    pub const SUBSCRIPT: u32 = 5;
+    pub const CHR: u32 = 6;
+    pub const DOMAIN: u32 = 7;
+    pub const EMPTY: u32 = 8;
+    pub const FAIL: u32 = 9;
+    pub const SPAN: u32 = 10;
+}
+
+pub const UNIT: Value = 1;
+pub const FALSE: Value = 1;
+pub const TRUE: Value = 3;
+/// `nil` and `NONE`.
+pub const NIL: Value = 1;
+
+pub fn header(kind: u8, tag: u32, len: u32) -> u64 {
+    (len as u64) << 32 | (tag as u64 & 0xff_ffff) << 8 | kind as u64
+}
+
+pub fn header_kind(header: u64) -> u8 {
+    header as u8
+}
+
+pub fn header_tag(header: u64) -> u32 {
+    (header >> 8) as u32 & 0xff_ffff
+}
+
+pub fn header_len(header: u64) -> usize {
+    (header >> 32) as usize
+}
+
+/// The number of words after an object's header.
+pub fn payload(header: u64) -> usize {
+    match header_kind(header) {
+        kind::STRING => header_len(header).div_ceil(8),
+        _ => header_len(header),
+    }
+}
+
+pub fn is_object(value: Value) -> bool {
+    value != 0 && value & 1 == 0
+}
+
+pub fn tagged(n: i64) -> Value {
+    n.wrapping_shl(1) | 1
+}
+
+pub fn untagged(value: Value) -> i64 {
+    value >> 1
+}
+
+pub fn bool(b: bool) -> Value {
+    if b {
+        TRUE
+    } else {
+        FALSE
+    }
+}
+
+/// The largest int, which is 63 bits.
+pub const MAX_INT: i64 = i64::MAX >> 1;
+pub const MIN_INT: i64 = i64::MIN >> 1;
+
+pub fn fits(n: i64) -> bool {
+    (MIN_INT..=MAX_INT).contains(&n)
+}
+
+/// Reading objects. These take values that must be objects of the right
+/// kind: compiled code is type checked, so they always are.
+///
+/// # Safety
+///
+/// `value` must point to a live object, and the result must not be used
+/// after anything allocates.
+pub unsafe fn object_header(value: Value) -> u64 {
+    *(value as *const u64)
+}
+
+/// # Safety
+///
+/// As `object_header`, and the object must have the field.
+pub unsafe fn field(value: Value, i: usize) -> Value {
+    *(value as *const i64).add(i + 1)
+}
+
+/// # Safety
+///
+/// As `field`.
+pub unsafe fn set_field(value: Value, i: usize, field: Value) {
+    *(value as *mut i64).add(i + 1) = field;
+}
+
+/// # Safety
+///
+/// As `object_header`.
+pub unsafe fn len(value: Value) -> usize {
+    header_len(object_header(value))
+}
+
+/// The fields of a record, vector or array.
+///
+/// # Safety
+///
+/// As `object_header`.
+pub unsafe fn fields<'a>(value: Value) -> &'a [Value] {
+    slice::from_raw_parts((value as *const i64).add(1), len(value))
+}
+
+/// The bytes of a string.
+///
+/// # Safety
+///
+/// As `object_header`.
+pub unsafe fn bytes<'a>(value: Value) -> &'a [u8] {
+    slice::from_raw_parts((value as *const u8).add(8), len(value))
+}
+
+/// # Safety
+///
+/// As `object_header`.
+pub unsafe fn word(value: Value) -> u64 {
+    field(value, 0) as u64
+}
+
+/// # Safety
+///
+/// As `object_header`.
+pub unsafe fn real(value: Value) -> f64 {
+    f64::from_bits(field(value, 0) as u64)
+}
+
+/// The elements of a list, which can't be used after anything allocates.
+///
+/// # Safety
+///
+/// `value` must be a list.
+pub unsafe fn list(mut value: Value) -> Vec<Value> {
+    let mut items = Vec::new();
+    while value != NIL {
+        let cell = field(value, 0);
+        items.push(field(cell, 0));
+        value = field(cell, 1);
+    }
+    items
+}
+
+/// Structural equality, for `=`. Refs and arrays are equal if they're the
+/// same ref or array.
+///
+/// # Safety
+///
+/// `a` and `b` must be values of the same type.
+pub unsafe fn equal(a: Value, b: Value) -> bool {
+    if a == b {
+        return true;
+    }
+    if !is_object(a) || !is_object(b) {
+        return false;
+    }
+    let header = object_header(a);
+    if header != object_header(b) {
+        return false;
+    }
+    match header_kind(header) {
+        kind::REF | kind::ARRAY => false,
+        kind::STRING => bytes(a) == bytes(b),
+        kind::WORD => word(a) == word(b),
+        kind::REAL => real(a) == real(b),
+        _ => fields(a).iter().zip(fields(b)).all(|(a, b)| equal(*a, *b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let h = header(kind::CON, 3, 1);
+        assert_eq!(h, 0x1_0000_0301);
+        assert_eq!(
+            (header_kind(h), header_tag(h), header_len(h)),
+            (kind::CON, 3, 1)
+        );
+        assert_eq!(payload(header(kind::STRING, 0, 9)), 2);
+        assert_eq!(payload(header(kind::STRING, 0, 0)), 0);
+        assert_eq!(payload(header(kind::RECORD, 0, 3)), 3);
+    }
+
+    #[test]
+    fn immediates() {
+        assert_eq!(untagged(tagged(-5)), -5);
+        assert!(!is_object(tagged(4)));
+        assert!(!is_object(0));
+        assert!(fits(MAX_INT) && !fits(MAX_INT + 1));
+        assert_eq!(untagged(tagged(MIN_INT)), MIN_INT);
+    }
+}
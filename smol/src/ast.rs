@@ -0,0 +1,456 @@
+//! The abstract syntax of Standard ML.
+//!
+//! Derived forms are kept where later passes care about them (e.g. tuples,
+//! `while`, `andalso`), and every node carries the span it was lowered from.
+//!
+//! Infix operators can't be resolved while parsing, since fixity depends on
+//! declarations. Lowering produces `ExpKind::Flat` and `PatKind::Flat` for
+//! sequences of atoms, and leaves the names of function clauses unset. These
+//! are resolved by a later pass.
+
+use std::fmt::{self, Display};
+
+use parsegen::Span;
+
+mod print;
+
+/// An unqualified identifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Id {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A possibly qualified identifier, e.g. `x` or `List.map`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LongId {
+    /// Structure ids, outermost first.
+    pub path: Vec<String>,
+    pub name: String,
+    pub span: Span,
+}
+
+impl LongId {
+    pub fn is_qualified(&self) -> bool {
+        !self.path.is_empty()
+    }
+}
+
+impl From<Id> for LongId {
+    fn from(id: Id) -> Self {
+        LongId {
+            path: Vec::new(),
+            name: id.name,
+            span: id.span,
+        }
+    }
+}
+
+impl Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Display for LongId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for strid in &self.path {
+            write!(f, "{}.", strid)?;
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A type variable, without its leading quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TyVar {
+    pub name: String,
+    /// Set for equality type variables (`''a`).
+    pub eq: bool,
+    pub span: Span,
+}
+
+impl Display for TyVar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.eq { "''" } else { "'" }, self.name)
+    }
+}
+
+/// A record label. Numeric labels sort before alphanumeric ones, and sort
+/// numerically.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lab {
+    Num(u32),
+    Id(String),
+}
+
+impl Display for Lab {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lab::Num(n) => write!(f, "{}", n),
+            Lab::Id(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// The smallest and largest ints. Ints are 63 bits, so that every backend
+/// can tag them, and the interpreter keeps to the same range.
+pub const MIN_INT: i64 = -(1 << 62);
+pub const MAX_INT: i64 = (1 << 62) - 1;
+
+/// Special constants.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Int(i64),
+    Word(u64),
+    Real(f64),
+    Char(char),
+    String(String),
+}
+
+impl Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Const::Int(n) if *n < 0 => write!(f, "~{}", n.unsigned_abs()),
+            Const::Int(n) => write!(f, "{}", n),
+            Const::Word(n) => write!(f, "0w{}", n),
+            Const::Real(n) => write!(f, "{}", n.to_string().replace('-', "~")),
+            Const::Char(c) => write!(f, "#{:?}", c.to_string()),
+            Const::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// A complete program: a sequence of top level declarations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub items: Vec<TopDec>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopDec {
+    Str(StrDec),
+    Sig(Vec<SigBind>),
+    Functor(Vec<FunctorBind>),
+    /// A top level expression, short for `val it = exp`.
+    Exp(Exp),
+}
+
+// Modules
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrDec {
+    pub kind: StrDecKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrDecKind {
+    Dec(Dec),
+    Structure(Vec<StrBind>),
+    Local(Vec<StrDec>, Vec<StrDec>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrBind {
+    pub id: Id,
+    pub sig: Option<Ascription>,
+    pub str: StrExp,
+    pub span: Span,
+}
+
+/// A signature constraint on a structure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ascription {
+    pub sig: SigExp,
+    /// Set for opaque (`:>`) ascription.
+    pub opaque: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrExp {
+    pub kind: StrExpKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrExpKind {
+    Struct(Vec<StrDec>),
+    Var(LongId),
+    Ascribe(Box<StrExp>, Box<Ascription>),
+    /// Functor application. Applying a functor to declarations is parsed as
+    /// applying it to a `struct ... end`.
+    App(Id, Box<StrExp>),
+    Let(Vec<StrDec>, Box<StrExp>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigBind {
+    pub id: Id,
+    pub sig: SigExp,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigExp {
+    pub kind: SigExpKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SigExpKind {
+    Sig(Vec<Spec>),
+    Var(Id),
+    /// `sig where type tyvarseq longtycon = ty`
+    Where {
+        sig: Box<SigExp>,
+        tyvars: Vec<TyVar>,
+        tycon: LongId,
+        ty: Ty,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spec {
+    pub kind: SpecKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpecKind {
+    Val(Vec<(Id, Ty)>),
+    /// `type` and `eqtype` specifications, with an optional definition.
+    Type {
+        tyvars: Vec<TyVar>,
+        tycon: Id,
+        eq: bool,
+        def: Option<Ty>,
+    },
+    Datatype(Vec<DatBind>),
+    DatatypeRepl(Id, LongId),
+    Exception(Vec<(Id, Option<Ty>)>),
+    Structure(Vec<(Id, SigExp)>),
+    Include(SigExp),
+    /// `sharing type longtycon = ... = longtycon`
+    SharingType(Vec<LongId>),
+    /// `sharing longstrid = ... = longstrid`
+    Sharing(Vec<LongId>),
+}
+
+/// `functor id (strid : sigexp) [: sigexp] = strexp`. The derived form with
+/// a specification as the argument uses `param: None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctorBind {
+    pub id: Id,
+    pub param: Option<Id>,
+    pub param_sig: SigExp,
+    pub sig: Option<Ascription>,
+    pub body: StrExp,
+    pub span: Span,
+}
+
+// Declarations
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dec {
+    pub kind: DecKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecKind {
+    Val {
+        tyvars: Vec<TyVar>,
+        binds: Vec<ValBind>,
+    },
+    Fun {
+        tyvars: Vec<TyVar>,
+        binds: Vec<FunBind>,
+    },
+    Type(Vec<TypBind>),
+    Datatype {
+        binds: Vec<DatBind>,
+        withtype: Vec<TypBind>,
+    },
+    /// `datatype tycon = datatype longtycon`
+    DatatypeRepl(Id, LongId),
+    Abstype {
+        binds: Vec<DatBind>,
+        withtype: Vec<TypBind>,
+        body: Vec<Dec>,
+    },
+    Exception(Vec<ExBind>),
+    Local(Vec<Dec>, Vec<Dec>),
+    Open(Vec<LongId>),
+    Fixity(Fixity, Vec<Id>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    Infix(u8),
+    Infixr(u8),
+    Nonfix,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValBind {
+    pub rec: bool,
+    pub pat: Pat,
+    pub exp: Exp,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunBind {
+    pub clauses: Vec<Clause>,
+    pub span: Span,
+}
+
+/// One clause of a function binding.
+///
+/// Lowering leaves `name` unset and puts every atomic pattern in `args`,
+/// since which one is the function name depends on fixity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    pub name: Option<Id>,
+    pub args: Vec<Pat>,
+    pub ty: Option<Ty>,
+    pub body: Exp,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypBind {
+    pub tyvars: Vec<TyVar>,
+    pub tycon: Id,
+    pub ty: Ty,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatBind {
+    pub tyvars: Vec<TyVar>,
+    pub tycon: Id,
+    pub cons: Vec<ConBind>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConBind {
+    pub id: Id,
+    pub arg: Option<Ty>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExBind {
+    /// `exception id [of ty]`
+    New { id: Id, arg: Option<Ty>, span: Span },
+    /// `exception id = longid`
+    Copy { id: Id, from: LongId, span: Span },
+}
+
+// Expressions
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exp {
+    pub kind: ExpKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpKind {
+    Const(Const),
+    /// A variable or constructor. `op` is set if it was prefixed with `op`.
+    Var {
+        op: bool,
+        id: LongId,
+    },
+    /// A record selector, e.g. `#1`.
+    Selector(Lab),
+    Record(Vec<(Lab, Exp)>),
+    /// Tuples, including unit.
+    Tuple(Vec<Exp>),
+    List(Vec<Exp>),
+    /// `(e1; ...; en)`
+    Seq(Vec<Exp>),
+    Let(Vec<Dec>, Box<Exp>),
+    /// A sequence of atomic expressions whose infix operators haven't been
+    /// resolved yet.
+    Flat(Vec<Exp>),
+    App(Box<Exp>, Box<Exp>),
+    Typed(Box<Exp>, Ty),
+    Andalso(Box<Exp>, Box<Exp>),
+    Orelse(Box<Exp>, Box<Exp>),
+    Handle(Box<Exp>, Vec<MRule>),
+    Raise(Box<Exp>),
+    If(Box<Exp>, Box<Exp>, Box<Exp>),
+    While(Box<Exp>, Box<Exp>),
+    Case(Box<Exp>, Vec<MRule>),
+    Fn(Vec<MRule>),
+}
+
+/// A `pat => exp` rule of a match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MRule {
+    pub pat: Pat,
+    pub exp: Exp,
+    pub span: Span,
+}
+
+// Patterns
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pat {
+    pub kind: PatKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatKind {
+    Wildcard,
+    Const(Const),
+    /// A variable, or a constructor without an argument.
+    Var {
+        op: bool,
+        id: LongId,
+    },
+    /// A record pattern. `flexible` is set if it ends in `...`.
+    Record {
+        rows: Vec<(Lab, Pat)>,
+        flexible: bool,
+    },
+    Tuple(Vec<Pat>),
+    List(Vec<Pat>),
+    /// A sequence of atomic patterns whose infix constructors haven't been
+    /// resolved yet.
+    Flat(Vec<Pat>),
+    /// A constructor applied to a pattern.
+    Con(LongId, Box<Pat>),
+    Typed(Box<Pat>, Ty),
+    /// `id [: ty] as pat`
+    Layered {
+        id: Id,
+        ty: Option<Ty>,
+        pat: Box<Pat>,
+    },
+}
+
+// Types
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ty {
+    pub kind: TyKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TyKind {
+    Var(TyVar),
+    Record(Vec<(Lab, Ty)>),
+    Tuple(Vec<Ty>),
+    /// A type constructor applied to arguments, e.g. `(int, string) either`.
+    Con(Vec<Ty>, LongId),
+    Arrow(Box<Ty>, Box<Ty>),
+}
@@ -0,0 +1,657 @@
+//! Printing the AST as SML, for dumps.
+//!
+//! Declarations print a line each, with the bodies of structures,
+//! signatures and `local` and `let` declarations indented, and expressions,
+//! patterns and types on one line, parenthesised where they need to be.
+//! Infix expressions, patterns and function clauses print as the prefix
+//! applications they're resolved to, e.g. `+ (x, 1)`, which shows how they
+//! were resolved:
+//!
+//! ```text
+//! structure Area =
+//!   struct
+//!     fun area (Circle r) = * (* (3, r), r)
+//!       | area (Square s) = * (s, s)
+//!   end
+//! fun ++ (a, b) = + (a, b)
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use super::*;
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut printer = Printer { f, indent: 0 };
+        for item in &self.items {
+            printer.top_dec(item)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Exp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", exp(self))
+    }
+}
+
+impl Display for Pat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", pat(self))
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", ty(self))
+    }
+}
+
+struct Printer<'f, 'a> {
+    f: &'f mut Formatter<'a>,
+    indent: usize,
+}
+
+impl Printer<'_, '_> {
+    fn line(&mut self, line: impl Display) -> fmt::Result {
+        writeln!(self.f, "{:indent$}{}", "", line, indent = self.indent * 2)
+    }
+
+    /// Lines printed by `body`, indented a level.
+    fn nested(&mut self, body: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        self.indent += 1;
+        body(self)?;
+        self.indent -= 1;
+        Ok(())
+    }
+
+    fn top_dec(&mut self, item: &TopDec) -> fmt::Result {
+        match item {
+            TopDec::Str(dec) => self.str_dec(dec),
+            TopDec::Sig(binds) => {
+                for (i, bind) in binds.iter().enumerate() {
+                    let keyword = if i == 0 { "signature" } else { "and" };
+                    self.line(format!("{} {} =", keyword, bind.id))?;
+                    self.nested(|p| p.sig_exp(&bind.sig))?;
+                }
+                Ok(())
+            }
+            TopDec::Functor(binds) => {
+                for (i, bind) in binds.iter().enumerate() {
+                    let keyword = if i == 0 { "functor" } else { "and" };
+                    let sig = bind.sig.as_ref().map(ascription).unwrap_or_default();
+                    match &bind.param {
+                        Some(param) => self.line(format!(
+                            "{} {} ({} : {}){} =",
+                            keyword,
+                            bind.id,
+                            param,
+                            sig_exp(&bind.param_sig),
+                            sig
+                        ))?,
+                        None => {
+                            self.line(format!("{} {} (", keyword, bind.id))?;
+                            self.nested(|p| p.specs(&bind.param_sig))?;
+                            self.line(format!("){} =", sig))?;
+                        }
+                    }
+                    self.nested(|p| p.str_exp(&bind.body))?;
+                }
+                Ok(())
+            }
+            TopDec::Exp(e) => self.line(format!("{};", exp(e))),
+        }
+    }
+
+    fn str_dec(&mut self, dec: &StrDec) -> fmt::Result {
+        match &dec.kind {
+            StrDecKind::Dec(dec) => self.dec(dec),
+            StrDecKind::Structure(binds) => {
+                for (i, bind) in binds.iter().enumerate() {
+                    let keyword = if i == 0 { "structure" } else { "and" };
+                    let sig = bind.sig.as_ref().map(ascription).unwrap_or_default();
+                    self.line(format!("{} {}{} =", keyword, bind.id, sig))?;
+                    self.nested(|p| p.str_exp(&bind.str))?;
+                }
+                Ok(())
+            }
+            StrDecKind::Local(local, body) => {
+                self.line("local")?;
+                self.nested(|p| local.iter().try_for_each(|dec| p.str_dec(dec)))?;
+                self.line("in")?;
+                self.nested(|p| body.iter().try_for_each(|dec| p.str_dec(dec)))?;
+                self.line("end")
+            }
+        }
+    }
+
+    fn str_exp(&mut self, str: &StrExp) -> fmt::Result {
+        match &str.kind {
+            StrExpKind::Struct(decs) => {
+                self.line("struct")?;
+                self.nested(|p| decs.iter().try_for_each(|dec| p.str_dec(dec)))?;
+                self.line("end")
+            }
+            StrExpKind::Var(id) => self.line(id),
+            StrExpKind::Ascribe(str, sig) => {
+                self.str_exp(str)?;
+                self.line(ascription(sig).trim_start())
+            }
+            StrExpKind::App(functor, arg) => {
+                self.line(format!("{} (", functor))?;
+                self.nested(|p| p.str_exp(arg))?;
+                self.line(")")
+            }
+            StrExpKind::Let(decs, body) => {
+                self.line("let")?;
+                self.nested(|p| decs.iter().try_for_each(|dec| p.str_dec(dec)))?;
+                self.line("in")?;
+                self.nested(|p| p.str_exp(body))?;
+                self.line("end")
+            }
+        }
+    }
+
+    fn sig_exp(&mut self, sig: &SigExp) -> fmt::Result {
+        match &sig.kind {
+            SigExpKind::Sig(_) => {
+                self.line("sig")?;
+                self.nested(|p| p.specs(sig))?;
+                self.line("end")
+            }
+            _ => self.line(sig_exp(sig)),
+        }
+    }
+
+    /// The specifications of a `sig ... end`, or, for any other signature,
+    /// an `include` of it.
+    fn specs(&mut self, sig: &SigExp) -> fmt::Result {
+        let specs = match &sig.kind {
+            SigExpKind::Sig(specs) => specs,
+            _ => return self.line(format!("include {}", sig_exp(sig))),
+        };
+        for spec in specs {
+            match &spec.kind {
+                SpecKind::Val(vals) => {
+                    for (i, (id, t)) in vals.iter().enumerate() {
+                        let keyword = if i == 0 { "val" } else { "and" };
+                        self.line(format!("{} {} : {}", keyword, id, ty(t)))?;
+                    }
+                }
+                SpecKind::Type {
+                    tyvars,
+                    tycon,
+                    eq,
+                    def,
+                } => {
+                    let keyword = if *eq { "eqtype" } else { "type" };
+                    let def = def.as_ref().map(|t| format!(" = {}", ty(t)));
+                    self.line(format!(
+                        "{} {}{}{}",
+                        keyword,
+                        ty_vars(tyvars),
+                        tycon,
+                        def.unwrap_or_default()
+                    ))?;
+                }
+                SpecKind::Datatype(binds) => self.dat_binds("datatype", binds)?,
+                SpecKind::DatatypeRepl(id, from) => {
+                    self.line(format!("datatype {} = datatype {}", id, from))?
+                }
+                SpecKind::Exception(exns) => {
+                    for (i, (id, arg)) in exns.iter().enumerate() {
+                        let keyword = if i == 0 { "exception" } else { "and" };
+                        self.line(format!("{} {}{}", keyword, id, of(arg.as_ref())))?;
+                    }
+                }
+                SpecKind::Structure(strs) => {
+                    for (i, (id, sig)) in strs.iter().enumerate() {
+                        let keyword = if i == 0 { "structure" } else { "and" };
+                        self.line(format!("{} {} :", keyword, id))?;
+                        self.nested(|p| p.sig_exp(sig))?;
+                    }
+                }
+                SpecKind::Include(sig) => self.line(format!("include {}", sig_exp(sig)))?,
+                SpecKind::SharingType(ids) => {
+                    self.line(format!("sharing type {}", joined(ids, " = ")))?
+                }
+                SpecKind::Sharing(ids) => self.line(format!("sharing {}", joined(ids, " = ")))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn dec(&mut self, dec: &Dec) -> fmt::Result {
+        match &dec.kind {
+            DecKind::Val { tyvars, binds } => {
+                for (i, bind) in binds.iter().enumerate() {
+                    let keyword = if i == 0 {
+                        format!("val {}", ty_vars(tyvars))
+                    } else {
+                        "and ".to_owned()
+                    };
+                    let rec = if bind.rec { "rec " } else { "" };
+                    self.line(format!(
+                        "{}{}{} = {}",
+                        keyword,
+                        rec,
+                        pat(&bind.pat),
+                        exp(&bind.exp)
+                    ))?;
+                }
+                Ok(())
+            }
+            DecKind::Fun { tyvars, binds } => {
+                for (i, bind) in binds.iter().enumerate() {
+                    for (j, clause) in bind.clauses.iter().enumerate() {
+                        let keyword = match (i, j) {
+                            (0, 0) => format!("fun {}", ty_vars(tyvars)),
+                            (_, 0) => "and ".to_owned(),
+                            _ => "  | ".to_owned(),
+                        };
+                        self.line(format!("{}{}", keyword, clause_str(clause)))?;
+                    }
+                }
+                Ok(())
+            }
+            DecKind::Type(binds) => self.typ_binds("type", binds),
+            DecKind::Datatype { binds, withtype } => {
+                self.dat_binds("datatype", binds)?;
+                self.typ_binds("withtype", withtype)
+            }
+            DecKind::DatatypeRepl(id, from) => {
+                self.line(format!("datatype {} = datatype {}", id, from))
+            }
+            DecKind::Abstype {
+                binds,
+                withtype,
+                body,
+            } => {
+                self.dat_binds("abstype", binds)?;
+                self.typ_binds("withtype", withtype)?;
+                self.line("with")?;
+                self.nested(|p| body.iter().try_for_each(|dec| p.dec(dec)))?;
+                self.line("end")
+            }
+            DecKind::Exception(binds) => {
+                for (i, bind) in binds.iter().enumerate() {
+                    let keyword = if i == 0 { "exception" } else { "and" };
+                    match bind {
+                        ExBind::New { id, arg, .. } => {
+                            self.line(format!("{} {}{}", keyword, id, of(arg.as_ref())))?
+                        }
+                        ExBind::Copy { id, from, .. } => {
+                            self.line(format!("{} {} = {}", keyword, id, from))?
+                        }
+                    }
+                }
+                Ok(())
+            }
+            DecKind::Local(local, body) => {
+                self.line("local")?;
+                self.nested(|p| local.iter().try_for_each(|dec| p.dec(dec)))?;
+                self.line("in")?;
+                self.nested(|p| body.iter().try_for_each(|dec| p.dec(dec)))?;
+                self.line("end")
+            }
+            DecKind::Open(ids) => self.line(format!("open {}", joined(ids, " "))),
+            DecKind::Fixity(fixity, ids) => {
+                let fixity = match fixity {
+                    Fixity::Infix(d) => format!("infix {}", d),
+                    Fixity::Infixr(d) => format!("infixr {}", d),
+                    Fixity::Nonfix => "nonfix".to_owned(),
+                };
+                self.line(format!("{} {}", fixity, joined(ids, " ")))
+            }
+        }
+    }
+
+    fn typ_binds(&mut self, keyword: &str, binds: &[TypBind]) -> fmt::Result {
+        for (i, bind) in binds.iter().enumerate() {
+            let keyword = if i == 0 { keyword } else { "and" };
+            self.line(format!(
+                "{} {}{} = {}",
+                keyword,
+                ty_vars(&bind.tyvars),
+                bind.tycon,
+                ty(&bind.ty)
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn dat_binds(&mut self, keyword: &str, binds: &[DatBind]) -> fmt::Result {
+        for (i, bind) in binds.iter().enumerate() {
+            let keyword = if i == 0 { keyword } else { "and" };
+            let cons: Vec<_> = bind
+                .cons
+                .iter()
+                .map(|con| format!("{}{}", con.id, of(con.arg.as_ref())))
+                .collect();
+            self.line(format!(
+                "{} {}{} = {}",
+                keyword,
+                ty_vars(&bind.tyvars),
+                bind.tycon,
+                cons.join(" | ")
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+fn joined<T: Display>(items: &[T], sep: &str) -> String {
+    let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+    items.join(sep)
+}
+
+/// ` : sig` or ` :> sig`.
+fn ascription(sig: &Ascription) -> String {
+    let colon = if sig.opaque { ":>" } else { ":" };
+    format!(" {} {}", colon, sig_exp(&sig.sig))
+}
+
+/// A signature on one line.
+fn sig_exp(sig: &SigExp) -> String {
+    match &sig.kind {
+        SigExpKind::Sig(_) => one_line(|p| p.sig_exp(sig)),
+        SigExpKind::Var(id) => id.to_string(),
+        SigExpKind::Where {
+            sig,
+            tyvars,
+            tycon,
+            ty: t,
+        } => format!(
+            "{} where type {}{} = {}",
+            sig_exp(sig),
+            ty_vars(tyvars),
+            tycon,
+            ty(t)
+        ),
+    }
+}
+
+/// Type variables before a type constructor or in a declaration, with the
+/// space after them.
+fn ty_vars(tyvars: &[TyVar]) -> String {
+    match tyvars {
+        [] => String::new(),
+        [tyvar] => format!("{} ", tyvar),
+        _ => format!("({}) ", joined(tyvars, ", ")),
+    }
+}
+
+/// ` of ty`, for constructors and exceptions that take an argument.
+fn of(arg: Option<&Ty>) -> String {
+    arg.map(|t| format!(" of {}", ty(t))).unwrap_or_default()
+}
+
+fn clause_str(clause: &Clause) -> String {
+    let mut s = clause
+        .name
+        .as_ref()
+        .map_or_else(|| "?".to_owned(), |name| name.to_string());
+    for arg in &clause.args {
+        s += " ";
+        s += &at_pat(arg);
+    }
+    if let Some(t) = &clause.ty {
+        s += &format!(" : {}", ty(t));
+    }
+    format!("{} = {}", s, exp(&clause.body))
+}
+
+fn rules(rules: &[MRule]) -> String {
+    let rules: Vec<_> = rules
+        .iter()
+        .map(|rule| format!("{} => {}", pat(&rule.pat), rule_body(&rule.exp)))
+        .collect();
+    rules.join(" | ")
+}
+
+/// The body of a rule, in parentheses if it ends in rules of its own,
+/// which would take the ones after it.
+fn rule_body(e: &Exp) -> String {
+    match &e.kind {
+        ExpKind::Case(..) | ExpKind::Fn(_) | ExpKind::Handle(..) => format!("({})", exp(e)),
+        _ => exp(e),
+    }
+}
+
+fn exp(e: &Exp) -> String {
+    match &e.kind {
+        ExpKind::Typed(e, t) => format!("{} : {}", app_exp(e), ty(t)),
+        ExpKind::Andalso(a, b) => format!("{} andalso {}", app_exp(a), app_exp(b)),
+        ExpKind::Orelse(a, b) => format!("{} orelse {}", app_exp(a), app_exp(b)),
+        ExpKind::Handle(e, rs) => format!("{} handle {}", app_exp(e), rules(rs)),
+        ExpKind::Raise(e) => format!("raise {}", exp(e)),
+        ExpKind::If(c, t, f) => format!("if {} then {} else {}", exp(c), exp(t), exp(f)),
+        ExpKind::While(c, body) => format!("while {} do {}", exp(c), exp(body)),
+        ExpKind::Case(e, rs) => format!("case {} of {}", exp(e), rules(rs)),
+        ExpKind::Fn(rs) => format!("fn {}", rules(rs)),
+        _ => app_exp(e),
+    }
+}
+
+/// An application, or an atomic expression.
+fn app_exp(e: &Exp) -> String {
+    match &e.kind {
+        ExpKind::App(f, arg) => format!("{} {}", app_exp(f), at_exp(arg)),
+        _ => at_exp(e),
+    }
+}
+
+fn at_exp(e: &Exp) -> String {
+    match &e.kind {
+        ExpKind::Const(c) => c.to_string(),
+        ExpKind::Var { op: true, id } => format!("op {}", id),
+        ExpKind::Var { op: false, id } => id.to_string(),
+        ExpKind::Selector(lab) => format!("#{}", lab),
+        ExpKind::Record(rows) => {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(lab, e)| format!("{} = {}", lab, exp(e)))
+                .collect();
+            format!("{{{}}}", rows.join(", "))
+        }
+        ExpKind::Tuple(exps) => {
+            let exps: Vec<_> = exps.iter().map(exp).collect();
+            format!("({})", exps.join(", "))
+        }
+        ExpKind::List(exps) => {
+            let exps: Vec<_> = exps.iter().map(exp).collect();
+            format!("[{}]", exps.join(", "))
+        }
+        ExpKind::Seq(exps) => {
+            let exps: Vec<_> = exps.iter().map(exp).collect();
+            format!("({})", exps.join("; "))
+        }
+        ExpKind::Let(decs, body) => {
+            let decs: Vec<_> = decs.iter().map(dec_str).collect();
+            format!("let {} in {} end", decs.join(" "), exp(body))
+        }
+        ExpKind::Flat(exps) => {
+            let exps: Vec<_> = exps.iter().map(at_exp).collect();
+            format!("({})", exps.join(" "))
+        }
+        _ => format!("({})", exp(e)),
+    }
+}
+
+/// A declaration on one line, as in a `let`.
+fn dec_str(dec: &Dec) -> String {
+    one_line(|p| p.dec(dec))
+}
+
+/// What a printer prints, on one line.
+fn one_line(print: impl Fn(&mut Printer) -> fmt::Result) -> String {
+    struct Lines<F>(F);
+
+    impl<F: Fn(&mut Printer) -> fmt::Result> Display for Lines<F> {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            (self.0)(&mut Printer { f, indent: 0 })
+        }
+    }
+
+    let lines = Lines(print).to_string();
+    let lines: Vec<_> = lines.lines().map(str::trim).collect();
+    lines.join(" ")
+}
+
+fn pat(p: &Pat) -> String {
+    match &p.kind {
+        PatKind::Typed(p, t) => format!("{} : {}", con_pat(p), ty(t)),
+        PatKind::Layered { id, ty: t, pat: p } => {
+            let t = t.as_ref().map(|t| format!(" : {}", ty(t)));
+            format!("{}{} as {}", id, t.unwrap_or_default(), pat(p))
+        }
+        _ => con_pat(p),
+    }
+}
+
+/// A constructor application, or an atomic pattern.
+fn con_pat(p: &Pat) -> String {
+    match &p.kind {
+        PatKind::Con(con, arg) => format!("{} {}", con, at_pat(arg)),
+        _ => at_pat(p),
+    }
+}
+
+fn at_pat(p: &Pat) -> String {
+    match &p.kind {
+        PatKind::Wildcard => "_".to_owned(),
+        PatKind::Const(c) => c.to_string(),
+        PatKind::Var { op: true, id } => format!("op {}", id),
+        PatKind::Var { op: false, id } => id.to_string(),
+        PatKind::Record { rows, flexible } => {
+            let mut rows: Vec<_> = rows
+                .iter()
+                .map(|(lab, p)| format!("{} = {}", lab, pat(p)))
+                .collect();
+            if *flexible {
+                rows.push("...".to_owned());
+            }
+            format!("{{{}}}", rows.join(", "))
+        }
+        PatKind::Tuple(pats) => {
+            let pats: Vec<_> = pats.iter().map(pat).collect();
+            format!("({})", pats.join(", "))
+        }
+        PatKind::List(pats) => {
+            let pats: Vec<_> = pats.iter().map(pat).collect();
+            format!("[{}]", pats.join(", "))
+        }
+        PatKind::Flat(pats) => {
+            let pats: Vec<_> = pats.iter().map(at_pat).collect();
+            format!("({})", pats.join(" "))
+        }
+        _ => format!("({})", pat(p)),
+    }
+}
+
+fn ty(t: &Ty) -> String {
+    match &t.kind {
+        TyKind::Arrow(arg, ret) => format!("{} -> {}", tuple_ty(arg), ty(ret)),
+        _ => tuple_ty(t),
+    }
+}
+
+fn tuple_ty(t: &Ty) -> String {
+    match &t.kind {
+        TyKind::Tuple(tys) if !tys.is_empty() => {
+            let tys: Vec<_> = tys.iter().map(at_ty).collect();
+            tys.join(" * ")
+        }
+        _ => at_ty(t),
+    }
+}
+
+fn at_ty(t: &Ty) -> String {
+    match &t.kind {
+        TyKind::Var(tyvar) => tyvar.to_string(),
+        TyKind::Record(rows) => {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(lab, t)| format!("{} : {}", lab, ty(t)))
+                .collect();
+            format!("{{{}}}", rows.join(", "))
+        }
+        TyKind::Tuple(tys) if tys.is_empty() => "unit".to_owned(),
+        TyKind::Con(args, tycon) => match &args[..] {
+            [] => tycon.to_string(),
+            [arg] => format!("{} {}", at_ty(arg), tycon),
+            _ => {
+                let args: Vec<_> = args.iter().map(ty).collect();
+                format!("({}) {}", args.join(", "), tycon)
+            }
+        },
+        _ => format!("({})", ty(t)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parsegen::SourceMap;
+
+    /// Parse and resolve `src`, and print it.
+    fn print(src: &str) -> String {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        crate::fixity::resolve(&mut program).unwrap();
+        program.to_string()
+    }
+
+    #[test]
+    fn declarations() {
+        let src = "\
+structure S :> sig type t val x : t end = struct
+  datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree
+  type t = int tree
+  fun size Leaf = 0
+    | size (Node (l, _, r)) = size l + 1 + size r
+  val x = Node (Leaf, 1, Leaf)
+end
+";
+        assert_eq!(
+            print(src),
+            "\
+structure S :> sig type t val x : t end =
+  struct
+    datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree
+    type t = int tree
+    fun size Leaf = 0
+      | size (Node (l, _, r)) = + (+ (size l, 1), size r)
+    val x = Node (Leaf, 1, Leaf)
+  end
+"
+        );
+    }
+
+    #[test]
+    fn expressions() {
+        let cases = [
+            ("val f = fn x => x : int", "val f = fn x => x : int\n"),
+            (
+                "val y = case x of 0 => (case y of _ => 1) | _ => 2",
+                "val y = case x of 0 => (case y of _ => 1) | _ => 2\n",
+            ),
+            (
+                "val z = let val a = 1 in (a; #b {b = ~2}) end handle e => raise e",
+                "val z = let val a = 1 in (a; #b {b = ~2}) end handle e => raise e\n",
+            ),
+            (
+                "val (a, [b], c as {d, ...}) = f (g x)",
+                "val (a, [b], c as {d = d, ...}) = f (g x)\n",
+            ),
+            (
+                "fun 'a f (x : 'a -> 'a) = if true then x else op o (x, x)",
+                "fun 'a f (x : 'a -> 'a) = if true then x else op o (x, x)\n",
+            ),
+        ];
+        for (src, printed) in cases.iter() {
+            assert_eq!(print(src), *printed, "{}", src);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! The subset of the Standard ML Basis Library that programs start with.
+//!
+//! The Basis is written in SML, in the `basis` directory, on top of the
+//! structure `Prim`, whose values the interpreter implements (see
+//! `eval::NATIVES`). `prim.sml` declares their types as the signature `PRIM`,
+//! and the rest of the files are loaded after it, in order.
+
+/// The signature of `Prim`.
+pub const PRIM: &str = include_str!("../basis/prim.sml");
+
+/// The name of the signature `PRIM` declares.
+pub const PRIM_SIG: &str = "PRIM";
+
+/// The Basis' files, in the order they're loaded.
+pub const FILES: &[(&str, &str)] = &[
+    ("general.sml", include_str!("../basis/general.sml")),
+    ("list.sml", include_str!("../basis/list.sml")),
+    ("char.sml", include_str!("../basis/char.sml")),
+    ("string.sml", include_str!("../basis/string.sml")),
+    ("string-cvt.sml", include_str!("../basis/string-cvt.sml")),
+    ("int.sml", include_str!("../basis/int.sml")),
+    ("real.sml", include_str!("../basis/real.sml")),
+    ("vector.sml", include_str!("../basis/vector.sml")),
+    ("text-io.sml", include_str!("../basis/text-io.sml")),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{Interpreter, NATIVES};
+    use crate::repl::Session;
+    use parsegen::SourceMap;
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    /// Output written by a program, shared with the test.
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn session() -> Session {
+        Session::new(Interpreter::with_output(Box::new(io::sink())))
+    }
+
+    /// Check that each expression evaluates to the value and type.
+    fn assert_evals(cases: &[(&str, &str)]) {
+        let mut s = session();
+        for (exp, expected) in cases {
+            let out = s.input(&format!("{};", exp));
+            assert_eq!(out, format!("val it = {}\n", expected), "{}", exp);
+        }
+    }
+
+    #[test]
+    fn prim_matches_natives() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("prim.sml", PRIM);
+        let program = crate::lower::parse(&sources, file).unwrap();
+        let mut checker = crate::types::Checker::new();
+        checker.check_program(&program).unwrap();
+        let specs: BTreeSet<_> = checker.sigs[PRIM_SIG].env.values.keys().cloned().collect();
+        let natives: BTreeSet<_> = NATIVES.iter().map(|n| n.name.to_owned()).collect();
+        assert_eq!(specs, natives);
+    }
+
+    #[test]
+    fn print_length() {
+        let out = Capture::default();
+        let mut s = Session::new(Interpreter::with_output(Box::new(out.clone())));
+        assert_eq!(
+            s.input("print (Int.toString (List.length [1,2,3]));"),
+            "val it = () : unit\n"
+        );
+        assert_eq!(&*out.0.borrow(), b"3");
+    }
+
+    #[test]
+    fn general() {
+        assert_evals(&[
+            ("exnName (Fail \"x\")", "\"Fail\" : string"),
+            ("exnMessage (Fail \"x\")", "\"Fail: x\" : string"),
+            ("exnMessage Div", "\"Div\" : string"),
+            ("(ignore 1; not true)", "false : bool"),
+            ("Bool.fromString \"true\"", "SOME true : bool option"),
+            ("(valOf NONE; 0) handle Option => 1", "1 : int"),
+            (
+                "(getOpt (NONE, 2), Option.map (fn x => x + 1) (SOME 1))",
+                "(2, SOME 2) : int * int option",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn lists() {
+        assert_evals(&[
+            ("rev [1, 2, 3] @ [4]", "[3, 2, 1, 4] : int list"),
+            (
+                "List.tabulate (4, fn i => i * i)",
+                "[0, 1, 4, 9] : int list",
+            ),
+            (
+                "List.partition (fn x => x mod 2 = 0) [1, 2, 3, 4]",
+                "([2, 4], [1, 3]) : int list * int list",
+            ),
+            ("foldl op - 0 [1, 2, 3]", "2 : int"),
+            ("foldr op - 0 [1, 2, 3]", "2 : int"),
+            ("(List.nth ([1], 1); 0) handle Subscript => 1", "1 : int"),
+            ("(hd []; 0) handle Empty => 1", "1 : int"),
+            (
+                "(List.take ([1, 2, 3], 2), List.drop ([1, 2, 3], 2), List.last [1, 2])",
+                "([1, 2], [3], 2) : int list * int list * int",
+            ),
+            ("length (List.tabulate (10000, fn i => i))", "10000 : int"),
+            (
+                "ListPair.unzip (ListPair.zip ([1, 2, 3], [#\"a\", #\"b\"]))",
+                "([1, 2], [#\"a\", #\"b\"]) : int list * char list",
+            ),
+            (
+                "(ListPair.zipEq ([1], []); 0) handle ListPair.UnequalLengths => 1",
+                "1 : int",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn chars_and_strings() {
+        assert_evals(&[
+            ("(ord #\"a\", chr 65)", "(97, #\"A\") : int * char"),
+            ("(chr 256; 0) handle Chr => 1", "1 : int"),
+            (
+                "map Char.toString [#\"a\", #\"\\n\", #\"\\001\", #\"\\200\"]",
+                "[\"a\", \"\\\\n\", \"\\\\^A\", \"\\\\200\"] : string list",
+            ),
+            ("String.map Char.toUpper \"abc\"", "\"ABC\" : string"),
+            (
+                "String.tokens (fn c => c = #\",\") \"a,,b,\"",
+                "[\"a\", \"b\"] : string list",
+            ),
+            (
+                "String.fields (fn c => c = #\",\") \"a,,b\"",
+                "[\"a\", \"\", \"b\"] : string list",
+            ),
+            (
+                "String.concatWith \", \" [\"a\", \"b\"]",
+                "\"a, b\" : string",
+            ),
+            (
+                "(String.isPrefix \"ab\" \"abc\", String.isSubstring \"bc\" \"abcd\")",
+                "(true, true) : bool * bool",
+            ),
+            ("String.compare (\"b\", \"ab\")", "GREATER : order"),
+            (
+                "(substring (\"hello\", 1, 3), size \"h\\u00e9\")",
+                "(\"ell\", 2) : string * int",
+            ),
+            (
+                "(String.sub (\"abc\", 3); 0) handle Subscript => 1",
+                "1 : int",
+            ),
+            (
+                "StringCvt.padLeft #\" \" 4 \"ab\" ^ StringCvt.padRight #\".\" 3 \"c\"",
+                "\"  abc..\" : string",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn substrings() {
+        assert_evals(&[
+            (
+                "Substring.string (Substring.dropl Char.isSpace (Substring.full \"  x y\"))",
+                "\"x y\" : string",
+            ),
+            (
+                "map Substring.string (Substring.tokens Char.isSpace (Substring.full \" a bc \"))",
+                "[\"a\", \"bc\"] : string list",
+            ),
+            (
+                "let val (a, b) = Substring.splitl Char.isDigit (Substring.full \"12ab\") \
+                 in (Substring.string a, Substring.size b) end",
+                "(\"12\", 2) : string * int",
+            ),
+            (
+                "Substring.string (Substring.slice (Substring.extract (\"abcdef\", 1, NONE), 1, SOME 2))",
+                "\"cd\" : string",
+            ),
+            ("Substring.first (Substring.triml 9 (Substring.full \"ab\"))", "NONE : char option"),
+        ]);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_evals(&[
+            (
+                "(Int.toString ~12, Int.fromString \" +42!\")",
+                "(\"~12\", SOME 42) : string * int option",
+            ),
+            ("Int.fromString \"x\"", "NONE : int option"),
+            ("Int.fmt StringCvt.HEX 255", "\"FF\" : string"),
+            (
+                "(Int.quot (~7, 2), Int.rem (~7, 2), ~7 div 2, ~7 mod 2)",
+                "(~3, ~1, ~4, 1) : int * int * int * int",
+            ),
+            ("(valOf Int.maxInt + 1; 0) handle Overflow => 1", "1 : int"),
+            (
+                "(Int.compare (1, 2), Int.min (3, 4), Int.sign ~5)",
+                "(LESS, 3, ~1) : order * int * int",
+            ),
+            (
+                "Word.toString (Word.andb (0wxff0, 0wx0ff))",
+                "\"F0\" : string",
+            ),
+            (
+                "(Word.>> (0wx10, 0w4), Word.~>> (Word.notb 0w0, 0w70))",
+                "(0wx1, 0wxffffffffffffffff) : word * word",
+            ),
+            (
+                "(Word.toInt (Word.fromInt ~1); 0) handle Overflow => 1",
+                "1 : int",
+            ),
+            ("Word.fromString \"1F\"", "SOME 0wx1f : word option"),
+            (
+                "(real 3 / 2.0, floor ~1.5, ceil 1.2, round 2.5, trunc ~1.7)",
+                "(1.5, ~2, 2, 2, ~1) : real * int * int * int * int",
+            ),
+            (
+                "(Real.fromString \"~1.5e3\", Real.fromString \"e\")",
+                "(SOME ~1500.0, NONE) : real option * real option",
+            ),
+            ("Real.toString 0.1", "\"0.1\" : string"),
+            (
+                "(Real.isNan (0.0 / 0.0), Real.isFinite Real.posInf)",
+                "(true, false) : bool * bool",
+            ),
+            ("(floor (0.0 / 0.0); 0) handle Domain => 1", "1 : int"),
+            ("Real.compare (2.0, 1.0)", "GREATER : order"),
+        ]);
+    }
+
+    #[test]
+    fn vectors_and_arrays() {
+        assert_evals(&[
+            (
+                "Vector.map (fn x => x + 1) (vector [1, 2])",
+                "#[2, 3] : int vector",
+            ),
+            (
+                "Vector.foldri (fn (i, x, acc) => (i, x) :: acc) [] (vector [#\"a\", #\"b\"])",
+                "[(0, #\"a\"), (1, #\"b\")] : (int * char) list",
+            ),
+            (
+                "Vector.update (vector [1, 2], 0, 3)",
+                "#[3, 2] : int vector",
+            ),
+            ("vector [1] = vector [1]", "true : bool"),
+            (
+                "Vector.findi (fn (_, x) => x > 1) (vector [1, 2, 3])",
+                "SOME (1, 2) : (int * int) option",
+            ),
+            (
+                "let val a = Array.array (3, 0) in Array.modifyi (fn (i, x) => x + i) a; a end",
+                "[|0, 1, 2|] : int array",
+            ),
+            ("Array.fromList [1] = Array.fromList [1]", "false : bool"),
+            (
+                "(Array.sub (Array.array (1, 0), 1); 0) handle Subscript => 1",
+                "1 : int",
+            ),
+            ("(Array.array (~1, 0); 0) handle Size => 1", "1 : int"),
+            (
+                "let val a = Array.tabulate (4, fn i => i) \
+                 in Array.copy {src = a, dst = a, di = 1} handle Subscript => (); \
+                    Array.copyVec {src = vector [9, 8], dst = a, di = 2}; Array.vector a end",
+                "#[0, 1, 9, 8] : int vector",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn text_io() {
+        let path = std::env::temp_dir().join(format!("smol-text-io-{}.txt", std::process::id()));
+        let path = path.display().to_string();
+        let mut s = session();
+        s.input(&format!("val path = {:?};", path));
+        s.input(
+            "val out = TextIO.openOut path;
+             val () = (TextIO.output (out, \"one\\ntwo\"); TextIO.output1 (out, #\"!\"));
+             val () = TextIO.closeOut out;
+             val out = TextIO.openAppend path;
+             val () = TextIO.output (out, \"\\nthree 42\\n\");
+             val () = TextIO.closeOut out;",
+        );
+        let cases = [
+            ("val ins = TextIO.openIn path", None),
+            (
+                "TextIO.inputLine ins",
+                Some("SOME \"one\\n\" : string option"),
+            ),
+            ("TextIO.input1 ins", Some("SOME #\"t\" : char option")),
+            ("TextIO.inputN (ins, 3)", Some("\"wo!\" : string")),
+            ("TextIO.inputLine ins", Some("SOME \"\\n\" : string option")),
+            (
+                "TextIO.scanStream (Int.scan StringCvt.DEC) ins",
+                Some("NONE : int option"),
+            ),
+            ("TextIO.inputN (ins, 5)", Some("\"three\" : string")),
+            (
+                "TextIO.scanStream (Int.scan StringCvt.DEC) ins",
+                Some("SOME 42 : int option"),
+            ),
+            ("TextIO.inputAll ins", Some("\"\\n\" : string")),
+            ("TextIO.endOfStream ins", Some("true : bool")),
+            ("TextIO.inputLine ins", Some("NONE : string option")),
+            (
+                "TextIO.output (out, \"closed\") handle IO.Io {function, ...} => print function",
+                Some("() : unit"),
+            ),
+        ];
+        for (input, expected) in &cases {
+            let out = s.input(&format!("{};", input));
+            if let Some(expected) = expected {
+                assert_eq!(out, format!("val it = {}\n", expected), "{}", input);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(s
+            .input("TextIO.openIn path handle e => (print (exnMessage e); TextIO.stdIn);")
+            .starts_with("val it = "));
+        assert!(s
+            .input("TextIO.openIn path;")
+            .starts_with("uncaught exception Io"));
+    }
+}
@@ -0,0 +1,423 @@
+//! The `smol` compiler.
+//!
+//! Usage: `smolc [options] file.sml ...`. The files are compiled in order,
+//! after the Basis, to an executable linked against the runtime. By default
+//! that goes through the C backend and `cc` (or `$CC`), so it needs no LLVM.
+//! The options that stop after a stage print what it made instead.
+//! `--emit-bytecode` writes bytecode for the virtual machine, which `smol
+//! --run` runs, and `--run` runs the program on it straight away, as it does
+//! a `.smbc` file of bytecode.
+//!
+//! A file can also be an ML Basis (`.mlb`) or SML/NJ (`.cm`) file, which
+//! compiles the files it lists, scoped as it says.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parsegen::FileId;
+use smol::codegen;
+use smol::driver::{Compiler, Options};
+use smol::ir::closure::Closures;
+use smol::ir::Representation;
+use smol::mlb::{self, Step};
+use smol::vm;
+
+const USAGE: &str = "\
+usage: smolc [options] file.sml ...
+
+Compiles the files, in order, to an executable. A file can also be an ML
+Basis (.mlb) or SML/NJ (.cm) file listing others.
+
+  -o <file>            write the executable, or what a stage made, to <file>
+  --check              only type check
+  --dump-parse-tree    stop after parsing, and print the parse trees
+  --dump-ast           stop after resolving infixes, and print the ASTs as SML
+  --dump-types         stop after type checking, and print each file's bindings
+  --dump-ir            stop after closure conversion, and print the IR
+  --emit-llvm          stop after generating LLVM IR, and print it
+  --emit-c             stop after generating C, and print it
+  --emit-bytecode      stop after compiling to bytecode, and write it to
+                       <file>.smbc
+  --run                run the program on the virtual machine, or run a
+                       .smbc file
+  --specialise         specialise polymorphic functions and functors
+  -O0                  don't optimise
+  --linked-closures    link closures to the closures they're nested in
+  --no-lift            don't lambda lift functions that don't escape
+  --runtime <lib>      link against <lib>, not the runtime beside smolc
+  --path-var <name>=<value>
+                       set the path variable $(<name>) in .mlb files
+  -h, --help           show this message
+";
+
+/// Lowering and type checking recurse over the Basis's syntax, so give them
+/// plenty of stack.
+const STACK_SIZE: usize = 256 << 20;
+
+/// Where to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    ParseTree,
+    Ast,
+    Types,
+    Check,
+    Ir,
+    Llvm,
+    C,
+    Bytecode,
+    Run,
+    Executable,
+}
+
+#[derive(Debug)]
+struct Args {
+    files: Vec<String>,
+    output: Option<PathBuf>,
+    stage: Stage,
+    options: Options,
+    runtime: Option<PathBuf>,
+    /// Path variables for `.mlb` files.
+    vars: HashMap<String, String>,
+}
+
+/// Why `smolc` stopped.
+#[derive(Debug)]
+enum Error {
+    /// Bad arguments, and what was wrong with them.
+    Usage(String),
+    /// The program doesn't compile, with the rendered diagnostics.
+    Compile(String),
+    /// The program raised an exception it didn't handle.
+    Uncaught(vm::Uncaught),
+    /// Something else went wrong.
+    Other(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "error: {}\n\n{}", message, USAGE),
+            Error::Compile(diags) => write!(f, "{}", diags),
+            Error::Uncaught(uncaught) => writeln!(f, "{}", uncaught),
+            Error::Other(message) => writeln!(f, "error: {}", message),
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return;
+    }
+    let status = match parse_args(&args) {
+        Ok(args) => thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || compile(&args))
+            .expect("failed to start the compiler")
+            .join()
+            .unwrap_or_else(|_| Err(Error::Other("the compiler panicked".to_owned()))),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = status {
+        eprint!("{}", err);
+        process::exit(match err {
+            Error::Usage(_) => 2,
+            _ => 1,
+        });
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Args, Error> {
+    let mut parsed = Args {
+        files: Vec::new(),
+        output: None,
+        stage: Stage::Executable,
+        options: Options::default(),
+        runtime: None,
+        vars: HashMap::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .map(PathBuf::from)
+                .ok_or_else(|| Error::Usage(format!("`{}` needs a file", flag)))
+        };
+        let stage = match arg.as_str() {
+            "-o" => {
+                parsed.output = Some(value(arg)?);
+                continue;
+            }
+            "--runtime" => {
+                parsed.runtime = Some(value(arg)?);
+                continue;
+            }
+            "--path-var" => {
+                let var = args.next().and_then(|var| var.split_once('='));
+                let (name, value) = var.ok_or_else(|| {
+                    Error::Usage("`--path-var` needs a `<name>=<value>`".to_owned())
+                })?;
+                parsed.vars.insert(name.to_owned(), value.to_owned());
+                continue;
+            }
+            "--specialise" => {
+                parsed.options.representation = Representation::Specialised;
+                continue;
+            }
+            "-O0" => {
+                parsed.options.optimise = false;
+                continue;
+            }
+            "--linked-closures" => {
+                parsed.options.closures.closures = Closures::Linked;
+                continue;
+            }
+            "--no-lift" => {
+                parsed.options.closures.lift = false;
+                continue;
+            }
+            "--check" => Stage::Check,
+            "--dump-parse-tree" => Stage::ParseTree,
+            "--dump-ast" => Stage::Ast,
+            "--dump-types" => Stage::Types,
+            "--dump-ir" => Stage::Ir,
+            "--emit-llvm" => Stage::Llvm,
+            "--emit-c" => Stage::C,
+            "--emit-bytecode" => Stage::Bytecode,
+            "--run" => Stage::Run,
+            flag if flag.starts_with('-') => {
+                return Err(Error::Usage(format!("unknown option `{}`", flag)))
+            }
+            file => {
+                parsed.files.push(file.to_owned());
+                continue;
+            }
+        };
+        if parsed.stage != Stage::Executable && parsed.stage != stage {
+            return Err(Error::Usage(
+                "only one stage can be stopped after".to_owned(),
+            ));
+        }
+        parsed.stage = stage;
+    }
+    if parsed.files.is_empty() {
+        return Err(Error::Usage("no input files".to_owned()));
+    }
+    Ok(parsed)
+}
+
+fn compile(args: &Args) -> Result<(), Error> {
+    if args.stage == Stage::Run && matches!(&args.files[..], [file] if vm::is_bytecode(file)) {
+        let module = vm::load(&args.files, None).map_err(Error::Compile)?;
+        return run(&module);
+    }
+    let mut compiler = Compiler::new();
+    let mut out = String::new();
+    for path in &args.files {
+        let src = fs::read_to_string(path)
+            .map_err(|err| Error::Other(format!("can't read {}: {}", path, err)))?;
+        let file = compiler.add(path, &src);
+        if !mlb::is_basis_file(path) {
+            compile_file(args, &mut compiler, file, &mut out)?;
+            continue;
+        }
+        let project = compiler
+            .load_project(file, &args.vars)
+            .map_err(|diags| Error::Compile(compiler.render(&diags)))?;
+        for step in &project.steps {
+            match step {
+                Step::File(i) => compile_file(args, &mut compiler, project.files[*i], &mut out)?,
+                // Files aren't checked at these stages, so there are no
+                // modules to alias.
+                Step::Alias(..) if matches!(args.stage, Stage::ParseTree | Stage::Ast) => (),
+                step => compiler
+                    .step(step)
+                    .map_err(|diags| Error::Compile(compiler.render(&diags)))?,
+            }
+        }
+    }
+    eprint!("{}", compiler.render(&compiler.warnings));
+
+    let program = match args.stage {
+        Stage::ParseTree | Stage::Ast | Stage::Types => return write(args, &out),
+        Stage::Check => return Ok(()),
+        Stage::Ir | Stage::Llvm | Stage::C | Stage::Bytecode | Stage::Run | Stage::Executable => {
+            compiler
+                .lower(args.options)
+                .map_err(|diags| Error::Compile(compiler.render(&diags)))?
+        }
+    };
+    match args.stage {
+        Stage::Ir => write(args, &program.to_string()),
+        Stage::Llvm => write(args, &codegen::llvm::emit(&program)),
+        Stage::C => write(args, &codegen::c::emit(&program)),
+        Stage::Bytecode => write_bytecode(args, &vm::compile(&program)),
+        Stage::Run => run(&vm::compile(&program)),
+        _ => link(args, &codegen::c::emit(&program)),
+    }
+}
+
+/// Take one file as far as the stage asks, before lowering, adding what
+/// it prints to `out`.
+fn compile_file(
+    args: &Args,
+    compiler: &mut Compiler,
+    file: FileId,
+    out: &mut String,
+) -> Result<(), Error> {
+    if args.stage == Stage::ParseTree {
+        let tree = smol::lower::parse_tree(&compiler.sources, file)
+            .map_err(|diags| Error::Compile(compiler.render(&diags)))?;
+        *out += &smol::lower::show_tree(&tree, compiler.sources.source(file));
+        return Ok(());
+    }
+    let program = compiler
+        .parse(file)
+        .map_err(|diags| Error::Compile(compiler.render(&diags)))?;
+    if args.stage == Stage::Ast {
+        *out += &program.to_string();
+        return Ok(());
+    }
+    let bound = compiler
+        .check(program)
+        .map_err(|diags| Error::Compile(compiler.render(&diags)))?;
+    if args.stage == Stage::Types {
+        *out += &compiler.show_bindings(&bound);
+    }
+    Ok(())
+}
+
+/// Write what a stage made to the output, or print it.
+fn write(args: &Args, text: &str) -> Result<(), Error> {
+    match &args.output {
+        Some(path) => fs::write(path, text)
+            .map_err(|err| Error::Other(format!("can't write {}: {}", path.display(), err))),
+        // A closed pipe, as from `smolc --emit-c file.sml | head`, isn't
+        // an error.
+        None => match io::stdout().lock().write_all(text.as_bytes()) {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+                Err(Error::Other(format!("can't print: {}", err)))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Where to write an executable: where `-o` says, or else a file named
+/// after the last input.
+fn output(args: &Args) -> PathBuf {
+    match &args.output {
+        Some(path) => path.clone(),
+        None => {
+            let last = Path::new(args.files.last().expect("there are input files"));
+            PathBuf::from(last.file_stem().unwrap_or(last.as_os_str()))
+        }
+    }
+}
+
+/// Write bytecode where `-o` says, or else to a `.smbc` file named after
+/// the last input.
+fn write_bytecode(args: &Args, module: &vm::Module) -> Result<(), Error> {
+    let path = match &args.output {
+        Some(path) => path.clone(),
+        None => output(args).with_extension(vm::EXTENSION),
+    };
+    let mut bytes = Vec::new();
+    module.write(&mut bytes).expect("writing to memory");
+    fs::write(&path, bytes)
+        .map_err(|err| Error::Other(format!("can't write {}: {}", path.display(), err)))
+}
+
+/// Run a program on the virtual machine.
+fn run(module: &vm::Module) -> Result<(), Error> {
+    vm::Machine::new(module).run().map_err(Error::Uncaught)
+}
+
+/// Compile C to an executable with the runtime.
+fn link(args: &Args, c: &str) -> Result<(), Error> {
+    let exe = output(args);
+    let runtime = runtime(args)?;
+    let dir = TempDir::new()
+        .map_err(|err| Error::Other(format!("can't make a temporary directory: {}", err)))?;
+    let source = dir.0.join("main.c");
+    fs::write(&source, c)
+        .map_err(|err| Error::Other(format!("can't write {}: {}", source.display(), err)))?;
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let result = Command::new(&cc)
+        .args(["-std=c99", "-O2"])
+        .arg(&source)
+        .arg(&runtime)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&exe)
+        .status();
+    match result {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Other(format!("{} failed ({})", cc, status))),
+        Err(err) => Err(Error::Other(format!("can't run {}: {}", cc, err))),
+    }
+}
+
+/// A directory only the user can read, made for one compilation's files,
+/// and removed with them when it's dropped, however that compilation ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Make a directory with a name nothing has. Making it fails if the name
+    /// is taken, even by a link, so what's written in it can't end up
+    /// anywhere else.
+    fn new() -> io::Result<TempDir> {
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        for attempt in 0..100u32 {
+            let name = format!("smolc-{}-{:x}", process::id(), nanos.wrapping_add(attempt));
+            let dir = env::temp_dir().join(name);
+            match builder.create(&dir) {
+                Ok(()) => return Ok(TempDir(dir)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "every name tried is taken",
+        ))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The runtime library: the one asked for, or `$SMOL_RUNTIME`, or the one
+/// beside `smolc`, where Cargo builds it.
+fn runtime(args: &Args) -> Result<PathBuf, Error> {
+    if let Some(path) = &args.runtime {
+        return Ok(path.clone());
+    }
+    if let Some(path) = env::var_os("SMOL_RUNTIME") {
+        return Ok(PathBuf::from(path));
+    }
+    let beside = env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("libsmol_runtime.a")))
+        .filter(|path| path.exists());
+    beside.ok_or_else(|| {
+        Error::Other(
+            "can't find the runtime library; build `smol-runtime`, or pass `--runtime`".to_owned(),
+        )
+    })
+}
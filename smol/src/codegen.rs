@@ -0,0 +1,166 @@
+//! Compiling closure converted IR to native code.
+//!
+//! Every value is one 64-bit word. Ints, chars, bools, unit and constructors
+//! without arguments are immediate, tagged with a low bit of 1: the int `n`
+//! is `n << 1 | 1`, so ints are 63 bits. Everything else is a pointer to an
+//! 8-byte aligned object, a header word followed by the object's fields:
+//!
+//! ```text
+//! header = len << 32 | tag << 8 | kind
+//! ```
+//!
+//! `len` is the number of fields, or of bytes for strings, and `tag` is a
+//! constructor's tag. Words and reals are boxed, so that words have all 64
+//! bits. Constants are objects in static memory, which the collector leaves
+//! where they are.
+//!
+//! The runtime (the `smol-runtime` crate) provides allocation, the garbage
+//! collector, and the primitives that are too big to inline. Its collector
+//! moves objects, so compiled code keeps every value in a frame on a shadow
+//! stack, where it can find and update them, rather than in registers across
+//! calls that can allocate. A raised exception is left in `smol_exn`, and a
+//! function that raises returns with it set. A function whose frame doesn't
+//! fit below `smol_sp_limit` raises `Overflow` before it pushes it.
+//!
+//! There are two backends: `llvm` emits LLVM IR, and `c` emits C99 for
+//! machines with a C compiler but no LLVM.
+
+use std::collections::HashMap;
+
+use crate::ast::{MAX_INT, MIN_INT};
+use crate::ir::{Cont, Term, Var};
+use crate::types::BUILTIN_EXNS;
+
+pub mod c;
+pub mod llvm;
+
+/// Kinds of object, the low byte of their header.
+pub mod kind {
+    /// Records and tuples.
+    pub const RECORD: u8 = 0;
+    /// A constructor with an argument, its one field.
+    pub const CON: u8 = 1;
+    /// An exception: its id (tagged) and its argument, or unit.
+    pub const EXN: u8 = 2;
+    pub const REF: u8 = 3;
+    pub const ARRAY: u8 = 4;
+    pub const VECTOR: u8 = 5;
+    /// A function's code pointer, which isn't a value, then the values of its
+    /// free variables.
+    pub const CLOSURE: u8 = 6;
+    /// Bytes, not values.
+    pub const STRING: u8 = 7;
+    /// An `f64`.
+    pub const REAL: u8 = 8;
+    /// A `u64`.
+    pub const WORD: u8 = 9;
+}
+
+/// An object's header.
+pub fn header(kind: u8, tag: u32, len: u32) -> u64 {
+    (len as u64) << 32 | (tag as u64 & 0xff_ffff) << 8 | kind as u64
+}
+
+/// The representation of an immediate int, which lowering has checked
+/// fits in 63 bits, reporting constants that don't.
+pub fn tagged(n: i64) -> i64 {
+    debug_assert!(
+        (MIN_INT..=MAX_INT).contains(&n),
+        "{} doesn't fit in an immediate",
+        n
+    );
+    n << 1 | 1
+}
+
+/// Unit, which is the empty record.
+pub const UNIT: i64 = 1;
+pub const FALSE: i64 = 1;
+pub const TRUE: i64 = 3;
+
+/// The id of a built in exception.
+fn exn_id(name: &str) -> u32 {
+    BUILTIN_EXNS
+        .iter()
+        .position(|exn| *exn == name)
+        .expect("a built in exception") as u32
+}
+
+/// A name made of the characters both LLVM and C allow in identifiers.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.is_empty() {
+        "v".to_owned()
+    } else {
+        name
+    }
+}
+
+/// Gives each variable of a function a slot in its frame.
+#[derive(Default)]
+struct Slots {
+    slots: HashMap<Var, usize>,
+    params: HashMap<Cont, Vec<Var>>,
+}
+
+impl Slots {
+    fn bind(&mut self, var: Var) {
+        let next = self.slots.len();
+        self.slots.entry(var).or_insert(next);
+    }
+
+    fn term(&mut self, mut term: &Term) {
+        loop {
+            match term {
+                Term::Let(var, _, body) | Term::Checked { var, body, .. } => {
+                    self.bind(*var);
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    for param in &def.params {
+                        self.bind(*param);
+                    }
+                    self.params.insert(def.cont, def.params.clone());
+                    self.term(&def.body);
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    for closure in closures {
+                        self.bind(closure.var);
+                    }
+                    term = body;
+                }
+                Term::LetFun(_, _) => {
+                    panic!("functions should be closure converted before code generation")
+                }
+                Term::Call { .. } | Term::Jump(_, _) | Term::If(_, _, _) | Term::Switch { .. } => {
+                    return
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn representation() {
+        assert_eq!(tagged(0), UNIT);
+        assert_eq!(tagged(0), FALSE);
+        assert_eq!(tagged(1), TRUE);
+        assert_eq!(tagged(-1), -1);
+        assert_eq!(header(kind::CON, 3, 1), 0x1_0000_0301);
+        assert_eq!(header(kind::STRING, 0, 5) >> 32, 5);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(sanitize("map"), "map");
+        assert_eq!(sanitize("#1"), "_1");
+        assert_eq!(sanitize(""), "v");
+    }
+}
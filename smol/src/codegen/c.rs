@@ -0,0 +1,933 @@
+//! Emitting C, as text.
+//!
+//! The output is C99 that needs only the runtime and a C compiler, for
+//! machines without LLVM. Each IR function becomes a C function and each
+//! continuation a labelled block, and variables live in slots of the
+//! function's frame on the shadow stack, as they do for `llvm`.
+//!
+//! C doesn't guarantee tail calls, so they're trampolined: a function making
+//! one leaves the function and its arguments in `next_code` and `next_args`
+//! and returns, and `call`, which makes every other call, loops making them.
+//! So that any function can be called that way, every function takes its
+//! arguments as an array, which it copies into its frame on entry.
+//!
+//! A call returns its result, or 0 with the exception left in `smol_exn`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use super::{exn_id, header, kind, sanitize, tagged, Slots, FALSE, TRUE, UNIT};
+use crate::ast::Const;
+use crate::ir::*;
+
+const PRELUDE: &str = r#"#include <math.h>
+#include <stdint.h>
+#include <string.h>
+
+typedef int64_t value;
+typedef value (*code)(const value *args);
+
+extern value *smol_sp;
+extern value *smol_sp_limit;
+extern value smol_exn;
+extern uint64_t *smol_alloc(int64_t len, int64_t header);
+extern value smol_equal(value a, value b);
+extern int64_t smol_string_compare(value a, value b);
+extern value smol_string_concat(value a, value b);
+extern void smol_print(value s);
+extern void smol_init(int32_t argc, char **argv, value *globals, int64_t globals_len,
+                      const char *const *names, int64_t names_len);
+extern void smol_run(void (*start)(void));
+extern int32_t smol_finish(void);
+
+/* Right shifts of negative ints are implementation defined, but arithmetic
+   everywhere that matters. */
+#define TAG(n) ((value)((uint64_t)(n) << 1) | 1)
+#define UNTAG(v) ((v) >> 1)
+#define FIELD(v, i) (((value *)(uintptr_t)(v))[(i) + 1])
+#define OBJECT(o) ((value)(uintptr_t)(o))
+#define MAX63 (INT64_MAX >> 1)
+#define MIN63 (-MAX63 - 1)
+#define FITS63(n) ((n) >= MIN63 && (n) <= MAX63)
+
+static inline uint64_t word_of(value v) {
+    return (uint64_t)FIELD(v, 0);
+}
+
+static inline double real_of(value v) {
+    double x;
+    memcpy(&x, &FIELD(v, 0), sizeof x);
+    return x;
+}
+
+static inline uint64_t bits_of(double x) {
+    uint64_t bits;
+    memcpy(&bits, &x, sizeof bits);
+    return bits;
+}
+
+/* A word or a real. */
+static inline value box(int64_t header, uint64_t bits) {
+    uint64_t *object = smol_alloc(1, header);
+    object[1] = bits;
+    return OBJECT(object);
+}
+
+/* Whether a * b doesn't fit in 63 bits, leaving it in *product if it does. */
+static inline int mul63(int64_t a, int64_t b, int64_t *product) {
+    if (a > 0 ? (b > 0 ? a > MAX63 / b : b < MIN63 / a)
+              : (b > 0 ? a < MIN63 / b : a != 0 && a < MAX63 / b))
+        return 1;
+    *product = a * b;
+    return 0;
+}
+"#;
+
+/// The C translation unit for a closure converted program, with a `main`
+/// that initializes the runtime, runs the program, and exits with the
+/// runtime's status.
+pub fn emit(program: &Program) -> String {
+    let mut module = Module {
+        program,
+        constants: String::new(),
+        strings: HashMap::new(),
+        exns: BTreeSet::new(),
+        natives: BTreeSet::new(),
+        next_constant: 0,
+    };
+    let mut functions = module.function(&program.main, "smol_main".to_owned());
+    for (id, fun) in program.funs.iter().enumerate() {
+        let name = fun_name(program, id);
+        functions += &module.function(fun, name);
+    }
+
+    let mut out = String::new();
+    out += "/* Generated by smol. */\n\n";
+    out += PRELUDE;
+    out += "\n";
+    for native in &module.natives {
+        writeln!(out, "extern value smol_prim_{}(value);", native).unwrap();
+    }
+
+    // The function and arguments of the tail call to make next, if any.
+    let max_params = program.funs.iter().map(|fun| fun.params.len()).max();
+    out += "\nstatic code next_code;\n";
+    writeln!(
+        out,
+        "static value next_args[{}];",
+        max_params.unwrap_or(0).max(1)
+    )
+    .unwrap();
+    out += r#"
+static value call(code f, const value *args) {
+    value result = f(args);
+    while (next_code) {
+        code next = next_code;
+        next_code = 0;
+        result = next(next_args);
+    }
+    return result;
+}
+"#;
+
+    writeln!(
+        out,
+        "\nstatic value smol_globals[{}];",
+        program.globals.max(1)
+    )
+    .unwrap();
+
+    // The names of exceptions, for exnName and uncaught exceptions.
+    let count = program.exns.keys().last().map_or(0, |id| id + 1);
+    let mut names = vec!["0".to_owned(); count as usize];
+    for (id, name) in &program.exns {
+        names[*id as usize] = format!("\"{}\"", escape(name.as_bytes()));
+    }
+    if names.is_empty() {
+        names.push("0".to_owned());
+    }
+    writeln!(
+        out,
+        "static const char *const smol_exn_names[] = {{ {} }};",
+        names.join(", ")
+    )
+    .unwrap();
+    for id in &module.exns {
+        writeln!(
+            out,
+            "static const uint64_t exn_{}[3] = {{ {}, {}, {} }};",
+            id,
+            word(header(kind::EXN, 0, 2)),
+            int(tagged(*id as i64)),
+            UNIT
+        )
+        .unwrap();
+    }
+    out += &module.constants;
+
+    out += "\n";
+    out += "static value smol_main(const value *args);\n";
+    for (id, _) in program.funs.iter().enumerate() {
+        writeln!(
+            out,
+            "static value {}(const value *args);",
+            fun_name(program, id)
+        )
+        .unwrap();
+    }
+
+    out += &functions;
+
+    out += "\nstatic void smol_start(void) {\n";
+    out += "    call(smol_main, 0);\n";
+    out += "}\n";
+
+    out += "\nint main(int argc, char **argv) {\n";
+    writeln!(
+        out,
+        "    smol_init(argc, argv, smol_globals, {}, smol_exn_names, {});",
+        program.globals, count
+    )
+    .unwrap();
+    out += "    smol_run(smol_start);\n";
+    out += "    return smol_finish();\n";
+    out += "}\n";
+    out
+}
+
+fn fun_name(program: &Program, id: FunId) -> String {
+    format!("f{}_{}", id, sanitize(program.name(program.funs[id].name)))
+}
+
+/// An int literal.
+fn int(n: i64) -> String {
+    if n == i64::MIN {
+        "INT64_MIN".to_owned()
+    } else if i32::try_from(n).is_ok() {
+        n.to_string()
+    } else {
+        format!("INT64_C({})", n)
+    }
+}
+
+/// An unsigned literal.
+fn word(n: u64) -> String {
+    format!("UINT64_C({})", n)
+}
+
+/// Bytes escaped for a string literal. Question marks are escaped too, so
+/// they can't make trigraphs.
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in bytes {
+        if b.is_ascii_graphic() && !matches!(b, b'"' | b'\\' | b'?') || b == b' ' {
+            out.push(b as char);
+        } else {
+            write!(out, "\\{:03o}", b).unwrap();
+        }
+    }
+    out
+}
+
+struct Module<'p> {
+    program: &'p Program,
+    /// Definitions of the constants functions use.
+    constants: String,
+    strings: HashMap<String, String>,
+    /// Exceptions without arguments that are used, which are constants.
+    exns: BTreeSet<u32>,
+    natives: BTreeSet<&'static str>,
+    next_constant: usize,
+}
+
+impl<'p> Module<'p> {
+    fn next(&mut self) -> String {
+        self.next_constant += 1;
+        format!("c{}", self.next_constant - 1)
+    }
+
+    /// A constant's value.
+    fn constant(&mut self, c: &Const) -> String {
+        match c {
+            Const::Int(n) => int(tagged(*n)),
+            Const::Char(c) => int(tagged(*c as i64)),
+            Const::String(s) => {
+                if let Some(name) = self.strings.get(s) {
+                    return format!("OBJECT(&{})", name);
+                }
+                let name = self.next();
+                writeln!(
+                    self.constants,
+                    "static const struct {{ uint64_t header; char bytes[{}]; }} {} = {{ {}, \"{}\" }};",
+                    s.len().max(1),
+                    name,
+                    word(header(kind::STRING, 0, s.len() as u32)),
+                    escape(s.as_bytes())
+                )
+                .unwrap();
+                self.strings.insert(s.clone(), name.clone());
+                format!("OBJECT(&{})", name)
+            }
+            Const::Word(w) => {
+                let name = self.next();
+                writeln!(
+                    self.constants,
+                    "static const uint64_t {}[2] = {{ {}, {} }};",
+                    name,
+                    word(header(kind::WORD, 0, 1)),
+                    word(*w)
+                )
+                .unwrap();
+                format!("OBJECT({})", name)
+            }
+            Const::Real(x) => {
+                let name = self.next();
+                writeln!(
+                    self.constants,
+                    "static const uint64_t {}[2] = {{ {}, {} }};",
+                    name,
+                    word(header(kind::REAL, 0, 1)),
+                    word(x.to_bits())
+                )
+                .unwrap();
+                format!("OBJECT({})", name)
+            }
+        }
+    }
+
+    /// An exception without an argument.
+    fn exn(&mut self, id: u32) -> String {
+        self.exns.insert(id);
+        format!("OBJECT(exn_{})", id)
+    }
+
+    fn function(&mut self, fun: &'p Fun, name: String) -> String {
+        let mut slots = Slots::default();
+        for param in &fun.params {
+            slots.bind(*param);
+        }
+        slots.term(&fun.body);
+
+        let mut f = Function {
+            module: self,
+            out: String::new(),
+            slots: slots.slots,
+            params: slots.params,
+            ret: fun.ret,
+            handler: fun.handler,
+            indent: 1,
+            next_temp: 0,
+            pending: Vec::new(),
+        };
+        let size = f.slots.len();
+        writeln!(f.out, "\nstatic value {}(const value *args) {{", name).unwrap();
+        f.line("value *fp = smol_sp;".to_owned());
+        f.raise_if(
+            &format!("fp + {} > smol_sp_limit", size),
+            "Overflow",
+            fun.handler,
+        );
+        f.line(format!("smol_sp = fp + {};", size));
+        if size > 0 {
+            f.line(format!("memset(fp, 0, {} * sizeof *fp);", size));
+        }
+        if fun.params.is_empty() {
+            f.line("(void)args;".to_owned());
+        }
+        for (i, param) in fun.params.iter().enumerate() {
+            f.store(*param, &format!("args[{}]", i));
+        }
+
+        f.block(&fun.body);
+        while let Some(def) = f.pending.pop() {
+            writeln!(f.out, "k{}:", def.cont.0).unwrap();
+            f.block(&def.body);
+        }
+        f.out += "}\n";
+        f.out
+    }
+}
+
+struct Function<'m, 'p> {
+    module: &'m mut Module<'p>,
+    out: String,
+    slots: HashMap<Var, usize>,
+    /// The parameters of the function's continuations.
+    params: HashMap<Cont, Vec<Var>>,
+    ret: Cont,
+    handler: Cont,
+    indent: usize,
+    next_temp: usize,
+    /// Continuations whose blocks haven't been emitted yet.
+    pending: Vec<&'p ContDef>,
+}
+
+impl<'m, 'p> Function<'m, 'p> {
+    fn line(&mut self, line: String) {
+        for _ in 0..self.indent {
+            self.out += "    ";
+        }
+        self.out += &line;
+        self.out += "\n";
+    }
+
+    /// Start a nested block, after `line`.
+    fn open(&mut self, line: String) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    fn close(&mut self) {
+        self.indent -= 1;
+        self.line("}".to_owned());
+    }
+
+    fn temp(&mut self) -> String {
+        self.next_temp += 1;
+        format!("t{}", self.next_temp - 1)
+    }
+
+    /// `type temp = expression;`, giving the temporary.
+    fn typed(&mut self, ty: &str, expression: String) -> String {
+        let temp = self.temp();
+        let space = if ty.ends_with('*') { "" } else { " " };
+        self.line(format!("{}{}{} = {};", ty, space, temp, expression));
+        temp
+    }
+
+    fn value(&mut self, expression: String) -> String {
+        self.typed("value", expression)
+    }
+
+    /// A term, in a block of its own, since C99 doesn't allow declarations
+    /// straight after labels.
+    fn block(&mut self, term: &'p Term) {
+        self.open("{".to_owned());
+        self.term(term);
+        self.close();
+    }
+
+    /// A variable's slot, which is only good until the next allocation.
+    fn load(&self, var: Var) -> String {
+        format!("fp[{}]", self.slots[&var])
+    }
+
+    fn store(&mut self, var: Var, value: &str) {
+        let slot = self.load(var);
+        self.line(format!("{} = {};", slot, value));
+    }
+
+    /// Allocate an object. The collector may run, so values must be loaded
+    /// after this.
+    fn alloc(&mut self, kind: u8, tag: u32, len: usize) -> String {
+        self.typed(
+            "uint64_t *",
+            format!(
+                "smol_alloc({}, {})",
+                len,
+                int(header(kind, tag, len as u32) as i64)
+            ),
+        )
+    }
+
+    /// Store the fields of a new object, and give its value.
+    fn init(&mut self, object: &str, fields: &[Var]) -> String {
+        for (i, var) in fields.iter().enumerate() {
+            let value = self.load(*var);
+            self.line(format!("{}[{}] = {};", object, i + 1, value));
+        }
+        format!("OBJECT({})", object)
+    }
+
+    fn pop(&mut self) {
+        self.line("smol_sp = fp;".to_owned());
+    }
+
+    /// Jump to a continuation with values.
+    fn goto(&mut self, cont: Cont, values: &[String]) {
+        if cont == self.ret {
+            self.pop();
+            self.line(format!("return {};", values[0]));
+        } else if cont == self.handler {
+            self.line(format!("smol_exn = {};", values[0]));
+            self.pop();
+            self.line("return 0;".to_owned());
+        } else {
+            let params = self.params[&cont].clone();
+            // Read every value before storing any, as they may be the
+            // parameters.
+            let values: Vec<_> = if params.len() > 1 {
+                values.iter().map(|v| self.value(v.clone())).collect()
+            } else {
+                values.to_vec()
+            };
+            for (param, value) in params.iter().zip(&values) {
+                self.store(*param, value);
+            }
+            self.line(format!("goto k{};", cont.0));
+        }
+    }
+
+    fn bool(&self, cond: &str) -> String {
+        format!("({}) ? {} : {}", cond, TRUE, FALSE)
+    }
+
+    /// Raise `exn` to `handler` if `cond`, continuing otherwise.
+    fn raise_if(&mut self, cond: &str, exn: &str, handler: Cont) {
+        self.open(format!("if ({}) {{", cond));
+        let exn = self.module.exn(exn_id(exn));
+        self.goto(handler, &[exn]);
+        self.close();
+    }
+
+    fn term(&mut self, mut term: &'p Term) {
+        loop {
+            match term {
+                Term::Let(var, exp, body) => {
+                    self.exp(*var, exp);
+                    term = body;
+                }
+                Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body,
+                } => {
+                    self.checked(*var, *op, args, *handler);
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    self.pending.push(def);
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    self.closures(closures);
+                    term = body;
+                }
+                Term::LetFun(_, _) => {
+                    panic!("functions should be closure converted before code generation")
+                }
+                Term::Call {
+                    callee,
+                    args,
+                    ret,
+                    handler,
+                } => return self.call(callee, args, *ret, *handler),
+                Term::Jump(cont, args) => {
+                    let values: Vec<_> = args.iter().map(|arg| self.load(*arg)).collect();
+                    return self.goto(*cont, &values);
+                }
+                Term::If(var, a, b) => {
+                    let value = self.load(*var);
+                    self.line(format!("if ({} != {}) goto k{};", value, FALSE, a.0));
+                    return self.line(format!("goto k{};", b.0));
+                }
+                Term::Switch {
+                    scrutinee,
+                    cases,
+                    default,
+                } => {
+                    let value = self.load(*scrutinee);
+                    let (default, cases) = match default {
+                        Some(default) => (*default, &cases[..]),
+                        None => {
+                            let (last, rest) = cases.split_last().expect("a switch has cases");
+                            (last.1, rest)
+                        }
+                    };
+                    self.open(format!("switch ({}) {{", value));
+                    for (n, cont) in cases {
+                        self.line(format!("case {}: goto k{};", int(tagged(*n)), cont.0));
+                    }
+                    self.line(format!("default: goto k{};", default.0));
+                    return self.close();
+                }
+            }
+        }
+    }
+
+    fn exp(&mut self, var: Var, exp: &Exp) {
+        let value = match exp {
+            Exp::Const(c) => self.module.constant(c),
+            Exp::Record(fields) if fields.is_empty() => UNIT.to_string(),
+            Exp::Record(fields) => {
+                let object = self.alloc(kind::RECORD, 0, fields.len());
+                self.init(&object, fields)
+            }
+            Exp::Select(i, record) => format!("FIELD({}, {})", self.load(*record), i),
+            Exp::Con(tag, None) => int(tagged(*tag as i64)),
+            Exp::Con(tag, Some(arg)) => {
+                let object = self.alloc(kind::CON, *tag, 1);
+                self.init(&object, &[*arg])
+            }
+            Exp::Tag(value) => {
+                // Its value if it's immediate, else the tag in its header.
+                let value = self.value(self.load(*value));
+                format!(
+                    "{} & 1 ? {} : TAG(*(uint64_t *)(uintptr_t){} >> 8 & 0xffffff)",
+                    value, value, value
+                )
+            }
+            Exp::ConArg(value) | Exp::ExnId(value) => format!("FIELD({}, 0)", self.load(*value)),
+            Exp::ExnArg(value) => format!("FIELD({}, 1)", self.load(*value)),
+            Exp::Exn(id, None) => self.module.exn(*id),
+            Exp::Exn(id, Some(arg)) => {
+                let object = self.alloc(kind::EXN, 0, 2);
+                self.line(format!("{}[1] = {};", object, int(tagged(*id as i64))));
+                let arg = self.load(*arg);
+                self.line(format!("{}[2] = {};", object, arg));
+                format!("OBJECT({})", object)
+            }
+            Exp::Prim(op, args) => self.prim(*op, args),
+            Exp::Free(i, closure) => format!("FIELD({}, {})", self.load(*closure), i + 1),
+            Exp::Global(global) => format!("smol_globals[{}]", global),
+            Exp::SetGlobal(global, value) => {
+                let value = self.load(*value);
+                self.line(format!("smol_globals[{}] = {};", global, value));
+                UNIT.to_string()
+            }
+        };
+        self.store(var, &value);
+    }
+
+    fn word(&self, var: Var) -> String {
+        format!("word_of({})", self.load(var))
+    }
+
+    fn real(&self, var: Var) -> String {
+        format!("real_of({})", self.load(var))
+    }
+
+    fn box_word(&self, raw: &str) -> String {
+        format!("box({}, {})", int(header(kind::WORD, 0, 1) as i64), raw)
+    }
+
+    fn box_real(&self, raw: &str) -> String {
+        format!(
+            "box({}, bits_of({}))",
+            int(header(kind::REAL, 0, 1) as i64),
+            raw
+        )
+    }
+
+    /// The value of a primitive that can't raise.
+    fn prim(&mut self, op: PrimOp, args: &[Var]) -> String {
+        use PrimOp::*;
+
+        let infix = match op {
+            IntLt | WordLt | RealLt | StringLt => Some("<"),
+            IntLe | WordLe | RealLe | StringLe => Some("<="),
+            IntGt | WordGt | RealGt | StringGt => Some(">"),
+            IntGe | WordGe | RealGe | StringGe => Some(">="),
+            IntEq => Some("=="),
+            WordAdd | RealAdd => Some("+"),
+            WordSub | RealSub => Some("-"),
+            WordMul | RealMul => Some("*"),
+            RealDiv => Some("/"),
+            _ => None,
+        };
+        match op {
+            // Tagging keeps the order of ints.
+            IntLt | IntLe | IntGt | IntGe | IntEq => {
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                self.bool(&format!("{} {} {}", a, infix.unwrap(), b))
+            }
+            WordAdd | WordSub | WordMul => {
+                let (a, b) = (self.word(args[0]), self.word(args[1]));
+                self.box_word(&format!("{} {} {}", a, infix.unwrap(), b))
+            }
+            WordNeg => {
+                let a = self.word(args[0]);
+                self.box_word(&format!("0 - {}", a))
+            }
+            WordLt | WordLe | WordGt | WordGe => {
+                let (a, b) = (self.word(args[0]), self.word(args[1]));
+                self.bool(&format!("{} {} {}", a, infix.unwrap(), b))
+            }
+            RealAdd | RealSub | RealMul | RealDiv => {
+                let (a, b) = (self.real(args[0]), self.real(args[1]));
+                self.box_real(&format!("{} {} {}", a, infix.unwrap(), b))
+            }
+            RealNeg => {
+                let a = self.real(args[0]);
+                self.box_real(&format!("-{}", a))
+            }
+            RealAbs => {
+                let a = self.real(args[0]);
+                self.box_real(&format!("fabs({})", a))
+            }
+            RealLt | RealLe | RealGt | RealGe => {
+                let (a, b) = (self.real(args[0]), self.real(args[1]));
+                self.bool(&format!("{} {} {}", a, infix.unwrap(), b))
+            }
+            StringLt | StringLe | StringGt | StringGe => {
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                self.bool(&format!(
+                    "smol_string_compare({}, {}) {} 0",
+                    a,
+                    b,
+                    infix.unwrap()
+                ))
+            }
+            StringConcat | Equal => {
+                let name = if op == Equal {
+                    "smol_equal"
+                } else {
+                    "smol_string_concat"
+                };
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                self.value(format!("{}({}, {})", name, a, b))
+            }
+            Not => format!("{} ^ {}", self.load(args[0]), TRUE ^ FALSE),
+            Ref => {
+                let object = self.alloc(kind::REF, 0, 1);
+                self.init(&object, args)
+            }
+            Deref => format!("FIELD({}, 0)", self.load(args[0])),
+            Assign => {
+                let (r, value) = (self.load(args[0]), self.load(args[1]));
+                self.line(format!("FIELD({}, 0) = {};", r, value));
+                UNIT.to_string()
+            }
+            Print => {
+                let s = self.load(args[0]);
+                self.line(format!("smol_print({});", s));
+                UNIT.to_string()
+            }
+            IntAdd | IntSub | IntMul | IntDiv | IntMod | IntNeg | IntAbs | WordDiv | WordMod => {
+                panic!("`{}` can raise, so it should be checked", op.name())
+            }
+        }
+    }
+
+    /// An int that raises Overflow unless it fits in 63 bits.
+    fn fits(&mut self, n: String, handler: Cont) -> String {
+        let n = self.typed("int64_t", n);
+        self.raise_if(&format!("!FITS63({})", n), "Overflow", handler);
+        format!("TAG({})", n)
+    }
+
+    fn checked(&mut self, var: Var, op: PrimOp, args: &[Var], handler: Cont) {
+        use PrimOp::*;
+
+        let untag = |f: &Self, i: usize| format!("UNTAG({})", f.load(args[i]));
+        let value = match op {
+            IntAdd | IntSub => {
+                let sign = if op == IntAdd { "+" } else { "-" };
+                let n = format!("{} {} {}", untag(self, 0), sign, untag(self, 1));
+                self.fits(n, handler)
+            }
+            IntMul => {
+                let product = self.temp();
+                self.line(format!("int64_t {};", product));
+                let cond = format!(
+                    "mul63({}, {}, &{})",
+                    untag(self, 0),
+                    untag(self, 1),
+                    product
+                );
+                self.raise_if(&cond, "Overflow", handler);
+                format!("TAG({})", product)
+            }
+            IntNeg => {
+                let n = format!("-{}", untag(self, 0));
+                self.fits(n, handler)
+            }
+            IntAbs => {
+                let a = self.typed("int64_t", untag(self, 0));
+                self.fits(format!("{} < 0 ? -{} : {}", a, a, a), handler)
+            }
+            IntDiv | IntMod => {
+                // Both round towards negative infinity, unlike C's.
+                let a = self.typed("int64_t", untag(self, 0));
+                let b = self.typed("int64_t", untag(self, 1));
+                self.raise_if(&format!("{} == 0", b), "Div", handler);
+                let quot = self.typed("int64_t", format!("{} / {}", a, b));
+                let rem = self.typed("int64_t", format!("{} % {}", a, b));
+                self.open(format!("if ({} != 0 && ({} ^ {}) < 0) {{", rem, rem, b));
+                self.line(format!("{} -= 1;", quot));
+                self.line(format!("{} += {};", rem, b));
+                self.close();
+                if op == IntDiv {
+                    // Only the smallest int divided by ~1 overflows.
+                    self.fits(quot, handler)
+                } else {
+                    format!("TAG({})", rem)
+                }
+            }
+            WordDiv | WordMod => {
+                let (a, b) = (self.word(args[0]), self.word(args[1]));
+                let b = self.typed("uint64_t", b);
+                self.raise_if(&format!("{} == 0", b), "Div", handler);
+                let sign = if op == WordDiv { "/" } else { "%" };
+                self.box_word(&format!("{} {} {}", a, sign, b))
+            }
+            _ => panic!("`{}` can't raise", op.name()),
+        };
+        self.store(var, &value);
+    }
+
+    fn closures(&mut self, closures: &[Closure]) {
+        for closure in closures {
+            let object = self.alloc(kind::CLOSURE, 0, closure.free.len() + 1);
+            let name = fun_name(self.module.program, closure.fun);
+            self.line(format!("{}[1] = (uint64_t)(uintptr_t)&{};", object, name));
+            self.store(closure.var, &format!("OBJECT({})", object));
+        }
+        // The closures are all allocated, so they can capture each other.
+        for closure in closures {
+            for (i, free) in closure.free.iter().enumerate() {
+                let (object, value) = (self.load(closure.var), self.load(*free));
+                self.line(format!("FIELD({}, {}) = {};", object, i + 1, value));
+            }
+        }
+    }
+
+    /// Call `code` with `args`, or leave it to our caller to if it's a tail
+    /// call, giving its result.
+    fn call_code(&mut self, code: String, args: Vec<String>, tail: bool) -> Option<String> {
+        if tail {
+            self.line(format!("next_code = {};", code));
+            for (i, arg) in args.iter().enumerate() {
+                self.line(format!("next_args[{}] = {};", i, arg));
+            }
+            self.pop();
+            self.line("return 0;".to_owned());
+            return None;
+        }
+        let array = self.temp();
+        self.line(format!(
+            "value {}[{}] = {{ {} }};",
+            array,
+            args.len(),
+            args.join(", ")
+        ));
+        Some(self.value(format!("call({}, {})", code, array)))
+    }
+
+    fn call(&mut self, callee: &Callee, args: &[Var], ret: Cont, handler: Cont) {
+        let tail = ret == self.ret && handler == self.handler;
+        let result = match callee {
+            Callee::Native(name) => {
+                self.module.natives.insert(name);
+                let arg = self.load(args[0]);
+                self.value(format!("smol_prim_{}({})", name, arg))
+            }
+            Callee::Closure(f) => {
+                let closure = self.value(self.load(*f));
+                let code = self.typed("code", format!("(code)(uintptr_t)FIELD({}, 0)", closure));
+                let args = vec![closure, self.load(args[0])];
+                match self.call_code(code, args, tail) {
+                    Some(result) => result,
+                    None => return,
+                }
+            }
+            Callee::Direct(id) => {
+                let code = fun_name(self.module.program, *id);
+                let args = args.iter().map(|arg| self.load(*arg)).collect();
+                match self.call_code(code, args, tail) {
+                    Some(result) => result,
+                    None => return,
+                }
+            }
+        };
+        self.open("if (smol_exn) {".to_owned());
+        if handler == self.handler {
+            // Leave the exception where it is for our caller.
+            self.pop();
+            self.line("return 0;".to_owned());
+        } else {
+            let exn = self.value("smol_exn".to_owned());
+            self.line("smol_exn = 0;".to_owned());
+            self.goto(handler, &[exn]);
+        }
+        self.close();
+        self.goto(ret, &[result]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            escape(b"a \"b\"\n\\??="),
+            "a \\042b\\042\\012\\134\\077\\077="
+        );
+        assert_eq!(int(i64::MIN), "INT64_MIN");
+        assert_eq!(int(-7), "-7");
+        assert_eq!(int(1 << 40), "INT64_C(1099511627776)");
+    }
+
+    #[test]
+    fn tail_calls() {
+        // fun loop x = loop x, converted by hand.
+        let mut program = Program {
+            vars: Vec::new(),
+            conts: 0,
+            main: Fun {
+                name: Var(0),
+                params: Vec::new(),
+                ret: Cont(0),
+                handler: Cont(0),
+                body: Term::Jump(Cont(0), Vec::new()),
+            },
+            funs: Vec::new(),
+            globals: 0,
+            exns: BTreeMap::new(),
+        };
+        let main = program.var("main");
+        let f = program.var("loop");
+        let x = program.var("x");
+        let unit = program.var("unit");
+        let (ret, handler) = (program.cont(), program.cont());
+        let (fret, fhandler) = (program.cont(), program.cont());
+        program.funs.push(Fun {
+            name: f,
+            params: vec![f, x],
+            ret: fret,
+            handler: fhandler,
+            body: Term::Call {
+                callee: Callee::Closure(f),
+                args: vec![x],
+                ret: fret,
+                handler: fhandler,
+            },
+        });
+        program.main = Fun {
+            name: main,
+            params: Vec::new(),
+            ret,
+            handler,
+            body: Term::LetClosures(
+                vec![Closure {
+                    var: f,
+                    fun: 0,
+                    free: Vec::new(),
+                }],
+                Box::new(Term::Let(
+                    unit,
+                    Exp::Record(Vec::new()),
+                    Box::new(Term::Call {
+                        callee: Callee::Closure(f),
+                        args: vec![unit],
+                        ret,
+                        handler,
+                    }),
+                )),
+            ),
+        };
+        let module = emit(&program);
+        // Both calls are tail calls, which go through the trampoline.
+        assert_eq!(module.matches("next_code = t").count(), 2, "{}", module);
+        assert_eq!(module.matches("= call(").count(), 0, "{}", module);
+        let allocs =
+            module.matches("= smol_alloc(").count() - PRELUDE.matches("= smol_alloc(").count();
+        assert_eq!(allocs, 1, "{}", module);
+    }
+}
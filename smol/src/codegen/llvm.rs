@@ -0,0 +1,969 @@
+//! Emitting LLVM IR, as text.
+//!
+//! Each IR function becomes an LLVM function taking its closure and its
+//! argument, or its free variables and its argument if it was lambda lifted,
+//! and each continuation a basic block. Variables live in slots of
+//! the function's frame on the shadow stack, so every use loads its variable
+//! and every binding stores it, and continuation parameters are just slots
+//! the jumps store to. LLVM's `mem2reg` can't promote them, since the
+//! collector must see them, but the loads and stores are cheap.
+//!
+//! A call returns its result, or 0 with the exception left in `smol_exn`.
+//! Calls that return to the function's own continuations are tail calls.
+//! Functions use the `tailcc` calling convention, which guarantees them even
+//! between functions with different parameters, so loops written as
+//! recursion run in constant stack.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use super::{exn_id, header, kind, sanitize, tagged, Slots, FALSE, TRUE, UNIT};
+use crate::ast::Const;
+use crate::ir::*;
+
+/// The LLVM module for a closure converted program, with a C `main` that
+/// initializes the runtime, runs the program, and exits with the runtime's
+/// status.
+pub fn emit(program: &Program) -> String {
+    let mut module = Module {
+        program,
+        constants: String::new(),
+        strings: HashMap::new(),
+        exns: BTreeSet::new(),
+        natives: BTreeSet::new(),
+        next_constant: 0,
+    };
+    let mut functions = module.function(&program.main, "smol_main".to_owned(), true);
+    for (id, fun) in program.funs.iter().enumerate() {
+        let name = fun_name(program, id);
+        functions += &module.function(fun, name, false);
+    }
+
+    let mut out = String::new();
+    out += "; Generated by smol.\n\n";
+    out += "@smol_sp = external global ptr\n";
+    out += "@smol_sp_limit = external global ptr\n";
+    out += "@smol_exn = external global i64\n";
+    writeln!(
+        out,
+        "@smol_globals = internal global [{} x i64] zeroinitializer",
+        program.globals
+    )
+    .unwrap();
+
+    // The names of exceptions, for exnName and uncaught exceptions.
+    let count = program.exns.keys().last().map_or(0, |id| id + 1);
+    let mut names = vec!["ptr null".to_owned(); count as usize];
+    for (id, name) in &program.exns {
+        writeln!(
+            out,
+            "@exn.name.{} = private constant [{} x i8] c\"{}\\00\"",
+            id,
+            name.len() + 1,
+            escape(name.as_bytes())
+        )
+        .unwrap();
+        names[*id as usize] = format!("ptr @exn.name.{}", id);
+    }
+    writeln!(
+        out,
+        "@smol_exn_names = private constant [{} x ptr] [{}]",
+        count,
+        names.join(", ")
+    )
+    .unwrap();
+    for id in &module.exns {
+        writeln!(
+            out,
+            "@exn.{} = private constant {{ i64, i64, i64 }} {{ i64 {}, i64 {}, i64 {} }}, align 8",
+            id,
+            header(kind::EXN, 0, 2),
+            tagged(*id as i64),
+            UNIT
+        )
+        .unwrap();
+    }
+    out += &module.constants;
+
+    out += "\n";
+    out += "declare ptr @smol_alloc(i64, i64)\n";
+    out += "declare i64 @smol_equal(i64, i64)\n";
+    out += "declare i64 @smol_string_compare(i64, i64)\n";
+    out += "declare i64 @smol_string_concat(i64, i64)\n";
+    out += "declare void @smol_print(i64)\n";
+    out += "declare void @smol_init(i32, ptr, ptr, i64, ptr, i64)\n";
+    out += "declare void @smol_run(ptr)\n";
+    out += "declare i32 @smol_finish()\n";
+    for native in &module.natives {
+        writeln!(out, "declare i64 @smol_prim_{}(i64)", native).unwrap();
+    }
+    out += "declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)\n";
+    out += "declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)\n";
+    out += "declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)\n";
+    out += "declare double @llvm.fabs.f64(double)\n";
+    out += "declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)\n";
+
+    out += &functions;
+
+    out += "\ndefine internal void @smol_start() {\n";
+    out += "  call i64 @smol_main()\n";
+    out += "  ret void\n";
+    out += "}\n";
+
+    out += "\ndefine i32 @main(i32 %argc, ptr %argv) {\n";
+    writeln!(
+        out,
+        "  call void @smol_init(i32 %argc, ptr %argv, ptr @smol_globals, i64 {}, ptr @smol_exn_names, i64 {})",
+        program.globals, count
+    )
+    .unwrap();
+    out += "  call void @smol_run(ptr @smol_start)\n";
+    out += "  %status = call i32 @smol_finish()\n";
+    out += "  ret i32 %status\n";
+    out += "}\n";
+    out
+}
+
+fn fun_name(program: &Program, id: FunId) -> String {
+    format!("@{}.{}", sanitize(program.name(program.funs[id].name)), id)
+}
+
+/// Bytes escaped for a `c"..."` string.
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in bytes {
+        if b.is_ascii_graphic() && b != b'"' && b != b'\\' || b == b' ' {
+            out.push(b as char);
+        } else {
+            write!(out, "\\{:02X}", b).unwrap();
+        }
+    }
+    out
+}
+
+struct Module<'p> {
+    program: &'p Program,
+    /// Definitions of the constants functions use.
+    constants: String,
+    strings: HashMap<String, String>,
+    /// Exceptions without arguments that are used, which are constants.
+    exns: BTreeSet<u32>,
+    natives: BTreeSet<&'static str>,
+    next_constant: usize,
+}
+
+impl<'p> Module<'p> {
+    /// A constant's value, as an i64.
+    fn constant(&mut self, c: &Const) -> String {
+        match c {
+            Const::Int(n) => tagged(*n).to_string(),
+            Const::Char(c) => tagged(*c as i64).to_string(),
+            Const::String(s) => {
+                if let Some(name) = self.strings.get(s) {
+                    return format!("ptrtoint (ptr {} to i64)", name);
+                }
+                let name = format!("@str.{}", self.next_constant);
+                self.next_constant += 1;
+                writeln!(
+                    self.constants,
+                    "{} = private constant {{ i64, [{} x i8] }} {{ i64 {}, [{} x i8] c\"{}\" }}, align 8",
+                    name,
+                    s.len(),
+                    header(kind::STRING, 0, s.len() as u32),
+                    s.len(),
+                    escape(s.as_bytes())
+                )
+                .unwrap();
+                self.strings.insert(s.clone(), name.clone());
+                format!("ptrtoint (ptr {} to i64)", name)
+            }
+            Const::Word(w) => {
+                let name = format!("@word.{}", self.next_constant);
+                self.next_constant += 1;
+                writeln!(
+                    self.constants,
+                    "{} = private constant {{ i64, i64 }} {{ i64 {}, i64 {} }}, align 8",
+                    name,
+                    header(kind::WORD, 0, 1),
+                    *w as i64
+                )
+                .unwrap();
+                format!("ptrtoint (ptr {} to i64)", name)
+            }
+            Const::Real(x) => {
+                let name = format!("@real.{}", self.next_constant);
+                self.next_constant += 1;
+                writeln!(
+                    self.constants,
+                    "{} = private constant {{ i64, double }} {{ i64 {}, double 0x{:016X} }}, align 8",
+                    name,
+                    header(kind::REAL, 0, 1),
+                    x.to_bits()
+                )
+                .unwrap();
+                format!("ptrtoint (ptr {} to i64)", name)
+            }
+        }
+    }
+
+    /// An exception without an argument.
+    fn exn(&mut self, id: u32) -> String {
+        self.exns.insert(id);
+        format!("ptrtoint (ptr @exn.{} to i64)", id)
+    }
+
+    fn function(&mut self, fun: &'p Fun, name: String, main: bool) -> String {
+        let mut slots = Slots::default();
+        for param in &fun.params {
+            slots.bind(*param);
+        }
+        slots.term(&fun.body);
+
+        let mut f = Function {
+            module: self,
+            out: String::new(),
+            slots: slots.slots,
+            params: slots.params,
+            ret: fun.ret,
+            handler: fun.handler,
+            main,
+            next_temp: 0,
+            next_block: 0,
+            pending: Vec::new(),
+        };
+        let size = f.slots.len();
+        let params: Vec<_> = (0..fun.params.len())
+            .map(|i| format!("i64 %p{}", i))
+            .collect();
+        writeln!(
+            f.out,
+            "\ndefine internal {}i64 {}({}) {{",
+            if main { "" } else { "tailcc " },
+            if main { format!("@{}", name) } else { name },
+            params.join(", ")
+        )
+        .unwrap();
+        f.out += "entry:\n";
+        f.line("%fp = load ptr, ptr @smol_sp".to_owned());
+        f.line(format!("%top = getelementptr i64, ptr %fp, i64 {}", size));
+        f.line("%limit = load ptr, ptr @smol_sp_limit".to_owned());
+        f.line("%overflow = icmp ugt ptr %top, %limit".to_owned());
+        f.raise_if("%overflow", "Overflow", fun.handler);
+        f.line("store ptr %top, ptr @smol_sp".to_owned());
+        if size > 0 {
+            f.line(format!(
+                "call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 {}, i1 false)",
+                size * 8
+            ));
+        }
+        let mut vars: Vec<_> = f.slots.iter().map(|(var, slot)| (*slot, *var)).collect();
+        vars.sort();
+        for (slot, var) in vars {
+            let name = sanitize(f.module.program.name(var));
+            f.line(format!(
+                "%s{}.{} = getelementptr i64, ptr %fp, i64 {}",
+                slot, name, slot
+            ));
+        }
+        for (i, param) in fun.params.iter().enumerate() {
+            let slot = f.slot(*param);
+            f.line(format!("store i64 %p{}, ptr {}", i, slot));
+        }
+
+        f.term(&fun.body);
+        while let Some(def) = f.pending.pop() {
+            writeln!(f.out, "k{}:", def.cont.0).unwrap();
+            f.term(&def.body);
+        }
+        f.out += "}\n";
+        f.out
+    }
+}
+
+struct Function<'m, 'p> {
+    module: &'m mut Module<'p>,
+    out: String,
+    slots: HashMap<Var, usize>,
+    /// The parameters of the function's continuations.
+    params: HashMap<Cont, Vec<Var>>,
+    ret: Cont,
+    handler: Cont,
+    /// Whether this is the program's top level, which can't make tail calls
+    /// since it has no parameters.
+    main: bool,
+    next_temp: usize,
+    next_block: usize,
+    /// Continuations whose blocks haven't been emitted yet.
+    pending: Vec<&'p ContDef>,
+}
+
+impl<'m, 'p> Function<'m, 'p> {
+    fn line(&mut self, line: String) {
+        self.out += "  ";
+        self.out += &line;
+        self.out += "\n";
+    }
+
+    fn temp(&mut self) -> String {
+        self.next_temp += 1;
+        format!("%t{}", self.next_temp - 1)
+    }
+
+    /// `%temp = instruction`, giving the temporary.
+    fn value(&mut self, instruction: String) -> String {
+        let temp = self.temp();
+        self.line(format!("{} = {}", temp, instruction));
+        temp
+    }
+
+    fn block(&mut self) -> String {
+        self.next_block += 1;
+        format!("b{}", self.next_block - 1)
+    }
+
+    fn start(&mut self, block: &str) {
+        writeln!(self.out, "{}:", block).unwrap();
+    }
+
+    fn slot(&self, var: Var) -> String {
+        let slot = self.slots[&var];
+        format!("%s{}.{}", slot, sanitize(self.module.program.name(var)))
+    }
+
+    fn load(&mut self, var: Var) -> String {
+        let slot = self.slot(var);
+        self.value(format!("load i64, ptr {}", slot))
+    }
+
+    fn store(&mut self, var: Var, value: &str) {
+        let slot = self.slot(var);
+        self.line(format!("store i64 {}, ptr {}", value, slot));
+    }
+
+    /// A pointer to the `index`th field of an object.
+    fn field(&mut self, object: &str, index: usize) -> String {
+        let ptr = self.value(format!("inttoptr i64 {} to ptr", object));
+        self.value(format!("getelementptr i64, ptr {}, i64 {}", ptr, index + 1))
+    }
+
+    fn load_field(&mut self, object: &str, index: usize) -> String {
+        let field = self.field(object, index);
+        self.value(format!("load i64, ptr {}", field))
+    }
+
+    /// Allocate an object. The collector may run, so values must be loaded
+    /// after this.
+    fn alloc(&mut self, kind: u8, tag: u32, len: usize) -> String {
+        self.value(format!(
+            "call ptr @smol_alloc(i64 {}, i64 {})",
+            len,
+            header(kind, tag, len as u32)
+        ))
+    }
+
+    /// Store the fields of a new object, and give its value.
+    fn init(&mut self, object: &str, fields: &[Var]) -> String {
+        for (i, var) in fields.iter().enumerate() {
+            let value = self.load(*var);
+            let field = self.value(format!("getelementptr i64, ptr {}, i64 {}", object, i + 1));
+            self.line(format!("store i64 {}, ptr {}", value, field));
+        }
+        self.value(format!("ptrtoint ptr {} to i64", object))
+    }
+
+    /// Make a call that returns straight to our caller, whatever it gives.
+    fn tail_call(&mut self, call: String) {
+        self.pop();
+        let result = self.value(format!("tail {}", call));
+        self.line(format!("ret i64 {}", result));
+    }
+
+    fn pop(&mut self) {
+        self.line("store ptr %fp, ptr @smol_sp".to_owned());
+    }
+
+    /// Jump to a continuation with values.
+    fn goto(&mut self, cont: Cont, values: &[String]) {
+        if cont == self.ret {
+            self.pop();
+            self.line(format!("ret i64 {}", values[0]));
+        } else if cont == self.handler {
+            self.line(format!("store i64 {}, ptr @smol_exn", values[0]));
+            self.pop();
+            self.line("ret i64 0".to_owned());
+        } else {
+            let params = self.params[&cont].clone();
+            for (param, value) in params.iter().zip(values) {
+                self.store(*param, value);
+            }
+            self.line(format!("br label %k{}", cont.0));
+        }
+    }
+
+    fn bool(&mut self, cond: &str) -> String {
+        self.value(format!("select i1 {}, i64 {}, i64 {}", cond, TRUE, FALSE))
+    }
+
+    /// Branch to raise `exn` to `handler` if `cond`, continuing otherwise.
+    fn raise_if(&mut self, cond: &str, exn: &str, handler: Cont) {
+        let raise = self.block();
+        let ok = self.block();
+        self.line(format!("br i1 {}, label %{}, label %{}", cond, raise, ok));
+        self.start(&raise);
+        let exn = self.module.exn(exn_id(exn));
+        self.goto(handler, &[exn]);
+        self.start(&ok);
+    }
+
+    fn term(&mut self, mut term: &'p Term) {
+        loop {
+            match term {
+                Term::Let(var, exp, body) => {
+                    self.exp(*var, exp);
+                    term = body;
+                }
+                Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body,
+                } => {
+                    self.checked(*var, *op, args, *handler);
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    self.pending.push(def);
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    self.closures(closures);
+                    term = body;
+                }
+                Term::LetFun(_, _) => {
+                    panic!("functions should be closure converted before code generation")
+                }
+                Term::Call {
+                    callee,
+                    args,
+                    ret,
+                    handler,
+                } => return self.call(callee, args, *ret, *handler),
+                Term::Jump(cont, args) => {
+                    let values: Vec<_> = args.iter().map(|arg| self.load(*arg)).collect();
+                    return self.goto(*cont, &values);
+                }
+                Term::If(var, a, b) => {
+                    let value = self.load(*var);
+                    let cond = self.value(format!("icmp ne i64 {}, {}", value, FALSE));
+                    return self.line(format!("br i1 {}, label %k{}, label %k{}", cond, a.0, b.0));
+                }
+                Term::Switch {
+                    scrutinee,
+                    cases,
+                    default,
+                } => {
+                    let value = self.load(*scrutinee);
+                    let (default, cases) = match default {
+                        Some(default) => (*default, &cases[..]),
+                        None => {
+                            let (last, rest) = cases.split_last().expect("a switch has cases");
+                            (last.1, rest)
+                        }
+                    };
+                    let cases: Vec<_> = cases
+                        .iter()
+                        .map(|(n, cont)| format!("i64 {}, label %k{}", tagged(*n), cont.0))
+                        .collect();
+                    return self.line(format!(
+                        "switch i64 {}, label %k{} [ {} ]",
+                        value,
+                        default.0,
+                        cases.join(" ")
+                    ));
+                }
+            }
+        }
+    }
+
+    fn exp(&mut self, var: Var, exp: &Exp) {
+        let value = match exp {
+            Exp::Const(c) => self.module.constant(c),
+            Exp::Record(fields) if fields.is_empty() => UNIT.to_string(),
+            Exp::Record(fields) => {
+                let object = self.alloc(kind::RECORD, 0, fields.len());
+                self.init(&object, fields)
+            }
+            Exp::Select(i, record) => {
+                let record = self.load(*record);
+                self.load_field(&record, *i)
+            }
+            Exp::Con(tag, None) => tagged(*tag as i64).to_string(),
+            Exp::Con(tag, Some(arg)) => {
+                let object = self.alloc(kind::CON, *tag, 1);
+                self.init(&object, &[*arg])
+            }
+            Exp::Tag(value) => return self.tag(var, *value),
+            Exp::ConArg(value) | Exp::ExnId(value) => {
+                let value = self.load(*value);
+                self.load_field(&value, 0)
+            }
+            Exp::ExnArg(value) => {
+                let value = self.load(*value);
+                self.load_field(&value, 1)
+            }
+            Exp::Exn(id, None) => self.module.exn(*id),
+            Exp::Exn(id, Some(arg)) => {
+                let object = self.alloc(kind::EXN, 0, 2);
+                let field = self.value(format!("getelementptr i64, ptr {}, i64 1", object));
+                self.line(format!("store i64 {}, ptr {}", tagged(*id as i64), field));
+                let arg = self.load(*arg);
+                let field = self.value(format!("getelementptr i64, ptr {}, i64 2", object));
+                self.line(format!("store i64 {}, ptr {}", arg, field));
+                self.value(format!("ptrtoint ptr {} to i64", object))
+            }
+            Exp::Prim(op, args) => return self.prim(var, *op, args),
+            Exp::Free(i, closure) => {
+                let closure = self.load(*closure);
+                self.load_field(&closure, i + 1)
+            }
+            Exp::Global(global) => {
+                let ptr = self.value(format!(
+                    "getelementptr i64, ptr @smol_globals, i64 {}",
+                    global
+                ));
+                self.value(format!("load i64, ptr {}", ptr))
+            }
+            Exp::SetGlobal(global, value) => {
+                let value = self.load(*value);
+                let ptr = self.value(format!(
+                    "getelementptr i64, ptr @smol_globals, i64 {}",
+                    global
+                ));
+                self.line(format!("store i64 {}, ptr {}", value, ptr));
+                UNIT.to_string()
+            }
+        };
+        self.store(var, &value);
+    }
+
+    /// The tag of a datatype value: its value if it's immediate, else the
+    /// tag in its header.
+    fn tag(&mut self, var: Var, value: Var) {
+        let value = self.load(value);
+        let bit = self.value(format!("and i64 {}, 1", value));
+        let immediate = self.value(format!("icmp ne i64 {}, 0", bit));
+        let (imm, boxed, done) = (self.block(), self.block(), self.block());
+        self.line(format!(
+            "br i1 {}, label %{}, label %{}",
+            immediate, imm, boxed
+        ));
+        self.start(&imm);
+        self.store(var, &value);
+        self.line(format!("br label %{}", done));
+        self.start(&boxed);
+        let ptr = self.value(format!("inttoptr i64 {} to ptr", value));
+        let header = self.value(format!("load i64, ptr {}", ptr));
+        let shifted = self.value(format!("lshr i64 {}, 7", header));
+        let tag = self.value(format!("and i64 {}, {}", shifted, 0xff_ffffu64 << 1));
+        let tag = self.value(format!("or i64 {}, 1", tag));
+        self.store(var, &tag);
+        self.line(format!("br label %{}", done));
+        self.start(&done);
+    }
+
+    fn word(&mut self, var: Var) -> String {
+        let value = self.load(var);
+        self.load_field(&value, 0)
+    }
+
+    fn real(&mut self, var: Var) -> String {
+        let value = self.load(var);
+        let field = self.field(&value, 0);
+        self.value(format!("load double, ptr {}", field))
+    }
+
+    fn box_word(&mut self, raw: &str) -> String {
+        let object = self.alloc(kind::WORD, 0, 1);
+        let field = self.value(format!("getelementptr i64, ptr {}, i64 1", object));
+        self.line(format!("store i64 {}, ptr {}", raw, field));
+        self.value(format!("ptrtoint ptr {} to i64", object))
+    }
+
+    fn box_real(&mut self, raw: &str) -> String {
+        let object = self.alloc(kind::REAL, 0, 1);
+        let field = self.value(format!("getelementptr i64, ptr {}, i64 1", object));
+        self.line(format!("store double {}, ptr {}", raw, field));
+        self.value(format!("ptrtoint ptr {} to i64", object))
+    }
+
+    fn prim(&mut self, var: Var, op: PrimOp, args: &[Var]) {
+        use PrimOp::*;
+
+        let value = match op {
+            IntLt | IntLe | IntGt | IntGe | IntEq => {
+                let cmp = match op {
+                    IntLt => "slt",
+                    IntLe => "sle",
+                    IntGt => "sgt",
+                    IntGe => "sge",
+                    _ => "eq",
+                };
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                let cond = self.value(format!("icmp {} i64 {}, {}", cmp, a, b));
+                self.bool(&cond)
+            }
+            WordAdd | WordSub | WordMul => {
+                let instr = match op {
+                    WordAdd => "add",
+                    WordSub => "sub",
+                    _ => "mul",
+                };
+                let (a, b) = (self.word(args[0]), self.word(args[1]));
+                let raw = self.value(format!("{} i64 {}, {}", instr, a, b));
+                self.box_word(&raw)
+            }
+            WordNeg => {
+                let a = self.word(args[0]);
+                let raw = self.value(format!("sub i64 0, {}", a));
+                self.box_word(&raw)
+            }
+            WordLt | WordLe | WordGt | WordGe => {
+                let cmp = match op {
+                    WordLt => "ult",
+                    WordLe => "ule",
+                    WordGt => "ugt",
+                    _ => "uge",
+                };
+                let (a, b) = (self.word(args[0]), self.word(args[1]));
+                let cond = self.value(format!("icmp {} i64 {}, {}", cmp, a, b));
+                self.bool(&cond)
+            }
+            RealAdd | RealSub | RealMul | RealDiv => {
+                let instr = match op {
+                    RealAdd => "fadd",
+                    RealSub => "fsub",
+                    RealMul => "fmul",
+                    _ => "fdiv",
+                };
+                let (a, b) = (self.real(args[0]), self.real(args[1]));
+                let raw = self.value(format!("{} double {}, {}", instr, a, b));
+                self.box_real(&raw)
+            }
+            RealNeg => {
+                let a = self.real(args[0]);
+                let raw = self.value(format!("fneg double {}", a));
+                self.box_real(&raw)
+            }
+            RealAbs => {
+                let a = self.real(args[0]);
+                let raw = self.value(format!("call double @llvm.fabs.f64(double {})", a));
+                self.box_real(&raw)
+            }
+            RealLt | RealLe | RealGt | RealGe => {
+                let cmp = match op {
+                    RealLt => "olt",
+                    RealLe => "ole",
+                    RealGt => "ogt",
+                    _ => "oge",
+                };
+                let (a, b) = (self.real(args[0]), self.real(args[1]));
+                let cond = self.value(format!("fcmp {} double {}, {}", cmp, a, b));
+                self.bool(&cond)
+            }
+            StringLt | StringLe | StringGt | StringGe => {
+                let cmp = match op {
+                    StringLt => "slt",
+                    StringLe => "sle",
+                    StringGt => "sgt",
+                    _ => "sge",
+                };
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                let order = self.value(format!(
+                    "call i64 @smol_string_compare(i64 {}, i64 {})",
+                    a, b
+                ));
+                let cond = self.value(format!("icmp {} i64 {}, 0", cmp, order));
+                self.bool(&cond)
+            }
+            StringConcat | Equal => {
+                let name = if op == Equal {
+                    "smol_equal"
+                } else {
+                    "smol_string_concat"
+                };
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                self.value(format!("call i64 @{}(i64 {}, i64 {})", name, a, b))
+            }
+            Not => {
+                let a = self.load(args[0]);
+                self.value(format!("xor i64 {}, {}", a, TRUE ^ FALSE))
+            }
+            Ref => {
+                let object = self.alloc(kind::REF, 0, 1);
+                self.init(&object, args)
+            }
+            Deref => {
+                let r = self.load(args[0]);
+                self.load_field(&r, 0)
+            }
+            Assign => {
+                let r = self.load(args[0]);
+                let value = self.load(args[1]);
+                let field = self.field(&r, 0);
+                self.line(format!("store i64 {}, ptr {}", value, field));
+                UNIT.to_string()
+            }
+            Print => {
+                let s = self.load(args[0]);
+                self.line(format!("call void @smol_print(i64 {})", s));
+                UNIT.to_string()
+            }
+            IntAdd | IntSub | IntMul | IntDiv | IntMod | IntNeg | IntAbs | WordDiv | WordMod => {
+                panic!("`{}` can raise, so it should be checked", op.name())
+            }
+        };
+        self.store(var, &value);
+    }
+
+    /// An arithmetic intrinsic with overflow, raising Overflow.
+    fn overflowing(&mut self, intrinsic: &str, a: &str, b: &str, handler: Cont) -> String {
+        let pair = self.value(format!(
+            "call {{ i64, i1 }} @llvm.{}.with.overflow.i64(i64 {}, i64 {})",
+            intrinsic, a, b
+        ));
+        let result = self.value(format!("extractvalue {{ i64, i1 }} {}, 0", pair));
+        let overflow = self.value(format!("extractvalue {{ i64, i1 }} {}, 1", pair));
+        self.raise_if(&overflow, "Overflow", handler);
+        result
+    }
+
+    fn checked(&mut self, var: Var, op: PrimOp, args: &[Var], handler: Cont) {
+        use PrimOp::*;
+
+        let value = match op {
+            IntAdd | IntSub => {
+                // (2a + 1) ± 2b + 1 - 1
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                let b = self.value(format!("sub i64 {}, 1", b));
+                let intrinsic = if op == IntAdd { "sadd" } else { "ssub" };
+                self.overflowing(intrinsic, &a, &b, handler)
+            }
+            IntMul => {
+                // a * 2b + 1
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                let a = self.value(format!("ashr i64 {}, 1", a));
+                let b = self.value(format!("sub i64 {}, 1", b));
+                let product = self.overflowing("smul", &a, &b, handler);
+                self.value(format!("or i64 {}, 1", product))
+            }
+            IntNeg => {
+                let a = self.load(args[0]);
+                self.overflowing("ssub", "2", &a, handler)
+            }
+            IntAbs => {
+                let a = self.load(args[0]);
+                let negative = self.value(format!("icmp slt i64 {}, 0", a));
+                let pair = self.value(format!(
+                    "call {{ i64, i1 }} @llvm.ssub.with.overflow.i64(i64 2, i64 {})",
+                    a
+                ));
+                let negated = self.value(format!("extractvalue {{ i64, i1 }} {}, 0", pair));
+                let overflow = self.value(format!("extractvalue {{ i64, i1 }} {}, 1", pair));
+                let overflow = self.value(format!("and i1 {}, {}", negative, overflow));
+                self.raise_if(&overflow, "Overflow", handler);
+                self.value(format!(
+                    "select i1 {}, i64 {}, i64 {}",
+                    negative, negated, a
+                ))
+            }
+            IntDiv | IntMod => {
+                // Both round towards negative infinity, unlike LLVM's.
+                let (a, b) = (self.load(args[0]), self.load(args[1]));
+                let a = self.value(format!("ashr i64 {}, 1", a));
+                let b = self.value(format!("ashr i64 {}, 1", b));
+                let zero = self.value(format!("icmp eq i64 {}, 0", b));
+                self.raise_if(&zero, "Div", handler);
+                let rem = self.value(format!("srem i64 {}, {}", a, b));
+                let inexact = self.value(format!("icmp ne i64 {}, 0", rem));
+                let signs = self.value(format!("xor i64 {}, {}", rem, b));
+                let differ = self.value(format!("icmp slt i64 {}, 0", signs));
+                let adjust = self.value(format!("and i1 {}, {}", inexact, differ));
+                if op == IntDiv {
+                    let quot = self.value(format!("sdiv i64 {}, {}", a, b));
+                    let one = self.value(format!("zext i1 {} to i64", adjust));
+                    let quot = self.value(format!("sub i64 {}, {}", quot, one));
+                    // Only the smallest int divided by ~1 overflows.
+                    let doubled = self.overflowing("sadd", &quot, &quot, handler);
+                    self.value(format!("or i64 {}, 1", doubled))
+                } else {
+                    let fixed = self.value(format!("add i64 {}, {}", rem, b));
+                    let rem =
+                        self.value(format!("select i1 {}, i64 {}, i64 {}", adjust, fixed, rem));
+                    let doubled = self.value(format!("shl i64 {}, 1", rem));
+                    self.value(format!("or i64 {}, 1", doubled))
+                }
+            }
+            WordDiv | WordMod => {
+                let (a, b) = (self.word(args[0]), self.word(args[1]));
+                let zero = self.value(format!("icmp eq i64 {}, 0", b));
+                self.raise_if(&zero, "Div", handler);
+                let instr = if op == WordDiv { "udiv" } else { "urem" };
+                let raw = self.value(format!("{} i64 {}, {}", instr, a, b));
+                self.box_word(&raw)
+            }
+            _ => panic!("`{}` can't raise", op.name()),
+        };
+        self.store(var, &value);
+    }
+
+    fn closures(&mut self, closures: &[Closure]) {
+        for closure in closures {
+            let object = self.alloc(kind::CLOSURE, 0, closure.free.len() + 1);
+            let code = self.value(format!("getelementptr i64, ptr {}, i64 1", object));
+            let name = fun_name(self.module.program, closure.fun);
+            self.line(format!("store ptr {}, ptr {}", name, code));
+            let value = self.value(format!("ptrtoint ptr {} to i64", object));
+            self.store(closure.var, &value);
+        }
+        // The closures are all allocated, so they can capture each other.
+        for closure in closures {
+            for (i, free) in closure.free.iter().enumerate() {
+                let object = self.load(closure.var);
+                let field = self.field(&object, i + 1);
+                let value = self.load(*free);
+                self.line(format!("store i64 {}, ptr {}", value, field));
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &Callee, args: &[Var], ret: Cont, handler: Cont) {
+        let result = match callee {
+            Callee::Native(name) => {
+                self.module.natives.insert(name);
+                let arg = self.load(args[0]);
+                self.value(format!("call i64 @smol_prim_{}(i64 {})", name, arg))
+            }
+            Callee::Closure(f) => {
+                let closure = self.load(*f);
+                let arg = self.load(args[0]);
+                let field = self.field(&closure, 0);
+                let code = self.value(format!("load ptr, ptr {}", field));
+                let call = format!("call tailcc i64 {}(i64 {}, i64 {})", code, closure, arg);
+                if ret == self.ret && handler == self.handler && !self.main {
+                    return self.tail_call(call);
+                }
+                self.value(call)
+            }
+            Callee::Direct(id) => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|arg| format!("i64 {}", self.load(*arg)))
+                    .collect();
+                let call = format!(
+                    "call tailcc i64 {}({})",
+                    fun_name(self.module.program, *id),
+                    args.join(", ")
+                );
+                if ret == self.ret && handler == self.handler && !self.main {
+                    return self.tail_call(call);
+                }
+                self.value(call)
+            }
+        };
+        let exn = self.value("load i64, ptr @smol_exn".to_owned());
+        let raised = self.value(format!("icmp ne i64 {}, 0", exn));
+        let (raise, ok) = (self.block(), self.block());
+        self.line(format!("br i1 {}, label %{}, label %{}", raised, raise, ok));
+        self.start(&raise);
+        if handler == self.handler {
+            // Leave the exception where it is for our caller.
+            self.pop();
+            self.line("ret i64 0".to_owned());
+        } else {
+            self.line("store i64 0, ptr @smol_exn".to_owned());
+            self.goto(handler, &[exn]);
+        }
+        self.start(&ok);
+        self.goto(ret, &[result]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape(b"a \"b\"\n\\"), "a \\22b\\22\\0A\\5C");
+    }
+
+    #[test]
+    fn tail_calls() {
+        // fun loop x = loop x, converted by hand.
+        let mut program = Program {
+            vars: Vec::new(),
+            conts: 0,
+            main: Fun {
+                name: Var(0),
+                params: Vec::new(),
+                ret: Cont(0),
+                handler: Cont(0),
+                body: Term::Jump(Cont(0), Vec::new()),
+            },
+            funs: Vec::new(),
+            globals: 0,
+            exns: BTreeMap::new(),
+        };
+        let main = program.var("main");
+        let f = program.var("loop");
+        let x = program.var("x");
+        let unit = program.var("unit");
+        let (ret, handler) = (program.cont(), program.cont());
+        let (fret, fhandler) = (program.cont(), program.cont());
+        program.funs.push(Fun {
+            name: f,
+            params: vec![f, x],
+            ret: fret,
+            handler: fhandler,
+            body: Term::Call {
+                callee: Callee::Closure(f),
+                args: vec![x],
+                ret: fret,
+                handler: fhandler,
+            },
+        });
+        program.main = Fun {
+            name: main,
+            params: Vec::new(),
+            ret,
+            handler,
+            body: Term::LetClosures(
+                vec![Closure {
+                    var: f,
+                    fun: 0,
+                    free: Vec::new(),
+                }],
+                Box::new(Term::Let(
+                    unit,
+                    Exp::Record(Vec::new()),
+                    Box::new(Term::Call {
+                        callee: Callee::Closure(f),
+                        args: vec![unit],
+                        ret,
+                        handler,
+                    }),
+                )),
+            ),
+        };
+        let module = emit(&program);
+        assert_eq!(module.matches("tail call tailcc").count(), 1, "{}", module);
+        assert_eq!(
+            module.matches("call ptr @smol_alloc").count(),
+            1,
+            "{}",
+            module
+        );
+    }
+}
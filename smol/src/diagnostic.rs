@@ -0,0 +1,144 @@
+//! Errors and warnings reported about a program.
+
+use std::fmt::{self, Display};
+
+use parsegen::{FileId, SourceMap, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A message about some span of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    /// Extra lines of explanation, printed after the source snippet.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render the diagnostic with its location and the line of source it
+    /// points at.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use parsegen::{SourceMap, Span};
+    /// use smol::diagnostic::Diagnostic;
+    ///
+    /// let mut sources = SourceMap::new();
+    /// let file = sources.add("a.sml", "val x = y");
+    /// let diag = Diagnostic::error(Span::new(file, 8, 9), "unbound variable y");
+    /// assert_eq!(
+    ///     diag.render(&sources),
+    ///     "a.sml:1:9: error: unbound variable y\n  |\n1 | val x = y\n  |         ^\n"
+    /// );
+    /// ```
+    pub fn render(&self, sources: &SourceMap) -> String {
+        if self.span.file == FileId::ANON {
+            let mut out = format!("{}: {}\n", self.severity, self.message);
+            for note in &self.notes {
+                out += &format!("  = note: {}\n", note);
+            }
+            return out;
+        }
+
+        let loc = sources.location(self.span);
+        let file = sources.file(self.span.file);
+        let mut out = format!("{}: {}: {}\n", loc, self.severity, self.message);
+
+        let line = file.src().lines().nth(loc.line - 1).unwrap_or("");
+        // Underline to the end of the span, or the end of the line if the span
+        // covers more than one.
+        let end = file.line_col(self.span.end);
+        let end_col = if end.0 == loc.line {
+            end.1
+        } else {
+            line.chars().count() + 1
+        };
+        let width = (end_col.saturating_sub(loc.col)).max(1);
+
+        let gutter = " ".repeat(loc.line.to_string().len());
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", loc.line, line);
+        out += &format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(loc.col - 1),
+            "^".repeat(width)
+        );
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", gutter, note);
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_multiline_span() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("b.sml", "fun f x =\n  x\n");
+        let diag = Diagnostic::warning(Span::new(file, 4, 13), "unused").with_note("remove it");
+        assert_eq!(
+            diag.render(&sources),
+            "b.sml:1:5: warning: unused\n  |\n1 | fun f x =\n  |     ^^^^^\n  = note: remove it\n"
+        );
+        assert_eq!(diag.to_string(), "warning: unused");
+    }
+
+    #[test]
+    fn render_without_source() {
+        let diag = Diagnostic::error(Span::new(FileId::ANON, 0, 1), "oops");
+        assert_eq!(diag.render(&SourceMap::new()), "error: oops\n");
+    }
+}
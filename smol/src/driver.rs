@@ -0,0 +1,212 @@
+//! Compiling whole programs, for `smolc`.
+//!
+//! A program is a sequence of files, compiled after the Basis as if they
+//! were all one file. Each file is parsed, has its infix expressions
+//! resolved, and is type checked and has its matches compiled before the
+//! next, so a file sees the fixities and bindings of the files before it.
+//! Then everything is lowered to IR together, optimised, and closure
+//! converted, ready for a backend. Each stage is a method of its own, so
+//! that a caller can stop after any of them.
+//!
+//! The files can come from an ML Basis file instead, which scopes them: the
+//! caller checks each file of the project in turn, and follows the steps
+//! between them with `step`. Lowering follows the same steps.
+
+use std::collections::HashMap;
+
+use parsegen::{FileId, SourceMap};
+
+use crate::ast::{Fixity, Program};
+use crate::diagnostic::Diagnostic;
+use crate::ir::{self, closure, Representation};
+use crate::matching::{self, Matches};
+use crate::mlb::{self, Project, Step};
+use crate::repl::show_modules;
+use crate::types::{self, Checker, ConKind, IdStatus, Scheme};
+use crate::{basis, fixity};
+
+/// How a program is lowered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub representation: Representation,
+    pub optimise: bool,
+    pub closures: closure::Options,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            representation: Representation::default(),
+            optimise: true,
+            closures: closure::Options::default(),
+        }
+    }
+}
+
+/// The files of a program compiled so far, after the Basis.
+pub struct Compiler {
+    pub sources: SourceMap,
+    fixity: fixity::Env,
+    checker: Checker,
+    /// The checked files, the Basis's first.
+    programs: Vec<Program>,
+    /// How the files were checked, for lowering to follow.
+    steps: Vec<Step>,
+    /// What was bound in each basis left so far.
+    bases: Vec<(HashMap<String, Fixity>, types::Basis)>,
+    matches: Matches,
+    /// Warnings about the checked files' matches.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    /// A compiler with the Basis checked.
+    ///
+    /// # Panics
+    ///
+    /// If the Basis doesn't compile, which is a bug.
+    pub fn new() -> Compiler {
+        let mut compiler = Compiler {
+            sources: SourceMap::new(),
+            fixity: fixity::Env::basis(),
+            checker: Checker::new(),
+            programs: Vec::new(),
+            steps: Vec::new(),
+            bases: Vec::new(),
+            matches: Matches::new(),
+            warnings: Vec::new(),
+        };
+        compiler.load("prim.sml", basis::PRIM);
+        let prim = compiler.checker.sigs[basis::PRIM_SIG].env.clone();
+        compiler.checker.declare_structure("Prim", prim);
+        for (name, src) in basis::FILES {
+            compiler.load(name, src);
+        }
+        compiler.warnings.clear();
+        compiler
+    }
+
+    fn load(&mut self, name: &str, src: &str) {
+        let file = self.add(&format!("<basis>/{}", name), src);
+        let checked = self
+            .parse(file)
+            .and_then(|program| self.check(program).map(|_| ()));
+        if let Err(diags) = checked {
+            let errors: String = diags.iter().map(|d| d.render(&self.sources)).collect();
+            panic!("the Basis doesn't compile:\n{}", errors);
+        }
+    }
+
+    pub fn add(&mut self, name: &str, src: &str) -> FileId {
+        self.sources.add(name, src)
+    }
+
+    /// Parse a file and resolve its infix expressions, with the fixities
+    /// declared by the files checked before it.
+    pub fn parse(&mut self, file: FileId) -> Result<Program, Vec<Diagnostic>> {
+        let mut program = crate::lower::parse(&self.sources, file)?;
+        fixity::resolve_with(&mut self.fixity, &mut program)?;
+        Ok(program)
+    }
+
+    /// Type check a parsed file and compile its matches, giving the values
+    /// it binds at top level.
+    pub fn check(&mut self, program: Program) -> Result<Vec<(String, Scheme)>, Vec<Diagnostic>> {
+        let bound = self.checker.check_program(&program)?;
+        let (matches, warnings) =
+            matching::compile_program(&program, &self.checker.info, &self.checker.tycons);
+        self.matches.extend(matches);
+        self.warnings.extend(warnings);
+        self.steps.push(Step::File(self.programs.len()));
+        self.programs.push(program);
+        Ok(bound)
+    }
+
+    /// Load an ML Basis or `.cm` file that's been added, and the files it
+    /// mentions. Its registers are numbered after those of any loaded
+    /// before, so it should be followed to the end before the next.
+    pub fn load_project(
+        &mut self,
+        file: FileId,
+        vars: &HashMap<String, String>,
+    ) -> Result<Project, Vec<Diagnostic>> {
+        let mut project = mlb::load(&mut self.sources, file, vars)?;
+        let offset = self.bases.len();
+        for step in &mut project.steps {
+            if let Step::Leave(register) | Step::Open(register) = step {
+                *register += offset;
+            }
+        }
+        Ok(project)
+    }
+
+    /// Follow a step between the bases of a project. Its files are checked
+    /// with `parse` and `check`, as any other.
+    pub fn step(&mut self, step: &Step) -> Result<(), Vec<Diagnostic>> {
+        match step {
+            Step::File(_) => panic!("a file is a step to check"),
+            Step::Enter => {
+                self.fixity.enter_basis();
+                self.checker.enter_basis();
+            }
+            Step::Leave(register) => {
+                debug_assert_eq!(*register, self.bases.len());
+                let fixities = self.fixity.leave_basis();
+                self.bases.push((fixities, self.checker.leave_basis()));
+            }
+            Step::Open(register) => {
+                let (fixities, basis) = &self.bases[*register];
+                self.fixity.open_basis(fixities);
+                self.checker.open_basis(basis);
+            }
+            Step::Alias(kind, binds) => self.checker.alias(*kind, binds)?,
+        }
+        self.steps.push(step.clone());
+        Ok(())
+    }
+
+    /// What the file just checked binds, a binding a line, as the top level
+    /// shows them.
+    pub fn show_bindings(&self, bound: &[(String, Scheme)]) -> String {
+        let program = self.programs.last().expect("a file has been checked");
+        let mut out: String = program.items.iter().map(show_modules).collect();
+        for (name, scheme) in bound {
+            let line = match self.checker.lookup_value(name).map(|b| &b.status) {
+                Some(IdStatus::Con(info)) => match info.kind {
+                    ConKind::Datatype { .. } => format!("con {} : {}\n", name, scheme),
+                    ConKind::Exn(_) => format!("exception {} : {}\n", name, scheme),
+                },
+                _ => format!("val {} : {}\n", name, scheme),
+            };
+            out += &line;
+        }
+        out
+    }
+
+    /// Lower every file checked so far, and optimise and closure convert
+    /// the IR, giving it as the backends take it.
+    pub fn lower(&self, options: Options) -> Result<ir::Program, Vec<Diagnostic>> {
+        let mut program = ir::lower_steps(
+            &self.programs,
+            &self.steps,
+            &self.checker.info,
+            &self.matches,
+            options.representation,
+        )?;
+        if options.optimise {
+            ir::opt::optimise(&mut program);
+        }
+        closure::convert_with(&mut program, options.closures);
+        Ok(program)
+    }
+
+    pub fn render(&self, diags: &[Diagnostic]) -> String {
+        diags.iter().map(|d| d.render(&self.sources)).collect()
+    }
+}
@@ -0,0 +1,1541 @@
+//! A tree-walking interpreter, used as the reference semantics for the
+//! compiled backends.
+//!
+//! A checked program is first lowered to a tree of reference-counted nodes,
+//! so that closures can hold on to their code after the program is dropped.
+//! Matches use the decision trees from `matching`, and identifiers that refer
+//! to constructors are resolved from the checker's `Info`, so constructors
+//! don't exist in the runtime environment.
+//!
+//! Calls in tail position (and the other tail positions: branches, `let`
+//! bodies, the last expression of a sequence, match rules) reuse the
+//! interpreter's loop instead of recursing, so tail recursive loops run in
+//! constant Rust stack.
+//!
+//! Exception declarations aren't generative at run time: every evaluation
+//! of `exception E` gives the same exception, the one the checker made.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::ast::{self, Const, Lab, LongId};
+use crate::matching::{Access, Decision, Matches, Step, Test};
+use crate::types::{self, ConInfo, ConKind, IdStatus, Info, TyCon};
+
+mod builtins;
+mod natives;
+
+pub use builtins::Prim;
+pub(crate) use natives::{scan_real, Input, Streams};
+pub use natives::{Native, NATIVES};
+
+// Values
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Word(u64),
+    Real(f64),
+    Char(char),
+    String(Rc<str>),
+    Bool(bool),
+    /// Records, including tuples and unit.
+    Record(Rc<BTreeMap<Lab, Value>>),
+    /// A datatype value or an exception, other than `bool` and `ref`.
+    Con(Rc<ConInfo>, Option<Rc<Value>>),
+    Ref(Rc<RefCell<Value>>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Vector(Rc<[Value]>),
+    Fn(Rc<Function>),
+}
+
+#[derive(Debug)]
+pub enum Function {
+    /// A `fn` or `fun`, with the arguments it's been given so far.
+    Closure {
+        code: Rc<Code>,
+        env: Env,
+        args: Vec<Value>,
+    },
+    /// A constructor that takes an argument.
+    Con(Rc<ConInfo>),
+    /// A record selector, e.g. `#1`.
+    Selector(Lab),
+    Prim(Prim),
+    /// A primitive of the Basis, from the structure `Prim`.
+    Native(&'static Native),
+    /// `f o g`
+    Compose(Value, Value),
+}
+
+impl Value {
+    pub fn unit() -> Value {
+        Value::Record(Rc::new(BTreeMap::new()))
+    }
+
+    pub fn tuple(values: Vec<Value>) -> Value {
+        Value::Record(Rc::new(
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| (Lab::Num(i as u32 + 1), v))
+                .collect(),
+        ))
+    }
+
+    pub fn string(s: &str) -> Value {
+        Value::String(Rc::from(s))
+    }
+
+    /// Build a list from its elements.
+    pub fn list(values: Vec<Value>) -> Value {
+        values
+            .into_iter()
+            .rev()
+            .fold(con(nil(), None), |tail, head| {
+                con(cons(), Some(Value::tuple(vec![head, tail])))
+            })
+    }
+
+    /// The elements of a list.
+    pub fn as_list(&self) -> Option<Vec<Value>> {
+        let mut items = Vec::new();
+        let mut value = self;
+        loop {
+            match value {
+                Value::Con(info, None) if is_list(info) => return Some(items),
+                Value::Con(info, Some(arg)) if is_list(info) => {
+                    let fields = arg.as_record()?;
+                    items.push(fields[&Lab::Num(1)].clone());
+                    value = &fields[&Lab::Num(2)];
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn as_record(&self) -> Option<&BTreeMap<Lab, Value>> {
+        match self {
+            Value::Record(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// The fields of a tuple, in order.
+    fn fields(&self) -> Vec<Value> {
+        self.as_record()
+            .map(|fields| fields.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Show the value, parenthesized unless it's atomic if `atomic` is set.
+    fn show(&self, atomic: bool) -> String {
+        let (s, is_atomic) = match self {
+            Value::Int(n) => (ast::Const::Int(*n).to_string(), true),
+            Value::Word(n) => (format!("0wx{:x}", n), true),
+            Value::Real(n) => (show_real(*n), true),
+            Value::Char(c) => (Const::Char(*c).to_string(), true),
+            Value::String(s) => (Const::String(s.to_string()).to_string(), true),
+            Value::Bool(b) => (b.to_string(), true),
+            Value::Record(fields) => {
+                let is_tuple = fields.len() != 1
+                    && fields
+                        .keys()
+                        .enumerate()
+                        .all(|(i, lab)| *lab == Lab::Num(i as u32 + 1));
+                let s = if is_tuple {
+                    let items: Vec<_> = fields.values().map(|v| v.show(false)).collect();
+                    format!("({})", items.join(", "))
+                } else {
+                    let rows: Vec<_> = fields
+                        .iter()
+                        .map(|(lab, v)| format!("{} = {}", lab, v.show(false)))
+                        .collect();
+                    format!("{{{}}}", rows.join(", "))
+                };
+                (s, true)
+            }
+            Value::Con(info, _) if is_list(info) => match self.as_list() {
+                Some(items) => {
+                    let items: Vec<_> = items.iter().map(|v| v.show(false)).collect();
+                    (format!("[{}]", items.join(", ")), true)
+                }
+                None => ("_".to_owned(), true),
+            },
+            Value::Con(info, None) => (info.name.clone(), true),
+            Value::Con(info, Some(arg)) => (format!("{} {}", info.name, arg.show(true)), false),
+            Value::Ref(cell) => (format!("ref {}", cell.borrow().show(true)), false),
+            Value::Array(items) => {
+                let items: Vec<_> = items.borrow().iter().map(|v| v.show(false)).collect();
+                (format!("[|{}|]", items.join(", ")), true)
+            }
+            Value::Vector(items) => {
+                let items: Vec<_> = items.iter().map(|v| v.show(false)).collect();
+                (format!("#[{}]", items.join(", ")), true)
+            }
+            Value::Fn(_) => ("fn".to_owned(), true),
+        };
+        if atomic && !is_atomic {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.show(false))
+    }
+}
+
+pub(crate) fn show_real(n: f64) -> String {
+    let s = if n.is_infinite() {
+        if n > 0.0 {
+            "inf".to_owned()
+        } else {
+            "-inf".to_owned()
+        }
+    } else if n.fract() == 0.0 && n.abs() < 1e16 {
+        format!("{:.1}", n)
+    } else {
+        n.to_string()
+    };
+    s.replace('-', "~")
+}
+
+/// Structural equality, for `=`. References and arrays are equal if they're
+/// the same reference or array.
+pub fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Word(a), Value::Word(b)) => a == b,
+        (Value::Real(a), Value::Real(b)) => a == b,
+        (Value::Char(a), Value::Char(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Record(a), Value::Record(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|((_, a), (_, b))| equal(a, b))
+        }
+        (Value::Con(a, x), Value::Con(b, y)) => {
+            same_con(a, b)
+                && match (x, y) {
+                    (Some(x), Some(y)) => equal(x, y),
+                    (None, None) => true,
+                    _ => false,
+                }
+        }
+        (Value::Ref(a), Value::Ref(b)) => Rc::ptr_eq(a, b),
+        (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+        (Value::Vector(a), Value::Vector(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+        }
+        _ => false,
+    }
+}
+
+/// Whether two constructors are the same. Datatypes are compared by tag,
+/// since typing already guarantees they're from the same datatype.
+fn same_con(a: &ConInfo, b: &ConInfo) -> bool {
+    match (&a.kind, &b.kind) {
+        (ConKind::Datatype { tag: a, .. }, ConKind::Datatype { tag: b, .. }) => a == b,
+        (ConKind::Exn(a), ConKind::Exn(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn is_list(info: &ConInfo) -> bool {
+    matches!(&info.kind, ConKind::Datatype { tycon, .. } if tycon.id == types::LIST)
+}
+
+fn builtin_con(name: &str, tycon: u32, tag: usize, has_arg: bool) -> Rc<ConInfo> {
+    Rc::new(ConInfo {
+        name: name.to_owned(),
+        kind: ConKind::Datatype {
+            tycon: TyCon {
+                id: tycon,
+                name: Rc::from(""),
+            },
+            tag,
+        },
+        has_arg,
+    })
+}
+
+thread_local! {
+    static NIL: Rc<ConInfo> = builtin_con("nil", types::LIST, 0, false);
+    static CONS: Rc<ConInfo> = builtin_con("::", types::LIST, 1, true);
+    static NONE: Rc<ConInfo> = builtin_con("NONE", types::OPTION, 0, false);
+    static SOME: Rc<ConInfo> = builtin_con("SOME", types::OPTION, 1, true);
+}
+
+fn nil() -> Rc<ConInfo> {
+    NIL.with(Rc::clone)
+}
+
+fn cons() -> Rc<ConInfo> {
+    CONS.with(Rc::clone)
+}
+
+/// An option value.
+fn option(value: Option<Value>) -> Value {
+    match value {
+        Some(value) => con(SOME.with(Rc::clone), Some(value)),
+        None => con(NONE.with(Rc::clone), None),
+    }
+}
+
+/// Apply a constructor. `bool` and `ref` have their own representations.
+fn con(info: Rc<ConInfo>, arg: Option<Value>) -> Value {
+    match (&info.kind, arg) {
+        (ConKind::Datatype { tycon, tag }, None) if tycon.id == types::BOOL => {
+            Value::Bool(*tag == 1)
+        }
+        (ConKind::Datatype { tycon, .. }, Some(arg)) if tycon.id == types::REF => {
+            Value::Ref(Rc::new(RefCell::new(arg)))
+        }
+        (_, arg) => Value::Con(info, arg.map(Rc::new)),
+    }
+}
+
+/// A raised exception.
+#[derive(Debug, Clone)]
+pub struct Raise(pub Value);
+
+impl Raise {
+    /// Raise one of the built in exceptions.
+    pub fn builtin(name: &str) -> Raise {
+        Raise(Value::Con(builtin_exn(name), None))
+    }
+
+    pub fn fail(message: &str) -> Raise {
+        Raise(Value::Con(
+            builtin_exn("Fail"),
+            Some(Rc::new(Value::string(message))),
+        ))
+    }
+}
+
+impl Display for Raise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "uncaught exception {}", self.0)
+    }
+}
+
+impl std::error::Error for Raise {}
+
+/// An int, or `Overflow` if there isn't one or it doesn't fit in 63 bits.
+fn checked_int(n: Option<i64>) -> Result<Value, Raise> {
+    match n {
+        Some(n) if (ast::MIN_INT..=ast::MAX_INT).contains(&n) => Ok(Value::Int(n)),
+        _ => Err(Raise::builtin("Overflow")),
+    }
+}
+
+fn builtin_exn(name: &str) -> Rc<ConInfo> {
+    let id = types::BUILTIN_EXNS
+        .iter()
+        .position(|exn| *exn == name)
+        .unwrap_or_else(|| panic!("no built in exception `{}`", name));
+    Rc::new(ConInfo {
+        name: name.to_owned(),
+        kind: ConKind::Exn(id as u32),
+        has_arg: name == "Fail",
+    })
+}
+
+// Environments
+
+/// The bindings of a structure, or of one scope.
+#[derive(Debug, Default)]
+pub struct Bindings {
+    pub values: HashMap<String, Value>,
+    pub structures: HashMap<String, Rc<Bindings>>,
+    pub functors: HashMap<String, Rc<Functor>>,
+}
+
+impl Bindings {
+    fn extend(&mut self, other: &Bindings) {
+        self.values
+            .extend(other.values.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.structures
+            .extend(other.structures.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.functors
+            .extend(other.functors.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Only the components a signature has.
+    fn restrict(&self, shape: &Shape) -> Bindings {
+        Bindings {
+            values: shape
+                .values
+                .iter()
+                .filter_map(|name| Some((name.clone(), self.values.get(name)?.clone())))
+                .collect(),
+            structures: shape
+                .structures
+                .iter()
+                .filter_map(|(name, shape)| {
+                    let str = self.structures.get(name)?;
+                    Some((name.clone(), Rc::new(str.restrict(shape))))
+                })
+                .collect(),
+            functors: HashMap::new(),
+        }
+    }
+}
+
+/// A chain of scopes. Each declaration adds a scope, so closures see the
+/// bindings as they were when they were made.
+#[derive(Debug, Clone, Default)]
+pub struct Env(Option<Rc<Frame>>);
+
+#[derive(Debug)]
+struct Frame {
+    /// Mutable so that recursive functions can be added to the scope their
+    /// closures hold.
+    bindings: RefCell<Bindings>,
+    parent: Env,
+}
+
+impl Env {
+    fn push(&self, bindings: Bindings) -> Env {
+        Env(Some(Rc::new(Frame {
+            bindings: RefCell::new(bindings),
+            parent: self.clone(),
+        })))
+    }
+
+    fn frames(&self) -> impl Iterator<Item = &Frame> {
+        std::iter::successors(self.0.as_deref(), |frame| frame.parent.0.as_deref())
+    }
+
+    fn is(&self, other: &Env) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    /// Everything bound in the scopes from `base` to here.
+    fn since(&self, base: &Env) -> Bindings {
+        let mut frames = Vec::new();
+        let mut env = self;
+        while !env.is(base) {
+            match &env.0 {
+                Some(frame) => {
+                    frames.push(frame);
+                    env = &frame.parent;
+                }
+                None => break,
+            }
+        }
+        let mut bindings = Bindings::default();
+        for frame in frames.iter().rev() {
+            bindings.extend(&frame.bindings.borrow());
+        }
+        bindings
+    }
+
+    pub fn lookup_value(&self, name: &str) -> Option<Value> {
+        self.frames()
+            .find_map(|frame| frame.bindings.borrow().values.get(name).cloned())
+    }
+
+    fn lookup_structure(&self, path: &[String]) -> Option<Rc<Bindings>> {
+        let (first, rest) = path.split_first()?;
+        let mut str = self
+            .frames()
+            .find_map(|frame| frame.bindings.borrow().structures.get(first).cloned())?;
+        for strid in rest {
+            let next = str.structures.get(strid)?.clone();
+            str = next;
+        }
+        Some(str)
+    }
+
+    fn lookup_long_value(&self, id: &LongId) -> Option<Value> {
+        if id.path.is_empty() {
+            self.lookup_value(&id.name)
+        } else {
+            self.lookup_structure(&id.path)?
+                .values
+                .get(&id.name)
+                .cloned()
+        }
+    }
+
+    fn lookup_functor(&self, name: &str) -> Option<Rc<Functor>> {
+        self.frames()
+            .find_map(|frame| frame.bindings.borrow().functors.get(name).cloned())
+    }
+}
+
+// Code
+
+/// A compiled match and the rule bodies it chooses between. Functions with
+/// several curried arguments match them all at once.
+#[derive(Debug)]
+pub struct Code {
+    arity: usize,
+    tree: Decision,
+    bodies: Vec<Rc<Expr>>,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Value(Value),
+    Var(LongId),
+    Record(Vec<(Lab, Rc<Expr>)>),
+    List(Vec<Rc<Expr>>),
+    Seq(Vec<Rc<Expr>>),
+    Let(Vec<Dec>, Rc<Expr>),
+    App(Rc<Expr>, Rc<Expr>),
+    Andalso(Rc<Expr>, Rc<Expr>),
+    Orelse(Rc<Expr>, Rc<Expr>),
+    If(Rc<Expr>, Rc<Expr>, Rc<Expr>),
+    While(Rc<Expr>, Rc<Expr>),
+    Raise(Rc<Expr>),
+    Handle(Rc<Expr>, Rc<Code>),
+    Case(Rc<Expr>, Rc<Code>),
+    Fn(Rc<Code>),
+}
+
+#[derive(Debug)]
+enum Dec {
+    /// `val pat = exp`, where the code binds the pattern's variables.
+    Val(Rc<Code>, Rc<Expr>),
+    /// `val rec` and `fun`, which bind functions that can see each other.
+    Rec(Vec<(String, Rc<Code>)>),
+    Local(Vec<Dec>, Vec<Dec>),
+    Open(Vec<LongId>),
+    Structure(Vec<(String, StrExpr)>),
+    Functor(Vec<(String, Rc<FunctorCode>)>),
+}
+
+#[derive(Debug)]
+enum StrExpr {
+    Struct(Vec<Dec>),
+    Var(LongId),
+    /// Ascription, which hides components that aren't in the signature.
+    Restrict(Box<StrExpr>, Rc<Shape>),
+    App(String, Box<StrExpr>),
+    Let(Vec<Dec>, Box<StrExpr>),
+}
+
+/// The components a signature lets through.
+#[derive(Debug, Default)]
+struct Shape {
+    values: Vec<String>,
+    structures: Vec<(String, Shape)>,
+}
+
+impl Shape {
+    fn new(env: &types::Env) -> Shape {
+        Shape {
+            values: env
+                .values
+                .iter()
+                .filter(|(_, binding)| matches!(binding.status, IdStatus::Var))
+                .map(|(name, _)| name.clone())
+                .collect(),
+            structures: env
+                .structures
+                .iter()
+                .map(|(name, env)| (name.clone(), Shape::new(env)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FunctorCode {
+    /// The parameter's name, or `None` if its specifications are opened in
+    /// the body.
+    param: Option<String>,
+    param_shape: Rc<Shape>,
+    body: StrExpr,
+}
+
+#[derive(Debug)]
+pub struct Functor {
+    code: Rc<FunctorCode>,
+    env: Env,
+}
+
+/// Lowers a checked program to code.
+struct Lowerer<'a> {
+    info: &'a Info,
+    matches: &'a Matches,
+}
+
+impl<'a> Lowerer<'a> {
+    fn code(&self, span: parsegen::Span, arity: usize, bodies: Vec<Rc<Expr>>) -> Rc<Code> {
+        let m = self
+            .matches
+            .get(&span)
+            .expect("matches should be compiled before evaluating");
+        Rc::new(Code {
+            arity,
+            tree: m.tree.clone(),
+            bodies,
+        })
+    }
+
+    fn top_dec(&self, dec: &ast::TopDec) -> Vec<Dec> {
+        match dec {
+            ast::TopDec::Str(dec) => self.str_dec(dec),
+            ast::TopDec::Sig(_) => Vec::new(),
+            ast::TopDec::Functor(binds) => vec![Dec::Functor(
+                binds
+                    .iter()
+                    .map(|bind| {
+                        let mut body = self.str_exp(&bind.body);
+                        if let Some(asc) = &bind.sig {
+                            body = StrExpr::Restrict(Box::new(body), self.shape(&asc.sig));
+                        }
+                        let code = FunctorCode {
+                            param: bind.param.as_ref().map(|id| id.name.clone()),
+                            param_shape: self.shape(&bind.param_sig),
+                            body,
+                        };
+                        (bind.id.name.clone(), Rc::new(code))
+                    })
+                    .collect(),
+            )],
+            ast::TopDec::Exp(exp) => {
+                // `exp` is short for `val it = exp`.
+                let code = Rc::new(Code {
+                    arity: 1,
+                    tree: Decision::Leaf {
+                        rule: 0,
+                        bindings: vec![("it".to_owned(), Access::root(0))],
+                    },
+                    bodies: Vec::new(),
+                });
+                vec![Dec::Val(code, self.exp(exp))]
+            }
+        }
+    }
+
+    fn str_dec(&self, dec: &ast::StrDec) -> Vec<Dec> {
+        match &dec.kind {
+            ast::StrDecKind::Dec(dec) => self.dec(dec),
+            ast::StrDecKind::Structure(binds) => vec![Dec::Structure(
+                binds
+                    .iter()
+                    .map(|bind| {
+                        let mut str = self.str_exp(&bind.str);
+                        if let Some(asc) = &bind.sig {
+                            str = StrExpr::Restrict(Box::new(str), self.shape(&asc.sig));
+                        }
+                        (bind.id.name.clone(), str)
+                    })
+                    .collect(),
+            )],
+            ast::StrDecKind::Local(local, body) => vec![Dec::Local(
+                local.iter().flat_map(|dec| self.str_dec(dec)).collect(),
+                body.iter().flat_map(|dec| self.str_dec(dec)).collect(),
+            )],
+        }
+    }
+
+    fn shape(&self, sig: &ast::SigExp) -> Rc<Shape> {
+        Rc::new(
+            self.info
+                .sigs
+                .get(&sig.span)
+                .map(Shape::new)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn str_exp(&self, exp: &ast::StrExp) -> StrExpr {
+        match &exp.kind {
+            ast::StrExpKind::Struct(decs) => {
+                StrExpr::Struct(decs.iter().flat_map(|dec| self.str_dec(dec)).collect())
+            }
+            ast::StrExpKind::Var(id) => StrExpr::Var(id.clone()),
+            ast::StrExpKind::Ascribe(exp, asc) => {
+                StrExpr::Restrict(Box::new(self.str_exp(exp)), self.shape(&asc.sig))
+            }
+            ast::StrExpKind::App(id, arg) => {
+                StrExpr::App(id.name.clone(), Box::new(self.str_exp(arg)))
+            }
+            ast::StrExpKind::Let(decs, exp) => StrExpr::Let(
+                decs.iter().flat_map(|dec| self.str_dec(dec)).collect(),
+                Box::new(self.str_exp(exp)),
+            ),
+        }
+    }
+
+    fn decs(&self, decs: &[ast::Dec]) -> Vec<Dec> {
+        decs.iter().flat_map(|dec| self.dec(dec)).collect()
+    }
+
+    fn dec(&self, dec: &ast::Dec) -> Vec<Dec> {
+        match &dec.kind {
+            ast::DecKind::Val { binds, .. } => {
+                let mut decs = Vec::new();
+                let mut rec = Vec::new();
+                for bind in binds {
+                    if bind.rec {
+                        rec.push((rec_name(&bind.pat), self.fn_code(&bind.exp)));
+                    } else {
+                        let code = self.code(bind.span, 1, Vec::new());
+                        decs.push(Dec::Val(code, self.exp(&bind.exp)));
+                    }
+                }
+                if !rec.is_empty() {
+                    decs.push(Dec::Rec(rec));
+                }
+                decs
+            }
+            ast::DecKind::Fun { binds, .. } => vec![Dec::Rec(
+                binds
+                    .iter()
+                    .map(|bind| {
+                        let first = &bind.clauses[0];
+                        let name = first
+                            .name
+                            .as_ref()
+                            .expect("fixity should be resolved before evaluating");
+                        let bodies = bind
+                            .clauses
+                            .iter()
+                            .map(|clause| self.exp(&clause.body))
+                            .collect();
+                        (
+                            name.name.clone(),
+                            self.code(bind.span, first.args.len(), bodies),
+                        )
+                    })
+                    .collect(),
+            )],
+            ast::DecKind::Abstype { body, .. } => self.decs(body),
+            ast::DecKind::Local(local, body) => {
+                vec![Dec::Local(self.decs(local), self.decs(body))]
+            }
+            ast::DecKind::Open(ids) => vec![Dec::Open(ids.clone())],
+            ast::DecKind::Type(_)
+            | ast::DecKind::Datatype { .. }
+            | ast::DecKind::DatatypeRepl(_, _)
+            | ast::DecKind::Exception(_)
+            | ast::DecKind::Fixity(_, _) => Vec::new(),
+        }
+    }
+
+    /// The code of a `val rec` binding, which must be a `fn`.
+    fn fn_code(&self, exp: &ast::Exp) -> Rc<Code> {
+        match &exp.kind {
+            ast::ExpKind::Fn(rules) => self.rules(exp.span, rules),
+            ast::ExpKind::Typed(exp, _) => self.fn_code(exp),
+            _ => panic!("`val rec` should bind a `fn`"),
+        }
+    }
+
+    fn rules(&self, span: parsegen::Span, rules: &[ast::MRule]) -> Rc<Code> {
+        let bodies = rules.iter().map(|rule| self.exp(&rule.exp)).collect();
+        self.code(span, 1, bodies)
+    }
+
+    fn exps(&self, exps: &[ast::Exp]) -> Vec<Rc<Expr>> {
+        exps.iter().map(|exp| self.exp(exp)).collect()
+    }
+
+    fn exp(&self, exp: &ast::Exp) -> Rc<Expr> {
+        use ast::ExpKind;
+
+        let expr = match &exp.kind {
+            ExpKind::Const(c) => Expr::Value(match c {
+                Const::Int(n) => Value::Int(*n),
+                Const::Word(n) => Value::Word(*n),
+                Const::Real(n) => Value::Real(*n),
+                Const::Char(c) => Value::Char(*c),
+                Const::String(s) => Value::string(s),
+            }),
+            ExpKind::Var { id, .. } => match self.info.cons.get(&id.span) {
+                Some(info) if info.has_arg => {
+                    Expr::Value(Value::Fn(Rc::new(Function::Con(info.clone()))))
+                }
+                Some(info) => Expr::Value(con(info.clone(), None)),
+                None => Expr::Var(id.clone()),
+            },
+            ExpKind::Selector(lab) => {
+                Expr::Value(Value::Fn(Rc::new(Function::Selector(lab.clone()))))
+            }
+            ExpKind::Record(rows) => Expr::Record(
+                rows.iter()
+                    .map(|(lab, exp)| (lab.clone(), self.exp(exp)))
+                    .collect(),
+            ),
+            ExpKind::Tuple(exps) => Expr::Record(
+                exps.iter()
+                    .enumerate()
+                    .map(|(i, exp)| (Lab::Num(i as u32 + 1), self.exp(exp)))
+                    .collect(),
+            ),
+            ExpKind::List(exps) => Expr::List(self.exps(exps)),
+            ExpKind::Seq(exps) => Expr::Seq(self.exps(exps)),
+            ExpKind::Let(decs, body) => Expr::Let(self.decs(decs), self.exp(body)),
+            ExpKind::Flat(_) => panic!("fixity should be resolved before evaluating"),
+            ExpKind::App(f, arg) => Expr::App(self.exp(f), self.exp(arg)),
+            ExpKind::Typed(exp, _) => return self.exp(exp),
+            ExpKind::Andalso(a, b) => Expr::Andalso(self.exp(a), self.exp(b)),
+            ExpKind::Orelse(a, b) => Expr::Orelse(self.exp(a), self.exp(b)),
+            ExpKind::Handle(body, rules) => {
+                Expr::Handle(self.exp(body), self.rules(exp.span, rules))
+            }
+            ExpKind::Raise(exp) => Expr::Raise(self.exp(exp)),
+            ExpKind::If(c, a, b) => Expr::If(self.exp(c), self.exp(a), self.exp(b)),
+            ExpKind::While(c, body) => Expr::While(self.exp(c), self.exp(body)),
+            ExpKind::Case(scrutinee, rules) => {
+                Expr::Case(self.exp(scrutinee), self.rules(exp.span, rules))
+            }
+            ExpKind::Fn(rules) => Expr::Fn(self.rules(exp.span, rules)),
+        };
+        Rc::new(expr)
+    }
+}
+
+/// The name a `val rec` binding binds.
+fn rec_name(pat: &ast::Pat) -> String {
+    match &pat.kind {
+        ast::PatKind::Var { id, .. } => id.name.clone(),
+        ast::PatKind::Typed(pat, _) => rec_name(pat),
+        _ => panic!("`val rec` should bind a variable"),
+    }
+}
+
+// Evaluation
+
+/// What's left to do after applying a function: either it's done, or its
+/// body is evaluated in place of the call.
+enum Tail {
+    Value(Value),
+    Eval(Rc<Expr>, Env),
+}
+
+/// An interpreter, holding the environment of everything evaluated so far.
+pub struct Interpreter {
+    pub env: Env,
+    /// Open streams, for `print` and `TextIO`.
+    streams: Streams,
+    /// How deeply evaluation may nest before raising `Overflow`.
+    max_depth: usize,
+    /// How deeply evaluation is nested now.
+    depth: usize,
+}
+
+/// The most Rust stack a level of nested evaluation takes, with room to
+/// spare: levels take up to about 8 KiB in unoptimised builds, and 1.5 KiB
+/// in optimised ones.
+const LEVEL_BYTES: usize = if cfg!(debug_assertions) {
+    12 << 10
+} else {
+    3 << 10
+};
+
+/// The stack of the thread running the interpreter, unless it's told
+/// otherwise: what Rust gives new threads.
+const DEFAULT_STACK_SIZE: usize = 2 << 20;
+
+/// How deeply evaluation can nest on a thread with a stack of `bytes`,
+/// leaving an eighth of it for what runs the interpreter.
+fn max_depth(bytes: usize) -> usize {
+    (bytes - bytes / 8) / LEVEL_BYTES
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the built in values, printing to stdout.
+    pub fn new() -> Self {
+        Interpreter::with_output(Box::new(io::stdout()))
+    }
+
+    /// An interpreter whose standard output goes to `out`.
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let mut bindings = Bindings::default();
+        for prim in Prim::ALL {
+            bindings.values.insert(
+                prim.name().to_owned(),
+                Value::Fn(Rc::new(Function::Prim(*prim))),
+            );
+        }
+        let natives = Bindings {
+            values: NATIVES
+                .iter()
+                .map(|native| {
+                    let value = Value::Fn(Rc::new(Function::Native(native)));
+                    (native.name.to_owned(), value)
+                })
+                .collect(),
+            ..Bindings::default()
+        };
+        bindings
+            .structures
+            .insert("Prim".to_owned(), Rc::new(natives));
+        Interpreter {
+            env: Env::default().push(bindings),
+            streams: Streams::new(out),
+            max_depth: max_depth(DEFAULT_STACK_SIZE),
+            depth: 0,
+        }
+    }
+
+    /// Say how big the stack of the thread running the interpreter is, in
+    /// bytes. Non-tail calls nested deeper than fits in most of it raise
+    /// `Overflow`.
+    pub fn set_stack_size(&mut self, bytes: usize) {
+        self.max_depth = max_depth(bytes);
+    }
+
+    /// Run a checked program whose matches have been compiled, adding its
+    /// declarations to the environment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use parsegen::SourceMap;
+    /// use smol::eval::Interpreter;
+    ///
+    /// let mut sources = SourceMap::new();
+    /// let file = sources.add("a.sml", "fun fact 0 = 1 | fact n = n * fact (n - 1) val x = fact 10");
+    /// let mut program = smol::lower::parse(&sources, file).unwrap();
+    /// smol::fixity::resolve(&mut program).unwrap();
+    /// let mut checker = smol::types::Checker::new();
+    /// checker.check_program(&program).unwrap();
+    /// let (matches, _) = smol::matching::compile_program(&program, &checker.info, &checker.tycons);
+    ///
+    /// let mut interp = Interpreter::new();
+    /// interp.run(&program, &checker.info, &matches).unwrap();
+    /// assert_eq!(interp.env.lookup_value("x").unwrap().to_string(), "3628800");
+    /// ```
+    pub fn run(
+        &mut self,
+        program: &ast::Program,
+        info: &Info,
+        matches: &Matches,
+    ) -> Result<(), Raise> {
+        let lowerer = Lowerer { info, matches };
+        let base = self.env.clone();
+        for item in &program.items {
+            let decs = lowerer.top_dec(item);
+            let env = self.env.clone();
+            self.env = self.decs(&decs, &env)?;
+        }
+        // Each declaration adds a frame, so keep lookups in later programs
+        // short by merging this program's frames into one.
+        self.env = base.push(self.env.since(&base));
+        Ok(())
+    }
+
+    fn decs(&mut self, decs: &[Dec], env: &Env) -> Result<Env, Raise> {
+        let mut env = env.clone();
+        for dec in decs {
+            env = self.dec(dec, &env)?;
+        }
+        Ok(env)
+    }
+
+    fn dec(&mut self, dec: &Dec, env: &Env) -> Result<Env, Raise> {
+        let bindings = match dec {
+            Dec::Val(code, exp) => {
+                let value = self.eval(exp, env)?;
+                match select(&code.tree, &[value]) {
+                    Some((_, values)) => Bindings {
+                        values: values.into_iter().collect(),
+                        ..Bindings::default()
+                    },
+                    None => return Err(Raise::builtin("Bind")),
+                }
+            }
+            Dec::Rec(fns) => {
+                let env = env.push(Bindings::default());
+                let values = fns
+                    .iter()
+                    .map(|(name, code)| {
+                        let closure = Function::Closure {
+                            code: code.clone(),
+                            env: env.clone(),
+                            args: Vec::new(),
+                        };
+                        (name.clone(), Value::Fn(Rc::new(closure)))
+                    })
+                    .collect();
+                if let Some(frame) = &env.0 {
+                    frame.bindings.borrow_mut().values = values;
+                }
+                return Ok(env);
+            }
+            Dec::Local(local, body) => {
+                let inner = self.decs(local, env)?;
+                let after = self.decs(body, &inner)?;
+                after.since(&inner)
+            }
+            Dec::Open(ids) => {
+                let mut bindings = Bindings::default();
+                for id in ids {
+                    let mut path = id.path.clone();
+                    path.push(id.name.clone());
+                    let str = env
+                        .lookup_structure(&path)
+                        .unwrap_or_else(|| panic!("unbound structure `{}`", id));
+                    bindings.extend(&str);
+                }
+                bindings
+            }
+            Dec::Structure(binds) => {
+                let mut bindings = Bindings::default();
+                for (name, exp) in binds {
+                    let str = self.str_expr(exp, env)?;
+                    bindings.structures.insert(name.clone(), str);
+                }
+                bindings
+            }
+            Dec::Functor(binds) => Bindings {
+                functors: binds
+                    .iter()
+                    .map(|(name, code)| {
+                        let functor = Functor {
+                            code: code.clone(),
+                            env: env.clone(),
+                        };
+                        (name.clone(), Rc::new(functor))
+                    })
+                    .collect(),
+                ..Bindings::default()
+            },
+        };
+        Ok(env.push(bindings))
+    }
+
+    fn str_expr(&mut self, exp: &StrExpr, env: &Env) -> Result<Rc<Bindings>, Raise> {
+        match exp {
+            StrExpr::Struct(decs) => {
+                let inner = self.decs(decs, env)?;
+                Ok(Rc::new(inner.since(env)))
+            }
+            StrExpr::Var(id) => {
+                let mut path = id.path.clone();
+                path.push(id.name.clone());
+                Ok(env
+                    .lookup_structure(&path)
+                    .unwrap_or_else(|| panic!("unbound structure `{}`", id)))
+            }
+            StrExpr::Restrict(exp, shape) => {
+                let str = self.str_expr(exp, env)?;
+                Ok(Rc::new(str.restrict(shape)))
+            }
+            StrExpr::App(name, arg) => {
+                let functor = env
+                    .lookup_functor(name)
+                    .unwrap_or_else(|| panic!("unbound functor `{}`", name));
+                let arg = self.str_expr(arg, env)?.restrict(&functor.code.param_shape);
+                let bindings = match &functor.code.param {
+                    Some(param) => {
+                        let mut bindings = Bindings::default();
+                        bindings.structures.insert(param.clone(), Rc::new(arg));
+                        bindings
+                    }
+                    None => arg,
+                };
+                let env = functor.env.push(bindings);
+                self.str_expr(&functor.code.body, &env)
+            }
+            StrExpr::Let(decs, exp) => {
+                let env = self.decs(decs, env)?;
+                self.str_expr(exp, &env)
+            }
+        }
+    }
+
+    /// Evaluate an expression, raising `Overflow` if it's nested too deeply
+    /// for the stack.
+    fn eval(&mut self, exp: &Rc<Expr>, env: &Env) -> Result<Value, Raise> {
+        if self.depth >= self.max_depth {
+            return Err(Raise::builtin("Overflow"));
+        }
+        self.depth += 1;
+        let result = self.eval_loop(exp, env);
+        self.depth -= 1;
+        result
+    }
+
+    /// Evaluate an expression. Expressions in tail position are evaluated
+    /// by the loop rather than recursively.
+    fn eval_loop(&mut self, exp: &Rc<Expr>, env: &Env) -> Result<Value, Raise> {
+        let mut exp = exp.clone();
+        let mut env = env.clone();
+        loop {
+            let (next, next_env) = match &*exp {
+                Expr::Value(value) => return Ok(value.clone()),
+                Expr::Var(id) => {
+                    return Ok(env
+                        .lookup_long_value(id)
+                        .unwrap_or_else(|| panic!("unbound variable `{}`", id)))
+                }
+                Expr::Record(rows) => {
+                    let mut fields = BTreeMap::new();
+                    for (lab, exp) in rows {
+                        fields.insert(lab.clone(), self.eval(exp, &env)?);
+                    }
+                    return Ok(Value::Record(Rc::new(fields)));
+                }
+                Expr::List(exps) => {
+                    let mut values = Vec::with_capacity(exps.len());
+                    for exp in exps {
+                        values.push(self.eval(exp, &env)?);
+                    }
+                    return Ok(Value::list(values));
+                }
+                Expr::Seq(exps) => {
+                    let (last, init) = exps.split_last().expect("empty sequence");
+                    for exp in init {
+                        self.eval(exp, &env)?;
+                    }
+                    (last.clone(), env)
+                }
+                Expr::Let(decs, body) => {
+                    let inner = self.decs(decs, &env)?;
+                    (body.clone(), inner)
+                }
+                Expr::App(f, arg) => {
+                    let f = self.eval(f, &env)?;
+                    let arg = self.eval(arg, &env)?;
+                    match self.call(&f, arg)? {
+                        Tail::Value(value) => return Ok(value),
+                        Tail::Eval(body, body_env) => (body, body_env),
+                    }
+                }
+                Expr::Andalso(a, b) => {
+                    if !self.eval_bool(a, &env)? {
+                        return Ok(Value::Bool(false));
+                    }
+                    (b.clone(), env)
+                }
+                Expr::Orelse(a, b) => {
+                    if self.eval_bool(a, &env)? {
+                        return Ok(Value::Bool(true));
+                    }
+                    (b.clone(), env)
+                }
+                Expr::If(c, a, b) => {
+                    let next = if self.eval_bool(c, &env)? { a } else { b };
+                    (next.clone(), env)
+                }
+                Expr::While(c, body) => {
+                    while self.eval_bool(c, &env)? {
+                        self.eval(body, &env)?;
+                    }
+                    return Ok(Value::unit());
+                }
+                Expr::Raise(exp) => return Err(Raise(self.eval(exp, &env)?)),
+                Expr::Handle(body, code) => match self.eval(body, &env) {
+                    Ok(value) => return Ok(value),
+                    Err(Raise(exn)) => match select(&code.tree, std::slice::from_ref(&exn)) {
+                        Some((rule, values)) => {
+                            let env = env.push(Bindings {
+                                values: values.into_iter().collect(),
+                                ..Bindings::default()
+                            });
+                            (code.bodies[rule].clone(), env)
+                        }
+                        None => return Err(Raise(exn)),
+                    },
+                },
+                Expr::Case(scrutinee, code) => {
+                    let value = self.eval(scrutinee, &env)?;
+                    match select(&code.tree, &[value]) {
+                        Some((rule, values)) => {
+                            let env = env.push(Bindings {
+                                values: values.into_iter().collect(),
+                                ..Bindings::default()
+                            });
+                            (code.bodies[rule].clone(), env)
+                        }
+                        None => return Err(Raise::builtin("Match")),
+                    }
+                }
+                Expr::Fn(code) => {
+                    let closure = Function::Closure {
+                        code: code.clone(),
+                        env: env.clone(),
+                        args: Vec::new(),
+                    };
+                    return Ok(Value::Fn(Rc::new(closure)));
+                }
+            };
+            exp = next;
+            env = next_env;
+        }
+    }
+
+    fn eval_bool(&mut self, exp: &Rc<Expr>, env: &Env) -> Result<bool, Raise> {
+        match self.eval(exp, env)? {
+            Value::Bool(b) => Ok(b),
+            value => panic!("expected a bool, found {}", value),
+        }
+    }
+
+    /// Apply a function to a value.
+    pub fn apply(&mut self, f: &Value, arg: Value) -> Result<Value, Raise> {
+        match self.call(f, arg)? {
+            Tail::Value(value) => Ok(value),
+            Tail::Eval(body, env) => self.eval(&body, &env),
+        }
+    }
+
+    fn call(&mut self, f: &Value, arg: Value) -> Result<Tail, Raise> {
+        let f = match f {
+            Value::Fn(f) => f,
+            value => panic!("expected a function, found {}", value),
+        };
+        let value = match &**f {
+            Function::Closure { code, env, args } => {
+                if args.len() + 1 < code.arity {
+                    let mut args = args.clone();
+                    args.push(arg);
+                    let closure = Function::Closure {
+                        code: code.clone(),
+                        env: env.clone(),
+                        args,
+                    };
+                    return Ok(Tail::Value(Value::Fn(Rc::new(closure))));
+                }
+                let mut args = args.clone();
+                args.push(arg);
+                return match select(&code.tree, &args) {
+                    Some((rule, values)) => {
+                        let env = env.push(Bindings {
+                            values: values.into_iter().collect(),
+                            ..Bindings::default()
+                        });
+                        Ok(Tail::Eval(code.bodies[rule].clone(), env))
+                    }
+                    None => Err(Raise::builtin("Match")),
+                };
+            }
+            Function::Con(info) => con(info.clone(), Some(arg)),
+            Function::Selector(lab) => match &arg {
+                Value::Record(fields) => fields[lab].clone(),
+                value => panic!("expected a record, found {}", value),
+            },
+            Function::Prim(prim) => prim.apply(self, arg)?,
+            Function::Native(native) => (native.apply)(self, arg)?,
+            Function::Compose(f, g) => {
+                let x = self.apply(g, arg)?;
+                return self.call(f, x);
+            }
+        };
+        Ok(Tail::Value(value))
+    }
+}
+
+// Matching
+
+/// Run a decision tree on the values being matched, returning the rule that
+/// matches and its variables' values.
+fn select(tree: &Decision, roots: &[Value]) -> Option<(usize, Vec<(String, Value)>)> {
+    let mut tree = tree;
+    loop {
+        match tree {
+            Decision::Fail => return None,
+            Decision::Leaf { rule, bindings } => {
+                let values = bindings
+                    .iter()
+                    .map(|(name, access)| (name.clone(), access_value(roots, access)))
+                    .collect();
+                return Some((*rule, values));
+            }
+            Decision::Switch {
+                access,
+                cases,
+                default,
+            } => {
+                let value = access_value(roots, access);
+                match cases.iter().find(|(test, _)| test_value(test, &value)) {
+                    Some((_, next)) => tree = next,
+                    None => tree = default.as_ref()?,
+                }
+            }
+        }
+    }
+}
+
+fn access_value(roots: &[Value], access: &Access) -> Value {
+    let mut value = roots[access.root].clone();
+    for step in &access.path {
+        value = match (step, &value) {
+            (Step::Field(lab, _), Value::Record(fields)) => fields[lab].clone(),
+            (Step::ConArg, Value::Con(_, Some(arg))) => (**arg).clone(),
+            (Step::ConArg, Value::Ref(cell)) => cell.borrow().clone(),
+            (step, value) => panic!("can't take {:?} of {}", step, value),
+        };
+    }
+    value
+}
+
+fn test_value(test: &Test, value: &Value) -> bool {
+    match (test, value) {
+        (Test::Con(info), Value::Con(con, _)) => same_con(info, con),
+        (Test::Con(info), Value::Bool(b)) => {
+            matches!(info.kind, ConKind::Datatype { tag, .. } if tag == *b as usize)
+        }
+        (Test::Con(_), Value::Ref(_)) => true,
+        (Test::Const(Const::Int(a)), Value::Int(b)) => a == b,
+        (Test::Const(Const::Word(a)), Value::Word(b)) => a == b,
+        (Test::Const(Const::Char(a)), Value::Char(b)) => a == b,
+        (Test::Const(Const::String(a)), Value::String(b)) => **a == **b,
+        (Test::Const(Const::Real(a)), Value::Real(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parsegen::SourceMap;
+
+    /// Output written by a program, shared with the test.
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run a program, returning the values of `names` and what it printed,
+    /// or the uncaught exception.
+    fn run(src: &str, names: &[&str]) -> Result<(Vec<String>, String), String> {
+        run_with(src, names, DEFAULT_STACK_SIZE)
+    }
+
+    /// Run a program like `run`, on a thread with a stack of `stack_size`.
+    fn run_with(
+        src: &str,
+        names: &[&str],
+        stack_size: usize,
+    ) -> Result<(Vec<String>, String), String> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        crate::fixity::resolve(&mut program).unwrap();
+        let mut checker = types::Checker::new();
+        checker.check_program(&program).unwrap();
+        let (matches, _) =
+            crate::matching::compile_program(&program, &checker.info, &checker.tycons);
+        let out = Capture::default();
+        let mut interp = Interpreter::with_output(Box::new(out.clone()));
+        interp.set_stack_size(stack_size);
+        interp
+            .run(&program, &checker.info, &matches)
+            .map_err(|raise| raise.to_string())?;
+        let values = names
+            .iter()
+            .map(|name| interp.env.lookup_value(name).unwrap().to_string())
+            .collect();
+        let printed = String::from_utf8(out.0.borrow().clone()).unwrap();
+        Ok((values, printed))
+    }
+
+    fn values(src: &str, names: &[&str]) -> Vec<String> {
+        run(src, names).unwrap().0
+    }
+
+    #[test]
+    fn closures() {
+        assert_eq!(
+            values(
+                "fun add x y = x + y
+                 val inc = add 1
+                 val x = 10
+                 fun getx () = x
+                 val x = 20
+                 val a = (inc 2, getx (), x)
+                 val compose = (fn x => x * 2) o inc
+                 val b = compose 3",
+                &["a", "b"]
+            ),
+            vec!["(3, 10, 20)", "8"]
+        );
+    }
+
+    #[test]
+    fn datatypes_and_records() {
+        assert_eq!(
+            values(
+                "datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree
+                 fun insert (x, Leaf) = Node (Leaf, x, Leaf)
+                   | insert (x, t as Node (l, y, r)) =
+                       if x < y then Node (insert (x, l), y, r)
+                       else if x > y then Node (l, y, insert (x, r))
+                       else t
+                 fun toList (Leaf, acc) = acc
+                   | toList (Node (l, x, r), acc) = toList (l, x :: toList (r, acc))
+                 fun fromList [] = Leaf | fromList (x :: xs) = insert (x, fromList xs)
+                 val xs = toList (fromList [3, 1, 2, 3], [])
+                 val p = {name = \"smol\", version = (0, 1)}
+                 val {version = (major, _), ...} = p
+                 val s = (#name p, major, SOME [1.5, ~2.0], #\"c\")",
+                &["xs", "s"]
+            ),
+            vec!["[1, 2, 3]", "(\"smol\", 0, SOME [1.5, ~2.0], #\"c\")"]
+        );
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            values(
+                "val r = ref 0
+                 fun count 0 = () | count n = (r := !r + 1; count (n - 1))
+                 val _ = count 5
+                 val n = !r
+                 val i = ref 0 and total = ref 0
+                 val _ = while !i < 10 do (total := !total + !i; i := !i + 1)
+                 val same = (r = r, ref 1 = ref 1)
+                 fun get (ref x) = x
+                 val v = get i",
+                &["n", "total", "same", "v"]
+            ),
+            vec!["5", "ref 45", "(true, false)", "10"]
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        assert_eq!(
+            values(
+                "exception Neg of int
+                 fun check n = if n < 0 then raise Neg n else n
+                 val a = check ~3 handle Neg n => ~n
+                 val b = (1 div 0) handle Div => 0
+                 fun hd (x :: _) = x | hd [] = raise Empty
+                 val c = (check 1; hd []) handle Empty => 1 | Neg _ => 2
+                 val d = (raise Fail \"inner\") handle Fail s => s ^ \"!\"
+                 val e = (case 3 of 1 => 0) handle Match => 3",
+                &["a", "b", "c", "d", "e"]
+            ),
+            vec!["3", "0", "1", "\"inner!\"", "3"]
+        );
+        assert_eq!(
+            run("exception E of string val _ = raise E \"oops\"", &[]),
+            Err("uncaught exception E \"oops\"".to_owned())
+        );
+        assert_eq!(
+            run("val SOME x = NONE : int option", &[]),
+            Err("uncaught exception Bind".to_owned())
+        );
+        assert_eq!(
+            run("val x = 4611686018427387903 + 1", &[]),
+            Err("uncaught exception Overflow".to_owned())
+        );
+    }
+
+    #[test]
+    fn tail_calls() {
+        // Deep enough to overflow the stack without tail calls.
+        assert_eq!(
+            values(
+                "fun loop (0, acc) = acc | loop (n, acc) = loop (n - 1, acc + 1)
+                 fun even 0 = true | even n = odd (n - 1)
+                 and odd 0 = false | odd n = even (n - 1)
+                 fun count n = let fun go i = if i = n then i else go (i + 1) in go 0 end
+                 val r = (loop (100000, 0), even 100001, count 100000)",
+                &["r"]
+            ),
+            vec!["(100000, false, 100000)"]
+        );
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // Too deep for the stack limit, but it's an exception rather than a
+        // crash. The thread is big enough to drop the list afterwards.
+        let src = "fun upto n = let fun go (i, acc) = if i = 0 then acc else go (i - 1, i :: acc)
+                                in go (n, []) end
+                   fun sum [] = 0 | sum (x :: xs) = x + sum xs
+                   val ok = sum (upto 50)
+                   val deep = sum (upto 100000) handle Overflow => ~1";
+        let deep = std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(move || values(src, &["ok", "deep"]))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deep, vec!["1275", "~1"]);
+        // Told how big that thread is, it goes as deep as fits.
+        let deep = std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(move || run_with(&src.replace("100000", "5000"), &["deep"], 256 << 20))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deep.unwrap().0, vec!["12502500"]);
+        assert_eq!(
+            run(
+                "fun count 0 = 0 | count n = 1 + count (n - 1) val _ = count 1000000",
+                &[]
+            ),
+            Err("uncaught exception Overflow".to_owned())
+        );
+    }
+
+    #[test]
+    fn output() {
+        assert_eq!(
+            run(
+                "fun greet name = print (\"hello, \" ^ name ^ \"\\n\")
+                 val _ = (greet \"world\"; greet \"smol\")",
+                &[]
+            )
+            .unwrap()
+            .1,
+            "hello, world\nhello, smol\n"
+        );
+    }
+
+    #[test]
+    fn modules() {
+        assert_eq!(
+            values(
+                "signature COUNTER = sig
+                   type t
+                   val new : unit -> t
+                   val incr : t -> int
+                 end
+                 structure Counter :> COUNTER = struct
+                   type t = int ref
+                   fun helper r = (r := !r + 1; !r)
+                   fun new () = ref 0
+                   val incr = helper
+                 end
+                 fun helper _ = 100
+                 functor Twice (C : COUNTER) = struct
+                   fun incr c = (C.incr c; C.incr c)
+                 end
+                 structure T = Twice (Counter)
+                 local open Counter in val c = new () val a = T.incr c val b = helper 0 end",
+                &["a", "b"]
+            ),
+            vec!["2", "100"]
+        );
+    }
+}
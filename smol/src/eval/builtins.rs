@@ -0,0 +1,227 @@
+//! The built in values. Overloaded operators look at their arguments to
+//! decide what to do.
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Divide,
+    Div,
+    Mod,
+    Neg,
+    Abs,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    Deref,
+    Assign,
+    Concat,
+    Compose,
+    Before,
+    Print,
+}
+
+impl Prim {
+    pub const ALL: &'static [Prim] = &[
+        Prim::Add,
+        Prim::Sub,
+        Prim::Mul,
+        Prim::Divide,
+        Prim::Div,
+        Prim::Mod,
+        Prim::Neg,
+        Prim::Abs,
+        Prim::Lt,
+        Prim::Gt,
+        Prim::Le,
+        Prim::Ge,
+        Prim::Eq,
+        Prim::Ne,
+        Prim::Deref,
+        Prim::Assign,
+        Prim::Concat,
+        Prim::Compose,
+        Prim::Before,
+        Prim::Print,
+    ];
+
+    /// The name the value is bound to in the initial environment.
+    pub fn name(self) -> &'static str {
+        match self {
+            Prim::Add => "+",
+            Prim::Sub => "-",
+            Prim::Mul => "*",
+            Prim::Divide => "/",
+            Prim::Div => "div",
+            Prim::Mod => "mod",
+            Prim::Neg => "~",
+            Prim::Abs => "abs",
+            Prim::Lt => "<",
+            Prim::Gt => ">",
+            Prim::Le => "<=",
+            Prim::Ge => ">=",
+            Prim::Eq => "=",
+            Prim::Ne => "<>",
+            Prim::Deref => "!",
+            Prim::Assign => ":=",
+            Prim::Concat => "^",
+            Prim::Compose => "o",
+            Prim::Before => "before",
+            Prim::Print => "print",
+        }
+    }
+
+    pub(super) fn apply(self, interp: &mut Interpreter, arg: Value) -> Result<Value, Raise> {
+        let value = match self {
+            Prim::Add | Prim::Sub | Prim::Mul => {
+                let (a, b) = pair(&arg);
+                arith(self, a, b)?
+            }
+            Prim::Divide => match pair(&arg) {
+                (Value::Real(a), Value::Real(b)) => Value::Real(a / b),
+                args => mismatch(self, args),
+            },
+            Prim::Div | Prim::Mod => {
+                let (a, b) = pair(&arg);
+                int_div(self, a, b)?
+            }
+            Prim::Neg => match arg {
+                Value::Int(n) => checked_int(n.checked_neg())?,
+                Value::Word(n) => Value::Word(n.wrapping_neg()),
+                Value::Real(n) => Value::Real(-n),
+                arg => panic!("`~` can't be applied to {}", arg),
+            },
+            Prim::Abs => match arg {
+                Value::Int(n) => checked_int(n.checked_abs())?,
+                Value::Word(n) => Value::Word(n),
+                Value::Real(n) => Value::Real(n.abs()),
+                arg => panic!("`abs` can't be applied to {}", arg),
+            },
+            Prim::Lt | Prim::Gt | Prim::Le | Prim::Ge => {
+                let (a, b) = pair(&arg);
+                let ordering = match (&a, &b) {
+                    (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+                    (Value::Word(a), Value::Word(b)) => a.partial_cmp(b),
+                    (Value::Real(a), Value::Real(b)) => a.partial_cmp(b),
+                    (Value::Char(a), Value::Char(b)) => a.partial_cmp(b),
+                    (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+                    _ => mismatch(self, (a, b)),
+                };
+                // Comparisons with NaN are all false.
+                Value::Bool(ordering.is_some_and(|ordering| match self {
+                    Prim::Lt => ordering.is_lt(),
+                    Prim::Gt => ordering.is_gt(),
+                    Prim::Le => ordering.is_le(),
+                    _ => ordering.is_ge(),
+                }))
+            }
+            Prim::Eq => {
+                let (a, b) = pair(&arg);
+                Value::Bool(equal(&a, &b))
+            }
+            Prim::Ne => {
+                let (a, b) = pair(&arg);
+                Value::Bool(!equal(&a, &b))
+            }
+            Prim::Deref => match arg {
+                Value::Ref(cell) => cell.borrow().clone(),
+                arg => panic!("`!` can't be applied to {}", arg),
+            },
+            Prim::Assign => match pair(&arg) {
+                (Value::Ref(cell), value) => {
+                    *cell.borrow_mut() = value;
+                    Value::unit()
+                }
+                args => mismatch(self, args),
+            },
+            Prim::Concat => match pair(&arg) {
+                (Value::String(a), Value::String(b)) => Value::string(&format!("{}{}", a, b)),
+                args => mismatch(self, args),
+            },
+            Prim::Compose => {
+                let (f, g) = pair(&arg);
+                Value::Fn(Rc::new(Function::Compose(f, g)))
+            }
+            Prim::Before => pair(&arg).0,
+            Prim::Print => match arg {
+                Value::String(s) => {
+                    // Output is best effort, as in the Basis' `print`.
+                    interp.streams.output(1, &s);
+                    interp.streams.flush(1);
+                    Value::unit()
+                }
+                arg => panic!("`print` can't be applied to {}", arg),
+            },
+        };
+        Ok(value)
+    }
+}
+
+fn pair(arg: &Value) -> (Value, Value) {
+    match arg.fields().as_slice() {
+        [a, b] => (a.clone(), b.clone()),
+        _ => panic!("expected a pair, found {}", arg),
+    }
+}
+
+fn mismatch(prim: Prim, (a, b): (Value, Value)) -> ! {
+    panic!("`{}` can't be applied to ({}, {})", prim.name(), a, b)
+}
+
+fn arith(prim: Prim, a: Value, b: Value) -> Result<Value, Raise> {
+    let value = match (&a, &b) {
+        (Value::Int(x), Value::Int(y)) => {
+            let result = match prim {
+                Prim::Add => x.checked_add(*y),
+                Prim::Sub => x.checked_sub(*y),
+                _ => x.checked_mul(*y),
+            };
+            checked_int(result)?
+        }
+        (Value::Word(x), Value::Word(y)) => Value::Word(match prim {
+            Prim::Add => x.wrapping_add(*y),
+            Prim::Sub => x.wrapping_sub(*y),
+            _ => x.wrapping_mul(*y),
+        }),
+        (Value::Real(x), Value::Real(y)) => Value::Real(match prim {
+            Prim::Add => x + y,
+            Prim::Sub => x - y,
+            _ => x * y,
+        }),
+        _ => mismatch(prim, (a, b)),
+    };
+    Ok(value)
+}
+
+/// `div` and `mod`, which round towards negative infinity.
+fn int_div(prim: Prim, a: Value, b: Value) -> Result<Value, Raise> {
+    let value = match (&a, &b) {
+        (Value::Int(_), Value::Int(0)) | (Value::Word(_), Value::Word(0)) => {
+            return Err(Raise::builtin("Div"))
+        }
+        (Value::Int(x), Value::Int(y)) => {
+            let q = x.checked_div(*y);
+            let r = x % y;
+            // Truncating division rounds towards zero, so adjust when the
+            // signs differ.
+            let floor = r != 0 && (r < 0) != (*y < 0);
+            checked_int(match prim {
+                Prim::Div if floor => q.map(|q| q - 1),
+                Prim::Div => q,
+                _ if floor => Some(r + y),
+                _ => Some(r),
+            })?
+        }
+        (Value::Word(x), Value::Word(y)) => {
+            Value::Word(if prim == Prim::Div { x / y } else { x % y })
+        }
+        _ => mismatch(prim, (a, b)),
+    };
+    Ok(value)
+}
@@ -0,0 +1,544 @@
+//! The primitives the Basis is written with, bound as the structure `Prim`.
+//! Their types are given by the signature `PRIM` in `basis/prim.sml`, which
+//! must list exactly these names.
+//!
+//! Streams are represented by ints, indexing the interpreter's streams.
+//! Characters are Unicode scalar values, and strings are UTF-8, with string
+//! positions counting characters.
+
+use std::collections::VecDeque;
+use std::convert::{TryFrom, TryInto};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read};
+
+use super::*;
+
+/// A function implemented by the interpreter.
+pub struct Native {
+    pub name: &'static str,
+    pub(super) apply: fn(&mut Interpreter, Value) -> Result<Value, Raise>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Prim.{}", self.name)
+    }
+}
+
+macro_rules! native {
+    ($name:expr, |$interp:pat_param, $arg:pat_param| $body:expr) => {
+        Native {
+            name: $name,
+            apply: |$interp, $arg| Ok($body),
+        }
+    };
+}
+
+pub const NATIVES: &[Native] = &[
+    native!("exnName", |_, exn| match exn {
+        Value::Con(info, _) => Value::string(&info.name),
+        exn => panic!("expected an exception, found {}", exn),
+    }),
+    // Int
+    native!("intToString", |_, n| Value::string(
+        &Const::Int(int(&n)).to_string()
+    )),
+    native!("intQuot", |_, arg| {
+        let (a, b) = (int(&arg.fields()[0]), int(&arg.fields()[1]));
+        if b == 0 {
+            return Err(Raise::builtin("Div"));
+        }
+        checked_int(a.checked_div(b))?
+    }),
+    native!("intRem", |_, arg| {
+        let (a, b) = (int(&arg.fields()[0]), int(&arg.fields()[1]));
+        if b == 0 {
+            return Err(Raise::builtin("Div"));
+        }
+        Value::Int(a.wrapping_rem(b))
+    }),
+    // Word
+    native!("wordFromInt", |_, n| Value::Word(int(&n) as u64)),
+    native!("wordToInt", |_, w| checked_int(
+        i64::try_from(word(&w)).ok()
+    )?),
+    native!("wordToIntX", |_, w| checked_int(Some(word(&w) as i64))?),
+    native!("wordAndb", |_, arg| words(&arg, |a, b| a & b)),
+    native!("wordOrb", |_, arg| words(&arg, |a, b| a | b)),
+    native!("wordXorb", |_, arg| words(&arg, |a, b| a ^ b)),
+    native!("wordNotb", |_, w| Value::Word(!word(&w))),
+    native!("wordShl", |_, arg| words(&arg, |a, b| {
+        a.checked_shl(b.try_into().unwrap_or(u32::MAX)).unwrap_or(0)
+    })),
+    native!("wordShr", |_, arg| words(&arg, |a, b| {
+        a.checked_shr(b.try_into().unwrap_or(u32::MAX)).unwrap_or(0)
+    })),
+    native!("wordAshr", |_, arg| words(&arg, |a, b| {
+        // Shifting by the word size or more fills with the sign bit.
+        (a as i64 >> b.min(63)) as u64
+    })),
+    // Real
+    native!("realFromInt", |_, n| Value::Real(int(&n) as f64)),
+    native!("realToString", |_, x| Value::string(&show_real(real(&x)))),
+    native!("realScan", |_, s| option(scan_real(&string(&s)).map(
+        |(x, len)| Value::tuple(vec![Value::Real(x), Value::Int(len as i64)])
+    ))),
+    native!("realFloor", |_, x| to_int(real(&x).floor())?),
+    native!("realCeil", |_, x| to_int(real(&x).ceil())?),
+    native!("realRound", |_, x| to_int(real(&x).round_ties_even())?),
+    native!("realTrunc", |_, x| to_int(real(&x).trunc())?),
+    // Char
+    native!("charOrd", |_, c| Value::Int(char(&c) as i64)),
+    native!("charChr", |_, n| match u8::try_from(int(&n)) {
+        Ok(n) => Value::Char(n as char),
+        Err(_) => return Err(Raise::builtin("Chr")),
+    }),
+    // String
+    native!("stringSize", |_, s| Value::Int(size(&string(&s)) as i64)),
+    native!("stringSub", |_, arg| {
+        let (s, i) = (string(&arg.fields()[0]), int(&arg.fields()[1]));
+        let c = usize::try_from(i).ok().and_then(|i| {
+            if s.is_ascii() {
+                s.as_bytes().get(i).map(|&b| b as char)
+            } else {
+                s.chars().nth(i)
+            }
+        });
+        Value::Char(c.ok_or_else(subscript)?)
+    }),
+    native!("stringExtract", |_, arg| {
+        let fields = arg.fields();
+        let (s, i, n) = (string(&fields[0]), int(&fields[1]), int(&fields[2]));
+        let len = size(&s) as i64;
+        if i < 0 || n < 0 || i > len - n {
+            return Err(subscript());
+        }
+        let (i, n) = (i as usize, n as usize);
+        if s.is_ascii() {
+            Value::string(&s[i..i + n])
+        } else {
+            Value::string(&s.chars().skip(i).take(n).collect::<String>())
+        }
+    }),
+    native!("stringConcat", |_, ss| {
+        let ss = ss.as_list().unwrap();
+        Value::string(&ss.iter().map(|s| string(s).to_string()).collect::<String>())
+    }),
+    native!("stringImplode", |_, cs| {
+        let cs = cs.as_list().unwrap();
+        Value::string(&cs.iter().map(char).collect::<String>())
+    }),
+    native!("stringExplode", |_, s| Value::list(
+        string(&s).chars().map(Value::Char).collect()
+    )),
+    // Vector
+    native!("vectorFromList", |_, xs| Value::Vector(Rc::from(
+        xs.as_list().unwrap()
+    ))),
+    native!("vectorLength", |_, v| match v {
+        Value::Vector(items) => Value::Int(items.len() as i64),
+        v => panic!("expected a vector, found {}", v),
+    }),
+    native!("vectorSub", |_, arg| match pair(arg) {
+        (Value::Vector(items), Value::Int(i)) => index(&items, i)?,
+        (v, i) => panic!("can't subscript {} with {}", v, i),
+    }),
+    // Array
+    native!("arrayArray", |_, arg| {
+        let (n, x) = pair(arg);
+        let n = usize::try_from(int(&n)).map_err(|_| Raise::builtin("Size"))?;
+        Value::Array(Rc::new(RefCell::new(vec![x; n])))
+    }),
+    native!("arrayFromList", |_, xs| Value::Array(Rc::new(
+        RefCell::new(xs.as_list().unwrap())
+    ))),
+    native!("arrayLength", |_, a| Value::Int(
+        array(&a).borrow().len() as i64
+    )),
+    native!("arraySub", |_, arg| {
+        let (a, i) = pair(arg);
+        let items = array(&a).borrow();
+        index(&items, int(&i))?
+    }),
+    native!("arrayUpdate", |_, arg| {
+        let fields = arg.fields();
+        let mut items = array(&fields[0]).borrow_mut();
+        let slot = usize::try_from(int(&fields[1]))
+            .ok()
+            .and_then(|i| items.get_mut(i))
+            .ok_or_else(subscript)?;
+        *slot = fields[2].clone();
+        Value::unit()
+    }),
+    native!("arrayVector", |_, a| Value::Vector(Rc::from(
+        array(&a).borrow().as_slice()
+    ))),
+    // TextIO
+    native!("ioOpenIn", |interp, name| {
+        let res = interp.streams.open_in(&string(&name));
+        opened(res)
+    }),
+    native!("ioOpenOut", |interp, name| {
+        let res = interp.streams.open_out(&string(&name), false);
+        opened(res)
+    }),
+    native!("ioOpenAppend", |interp, name| {
+        let res = interp.streams.open_out(&string(&name), true);
+        opened(res)
+    }),
+    native!("ioClose", |interp, id| {
+        interp.streams.close(int(&id) as usize);
+        Value::unit()
+    }),
+    native!("ioOutput", |interp, arg| {
+        let (id, s) = pair(arg);
+        Value::Bool(interp.streams.output(int(&id) as usize, &string(&s)))
+    }),
+    native!("ioFlush", |interp, id| Value::Bool(
+        interp.streams.flush(int(&id) as usize)
+    )),
+    native!("ioInput", |interp, arg| {
+        let (id, n) = pair(arg);
+        let mut s = String::new();
+        if let Some(input) = interp.streams.input(int(&id) as usize) {
+            for _ in 0..int(&n) {
+                match input.read_char() {
+                    Some(c) => s.push(c),
+                    None => break,
+                }
+            }
+        }
+        Value::string(&s)
+    }),
+    native!("ioPeek", |interp, arg| {
+        let (id, n) = pair(arg);
+        let n = usize::try_from(int(&n)).unwrap_or(usize::MAX);
+        option(
+            interp
+                .streams
+                .input(int(&id) as usize)
+                .and_then(|input| input.peek(n))
+                .map(Value::Char),
+        )
+    }),
+    native!("ioInputLine", |interp, id| {
+        match interp
+            .streams
+            .input(int(&id) as usize)
+            .and_then(Input::read_line)
+        {
+            Some(line) => option(Some(Value::string(&line))),
+            None => option(None),
+        }
+    }),
+    native!("ioInputAll", |interp, id| {
+        let s = match interp.streams.input(int(&id) as usize) {
+            Some(input) => input.read_all(),
+            None => String::new(),
+        };
+        Value::string(&s)
+    }),
+    native!("ioEndOfStream", |interp, id| Value::Bool(
+        interp
+            .streams
+            .input(int(&id) as usize)
+            .and_then(|input| input.peek(0))
+            .is_none()
+    )),
+];
+
+/// A stream's id and an empty error, or ~1 and the error opening it.
+fn opened(res: io::Result<usize>) -> Value {
+    match res {
+        Ok(id) => Value::tuple(vec![Value::Int(id as i64), Value::string("")]),
+        Err(err) => Value::tuple(vec![Value::Int(-1), Value::string(&err.to_string())]),
+    }
+}
+
+/// An open stream.
+pub(crate) enum Stream {
+    In(Input),
+    Out(Box<dyn Write>),
+}
+
+/// The open streams, indexed by the ids `TextIO` represents them with.
+/// Standard input, output and error are 0, 1 and 2.
+pub(crate) struct Streams(Vec<Option<Stream>>);
+
+impl Streams {
+    /// The standard streams, with standard output going to `out`.
+    pub(crate) fn new(out: Box<dyn Write>) -> Self {
+        Streams(vec![
+            Some(Stream::In(Input::new(Box::new(
+                BufReader::new(io::stdin()),
+            )))),
+            Some(Stream::Out(out)),
+            Some(Stream::Out(Box::new(io::stderr()))),
+        ])
+    }
+
+    pub(crate) fn open_in(&mut self, path: &str) -> io::Result<usize> {
+        let file = File::open(path)?;
+        Ok(self.add(Stream::In(Input::new(Box::new(BufReader::new(file))))))
+    }
+
+    pub(crate) fn open_out(&mut self, path: &str, append: bool) -> io::Result<usize> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(self.add(Stream::Out(Box::new(io::BufWriter::new(file)))))
+    }
+
+    fn add(&mut self, stream: Stream) -> usize {
+        self.0.push(Some(stream));
+        self.0.len() - 1
+    }
+
+    pub(crate) fn close(&mut self, id: usize) {
+        self.flush(id);
+        if let Some(stream) = self.0.get_mut(id) {
+            *stream = None;
+        }
+    }
+
+    /// An input stream, unless it's closed.
+    pub(crate) fn input(&mut self, id: usize) -> Option<&mut Input> {
+        match self.0.get_mut(id) {
+            Some(Some(Stream::In(input))) => Some(input),
+            _ => None,
+        }
+    }
+
+    /// Write to an output stream, returning whether it worked.
+    pub(crate) fn output(&mut self, id: usize, s: &str) -> bool {
+        match self.0.get_mut(id) {
+            Some(Some(Stream::Out(out))) => out.write_all(s.as_bytes()).is_ok(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn flush(&mut self, id: usize) -> bool {
+        match self.0.get_mut(id) {
+            Some(Some(Stream::Out(out))) => out.flush().is_ok(),
+            _ => false,
+        }
+    }
+}
+
+/// An input stream, with the characters that have been looked at but not
+/// read yet.
+pub(crate) struct Input {
+    reader: Box<dyn BufRead>,
+    ahead: VecDeque<char>,
+}
+
+impl Input {
+    fn new(reader: Box<dyn BufRead>) -> Self {
+        Input {
+            reader,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn read_char(&mut self) -> Option<char> {
+        self.ahead.pop_front().or_else(|| self.decode())
+    }
+
+    /// Look at the nth character from here, without reading it.
+    pub(crate) fn peek(&mut self, n: usize) -> Option<char> {
+        while self.ahead.len() <= n {
+            let c = self.decode()?;
+            self.ahead.push_back(c);
+        }
+        Some(self.ahead[n])
+    }
+
+    /// The next line, ending with a newline even if the stream doesn't, or
+    /// `None` at the end of the stream.
+    pub(crate) fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        while let Some(c) = self.read_char() {
+            line.push(c);
+            if c == '\n' {
+                break;
+            }
+        }
+        if line.is_empty() {
+            return None;
+        }
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        Some(line)
+    }
+
+    /// Everything left.
+    pub(crate) fn read_all(&mut self) -> String {
+        let mut s: String = self.ahead.drain(..).collect();
+        let mut bytes = Vec::new();
+        let _ = self.reader.read_to_end(&mut bytes);
+        s += &String::from_utf8_lossy(&bytes);
+        s
+    }
+
+    /// Read a UTF-8 character from the underlying reader. Invalid input
+    /// reads as U+FFFD.
+    fn decode(&mut self) -> Option<char> {
+        let first = *self.reader.fill_buf().ok()?.first()?;
+        self.reader.consume(1);
+        let width = match first {
+            0x00..=0x7f => return Some(first as char),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        let mut bytes = vec![first];
+        for _ in 1..width {
+            match self
+                .reader
+                .fill_buf()
+                .ok()
+                .and_then(|buf| buf.first().copied())
+            {
+                Some(b) if b & 0xc0 == 0x80 => {
+                    bytes.push(b);
+                    self.reader.consume(1);
+                }
+                _ => break,
+            }
+        }
+        String::from_utf8_lossy(&bytes).chars().next()
+    }
+}
+
+/// Scan a real in SML's syntax from the start of a string, after any
+/// whitespace, returning it and the length of the whole prefix read.
+pub(crate) fn scan_real(s: &str) -> Option<(f64, usize)> {
+    let skipped = s.len() - s.trim_start().len();
+    let bytes = &s.as_bytes()[skipped..];
+    let digits = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        i
+    };
+    let mut i = 0;
+    if matches!(bytes.first(), Some(b'~' | b'-' | b'+')) {
+        i += 1;
+    }
+    let start = i;
+    i = digits(i);
+    let mut mantissa = i - start;
+    if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+        let end = digits(i + 1);
+        mantissa += end - i - 1;
+        i = end;
+    }
+    if mantissa == 0 {
+        return None;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(i + 1), Some(b'~' | b'-' | b'+')));
+        if bytes.get(i + 1 + sign).is_some_and(u8::is_ascii_digit) {
+            i = digits(i + 1 + sign);
+        }
+    }
+    let text = s[skipped..skipped + i].replace('~', "-");
+    let x = text.parse().ok()?;
+    // Lengths are in characters, but everything read is ASCII.
+    Some((x, s[..skipped].chars().count() + i))
+}
+
+fn to_int(x: f64) -> Result<Value, Raise> {
+    if x.is_nan() {
+        Err(Raise::builtin("Domain"))
+    } else if x < i64::MIN as f64 || x >= i64::MAX as f64 {
+        Err(overflow())
+    } else {
+        checked_int(Some(x as i64))
+    }
+}
+
+/// The number of characters in a string.
+fn size(s: &str) -> usize {
+    if s.is_ascii() {
+        s.len()
+    } else {
+        s.chars().count()
+    }
+}
+
+fn index(items: &[Value], i: i64) -> Result<Value, Raise> {
+    usize::try_from(i)
+        .ok()
+        .and_then(|i| items.get(i))
+        .cloned()
+        .ok_or_else(subscript)
+}
+
+fn words(arg: &Value, f: fn(u64, u64) -> u64) -> Value {
+    let fields = arg.fields();
+    Value::Word(f(word(&fields[0]), word(&fields[1])))
+}
+
+fn pair(arg: Value) -> (Value, Value) {
+    let mut fields = arg.fields().into_iter();
+    match (fields.next(), fields.next()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => panic!("expected a pair, found {}", arg),
+    }
+}
+
+fn overflow() -> Raise {
+    Raise::builtin("Overflow")
+}
+
+fn subscript() -> Raise {
+    Raise::builtin("Subscript")
+}
+
+fn int(value: &Value) -> i64 {
+    match value {
+        Value::Int(n) => *n,
+        value => panic!("expected an int, found {}", value),
+    }
+}
+
+fn word(value: &Value) -> u64 {
+    match value {
+        Value::Word(n) => *n,
+        value => panic!("expected a word, found {}", value),
+    }
+}
+
+fn real(value: &Value) -> f64 {
+    match value {
+        Value::Real(x) => *x,
+        value => panic!("expected a real, found {}", value),
+    }
+}
+
+fn char(value: &Value) -> char {
+    match value {
+        Value::Char(c) => *c,
+        value => panic!("expected a char, found {}", value),
+    }
+}
+
+fn string(value: &Value) -> Rc<str> {
+    match value {
+        Value::String(s) => s.clone(),
+        value => panic!("expected a string, found {}", value),
+    }
+}
+
+fn array(value: &Value) -> &Rc<RefCell<Vec<Value>>> {
+    match value {
+        Value::Array(items) => items,
+        value => panic!("expected an array, found {}", value),
+    }
+}
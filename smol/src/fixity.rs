@@ -0,0 +1,948 @@
+//! Infix operator resolution.
+//!
+//! Lowering leaves applications and infix expressions as flat sequences of
+//! atoms, since which identifiers are infix depends on the `infix`, `infixr`
+//! and `nonfix` declarations in scope. This pass walks the program keeping
+//! track of fixity, and rewrites each sequence into applications:
+//!
+//! - Juxtaposition binds tightest, and associates to the left.
+//! - `a op b` becomes `op (a, b)`, for both expressions and patterns.
+//! - Operators of different precedence (0-9) bind in the usual way. Operators
+//!   of equal precedence must agree on associativity.
+//! - An identifier prefixed with `op` is never infix, and qualified
+//!   identifiers are never infix.
+
+use std::collections::HashMap;
+
+use parsegen::Span;
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+
+/// Fixity declarations in scope.
+#[derive(Debug, Clone)]
+pub struct Env {
+    /// Innermost scope last.
+    scopes: Vec<HashMap<String, Fixity>>,
+}
+
+impl Env {
+    /// An environment with no infix identifiers.
+    pub fn empty() -> Self {
+        Env {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    /// The fixities of the top level environment of the Basis Library.
+    pub fn basis() -> Self {
+        let mut env = Env::empty();
+        let fixities: &[(Fixity, &[&str])] = &[
+            (Fixity::Infix(7), &["*", "/", "div", "mod"]),
+            (Fixity::Infix(6), &["+", "-", "^"]),
+            (Fixity::Infixr(5), &["::", "@"]),
+            (Fixity::Infix(4), &["=", "<>", ">", ">=", "<", "<="]),
+            (Fixity::Infix(3), &[":=", "o"]),
+            (Fixity::Infix(0), &["before"]),
+        ];
+        for (fixity, ids) in fixities {
+            for id in *ids {
+                env.declare(id, *fixity);
+            }
+        }
+        env
+    }
+
+    pub fn declare(&mut self, id: &str, fixity: Fixity) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(id.to_owned(), fixity);
+    }
+
+    /// The fixity of an identifier. Identifiers are nonfix unless declared
+    /// otherwise.
+    pub fn lookup(&self, id: &str) -> Fixity {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id).copied())
+            .unwrap_or(Fixity::Nonfix)
+    }
+
+    /// Start a basis, for ML Basis files. Fixities declared in it are only
+    /// in scope until it's left.
+    pub fn enter_basis(&mut self) {
+        self.push();
+    }
+
+    /// Go back to the basis before the last one entered, giving the
+    /// fixities declared in that one.
+    pub fn leave_basis(&mut self) -> HashMap<String, Fixity> {
+        self.pop()
+    }
+
+    /// Declare the fixities a basis declared.
+    pub fn open_basis(&mut self, fixities: &HashMap<String, Fixity>) {
+        self.merge(fixities.clone());
+    }
+
+    fn push(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop(&mut self) -> HashMap<String, Fixity> {
+        self.scopes.pop().unwrap()
+    }
+
+    /// Add declarations from an inner scope to the current one.
+    fn merge(&mut self, scope: HashMap<String, Fixity>) {
+        self.scopes.last_mut().unwrap().extend(scope);
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Env::basis()
+    }
+}
+
+/// Resolve infix operators in a program, starting from the Basis Library's
+/// fixities.
+pub fn resolve(program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+    resolve_with(&mut Env::basis(), program)
+}
+
+/// Resolve infix operators in a program. Top level fixity declarations are
+/// added to `env`, so it can be reused for later programs, e.g. in a REPL.
+pub fn resolve_with(env: &mut Env, program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        env,
+        diagnostics: Vec::new(),
+    };
+    for item in &mut program.items {
+        resolver.top_dec(item);
+    }
+    if resolver.diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(resolver.diagnostics)
+    }
+}
+
+/// An element of a flat sequence, once infix operators have been picked out.
+enum Elem<T> {
+    Operand(T),
+    Operator {
+        id: LongId,
+        span: Span,
+        prec: u8,
+        right: bool,
+    },
+}
+
+struct Resolver<'e> {
+    env: &'e mut Env,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'e> Resolver<'e> {
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    /// The precedence and associativity of an identifier, if it's infix.
+    fn infix(&self, op: bool, id: &LongId) -> Option<(u8, bool)> {
+        if op || id.is_qualified() {
+            return None;
+        }
+        match self.env.lookup(&id.name) {
+            Fixity::Infix(prec) => Some((prec, false)),
+            Fixity::Infixr(prec) => Some((prec, true)),
+            Fixity::Nonfix => None,
+        }
+    }
+
+    fn infix_exp(&self, exp: &Exp) -> Option<(u8, bool)> {
+        match &exp.kind {
+            ExpKind::Var { op, id } => self.infix(*op, id),
+            _ => None,
+        }
+    }
+
+    fn infix_pat(&self, pat: &Pat) -> Option<(u8, bool)> {
+        match &pat.kind {
+            PatKind::Var { op, id } => self.infix(*op, id),
+            _ => None,
+        }
+    }
+
+    /// Combine operands and infix operators by precedence. Operands and
+    /// operators must alternate, starting and ending with an operand.
+    fn shunt<T>(
+        &mut self,
+        elems: Vec<Elem<T>>,
+        span: Span,
+        apply: impl Fn(LongId, Span, T, T) -> T,
+    ) -> Option<T> {
+        let mut operands: Vec<T> = Vec::new();
+        let mut operators: Vec<(LongId, Span, u8, bool)> = Vec::new();
+        let reduce = |operands: &mut Vec<T>, (id, span, _, _): (LongId, Span, u8, bool)| {
+            let rhs = operands.pop().unwrap();
+            let lhs = operands.pop().unwrap();
+            operands.push(apply(id, span, lhs, rhs));
+        };
+
+        let mut expect_operand = true;
+        for elem in elems {
+            match elem {
+                Elem::Operand(t) => {
+                    debug_assert!(expect_operand, "operands are grouped before shunting");
+                    operands.push(t);
+                    expect_operand = false;
+                }
+                Elem::Operator { id, span, .. } if expect_operand => {
+                    self.error(
+                        span,
+                        format!("infix operator `{}` is missing its left operand", id),
+                    );
+                    return None;
+                }
+                Elem::Operator {
+                    id,
+                    span,
+                    prec,
+                    right,
+                } => {
+                    while let Some(&(ref top, _, top_prec, top_right)) = operators.last() {
+                        if top_prec == prec && top_right != right {
+                            self.error(
+                                span,
+                                format!(
+                                    "`{}` and `{}` have the same precedence but different associativity",
+                                    top, id
+                                ),
+                            );
+                            return None;
+                        }
+                        if top_prec > prec || (top_prec == prec && !right) {
+                            reduce(&mut operands, operators.pop().unwrap());
+                        } else {
+                            break;
+                        }
+                    }
+                    operators.push((id, span, prec, right));
+                    expect_operand = true;
+                }
+            }
+        }
+
+        if expect_operand {
+            let (id, op_span) = match operators.last() {
+                Some((id, op_span, _, _)) => (id.to_string(), *op_span),
+                None => (String::new(), span),
+            };
+            self.error(
+                op_span,
+                format!("infix operator `{}` is missing its right operand", id),
+            );
+            return None;
+        }
+        while let Some(op) = operators.pop() {
+            reduce(&mut operands, op);
+        }
+        operands.pop()
+    }
+
+    // Modules
+
+    fn top_dec(&mut self, item: &mut TopDec) {
+        match item {
+            TopDec::Str(dec) => self.str_dec(dec),
+            TopDec::Sig(_) => (),
+            TopDec::Functor(binds) => {
+                for bind in binds {
+                    self.env.push();
+                    self.str_exp(&mut bind.body);
+                    self.env.pop();
+                }
+            }
+            TopDec::Exp(exp) => self.exp(exp),
+        }
+    }
+
+    fn str_dec(&mut self, dec: &mut StrDec) {
+        match &mut dec.kind {
+            StrDecKind::Dec(dec) => self.dec(dec),
+            StrDecKind::Structure(binds) => {
+                for bind in binds {
+                    self.env.push();
+                    self.str_exp(&mut bind.str);
+                    self.env.pop();
+                }
+            }
+            StrDecKind::Local(local, body) => {
+                self.env.push();
+                for dec in local {
+                    self.str_dec(dec);
+                }
+                self.env.push();
+                for dec in body {
+                    self.str_dec(dec);
+                }
+                let body = self.env.pop();
+                self.env.pop();
+                self.env.merge(body);
+            }
+        }
+    }
+
+    fn str_exp(&mut self, exp: &mut StrExp) {
+        match &mut exp.kind {
+            StrExpKind::Struct(decs) => {
+                self.env.push();
+                for dec in decs {
+                    self.str_dec(dec);
+                }
+                self.env.pop();
+            }
+            StrExpKind::Var(_) => (),
+            StrExpKind::Ascribe(exp, _) | StrExpKind::App(_, exp) => self.str_exp(exp),
+            StrExpKind::Let(decs, exp) => {
+                self.env.push();
+                for dec in decs {
+                    self.str_dec(dec);
+                }
+                self.str_exp(exp);
+                self.env.pop();
+            }
+        }
+    }
+
+    // Declarations
+
+    fn dec(&mut self, dec: &mut Dec) {
+        match &mut dec.kind {
+            DecKind::Val { binds, .. } => {
+                for bind in binds {
+                    self.pat(&mut bind.pat);
+                    self.exp(&mut bind.exp);
+                }
+            }
+            DecKind::Fun { binds, .. } => {
+                for bind in binds {
+                    self.fun_bind(bind);
+                }
+            }
+            DecKind::Type(_)
+            | DecKind::Datatype { .. }
+            | DecKind::DatatypeRepl(_, _)
+            | DecKind::Exception(_)
+            | DecKind::Open(_) => (),
+            DecKind::Abstype { body, .. } => {
+                for dec in body {
+                    self.dec(dec);
+                }
+            }
+            DecKind::Local(local, body) => {
+                self.env.push();
+                for dec in local {
+                    self.dec(dec);
+                }
+                self.env.push();
+                for dec in body {
+                    self.dec(dec);
+                }
+                let body = self.env.pop();
+                self.env.pop();
+                self.env.merge(body);
+            }
+            DecKind::Fixity(fixity, ids) => {
+                for id in ids {
+                    self.env.declare(&id.name, *fixity);
+                }
+            }
+        }
+    }
+
+    fn fun_bind(&mut self, bind: &mut FunBind) {
+        for clause in &mut bind.clauses {
+            self.clause(clause);
+        }
+
+        let first = &bind.clauses[0];
+        let (name, arity) = match &first.name {
+            Some(name) => (name.name.clone(), first.args.len()),
+            None => return,
+        };
+        let mut errors = Vec::new();
+        for clause in &bind.clauses[1..] {
+            match &clause.name {
+                Some(other) if other.name != name => errors.push((
+                    other.span,
+                    format!(
+                        "clauses don't all have the same function name: expected `{}`, found `{}`",
+                        name, other
+                    ),
+                )),
+                Some(_) if clause.args.len() != arity => errors.push((
+                    clause.span,
+                    "clauses don't all have the same number of arguments".to_owned(),
+                )),
+                _ => (),
+            }
+        }
+        for (span, message) in errors {
+            self.error(span, message);
+        }
+    }
+
+    /// Work out the function name of a clause, which is one of:
+    ///
+    /// - `[op] name atpat ... atpat`
+    /// - `atpat name atpat`, if `name` is infix
+    /// - `(atpat name atpat) atpat ... atpat`, if `name` is infix
+    fn clause(&mut self, clause: &mut Clause) {
+        self.exp(&mut clause.body);
+        if clause.name.is_some() {
+            return;
+        }
+
+        let mut args = std::mem::take(&mut clause.args);
+        let infix_name = |resolver: &Self, pats: &[Pat]| match pats {
+            [_, Pat {
+                kind: PatKind::Var { op, id },
+                ..
+            }, _]
+                if !id.is_qualified() && resolver.infix(*op, id).is_some() =>
+            {
+                Some(Id {
+                    name: id.name.clone(),
+                    span: id.span,
+                })
+            }
+            _ => None,
+        };
+
+        if let Some(name) = infix_name(self, &args) {
+            let rhs = args.pop().unwrap();
+            args.pop();
+            let lhs = args.pop().unwrap();
+            clause.name = Some(name);
+            clause.args = vec![self.infix_args(lhs, rhs)];
+        } else if let Some(PatKind::Flat(pats)) = args.first().map(|p| &p.kind) {
+            match infix_name(self, pats) {
+                Some(name) => {
+                    let mut pats = match args.remove(0).kind {
+                        PatKind::Flat(pats) => pats,
+                        _ => unreachable!(),
+                    };
+                    let rhs = pats.pop().unwrap();
+                    let lhs = pats.remove(0);
+                    clause.name = Some(name);
+                    clause.args = vec![self.infix_args(lhs, rhs)];
+                    clause.args.extend(args);
+                }
+                None => {
+                    self.error(args[0].span, "expected a function name");
+                    clause.args = args;
+                    return;
+                }
+            }
+        } else {
+            let name = match args.first().map(|p| &p.kind) {
+                Some(PatKind::Var { op, id }) if !id.is_qualified() => {
+                    if self.infix(*op, id).is_some() {
+                        self.error(
+                            id.span,
+                            format!(
+                                "infix operator `{}` used as a prefix function; use `op {}`",
+                                id, id
+                            ),
+                        );
+                    }
+                    Id {
+                        name: id.name.clone(),
+                        span: id.span,
+                    }
+                }
+                _ => {
+                    let span = args.first().map_or(clause.span, |p| p.span);
+                    self.error(span, "expected a function name");
+                    clause.args = args;
+                    return;
+                }
+            };
+            args.remove(0);
+            if args.is_empty() {
+                self.error(name.span, format!("function `{}` has no arguments", name));
+            }
+            clause.name = Some(name);
+            clause.args = args;
+        }
+
+        for arg in &mut clause.args {
+            self.pat(arg);
+        }
+    }
+
+    /// The argument of an infix function clause, `(lhs, rhs)`.
+    fn infix_args(&mut self, lhs: Pat, rhs: Pat) -> Pat {
+        Pat {
+            span: lhs.span.to(rhs.span),
+            kind: PatKind::Tuple(vec![lhs, rhs]),
+        }
+    }
+
+    // Expressions
+
+    fn exp(&mut self, exp: &mut Exp) {
+        match &mut exp.kind {
+            ExpKind::Const(_) | ExpKind::Selector(_) => (),
+            ExpKind::Var { op, id } => {
+                if self.infix(*op, id).is_some() {
+                    let id = id.clone();
+                    self.error(
+                        id.span,
+                        format!(
+                            "infix operator `{}` used without operands; use `op {}`",
+                            id, id
+                        ),
+                    );
+                }
+            }
+            ExpKind::Record(rows) => {
+                for (_, exp) in rows {
+                    self.exp(exp);
+                }
+            }
+            ExpKind::Tuple(exps) | ExpKind::List(exps) | ExpKind::Seq(exps) => {
+                for exp in exps {
+                    self.exp(exp);
+                }
+            }
+            ExpKind::Let(decs, body) => {
+                self.env.push();
+                for dec in decs {
+                    self.dec(dec);
+                }
+                self.exp(body);
+                self.env.pop();
+            }
+            ExpKind::Flat(_) => {
+                let span = exp.span;
+                let items = match std::mem::replace(&mut exp.kind, ExpKind::Tuple(Vec::new())) {
+                    ExpKind::Flat(items) => items,
+                    _ => unreachable!(),
+                };
+                if let Some(resolved) = self.flat_exp(items, span) {
+                    *exp = resolved;
+                }
+            }
+            ExpKind::App(f, arg) => {
+                self.exp(f);
+                self.exp(arg);
+            }
+            ExpKind::Typed(exp, _) | ExpKind::Raise(exp) => self.exp(exp),
+            ExpKind::Andalso(a, b) | ExpKind::Orelse(a, b) | ExpKind::While(a, b) => {
+                self.exp(a);
+                self.exp(b);
+            }
+            ExpKind::Handle(exp, rules) | ExpKind::Case(exp, rules) => {
+                self.exp(exp);
+                self.mrules(rules);
+            }
+            ExpKind::If(c, t, e) => {
+                self.exp(c);
+                self.exp(t);
+                self.exp(e);
+            }
+            ExpKind::Fn(rules) => self.mrules(rules),
+        }
+    }
+
+    fn mrules(&mut self, rules: &mut [MRule]) {
+        for rule in rules {
+            self.pat(&mut rule.pat);
+            self.exp(&mut rule.exp);
+        }
+    }
+
+    fn flat_exp(&mut self, items: Vec<Exp>, span: Span) -> Option<Exp> {
+        let mut elems = Vec::new();
+        // The current run of applications.
+        let mut app: Option<Exp> = None;
+        for mut item in items {
+            if let Some((prec, right)) = self.infix_exp(&item) {
+                if let Some(app) = app.take() {
+                    elems.push(Elem::Operand(app));
+                }
+                let id = match item.kind {
+                    ExpKind::Var { id, .. } => id,
+                    _ => unreachable!(),
+                };
+                elems.push(Elem::Operator {
+                    id,
+                    span: item.span,
+                    prec,
+                    right,
+                });
+                continue;
+            }
+
+            self.exp(&mut item);
+            app = Some(match app {
+                Some(f) => Exp {
+                    span: f.span.to(item.span),
+                    kind: ExpKind::App(Box::new(f), Box::new(item)),
+                },
+                None => item,
+            });
+        }
+        if let Some(app) = app {
+            elems.push(Elem::Operand(app));
+        }
+
+        self.shunt(elems, span, |id, span, lhs, rhs| {
+            let f = Exp {
+                kind: ExpKind::Var { op: false, id },
+                span,
+            };
+            let arg = Exp {
+                span: lhs.span.to(rhs.span),
+                kind: ExpKind::Tuple(vec![lhs, rhs]),
+            };
+            Exp {
+                span: arg.span,
+                kind: ExpKind::App(Box::new(f), Box::new(arg)),
+            }
+        })
+    }
+
+    // Patterns
+
+    fn pat(&mut self, pat: &mut Pat) {
+        match &mut pat.kind {
+            PatKind::Wildcard | PatKind::Const(_) => (),
+            PatKind::Var { op, id } => {
+                if self.infix(*op, id).is_some() {
+                    let id = id.clone();
+                    self.error(
+                        id.span,
+                        format!(
+                            "infix operator `{}` used without operands; use `op {}`",
+                            id, id
+                        ),
+                    );
+                }
+            }
+            PatKind::Record { rows, .. } => {
+                for (_, pat) in rows {
+                    self.pat(pat);
+                }
+            }
+            PatKind::Tuple(pats) | PatKind::List(pats) => {
+                for pat in pats {
+                    self.pat(pat);
+                }
+            }
+            PatKind::Flat(_) => {
+                let span = pat.span;
+                let items = match std::mem::replace(&mut pat.kind, PatKind::Wildcard) {
+                    PatKind::Flat(items) => items,
+                    _ => unreachable!(),
+                };
+                if let Some(resolved) = self.flat_pat(items, span) {
+                    *pat = resolved;
+                }
+            }
+            PatKind::Con(_, pat) | PatKind::Typed(pat, _) | PatKind::Layered { pat, .. } => {
+                self.pat(pat)
+            }
+        }
+    }
+
+    fn flat_pat(&mut self, items: Vec<Pat>, span: Span) -> Option<Pat> {
+        let mut elems = Vec::new();
+        let mut run: Vec<Pat> = Vec::new();
+        for mut item in items {
+            if let Some((prec, right)) = self.infix_pat(&item) {
+                if !run.is_empty() {
+                    elems.push(Elem::Operand(self.con_app(std::mem::take(&mut run))?));
+                }
+                let id = match item.kind {
+                    PatKind::Var { id, .. } => id,
+                    _ => unreachable!(),
+                };
+                elems.push(Elem::Operator {
+                    id,
+                    span: item.span,
+                    prec,
+                    right,
+                });
+                continue;
+            }
+            self.pat(&mut item);
+            run.push(item);
+        }
+        if !run.is_empty() {
+            elems.push(Elem::Operand(self.con_app(run)?));
+        }
+
+        self.shunt(elems, span, |id, _, lhs, rhs| {
+            let span = lhs.span.to(rhs.span);
+            let arg = Pat {
+                span,
+                kind: PatKind::Tuple(vec![lhs, rhs]),
+            };
+            Pat {
+                span,
+                kind: PatKind::Con(id, Box::new(arg)),
+            }
+        })
+    }
+
+    /// A constructor applied to an argument, or a single atomic pattern.
+    fn con_app(&mut self, mut run: Vec<Pat>) -> Option<Pat> {
+        if run.len() == 1 {
+            return run.pop();
+        }
+        if run.len() > 2 {
+            self.error(
+                run[2].span.to(run[run.len() - 1].span),
+                "too many arguments in constructor pattern",
+            );
+            return None;
+        }
+        let arg = run.pop().unwrap();
+        let con = run.pop().unwrap();
+        match con.kind {
+            PatKind::Var { id, .. } => Some(Pat {
+                span: con.span.to(arg.span),
+                kind: PatKind::Con(id, Box::new(arg)),
+            }),
+            _ => {
+                self.error(con.span, "expected a constructor to apply");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parsegen::SourceMap;
+
+    fn program(src: &str) -> Result<Program, Vec<String>> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        match resolve(&mut program) {
+            Ok(()) => Ok(program),
+            Err(diags) => Err(diags.into_iter().map(|d| d.message).collect()),
+        }
+    }
+
+    fn show_exp(exp: &Exp) -> String {
+        match &exp.kind {
+            ExpKind::Var { id, .. } => id.to_string(),
+            ExpKind::Const(c) => c.to_string(),
+            ExpKind::App(f, arg) => match &arg.kind {
+                ExpKind::Tuple(args) if args.len() == 2 => format!(
+                    "({} {} {})",
+                    show_exp(f),
+                    show_exp(&args[0]),
+                    show_exp(&args[1])
+                ),
+                _ => format!("({} {})", show_exp(f), show_exp(arg)),
+            },
+            ExpKind::Tuple(exps) => {
+                let exps: Vec<_> = exps.iter().map(show_exp).collect();
+                format!("<{}>", exps.join(", "))
+            }
+            kind => format!("{:?}", kind),
+        }
+    }
+
+    fn show_pat(pat: &Pat) -> String {
+        match &pat.kind {
+            PatKind::Var { id, .. } => id.to_string(),
+            PatKind::Const(c) => c.to_string(),
+            PatKind::Wildcard => "_".to_owned(),
+            PatKind::Con(con, arg) => match &arg.kind {
+                PatKind::Tuple(args) if args.len() == 2 => {
+                    format!("({} {} {})", con, show_pat(&args[0]), show_pat(&args[1]))
+                }
+                _ => format!("({} {})", con, show_pat(arg)),
+            },
+            PatKind::Tuple(pats) => {
+                let pats: Vec<_> = pats.iter().map(show_pat).collect();
+                format!("<{}>", pats.join(", "))
+            }
+            kind => format!("{:?}", kind),
+        }
+    }
+
+    /// Resolve the expression bound by the last declaration in `src`.
+    fn exp(src: &str) -> String {
+        let program = program(src).unwrap();
+        match program.items.last().unwrap() {
+            TopDec::Str(StrDec {
+                kind: StrDecKind::Dec(dec),
+                ..
+            }) => match &dec.kind {
+                DecKind::Val { binds, .. } => show_exp(&binds[0].exp),
+                kind => panic!("{:?}", kind),
+            },
+            TopDec::Exp(exp) => show_exp(exp),
+            item => panic!("{:?}", item),
+        }
+    }
+
+    fn pat(src: &str) -> String {
+        let program = program(&format!("val {} = x", src)).unwrap();
+        match &program.items[0] {
+            TopDec::Str(StrDec {
+                kind:
+                    StrDecKind::Dec(Dec {
+                        kind: DecKind::Val { binds, .. },
+                        ..
+                    }),
+                ..
+            }) => show_pat(&binds[0].pat),
+            item => panic!("{:?}", item),
+        }
+    }
+
+    fn clauses(src: &str) -> Vec<(String, Vec<String>)> {
+        let program = program(src).unwrap();
+        match program.items.last().unwrap() {
+            TopDec::Str(StrDec {
+                kind:
+                    StrDecKind::Dec(Dec {
+                        kind: DecKind::Fun { binds, .. },
+                        ..
+                    }),
+                ..
+            }) => binds[0]
+                .clauses
+                .iter()
+                .map(|c| {
+                    let name = c.name.as_ref().unwrap().name.clone();
+                    (name, c.args.iter().map(show_pat).collect())
+                })
+                .collect(),
+            item => panic!("{:?}", item),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(exp("1 + 2 * 3;"), "(+ 1 (* 2 3))");
+        assert_eq!(exp("1 * 2 + 3;"), "(+ (* 1 2) 3)");
+        assert_eq!(exp("1 - 2 - 3;"), "(- (- 1 2) 3)");
+        assert_eq!(exp("a :: b :: c;"), "(:: a (:: b c))");
+        assert_eq!(exp("f x + g y z;"), "(+ (f x) ((g y) z))");
+        assert_eq!(exp("a = b + 1;"), "(= a (+ b 1))");
+        assert_eq!(exp("f o g before h;"), "(before (o f g) h)");
+    }
+
+    #[test]
+    fn declared_fixity() {
+        assert_eq!(exp("infix 5 ++; val x = a ++ b ++ c"), "(++ (++ a b) c)");
+        assert_eq!(exp("infixr 5 ++; val x = a ++ b ++ c"), "(++ a (++ b c))");
+        assert_eq!(exp("infix 9 ++; val x = a ++ b * c"), "(* (++ a b) c)");
+        assert_eq!(exp("nonfix +; val x = + (1, 2)"), "(+ 1 2)");
+        // `op` removes infix status.
+        assert_eq!(exp("val x = op + (1, 2)"), "(+ 1 2)");
+        assert_eq!(exp("val x = foldl op + 0 xs"), "(((foldl +) 0) xs)");
+        // Qualified identifiers are never infix.
+        assert_eq!(exp("val x = Int.+ (1, 2)"), "(Int.+ 1 2)");
+    }
+
+    #[test]
+    fn scoping() {
+        // Fixity declared in a `let` doesn't escape it.
+        assert_eq!(
+            exp("val y = let infix 1 f in a f b end; val x = a f b"),
+            "((a f) b)"
+        );
+        // Only the body of a `local` escapes.
+        assert_eq!(
+            exp("local infix 1 f in infix 1 g end; val x = (a f b, a g b)"),
+            "<((a f) b), (g a b)>"
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(pat("x :: y :: rest"), "(:: x (:: y rest))");
+        assert_eq!(pat("SOME x"), "(SOME x)");
+        assert_eq!(pat("SOME (a, b) :: _"), "(:: (SOME a b) _)");
+        assert_eq!(pat("op :: (a, b)"), "(:: a b)");
+    }
+
+    #[test]
+    fn function_clauses() {
+        assert_eq!(
+            clauses("fun f 0 = 1 | f n = n"),
+            vec![
+                ("f".to_owned(), vec!["0".to_owned()]),
+                ("f".to_owned(), vec!["n".to_owned()]),
+            ]
+        );
+        assert_eq!(
+            clauses("infix 5 ++; fun x ++ y = x"),
+            vec![("++".to_owned(), vec!["<x, y>".to_owned()])]
+        );
+        assert_eq!(
+            clauses("fun op + (a, b) = a"),
+            vec![("+".to_owned(), vec!["<a, b>".to_owned()])]
+        );
+        assert_eq!(
+            clauses("fun (x :: xs) @ ys = x"),
+            vec![("@".to_owned(), vec!["<(:: x xs), ys>".to_owned()])]
+        );
+        assert_eq!(
+            clauses("fun (f o g) x = f (g x)"),
+            vec![("o".to_owned(), vec!["<f, g>".to_owned(), "x".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            program("infix 5 ++; infixr 5 **; val x = a ++ b ** c").unwrap_err(),
+            vec!["`++` and `**` have the same precedence but different associativity"]
+        );
+        assert_eq!(
+            program("val x = + 1").unwrap_err(),
+            vec!["infix operator `+` is missing its left operand"]
+        );
+        assert_eq!(
+            program("val x = 1 +").unwrap_err(),
+            vec!["infix operator `+` is missing its right operand"]
+        );
+        assert_eq!(
+            program("val f = +").unwrap_err(),
+            vec!["infix operator `+` used without operands; use `op +`"]
+        );
+        assert_eq!(
+            program("val SOME x y = z").unwrap_err(),
+            vec!["too many arguments in constructor pattern"]
+        );
+        assert_eq!(
+            program("fun f 0 = 1 | g n = n").unwrap_err(),
+            vec!["clauses don't all have the same function name: expected `f`, found `g`"]
+        );
+        assert_eq!(
+            program("fun f 0 = 1 | f a b = 2").unwrap_err(),
+            vec!["clauses don't all have the same number of arguments"]
+        );
+        assert_eq!(
+            program("fun + (a, b) = a").unwrap_err(),
+            vec!["infix operator `+` used as a prefix function; use `op +`"]
+        );
+    }
+}
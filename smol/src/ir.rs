@@ -0,0 +1,513 @@
+//! A continuation-passing intermediate representation, for the compiled
+//! backends.
+//!
+//! Every intermediate value is named by a variable, and all control flow is
+//! explicit: a function is called with the continuation it returns to and the
+//! handler continuation it raises to, so `raise` is just a jump to the
+//! handler in scope. Continuations are second class. They're local to the
+//! function that binds them, can only be jumped to or passed to calls, and
+//! never escape, so a backend can compile them to basic blocks.
+//!
+//! Values are untyped and uniformly represented. Overloading, record labels
+//! and constructors have all been resolved to explicit operations, tags and
+//! field positions.
+//!
+//! Lowering from a checked program gives nested functions (`Term::LetFun`),
+//! either once for values of every type, or specialised for each type they're
+//! used at (see `Representation`).
+//! `closure::convert` then moves every function to the top level, making
+//! closures explicit (`Term::LetClosures` and `Exp::Free`), or lambda lifting
+//! the ones that are only called (`Callee::Direct`). Programs print
+//! in a readable form with `Display`, and `check::check` checks that one is
+//! well formed at either stage.
+
+use std::collections::BTreeMap;
+
+use crate::ast::Const;
+
+pub mod check;
+pub mod closure;
+mod lower;
+pub mod opt;
+mod print;
+
+pub use lower::{lower, lower_steps, lower_with, Representation};
+
+/// A variable, bound once in each function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Var(pub u32);
+
+/// A continuation, bound once in each function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cont(pub u32);
+
+/// The index of a function in `Program::funs`.
+pub type FunId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The names variables were made from, indexed by `Var`, for printing.
+    pub vars: Vec<String>,
+    /// How many continuations have been made.
+    pub conts: u32,
+    /// The program's top level. It has no parameters, and returns unit when
+    /// the program finishes.
+    pub main: Fun,
+    /// The functions closure conversion moved to the top level.
+    pub funs: Vec<Fun>,
+    /// How many global variables closure conversion made, for the top level
+    /// values functions use.
+    pub globals: u32,
+    /// The names of exceptions, by id, for `exnName`.
+    pub exns: BTreeMap<u32, String>,
+}
+
+impl Program {
+    /// A new variable, named after `name`.
+    pub fn var(&mut self, name: &str) -> Var {
+        self.vars.push(name.to_owned());
+        Var(self.vars.len() as u32 - 1)
+    }
+
+    pub fn cont(&mut self) -> Cont {
+        self.conts += 1;
+        Cont(self.conts - 1)
+    }
+
+    pub fn name(&self, var: Var) -> &str {
+        &self.vars[var.0 as usize]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fun {
+    /// The variable the function's closure is bound to. Inside the function
+    /// it refers to the function itself.
+    pub name: Var,
+    /// One parameter, the function's argument. After closure conversion a
+    /// function's closure comes first.
+    pub params: Vec<Var>,
+    pub ret: Cont,
+    pub handler: Cont,
+    pub body: Term,
+}
+
+/// A continuation and the body it jumps to. Its body can jump to itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContDef {
+    pub cont: Cont,
+    pub params: Vec<Var>,
+    pub body: Term,
+}
+
+/// A closure of a top level function, capturing the values of its free
+/// variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub var: Var,
+    pub fun: FunId,
+    pub free: Vec<Var>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Let(Var, Exp, Box<Term>),
+    /// `let var = op args` for an operation that can raise one of the built
+    /// in exceptions, which jumps to `handler`.
+    Checked {
+        var: Var,
+        op: PrimOp,
+        args: Vec<Var>,
+        handler: Cont,
+        body: Box<Term>,
+    },
+    LetCont(Box<ContDef>, Box<Term>),
+    /// Mutually recursive functions.
+    LetFun(Vec<Fun>, Box<Term>),
+    /// Closures that can capture each other.
+    LetClosures(Vec<Closure>, Box<Term>),
+    Call {
+        callee: Callee,
+        args: Vec<Var>,
+        ret: Cont,
+        handler: Cont,
+    },
+    Jump(Cont, Vec<Var>),
+    /// Jump to the first continuation if the bool is true, else the second.
+    /// Neither takes parameters.
+    If(Var, Cont, Cont),
+    /// Jump to the continuation for an int value, or to `default`. Without a
+    /// default, the cases cover every value the scrutinee can have.
+    Switch {
+        scrutinee: Var,
+        cases: Vec<(i64, Cont)>,
+        default: Option<Cont>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    /// A function value.
+    Closure(Var),
+    /// A primitive of the Basis, from the structure `Prim`, implemented by
+    /// the runtime.
+    Native(&'static str),
+    /// A lambda lifted function, given its free variables before its
+    /// argument.
+    Direct(FunId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exp {
+    Const(Const),
+    /// Records and tuples, with their fields in label order. The empty
+    /// record is unit.
+    Record(Vec<Var>),
+    Select(usize, Var),
+    /// A value of a datatype other than `ref`: the constructor's tag, and
+    /// its argument if it has one.
+    Con(u32, Option<Var>),
+    /// The tag of a datatype value's constructor.
+    Tag(Var),
+    ConArg(Var),
+    /// An exception, by id, and its argument if it has one.
+    Exn(u32, Option<Var>),
+    ExnId(Var),
+    ExnArg(Var),
+    Prim(PrimOp, Vec<Var>),
+    /// The nth value a closure captured.
+    Free(usize, Var),
+    Global(u32),
+    /// Set a global variable, giving unit.
+    SetGlobal(u32, Var),
+}
+
+/// Primitive operations. Overloaded operators have been resolved to the
+/// operation for their type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrimOp {
+    // These can raise Overflow or Div.
+    IntAdd,
+    IntSub,
+    IntMul,
+    IntDiv,
+    IntMod,
+    IntNeg,
+    IntAbs,
+    WordDiv,
+    WordMod,
+
+    /// Comparisons of ints, and of other values represented by ints: chars,
+    /// and constructors without arguments.
+    IntLt,
+    IntLe,
+    IntGt,
+    IntGe,
+    IntEq,
+    WordAdd,
+    WordSub,
+    WordMul,
+    WordNeg,
+    WordLt,
+    WordLe,
+    WordGt,
+    WordGe,
+    RealAdd,
+    RealSub,
+    RealMul,
+    RealDiv,
+    RealNeg,
+    RealAbs,
+    RealLt,
+    RealLe,
+    RealGt,
+    RealGe,
+    StringLt,
+    StringLe,
+    StringGt,
+    StringGe,
+    StringConcat,
+    /// Structural equality, for equality types.
+    Equal,
+    Not,
+    Ref,
+    Deref,
+    Assign,
+    Print,
+}
+
+impl PrimOp {
+    pub const ALL: [PrimOp; 43] = {
+        use PrimOp::*;
+        [
+            IntAdd,
+            IntSub,
+            IntMul,
+            IntDiv,
+            IntMod,
+            IntNeg,
+            IntAbs,
+            WordDiv,
+            WordMod,
+            IntLt,
+            IntLe,
+            IntGt,
+            IntGe,
+            IntEq,
+            WordAdd,
+            WordSub,
+            WordMul,
+            WordNeg,
+            WordLt,
+            WordLe,
+            WordGt,
+            WordGe,
+            RealAdd,
+            RealSub,
+            RealMul,
+            RealDiv,
+            RealNeg,
+            RealAbs,
+            RealLt,
+            RealLe,
+            RealGt,
+            RealGe,
+            StringLt,
+            StringLe,
+            StringGt,
+            StringGe,
+            StringConcat,
+            Equal,
+            Not,
+            Ref,
+            Deref,
+            Assign,
+            Print,
+        ]
+    };
+
+    /// Whether the operation can raise an exception, so it must be used
+    /// with `Term::Checked`.
+    pub fn can_raise(self) -> bool {
+        use PrimOp::*;
+        matches!(
+            self,
+            IntAdd | IntSub | IntMul | IntDiv | IntMod | IntNeg | IntAbs | WordDiv | WordMod
+        )
+    }
+
+    pub fn name(self) -> &'static str {
+        use PrimOp::*;
+        match self {
+            IntAdd => "int_add",
+            IntSub => "int_sub",
+            IntMul => "int_mul",
+            IntDiv => "int_div",
+            IntMod => "int_mod",
+            IntNeg => "int_neg",
+            IntAbs => "int_abs",
+            WordDiv => "word_div",
+            WordMod => "word_mod",
+            IntLt => "int_lt",
+            IntLe => "int_le",
+            IntGt => "int_gt",
+            IntGe => "int_ge",
+            IntEq => "int_eq",
+            WordAdd => "word_add",
+            WordSub => "word_sub",
+            WordMul => "word_mul",
+            WordNeg => "word_neg",
+            WordLt => "word_lt",
+            WordLe => "word_le",
+            WordGt => "word_gt",
+            WordGe => "word_ge",
+            RealAdd => "real_add",
+            RealSub => "real_sub",
+            RealMul => "real_mul",
+            RealDiv => "real_div",
+            RealNeg => "real_neg",
+            RealAbs => "real_abs",
+            RealLt => "real_lt",
+            RealLe => "real_le",
+            RealGt => "real_gt",
+            RealGe => "real_ge",
+            StringLt => "string_lt",
+            StringLe => "string_le",
+            StringGt => "string_gt",
+            StringGe => "string_ge",
+            StringConcat => "string_concat",
+            Equal => "equal",
+            Not => "not",
+            Ref => "ref",
+            Deref => "deref",
+            Assign => "assign",
+            Print => "print",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Checker;
+    use crate::{fixity, matching};
+    use parsegen::SourceMap;
+
+    /// Lower a program, without the Basis.
+    pub(super) fn lower_str(src: &str) -> Program {
+        lower_str_with(src, Representation::Uniform)
+    }
+
+    pub(super) fn lower_str_with(src: &str, representation: Representation) -> Program {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let render = |diags: Vec<crate::diagnostic::Diagnostic>| -> String {
+            diags.iter().map(|d| d.render(&sources)).collect()
+        };
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        fixity::resolve(&mut program).unwrap();
+        let mut checker = Checker::new();
+        if let Err(diags) = checker.check_program(&program) {
+            panic!("{}", render(diags));
+        }
+        let (matches, _) = matching::compile_program(&program, &checker.info, &checker.tycons);
+        lower_with(&[program], &checker.info, &matches, representation)
+            .unwrap_or_else(|diags| panic!("{}", render(diags)))
+    }
+
+    /// Lower a program after the Basis, on a thread with enough stack.
+    pub(super) fn lower_basis_with(src: &str, representation: Representation) -> Program {
+        let src = src.to_owned();
+        let lower = move || {
+            let mut sources = SourceMap::new();
+            let mut checker = Checker::new();
+            let mut fixity = fixity::Env::basis();
+            let mut programs = Vec::new();
+            let mut matches = matching::Matches::new();
+            let mut files = vec![("prim.sml", crate::basis::PRIM)];
+            files.extend(crate::basis::FILES.iter().copied());
+            files.push(("test.sml", &src));
+            for (i, (name, src)) in files.into_iter().enumerate() {
+                let file = sources.add(name, src);
+                let mut program = crate::lower::parse(&sources, file).unwrap();
+                fixity::resolve_with(&mut fixity, &mut program).unwrap();
+                checker.check_program(&program).unwrap();
+                if i == 0 {
+                    let prim = checker.sigs[crate::basis::PRIM_SIG].env.clone();
+                    checker.declare_structure("Prim", prim);
+                }
+                let (compiled, _) =
+                    matching::compile_program(&program, &checker.info, &checker.tycons);
+                matches.extend(compiled);
+                programs.push(program);
+            }
+            lower_with(&programs, &checker.info, &matches, representation).unwrap()
+        };
+        std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(lower)
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    /// Every expression bound in a term, including in nested functions.
+    pub(super) fn exps(term: &Term) -> Vec<&Exp> {
+        let mut exps = Vec::new();
+        let mut terms = vec![term];
+        while let Some(term) = terms.pop() {
+            match term {
+                Term::Let(_, exp, body) => {
+                    exps.push(exp);
+                    terms.push(body);
+                }
+                Term::Checked { body, .. } | Term::LetClosures(_, body) => terms.push(body),
+                Term::LetCont(def, body) => terms.extend([&def.body, &**body]),
+                Term::LetFun(funs, body) => {
+                    terms.extend(funs.iter().map(|fun| &fun.body));
+                    terms.push(body);
+                }
+                Term::Call { .. } | Term::Jump(_, _) | Term::If(_, _, _) | Term::Switch { .. } => {}
+            }
+        }
+        exps
+    }
+
+    fn ops(program: &Program) -> Vec<PrimOp> {
+        let mut ops: Vec<_> = exps(&program.main.body)
+            .into_iter()
+            .filter_map(|exp| match exp {
+                Exp::Prim(op, _) => Some(*op),
+                _ => None,
+            })
+            .collect();
+        ops.sort_by_key(|op| op.name());
+        ops
+    }
+
+    #[test]
+    fn overloads() {
+        let program = lower_str("val a = 0w1 + 0w2 val b = 1.0 < 2.0 val c = \"a\" ^ \"b\"");
+        assert_eq!(
+            ops(&program),
+            vec![PrimOp::RealLt, PrimOp::StringConcat, PrimOp::WordAdd]
+        );
+        let program = lower_str("val a = 1 = 2 val b = \"a\" <> \"b\"");
+        assert_eq!(
+            ops(&program),
+            vec![PrimOp::Equal, PrimOp::IntEq, PrimOp::Not]
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let program = lower_str("val a = 1 + 2 * 3");
+        let mut ops = Vec::new();
+        let mut term = &program.main.body;
+        loop {
+            match term {
+                Term::Let(_, _, body) => term = body,
+                Term::Checked { op, body, .. } => {
+                    ops.push(*op);
+                    term = body;
+                }
+                _ => break,
+            }
+        }
+        assert_eq!(ops, vec![PrimOp::IntMul, PrimOp::IntAdd]);
+    }
+
+    #[test]
+    fn specialised() {
+        let src = "
+            fun member (x, []) = false
+              | member (x, y :: ys) = x = y orelse member (x, ys)
+            fun unused x = x
+            val a = member (1, [1, 2])
+            val b = member (\"a\", [\"b\"])
+        ";
+        let program = lower_str(src);
+        assert_eq!(ops(&program), vec![PrimOp::Equal]);
+        assert!(program.vars.iter().any(|name| name == "unused"));
+
+        let program = lower_str_with(src, Representation::Specialised);
+        assert_eq!(ops(&program), vec![PrimOp::Equal, PrimOp::IntEq]);
+        let members = program.vars.iter().filter(|name| *name == "member");
+        assert_eq!(members.count(), 2);
+        assert!(!program.vars.iter().any(|name| name == "unused"));
+    }
+
+    #[test]
+    fn defunctorised() {
+        let src = "
+            functor F (X : sig val x : int end) = struct val y = X.x + 1 end
+            structure A = F (struct val x = 1 end)
+            structure B = F (struct val x = 2 end)
+            val z = A.y + B.y
+        ";
+        let program = lower_str(src);
+        assert!(program.vars.iter().any(|name| name == "F"));
+        let program = lower_str_with(src, Representation::Specialised);
+        assert!(!program.vars.iter().any(|name| name == "F"));
+    }
+}
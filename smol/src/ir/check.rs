@@ -0,0 +1,494 @@
+//! Checking that the IR is well formed, so that a pass that breaks it is
+//! caught where it happens rather than in a backend.
+//!
+//! Variables must be bound before they're used, and at most once in each
+//! function. Continuations are local to the function that binds them, are
+//! jumped to with as many values as they have parameters, and the targets of
+//! `if` and `switch` have none. Closures must agree with the functions they
+//! close over on how many values they capture, and calls of functions that
+//! aren't closures with the functions they call on how many arguments they
+//! take.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use super::*;
+use crate::eval::NATIVES;
+
+/// Something wrong with a program, and the function it's in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub fun: String,
+    pub message: String,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "in {}: {}", self.fun, self.message)
+    }
+}
+
+/// Check a program, either straight from lowering or closure converted.
+pub fn check(program: &Program) -> Result<(), Vec<Error>> {
+    let mut checker = Checker {
+        program,
+        vars: HashSet::new(),
+        undo: Vec::new(),
+        conts: HashMap::new(),
+        arities: HashMap::new(),
+        bound: HashSet::new(),
+        captures: HashMap::new(),
+        frees: HashMap::new(),
+        current: None,
+        fun: program.name(program.main.name).to_owned(),
+        errors: Vec::new(),
+    };
+    checker.fun(&program.main, None);
+    for (id, fun) in program.funs.iter().enumerate() {
+        checker.vars.clear();
+        checker.fun(fun, Some(id));
+    }
+    let mut frees: Vec<_> = checker
+        .frees
+        .iter()
+        .map(|(id, used)| (*id, *used))
+        .collect();
+    frees.sort();
+    for (id, used) in frees {
+        match checker.captures.get(&id).copied() {
+            Some(captured) if captured < used => {
+                let fun = &program.funs[id];
+                checker.fun = program.name(fun.name).to_owned();
+                checker.error(format!(
+                    "closures capture {} values, but it uses {}",
+                    captured, used
+                ));
+            }
+            _ => (),
+        }
+    }
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// How many arguments an operation takes.
+fn arity(op: PrimOp) -> usize {
+    use PrimOp::*;
+    match op {
+        IntNeg | IntAbs | WordNeg | RealNeg | RealAbs | Not | Ref | Deref | Print => 1,
+        _ => 2,
+    }
+}
+
+struct Checker<'p> {
+    program: &'p Program,
+    /// The variables in scope.
+    vars: HashSet<Var>,
+    /// The variables bound since entering each scope, to unbind on leaving.
+    undo: Vec<Var>,
+    /// The continuations in scope, and how many parameters they have.
+    conts: HashMap<Cont, usize>,
+    /// How many parameters functions bound by `LetFun` have.
+    arities: HashMap<Var, usize>,
+    /// The variables bound in the current function.
+    bound: HashSet<Var>,
+    /// How many values closures of each function capture.
+    captures: HashMap<FunId, usize>,
+    /// How many captured values each function uses, at least.
+    frees: HashMap<FunId, usize>,
+    /// The top level function being checked.
+    current: Option<FunId>,
+    fun: String,
+    errors: Vec<Error>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String) {
+        self.errors.push(Error {
+            fun: self.fun.clone(),
+            message,
+        });
+    }
+
+    fn bind(&mut self, var: Var) {
+        if !self.bound.insert(var) {
+            self.error(format!(
+                "`{}.{}` is bound twice",
+                self.program.name(var),
+                var.0
+            ));
+        }
+        if self.vars.insert(var) {
+            self.undo.push(var);
+        }
+    }
+
+    fn unbind_to(&mut self, mark: usize) {
+        for var in self.undo.drain(mark..) {
+            self.vars.remove(&var);
+        }
+    }
+
+    fn use_var(&mut self, var: Var) {
+        if !self.vars.contains(&var) {
+            let name = self.program.vars.get(var.0 as usize).cloned();
+            self.error(format!(
+                "`{}.{}` is used out of scope",
+                name.as_deref().unwrap_or("?"),
+                var.0
+            ));
+        }
+    }
+
+    fn use_cont(&mut self, cont: Cont, args: usize) {
+        match self.conts.get(&cont) {
+            None => self.error(format!("{} is used out of scope", cont)),
+            Some(&params) if params != args => self.error(format!(
+                "{} takes {} values, but is given {}",
+                cont, params, args
+            )),
+            Some(_) => (),
+        }
+    }
+
+    /// Check a function. `id` is set for top level functions, which can use
+    /// their closure's free variables.
+    fn fun(&mut self, fun: &Fun, id: Option<FunId>) {
+        let conts = std::mem::take(&mut self.conts);
+        let bound = std::mem::take(&mut self.bound);
+        let name = std::mem::replace(&mut self.fun, self.program.name(fun.name).to_owned());
+        let mark = self.undo.len();
+
+        self.conts.insert(fun.ret, 1);
+        self.conts.insert(fun.handler, 1);
+        for param in &fun.params {
+            self.bind(*param);
+        }
+        let outer = std::mem::replace(&mut self.current, id);
+        self.term(&fun.body);
+        self.current = outer;
+
+        self.unbind_to(mark);
+        self.conts = conts;
+        self.bound = bound;
+        self.fun = name;
+    }
+
+    fn term(&mut self, mut term: &Term) {
+        let mark = self.undo.len();
+        let mut conts = Vec::new();
+        loop {
+            match term {
+                Term::Let(var, exp, body) => {
+                    self.exp(exp);
+                    self.bind(*var);
+                    term = body;
+                }
+                Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body,
+                } => {
+                    if !op.can_raise() {
+                        self.error(format!("`{}` can't raise, but is checked", op.name()));
+                    }
+                    self.prim(*op, args);
+                    self.use_cont(*handler, 1);
+                    self.bind(*var);
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    // A continuation can jump to itself.
+                    self.conts.insert(def.cont, def.params.len());
+                    conts.push(def.cont);
+                    let inner = self.undo.len();
+                    for param in &def.params {
+                        self.bind(*param);
+                    }
+                    self.term(&def.body);
+                    self.unbind_to(inner);
+                    term = body;
+                }
+                Term::LetFun(funs, body) => {
+                    for fun in funs {
+                        self.bind(fun.name);
+                        self.arities.insert(fun.name, fun.params.len());
+                    }
+                    for fun in funs {
+                        self.fun(fun, None);
+                    }
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    for closure in closures {
+                        self.bind(closure.var);
+                    }
+                    for closure in closures {
+                        for var in &closure.free {
+                            self.use_var(*var);
+                        }
+                        self.closure(closure);
+                    }
+                    term = body;
+                }
+                Term::Call {
+                    callee,
+                    args,
+                    ret,
+                    handler,
+                } => {
+                    let params = match callee {
+                        Callee::Closure(var) => {
+                            self.use_var(*var);
+                            self.arities.get(var).copied().unwrap_or(1)
+                        }
+                        Callee::Native(name) => {
+                            if !NATIVES.iter().any(|native| native.name == *name) {
+                                self.error(format!("there's no primitive `{}`", name));
+                            }
+                            1
+                        }
+                        Callee::Direct(id) => match self.program.funs.get(*id) {
+                            Some(fun) => fun.params.len(),
+                            None => {
+                                self.error(format!("there's no function #{}", id));
+                                args.len()
+                            }
+                        },
+                    };
+                    if args.len() != params {
+                        self.error(format!(
+                            "a call has {} arguments, not {}",
+                            args.len(),
+                            params
+                        ));
+                    }
+                    for arg in args {
+                        self.use_var(*arg);
+                    }
+                    self.use_cont(*ret, 1);
+                    self.use_cont(*handler, 1);
+                    break;
+                }
+                Term::Jump(cont, args) => {
+                    for arg in args {
+                        self.use_var(*arg);
+                    }
+                    self.use_cont(*cont, args.len());
+                    break;
+                }
+                Term::If(var, a, b) => {
+                    self.use_var(*var);
+                    self.use_cont(*a, 0);
+                    self.use_cont(*b, 0);
+                    break;
+                }
+                Term::Switch {
+                    scrutinee,
+                    cases,
+                    default,
+                } => {
+                    self.use_var(*scrutinee);
+                    let mut seen = HashSet::new();
+                    for (n, cont) in cases {
+                        if !seen.insert(n) {
+                            self.error(format!("a switch has two cases for {}", n));
+                        }
+                        self.use_cont(*cont, 0);
+                    }
+                    match default {
+                        Some(cont) => self.use_cont(*cont, 0),
+                        None if cases.is_empty() => self.error("a switch has no cases".to_owned()),
+                        None => (),
+                    }
+                    break;
+                }
+            }
+        }
+        self.unbind_to(mark);
+        for cont in conts {
+            self.conts.remove(&cont);
+        }
+    }
+
+    fn closure(&mut self, closure: &Closure) {
+        let fun = match self.program.funs.get(closure.fun) {
+            Some(fun) => fun,
+            None => return self.error(format!("there's no function #{}", closure.fun)),
+        };
+        if fun.params.len() != 2 || fun.params[0] != fun.name {
+            self.error(format!(
+                "`{}` has a closure, so it should take it and one argument",
+                self.program.name(fun.name)
+            ));
+        }
+        let free = closure.free.len();
+        let expected = *self.captures.entry(closure.fun).or_insert(free);
+        if expected != free {
+            self.error(format!(
+                "closures of `{}` capture both {} and {} values",
+                self.program.name(fun.name),
+                expected,
+                free
+            ));
+        }
+    }
+
+    fn prim(&mut self, op: PrimOp, args: &[Var]) {
+        if args.len() != arity(op) {
+            self.error(format!(
+                "`{}` takes {} arguments, but is given {}",
+                op.name(),
+                arity(op),
+                args.len()
+            ));
+        }
+        for arg in args {
+            self.use_var(*arg);
+        }
+    }
+
+    fn exp(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Prim(op, args) => {
+                if op.can_raise() {
+                    self.error(format!("`{}` can raise, so it must be checked", op.name()));
+                }
+                self.prim(*op, args);
+            }
+            Exp::Exn(id, _) if !self.program.exns.contains_key(id) => {
+                self.error(format!("exception {} has no name", id))
+            }
+            Exp::Global(global) | Exp::SetGlobal(global, _) if *global >= self.program.globals => {
+                self.error(format!("there's no global {}", global))
+            }
+            // Linked closures' values can be other closures, whose sizes
+            // aren't known here.
+            Exp::Free(i, closure) => match self.current {
+                Some(id) if *closure == self.program.funs[id].name => {
+                    let used = self.frees.entry(id).or_insert(0);
+                    *used = (*used).max(i + 1);
+                }
+                Some(_) => (),
+                None => self.error("only top level functions have closures".to_owned()),
+            },
+            _ => (),
+        }
+        for var in super::closure::exp_uses(exp) {
+            self.use_var(var);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{lower_basis_with, lower_str_with};
+    use super::*;
+    use crate::ir::{closure, Representation};
+
+    fn messages(program: &Program) -> Vec<String> {
+        match check(program) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(Error::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn lowered_programs() {
+        let programs = [
+            "fun fact 0 = 1 | fact n = n * fact (n - 1) val x = fact 5",
+            "datatype t = A | B of int * t
+             fun sum A = 0 | sum (B (n, t)) = n + sum t
+             val r = ref 0
+             val _ = while !r < 10 do r := !r + sum (B (1, A))",
+            "exception E of string
+             fun f x = (if x then raise E \"no\" else 1) handle E s => 2 | Div => 3
+             val s = case \"a\" of \"a\" => 0w1 | _ => 0w2",
+            "structure S = struct val x = 1 fun f y = x + y end
+             functor F (X : sig val x : int end) = struct val y = X.x end
+             structure T = F (S)
+             val z = S.f T.y",
+            "val {a, ...} = {a = 1, b = 2} val f = #b : {a : int, b : int} -> int val g = (op +) o (fn x => (x, 1))",
+        ];
+        let options = [closure::Closures::Flat, closure::Closures::Linked]
+            .iter()
+            .flat_map(|closures| {
+                [true, false].iter().map(move |lift| closure::Options {
+                    closures: *closures,
+                    lift: *lift,
+                })
+            });
+        let representations = [Representation::Uniform, Representation::Specialised];
+        for options in options {
+            for (src, representation) in programs.iter().flat_map(|src| {
+                representations
+                    .iter()
+                    .map(move |representation| (src, *representation))
+            }) {
+                let mut program = lower_str_with(src, representation);
+                assert_eq!(messages(&program), Vec::<String>::new(), "{}", program);
+                closure::convert_with(&mut program, options);
+                assert_eq!(messages(&program), Vec::<String>::new(), "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn basis() {
+        for representation in [Representation::Uniform, Representation::Specialised] {
+            let src = "val _ = print (Int.toString (length [1, 2]))";
+            let mut program = lower_basis_with(src, representation);
+            assert_eq!(messages(&program), Vec::<String>::new());
+            closure::convert(&mut program);
+            assert_eq!(messages(&program), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let mut program = lower_str_with("val x = 1", Representation::Uniform);
+        let x = Var(1);
+        let y = program.var("y");
+        let k = program.cont();
+        program.main.body = Term::Let(
+            x,
+            Exp::Record(vec![y]),
+            Box::new(Term::LetCont(
+                Box::new(ContDef {
+                    cont: k,
+                    params: vec![x],
+                    body: Term::Jump(program.main.ret, vec![x]),
+                }),
+                Box::new(Term::Jump(k, Vec::new())),
+            )),
+        );
+        assert_eq!(
+            messages(&program),
+            vec![
+                "in main: `y.3` is used out of scope",
+                "in main: `c.1` is bound twice",
+                "in main: k2 takes 1 values, but is given 0",
+            ]
+        );
+
+        program.main.body = Term::Let(
+            x,
+            Exp::Prim(PrimOp::IntAdd, vec![]),
+            Box::new(Term::If(x, program.main.ret, program.main.ret)),
+        );
+        assert_eq!(
+            messages(&program),
+            vec![
+                "in main: `int_add` can raise, so it must be checked",
+                "in main: `int_add` takes 2 arguments, but is given 0",
+                "in main: k0 takes 1 values, but is given 0",
+                "in main: k0 takes 1 values, but is given 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,738 @@
+//! Closure conversion and lambda lifting.
+//!
+//! Every function is moved to `Program::funs`. A function that escapes, by
+//! being used as a value, gets the values of its free variables from its
+//! closure: its first parameter is its own closure, named after the function
+//! as it was before, so recursive uses stay as they were. Closures are flat,
+//! with a copy of every free variable, or linked, pointing to the closure of
+//! the function they're nested in for the free variables they share with it.
+//!
+//! A function that's only ever called, which is most of them, is lambda
+//! lifted instead: it takes its free variables as extra parameters, before
+//! its argument, and calls to it become direct calls passing them. It has no
+//! closure at all. The free variables of the functions a function calls
+//! directly are its free variables too, so they're found together.
+//!
+//! Values bound at the top level, outside of loops, are bound once, so the
+//! ones functions use become global variables instead of being copied into
+//! every closure that needs them. This matters for the Basis, whose
+//! functions use each other a lot.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use super::*;
+
+/// How closures hold the values of their free variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Closures {
+    /// Every closure has its own copy of each of them.
+    Flat,
+    /// A closure of a function nested in another that escapes points to the
+    /// other's closure for the free variables they share, so making it copies
+    /// less, but using them follows the links.
+    Linked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub closures: Closures,
+    /// Whether to lambda lift functions that don't escape. The ones that
+    /// take other than one argument, which only optimisation makes, are
+    /// lifted anyway, since closures take one.
+    pub lift: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            closures: Closures::Flat,
+            lift: true,
+        }
+    }
+}
+
+/// Convert a program straight from lowering, with nested functions, to one
+/// with closures, with the default options.
+pub fn convert(program: &mut Program) {
+    convert_with(program, Options::default())
+}
+
+pub fn convert_with(program: &mut Program, options: Options) {
+    let mut analysis = Analysis::default();
+    analysis.fun(program.main.name, None, &program.main.params);
+    analysis.term(&program.main.body, program.main.name);
+    let known: HashSet<Var> = analysis
+        .order
+        .iter()
+        .filter(|fun| !analysis.values.contains(fun))
+        .filter(|fun| options.lift || analysis.funs[fun].arity != 1)
+        .copied()
+        .collect();
+
+    let mut scan = Scan::default();
+    scan.term(&program.main.body, false, false);
+    let mut candidates: Vec<Var> = scan
+        .top
+        .intersection(&scan.in_funs)
+        .filter(|var| !known.contains(var))
+        .copied()
+        .collect();
+    candidates.sort();
+    let globals: HashMap<Var, u32> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(i, var)| (var, i as u32))
+        .collect();
+    program.globals = globals.len() as u32;
+
+    let free = analysis.free(&known, &globals);
+    let mut layouts = HashMap::new();
+    for fun in analysis.order.iter().filter(|fun| !known.contains(fun)) {
+        let info = &analysis.funs[fun];
+        let parent = info
+            .parent
+            .filter(|parent| *parent != program.main.name && !known.contains(parent));
+        let link = match (options.closures, parent) {
+            (Closures::Linked, Some(parent)) if !free[fun].is_disjoint(&free[&parent]) => {
+                Some(parent)
+            }
+            _ => None,
+        };
+        let mut vars: Vec<Var> = link.into_iter().collect();
+        vars.extend(free[fun].iter().filter(|var| match link {
+            Some(parent) => **var != parent && !free[&parent].contains(var),
+            None => true,
+        }));
+        layouts.insert(*fun, Layout { link, vars });
+    }
+
+    let body = std::mem::replace(
+        &mut program.main.body,
+        Term::Jump(program.main.ret, Vec::new()),
+    );
+    let mut converter = Converter {
+        program,
+        globals,
+        known,
+        free,
+        layouts,
+        direct: HashMap::new(),
+    };
+    let body = converter.term(body, true);
+    converter.program.main.body = body;
+}
+
+/// Finds the top level variables functions use.
+#[derive(Default)]
+struct Scan {
+    /// Variables bound at the top level, outside of loops.
+    top: HashSet<Var>,
+    /// Variables used in functions.
+    in_funs: HashSet<Var>,
+}
+
+impl Scan {
+    fn bind(&mut self, var: Var, in_fun: bool, in_loop: bool) {
+        if !in_fun && !in_loop {
+            self.top.insert(var);
+        }
+    }
+
+    fn uses(&mut self, vars: impl IntoIterator<Item = Var>, in_fun: bool) {
+        if in_fun {
+            self.in_funs.extend(vars);
+        }
+    }
+
+    fn term(&mut self, term: &Term, in_fun: bool, in_loop: bool) {
+        match term {
+            Term::Let(var, exp, body) => {
+                self.uses(exp_uses(exp), in_fun);
+                self.bind(*var, in_fun, in_loop);
+                self.term(body, in_fun, in_loop);
+            }
+            Term::Checked {
+                var, args, body, ..
+            } => {
+                self.uses(args.iter().copied(), in_fun);
+                self.bind(*var, in_fun, in_loop);
+                self.term(body, in_fun, in_loop);
+            }
+            Term::LetCont(def, body) => {
+                let in_loop = in_loop || mentions(&def.body, def.cont);
+                for param in &def.params {
+                    self.bind(*param, in_fun, in_loop);
+                }
+                self.term(&def.body, in_fun, in_loop);
+                self.term(body, in_fun, in_loop);
+            }
+            Term::LetFun(funs, body) => {
+                for fun in funs {
+                    self.bind(fun.name, in_fun, in_loop);
+                    self.term(&fun.body, true, in_loop);
+                }
+                self.term(body, in_fun, in_loop);
+            }
+            Term::LetClosures(closures, body) => {
+                for closure in closures {
+                    self.bind(closure.var, in_fun, in_loop);
+                }
+                self.term(body, in_fun, in_loop);
+            }
+            Term::Call { callee, args, .. } => {
+                if let Callee::Closure(var) = callee {
+                    self.uses(Some(*var), in_fun);
+                }
+                self.uses(args.iter().copied(), in_fun);
+            }
+            Term::Jump(_, args) => self.uses(args.iter().copied(), in_fun),
+            Term::If(var, _, _) => self.uses(Some(*var), in_fun),
+            Term::Switch { scrutinee, .. } => self.uses(Some(*scrutinee), in_fun),
+        }
+    }
+}
+
+/// Whether a term jumps to or passes on a continuation.
+fn mentions(term: &Term, cont: Cont) -> bool {
+    match term {
+        Term::Let(_, _, body) | Term::LetFun(_, body) | Term::LetClosures(_, body) => {
+            mentions(body, cont)
+        }
+        Term::Checked { handler, body, .. } => *handler == cont || mentions(body, cont),
+        Term::LetCont(def, body) => mentions(&def.body, cont) || mentions(body, cont),
+        Term::Call { ret, handler, .. } => *ret == cont || *handler == cont,
+        Term::Jump(target, _) => *target == cont,
+        Term::If(_, a, b) => *a == cont || *b == cont,
+        Term::Switch { cases, default, .. } => {
+            cases.iter().any(|(_, target)| *target == cont) || *default == Some(cont)
+        }
+    }
+}
+
+/// The variables an expression uses.
+pub(crate) fn exp_uses(exp: &Exp) -> Vec<Var> {
+    match exp {
+        Exp::Const(_) | Exp::Global(_) => Vec::new(),
+        Exp::Record(vars) | Exp::Prim(_, vars) => vars.clone(),
+        Exp::Con(_, arg) | Exp::Exn(_, arg) => arg.iter().copied().collect(),
+        Exp::Select(_, var)
+        | Exp::Tag(var)
+        | Exp::ConArg(var)
+        | Exp::ExnId(var)
+        | Exp::ExnArg(var)
+        | Exp::Free(_, var)
+        | Exp::SetGlobal(_, var) => vec![*var],
+    }
+}
+
+/// What a function binds and uses, not counting the functions nested in it.
+#[derive(Default)]
+struct FunInfo {
+    /// The function it's nested in, unless it's `main`.
+    parent: Option<Var>,
+    bound: HashSet<Var>,
+    /// Variables used as values.
+    uses: BTreeSet<Var>,
+    /// Variables called.
+    callees: BTreeSet<Var>,
+    children: Vec<Var>,
+    arity: usize,
+}
+
+/// Finds what each function binds and uses, and which functions are used as
+/// values.
+#[derive(Default)]
+struct Analysis {
+    funs: HashMap<Var, FunInfo>,
+    /// The functions other than `main`, each after the ones nested in it.
+    order: Vec<Var>,
+    values: HashSet<Var>,
+}
+
+impl Analysis {
+    fn fun(&mut self, name: Var, parent: Option<Var>, params: &[Var]) {
+        let mut info = FunInfo {
+            parent,
+            arity: params.len(),
+            ..FunInfo::default()
+        };
+        info.bound.insert(name);
+        info.bound.extend(params.iter().copied());
+        self.funs.insert(name, info);
+    }
+
+    fn info(&mut self, fun: Var) -> &mut FunInfo {
+        self.funs.get_mut(&fun).unwrap()
+    }
+
+    fn uses(&mut self, vars: impl IntoIterator<Item = Var>, fun: Var) {
+        for var in vars {
+            self.values.insert(var);
+            self.info(fun).uses.insert(var);
+        }
+    }
+
+    fn term(&mut self, term: &Term, fun: Var) {
+        match term {
+            Term::Let(var, exp, body) => {
+                self.uses(exp_uses(exp), fun);
+                self.info(fun).bound.insert(*var);
+                self.term(body, fun);
+            }
+            Term::Checked {
+                var, args, body, ..
+            } => {
+                self.uses(args.iter().copied(), fun);
+                self.info(fun).bound.insert(*var);
+                self.term(body, fun);
+            }
+            Term::LetCont(def, body) => {
+                self.info(fun).bound.extend(def.params.iter().copied());
+                self.term(&def.body, fun);
+                self.term(body, fun);
+            }
+            Term::LetFun(funs, body) => {
+                for def in funs {
+                    let info = self.info(fun);
+                    info.bound.insert(def.name);
+                    info.children.push(def.name);
+                    self.fun(def.name, Some(fun), &def.params);
+                }
+                for def in funs {
+                    self.term(&def.body, def.name);
+                    self.order.push(def.name);
+                }
+                self.term(body, fun);
+            }
+            Term::LetClosures(closures, body) => {
+                for closure in closures {
+                    self.info(fun).bound.insert(closure.var);
+                    self.uses(closure.free.iter().copied(), fun);
+                }
+                self.term(body, fun);
+            }
+            Term::Call { callee, args, .. } => {
+                if let Callee::Closure(var) = callee {
+                    self.info(fun).callees.insert(*var);
+                }
+                self.uses(args.iter().copied(), fun);
+            }
+            Term::Jump(_, args) => self.uses(args.iter().copied(), fun),
+            Term::If(var, _, _) => self.uses(Some(*var), fun),
+            Term::Switch { scrutinee, .. } => self.uses(Some(*scrutinee), fun),
+        }
+    }
+
+    /// The free variables of each function, other than globals. A function
+    /// needs the free variables of the known functions it calls, and of the
+    /// closures it makes, so this goes round until nothing changes.
+    fn free(
+        &self,
+        known: &HashSet<Var>,
+        globals: &HashMap<Var, u32>,
+    ) -> HashMap<Var, BTreeSet<Var>> {
+        let mut free: HashMap<Var, BTreeSet<Var>> = self
+            .order
+            .iter()
+            .map(|fun| (*fun, BTreeSet::new()))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for fun in &self.order {
+                let info = &self.funs[fun];
+                let mut needs = info.uses.clone();
+                for callee in &info.callees {
+                    match free.get(callee) {
+                        Some(vars) if known.contains(callee) => needs.extend(vars),
+                        _ => {
+                            needs.insert(*callee);
+                        }
+                    }
+                }
+                for child in info.children.iter().filter(|child| !known.contains(child)) {
+                    needs.extend(&free[child]);
+                }
+                let vars: BTreeSet<Var> = needs
+                    .into_iter()
+                    .filter(|var| !info.bound.contains(var) && !globals.contains_key(var))
+                    .collect();
+                if vars != free[fun] {
+                    free.insert(*fun, vars);
+                    changed = true;
+                }
+            }
+        }
+        free
+    }
+}
+
+/// What a function's closures capture.
+struct Layout {
+    /// The function it's nested in, whose closure comes first, if it's linked.
+    link: Option<Var>,
+    vars: Vec<Var>,
+}
+
+struct Converter<'p> {
+    program: &'p mut Program,
+    globals: HashMap<Var, u32>,
+    /// The functions to lambda lift.
+    known: HashSet<Var>,
+    free: HashMap<Var, BTreeSet<Var>>,
+    layouts: HashMap<Var, Layout>,
+    /// Where the lifted functions went.
+    direct: HashMap<Var, FunId>,
+}
+
+impl Converter<'_> {
+    /// Set the globals for variables just bound at the top level.
+    fn set_globals(&mut self, vars: &[Var], mut body: Term) -> Term {
+        for var in vars.iter().rev() {
+            if let Some(&global) = self.globals.get(var) {
+                let unit = self.program.var("unit");
+                body = Term::Let(unit, Exp::SetGlobal(global, *var), Box::new(body));
+            }
+        }
+        body
+    }
+
+    fn term(&mut self, term: Term, main: bool) -> Term {
+        match term {
+            Term::Let(var, exp, body) => {
+                let mut body = self.term(*body, main);
+                if main {
+                    body = self.set_globals(&[var], body);
+                }
+                Term::Let(var, exp, Box::new(body))
+            }
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => {
+                let mut body = self.term(*body, main);
+                if main {
+                    body = self.set_globals(&[var], body);
+                }
+                Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body: Box::new(body),
+                }
+            }
+            Term::LetCont(def, body) => {
+                let ContDef {
+                    cont,
+                    params,
+                    body: def_body,
+                } = *def;
+                let mut def_body = self.term(def_body, main);
+                if main {
+                    def_body = self.set_globals(&params, def_body);
+                }
+                let body = self.term(*body, main);
+                Term::LetCont(
+                    Box::new(ContDef {
+                        cont,
+                        params,
+                        body: def_body,
+                    }),
+                    Box::new(body),
+                )
+            }
+            Term::LetFun(funs, body) => {
+                // Functions can call each other, so they all need their ids
+                // before any is converted.
+                let ids: Vec<FunId> = funs
+                    .iter()
+                    .map(|fun| {
+                        let id = self.program.funs.len();
+                        self.program.funs.push(Fun {
+                            name: fun.name,
+                            params: Vec::new(),
+                            ret: fun.ret,
+                            handler: fun.handler,
+                            body: Term::Jump(fun.ret, Vec::new()),
+                        });
+                        if self.known.contains(&fun.name) {
+                            self.direct.insert(fun.name, id);
+                        }
+                        id
+                    })
+                    .collect();
+                let mut closures = Vec::new();
+                for (fun, id) in funs.into_iter().zip(ids) {
+                    let var = fun.name;
+                    self.program.funs[id] = self.fun(fun);
+                    if let Some(layout) = self.layouts.get(&var) {
+                        closures.push(Closure {
+                            var,
+                            fun: id,
+                            free: layout.vars.clone(),
+                        });
+                    }
+                }
+                let names: Vec<Var> = closures.iter().map(|closure| closure.var).collect();
+                let mut body = self.term(*body, main);
+                if main {
+                    body = self.set_globals(&names, body);
+                }
+                if closures.is_empty() {
+                    body
+                } else {
+                    Term::LetClosures(closures, Box::new(body))
+                }
+            }
+            Term::LetClosures(closures, body) => {
+                let body = self.term(*body, main);
+                Term::LetClosures(closures, Box::new(body))
+            }
+            Term::Call {
+                callee: Callee::Closure(f),
+                args,
+                ret,
+                handler,
+            } if self.direct.contains_key(&f) => {
+                let mut free: Vec<Var> = self.free[&f].iter().copied().collect();
+                free.extend(args);
+                Term::Call {
+                    callee: Callee::Direct(self.direct[&f]),
+                    args: free,
+                    ret,
+                    handler,
+                }
+            }
+            term => term,
+        }
+    }
+
+    /// Where a free variable is in a function's closure: the index of each
+    /// link to follow, then its own.
+    fn path(&self, var: Var, fun: Var) -> Vec<usize> {
+        let layout = &self.layouts[&fun];
+        match layout.vars.iter().position(|captured| *captured == var) {
+            Some(i) => vec![i],
+            None => {
+                let parent = layout.link.expect("a free variable isn't captured");
+                let mut path = vec![0];
+                path.extend(self.path(var, parent));
+                path
+            }
+        }
+    }
+
+    /// Convert a function's body, and give it its free variables.
+    fn fun(&mut self, fun: Fun) -> Fun {
+        let mut body = self.term(fun.body, false);
+
+        let mut lets = Vec::new();
+        let mut params = Vec::new();
+        let free: Vec<Var> = self.free[&fun.name].iter().copied().collect();
+        if self.known.contains(&fun.name) {
+            params.extend(free);
+        } else {
+            params.push(fun.name);
+            let mut links: HashMap<Vec<usize>, Var> = HashMap::new();
+            for var in free {
+                let path = self.path(var, fun.name);
+                let mut closure = fun.name;
+                for depth in 1..path.len() {
+                    closure = match links.get(&path[..depth]) {
+                        Some(link) => *link,
+                        None => {
+                            let link = self.program.var("link");
+                            lets.push((link, Exp::Free(path[depth - 1], closure)));
+                            links.insert(path[..depth].to_vec(), link);
+                            link
+                        }
+                    };
+                }
+                lets.push((var, Exp::Free(path[path.len() - 1], closure)));
+            }
+        }
+        params.extend(fun.params);
+
+        let mut used = BTreeSet::new();
+        uses(&body, &mut used);
+        let mut globals: Vec<(u32, Var)> = used
+            .iter()
+            .filter(|var| !params.contains(var))
+            .filter_map(|var| self.globals.get(var).map(|global| (*global, *var)))
+            .collect();
+        globals.sort();
+        lets.extend(
+            globals
+                .into_iter()
+                .map(|(global, var)| (var, Exp::Global(global))),
+        );
+        for (var, exp) in lets.into_iter().rev() {
+            body = Term::Let(var, exp, Box::new(body));
+        }
+
+        Fun {
+            name: fun.name,
+            params,
+            ret: fun.ret,
+            handler: fun.handler,
+            body,
+        }
+    }
+}
+
+/// The variables a converted function's body uses, not counting the
+/// functions moved out of it.
+fn uses(term: &Term, used: &mut BTreeSet<Var>) {
+    match term {
+        Term::Let(_, exp, body) => {
+            used.extend(exp_uses(exp));
+            uses(body, used);
+        }
+        Term::Checked { args, body, .. } => {
+            used.extend(args);
+            uses(body, used);
+        }
+        Term::LetCont(def, body) => {
+            uses(&def.body, used);
+            uses(body, used);
+        }
+        Term::LetFun(_, body) => uses(body, used),
+        Term::LetClosures(closures, body) => {
+            for closure in closures {
+                used.extend(&closure.free);
+            }
+            uses(body, used);
+        }
+        Term::Call { callee, args, .. } => {
+            if let Callee::Closure(var) = callee {
+                used.insert(*var);
+            }
+            used.extend(args);
+        }
+        Term::Jump(_, args) => used.extend(args),
+        Term::If(var, _, _) => {
+            used.insert(*var);
+        }
+        Term::Switch { scrutinee, .. } => {
+            used.insert(*scrutinee);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{exps, lower_str};
+    use super::*;
+
+    /// The values a function loads from its closure and the globals.
+    fn loads(fun: &Fun) -> Vec<String> {
+        exps(&fun.body)
+            .into_iter()
+            .filter_map(|exp| match exp {
+                Exp::Free(i, _) => Some(format!("free {}", i)),
+                Exp::Global(n) => Some(format!("global {}", n)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn closures(term: &Term) -> &[Closure] {
+        match term {
+            Term::Let(_, _, body) | Term::Checked { body, .. } => closures(body),
+            Term::LetClosures(closures, _) => closures,
+            term => panic!("expected closures, found {:?}", term),
+        }
+    }
+
+    fn find<'p>(program: &'p Program, name: &str) -> &'p Fun {
+        let fun = program
+            .funs
+            .iter()
+            .find(|fun| program.name(fun.name) == name);
+        fun.unwrap_or_else(|| panic!("no function `{}` in\n{}", name, program))
+    }
+
+    #[test]
+    fn globals_and_free_variables() {
+        let mut program = lower_str(
+            "val x = 1
+             fun f y = let fun g z = x + y + z in g end",
+        );
+        convert(&mut program);
+        // `x` is bound once, at the top level.
+        assert_eq!(program.globals, 1);
+        assert_eq!(program.funs.len(), 2);
+        let f = find(&program, "f");
+        let g = find(&program, "g");
+        assert_eq!(g.params.len(), 2);
+        // `g` captures `y`, and reads `x` from its global.
+        assert_eq!(loads(g), vec!["free 0", "global 0"]);
+        assert!(loads(f).is_empty());
+        // `f` doesn't escape, so it has no closure of its own.
+        assert_eq!(closures(&f.body)[0].free, f.params);
+    }
+
+    #[test]
+    fn lifting() {
+        let src = "fun f y = let fun g z = y + z in g 1 + g 2 end
+                   val n = f 3";
+        let mut program = lower_str(src);
+        convert(&mut program);
+        // Neither function escapes, so neither has a closure, and `g` takes
+        // `y` before its argument.
+        assert!(program.funs.iter().all(|fun| loads(fun).is_empty()));
+        let f = find(&program, "f");
+        let g = find(&program, "g");
+        assert_eq!(f.params.len(), 1);
+        assert_eq!(g.params, vec![f.params[0], g.params[1]]);
+        let printed = program.to_string();
+        assert!(!printed.contains("closure"), "{}", printed);
+        assert_eq!(printed.matches("call #").count(), 3, "{}", printed);
+
+        let mut program = lower_str(src);
+        convert_with(
+            &mut program,
+            Options {
+                closures: Closures::Flat,
+                lift: false,
+            },
+        );
+        assert_eq!(program.to_string().matches("closure ").count(), 2);
+        assert!(!program.to_string().contains("call #"));
+    }
+
+    #[test]
+    fn linked() {
+        let src = "fun f x = fn y => fn z => x + y + z
+                   val n = f 1 2 3";
+        let converted = |closures| {
+            let mut program = lower_str(src);
+            convert_with(
+                &mut program,
+                Options {
+                    closures,
+                    lift: true,
+                },
+            );
+            assert_eq!(crate::ir::check::check(&program), Ok(()), "{}", program);
+            program
+        };
+        let program = converted(Closures::Flat);
+        let inner = &program.funs[2];
+        assert_eq!(closures(&program.funs[1].body)[0].free.len(), 2);
+        assert_eq!(loads(inner), vec!["free 0", "free 1"]);
+
+        // The innermost function shares `x` with the one it's nested in, so
+        // it finds it through that one's closure.
+        let program = converted(Closures::Linked);
+        let (middle, inner) = (&program.funs[1], &program.funs[2]);
+        assert_eq!(
+            closures(&middle.body)[0].free,
+            vec![middle.params[0], middle.params[1]]
+        );
+        assert_eq!(loads(inner), vec!["free 0", "free 0", "free 1"]);
+    }
+}
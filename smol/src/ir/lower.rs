@@ -0,0 +1,2122 @@
+//! Lowering checked programs to the IR.
+//!
+//! This is a one-pass CPS conversion. Expressions are lowered with a
+//! continuation that is either a continuation of the IR, or a Rust closure
+//! that builds the rest of the term from the variable holding the value, so
+//! that no administrative continuations are made for straight-line code.
+//!
+//! Matches use the decision trees from `matching`. Modules are resolved
+//! statically: a structure is just the variables its components are bound
+//! to. Functors are functions from a record of their argument's components
+//! to a record of their result's, in the order their shapes give.
+//!
+//! With `Representation::Specialised`, lowering is whole program instead.
+//! Functors are instantiated at each application, lowering their body with
+//! the argument's bindings, so no functor exists at run time. Groups of
+//! functions are lowered once for each type they're used at, with the types
+//! in their bodies specialised to match, so that, for example, `=` on an
+//! `''a` that's an int compares ints. Each instance is lowered after the
+//! rest of the group's scope, when all its uses have been found, so
+//! functions that are never used aren't lowered at all.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use parsegen::Span;
+
+use super::*;
+use crate::ast::{self, ExpKind, Lab, LongId};
+use crate::diagnostic::Diagnostic;
+use crate::eval::{Prim, NATIVES};
+use crate::matching::{self, Access, Decision, Match, Matches, Test};
+use crate::mlb::{ModuleKind, Step};
+use crate::types::{self, ConInfo, ConKind, IdStatus, Info, Subst, Type};
+
+/// How polymorphic functions and functors are compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Representation {
+    /// Every value is one word, whatever its type, so each function and
+    /// functor is compiled once and works for all of them.
+    #[default]
+    Uniform,
+    /// Functions are specialised for each type they're used at, and
+    /// functors for each application, so every function is monomorphic and
+    /// every call of a known function can be direct.
+    Specialised,
+}
+
+/// Lower a checked program whose matches have been compiled, with a uniform
+/// representation. Programs are lowered one after another, as if they were
+/// one program, so the Basis can come first.
+///
+/// Lowering recurses once for each declaration in scope, so programs the
+/// size of the Basis need more stack than a test thread has.
+pub fn lower<'a>(
+    programs: &'a [ast::Program],
+    info: &'a Info,
+    matches: &'a Matches,
+) -> Result<Program, Vec<Diagnostic>> {
+    lower_with(programs, info, matches, Representation::Uniform)
+}
+
+/// Lower a checked program, choosing how polymorphism is compiled.
+pub fn lower_with<'a>(
+    programs: &'a [ast::Program],
+    info: &'a Info,
+    matches: &'a Matches,
+    representation: Representation,
+) -> Result<Program, Vec<Diagnostic>> {
+    let steps: Vec<_> = (0..programs.len()).map(Step::File).collect();
+    lower_steps(programs, &steps, info, matches, representation)
+}
+
+/// Lower the checked programs of an ML Basis file, following the same
+/// steps between bases as they were checked with. `Step::File(i)` lowers
+/// `programs[i]`.
+pub fn lower_steps<'a>(
+    programs: &'a [ast::Program],
+    steps: &'a [Step],
+    info: &'a Info,
+    matches: &'a Matches,
+    representation: Representation,
+) -> Result<Program, Vec<Diagnostic>> {
+    let mut program = Program {
+        vars: Vec::new(),
+        conts: 0,
+        main: Fun {
+            name: Var(0),
+            params: Vec::new(),
+            ret: Cont(0),
+            handler: Cont(1),
+            body: Term::Jump(Cont(0), Vec::new()),
+        },
+        funs: Vec::new(),
+        globals: 0,
+        exns: types::BUILTIN_EXNS
+            .iter()
+            .enumerate()
+            .map(|(id, name)| (id as u32, (*name).to_owned()))
+            .collect(),
+    };
+    let name = program.var("main");
+    let ret = program.cont();
+    let handler = program.cont();
+
+    let mut initial = Bindings::default();
+    for prim in Prim::ALL {
+        initial
+            .values
+            .insert(prim.name().to_owned(), Binding::Prim(*prim));
+    }
+    let natives = Bindings {
+        values: NATIVES
+            .iter()
+            .map(|native| (native.name.to_owned(), Binding::Native(native.name)))
+            .collect(),
+        ..Bindings::default()
+    };
+    initial
+        .structures
+        .insert("Prim".to_owned(), Rc::new(natives));
+
+    let mut lowerer = Lowerer {
+        info,
+        matches,
+        program,
+        env: Env::default().push(initial),
+        representation,
+        subst: Subst::default(),
+        bases: Vec::new(),
+        registers: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    let items: Rc<[Item]> = steps
+        .iter()
+        .flat_map(|step| match step {
+            Step::File(i) => programs[*i].items.iter().map(Item::Dec).collect(),
+            step => vec![Item::Step(step)],
+        })
+        .collect();
+    let body = lowerer.top_decs(
+        items,
+        0,
+        handler,
+        Box::new(move |l| {
+            let unit = l.var("unit");
+            Term::Let(
+                unit,
+                Exp::Record(Vec::new()),
+                Box::new(Term::Jump(ret, vec![unit])),
+            )
+        }),
+    );
+    if !lowerer.diagnostics.is_empty() {
+        return Err(lowerer.diagnostics);
+    }
+    let mut program = lowerer.program;
+    program.main = Fun {
+        name,
+        params: Vec::new(),
+        ret,
+        handler,
+        body,
+    };
+    Ok(program)
+}
+
+// Environments
+
+/// What an identifier stands for.
+#[derive(Debug, Clone)]
+enum Binding<'a> {
+    Var(Var),
+    Prim(Prim),
+    Native(&'static str),
+    /// The nth of a group of functions specialised for each type they're
+    /// used at.
+    Poly(Rc<Poly<'a>>, usize),
+}
+
+/// The bindings of a structure, or of one scope.
+#[derive(Debug, Clone, Default)]
+struct Bindings<'a> {
+    values: HashMap<String, Binding<'a>>,
+    structures: HashMap<String, Rc<Bindings<'a>>>,
+    functors: HashMap<String, Rc<Functor<'a>>>,
+}
+
+impl<'a> Bindings<'a> {
+    fn vars(vars: Vec<(String, Var)>) -> Bindings<'a> {
+        Bindings {
+            values: vars
+                .into_iter()
+                .map(|(name, var)| (name, Binding::Var(var)))
+                .collect(),
+            ..Bindings::default()
+        }
+    }
+
+    fn extend(&mut self, other: &Bindings<'a>) {
+        self.values
+            .extend(other.values.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.structures
+            .extend(other.structures.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.functors
+            .extend(other.functors.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Only the components a signature has.
+    fn restrict(&self, shape: &Shape) -> Bindings<'a> {
+        Bindings {
+            values: shape
+                .values
+                .iter()
+                .filter_map(|name| Some((name.clone(), self.values.get(name)?.clone())))
+                .collect(),
+            structures: shape
+                .structures
+                .iter()
+                .filter_map(|(name, shape)| {
+                    let str = self.structures.get(name)?;
+                    Some((name.clone(), Rc::new(str.restrict(shape))))
+                })
+                .collect(),
+            functors: HashMap::new(),
+        }
+    }
+}
+
+/// The components of a structure, in the order they're stored in records.
+#[derive(Debug, Default)]
+struct Shape {
+    values: Vec<String>,
+    structures: Vec<(String, Shape)>,
+}
+
+impl Shape {
+    /// The shape of a signature. Constructors aren't stored.
+    fn of_env(env: &types::Env) -> Shape {
+        let mut values: Vec<_> = env
+            .values
+            .iter()
+            .filter(|(_, binding)| matches!(binding.status, IdStatus::Var))
+            .map(|(name, _)| name.clone())
+            .collect();
+        values.sort();
+        let mut structures: Vec<_> = env
+            .structures
+            .iter()
+            .map(|(name, env)| (name.clone(), Shape::of_env(env)))
+            .collect();
+        structures.sort_by(|a, b| a.0.cmp(&b.0));
+        Shape { values, structures }
+    }
+
+    fn of(bindings: &Bindings<'_>) -> Shape {
+        let mut values: Vec<_> = bindings.values.keys().cloned().collect();
+        values.sort();
+        let mut structures: Vec<_> = bindings
+            .structures
+            .iter()
+            .map(|(name, str)| (name.clone(), Shape::of(str)))
+            .collect();
+        structures.sort_by(|a, b| a.0.cmp(&b.0));
+        Shape { values, structures }
+    }
+}
+
+#[derive(Debug)]
+enum Functor<'a> {
+    /// A function from a record of the argument's components to a record of
+    /// the result's.
+    Fun {
+        var: Var,
+        param: Shape,
+        result: Shape,
+    },
+    /// A functor lowered at each application, in the scope it was declared
+    /// in.
+    Inline {
+        bind: &'a ast::FunctorBind,
+        env: Env<'a>,
+    },
+}
+
+/// A group of mutually recursive functions, specialised for each type
+/// they're used at.
+#[derive(Debug)]
+struct Poly<'a> {
+    binds: PolyBinds<'a>,
+    /// The functions' names, and their types.
+    names: Vec<(String, Option<Type>)>,
+    /// The scope the functions were declared in, and the types specialising
+    /// it.
+    env: Env<'a>,
+    subst: Subst,
+    /// The instances so far, and the variables each one's functions are
+    /// bound to.
+    instances: RefCell<Vec<(Subst, Vec<Var>)>>,
+}
+
+#[derive(Debug)]
+enum PolyBinds<'a> {
+    Fun(&'a [ast::FunBind]),
+    Rec(Vec<&'a ast::ValBind>),
+}
+
+/// A chain of scopes, shared so that continuations can go back to the
+/// scope they were made in.
+#[derive(Debug, Clone, Default)]
+struct Env<'a>(Option<Rc<Frame<'a>>>);
+
+#[derive(Debug)]
+struct Frame<'a> {
+    bindings: Bindings<'a>,
+    parent: Env<'a>,
+}
+
+impl<'a> Env<'a> {
+    fn push(&self, bindings: Bindings<'a>) -> Env<'a> {
+        Env(Some(Rc::new(Frame {
+            bindings,
+            parent: self.clone(),
+        })))
+    }
+
+    fn frames(&self) -> impl Iterator<Item = &Frame<'a>> {
+        std::iter::successors(self.0.as_deref(), |frame| frame.parent.0.as_deref())
+    }
+
+    fn is(&self, other: &Env<'a>) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    /// Everything bound in the scopes from `base` to here.
+    fn since(&self, base: &Env<'a>) -> Bindings<'a> {
+        let mut frames = Vec::new();
+        let mut env = self;
+        while !env.is(base) {
+            match &env.0 {
+                Some(frame) => {
+                    frames.push(frame);
+                    env = &frame.parent;
+                }
+                None => break,
+            }
+        }
+        let mut bindings = Bindings::default();
+        for frame in frames.iter().rev() {
+            bindings.extend(&frame.bindings);
+        }
+        bindings
+    }
+
+    fn structure(&self, path: &[String]) -> Option<Rc<Bindings<'a>>> {
+        let (first, rest) = path.split_first()?;
+        let mut str = self
+            .frames()
+            .find_map(|frame| frame.bindings.structures.get(first).cloned())?;
+        for strid in rest {
+            let next = str.structures.get(strid)?.clone();
+            str = next;
+        }
+        Some(str)
+    }
+
+    fn value(&self, id: &LongId) -> Option<Binding<'a>> {
+        if id.path.is_empty() {
+            self.frames()
+                .find_map(|frame| frame.bindings.values.get(&id.name).cloned())
+        } else {
+            self.structure(&id.path)?.values.get(&id.name).cloned()
+        }
+    }
+
+    fn functor(&self, name: &str) -> Option<Rc<Functor<'a>>> {
+        self.frames()
+            .find_map(|frame| frame.bindings.functors.get(name).cloned())
+    }
+}
+
+// Continuations
+
+type Meta<'a> = Box<dyn FnOnce(&mut Lowerer<'a>, Var) -> Term + 'a>;
+
+/// Where the value of an expression goes.
+enum Kont<'a> {
+    Cont(Cont),
+    /// Builds the rest of the term. It goes back to the scope it was made in.
+    Meta(Meta<'a>),
+}
+
+/// The rest of the term after a declaration, in the scope it made.
+type DecK<'a> = Box<dyn FnOnce(&mut Lowerer<'a>) -> Term + 'a>;
+type VarsK<'a> = Box<dyn FnOnce(&mut Lowerer<'a>, Vec<Var>) -> Term + 'a>;
+type StrK<'a> = Box<dyn FnOnce(&mut Lowerer<'a>, Rc<Bindings<'a>>) -> Term + 'a>;
+type RuleK<'a, 'f> = &'f mut dyn FnMut(&mut Lowerer<'a>, usize, Vec<(String, Var)>) -> Term;
+type FailK<'a, 'f> = &'f mut dyn FnMut(&mut Lowerer<'a>) -> Term;
+
+/// The parts of the values being matched that have been taken apart so far.
+#[derive(Clone)]
+struct Paths {
+    roots: Vec<Var>,
+    vars: HashMap<Access, Var>,
+    /// The constructor each tested part has.
+    known: HashMap<Access, Rc<ConInfo>>,
+}
+
+fn let_conts(defs: Vec<ContDef>, term: Term) -> Term {
+    defs.into_iter().rev().fold(term, |term, def| {
+        Term::LetCont(Box::new(def), Box::new(term))
+    })
+}
+
+fn lets(lets: Vec<(Var, Exp)>, term: Term) -> Term {
+    lets.into_iter()
+        .rev()
+        .fold(term, |term, (var, exp)| Term::Let(var, exp, Box::new(term)))
+}
+
+/// The type an overloaded operator is used at: its argument's type, or the
+/// type of the first component of a pair.
+fn operand(ty: Option<&Type>) -> Option<Type> {
+    match ty?.resolve() {
+        Type::Arrow(arg, _) => match arg.resolve() {
+            Type::Record(fields) if fields.len() == 2 => {
+                fields.get(&Lab::Num(1)).map(|ty| ty.resolve())
+            }
+            ty => Some(ty),
+        },
+        _ => None,
+    }
+}
+
+fn tycon(ty: &Option<Type>) -> u32 {
+    match ty {
+        Some(Type::Con(tycon, _)) => tycon.id,
+        // Overloads default to int.
+        _ => types::INT,
+    }
+}
+
+/// The name a `val rec` binding binds.
+fn rec_name(pat: &ast::Pat) -> &ast::LongId {
+    match &pat.kind {
+        ast::PatKind::Var { id, .. } => id,
+        ast::PatKind::Typed(pat, _) => rec_name(pat),
+        _ => panic!("`val rec` should bind a variable"),
+    }
+}
+
+/// The `fn` a `val rec` binding binds.
+fn rec_fn(exp: &ast::Exp) -> (&ast::Exp, &[ast::MRule]) {
+    match &exp.kind {
+        ExpKind::Fn(rules) => (exp, rules),
+        ExpKind::Typed(exp, _) => rec_fn(exp),
+        _ => panic!("`val rec` should bind a `fn`"),
+    }
+}
+
+/// A top level declaration, or a step between bases.
+#[derive(Debug, Clone, Copy)]
+enum Item<'a> {
+    Dec(&'a ast::TopDec),
+    Step(&'a Step),
+}
+
+struct Lowerer<'a> {
+    info: &'a Info,
+    matches: &'a Matches,
+    program: Program,
+    env: Env<'a>,
+    representation: Representation,
+    /// The types of the function instance being lowered.
+    subst: Subst,
+    /// The scopes each basis being lowered was entered from, innermost last.
+    bases: Vec<Env<'a>>,
+    /// What was bound in each basis left so far.
+    registers: Vec<Bindings<'a>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn var(&mut self, name: &str) -> Var {
+        self.program.var(name)
+    }
+
+    fn bind(&mut self, bindings: Bindings<'a>) {
+        self.env = self.env.push(bindings);
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    /// The type recorded for a span, specialised to the instance being
+    /// lowered.
+    fn ty(&self, span: Span) -> Option<Type> {
+        self.info.types.get(&span).map(|ty| ty.apply(&self.subst))
+    }
+
+    fn compiled(&self, span: Span) -> &'a Match {
+        self.matches
+            .get(&span)
+            .expect("matches should be compiled before lowering")
+    }
+
+    /// A continuation that builds the rest of the term in the current scope.
+    fn meta(&self, f: impl FnOnce(&mut Lowerer<'a>, Var) -> Term + 'a) -> Kont<'a> {
+        let env = self.env.clone();
+        Kont::Meta(Box::new(move |l, var| {
+            l.env = env;
+            f(l, var)
+        }))
+    }
+
+    /// Give `var` to a continuation.
+    fn ret(&mut self, k: Kont<'a>, var: Var) -> Term {
+        match k {
+            Kont::Cont(cont) => Term::Jump(cont, vec![var]),
+            Kont::Meta(f) => f(self, var),
+        }
+    }
+
+    /// Bind a new variable to `exp`, and give it to `k`.
+    fn let_(&mut self, name: &str, exp: Exp, k: Kont<'a>) -> Term {
+        let var = self.var(name);
+        let body = self.ret(k, var);
+        Term::Let(var, exp, Box::new(body))
+    }
+
+    /// Make `k` a continuation of the IR, for terms that need one.
+    fn reify(&mut self, k: Kont<'a>, f: impl FnOnce(&mut Self, Cont) -> Term) -> Term {
+        match k {
+            Kont::Cont(cont) => f(self, cont),
+            Kont::Meta(meta) => {
+                let cont = self.program.cont();
+                let var = self.var("r");
+                let term = f(self, cont);
+                let body = meta(self, var);
+                Term::LetCont(
+                    Box::new(ContDef {
+                        cont,
+                        params: vec![var],
+                        body,
+                    }),
+                    Box::new(term),
+                )
+            }
+        }
+    }
+
+    /// A continuation without parameters for `body`.
+    fn block(&mut self, body: Term) -> (Cont, ContDef) {
+        let cont = self.program.cont();
+        let def = ContDef {
+            cont,
+            params: Vec::new(),
+            body,
+        };
+        (cont, def)
+    }
+
+    fn raise_builtin(&mut self, name: &str, handler: Cont) -> Term {
+        let id = types::BUILTIN_EXNS
+            .iter()
+            .position(|exn| *exn == name)
+            .expect("a built in exception") as u32;
+        let exn = self.var("exn");
+        Term::Let(
+            exn,
+            Exp::Exn(id, None),
+            Box::new(Term::Jump(handler, vec![exn])),
+        )
+    }
+
+    // Declarations
+
+    fn top_decs(&mut self, items: Rc<[Item<'a>]>, i: usize, h: Cont, k: DecK<'a>) -> Term {
+        let item = match items.get(i) {
+            Some(Item::Dec(item)) => *item,
+            Some(Item::Step(step)) => {
+                self.step(step);
+                return self.top_decs(items, i + 1, h, k);
+            }
+            None => return k(self),
+        };
+        let rest: DecK<'a> = Box::new(move |l| l.top_decs(items, i + 1, h, k));
+        match item {
+            ast::TopDec::Str(dec) => self.str_dec(dec, h, rest),
+            ast::TopDec::Sig(_) => rest(self),
+            ast::TopDec::Functor(binds) => self.functor_dec(binds, rest),
+            ast::TopDec::Exp(exp) => {
+                // `exp` is short for `val it = exp`.
+                let k = self.meta(move |l, var| {
+                    l.bind(Bindings::vars(vec![("it".to_owned(), var)]));
+                    rest(l)
+                });
+                self.exp(exp, h, k)
+            }
+        }
+    }
+
+    /// Follow a step between bases, which are scopes like `local`'s.
+    fn step(&mut self, step: &'a Step) {
+        match step {
+            Step::File(_) => unreachable!("files are lowered as their declarations"),
+            Step::Enter => self.bases.push(self.env.clone()),
+            Step::Leave(_) => {
+                let base = self.bases.pop().expect("a basis was entered");
+                self.registers.push(self.env.since(&base));
+                self.env = base;
+            }
+            Step::Open(register) => self.bind(self.registers[*register].clone()),
+            Step::Alias(kind, binds) => {
+                let mut bindings = Bindings::default();
+                for (new, old) in binds {
+                    let name = new.name.clone();
+                    let found = match kind {
+                        ModuleKind::Structure => {
+                            let str = self.env.structure(std::slice::from_ref(&old.name));
+                            str.map(|str| bindings.structures.insert(name, str))
+                                .is_some()
+                        }
+                        ModuleKind::Functor => {
+                            let functor = self.env.functor(&old.name);
+                            functor
+                                .map(|functor| bindings.functors.insert(name, functor))
+                                .is_some()
+                        }
+                        // Signatures have gone by now.
+                        ModuleKind::Signature => true,
+                    };
+                    if !found {
+                        self.error(old.span, format!("unbound {} `{}`", kind, old.name));
+                    }
+                }
+                self.bind(bindings);
+            }
+        }
+    }
+
+    fn str_decs(&mut self, decs: &'a [ast::StrDec], h: Cont, k: DecK<'a>) -> Term {
+        match decs.split_first() {
+            None => k(self),
+            Some((dec, rest)) => self.str_dec(dec, h, Box::new(move |l| l.str_decs(rest, h, k))),
+        }
+    }
+
+    fn str_dec(&mut self, dec: &'a ast::StrDec, h: Cont, k: DecK<'a>) -> Term {
+        match &dec.kind {
+            ast::StrDecKind::Dec(dec) => self.dec(dec, h, k),
+            ast::StrDecKind::Structure(binds) => self.structures(binds, h, Vec::new(), k),
+            ast::StrDecKind::Local(local, body) => {
+                let base = self.env.clone();
+                self.str_decs(
+                    local,
+                    h,
+                    Box::new(move |l| {
+                        let inner = l.env.clone();
+                        l.str_decs(
+                            body,
+                            h,
+                            Box::new(move |l| {
+                                let bindings = l.env.since(&inner);
+                                l.env = base.push(bindings);
+                                k(l)
+                            }),
+                        )
+                    }),
+                )
+            }
+        }
+    }
+
+    /// `structure a = ... and b = ...`, binding them all at the end.
+    fn structures(
+        &mut self,
+        binds: &'a [ast::StrBind],
+        h: Cont,
+        mut done: Vec<(String, Rc<Bindings<'a>>)>,
+        k: DecK<'a>,
+    ) -> Term {
+        let (bind, rest) = match binds.split_first() {
+            Some(split) => split,
+            None => {
+                self.bind(Bindings {
+                    structures: done.into_iter().collect(),
+                    ..Bindings::default()
+                });
+                return k(self);
+            }
+        };
+        let env = self.env.clone();
+        self.str_exp(
+            &bind.str,
+            h,
+            Box::new(move |l, mut str| {
+                l.env = env;
+                if let Some(asc) = &bind.sig {
+                    str = Rc::new(str.restrict(&l.shape(&asc.sig)));
+                }
+                done.push((bind.id.name.clone(), str));
+                l.structures(rest, h, done, k)
+            }),
+        )
+    }
+
+    fn shape(&self, sig: &ast::SigExp) -> Shape {
+        self.info
+            .sigs
+            .get(&sig.span)
+            .map(Shape::of_env)
+            .unwrap_or_default()
+    }
+
+    fn str_exp(&mut self, exp: &'a ast::StrExp, h: Cont, k: StrK<'a>) -> Term {
+        match &exp.kind {
+            ast::StrExpKind::Struct(decs) => {
+                let base = self.env.clone();
+                self.str_decs(
+                    decs,
+                    h,
+                    Box::new(move |l| {
+                        let bindings = l.env.since(&base);
+                        l.env = base;
+                        k(l, Rc::new(bindings))
+                    }),
+                )
+            }
+            ast::StrExpKind::Var(id) => {
+                let mut path = id.path.clone();
+                path.push(id.name.clone());
+                match self.env.structure(&path) {
+                    Some(str) => k(self, str),
+                    None => {
+                        self.error(id.span, format!("unbound structure `{}`", id));
+                        k(self, Rc::default())
+                    }
+                }
+            }
+            ast::StrExpKind::Ascribe(exp, asc) => self.str_exp(
+                exp,
+                h,
+                Box::new(move |l, str| {
+                    let shape = l.shape(&asc.sig);
+                    k(l, Rc::new(str.restrict(&shape)))
+                }),
+            ),
+            ast::StrExpKind::App(id, arg) => match self.env.functor(&id.name) {
+                Some(functor) => self.str_exp(
+                    arg,
+                    h,
+                    Box::new(move |l, str| l.apply_functor(&functor, &str, exp.span, h, k)),
+                ),
+                None => {
+                    self.error(id.span, format!("unbound functor `{}`", id));
+                    k(self, Rc::default())
+                }
+            },
+            ast::StrExpKind::Let(decs, exp) => {
+                let base = self.env.clone();
+                self.str_decs(
+                    decs,
+                    h,
+                    Box::new(move |l| {
+                        l.str_exp(
+                            exp,
+                            h,
+                            Box::new(move |l, str| {
+                                l.env = base;
+                                k(l, str)
+                            }),
+                        )
+                    }),
+                )
+            }
+        }
+    }
+
+    /// The variables holding a structure's components, in the order of
+    /// `shape`. Built in values are wrapped in functions, added to `funs`.
+    fn flatten(
+        &mut self,
+        str: &Bindings<'a>,
+        shape: &Shape,
+        fields: &mut Vec<Var>,
+        funs: &mut Vec<Fun>,
+        span: Span,
+    ) {
+        for name in &shape.values {
+            match str.values.get(name) {
+                Some(Binding::Var(var)) => fields.push(*var),
+                Some(binding) => {
+                    let fun = self.eta(binding, None);
+                    fields.push(fun.name);
+                    funs.push(fun);
+                }
+                None => {
+                    self.error(
+                        span,
+                        format!("`{}` is a constructor, but the signature has a value", name),
+                    );
+                    fields.push(self.program.main.name);
+                }
+            }
+        }
+        for (name, shape) in &shape.structures {
+            let str = str.structures.get(name).cloned().unwrap_or_default();
+            self.flatten(&str, shape, fields, funs, span);
+        }
+    }
+
+    /// Take a record of a structure's components apart.
+    fn unflatten(
+        &mut self,
+        record: Var,
+        shape: &Shape,
+        lets: &mut Vec<(Var, Exp)>,
+        index: &mut usize,
+    ) -> Bindings<'a> {
+        let mut bindings = Bindings::default();
+        for name in &shape.values {
+            let var = self.var(name);
+            lets.push((var, Exp::Select(*index, record)));
+            *index += 1;
+            bindings.values.insert(name.clone(), Binding::Var(var));
+        }
+        for (name, shape) in &shape.structures {
+            let str = self.unflatten(record, shape, lets, index);
+            bindings.structures.insert(name.clone(), Rc::new(str));
+        }
+        bindings
+    }
+
+    fn apply_functor(
+        &mut self,
+        functor: &Functor<'a>,
+        arg: &Bindings<'a>,
+        span: Span,
+        h: Cont,
+        k: StrK<'a>,
+    ) -> Term {
+        let (var, param, result_shape) = match functor {
+            Functor::Fun { var, param, result } => (*var, param, result),
+            Functor::Inline { bind, env } => return self.instantiate(bind, env, arg, h, k),
+        };
+        let mut fields = Vec::new();
+        let mut funs = Vec::new();
+        self.flatten(arg, param, &mut fields, &mut funs, span);
+        let record = self.var("arg");
+        let result = self.var("str");
+        let cont = self.program.cont();
+        let mut selects = Vec::new();
+        let str = self.unflatten(result, result_shape, &mut selects, &mut 0);
+        let rest = k(self, Rc::new(str));
+        let mut term = Term::Let(
+            record,
+            Exp::Record(fields),
+            Box::new(Term::LetCont(
+                Box::new(ContDef {
+                    cont,
+                    params: vec![result],
+                    body: lets(selects, rest),
+                }),
+                Box::new(Term::Call {
+                    callee: Callee::Closure(var),
+                    args: vec![record],
+                    ret: cont,
+                    handler: h,
+                }),
+            )),
+        );
+        if !funs.is_empty() {
+            term = Term::LetFun(funs, Box::new(term));
+        }
+        term
+    }
+
+    /// Lower a functor's body for one application, in the scope the functor
+    /// was declared in, with its parameter bound to the argument.
+    fn instantiate(
+        &mut self,
+        bind: &'a ast::FunctorBind,
+        env: &Env<'a>,
+        arg: &Bindings<'a>,
+        h: Cont,
+        k: StrK<'a>,
+    ) -> Term {
+        let arg = arg.restrict(&self.shape(&bind.param_sig));
+        let base = std::mem::replace(&mut self.env, env.clone());
+        match &bind.param {
+            Some(id) => {
+                let mut bindings = Bindings::default();
+                bindings.structures.insert(id.name.clone(), Rc::new(arg));
+                self.bind(bindings);
+            }
+            None => self.bind(arg),
+        }
+        self.str_exp(
+            &bind.body,
+            h,
+            Box::new(move |l, mut str| {
+                if let Some(asc) = &bind.sig {
+                    str = Rc::new(str.restrict(&l.shape(&asc.sig)));
+                }
+                l.env = base;
+                k(l, str)
+            }),
+        )
+    }
+
+    fn functor_dec(&mut self, binds: &'a [ast::FunctorBind], k: DecK<'a>) -> Term {
+        if self.representation == Representation::Specialised {
+            let env = self.env.clone();
+            let functors = binds
+                .iter()
+                .map(|bind| {
+                    let functor = Functor::Inline {
+                        bind,
+                        env: env.clone(),
+                    };
+                    (bind.id.name.clone(), Rc::new(functor))
+                })
+                .collect();
+            self.bind(Bindings {
+                functors,
+                ..Bindings::default()
+            });
+            return k(self);
+        }
+        let mut funs = Vec::new();
+        let mut functors = HashMap::new();
+        for bind in binds {
+            let name = self.var(&bind.id.name);
+            let param = self.var("param");
+            let ret = self.program.cont();
+            let handler = self.program.cont();
+            let param_shape = self.shape(&bind.param_sig);
+            let mut selects = Vec::new();
+            let param_str = self.unflatten(param, &param_shape, &mut selects, &mut 0);
+
+            let base = self.env.clone();
+            match &bind.param {
+                Some(id) => {
+                    let mut bindings = Bindings::default();
+                    bindings
+                        .structures
+                        .insert(id.name.clone(), Rc::new(param_str));
+                    self.bind(bindings);
+                }
+                None => self.bind(param_str),
+            }
+            let result_shape = Rc::new(RefCell::new(Shape::default()));
+            let result = result_shape.clone();
+            let body = self.str_exp(
+                &bind.body,
+                handler,
+                Box::new(move |l, mut str| {
+                    if let Some(asc) = &bind.sig {
+                        str = Rc::new(str.restrict(&l.shape(&asc.sig)));
+                    }
+                    let shape = Shape::of(&str);
+                    let mut fields = Vec::new();
+                    let mut funs = Vec::new();
+                    l.flatten(&str, &shape, &mut fields, &mut funs, bind.span);
+                    *result.borrow_mut() = shape;
+                    let record = l.var("result");
+                    let term = Term::Let(
+                        record,
+                        Exp::Record(fields),
+                        Box::new(Term::Jump(ret, vec![record])),
+                    );
+                    if funs.is_empty() {
+                        term
+                    } else {
+                        Term::LetFun(funs, Box::new(term))
+                    }
+                }),
+            );
+            self.env = base;
+
+            funs.push(Fun {
+                name,
+                params: vec![param],
+                ret,
+                handler,
+                body: lets(selects, body),
+            });
+            let functor = Functor::Fun {
+                var: name,
+                param: param_shape,
+                result: result_shape.take(),
+            };
+            functors.insert(bind.id.name.clone(), Rc::new(functor));
+        }
+        self.bind(Bindings {
+            functors,
+            ..Bindings::default()
+        });
+        let rest = k(self);
+        Term::LetFun(funs, Box::new(rest))
+    }
+
+    fn decs(&mut self, decs: &'a [ast::Dec], h: Cont, k: DecK<'a>) -> Term {
+        match decs.split_first() {
+            None => k(self),
+            Some((dec, rest)) => self.dec(dec, h, Box::new(move |l| l.decs(rest, h, k))),
+        }
+    }
+
+    fn dec(&mut self, dec: &'a ast::Dec, h: Cont, k: DecK<'a>) -> Term {
+        match &dec.kind {
+            ast::DecKind::Val { binds, .. } => {
+                let plain: Vec<&'a ast::ValBind> = binds.iter().filter(|b| !b.rec).collect();
+                let rec: Vec<&'a ast::ValBind> = binds.iter().filter(|b| b.rec).collect();
+                let exps = plain.iter().map(|bind| &bind.exp).collect();
+                self.exps(
+                    exps,
+                    h,
+                    Box::new(move |l, values| {
+                        l.val_binds(
+                            plain,
+                            values,
+                            h,
+                            Vec::new(),
+                            Box::new(move |l| l.rec_binds(rec, k)),
+                        )
+                    }),
+                )
+            }
+            ast::DecKind::Fun { binds, .. } => {
+                let ids = binds.iter().map(|bind| {
+                    bind.clauses[0]
+                        .name
+                        .as_ref()
+                        .expect("fixity should be resolved before lowering")
+                });
+                if self.representation == Representation::Specialised {
+                    let names = ids.map(|id| (id.name.clone(), id.span)).collect();
+                    return self.poly(PolyBinds::Fun(binds), names, k);
+                }
+                let names: Vec<_> = ids
+                    .map(|id| (id.name.clone(), self.var(&id.name)))
+                    .collect();
+                self.bind(Bindings::vars(names.clone()));
+                let funs = binds
+                    .iter()
+                    .zip(&names)
+                    .map(|(bind, (_, name))| self.fun_bind(bind, *name))
+                    .collect();
+                let rest = k(self);
+                Term::LetFun(funs, Box::new(rest))
+            }
+            ast::DecKind::Abstype { body, .. } => self.decs(body, h, k),
+            ast::DecKind::Local(local, body) => {
+                let base = self.env.clone();
+                self.decs(
+                    local,
+                    h,
+                    Box::new(move |l| {
+                        let inner = l.env.clone();
+                        l.decs(
+                            body,
+                            h,
+                            Box::new(move |l| {
+                                let bindings = l.env.since(&inner);
+                                l.env = base.push(bindings);
+                                k(l)
+                            }),
+                        )
+                    }),
+                )
+            }
+            ast::DecKind::Open(ids) => {
+                let mut bindings = Bindings::default();
+                for id in ids {
+                    let mut path = id.path.clone();
+                    path.push(id.name.clone());
+                    match self.env.structure(&path) {
+                        Some(str) => bindings.extend(&str),
+                        None => self.error(id.span, format!("unbound structure `{}`", id)),
+                    }
+                }
+                self.bind(bindings);
+                k(self)
+            }
+            ast::DecKind::Type(_)
+            | ast::DecKind::Datatype { .. }
+            | ast::DecKind::DatatypeRepl(_, _)
+            | ast::DecKind::Exception(_)
+            | ast::DecKind::Fixity(_, _) => k(self),
+        }
+    }
+
+    /// Match the values of `val` bindings against their patterns, then bind
+    /// all their variables at once.
+    fn val_binds(
+        &mut self,
+        binds: Vec<&'a ast::ValBind>,
+        values: Vec<Var>,
+        h: Cont,
+        bound: Vec<(String, Var)>,
+        k: DecK<'a>,
+    ) -> Term {
+        let (bind, value) = match (binds.first(), values.first()) {
+            (Some(bind), Some(value)) => (*bind, *value),
+            _ => {
+                self.bind(Bindings::vars(bound));
+                return k(self);
+            }
+        };
+        let m = self.compiled(bind.span);
+        let mut next = Some((binds[1..].to_vec(), values[1..].to_vec(), bound, k));
+        self.decide(
+            m,
+            vec![value],
+            &mut |l| l.raise_builtin("Bind", h),
+            &mut |l, _, vars| {
+                let (binds, values, mut bound, k) = next.take().expect("a `val` has one rule");
+                bound.extend(vars);
+                l.val_binds(binds, values, h, bound, k)
+            },
+        )
+    }
+
+    fn rec_binds(&mut self, binds: Vec<&'a ast::ValBind>, k: DecK<'a>) -> Term {
+        if binds.is_empty() {
+            return k(self);
+        }
+        if self.representation == Representation::Specialised {
+            let names = binds
+                .iter()
+                .map(|bind| {
+                    let id = rec_name(&bind.pat);
+                    (id.name.clone(), id.span)
+                })
+                .collect();
+            return self.poly(PolyBinds::Rec(binds), names, k);
+        }
+        let names: Vec<_> = binds
+            .iter()
+            .map(|bind| {
+                let id = rec_name(&bind.pat);
+                (id.name.clone(), self.var(&id.name))
+            })
+            .collect();
+        self.bind(Bindings::vars(names.clone()));
+        let funs = binds
+            .iter()
+            .zip(&names)
+            .map(|(bind, (_, name))| {
+                let (exp, rules) = rec_fn(&bind.exp);
+                self.fn_fun(*name, exp.span, rules)
+            })
+            .collect();
+        let rest = k(self);
+        Term::LetFun(funs, Box::new(rest))
+    }
+
+    /// Bind a group of functions to be specialised, lower the rest of their
+    /// scope, then lower each instance of them it used. Instances can use
+    /// more instances of groups declared before, whose scopes are still
+    /// being lowered, but not more of this one, since functions are
+    /// monomorphic in their own group.
+    fn poly(&mut self, binds: PolyBinds<'a>, names: Vec<(String, Span)>, k: DecK<'a>) -> Term {
+        let poly = Rc::new(Poly {
+            binds,
+            names: names
+                .into_iter()
+                .map(|(name, span)| (name, self.ty(span)))
+                .collect(),
+            env: self.env.clone(),
+            subst: self.subst.clone(),
+            instances: RefCell::default(),
+        });
+        let values = poly
+            .names
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (name.clone(), Binding::Poly(poly.clone(), i)))
+            .collect();
+        self.bind(Bindings {
+            values,
+            ..Bindings::default()
+        });
+        let mut term = k(self);
+
+        let env = self.env.clone();
+        let subst = self.subst.clone();
+        let mut next = 0;
+        loop {
+            let instance = poly.instances.borrow().get(next).cloned();
+            let (subst, vars) = match instance {
+                Some(instance) => instance,
+                None => break,
+            };
+            next += 1;
+            let names = poly.names.iter().map(|(name, _)| name.clone());
+            self.env = poly
+                .env
+                .push(Bindings::vars(names.zip(vars.clone()).collect()));
+            self.subst = subst;
+            let funs = match &poly.binds {
+                PolyBinds::Fun(binds) => binds
+                    .iter()
+                    .zip(&vars)
+                    .map(|(bind, var)| self.fun_bind(bind, *var))
+                    .collect(),
+                PolyBinds::Rec(binds) => binds
+                    .iter()
+                    .zip(&vars)
+                    .map(|(bind, var)| {
+                        let (exp, rules) = rec_fn(&bind.exp);
+                        self.fn_fun(*var, exp.span, rules)
+                    })
+                    .collect(),
+            };
+            term = Term::LetFun(funs, Box::new(term));
+        }
+        self.env = env;
+        self.subst = subst;
+        term
+    }
+
+    /// The variable of the instance of a group's nth function used at type
+    /// `ty`, adding the instance if it's new. Without a type, the instance
+    /// is the general one, whose types aren't specialised.
+    fn instance(&mut self, poly: &Poly<'a>, index: usize, ty: Option<Type>) -> Var {
+        let mut subst = poly.subst.clone();
+        if let (Some(general), Some(ty)) = (&poly.names[index].1, ty) {
+            general.match_instance(&ty, &mut subst);
+        }
+        if let Some((_, vars)) = poly.instances.borrow().iter().find(|(s, _)| *s == subst) {
+            return vars[index];
+        }
+        let vars: Vec<Var> = poly.names.iter().map(|(name, _)| self.var(name)).collect();
+        let var = vars[index];
+        poly.instances.borrow_mut().push((subst, vars));
+        var
+    }
+
+    /// A `fun` binding, taking its curried arguments one at a time and then
+    /// matching them all at once.
+    fn fun_bind(&mut self, bind: &'a ast::FunBind, name: Var) -> Fun {
+        let arity = bind.clauses[0].args.len();
+        let args: Vec<Var> = (0..arity).map(|_| self.var("x")).collect();
+        let ret = self.program.cont();
+        let handler = self.program.cont();
+        let bodies = bind.clauses.iter().map(|clause| &clause.body).collect();
+        let body = self.match_rules(
+            self.compiled(bind.span),
+            args.clone(),
+            handler,
+            ret,
+            bodies,
+            &mut |l| l.raise_builtin("Match", handler),
+        );
+        let mut fun = Fun {
+            name,
+            params: vec![args[arity - 1]],
+            ret,
+            handler,
+            body,
+        };
+        for &arg in args[..arity - 1].iter().rev() {
+            fun.name = self.var(self.program.name(name).to_owned().as_str());
+            let ret = self.program.cont();
+            let handler = self.program.cont();
+            let inner = fun.name;
+            fun = Fun {
+                name,
+                params: vec![arg],
+                ret,
+                handler,
+                body: Term::LetFun(vec![fun], Box::new(Term::Jump(ret, vec![inner]))),
+            };
+        }
+        fun
+    }
+
+    /// A function matching its argument against `rules`.
+    fn fn_fun(&mut self, name: Var, span: Span, rules: &'a [ast::MRule]) -> Fun {
+        let arg = self.var("x");
+        let ret = self.program.cont();
+        let handler = self.program.cont();
+        let bodies = rules.iter().map(|rule| &rule.exp).collect();
+        let body = self.match_rules(
+            self.compiled(span),
+            vec![arg],
+            handler,
+            ret,
+            bodies,
+            &mut |l| l.raise_builtin("Match", handler),
+        );
+        Fun {
+            name,
+            params: vec![arg],
+            ret,
+            handler,
+            body,
+        }
+    }
+
+    // Matches
+
+    /// Match `roots`, then evaluate the chosen rule's body and jump to `join`.
+    fn match_rules(
+        &mut self,
+        m: &'a Match,
+        roots: Vec<Var>,
+        h: Cont,
+        join: Cont,
+        bodies: Vec<&'a ast::Exp>,
+        fail: FailK<'a, '_>,
+    ) -> Term {
+        self.decide(m, roots, fail, &mut |l, rule, vars| {
+            l.bind(Bindings::vars(vars));
+            l.exp(bodies[rule], h, Kont::Cont(join))
+        })
+    }
+
+    /// Lower a decision tree. Rules reached from more than one leaf become
+    /// continuations, so their bodies are only lowered once.
+    fn decide(
+        &mut self,
+        m: &'a Match,
+        roots: Vec<Var>,
+        fail: FailK<'a, '_>,
+        rule: RuleK<'a, '_>,
+    ) -> Term {
+        let mut leaves: HashMap<usize, (usize, Vec<String>)> = HashMap::new();
+        count_leaves(&m.tree, &mut leaves);
+        let mut shared: Vec<_> = leaves
+            .into_iter()
+            .filter(|(_, (count, _))| *count > 1)
+            .map(|(rule, (_, names))| (rule, names))
+            .collect();
+        shared.sort_by_key(|(rule, _)| *rule);
+
+        let mut defs = Vec::new();
+        let mut conts = HashMap::new();
+        for (index, mut names) in shared {
+            names.sort();
+            let params: Vec<Var> = names.iter().map(|name| self.var(name)).collect();
+            let env = self.env.clone();
+            let body = rule(
+                self,
+                index,
+                names.iter().cloned().zip(params.clone()).collect(),
+            );
+            self.env = env;
+            let cont = self.program.cont();
+            defs.push(ContDef { cont, params, body });
+            conts.insert(index, (cont, names));
+        }
+
+        let paths = Paths {
+            roots,
+            vars: HashMap::new(),
+            known: HashMap::new(),
+        };
+        let tree = self.decision(&m.tree, paths, fail, rule, &conts);
+        let_conts(defs, tree)
+    }
+
+    fn decision(
+        &mut self,
+        tree: &'a Decision,
+        mut paths: Paths,
+        fail: FailK<'a, '_>,
+        rule: RuleK<'a, '_>,
+        shared: &HashMap<usize, (Cont, Vec<String>)>,
+    ) -> Term {
+        let mut bound = Vec::new();
+        let term = match tree {
+            Decision::Fail => fail(self),
+            Decision::Leaf {
+                rule: index,
+                bindings,
+            } => {
+                let vars: Vec<(String, Var)> = bindings
+                    .iter()
+                    .map(|(name, access)| (name.clone(), self.path(&mut paths, access, &mut bound)))
+                    .collect();
+                match shared.get(index) {
+                    Some((cont, names)) => {
+                        let args = names
+                            .iter()
+                            .map(|name| vars.iter().find(|(n, _)| n == name).unwrap().1)
+                            .collect();
+                        Term::Jump(*cont, args)
+                    }
+                    None => {
+                        let env = self.env.clone();
+                        let term = rule(self, *index, vars);
+                        self.env = env;
+                        term
+                    }
+                }
+            }
+            Decision::Switch {
+                access,
+                cases,
+                default,
+            } => {
+                let value = self.path(&mut paths, access, &mut bound);
+                let mut defs = Vec::new();
+                let mut branches = Vec::new();
+                for (test, tree) in cases {
+                    let mut paths = paths.clone();
+                    if let Test::Con(info) = test {
+                        paths.known.insert(access.clone(), info.clone());
+                    }
+                    let body = self.decision(tree, paths, fail, rule, shared);
+                    // `ref` has one constructor, and nothing to test.
+                    if matches!(test, Test::Con(info) if is_ref(info)) {
+                        return lets(bound, body);
+                    }
+                    let (cont, def) = self.block(body);
+                    defs.push(def);
+                    branches.push((test, cont));
+                }
+                let default = default.as_ref().map(|tree| {
+                    let body = self.decision(tree, paths.clone(), fail, rule, shared);
+                    let (cont, def) = self.block(body);
+                    defs.push(def);
+                    cont
+                });
+                let switch = self.switch(value, &branches, default, fail, &mut bound);
+                let_conts(defs, switch)
+            }
+        };
+        lets(bound, term)
+    }
+
+    /// Test a value against each case.
+    fn switch(
+        &mut self,
+        value: Var,
+        cases: &[(&Test, Cont)],
+        default: Option<Cont>,
+        fail: FailK<'a, '_>,
+        bound: &mut Vec<(Var, Exp)>,
+    ) -> Term {
+        let first = match cases.first() {
+            Some((test, _)) => *test,
+            None => return Term::Jump(default.expect("a default"), Vec::new()),
+        };
+        let int_cases = |key: &dyn Fn(&Test) -> i64| {
+            cases
+                .iter()
+                .map(|(test, cont)| (key(test), *cont))
+                .collect::<Vec<_>>()
+        };
+        match first {
+            Test::Con(info) => match info.kind {
+                ConKind::Datatype { ref tycon, .. } if tycon.id == types::BOOL => {
+                    let cont_for = |tag| {
+                        cases
+                            .iter()
+                            .find(|(test, _)| con_tag(test) == tag)
+                            .map(|(_, cont)| *cont)
+                            .or(default)
+                            .expect("a case for each bool")
+                    };
+                    Term::If(value, cont_for(1), cont_for(0))
+                }
+                ConKind::Datatype { .. } => {
+                    let tag = self.var("tag");
+                    bound.push((tag, Exp::Tag(value)));
+                    Term::Switch {
+                        scrutinee: tag,
+                        cases: int_cases(&con_tag),
+                        default,
+                    }
+                }
+                ConKind::Exn(_) => {
+                    for (test, _) in cases {
+                        if let Test::Con(info) = test {
+                            self.exn_name(info);
+                        }
+                    }
+                    let id = self.var("id");
+                    bound.push((id, Exp::ExnId(value)));
+                    Term::Switch {
+                        scrutinee: id,
+                        cases: int_cases(&con_tag),
+                        default,
+                    }
+                }
+            },
+            Test::Const(ast::Const::Int(_)) | Test::Const(ast::Const::Char(_)) => Term::Switch {
+                scrutinee: value,
+                cases: int_cases(&|test| match test {
+                    Test::Const(ast::Const::Int(n)) => *n,
+                    Test::Const(ast::Const::Char(c)) => *c as i64,
+                    _ => unreachable!(),
+                }),
+                default,
+            },
+            Test::Const(_) => {
+                // Strings and words are compared one at a time.
+                let mut term = match default {
+                    Some(cont) => Term::Jump(cont, Vec::new()),
+                    None => fail(self),
+                };
+                for (test, cont) in cases.iter().rev() {
+                    let c = match test {
+                        Test::Const(c) => c.clone(),
+                        Test::Con(_) => unreachable!(),
+                    };
+                    let (next, def) = self.block(term);
+                    let constant = self.var("c");
+                    let equal = self.var("eq");
+                    term = Term::LetCont(
+                        Box::new(def),
+                        Box::new(Term::Let(
+                            constant,
+                            Exp::Const(c),
+                            Box::new(Term::Let(
+                                equal,
+                                Exp::Prim(PrimOp::Equal, vec![value, constant]),
+                                Box::new(Term::If(equal, *cont, next)),
+                            )),
+                        )),
+                    );
+                }
+                term
+            }
+        }
+    }
+
+    /// The variable for part of the values being matched, taking it apart
+    /// if that hasn't been done on the way here.
+    fn path(&mut self, paths: &mut Paths, access: &Access, bound: &mut Vec<(Var, Exp)>) -> Var {
+        let (step, parent) = match access.path.split_last() {
+            None => return paths.roots[access.root],
+            Some((step, parent)) => (
+                step,
+                Access {
+                    root: access.root,
+                    path: parent.to_vec(),
+                },
+            ),
+        };
+        if let Some(var) = paths.vars.get(access) {
+            return *var;
+        }
+        let value = self.path(paths, &parent, bound);
+        let exp = match step {
+            matching::Step::Field(_, index) => Exp::Select(*index, value),
+            matching::Step::ConArg => match paths.known.get(&parent) {
+                Some(info) if is_ref(info) => Exp::Prim(PrimOp::Deref, vec![value]),
+                Some(info) if matches!(info.kind, ConKind::Exn(_)) => Exp::ExnArg(value),
+                _ => Exp::ConArg(value),
+            },
+        };
+        let var = self.var("p");
+        bound.push((var, exp));
+        paths.vars.insert(access.clone(), var);
+        var
+    }
+
+    // Expressions
+
+    fn exps(&mut self, mut exps: Vec<&'a ast::Exp>, h: Cont, k: VarsK<'a>) -> Term {
+        if exps.is_empty() {
+            return k(self, Vec::new());
+        }
+        let first = exps.remove(0);
+        self.exp(
+            first,
+            h,
+            self.meta(move |l, var| {
+                l.exps(
+                    exps,
+                    h,
+                    Box::new(move |l, mut vars| {
+                        vars.insert(0, var);
+                        k(l, vars)
+                    }),
+                )
+            }),
+        )
+    }
+
+    fn exp(&mut self, exp: &'a ast::Exp, h: Cont, k: Kont<'a>) -> Term {
+        let env = self.env.clone();
+        let term = self.exp_kind(exp, h, k);
+        self.env = env;
+        term
+    }
+
+    fn exp_kind(&mut self, exp: &'a ast::Exp, h: Cont, k: Kont<'a>) -> Term {
+        match &exp.kind {
+            ExpKind::Const(c) => self.let_("c", Exp::Const(c.clone()), k),
+            ExpKind::Var { id, .. } => self.var_exp(id, k),
+            ExpKind::Selector(lab) => {
+                let index = self.field_index(exp.span, lab);
+                let arg = self.var("x");
+                let ret = self.program.cont();
+                let handler = self.program.cont();
+                let field = self.var("field");
+                let fun = Fun {
+                    name: self.var(&format!("#{}", lab)),
+                    params: vec![arg],
+                    ret,
+                    handler,
+                    body: Term::Let(
+                        field,
+                        Exp::Select(index, arg),
+                        Box::new(Term::Jump(ret, vec![field])),
+                    ),
+                };
+                self.let_fun(fun, k)
+            }
+            ExpKind::Record(rows) => {
+                let exps = rows.iter().map(|(_, exp)| exp).collect();
+                self.exps(
+                    exps,
+                    h,
+                    Box::new(move |l, vars| {
+                        let mut fields: Vec<(&Lab, Var)> =
+                            rows.iter().map(|(lab, _)| lab).zip(vars).collect();
+                        fields.sort_by(|a, b| a.0.cmp(b.0));
+                        let record = Exp::Record(fields.into_iter().map(|(_, var)| var).collect());
+                        l.let_("r", record, k)
+                    }),
+                )
+            }
+            ExpKind::Tuple(exps) => self.exps(
+                exps.iter().collect(),
+                h,
+                Box::new(move |l, vars| {
+                    let name = if vars.is_empty() { "unit" } else { "t" };
+                    l.let_(name, Exp::Record(vars), k)
+                }),
+            ),
+            ExpKind::List(exps) => self.exps(
+                exps.iter().collect(),
+                h,
+                Box::new(move |l, vars| {
+                    let mut bound = Vec::new();
+                    let mut list = l.var("nil");
+                    bound.push((list, Exp::Con(0, None)));
+                    for var in vars.into_iter().rev() {
+                        let pair = l.var("t");
+                        bound.push((pair, Exp::Record(vec![var, list])));
+                        list = l.var("list");
+                        bound.push((list, Exp::Con(1, Some(pair))));
+                    }
+                    let rest = l.ret(k, list);
+                    lets(bound, rest)
+                }),
+            ),
+            ExpKind::Seq(exps) => self.exps(
+                exps.iter().collect(),
+                h,
+                Box::new(move |l, vars| {
+                    let last = *vars.last().expect("a sequence isn't empty");
+                    l.ret(k, last)
+                }),
+            ),
+            ExpKind::Let(decs, body) => self.decs(decs, h, Box::new(move |l| l.exp(body, h, k))),
+            ExpKind::Flat(_) => panic!("fixity should be resolved before lowering"),
+            ExpKind::App(f, arg) => self.app(f, arg, h, k),
+            ExpKind::Typed(exp, _) => self.exp_kind(exp, h, k),
+            ExpKind::Andalso(a, b) | ExpKind::Orelse(a, b) => {
+                let andalso = matches!(exp.kind, ExpKind::Andalso(_, _));
+                self.reify(k, |l, join| {
+                    let k = l.meta(move |l, cond| {
+                        let rest = l.exp(b, h, Kont::Cont(join));
+                        let short = l.var("b");
+                        let tag = if andalso { 0 } else { 1 };
+                        let done = Term::Let(
+                            short,
+                            Exp::Con(tag, None),
+                            Box::new(Term::Jump(join, vec![short])),
+                        );
+                        let (rest, rest_def) = l.block(rest);
+                        let (done, done_def) = l.block(done);
+                        let test = if andalso {
+                            Term::If(cond, rest, done)
+                        } else {
+                            Term::If(cond, done, rest)
+                        };
+                        let_conts(vec![rest_def, done_def], test)
+                    });
+                    l.exp(a, h, k)
+                })
+            }
+            ExpKind::If(cond, a, b) => self.reify(k, |l, join| {
+                let k = l.meta(move |l, cond| {
+                    let a = l.exp(a, h, Kont::Cont(join));
+                    let b = l.exp(b, h, Kont::Cont(join));
+                    let (a, a_def) = l.block(a);
+                    let (b, b_def) = l.block(b);
+                    let_conts(vec![a_def, b_def], Term::If(cond, a, b))
+                });
+                l.exp(cond, h, k)
+            }),
+            ExpKind::While(cond, body) => self.reify(k, |l, join| {
+                let top = l.program.cont();
+                let k = l.meta(move |l, cond| {
+                    let k = l.meta(move |_, _| Term::Jump(top, Vec::new()));
+                    let body = l.exp(body, h, k);
+                    let unit = l.var("unit");
+                    let exit = Term::Let(
+                        unit,
+                        Exp::Record(Vec::new()),
+                        Box::new(Term::Jump(join, vec![unit])),
+                    );
+                    let (body, body_def) = l.block(body);
+                    let (exit, exit_def) = l.block(exit);
+                    let_conts(vec![body_def, exit_def], Term::If(cond, body, exit))
+                });
+                let test = l.exp(cond, h, k);
+                Term::LetCont(
+                    Box::new(ContDef {
+                        cont: top,
+                        params: Vec::new(),
+                        body: test,
+                    }),
+                    Box::new(Term::Jump(top, Vec::new())),
+                )
+            }),
+            ExpKind::Raise(exn) => {
+                let k = self.meta(move |_, exn| Term::Jump(h, vec![exn]));
+                self.exp(exn, h, k)
+            }
+            ExpKind::Handle(body, rules) => {
+                let m = self.compiled(exp.span);
+                self.reify(k, |l, join| {
+                    let handler = l.program.cont();
+                    let exn = l.var("exn");
+                    let bodies = rules.iter().map(|rule| &rule.exp).collect();
+                    let handle = l.match_rules(m, vec![exn], h, join, bodies, &mut |_| {
+                        Term::Jump(h, vec![exn])
+                    });
+                    let body = l.exp(body, handler, Kont::Cont(join));
+                    Term::LetCont(
+                        Box::new(ContDef {
+                            cont: handler,
+                            params: vec![exn],
+                            body: handle,
+                        }),
+                        Box::new(body),
+                    )
+                })
+            }
+            ExpKind::Case(scrutinee, rules) => {
+                let m = self.compiled(exp.span);
+                let k = self.meta(move |l, value| {
+                    l.reify(k, |l, join| {
+                        let bodies = rules.iter().map(|rule| &rule.exp).collect();
+                        l.match_rules(m, vec![value], h, join, bodies, &mut |l| {
+                            l.raise_builtin("Match", h)
+                        })
+                    })
+                });
+                self.exp(scrutinee, h, k)
+            }
+            ExpKind::Fn(rules) => {
+                let name = self.var("fn");
+                let fun = self.fn_fun(name, exp.span, rules);
+                self.let_fun(fun, k)
+            }
+        }
+    }
+
+    fn let_fun(&mut self, fun: Fun, k: Kont<'a>) -> Term {
+        let rest = self.ret(k, fun.name);
+        Term::LetFun(vec![fun], Box::new(rest))
+    }
+
+    /// The position of a selector's field in its record.
+    fn field_index(&self, span: Span, lab: &Lab) -> usize {
+        match self.ty(span).map(|ty| ty.resolve()) {
+            Some(Type::Arrow(record, _)) => match record.resolve() {
+                Type::Record(fields) => fields.keys().position(|l| l == lab),
+                _ => None,
+            },
+            _ => None,
+        }
+        .expect("a selector's record type should be known")
+    }
+
+    fn exn_name(&mut self, info: &ConInfo) {
+        if let ConKind::Exn(id) = info.kind {
+            self.program
+                .exns
+                .entry(id)
+                .or_insert_with(|| info.name.clone());
+        }
+    }
+
+    /// Apply a constructor.
+    fn con(&mut self, info: &ConInfo, arg: Option<Var>) -> Exp {
+        self.exn_name(info);
+        match &info.kind {
+            ConKind::Datatype { tycon, .. } if tycon.id == types::REF => {
+                Exp::Prim(PrimOp::Ref, arg.into_iter().collect())
+            }
+            ConKind::Datatype { tag, .. } => Exp::Con(*tag as u32, arg),
+            ConKind::Exn(id) => Exp::Exn(*id, arg),
+        }
+    }
+
+    fn var_exp(&mut self, id: &'a LongId, k: Kont<'a>) -> Term {
+        if let Some(info) = self.info.cons.get(&id.span) {
+            if !info.has_arg {
+                let exp = self.con(info, None);
+                return self.let_(&info.name, exp, k);
+            }
+            let arg = self.var("x");
+            let ret = self.program.cont();
+            let handler = self.program.cont();
+            let value = self.var("c");
+            let exp = self.con(info, Some(arg));
+            let fun = Fun {
+                name: self.var(&info.name),
+                params: vec![arg],
+                ret,
+                handler,
+                body: Term::Let(value, exp, Box::new(Term::Jump(ret, vec![value]))),
+            };
+            return self.let_fun(fun, k);
+        }
+        match self.env.value(id) {
+            Some(Binding::Var(var)) => self.ret(k, var),
+            Some(Binding::Poly(poly, index)) => {
+                let var = self.instance(&poly, index, self.ty(id.span));
+                self.ret(k, var)
+            }
+            Some(binding) => {
+                let fun = self.eta(&binding, self.ty(id.span));
+                self.let_fun(fun, k)
+            }
+            None => {
+                self.error(id.span, format!("unbound variable `{}`", id));
+                self.let_("unit", Exp::Record(Vec::new()), k)
+            }
+        }
+    }
+
+    /// A function applying a built in value, used at type `ty`.
+    fn eta(&mut self, binding: &Binding<'a>, ty: Option<Type>) -> Fun {
+        if let Binding::Poly(poly, index) = binding {
+            let var = self.instance(poly, *index, ty);
+            return self.eta(&Binding::Var(var), None);
+        }
+        let arg = self.var("x");
+        let ret = self.program.cont();
+        let handler = self.program.cont();
+        let (name, body) = match binding {
+            Binding::Var(var) => (
+                self.program.name(*var).to_owned(),
+                Term::Call {
+                    callee: Callee::Closure(*var),
+                    args: vec![arg],
+                    ret,
+                    handler,
+                },
+            ),
+            Binding::Native(name) => (
+                name.to_string(),
+                Term::Call {
+                    callee: Callee::Native(name),
+                    args: vec![arg],
+                    ret,
+                    handler,
+                },
+            ),
+            Binding::Poly(..) => unreachable!("instances are applied as variables"),
+            Binding::Prim(prim) => {
+                let body = if is_binary(*prim) {
+                    let a = self.var("a");
+                    let b = self.var("b");
+                    let body = self.prim(*prim, ty, vec![a, b], handler, Kont::Cont(ret));
+                    lets(
+                        vec![(a, Exp::Select(0, arg)), (b, Exp::Select(1, arg))],
+                        body,
+                    )
+                } else {
+                    self.prim(*prim, ty, vec![arg], handler, Kont::Cont(ret))
+                };
+                (prim.name().to_owned(), body)
+            }
+        };
+        Fun {
+            name: self.var(&name),
+            params: vec![arg],
+            ret,
+            handler,
+            body,
+        }
+    }
+
+    fn call(&mut self, callee: Callee, arg: Var, h: Cont, k: Kont<'a>) -> Term {
+        self.reify(k, |_, ret| Term::Call {
+            callee,
+            args: vec![arg],
+            ret,
+            handler: h,
+        })
+    }
+
+    fn app(&mut self, f: &'a ast::Exp, arg: &'a ast::Exp, h: Cont, k: Kont<'a>) -> Term {
+        match &f.kind {
+            ExpKind::Var { id, .. } => {
+                if let Some(info) = self.info.cons.get(&id.span) {
+                    let k = self.meta(move |l, arg| {
+                        let exp = l.con(info, Some(arg));
+                        l.let_(&info.name, exp, k)
+                    });
+                    return self.exp(arg, h, k);
+                }
+                match self.env.value(id) {
+                    Some(Binding::Prim(prim)) => {
+                        let ty = self.ty(id.span);
+                        return self.prim_app(prim, ty, arg, h, k);
+                    }
+                    Some(Binding::Native(name)) => {
+                        let k = self.meta(move |l, arg| l.call(Callee::Native(name), arg, h, k));
+                        return self.exp(arg, h, k);
+                    }
+                    _ => (),
+                }
+            }
+            ExpKind::Selector(lab) => {
+                let index = self.field_index(f.span, lab);
+                let k = self.meta(move |l, arg| l.let_("field", Exp::Select(index, arg), k));
+                return self.exp(arg, h, k);
+            }
+            _ => (),
+        }
+        let k = self.meta(move |l, f| {
+            let k = l.meta(move |l, arg| l.call(Callee::Closure(f), arg, h, k));
+            l.exp(arg, h, k)
+        });
+        self.exp(f, h, k)
+    }
+
+    /// Apply a built in value. Pairs written out aren't built.
+    fn prim_app(
+        &mut self,
+        prim: Prim,
+        ty: Option<Type>,
+        arg: &'a ast::Exp,
+        h: Cont,
+        k: Kont<'a>,
+    ) -> Term {
+        if !is_binary(prim) {
+            let k = self.meta(move |l, arg| l.prim(prim, ty, vec![arg], h, k));
+            return self.exp(arg, h, k);
+        }
+        if let ExpKind::Tuple(exps) = &arg.kind {
+            if exps.len() == 2 {
+                return self.exps(
+                    exps.iter().collect(),
+                    h,
+                    Box::new(move |l, args| l.prim(prim, ty, args, h, k)),
+                );
+            }
+        }
+        let k = self.meta(move |l, pair| {
+            let a = l.var("a");
+            let b = l.var("b");
+            let body = l.prim(prim, ty, vec![a, b], h, k);
+            lets(
+                vec![(a, Exp::Select(0, pair)), (b, Exp::Select(1, pair))],
+                body,
+            )
+        });
+        self.exp(arg, h, k)
+    }
+
+    /// Apply a built in value to its arguments, used at type `ty`.
+    fn prim(&mut self, prim: Prim, ty: Option<Type>, args: Vec<Var>, h: Cont, k: Kont<'a>) -> Term {
+        use PrimOp::*;
+
+        let operand = operand(ty.as_ref());
+        let by_type = |int, word, real, string| match tycon(&operand) {
+            types::WORD => word,
+            types::REAL => real,
+            types::STRING => string,
+            _ => int,
+        };
+        let op = match prim {
+            Prim::Add => by_type(IntAdd, WordAdd, RealAdd, IntAdd),
+            Prim::Sub => by_type(IntSub, WordSub, RealSub, IntSub),
+            Prim::Mul => by_type(IntMul, WordMul, RealMul, IntMul),
+            Prim::Divide => RealDiv,
+            Prim::Div => by_type(IntDiv, WordDiv, IntDiv, IntDiv),
+            Prim::Mod => by_type(IntMod, WordMod, IntMod, IntMod),
+            Prim::Neg => by_type(IntNeg, WordNeg, RealNeg, IntNeg),
+            Prim::Abs if tycon(&operand) == types::WORD => return self.ret(k, args[0]),
+            Prim::Abs => by_type(IntAbs, IntAbs, RealAbs, IntAbs),
+            Prim::Lt => by_type(IntLt, WordLt, RealLt, StringLt),
+            Prim::Gt => by_type(IntGt, WordGt, RealGt, StringGt),
+            Prim::Le => by_type(IntLe, WordLe, RealLe, StringLe),
+            Prim::Ge => by_type(IntGe, WordGe, RealGe, StringGe),
+            Prim::Eq | Prim::Ne => {
+                let op = if is_immediate(&operand) { IntEq } else { Equal };
+                if prim == Prim::Eq {
+                    return self.let_("eq", Exp::Prim(op, args), k);
+                }
+                let equal = self.var("eq");
+                let rest = self.let_("ne", Exp::Prim(Not, vec![equal]), k);
+                return Term::Let(equal, Exp::Prim(op, args), Box::new(rest));
+            }
+            Prim::Deref => Deref,
+            Prim::Assign => Assign,
+            Prim::Concat => StringConcat,
+            Prim::Print => Print,
+            Prim::Before => return self.ret(k, args[0]),
+            Prim::Compose => {
+                let (f, g) = (args[0], args[1]);
+                let arg = self.var("x");
+                let ret = self.program.cont();
+                let handler = self.program.cont();
+                let then = self.program.cont();
+                let y = self.var("y");
+                let fun = Fun {
+                    name: self.var("o"),
+                    params: vec![arg],
+                    ret,
+                    handler,
+                    body: Term::LetCont(
+                        Box::new(ContDef {
+                            cont: then,
+                            params: vec![y],
+                            body: Term::Call {
+                                callee: Callee::Closure(f),
+                                args: vec![y],
+                                ret,
+                                handler,
+                            },
+                        }),
+                        Box::new(Term::Call {
+                            callee: Callee::Closure(g),
+                            args: vec![arg],
+                            ret: then,
+                            handler,
+                        }),
+                    ),
+                };
+                return self.let_fun(fun, k);
+            }
+        };
+        if op.can_raise() {
+            let var = self.var("n");
+            let body = self.ret(k, var);
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler: h,
+                body: Box::new(body),
+            }
+        } else {
+            self.let_("v", Exp::Prim(op, args), k)
+        }
+    }
+}
+
+fn is_binary(prim: Prim) -> bool {
+    !matches!(prim, Prim::Neg | Prim::Abs | Prim::Deref | Prim::Print)
+}
+
+/// Whether values of a type are represented by ints, so that equality is
+/// comparing them.
+fn is_immediate(ty: &Option<Type>) -> bool {
+    match ty {
+        Some(Type::Con(tycon, _)) => {
+            matches!(tycon.id, types::INT | types::CHAR | types::BOOL)
+        }
+        Some(Type::Record(fields)) => fields.is_empty(),
+        _ => false,
+    }
+}
+
+fn is_ref(info: &ConInfo) -> bool {
+    matches!(&info.kind, ConKind::Datatype { tycon, .. } if tycon.id == types::REF)
+}
+
+fn con_tag(test: &Test) -> i64 {
+    match test {
+        Test::Con(info) => match info.kind {
+            ConKind::Datatype { tag, .. } => tag as i64,
+            ConKind::Exn(id) => id as i64,
+        },
+        Test::Const(_) => unreachable!(),
+    }
+}
+
+/// How many leaves each rule has, and the variables it binds.
+fn count_leaves(tree: &Decision, leaves: &mut HashMap<usize, (usize, Vec<String>)>) {
+    match tree {
+        Decision::Fail => (),
+        Decision::Leaf { rule, bindings } => {
+            let entry = leaves.entry(*rule).or_insert_with(|| {
+                let names = bindings.iter().map(|(name, _)| name.clone()).collect();
+                (0, names)
+            });
+            entry.0 += 1;
+        }
+        Decision::Switch { cases, default, .. } => {
+            for (_, tree) in cases {
+                count_leaves(tree, leaves);
+            }
+            if let Some(tree) = default {
+                count_leaves(tree, leaves);
+            }
+        }
+    }
+}
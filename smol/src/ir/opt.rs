@@ -0,0 +1,658 @@
+//! Optimisation passes, over programs straight from lowering, before closure
+//! conversion.
+//!
+//! Each pass rewrites the program and says whether it changed anything, and
+//! `optimise_with` runs them in turn, round after round, until none of them
+//! does or it's run enough rounds. Passes leave the program well formed, and
+//! keep every variable and continuation bound once in the whole program, not
+//! just in each function, which lowering guarantees to begin with. That lets
+//! them keep what they know about a variable in one map, without scopes.
+//!
+//! The passes are:
+//!
+//! - `Inline`: calls to small functions that aren't recursive are replaced
+//!   by copies of their bodies.
+//! - `Beta`: functions called once and continuations jumped to once are
+//!   replaced by their bodies where they're used, and continuations that
+//!   just jump to another are replaced by it.
+//! - `Fold`: operations on constants are done at compile time.
+//! - `KnownCase`: taking apart a value whose constructor is known, and
+//!   branching on one, is done at compile time, including in the branches
+//!   of a switch on the value.
+//! - `Flatten`: functions that only take their tuple argument apart take its
+//!   fields as separate arguments instead.
+//! - `Dce`: values that are never used and have no effects, and functions
+//!   and continuations that are never used, are removed.
+//! - `Float`: values used in only one branch are computed in that branch.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use super::closure::exp_uses;
+use super::*;
+
+mod beta;
+mod dce;
+mod flatten;
+mod float;
+mod fold;
+mod inline;
+mod known;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    Inline,
+    Beta,
+    Fold,
+    KnownCase,
+    Flatten,
+    Dce,
+    Float,
+}
+
+impl Pass {
+    /// Every pass, in the order they run by default.
+    pub const ALL: [Pass; 7] = [
+        Pass::Beta,
+        Pass::Inline,
+        Pass::Fold,
+        Pass::KnownCase,
+        Pass::Flatten,
+        Pass::Dce,
+        Pass::Float,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::Beta => "beta",
+            Pass::Fold => "fold",
+            Pass::KnownCase => "known-case",
+            Pass::Flatten => "flatten",
+            Pass::Dce => "dce",
+            Pass::Float => "float",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.iter().copied().find(|pass| pass.name() == name)
+    }
+
+    /// Run the pass, and say whether it changed the program.
+    pub fn run(self, program: &mut Program, options: &Options) -> bool {
+        match self {
+            Pass::Inline => inline::run(program, options.inline_size),
+            Pass::Beta => beta::run(program),
+            Pass::Fold => fold::run(program),
+            Pass::KnownCase => known::run(program),
+            Pass::Flatten => flatten::run(program),
+            Pass::Dce => dce::run(program),
+            Pass::Float => float::run(program),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The passes to run each round, in order.
+    pub passes: Vec<Pass>,
+    /// The most rounds to run.
+    pub rounds: usize,
+    /// The largest function `Inline` copies, counting the terms in it.
+    pub inline_size: usize,
+    /// Whether to check the program after every pass.
+    pub check: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            passes: Pass::ALL.to_vec(),
+            rounds: 4,
+            inline_size: 24,
+            check: false,
+        }
+    }
+}
+
+/// A pass that left a program malformed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Broken {
+    pub pass: Pass,
+    pub errors: Vec<check::Error>,
+}
+
+impl Display for Broken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "`{}` made a malformed program:", self.pass.name())?;
+        for error in &self.errors {
+            write!(f, "\n{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Optimise a program with the default options.
+pub fn optimise(program: &mut Program) {
+    optimise_with(program, &Options::default()).expect("the program isn't checked");
+}
+
+pub fn optimise_with(program: &mut Program, options: &Options) -> Result<(), Broken> {
+    assert!(
+        program.funs.is_empty(),
+        "programs are optimised before closure conversion"
+    );
+    for _ in 0..options.rounds {
+        let mut changed = false;
+        for pass in &options.passes {
+            changed |= pass.run(program, options);
+            if options.check {
+                check::check(program).map_err(|errors| Broken {
+                    pass: *pass,
+                    errors,
+                })?;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Take `main`'s body out of a program to rewrite it.
+fn take_body(program: &mut Program) -> Term {
+    let placeholder = Term::Jump(program.main.ret, Vec::new());
+    std::mem::replace(&mut program.main.body, placeholder)
+}
+
+/// A use of a variable or continuation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Use {
+    Var(Var),
+    /// A variable called as a function.
+    Call(Var),
+    Cont(Cont),
+    /// A continuation jumped to, rather than passed to a call or branched
+    /// to.
+    Jump(Cont),
+}
+
+/// The uses in a term itself, not in the terms in it.
+fn uses(term: &Term, mut f: impl FnMut(Use)) {
+    match term {
+        Term::Let(_, exp, _) => exp_uses(exp).into_iter().for_each(|var| f(Use::Var(var))),
+        Term::Checked { args, handler, .. } => {
+            args.iter().for_each(|var| f(Use::Var(*var)));
+            f(Use::Cont(*handler));
+        }
+        Term::LetCont(_, _) | Term::LetFun(_, _) => (),
+        Term::LetClosures(closures, _) => closures
+            .iter()
+            .flat_map(|closure| &closure.free)
+            .for_each(|var| f(Use::Var(*var))),
+        Term::Call {
+            callee,
+            args,
+            ret,
+            handler,
+        } => {
+            if let Callee::Closure(var) = callee {
+                f(Use::Call(*var));
+            }
+            args.iter().for_each(|var| f(Use::Var(*var)));
+            f(Use::Cont(*ret));
+            f(Use::Cont(*handler));
+        }
+        Term::Jump(cont, args) => {
+            args.iter().for_each(|var| f(Use::Var(*var)));
+            f(Use::Jump(*cont));
+        }
+        Term::If(var, a, b) => {
+            f(Use::Var(*var));
+            f(Use::Cont(*a));
+            f(Use::Cont(*b));
+        }
+        Term::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            f(Use::Var(*scrutinee));
+            cases.iter().for_each(|(_, cont)| f(Use::Cont(*cont)));
+            default.iter().for_each(|cont| f(Use::Cont(*cont)));
+        }
+    }
+}
+
+/// The terms in a term, including function bodies.
+fn children(term: &Term) -> Vec<&Term> {
+    match term {
+        Term::Let(_, _, body) | Term::Checked { body, .. } | Term::LetClosures(_, body) => {
+            vec![body]
+        }
+        Term::LetCont(def, body) => vec![&def.body, body],
+        Term::LetFun(funs, body) => {
+            let mut terms: Vec<&Term> = funs.iter().map(|fun| &fun.body).collect();
+            terms.push(body);
+            terms
+        }
+        Term::Call { .. } | Term::Jump(_, _) | Term::If(_, _, _) | Term::Switch { .. } => {
+            Vec::new()
+        }
+    }
+}
+
+/// How many times each variable and continuation is used.
+#[derive(Debug, Default)]
+struct Census {
+    vars: HashMap<Var, usize>,
+    calls: HashMap<Var, usize>,
+    conts: HashMap<Cont, usize>,
+    jumps: HashMap<Cont, usize>,
+}
+
+impl Census {
+    fn of(term: &Term) -> Census {
+        let mut census = Census::default();
+        census.add(term);
+        census
+    }
+
+    fn add(&mut self, term: &Term) {
+        self.count(term, true)
+    }
+
+    /// Stop counting the uses in a term that's been removed.
+    fn remove(&mut self, term: &Term) {
+        self.count(term, false)
+    }
+
+    fn count(&mut self, term: &Term, add: bool) {
+        uses(term, |used| self.count_use(used, add));
+        for child in children(term) {
+            self.count(child, add);
+        }
+    }
+
+    fn count_use(&mut self, used: Use, add: bool) {
+        let update = |n: &mut usize| {
+            if add {
+                *n += 1
+            } else {
+                *n -= 1
+            }
+        };
+        match used {
+            Use::Var(var) => update(self.vars.entry(var).or_insert(0)),
+            Use::Call(var) => update(self.calls.entry(var).or_insert(0)),
+            Use::Cont(cont) => update(self.conts.entry(cont).or_insert(0)),
+            Use::Jump(cont) => {
+                update(self.conts.entry(cont).or_insert(0));
+                update(self.jumps.entry(cont).or_insert(0));
+            }
+        }
+    }
+
+    /// How many times a variable is used as a value.
+    fn values(&self, var: Var) -> usize {
+        self.vars.get(&var).copied().unwrap_or(0)
+    }
+
+    fn calls(&self, var: Var) -> usize {
+        self.calls.get(&var).copied().unwrap_or(0)
+    }
+
+    fn var(&self, var: Var) -> usize {
+        self.values(var) + self.calls(var)
+    }
+
+    fn cont(&self, cont: Cont) -> usize {
+        self.conts.get(&cont).copied().unwrap_or(0)
+    }
+
+    fn jumps(&self, cont: Cont) -> usize {
+        self.jumps.get(&cont).copied().unwrap_or(0)
+    }
+}
+
+/// Variables and continuations to replace by others.
+#[derive(Debug, Default)]
+struct Rename {
+    vars: HashMap<Var, Var>,
+    conts: HashMap<Cont, Cont>,
+}
+
+impl Rename {
+    fn var(&self, mut var: Var) -> Var {
+        while let Some(next) = self.vars.get(&var) {
+            var = *next;
+        }
+        var
+    }
+
+    fn cont(&self, mut cont: Cont) -> Cont {
+        while let Some(next) = self.conts.get(&cont) {
+            cont = *next;
+        }
+        cont
+    }
+
+    fn vars(&self, vars: &mut [Var]) {
+        for var in vars {
+            *var = self.var(*var);
+        }
+    }
+
+    fn exp(&self, exp: &mut Exp) {
+        match exp {
+            Exp::Const(_) | Exp::Global(_) => (),
+            Exp::Record(vars) | Exp::Prim(_, vars) => self.vars(vars),
+            Exp::Con(_, arg) | Exp::Exn(_, arg) => self.vars(arg.as_mut_slice()),
+            Exp::Select(_, var)
+            | Exp::Tag(var)
+            | Exp::ConArg(var)
+            | Exp::ExnId(var)
+            | Exp::ExnArg(var)
+            | Exp::Free(_, var)
+            | Exp::SetGlobal(_, var) => *var = self.var(*var),
+        }
+    }
+
+    /// Rename the uses in a term itself, not in the terms in it.
+    fn uses(&self, term: &mut Term) {
+        match term {
+            Term::Let(_, exp, _) => self.exp(exp),
+            Term::Checked { args, handler, .. } => {
+                self.vars(args);
+                *handler = self.cont(*handler);
+            }
+            Term::LetCont(_, _) | Term::LetFun(_, _) => (),
+            Term::LetClosures(closures, _) => {
+                for closure in closures {
+                    self.vars(&mut closure.free);
+                }
+            }
+            Term::Call {
+                callee,
+                args,
+                ret,
+                handler,
+            } => {
+                if let Callee::Closure(var) = callee {
+                    *var = self.var(*var);
+                }
+                self.vars(args);
+                *ret = self.cont(*ret);
+                *handler = self.cont(*handler);
+            }
+            Term::Jump(cont, args) => {
+                *cont = self.cont(*cont);
+                self.vars(args);
+            }
+            Term::If(var, a, b) => {
+                *var = self.var(*var);
+                *a = self.cont(*a);
+                *b = self.cont(*b);
+            }
+            Term::Switch {
+                scrutinee,
+                cases,
+                default,
+            } => {
+                *scrutinee = self.var(*scrutinee);
+                for (_, cont) in cases {
+                    *cont = self.cont(*cont);
+                }
+                if let Some(cont) = default {
+                    *cont = self.cont(*cont);
+                }
+            }
+        }
+    }
+}
+
+/// Copies terms, binding new variables and continuations in the copy.
+struct Copier<'p> {
+    program: &'p mut Program,
+    rename: Rename,
+}
+
+impl Copier<'_> {
+    fn var(&mut self, var: Var) -> Var {
+        let name = self.program.name(var).to_owned();
+        let copy = self.program.var(&name);
+        self.rename.vars.insert(var, copy);
+        copy
+    }
+
+    fn cont(&mut self, cont: Cont) -> Cont {
+        let copy = self.program.cont();
+        self.rename.conts.insert(cont, copy);
+        copy
+    }
+
+    fn fun(&mut self, fun: &Fun) -> Fun {
+        let params = fun.params.iter().map(|param| self.var(*param)).collect();
+        let ret = self.cont(fun.ret);
+        let handler = self.cont(fun.handler);
+        Fun {
+            name: self.rename.var(fun.name),
+            params,
+            ret,
+            handler,
+            body: self.term(&fun.body),
+        }
+    }
+
+    fn term(&mut self, term: &Term) -> Term {
+        let mut copy = match term {
+            Term::Let(var, exp, body) => {
+                let var = self.var(*var);
+                Term::Let(var, exp.clone(), Box::new(self.term(body)))
+            }
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => {
+                let var = self.var(*var);
+                Term::Checked {
+                    var,
+                    op: *op,
+                    args: args.clone(),
+                    handler: *handler,
+                    body: Box::new(self.term(body)),
+                }
+            }
+            Term::LetCont(def, body) => {
+                let cont = self.cont(def.cont);
+                let params = def.params.iter().map(|param| self.var(*param)).collect();
+                let def = ContDef {
+                    cont,
+                    params,
+                    body: self.term(&def.body),
+                };
+                Term::LetCont(Box::new(def), Box::new(self.term(body)))
+            }
+            Term::LetFun(funs, body) => {
+                for fun in funs {
+                    self.var(fun.name);
+                }
+                let funs = funs.iter().map(|fun| self.fun(fun)).collect();
+                Term::LetFun(funs, Box::new(self.term(body)))
+            }
+            Term::LetClosures(closures, body) => {
+                let closures = closures
+                    .iter()
+                    .map(|closure| Closure {
+                        var: self.var(closure.var),
+                        ..closure.clone()
+                    })
+                    .collect();
+                Term::LetClosures(closures, Box::new(self.term(body)))
+            }
+            term => term.clone(),
+        };
+        // Everything the uses could refer to has its copy by now.
+        self.rename.uses(&mut copy);
+        copy
+    }
+}
+
+/// Whether an expression can be dropped if its value isn't used.
+fn is_pure(exp: &Exp) -> bool {
+    !matches!(
+        exp,
+        Exp::Prim(PrimOp::Print, _) | Exp::Prim(PrimOp::Assign, _) | Exp::SetGlobal(_, _)
+    )
+}
+
+/// How many terms there are in a term, including in function bodies.
+fn size(term: &Term) -> usize {
+    1 + children(term).into_iter().map(size).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{lower_basis_with, lower_str};
+    use super::*;
+    use std::collections::HashSet;
+
+    /// A program whose `main` is the term `f` makes, given `main`'s return
+    /// and handler continuations.
+    pub(super) fn build(f: impl FnOnce(&mut Program, Cont, Cont) -> Term) -> Program {
+        let mut program = lower_str("");
+        let (ret, handler) = (program.main.ret, program.main.handler);
+        program.main.body = f(&mut program, ret, handler);
+        check::check(&program).unwrap();
+        program
+    }
+
+    pub(super) fn lets(lets: Vec<(Var, Exp)>, term: Term) -> Term {
+        lets.into_iter()
+            .rev()
+            .fold(term, |term, (var, exp)| Term::Let(var, exp, Box::new(term)))
+    }
+
+    pub(super) fn int(n: i64) -> Exp {
+        Exp::Const(Const::Int(n))
+    }
+
+    /// Run a pass, check the program, and print `main`'s body.
+    pub(super) fn after(pass: Pass, program: &mut Program) -> String {
+        pass.run(program, &Options::default());
+        if let Err(errors) = check::check(program) {
+            let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            panic!("{}\n{}", errors.join("\n"), program);
+        }
+        assert_unique(program);
+        let printed = program.to_string();
+        let lines: Vec<&str> = printed.lines().collect();
+        let body: Vec<String> = lines[1..lines.len() - 1]
+            .iter()
+            .map(|line| line[2..].to_owned() + "\n")
+            .collect();
+        body.concat()
+    }
+
+    /// Every binder in a term, to check that they're all different.
+    fn binders(term: &Term, vars: &mut Vec<Var>, conts: &mut Vec<Cont>) {
+        match term {
+            Term::Let(var, _, _) | Term::Checked { var, .. } => vars.push(*var),
+            Term::LetCont(def, _) => {
+                conts.push(def.cont);
+                vars.extend(&def.params);
+            }
+            Term::LetFun(funs, _) => {
+                for fun in funs {
+                    vars.push(fun.name);
+                    vars.extend(&fun.params);
+                    conts.extend([fun.ret, fun.handler]);
+                }
+            }
+            Term::LetClosures(closures, _) => {
+                vars.extend(closures.iter().map(|closure| closure.var));
+            }
+            _ => (),
+        }
+        for child in children(term) {
+            binders(child, vars, conts);
+        }
+    }
+
+    pub(super) fn assert_unique(program: &Program) {
+        let (mut vars, mut conts) = (Vec::new(), Vec::new());
+        binders(&program.main.body, &mut vars, &mut conts);
+        let unique: HashSet<_> = vars.iter().collect();
+        assert_eq!(unique.len(), vars.len(), "{}", program);
+        let unique: HashSet<_> = conts.iter().collect();
+        assert_eq!(unique.len(), conts.len(), "{}", program);
+    }
+
+    /// Optimise with every pass, checking the program after each.
+    pub(super) fn optimised(mut program: Program) -> Program {
+        let options = Options {
+            check: true,
+            ..Options::default()
+        };
+        if let Err(broken) = optimise_with(&mut program, &options) {
+            panic!("{}\n{}", broken, program);
+        }
+        assert_unique(&program);
+        program
+    }
+
+    #[test]
+    fn copies_bind_new_variables() {
+        let mut program =
+            lower_str("fun f x = let val (a, b) = x fun g y = a + y in g b handle Div => 0 end");
+        let fun = match &program.main.body {
+            Term::LetFun(funs, _) => funs[0].clone(),
+            term => panic!("expected a function, found {:?}", term),
+        };
+        let mut copier = Copier {
+            program: &mut program,
+            rename: Rename::default(),
+        };
+        let copy = copier.fun(&fun);
+        let (mut original, mut copied) = (Vec::new(), Vec::new());
+        let (mut conts, mut copied_conts) = (Vec::new(), Vec::new());
+        binders(&fun.body, &mut original, &mut conts);
+        binders(&copy.body, &mut copied, &mut copied_conts);
+        assert_eq!(original.len(), copied.len());
+        assert!(original.iter().all(|var| !copied.contains(var)));
+        assert!(conts.iter().all(|cont| !copied_conts.contains(cont)));
+        // The copy uses its own variables, and only those.
+        let census = Census::of(&copy.body);
+        assert!(original.iter().all(|var| census.var(*var) == 0));
+        assert!(census.var(copy.params[0]) > 0);
+    }
+
+    #[test]
+    fn passes() {
+        for pass in Pass::ALL {
+            assert_eq!(Pass::from_name(pass.name()), Some(pass));
+        }
+        assert_eq!(Pass::from_name("unroll"), None);
+    }
+
+    #[test]
+    fn basis() {
+        let program = lower_basis_with(
+            "val _ = print (Int.toString (foldl op + 0 [1, 2, 3]))",
+            Representation::Specialised,
+        );
+        assert_unique(&program);
+        let before = size(&program.main.body);
+        let program = std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(move || optimised(program))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(size(&program.main.body) < before);
+    }
+}
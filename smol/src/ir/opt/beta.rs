@@ -0,0 +1,200 @@
+//! Beta contraction: a function that's only called once, and a continuation
+//! that's only jumped to once, are replaced by their bodies where they're
+//! used, with their parameters renamed to the arguments. Nothing is copied,
+//! so this only ever makes a program smaller.
+//!
+//! A continuation that just passes its parameters on to another is replaced
+//! by the other.
+
+use std::collections::HashMap;
+
+use super::*;
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let body = take_body(program);
+    let mut beta = Beta {
+        census: Census::of(&body),
+        conts: HashMap::new(),
+        funs: HashMap::new(),
+        rename: Rename::default(),
+        changed: false,
+    };
+    program.main.body = beta.term(body);
+    beta.changed
+}
+
+struct Beta {
+    census: Census,
+    /// The continuations and functions taken out to be put where they're
+    /// used, which is always after where they were bound.
+    conts: HashMap<Cont, ContDef>,
+    funs: HashMap<Var, Fun>,
+    rename: Rename,
+    changed: bool,
+}
+
+impl Beta {
+    /// Rename parameters to arguments, and go on with the body they're in.
+    fn contract(&mut self, params: Vec<Var>, args: Vec<Var>, body: Term) -> Term {
+        self.changed = true;
+        for (param, arg) in params.into_iter().zip(args) {
+            self.rename.vars.insert(param, arg);
+        }
+        self.term(body)
+    }
+
+    fn term(&mut self, mut term: Term) -> Term {
+        self.rename.uses(&mut term);
+        match term {
+            Term::Let(var, exp, body) => Term::Let(var, exp, Box::new(self.term(*body))),
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body: Box::new(self.term(*body)),
+            },
+            Term::LetCont(mut def, body) => {
+                if let Term::Jump(target, args) = &def.body {
+                    // A continuation that's been taken out must stay where
+                    // its one jump is.
+                    let target = self.rename.cont(*target);
+                    if *args == def.params
+                        && target != def.cont
+                        && !self.conts.contains_key(&target)
+                    {
+                        self.changed = true;
+                        self.rename.conts.insert(def.cont, target);
+                        return self.term(*body);
+                    }
+                }
+                if self.census.jumps(def.cont) == 1 && self.census.cont(def.cont) == 1 {
+                    self.conts.insert(def.cont, *def);
+                    return self.term(*body);
+                }
+                def.body = self.term(def.body);
+                Term::LetCont(def, Box::new(self.term(*body)))
+            }
+            Term::LetFun(funs, body) => {
+                let mut kept = Vec::new();
+                for fun in funs {
+                    if self.census.calls(fun.name) == 1 && self.census.values(fun.name) == 0 {
+                        self.funs.insert(fun.name, fun);
+                    } else {
+                        kept.push(fun);
+                    }
+                }
+                let kept: Vec<Fun> = kept
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                let body = self.term(*body);
+                if kept.is_empty() {
+                    body
+                } else {
+                    Term::LetFun(kept, Box::new(body))
+                }
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            Term::Jump(cont, args) => match self.conts.remove(&cont) {
+                Some(def) => self.contract(def.params, args, def.body),
+                None => Term::Jump(cont, args),
+            },
+            Term::Call {
+                callee: Callee::Closure(f),
+                args,
+                ret,
+                handler,
+            } if self.funs.contains_key(&f) => {
+                let fun = self.funs.remove(&f).unwrap();
+                self.rename.conts.insert(fun.ret, ret);
+                self.rename.conts.insert(fun.handler, handler);
+                self.contract(fun.params, args, fun.body)
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::tests::lower_str;
+    use super::super::tests::{after, build, int, lets};
+    use super::*;
+
+    #[test]
+    fn continuations() {
+        let mut program = build(|p, ret, _| {
+            let (a, x, y) = (p.var("a"), p.var("x"), p.var("y"));
+            let (k1, k2) = (p.cont(), p.cont());
+            // `k2` just passes `y` on to `k0`, and `k1` is only jumped to once.
+            let k2_def = ContDef {
+                cont: k2,
+                params: vec![y],
+                body: Term::Jump(ret, vec![y]),
+            };
+            let k1_def = ContDef {
+                cont: k1,
+                params: vec![x],
+                body: Term::Jump(k2, vec![x]),
+            };
+            let body = Term::LetCont(
+                Box::new(k1_def),
+                Box::new(lets(vec![(a, int(1))], Term::Jump(k1, vec![a]))),
+            );
+            Term::LetCont(Box::new(k2_def), Box::new(body))
+        });
+        assert_eq!(
+            after(Pass::Beta, &mut program),
+            "\
+let a.2 = 1
+jump k0 (a.2)
+"
+        );
+    }
+
+    #[test]
+    fn loops_stay() {
+        let program = build(|p, _, _| {
+            let k = p.cont();
+            let def = ContDef {
+                cont: k,
+                params: Vec::new(),
+                body: Term::Jump(k, Vec::new()),
+            };
+            Term::LetCont(Box::new(def), Box::new(Term::Jump(k, Vec::new())))
+        });
+        // `k` is jumped to once to start it, and once by itself.
+        let mut contracted = program.clone();
+        assert!(!run(&mut contracted));
+        assert_eq!(contracted, program);
+    }
+
+    #[test]
+    fn functions() {
+        let mut program = lower_str(
+            "fun f (x, y) = x + y
+             fun twice g = g (g 1)
+             val a = f (1, 2)
+             val b = twice (fn n => n * 2)",
+        );
+        let printed = after(Pass::Beta, &mut program);
+        // `f` and `twice` are called once, but `twice` calls the anonymous
+        // function twice.
+        assert!(!printed.contains("fun f."), "{}", printed);
+        assert!(!printed.contains("fun twice"), "{}", printed);
+        assert_eq!(printed.matches("call fn").count(), 2, "{}", printed);
+        assert_eq!(printed.matches("call").count(), 2, "{}", printed);
+    }
+}
@@ -0,0 +1,208 @@
+//! Dead code elimination: values that are never used and whose expressions
+//! have no effects, and functions and continuations that are never used
+//! except by themselves, are removed.
+//!
+//! Terms are visited after the terms in them, and removing one stops
+//! counting its uses, so whatever only it used is removed too.
+
+use super::*;
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let body = take_body(program);
+    let mut dce = Dce {
+        census: Census::of(&body),
+        changed: false,
+    };
+    program.main.body = dce.term(body);
+    dce.changed
+}
+
+struct Dce {
+    census: Census,
+    changed: bool,
+}
+
+impl Dce {
+    fn remove(&mut self, term: &Term) {
+        self.changed = true;
+        self.census.remove(term);
+    }
+
+    fn term(&mut self, term: Term) -> Term {
+        match term {
+            Term::Let(var, exp, body) => {
+                let body = self.term(*body);
+                if self.census.var(var) == 0 && is_pure(&exp) {
+                    self.changed = true;
+                    for used in exp_uses(&exp) {
+                        self.census.count_use(Use::Var(used), false);
+                    }
+                    return body;
+                }
+                Term::Let(var, exp, Box::new(body))
+            }
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body: Box::new(self.term(*body)),
+            },
+            Term::LetCont(mut def, body) => {
+                let body = self.term(*body);
+                def.body = self.term(def.body);
+                let own = Census::of(&def.body).cont(def.cont);
+                if self.census.cont(def.cont) == own {
+                    self.remove(&def.body);
+                    return body;
+                }
+                Term::LetCont(def, Box::new(body))
+            }
+            Term::LetFun(funs, body) => {
+                let body = self.term(*body);
+                let funs: Vec<Fun> = funs
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                // A function is used if something outside the group uses it,
+                // or a function that's used does.
+                let censuses: Vec<Census> = funs.iter().map(|fun| Census::of(&fun.body)).collect();
+                let mut live: Vec<bool> = funs
+                    .iter()
+                    .map(|fun| {
+                        let own: usize = censuses.iter().map(|census| census.var(fun.name)).sum();
+                        self.census.var(fun.name) > own
+                    })
+                    .collect();
+                let mut changed = true;
+                while changed {
+                    changed = false;
+                    for i in 0..funs.len() {
+                        for j in 0..funs.len() {
+                            if live[i] && !live[j] && censuses[i].var(funs[j].name) > 0 {
+                                live[j] = true;
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+                let mut kept = Vec::new();
+                for (fun, live) in funs.into_iter().zip(live) {
+                    if live {
+                        kept.push(fun);
+                    } else {
+                        self.remove(&fun.body);
+                    }
+                }
+                if kept.is_empty() {
+                    body
+                } else {
+                    Term::LetFun(kept, Box::new(body))
+                }
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::tests::lower_str;
+    use super::super::tests::{after, build, int, lets};
+    use super::*;
+
+    #[test]
+    fn values() {
+        let mut program = build(|p, ret, _| {
+            let (a, b, pair, first, r, unit) = (
+                p.var("a"),
+                p.var("b"),
+                p.var("pair"),
+                p.var("first"),
+                p.var("r"),
+                p.var("unit"),
+            );
+            let (s, printed) = (p.var("s"), p.var("printed"));
+            lets(
+                vec![
+                    (a, int(1)),
+                    (b, int(2)),
+                    (pair, Exp::Record(vec![a, b])),
+                    (first, Exp::Select(0, pair)),
+                    (r, Exp::Prim(PrimOp::Ref, vec![b])),
+                    (s, Exp::Const(Const::String("hi".to_owned()))),
+                    (printed, Exp::Prim(PrimOp::Print, vec![s])),
+                    (unit, Exp::Record(Vec::new())),
+                ],
+                Term::Jump(ret, vec![unit]),
+            )
+        });
+        // Only printing has an effect, so only the string is kept with it.
+        assert_eq!(
+            after(Pass::Dce, &mut program),
+            "\
+let s.8 = \"hi\"
+let printed.9 = print s.8
+let unit.7 = {}
+jump k0 (unit.7)
+"
+        );
+    }
+
+    #[test]
+    fn functions_and_continuations() {
+        let mut program = lower_str(
+            "fun even 0 = true | even n = odd (n - 1)
+             and odd 0 = false | odd n = even (n - 1)
+             fun f x = x
+             val y = f 1",
+        );
+        let printed = after(Pass::Dce, &mut program);
+        // `even` and `odd` only call each other, and `y` is never used, but
+        // calling `f` is kept, since calls can have effects.
+        assert!(!printed.contains("even"), "{}", printed);
+        assert!(printed.contains("call f"), "{}", printed);
+
+        let mut program = build(|p, ret, _| {
+            let (unit, k) = (p.var("unit"), p.cont());
+            let def = ContDef {
+                cont: k,
+                params: Vec::new(),
+                body: Term::Jump(k, Vec::new()),
+            };
+            let body = lets(
+                vec![(unit, Exp::Record(Vec::new()))],
+                Term::Jump(ret, vec![unit]),
+            );
+            Term::LetCont(Box::new(def), Box::new(body))
+        });
+        assert_eq!(
+            after(Pass::Dce, &mut program),
+            "\
+let unit.2 = {}
+jump k0 (unit.2)
+"
+        );
+    }
+
+    #[test]
+    fn checked_operations_stay() {
+        // Adding can raise `Overflow`, so it's kept even if its result isn't
+        // used.
+        let mut program = lower_str("fun f x = (x + 1; ()) val _ = f 1");
+        let printed = after(Pass::Dce, &mut program);
+        assert!(printed.contains("int_add"), "{}", printed);
+    }
+}
@@ -0,0 +1,223 @@
+//! Tuple flattening: a function that's only ever called, and only takes its
+//! argument apart, takes the fields it uses as separate arguments instead,
+//! so calling it doesn't make a tuple. A call passes the fields of a tuple
+//! it's just made, or takes the fields out of the argument it has.
+//!
+//! Functions that take more than one argument aren't closures after closure
+//! conversion, so they're always lambda lifted.
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::*;
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let body = take_body(program);
+    let census = Census::of(&body);
+    let mut selects = HashMap::new();
+    count_selects(&body, &mut selects);
+    let mut flattener = Flattener {
+        program,
+        census,
+        selects,
+        fields: HashMap::new(),
+        params: HashMap::new(),
+        records: HashMap::new(),
+        rename: Rename::default(),
+    };
+    let body = flattener.term(body);
+    let changed = !flattener.fields.is_empty();
+    program.main.body = body;
+    changed
+}
+
+/// How many times each variable has a field selected from it, and which.
+fn count_selects(term: &Term, selects: &mut HashMap<Var, (usize, BTreeSet<usize>)>) {
+    if let Term::Let(_, Exp::Select(i, var), _) = term {
+        let entry = selects.entry(*var).or_default();
+        entry.0 += 1;
+        entry.1.insert(*i);
+    }
+    for child in children(term) {
+        count_selects(child, selects);
+    }
+}
+
+struct Flattener<'p> {
+    program: &'p mut Program,
+    census: Census,
+    selects: HashMap<Var, (usize, BTreeSet<usize>)>,
+    /// The fields each flattened function takes.
+    fields: HashMap<Var, Vec<usize>>,
+    /// The parameters for the fields of flattened functions' arguments.
+    params: HashMap<(Var, usize), Var>,
+    /// The tuples made, and their fields.
+    records: HashMap<Var, Vec<Var>>,
+    rename: Rename,
+}
+
+impl Flattener<'_> {
+    /// The fields of a function's argument it uses, if it only takes it
+    /// apart, and it's only ever called.
+    fn flattens(&self, fun: &Fun) -> Option<Vec<usize>> {
+        let param = match fun.params[..] {
+            [param] => param,
+            _ => return None,
+        };
+        let (count, fields) = self.selects.get(&param)?;
+        let only_called = self.census.values(fun.name) == 0 && self.census.calls(fun.name) > 0;
+        if only_called && self.census.var(param) == *count {
+            Some(fields.iter().copied().collect())
+        } else {
+            None
+        }
+    }
+
+    fn term(&mut self, mut term: Term) -> Term {
+        self.rename.uses(&mut term);
+        match term {
+            Term::Let(var, Exp::Select(i, tuple), body)
+                if self.params.contains_key(&(tuple, i)) =>
+            {
+                self.rename.vars.insert(var, self.params[&(tuple, i)]);
+                self.term(*body)
+            }
+            Term::Let(var, exp, body) => {
+                if let Exp::Record(fields) = &exp {
+                    self.records.insert(var, fields.clone());
+                }
+                Term::Let(var, exp, Box::new(self.term(*body)))
+            }
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body: Box::new(self.term(*body)),
+            },
+            Term::LetCont(mut def, body) => {
+                def.body = self.term(def.body);
+                Term::LetCont(def, Box::new(self.term(*body)))
+            }
+            Term::LetFun(mut funs, body) => {
+                for fun in &mut funs {
+                    let fields = match self.flattens(fun) {
+                        Some(fields) => fields,
+                        None => continue,
+                    };
+                    let tuple = fun.params[0];
+                    let name = self.program.name(tuple).to_owned();
+                    fun.params = fields
+                        .iter()
+                        .map(|i| {
+                            let param = self.program.var(&name);
+                            self.params.insert((tuple, *i), param);
+                            param
+                        })
+                        .collect();
+                    self.fields.insert(fun.name, fields);
+                }
+                let funs = funs
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                Term::LetFun(funs, Box::new(self.term(*body)))
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            Term::Call {
+                callee: Callee::Closure(f),
+                args,
+                ret,
+                handler,
+            } if self.fields.contains_key(&f) => {
+                let tuple = args[0];
+                let mut lets = Vec::new();
+                let args = self.fields[&f]
+                    .clone()
+                    .into_iter()
+                    .map(|i| match self.records.get(&tuple) {
+                        Some(fields) => fields[i],
+                        None => {
+                            let name = self.program.name(tuple).to_owned();
+                            let field = self.program.var(&name);
+                            lets.push((field, Exp::Select(i, tuple)));
+                            field
+                        }
+                    })
+                    .collect();
+                let call = Term::Call {
+                    callee: Callee::Closure(f),
+                    args,
+                    ret,
+                    handler,
+                };
+                lets.into_iter()
+                    .rev()
+                    .fold(call, |term, (var, exp)| Term::Let(var, exp, Box::new(term)))
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::tests::lower_str;
+    use super::super::tests::after;
+    use super::*;
+    use crate::ir::closure;
+
+    #[test]
+    fn tuples() {
+        let mut program = lower_str(
+            "fun f (x, y, _) = x + y
+             val a = f (1, 2, 3)
+             fun g t = f t
+             val b = g (4, 5, 6)",
+        );
+        let printed = after(Pass::Flatten, &mut program);
+        // `f` takes the two fields it uses, so `g` takes them out of its
+        // argument to call it. That's all `g` does with it now, so the next
+        // round flattens `g` too.
+        assert!(printed.contains("fun f.1 (x.19, x.20)"), "{}", printed);
+        assert_eq!(printed.matches("select").count(), 2, "{}", printed);
+        let printed = after(Pass::Flatten, &mut program);
+        assert_eq!(printed.matches("select").count(), 0, "{}", printed);
+
+        // Closure conversion lifts `f` and `g`, since they can't be closures.
+        closure::convert_with(
+            &mut program,
+            closure::Options {
+                closures: closure::Closures::Flat,
+                lift: false,
+            },
+        );
+        assert_eq!(check::check(&program), Ok(()), "{}", program);
+        assert_eq!(
+            program.to_string().matches("call #").count(),
+            3,
+            "{}",
+            program
+        );
+    }
+
+    #[test]
+    fn escaping_functions_stay() {
+        let mut program = lower_str(
+            "fun f (x, y) = x + y
+             val a = f (1, 2)
+             val p = (f, 1)",
+        );
+        assert!(!run(&mut program));
+    }
+}
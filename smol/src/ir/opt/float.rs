@@ -0,0 +1,255 @@
+//! Let-floating: a value that's only used in one branch is computed in that
+//! branch, so the other branches don't compute it.
+//!
+//! A value moves into the continuation its one use is in, as deep as it can
+//! go without moving into a loop or a function, which would compute it more
+//! than once. Only expressions without effects move, and not `deref`, whose
+//! value depends on when it's done.
+
+use std::collections::HashMap;
+
+use super::*;
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let body = take_body(program);
+    let mut analysis = Analysis {
+        census: Census::of(&body),
+        frames: Vec::new(),
+        depths: HashMap::new(),
+        targets: HashMap::new(),
+    };
+    analysis.term(&body);
+    let changed = !analysis.targets.is_empty();
+    let mut floater = Floater {
+        targets: analysis.targets,
+        moving: HashMap::new(),
+    };
+    program.main.body = floater.term(body);
+    changed
+}
+
+fn floats(exp: &Exp) -> bool {
+    match exp {
+        Exp::Prim(op, _) => !matches!(op, PrimOp::Print | PrimOp::Assign | PrimOp::Deref),
+        Exp::Free(_, _) | Exp::Global(_) | Exp::SetGlobal(_, _) => false,
+        _ => true,
+    }
+}
+
+/// What a term is in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frame {
+    /// The body of a continuation that doesn't jump to itself.
+    Cont(Cont),
+    /// A continuation that does, or a function, which can run more than
+    /// once.
+    Loop,
+}
+
+/// Finds where each value is used.
+struct Analysis {
+    census: Census,
+    frames: Vec<Frame>,
+    /// The values that could move, and how deep they're bound.
+    depths: HashMap<Var, usize>,
+    /// Where the values that will move go.
+    targets: HashMap<Var, Cont>,
+}
+
+impl Analysis {
+    fn term(&mut self, term: &Term) {
+        uses(term, |used| {
+            let var = match used {
+                Use::Var(var) | Use::Call(var) => var,
+                Use::Cont(_) | Use::Jump(_) => return,
+            };
+            let depth = match self.depths.get(&var) {
+                Some(depth) => *depth,
+                None => return,
+            };
+            let target = self.frames[depth..]
+                .iter()
+                .map_while(|frame| match frame {
+                    Frame::Cont(cont) => Some(*cont),
+                    Frame::Loop => None,
+                })
+                .last();
+            if let Some(target) = target {
+                self.targets.insert(var, target);
+            }
+        });
+        match term {
+            Term::Let(var, exp, body) => {
+                if floats(exp) && self.census.var(*var) == 1 {
+                    self.depths.insert(*var, self.frames.len());
+                }
+                self.term(body);
+            }
+            Term::LetCont(def, body) => {
+                let frame = if Census::of(&def.body).cont(def.cont) > 0 {
+                    Frame::Loop
+                } else {
+                    Frame::Cont(def.cont)
+                };
+                self.frames.push(frame);
+                self.term(&def.body);
+                self.frames.pop();
+                self.term(body);
+            }
+            Term::LetFun(funs, body) => {
+                self.frames.push(Frame::Loop);
+                for fun in funs {
+                    self.term(&fun.body);
+                }
+                self.frames.pop();
+                self.term(body);
+            }
+            term => {
+                for child in children(term) {
+                    self.term(child);
+                }
+            }
+        }
+    }
+}
+
+struct Floater {
+    targets: HashMap<Var, Cont>,
+    /// The values taken out to be put in each continuation, in the order
+    /// they were bound.
+    moving: HashMap<Cont, Vec<(Var, Exp)>>,
+}
+
+impl Floater {
+    fn term(&mut self, term: Term) -> Term {
+        match term {
+            Term::Let(var, exp, body) => match self.targets.get(&var) {
+                Some(target) => {
+                    self.moving.entry(*target).or_default().push((var, exp));
+                    self.term(*body)
+                }
+                None => Term::Let(var, exp, Box::new(self.term(*body))),
+            },
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body: Box::new(self.term(*body)),
+            },
+            Term::LetCont(mut def, body) => {
+                let moved = self.moving.remove(&def.cont).unwrap_or_default();
+                let inner = self.term(def.body);
+                def.body = moved.into_iter().rev().fold(inner, |term, (var, exp)| {
+                    Term::Let(var, exp, Box::new(term))
+                });
+                Term::LetCont(def, Box::new(self.term(*body)))
+            }
+            Term::LetFun(funs, body) => {
+                let funs = funs
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                Term::LetFun(funs, Box::new(self.term(*body)))
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{after, build, int, lets};
+    use super::*;
+
+    fn cont(cont: Cont, body: Term, term: Term) -> Term {
+        let def = ContDef {
+            cont,
+            params: Vec::new(),
+            body,
+        };
+        Term::LetCont(Box::new(def), Box::new(term))
+    }
+
+    #[test]
+    fn into_branches() {
+        let mut program = build(|p, ret, _| {
+            let (b, one, pair, r, x) = (
+                p.var("b"),
+                p.var("one"),
+                p.var("pair"),
+                p.var("r"),
+                p.var("x"),
+            );
+            let (k1, k2) = (p.cont(), p.cont());
+            // `pair` is only used by `k1`, and `r` by `k2`, where `x` stays,
+            // since what it reads could change if it moved.
+            let k1_body = Term::Jump(ret, vec![pair]);
+            let k2_body = lets(
+                vec![(x, Exp::Prim(PrimOp::Deref, vec![r]))],
+                Term::Jump(ret, vec![x]),
+            );
+            lets(
+                vec![
+                    (b, Exp::Con(1, None)),
+                    (one, int(1)),
+                    (pair, Exp::Record(vec![one, one])),
+                    (r, Exp::Prim(PrimOp::Ref, vec![one])),
+                ],
+                cont(k1, k1_body, cont(k2, k2_body, Term::If(b, k1, k2))),
+            )
+        });
+        assert_eq!(
+            after(Pass::Float, &mut program),
+            "\
+let b.2 = con 1
+let one.3 = 1
+cont k2 () {
+  let pair.4 = {one.3, one.3}
+  jump k0 (pair.4)
+}
+cont k3 () {
+  let r.5 = ref one.3
+  let x.6 = deref r.5
+  jump k0 (x.6)
+}
+if b.2 then k2 else k3
+"
+        );
+    }
+
+    #[test]
+    fn not_into_loops() {
+        // Making `pair` in the loop would make it each time round.
+        let mut program = build(|p, ret, _| {
+            let (t, one, pair) = (p.var("t"), p.var("one"), p.var("pair"));
+            let (k1, k2) = (p.cont(), p.cont());
+            let exit = Term::Jump(ret, vec![pair]);
+            lets(
+                vec![
+                    (t, Exp::Con(1, None)),
+                    (one, int(1)),
+                    (pair, Exp::Record(vec![one, one])),
+                ],
+                cont(
+                    k1,
+                    cont(k2, exit, Term::If(t, k2, k1)),
+                    Term::Jump(k1, Vec::new()),
+                ),
+            )
+        });
+        assert!(!run(&mut program));
+    }
+}
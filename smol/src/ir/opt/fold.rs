@@ -0,0 +1,446 @@
+//! Constant folding.
+//!
+//! Ints are only folded when the result fits in 63 bits, which every backend
+//! can represent, so overflow is left to the backend; an operation that
+//! raises `Div` becomes a raise. A few operations whose result is one of
+//! their arguments, like adding zero, are removed.
+
+use std::collections::HashMap;
+
+use super::*;
+use crate::ast::{MAX_INT, MIN_INT};
+use crate::types::BUILTIN_EXNS;
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let body = take_body(program);
+    let mut folder = Folder {
+        program,
+        values: HashMap::new(),
+        rename: Rename::default(),
+        changed: false,
+    };
+    let body = folder.term(body);
+    let changed = folder.changed;
+    program.main.body = body;
+    changed
+}
+
+/// A constant value, as operations see it: chars and constructors without
+/// arguments are ints.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i64),
+    Word(u64),
+    Real(f64),
+    String(String),
+}
+
+impl Value {
+    fn of(exp: &Exp) -> Option<Value> {
+        match exp {
+            Exp::Const(Const::Int(n)) if (MIN_INT..=MAX_INT).contains(n) => Some(Value::Int(*n)),
+            Exp::Const(Const::Int(_)) => None,
+            Exp::Const(Const::Char(c)) => Some(Value::Int(*c as i64)),
+            Exp::Const(Const::Word(w)) => Some(Value::Word(*w)),
+            Exp::Const(Const::Real(x)) => Some(Value::Real(*x)),
+            Exp::Const(Const::String(s)) => Some(Value::String(s.clone())),
+            Exp::Con(tag, None) => Some(Value::Int(*tag as i64)),
+            _ => None,
+        }
+    }
+}
+
+/// What an operation on constants gives: its value, or the built in
+/// exception it raises.
+type Folded = Result<Exp, &'static str>;
+
+fn int(n: Option<i64>) -> Option<Folded> {
+    n.filter(|n| (MIN_INT..=MAX_INT).contains(n))
+        .map(|n| Ok(Exp::Const(Const::Int(n))))
+}
+
+fn bool(b: bool) -> Option<Folded> {
+    Some(Ok(Exp::Con(b as u32, None)))
+}
+
+fn word(w: u64) -> Option<Folded> {
+    Some(Ok(Exp::Const(Const::Word(w))))
+}
+
+fn real(x: f64) -> Option<Folded> {
+    Some(Ok(Exp::Const(Const::Real(x))))
+}
+
+/// Division rounding down, as `div` does.
+fn div(a: i64, b: i64) -> Option<i64> {
+    let q = a.checked_div(b)?;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        Some(q - 1)
+    } else {
+        Some(q)
+    }
+}
+
+/// The remainder with the sign of the divisor, as `mod` gives.
+fn modulo(a: i64, b: i64) -> Option<i64> {
+    let r = a.checked_rem(b)?;
+    if r != 0 && (r < 0) != (b < 0) {
+        Some(r + b)
+    } else {
+        Some(r)
+    }
+}
+
+/// Apply an operation to constants, if that can be done at compile time.
+fn fold(op: PrimOp, args: &[Value]) -> Option<Folded> {
+    use PrimOp::*;
+    use Value::*;
+    match (op, args) {
+        (IntAdd, [Int(a), Int(b)]) => int(a.checked_add(*b)),
+        (IntSub, [Int(a), Int(b)]) => int(a.checked_sub(*b)),
+        (IntMul, [Int(a), Int(b)]) => int(a.checked_mul(*b)),
+        (IntDiv, [Int(_), Int(0)]) | (IntMod, [Int(_), Int(0)]) => Some(Err("Div")),
+        (IntDiv, [Int(a), Int(b)]) => int(div(*a, *b)),
+        (IntMod, [Int(a), Int(b)]) => int(modulo(*a, *b)),
+        (IntNeg, [Int(a)]) => int(a.checked_neg()),
+        (IntAbs, [Int(a)]) => int(a.checked_abs()),
+        (IntLt, [Int(a), Int(b)]) => bool(a < b),
+        (IntLe, [Int(a), Int(b)]) => bool(a <= b),
+        (IntGt, [Int(a), Int(b)]) => bool(a > b),
+        (IntGe, [Int(a), Int(b)]) => bool(a >= b),
+        (IntEq, [Int(a), Int(b)]) => bool(a == b),
+        (WordDiv, [Word(_), Word(0)]) | (WordMod, [Word(_), Word(0)]) => Some(Err("Div")),
+        (WordDiv, [Word(a), Word(b)]) => word(a / b),
+        (WordMod, [Word(a), Word(b)]) => word(a % b),
+        (WordAdd, [Word(a), Word(b)]) => word(a.wrapping_add(*b)),
+        (WordSub, [Word(a), Word(b)]) => word(a.wrapping_sub(*b)),
+        (WordMul, [Word(a), Word(b)]) => word(a.wrapping_mul(*b)),
+        (WordNeg, [Word(a)]) => word(a.wrapping_neg()),
+        (WordLt, [Word(a), Word(b)]) => bool(a < b),
+        (WordLe, [Word(a), Word(b)]) => bool(a <= b),
+        (WordGt, [Word(a), Word(b)]) => bool(a > b),
+        (WordGe, [Word(a), Word(b)]) => bool(a >= b),
+        (RealAdd, [Real(a), Real(b)]) => real(a + b),
+        (RealSub, [Real(a), Real(b)]) => real(a - b),
+        (RealMul, [Real(a), Real(b)]) => real(a * b),
+        (RealDiv, [Real(a), Real(b)]) => real(a / b),
+        (RealNeg, [Real(a)]) => real(-a),
+        (RealAbs, [Real(a)]) => real(a.abs()),
+        (RealLt, [Real(a), Real(b)]) => bool(a < b),
+        (RealLe, [Real(a), Real(b)]) => bool(a <= b),
+        (RealGt, [Real(a), Real(b)]) => bool(a > b),
+        (RealGe, [Real(a), Real(b)]) => bool(a >= b),
+        (StringLt, [String(a), String(b)]) => bool(a < b),
+        (StringLe, [String(a), String(b)]) => bool(a <= b),
+        (StringGt, [String(a), String(b)]) => bool(a > b),
+        (StringGe, [String(a), String(b)]) => bool(a >= b),
+        (StringConcat, [String(a), String(b)]) => {
+            Some(Ok(Exp::Const(Const::String(format!("{}{}", a, b)))))
+        }
+        (Equal, [Int(a), Int(b)]) => bool(a == b),
+        (Equal, [Word(a), Word(b)]) => bool(a == b),
+        (Equal, [String(a), String(b)]) => bool(a == b),
+        (Not, [Int(a)]) => bool(*a == 0),
+        _ => None,
+    }
+}
+
+/// The argument an operation gives back when its other argument is a
+/// constant that makes it do nothing, like `x + 0`.
+fn identity(op: PrimOp, args: &[Var], values: &[Option<Value>]) -> Option<Var> {
+    use PrimOp::*;
+    let empty = Some(Value::String(std::string::String::new()));
+    match (op, values) {
+        (IntAdd, [_, Some(Value::Int(0))])
+        | (IntSub, [_, Some(Value::Int(0))])
+        | (IntMul, [_, Some(Value::Int(1))])
+        | (WordAdd, [_, Some(Value::Word(0))])
+        | (WordSub, [_, Some(Value::Word(0))]) => Some(args[0]),
+        (IntAdd, [Some(Value::Int(0)), _])
+        | (IntMul, [Some(Value::Int(1)), _])
+        | (WordAdd, [Some(Value::Word(0)), _]) => Some(args[1]),
+        (StringConcat, [_, b]) if *b == empty => Some(args[0]),
+        (StringConcat, [a, _]) if *a == empty => Some(args[1]),
+        _ => None,
+    }
+}
+
+struct Folder<'p> {
+    program: &'p mut Program,
+    values: HashMap<Var, Value>,
+    rename: Rename,
+    changed: bool,
+}
+
+impl Folder<'_> {
+    /// Fold an operation, or find the argument it gives back.
+    fn op(&self, op: PrimOp, args: &[Var]) -> Option<Result<Folded, Var>> {
+        let values: Vec<Option<Value>> = args
+            .iter()
+            .map(|arg| self.values.get(arg).cloned())
+            .collect();
+        let known: Option<Vec<Value>> = values.iter().cloned().collect();
+        match known.and_then(|known| fold(op, &known)) {
+            Some(folded) => Some(Ok(folded)),
+            None => identity(op, args, &values).map(Err),
+        }
+    }
+
+    fn raise(&mut self, name: &str, handler: Cont) -> Term {
+        let id = BUILTIN_EXNS
+            .iter()
+            .position(|exn| *exn == name)
+            .expect("a built in exception") as u32;
+        self.program
+            .exns
+            .entry(id)
+            .or_insert_with(|| name.to_owned());
+        let exn = self.program.var("exn");
+        Term::Let(
+            exn,
+            Exp::Exn(id, None),
+            Box::new(Term::Jump(handler, vec![exn])),
+        )
+    }
+
+    fn term(&mut self, mut term: Term) -> Term {
+        self.rename.uses(&mut term);
+        match term {
+            Term::Let(var, mut exp, body) => {
+                if let Exp::Prim(op, args) = &exp {
+                    match self.op(*op, args) {
+                        Some(Ok(Ok(folded))) => exp = folded,
+                        Some(Err(arg)) => {
+                            self.changed = true;
+                            self.rename.vars.insert(var, arg);
+                            return self.term(*body);
+                        }
+                        // Only checked operations raise.
+                        Some(Ok(Err(_))) | None => (),
+                    }
+                    if !matches!(exp, Exp::Prim(_, _)) {
+                        self.changed = true;
+                    }
+                }
+                if let Some(value) = Value::of(&exp) {
+                    self.values.insert(var, value);
+                }
+                Term::Let(var, exp, Box::new(self.term(*body)))
+            }
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => match self.op(op, &args) {
+                Some(Ok(Ok(exp))) => {
+                    self.changed = true;
+                    self.term(Term::Let(var, exp, body))
+                }
+                Some(Ok(Err(exn))) => {
+                    self.changed = true;
+                    self.raise(exn, handler)
+                }
+                Some(Err(arg)) => {
+                    self.changed = true;
+                    self.rename.vars.insert(var, arg);
+                    self.term(*body)
+                }
+                None => Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body: Box::new(self.term(*body)),
+                },
+            },
+            Term::LetCont(mut def, body) => {
+                def.body = self.term(def.body);
+                Term::LetCont(def, Box::new(self.term(*body)))
+            }
+            Term::LetFun(funs, body) => {
+                let funs = funs
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                Term::LetFun(funs, Box::new(self.term(*body)))
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{after, build, int, lets};
+    use super::*;
+
+    fn checked(var: Var, op: PrimOp, args: Vec<Var>, handler: Cont, body: Term) -> Term {
+        Term::Checked {
+            var,
+            op,
+            args,
+            handler,
+            body: Box::new(body),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut program = build(|p, ret, handler| {
+            let (a, b, n, m) = (p.var("a"), p.var("b"), p.var("n"), p.var("m"));
+            let lt = p.var("lt");
+            let body = checked(m, PrimOp::IntMul, vec![n, b], handler, {
+                let exp = Exp::Prim(PrimOp::IntLt, vec![m, a]);
+                lets(vec![(lt, exp)], Term::Jump(ret, vec![m]))
+            });
+            let body = checked(n, PrimOp::IntAdd, vec![a, b], handler, body);
+            lets(vec![(a, int(-7)), (b, int(2))], body)
+        });
+        assert_eq!(
+            after(Pass::Fold, &mut program),
+            "\
+let a.2 = ~7
+let b.3 = 2
+let n.4 = ~5
+let m.5 = ~10
+let lt.6 = con 1
+jump k0 (m.5)
+"
+        );
+    }
+
+    #[test]
+    fn division() {
+        let mut program = build(|p, ret, handler| {
+            let (a, b, q, r) = (p.var("a"), p.var("b"), p.var("q"), p.var("r"));
+            let body = checked(r, PrimOp::IntMod, vec![a, b], handler, {
+                Term::Jump(ret, vec![r])
+            });
+            let body = checked(q, PrimOp::IntDiv, vec![a, b], handler, body);
+            lets(vec![(a, int(7)), (b, int(-2))], body)
+        });
+        assert_eq!(
+            after(Pass::Fold, &mut program),
+            "\
+let a.2 = 7
+let b.3 = ~2
+let q.4 = ~4
+let r.5 = ~1
+jump k0 (r.5)
+"
+        );
+
+        let mut program = build(|p, ret, handler| {
+            let (a, zero, q) = (p.var("a"), p.var("zero"), p.var("q"));
+            let body = checked(q, PrimOp::IntDiv, vec![a, zero], handler, {
+                Term::Jump(ret, vec![q])
+            });
+            lets(vec![(a, int(7)), (zero, int(0))], body)
+        });
+        assert_eq!(
+            after(Pass::Fold, &mut program),
+            "\
+let a.2 = 7
+let zero.3 = 0
+let exn.5 = exn Div.2
+jump k1 (exn.5)
+"
+        );
+    }
+
+    #[test]
+    fn overflow_is_left_to_the_backend() {
+        let mut program = build(|p, ret, handler| {
+            let (a, b, n) = (p.var("a"), p.var("b"), p.var("n"));
+            let body = checked(n, PrimOp::IntAdd, vec![a, b], handler, {
+                Term::Jump(ret, vec![n])
+            });
+            lets(vec![(a, int(MAX_INT)), (b, int(1))], body)
+        });
+        let before = after(Pass::Dce, &mut program);
+        assert!(!Pass::Fold.run(&mut program, &Options::default()));
+        assert_eq!(after(Pass::Fold, &mut program), before);
+    }
+
+    #[test]
+    fn identities() {
+        let mut program = build(|p, ret, handler| {
+            let (zero, one, r, x) = (p.var("zero"), p.var("one"), p.var("r"), p.var("x"));
+            let (a, b) = (p.var("a"), p.var("b"));
+            let body = checked(b, PrimOp::IntMul, vec![one, a], handler, {
+                Term::Jump(ret, vec![b])
+            });
+            let body = checked(a, PrimOp::IntAdd, vec![x, zero], handler, body);
+            lets(
+                vec![
+                    (zero, int(0)),
+                    (one, int(1)),
+                    (r, Exp::Prim(PrimOp::Ref, vec![zero])),
+                    (x, Exp::Prim(PrimOp::Deref, vec![r])),
+                ],
+                body,
+            )
+        });
+        assert_eq!(
+            after(Pass::Fold, &mut program),
+            "\
+let zero.2 = 0
+let one.3 = 1
+let r.4 = ref zero.2
+let x.5 = deref r.4
+jump k0 (x.5)
+"
+        );
+    }
+
+    #[test]
+    fn other_types() {
+        let mut program = build(|p, ret, _| {
+            let vars: Vec<Var> = (0..9).map(|i| p.var(&format!("v{}", i))).collect();
+            let string = |s: &str| Exp::Const(Const::String(s.to_owned()));
+            let unit = p.var("unit");
+            lets(
+                vec![
+                    (vars[0], Exp::Const(Const::Word(1))),
+                    (vars[1], Exp::Prim(PrimOp::WordSub, vec![vars[0], vars[0]])),
+                    (vars[2], Exp::Prim(PrimOp::WordSub, vec![vars[1], vars[0]])),
+                    (vars[3], Exp::Const(Const::Real(1.5))),
+                    (vars[4], Exp::Prim(PrimOp::RealMul, vec![vars[3], vars[3]])),
+                    (vars[5], string("ab")),
+                    (
+                        vars[6],
+                        Exp::Prim(PrimOp::StringConcat, vec![vars[5], vars[5]]),
+                    ),
+                    (vars[7], Exp::Prim(PrimOp::Equal, vec![vars[6], vars[5]])),
+                    (vars[8], Exp::Prim(PrimOp::Not, vec![vars[7]])),
+                    (unit, Exp::Record(Vec::new())),
+                ],
+                Term::Jump(ret, vec![unit]),
+            )
+        });
+        assert_eq!(
+            after(Pass::Fold, &mut program),
+            "\
+let v0.2 = 0w1
+let v1.3 = 0w0
+let v2.4 = 0w18446744073709551615
+let v3.5 = 1.5
+let v4.6 = 2.25
+let v5.7 = \"ab\"
+let v6.8 = \"abab\"
+let v7.9 = con 0
+let v8.10 = con 1
+let unit.11 = {}
+jump k0 (unit.11)
+"
+        );
+    }
+}
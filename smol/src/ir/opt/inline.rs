@@ -0,0 +1,146 @@
+//! Inlining: calls to small functions are replaced by copies of their
+//! bodies.
+//!
+//! A function is inlined if it's no bigger than the limit and doesn't use
+//! itself or the functions it's defined with, so copying it can't go on for
+//! ever. The copies aren't inlined into in the same round, so each round
+//! makes a program at most a limited amount bigger; `Dce` removes the
+//! functions that are no longer called.
+
+use std::collections::HashMap;
+
+use super::*;
+
+pub(super) fn run(program: &mut Program, limit: usize) -> bool {
+    let body = take_body(program);
+    let mut inliner = Inliner {
+        program,
+        limit,
+        funs: HashMap::new(),
+        changed: false,
+    };
+    let body = inliner.term(body);
+    let changed = inliner.changed;
+    program.main.body = body;
+    changed
+}
+
+struct Inliner<'p> {
+    program: &'p mut Program,
+    limit: usize,
+    /// The functions to inline, as they were before this pass.
+    funs: HashMap<Var, Fun>,
+    changed: bool,
+}
+
+impl Inliner<'_> {
+    fn inline(&mut self, fun: &Fun, args: Vec<Var>, ret: Cont, handler: Cont) -> Term {
+        self.changed = true;
+        let mut rename = Rename::default();
+        rename.vars.extend(fun.params.iter().copied().zip(args));
+        rename.conts.insert(fun.ret, ret);
+        rename.conts.insert(fun.handler, handler);
+        let mut copier = Copier {
+            program: self.program,
+            rename,
+        };
+        copier.term(&fun.body)
+    }
+
+    fn term(&mut self, term: Term) -> Term {
+        match term {
+            Term::Let(var, exp, body) => Term::Let(var, exp, Box::new(self.term(*body))),
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body: Box::new(self.term(*body)),
+            },
+            Term::LetCont(mut def, body) => {
+                def.body = self.term(def.body);
+                Term::LetCont(def, Box::new(self.term(*body)))
+            }
+            Term::LetFun(funs, body) => {
+                for fun in &funs {
+                    if size(&fun.body) > self.limit {
+                        continue;
+                    }
+                    let census = Census::of(&fun.body);
+                    if funs.iter().all(|group| census.var(group.name) == 0) {
+                        self.funs.insert(fun.name, fun.clone());
+                    }
+                }
+                let funs = funs
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                Term::LetFun(funs, Box::new(self.term(*body)))
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            Term::Call {
+                callee: Callee::Closure(f),
+                args,
+                ret,
+                handler,
+            } if self.funs.contains_key(&f) => {
+                let fun = self.funs.remove(&f).unwrap();
+                let copy = self.inline(&fun, args, ret, handler);
+                self.funs.insert(f, fun);
+                copy
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::tests::lower_str;
+    use super::super::tests::after;
+    use super::*;
+
+    #[test]
+    fn small_functions() {
+        let mut program = lower_str(
+            "fun add (x, y) = x + y
+             fun fact 0 = 1 | fact n = n * fact (n - 1)
+             val a = add (1, 2) + add (3, 4)
+             val b = fact a",
+        );
+        let printed = after(Pass::Inline, &mut program);
+        // Both calls to `add` are copies of it now, next to `add` itself and
+        // the sum of the two, but `fact` is recursive.
+        assert!(!printed.contains("call add"), "{}", printed);
+        assert_eq!(printed.matches("int_add").count(), 4, "{}", printed);
+        assert_eq!(printed.matches("call fact").count(), 2, "{}", printed);
+        let printed = after(Pass::Dce, &mut program);
+        assert!(!printed.contains("fun add"), "{}", printed);
+    }
+
+    #[test]
+    fn the_limit() {
+        let src = "fun f x = if x > 0 then x * 2 + 1 else x div 3 - 4
+                   val a = f 1";
+        let mut program = lower_str(src);
+        let limit = size(&match &program.main.body {
+            Term::LetFun(funs, _) => funs[0].body.clone(),
+            term => panic!("expected a function, found {:?}", term),
+        });
+        assert!(!run(&mut program, limit - 1));
+        assert!(run(&mut program, limit));
+        let printed = after(Pass::Dce, &mut program);
+        assert!(!printed.contains("fun f"), "{}", printed);
+    }
+}
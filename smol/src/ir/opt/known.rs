@@ -0,0 +1,332 @@
+//! Known-case elimination: taking apart a value whose constructor is known,
+//! and branching on it, is done at compile time.
+//!
+//! A value is known where it's made, and in a branch of an `if` or a switch
+//! on it that only that branch jumps to: there, a bool is known to be true
+//! or false, an int to be the case's, and a datatype value or exception to
+//! have the case's tag, so nested matches on the same value don't test it
+//! again.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use super::*;
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let body = take_body(program);
+    let mut known = Known {
+        census: Census::of(&body),
+        facts: HashMap::new(),
+        values: HashMap::new(),
+        tags: HashMap::new(),
+        rename: Rename::default(),
+        changed: false,
+    };
+    known.branches(&body);
+    program.main.body = known.term(body);
+    known.changed
+}
+
+/// What's known about a value.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Record(Vec<Var>),
+    Con(u32, Option<Var>),
+    Exn(u32, Option<Var>),
+    Int(i64),
+    /// A datatype value or exception with a known tag, but not a known
+    /// argument.
+    Tag(i64),
+}
+
+struct Known {
+    census: Census,
+    /// What's known in each branch, on entering it.
+    facts: HashMap<Cont, Vec<(Var, Value)>>,
+    values: HashMap<Var, Value>,
+    /// The values whose tags switches test, by the variables for the tags.
+    tags: HashMap<Var, Var>,
+    rename: Rename,
+    changed: bool,
+}
+
+impl Known {
+    /// Find what each branch knows, before any of them is rewritten.
+    fn branches(&mut self, term: &Term) {
+        match term {
+            Term::Let(var, Exp::Tag(value), _) | Term::Let(var, Exp::ExnId(value), _) => {
+                self.tags.insert(*var, *value);
+            }
+            Term::If(var, a, b) if a != b => {
+                self.fact(*a, *var, Value::Con(1, None));
+                self.fact(*b, *var, Value::Con(0, None));
+            }
+            Term::Switch {
+                scrutinee, cases, ..
+            } => {
+                for (n, cont) in cases {
+                    self.fact(*cont, *scrutinee, Value::Int(*n));
+                    if let Some(value) = self.tags.get(scrutinee).copied() {
+                        self.fact(*cont, value, Value::Tag(*n));
+                    }
+                }
+            }
+            _ => (),
+        }
+        for child in children(term) {
+            self.branches(child);
+        }
+    }
+
+    /// Record what a branch knows, if nothing else jumps to it.
+    fn fact(&mut self, cont: Cont, var: Var, value: Value) {
+        if self.census.cont(cont) == 1 {
+            self.facts.entry(cont).or_default().push((var, value));
+        }
+    }
+
+    fn tag(&self, var: Var) -> Option<i64> {
+        match self.values.get(&var)? {
+            Value::Con(tag, _) | Value::Exn(tag, _) => Some(*tag as i64),
+            Value::Tag(tag) => Some(*tag),
+            _ => None,
+        }
+    }
+
+    /// Simplify an expression, or find the variable it gives.
+    fn exp(&self, exp: &Exp) -> Option<Result<Exp, Var>> {
+        match (
+            exp,
+            exp_uses(exp).first().and_then(|var| self.values.get(var)),
+        ) {
+            (Exp::Select(i, _), Some(Value::Record(fields))) => Some(Err(fields[*i])),
+            (Exp::ConArg(_), Some(Value::Con(_, Some(arg))))
+            | (Exp::ExnArg(_), Some(Value::Exn(_, Some(arg)))) => Some(Err(*arg)),
+            (Exp::Tag(var), _) | (Exp::ExnId(var), _) => {
+                let tag = self.tag(*var)?;
+                Some(Ok(Exp::Const(Const::Int(tag))))
+            }
+            _ => None,
+        }
+    }
+
+    fn value(exp: &Exp) -> Option<Value> {
+        match exp {
+            Exp::Record(fields) => Some(Value::Record(fields.clone())),
+            Exp::Con(tag, arg) => Some(Value::Con(*tag, *arg)),
+            Exp::Exn(id, arg) => Some(Value::Exn(*id, *arg)),
+            Exp::Const(Const::Int(n)) => Some(Value::Int(*n)),
+            Exp::Const(Const::Char(c)) => Some(Value::Int(*c as i64)),
+            _ => None,
+        }
+    }
+
+    /// The continuation a branch on a known value goes to.
+    fn branch(&self, term: &Term) -> Option<Cont> {
+        match term {
+            Term::If(_, a, b) if a == b => Some(*a),
+            Term::If(var, a, b) => match self.values.get(var)? {
+                Value::Con(tag, None) => Some(if *tag != 0 { *a } else { *b }),
+                _ => None,
+            },
+            Term::Switch {
+                scrutinee,
+                cases,
+                default,
+            } => {
+                let targets = cases.iter().map(|(_, cont)| *cont).chain(*default);
+                let first = targets.clone().next()?;
+                if targets.clone().all(|cont| cont == first) {
+                    return Some(first);
+                }
+                let n = match self.values.get(scrutinee)? {
+                    Value::Int(n) => *n,
+                    Value::Con(tag, None) => *tag as i64,
+                    _ => return None,
+                };
+                cases
+                    .iter()
+                    .find(|(case, _)| *case == n)
+                    .map(|(_, cont)| *cont)
+                    .or(*default)
+            }
+            _ => None,
+        }
+    }
+
+    fn term(&mut self, mut term: Term) -> Term {
+        self.rename.uses(&mut term);
+        if let Some(cont) = self.branch(&term) {
+            self.changed = true;
+            return Term::Jump(cont, Vec::new());
+        }
+        match term {
+            Term::Let(var, mut exp, body) => {
+                match self.exp(&exp) {
+                    Some(Ok(simpler)) => {
+                        self.changed = true;
+                        exp = simpler;
+                    }
+                    Some(Err(known)) => {
+                        self.changed = true;
+                        self.rename.vars.insert(var, known);
+                        return self.term(*body);
+                    }
+                    None => (),
+                }
+                if let Some(value) = Known::value(&exp) {
+                    self.values.insert(var, value);
+                }
+                Term::Let(var, exp, Box::new(self.term(*body)))
+            }
+            Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body,
+            } => Term::Checked {
+                var,
+                op,
+                args,
+                handler,
+                body: Box::new(self.term(*body)),
+            },
+            Term::LetCont(mut def, body) => {
+                // Facts only hold in their branch, and don't replace what's
+                // known everywhere.
+                let mut added = Vec::new();
+                for (var, value) in self.facts.remove(&def.cont).unwrap_or_default() {
+                    let var = self.rename.var(var);
+                    if let Entry::Vacant(entry) = self.values.entry(var) {
+                        entry.insert(value);
+                        added.push(var);
+                    }
+                }
+                def.body = self.term(def.body);
+                for var in added {
+                    self.values.remove(&var);
+                }
+                Term::LetCont(def, Box::new(self.term(*body)))
+            }
+            Term::LetFun(funs, body) => {
+                let funs = funs
+                    .into_iter()
+                    .map(|mut fun| {
+                        fun.body = self.term(fun.body);
+                        fun
+                    })
+                    .collect();
+                Term::LetFun(funs, Box::new(self.term(*body)))
+            }
+            Term::LetClosures(closures, body) => {
+                Term::LetClosures(closures, Box::new(self.term(*body)))
+            }
+            term => term,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{after, build, int, lets};
+    use super::*;
+
+    fn cont(cont: Cont, body: Term, term: Term) -> Term {
+        let def = ContDef {
+            cont,
+            params: Vec::new(),
+            body,
+        };
+        Term::LetCont(Box::new(def), Box::new(term))
+    }
+
+    #[test]
+    fn known_values() {
+        let mut program = build(|p, ret, _| {
+            let (a, b, pair, x, y) = (
+                p.var("a"),
+                p.var("b"),
+                p.var("pair"),
+                p.var("x"),
+                p.var("y"),
+            );
+            let (con, tag, arg, sum) = (p.var("con"), p.var("tag"), p.var("arg"), p.var("sum"));
+            lets(
+                vec![
+                    (a, int(1)),
+                    (b, int(2)),
+                    (pair, Exp::Record(vec![a, b])),
+                    (x, Exp::Select(0, pair)),
+                    (y, Exp::Select(1, pair)),
+                    (con, Exp::Con(3, Some(y))),
+                    (tag, Exp::Tag(con)),
+                    (arg, Exp::ConArg(con)),
+                    (sum, Exp::Record(vec![x, tag, arg])),
+                ],
+                Term::Jump(ret, vec![sum]),
+            )
+        });
+        assert_eq!(
+            after(Pass::KnownCase, &mut program),
+            "\
+let a.2 = 1
+let b.3 = 2
+let pair.4 = {a.2, b.3}
+let con.7 = con 3 b.3
+let tag.8 = 3
+let sum.10 = {a.2, tag.8, b.3}
+jump k0 (sum.10)
+"
+        );
+    }
+
+    #[test]
+    fn known_branches() {
+        let mut program = build(|p, ret, _| {
+            let (t, n) = (p.var("t"), p.var("n"));
+            let (k1, k2, k3, k4) = (p.cont(), p.cont(), p.cont(), p.cont());
+            let switch = Term::Switch {
+                scrutinee: n,
+                cases: vec![(0, k3), (2, k4)],
+                default: None,
+            };
+            let switch = cont(k3, Term::Jump(ret, vec![n]), switch);
+            let switch = cont(k4, Term::Jump(ret, vec![t]), switch);
+            let body = cont(k2, lets(vec![(n, int(2))], switch), Term::If(t, k1, k2));
+            let body = cont(k1, Term::Jump(ret, vec![t]), body);
+            lets(vec![(t, Exp::Con(0, None))], body)
+        });
+        assert_eq!(
+            after(Pass::KnownCase, &mut program),
+            "\
+let t.2 = con 0
+cont k2 () {
+  jump k0 (t.2)
+}
+cont k3 () {
+  let n.3 = 2
+  cont k5 () {
+    jump k0 (t.2)
+  }
+  cont k4 () {
+    jump k0 (n.3)
+  }
+  jump k5 ()
+}
+jump k3 ()
+"
+        );
+    }
+
+    #[test]
+    fn nested_matches() {
+        // The inner match on `xs` knows it's a `::`, so it doesn't test its
+        // tag again.
+        let mut program = super::super::super::tests::lower_str(
+            "fun f xs = case xs of [] => 0 | x :: _ => (case xs of x :: _ => x | [] => 1)",
+        );
+        let printed = after(Pass::KnownCase, &mut program);
+        assert_eq!(printed.matches("switch").count(), 1, "{}", printed);
+    }
+}
@@ -0,0 +1,232 @@
+//! Printing the IR, for dumps and tests.
+//!
+//! Variables print as their name and number, e.g. `xs.12`, and continuations
+//! as `k3`. A function prints its continuations after its parameters:
+//!
+//! ```text
+//! fun length.3 (xs.4) -> k2, k3 {
+//!   let tag.5 = tag xs.4
+//!   switch tag.5 { 0 => k4, 1 => k5 }
+//!   ...
+//! }
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use super::*;
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut printer = Printer {
+            program: self,
+            f,
+            indent: 0,
+        };
+        printer.fun("main", &self.main)?;
+        for (id, fun) in self.funs.iter().enumerate() {
+            writeln!(printer.f)?;
+            printer.fun(&format!("#{}", id), fun)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Cont {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "k{}", self.0)
+    }
+}
+
+struct Printer<'p, 'f, 'a> {
+    program: &'p Program,
+    f: &'f mut Formatter<'a>,
+    indent: usize,
+}
+
+impl Printer<'_, '_, '_> {
+    fn var(&self, var: Var) -> String {
+        format!("{}.{}", self.program.name(var), var.0)
+    }
+
+    fn vars(&self, vars: &[Var]) -> String {
+        let vars: Vec<_> = vars.iter().map(|var| self.var(*var)).collect();
+        vars.join(", ")
+    }
+
+    fn line(&mut self, line: impl Display) -> fmt::Result {
+        writeln!(self.f, "{:indent$}{}", "", line, indent = self.indent * 2)
+    }
+
+    fn fun(&mut self, keyword: &str, fun: &Fun) -> fmt::Result {
+        self.line(format!(
+            "{} {} ({}) -> {}, {} {{",
+            keyword,
+            self.var(fun.name),
+            self.vars(&fun.params),
+            fun.ret,
+            fun.handler
+        ))?;
+        self.block(&fun.body)
+    }
+
+    /// A term, then the closing brace of the block it's in.
+    fn block(&mut self, term: &Term) -> fmt::Result {
+        self.indent += 1;
+        self.term(term)?;
+        self.indent -= 1;
+        self.line("}")
+    }
+
+    fn term(&mut self, mut term: &Term) -> fmt::Result {
+        loop {
+            match term {
+                Term::Let(var, exp, body) => {
+                    self.line(format!("let {} = {}", self.var(*var), self.exp(exp)))?;
+                    term = body;
+                }
+                Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body,
+                } => {
+                    let args: Vec<_> = args.iter().map(|arg| self.var(*arg)).collect();
+                    self.line(format!(
+                        "let {} = {} {} else {}",
+                        self.var(*var),
+                        op.name(),
+                        args.join(" "),
+                        handler
+                    ))?;
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    self.line(format!("cont {} ({}) {{", def.cont, self.vars(&def.params)))?;
+                    self.block(&def.body)?;
+                    term = body;
+                }
+                Term::LetFun(funs, body) => {
+                    for fun in funs {
+                        self.fun("fun", fun)?;
+                    }
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    for closure in closures {
+                        self.line(format!(
+                            "closure {} = #{} [{}]",
+                            self.var(closure.var),
+                            closure.fun,
+                            self.vars(&closure.free)
+                        ))?;
+                    }
+                    term = body;
+                }
+                Term::Call {
+                    callee,
+                    args,
+                    ret,
+                    handler,
+                } => {
+                    let callee = match callee {
+                        Callee::Closure(var) => self.var(*var),
+                        Callee::Native(name) => format!("Prim.{}", name),
+                        Callee::Direct(id) => format!("#{}", id),
+                    };
+                    return self.line(format!(
+                        "call {} ({}) -> {}, {}",
+                        callee,
+                        self.vars(args),
+                        ret,
+                        handler
+                    ));
+                }
+                Term::Jump(cont, args) => {
+                    return self.line(format!("jump {} ({})", cont, self.vars(args)))
+                }
+                Term::If(var, a, b) => {
+                    return self.line(format!("if {} then {} else {}", self.var(*var), a, b))
+                }
+                Term::Switch {
+                    scrutinee,
+                    cases,
+                    default,
+                } => {
+                    let mut arms: Vec<_> = cases
+                        .iter()
+                        .map(|(n, cont)| format!("{} => {}", n, cont))
+                        .collect();
+                    if let Some(default) = default {
+                        arms.push(format!("_ => {}", default));
+                    }
+                    return self.line(format!(
+                        "switch {} {{ {} }}",
+                        self.var(*scrutinee),
+                        arms.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+
+    fn exp(&self, exp: &Exp) -> String {
+        match exp {
+            Exp::Const(c) => c.to_string(),
+            Exp::Record(fields) => format!("{{{}}}", self.vars(fields)),
+            Exp::Select(i, var) => format!("select {} {}", i, self.var(*var)),
+            Exp::Con(tag, None) => format!("con {}", tag),
+            Exp::Con(tag, Some(arg)) => format!("con {} {}", tag, self.var(*arg)),
+            Exp::Tag(var) => format!("tag {}", self.var(*var)),
+            Exp::ConArg(var) => format!("con_arg {}", self.var(*var)),
+            Exp::Exn(id, arg) => {
+                let name = self.program.exns.get(id).map_or("?", |name| name);
+                match arg {
+                    Some(arg) => format!("exn {}.{} {}", name, id, self.var(*arg)),
+                    None => format!("exn {}.{}", name, id),
+                }
+            }
+            Exp::ExnId(var) => format!("exn_id {}", self.var(*var)),
+            Exp::ExnArg(var) => format!("exn_arg {}", self.var(*var)),
+            Exp::Prim(op, args) => {
+                let args: Vec<_> = args.iter().map(|arg| self.var(*arg)).collect();
+                format!("{} {}", op.name(), args.join(" "))
+            }
+            Exp::Free(i, var) => format!("free {} {}", i, self.var(*var)),
+            Exp::Global(global) => format!("global {}", global),
+            Exp::SetGlobal(global, var) => format!("set_global {} {}", global, self.var(*var)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::lower_str;
+
+    #[test]
+    fn print() {
+        let program = lower_str("fun f x = if x then 1 else 2 div 0");
+        assert_eq!(
+            program.to_string(),
+            "\
+main main.0 () -> k0, k1 {
+  fun f.1 (x.2) -> k2, k3 {
+    cont k4 () {
+      let c.3 = 1
+      jump k2 (c.3)
+    }
+    cont k5 () {
+      let c.4 = 2
+      let c.5 = 0
+      let n.6 = int_div c.4 c.5 else k3
+      jump k2 (n.6)
+    }
+    if x.2 then k4 else k5
+  }
+  let unit.7 = {}
+  jump k0 (unit.7)
+}
+"
+        );
+    }
+}
@@ -0,0 +1,779 @@
+//! A lexer for Standard ML, following the lexical rules in section 2 of the
+//! Definition.
+//!
+//! `SmlParser` doesn't consume these tokens: it is generated by `derive` from
+//! the character-level rules in `standard_ml.ebnf`, and `parsegen` parsers
+//! run on text. The grammar recognizes the same tokens, and the lexer is what
+//! gives them meaning: lowering takes the values of constants from it, parse
+//! errors name the token the parser stopped at, and the REPL and CM reader
+//! work on tokens directly.
+//!
+//! # Examples
+//!
+//! ```
+//! use parsegen::FileId;
+//! use smol::lexer::{tokenize, TokenKind};
+//!
+//! let toks = tokenize(FileId::ANON, "val x = List.map f [~1]").unwrap();
+//! assert_eq!(toks[3].kind, TokenKind::LongId(vec!["List".into()], "map".into()));
+//! assert_eq!(toks[6].kind, TokenKind::Int(-1));
+//! ```
+
+use std::error;
+use std::fmt::{self, Display};
+
+use parsegen::{FileId, Span};
+
+/// Reserved words and reserved symbols.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Keyword {
+    Abstype,
+    And,
+    Andalso,
+    As,
+    Case,
+    Datatype,
+    Do,
+    Else,
+    End,
+    Eqtype,
+    Exception,
+    Fn,
+    Fun,
+    Functor,
+    Handle,
+    If,
+    In,
+    Include,
+    Infix,
+    Infixr,
+    Let,
+    Local,
+    Nonfix,
+    Of,
+    Op,
+    Open,
+    Orelse,
+    Raise,
+    Rec,
+    Sharing,
+    Sig,
+    Signature,
+    Struct,
+    Structure,
+    Then,
+    Type,
+    Val,
+    Where,
+    While,
+    With,
+    Withtype,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Colon,
+    ColonGt,
+    Semicolon,
+    Ellipsis,
+    Underscore,
+    Bar,
+    Equals,
+    DArrow,
+    Arrow,
+    Hash,
+}
+
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("abstype", Keyword::Abstype),
+    ("and", Keyword::And),
+    ("andalso", Keyword::Andalso),
+    ("as", Keyword::As),
+    ("case", Keyword::Case),
+    ("datatype", Keyword::Datatype),
+    ("do", Keyword::Do),
+    ("else", Keyword::Else),
+    ("end", Keyword::End),
+    ("eqtype", Keyword::Eqtype),
+    ("exception", Keyword::Exception),
+    ("fn", Keyword::Fn),
+    ("fun", Keyword::Fun),
+    ("functor", Keyword::Functor),
+    ("handle", Keyword::Handle),
+    ("if", Keyword::If),
+    ("in", Keyword::In),
+    ("include", Keyword::Include),
+    ("infix", Keyword::Infix),
+    ("infixr", Keyword::Infixr),
+    ("let", Keyword::Let),
+    ("local", Keyword::Local),
+    ("nonfix", Keyword::Nonfix),
+    ("of", Keyword::Of),
+    ("op", Keyword::Op),
+    ("open", Keyword::Open),
+    ("orelse", Keyword::Orelse),
+    ("raise", Keyword::Raise),
+    ("rec", Keyword::Rec),
+    ("sharing", Keyword::Sharing),
+    ("sig", Keyword::Sig),
+    ("signature", Keyword::Signature),
+    ("struct", Keyword::Struct),
+    ("structure", Keyword::Structure),
+    ("then", Keyword::Then),
+    ("type", Keyword::Type),
+    ("val", Keyword::Val),
+    ("where", Keyword::Where),
+    ("while", Keyword::While),
+    ("with", Keyword::With),
+    ("withtype", Keyword::Withtype),
+    ("(", Keyword::LParen),
+    (")", Keyword::RParen),
+    ("[", Keyword::LBracket),
+    ("]", Keyword::RBracket),
+    ("{", Keyword::LBrace),
+    ("}", Keyword::RBrace),
+    (",", Keyword::Comma),
+    (":", Keyword::Colon),
+    (":>", Keyword::ColonGt),
+    (";", Keyword::Semicolon),
+    ("...", Keyword::Ellipsis),
+    ("_", Keyword::Underscore),
+    ("|", Keyword::Bar),
+    ("=", Keyword::Equals),
+    ("=>", Keyword::DArrow),
+    ("->", Keyword::Arrow),
+    ("#", Keyword::Hash),
+];
+
+impl Keyword {
+    /// Look up a reserved word or symbol.
+    pub fn lookup(s: &str) -> Option<Keyword> {
+        KEYWORDS.iter().find(|(k, _)| *k == s).map(|(_, kw)| *kw)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        KEYWORDS.iter().find(|(_, kw)| kw == self).unwrap().0
+    }
+}
+
+impl Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    /// An alphanumeric identifier, e.g. `map`.
+    Id(String),
+    /// A symbolic identifier, e.g. `+` or `::`.
+    SymId(String),
+    /// A qualified identifier, e.g. `List.map` or `Int.+`. Holds the structure
+    /// ids, then the final identifier.
+    LongId(Vec<String>, String),
+    /// A type variable, without the leading quotes. `eq` is set for equality
+    /// type variables (`''a`).
+    TyVar {
+        name: String,
+        eq: bool,
+    },
+    Int(i64),
+    Word(u64),
+    Real(f64),
+    Char(char),
+    String(String),
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Keyword(kw) => write!(f, "{}", kw),
+            TokenKind::Id(s) | TokenKind::SymId(s) => write!(f, "{}", s),
+            TokenKind::LongId(strids, id) => {
+                for strid in strids {
+                    write!(f, "{}.", strid)?;
+                }
+                write!(f, "{}", id)
+            }
+            TokenKind::TyVar { name, eq } => {
+                write!(f, "{}{}", if *eq { "''" } else { "'" }, name)
+            }
+            TokenKind::Int(n) if *n < 0 => write!(f, "~{}", n.unsigned_abs()),
+            TokenKind::Int(n) => write!(f, "{}", n),
+            TokenKind::Word(n) => write!(f, "0w{}", n),
+            TokenKind::Real(n) => write!(f, "{}", n.to_string().replace('-', "~")),
+            TokenKind::Char(c) => write!(f, "#{:?}", c.to_string()),
+            TokenKind::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnexpectedChar(char),
+    UnterminatedComment,
+    UnterminatedString,
+    /// An unknown or malformed escape sequence in a string or char constant.
+    InvalidEscape(String),
+    /// A char constant that doesn't contain exactly one character.
+    InvalidChar,
+    /// An integer or word constant that doesn't fit in 64 bits.
+    ConstantTooLarge,
+    /// A qualified identifier that's missing a part, e.g. `List.`.
+    InvalidLongId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Span,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            ErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            ErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ErrorKind::InvalidEscape(s) => write!(f, "invalid escape sequence \\{}", s),
+            ErrorKind::InvalidChar => {
+                write!(f, "character constant must contain exactly one character")
+            }
+            ErrorKind::ConstantTooLarge => write!(f, "constant is too large"),
+            ErrorKind::InvalidLongId => write!(f, "invalid qualified identifier"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Tokenize a whole file, stopping at the first error.
+pub fn tokenize(file: FileId, src: &str) -> Result<Vec<Token>, Error> {
+    Lexer::new(file, src).collect()
+}
+
+fn is_idchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '\'' || c == '_'
+}
+
+fn is_symchar(c: char) -> bool {
+    "!%&$#+-/:<=>?@\\~`^|*".contains(c)
+}
+
+fn is_formatting(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\x0b' | '\x0c' | '\r')
+}
+
+/// An iterator over the tokens of a file. Whitespace and comments are
+/// skipped.
+pub struct Lexer<'a> {
+    file: FileId,
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: FileId, src: &'a str) -> Self {
+        Lexer { file, src, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Consume characters while `f` holds, returning them.
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.bump();
+        }
+        &self.src[start..self.pos]
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span::new(self.file, start, self.pos)
+    }
+
+    fn error(&self, kind: ErrorKind, start: usize) -> Error {
+        Error {
+            kind,
+            span: self.span(start),
+        }
+    }
+
+    /// Skip whitespace and comments.
+    fn skip_trivia(&mut self) -> Result<(), Error> {
+        loop {
+            self.eat_while(is_formatting);
+            if !self.rest().starts_with("(*") {
+                return Ok(());
+            }
+            let start = self.pos;
+            self.pos += 2;
+            let mut depth = 1;
+            while depth > 0 {
+                if self.rest().starts_with("(*") {
+                    self.pos += 2;
+                    depth += 1;
+                } else if self.rest().starts_with("*)") {
+                    self.pos += 2;
+                    depth -= 1;
+                } else if self.bump().is_none() {
+                    return Err(self.error(ErrorKind::UnterminatedComment, start));
+                }
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<TokenKind, Error> {
+        let start = self.pos;
+        let c = self.peek().unwrap();
+        let kind = match c {
+            '(' | ')' | '[' | ']' | '{' | '}' | ',' | ';' | '_' => {
+                self.bump();
+                TokenKind::Keyword(Keyword::lookup(&c.to_string()).unwrap())
+            }
+            '.' if self.rest().starts_with("...") => {
+                self.pos += 3;
+                TokenKind::Keyword(Keyword::Ellipsis)
+            }
+            '"' => TokenKind::String(self.string()?),
+            '#' if self.peek_nth(1) == Some('"') => {
+                self.bump();
+                let s = self.string()?;
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => TokenKind::Char(c),
+                    _ => return Err(self.error(ErrorKind::InvalidChar, start)),
+                }
+            }
+            '\'' => {
+                let quotes = self.eat_while(|c| c == '\'').len();
+                let name = self.eat_while(is_idchar);
+                TokenKind::TyVar {
+                    name: name.to_owned(),
+                    eq: quotes > 1,
+                }
+            }
+            '~' if self.peek_nth(1).is_some_and(|c| c.is_ascii_digit()) => self.number()?,
+            c if c.is_ascii_digit() => self.number()?,
+            c if c.is_ascii_alphabetic() => self.long_id()?,
+            c if is_symchar(c) => {
+                let sym = self.eat_while(is_symchar);
+                match Keyword::lookup(sym) {
+                    Some(kw) => TokenKind::Keyword(kw),
+                    None => TokenKind::SymId(sym.to_owned()),
+                }
+            }
+            c => {
+                self.bump();
+                return Err(self.error(ErrorKind::UnexpectedChar(c), start));
+            }
+        };
+        Ok(kind)
+    }
+
+    /// An identifier, possibly qualified by structure ids.
+    fn long_id(&mut self) -> Result<TokenKind, Error> {
+        let start = self.pos;
+        let first = self.eat_while(is_idchar);
+        if let Some(kw) = Keyword::lookup(first) {
+            return Ok(TokenKind::Keyword(kw));
+        }
+
+        let mut strids = Vec::new();
+        let mut id = first;
+        while self.peek() == Some('.') && !self.rest().starts_with("...") {
+            self.bump();
+            strids.push(id.to_owned());
+            match self.peek() {
+                Some(c) if c.is_ascii_alphabetic() => id = self.eat_while(is_idchar),
+                Some(c) if is_symchar(c) => {
+                    id = self.eat_while(is_symchar);
+                    break;
+                }
+                _ => return Err(self.error(ErrorKind::InvalidLongId, start)),
+            }
+        }
+
+        if strids.is_empty() {
+            return Ok(TokenKind::Id(id.to_owned()));
+        }
+        // Only `=` may be qualified out of the reserved words and symbols, and
+        // only because it can be rebound as a value.
+        if Keyword::lookup(id).is_some_and(|kw| kw != Keyword::Equals) {
+            return Err(self.error(ErrorKind::InvalidLongId, start));
+        }
+        Ok(TokenKind::LongId(strids, id.to_owned()))
+    }
+
+    /// Integer, word and real constants.
+    fn number(&mut self) -> Result<TokenKind, Error> {
+        let start = self.pos;
+        let negative = self.peek() == Some('~');
+        if negative {
+            self.bump();
+        }
+
+        let too_large = |lexer: &Self| lexer.error(ErrorKind::ConstantTooLarge, start);
+        let hex = |c: char| c.is_ascii_hexdigit();
+        let digit = |c: char| c.is_ascii_digit();
+        let rest = self.rest();
+
+        if !negative && rest.starts_with("0wx") && self.peek_nth(3).is_some_and(hex) {
+            self.pos += 3;
+            let digits = self.eat_while(hex);
+            let n = u64::from_str_radix(digits, 16).map_err(|_| too_large(self))?;
+            return Ok(TokenKind::Word(n));
+        }
+        if !negative && rest.starts_with("0w") && self.peek_nth(2).is_some_and(digit) {
+            self.pos += 2;
+            let n = self.eat_while(digit).parse().map_err(|_| too_large(self))?;
+            return Ok(TokenKind::Word(n));
+        }
+        if rest.starts_with("0x") && self.peek_nth(2).is_some_and(hex) {
+            self.pos += 2;
+            let digits = self.eat_while(hex);
+            let n = i128::from_str_radix(digits, 16).map_err(|_| too_large(self))?;
+            return self.int(if negative { -n } else { n }, start);
+        }
+
+        self.eat_while(digit);
+        let mut is_real = false;
+        if self.peek() == Some('.') && self.peek_nth(1).is_some_and(digit) {
+            self.bump();
+            self.eat_while(digit);
+            is_real = true;
+        }
+        if let Some('e') | Some('E') = self.peek() {
+            let digits_at = if self.peek_nth(1) == Some('~') { 2 } else { 1 };
+            if self.peek_nth(digits_at).is_some_and(digit) {
+                self.pos += digits_at;
+                self.eat_while(digit);
+                is_real = true;
+            }
+        }
+
+        let text = &self.src[start..self.pos];
+        if is_real {
+            // Rust's float syntax only differs in the sign.
+            let n: f64 = text.replace('~', "-").parse().unwrap();
+            return Ok(TokenKind::Real(n));
+        }
+        let n: i128 = text
+            .trim_start_matches('~')
+            .parse()
+            .map_err(|_| too_large(self))?;
+        self.int(if negative { -n } else { n }, start)
+    }
+
+    fn int(&self, n: i128, start: usize) -> Result<TokenKind, Error> {
+        if n < i64::MIN as i128 || n > i64::MAX as i128 {
+            Err(self.error(ErrorKind::ConstantTooLarge, start))
+        } else {
+            Ok(TokenKind::Int(n as i64))
+        }
+    }
+
+    /// The contents of a string constant, with escapes replaced.
+    fn string(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => {
+                    return Err(self.error(ErrorKind::UnterminatedString, start));
+                }
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    if let Some(c) = self.escape()? {
+                        s.push(c);
+                    }
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// An escape sequence after a backslash. Returns `None` for gaps, which
+    /// are ignored.
+    fn escape(&mut self) -> Result<Option<char>, Error> {
+        let start = self.pos - 1;
+        let invalid = |lexer: &Self| {
+            let text = lexer.src[start + 1..lexer.pos].to_owned();
+            lexer.error(ErrorKind::InvalidEscape(text), start)
+        };
+
+        let c = match self.bump() {
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('v') => '\x0b',
+            Some('f') => '\x0c',
+            Some('r') => '\r',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('^') => match self.bump() {
+                Some(c @ '@'..='_') => (c as u8 - 64) as char,
+                _ => return Err(invalid(self)),
+            },
+            Some('u') => {
+                let digits = self.rest().get(..4).unwrap_or("");
+                if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(invalid(self));
+                }
+                self.pos += 4;
+                let n = u32::from_str_radix(digits, 16).unwrap();
+                std::char::from_u32(n).ok_or_else(|| invalid(self))?
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let digits = self.src.get(self.pos - 1..self.pos + 2).unwrap_or("");
+                if digits.len() != 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid(self));
+                }
+                self.pos += 2;
+                let n: u32 = digits.parse().unwrap();
+                if n > 255 {
+                    return Err(invalid(self));
+                }
+                std::char::from_u32(n).unwrap()
+            }
+            Some(c) if is_formatting(c) => {
+                self.eat_while(is_formatting);
+                return match self.bump() {
+                    Some('\\') => Ok(None),
+                    _ => Err(invalid(self)),
+                };
+            }
+            _ => return Err(invalid(self)),
+        };
+        Ok(Some(c))
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.skip_trivia() {
+            // Don't report the same error again.
+            self.pos = self.src.len();
+            return Some(Err(err));
+        }
+        if self.pos == self.src.len() {
+            return None;
+        }
+        let start = self.pos;
+        Some(self.token().map(|kind| Token {
+            kind,
+            span: self.span(start),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(s: &str) -> Vec<TokenKind> {
+        tokenize(FileId::ANON, s)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    fn error(s: &str) -> ErrorKind {
+        tokenize(FileId::ANON, s).unwrap_err().kind
+    }
+
+    fn id(s: &str) -> TokenKind {
+        TokenKind::Id(s.to_owned())
+    }
+
+    fn sym(s: &str) -> TokenKind {
+        TokenKind::SymId(s.to_owned())
+    }
+
+    fn kw(kw: Keyword) -> TokenKind {
+        TokenKind::Keyword(kw)
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            kinds("val value x' snake_case :: := = => ->"),
+            vec![
+                kw(Keyword::Val),
+                id("value"),
+                id("x'"),
+                id("snake_case"),
+                sym("::"),
+                sym(":="),
+                kw(Keyword::Equals),
+                kw(Keyword::DArrow),
+                kw(Keyword::Arrow),
+            ]
+        );
+        assert_eq!(kinds("x+y"), vec![id("x"), sym("+"), id("y")]);
+        assert_eq!(kinds("_x"), vec![kw(Keyword::Underscore), id("x")]);
+    }
+
+    #[test]
+    fn long_identifiers() {
+        assert_eq!(
+            kinds("List.map A.B.c Int.+ General.="),
+            vec![
+                TokenKind::LongId(vec!["List".into()], "map".into()),
+                TokenKind::LongId(vec!["A".into(), "B".into()], "c".into()),
+                TokenKind::LongId(vec!["Int".into()], "+".into()),
+                TokenKind::LongId(vec!["General".into()], "=".into()),
+            ]
+        );
+        assert_eq!(
+            kinds("{x, ...}"),
+            vec![
+                kw(Keyword::LBrace),
+                id("x"),
+                kw(Keyword::Comma),
+                kw(Keyword::Ellipsis),
+                kw(Keyword::RBrace),
+            ]
+        );
+        assert_eq!(error("List."), ErrorKind::InvalidLongId);
+        assert_eq!(error("A.val"), ErrorKind::InvalidLongId);
+    }
+
+    #[test]
+    fn type_variables() {
+        assert_eq!(
+            kinds("'a ''b 'a1"),
+            vec![
+                TokenKind::TyVar {
+                    name: "a".into(),
+                    eq: false
+                },
+                TokenKind::TyVar {
+                    name: "b".into(),
+                    eq: true
+                },
+                TokenKind::TyVar {
+                    name: "a1".into(),
+                    eq: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            kinds("0 ~12 0x1f ~0x10 0w7 0wxFF 1.5 ~1.5e~2 3E2"),
+            vec![
+                TokenKind::Int(0),
+                TokenKind::Int(-12),
+                TokenKind::Int(31),
+                TokenKind::Int(-16),
+                TokenKind::Word(7),
+                TokenKind::Word(255),
+                TokenKind::Real(1.5),
+                TokenKind::Real(-0.015),
+                TokenKind::Real(300.0),
+            ]
+        );
+        // `~` is only part of a constant directly before a digit.
+        assert_eq!(kinds("~ 1"), vec![sym("~"), TokenKind::Int(1)]);
+        assert_eq!(kinds("x-~1"), vec![id("x"), sym("-~"), TokenKind::Int(1)]);
+        // Incomplete suffixes aren't part of the constant.
+        assert_eq!(kinds("1e"), vec![TokenKind::Int(1), id("e")]);
+        assert_eq!(kinds("0wx"), vec![TokenKind::Int(0), id("wx")]);
+        assert_eq!(kinds("#1"), vec![kw(Keyword::Hash), TokenKind::Int(1)]);
+        assert_eq!(error("99999999999999999999"), ErrorKind::ConstantTooLarge);
+        assert_eq!(
+            kinds("~9223372036854775808"),
+            vec![TokenKind::Int(i64::MIN)]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            kinds(r#""" "a\"b\\" #"c" "\t\n\^@\^_\065\u00e9""#),
+            vec![
+                TokenKind::String("".into()),
+                TokenKind::String("a\"b\\".into()),
+                TokenKind::Char('c'),
+                TokenKind::String("\t\n\x00\x1fAé".into()),
+            ]
+        );
+        // Gaps are ignored.
+        assert_eq!(
+            kinds("\"abc\\  \n\t  \\def\""),
+            vec![TokenKind::String("abcdef".into())]
+        );
+        assert_eq!(error(r#""\q""#), ErrorKind::InvalidEscape("q".into()));
+        assert_eq!(error(r#""\256""#), ErrorKind::InvalidEscape("256".into()));
+        assert_eq!(error(r#""\u12""#), ErrorKind::InvalidEscape("u".into()));
+        assert_eq!(error(r#""\^a""#), ErrorKind::InvalidEscape("^a".into()));
+        assert_eq!(error("\"\\ x\\\""), ErrorKind::InvalidEscape(" x".into()));
+        assert_eq!(error("\"abc"), ErrorKind::UnterminatedString);
+        assert_eq!(error("\"a\nb\""), ErrorKind::UnterminatedString);
+        assert_eq!(error("#\"ab\""), ErrorKind::InvalidChar);
+        assert_eq!(error("#\"\""), ErrorKind::InvalidChar);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            kinds("a (* b (* c *) d *) e (***) f"),
+            vec![id("a"), id("e"), id("f")]
+        );
+        assert_eq!(error("a (* (* *)"), ErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn spans() {
+        let src = "val x = (* c *) List.map";
+        let toks = tokenize(FileId::ANON, src).unwrap();
+        let text: Vec<&str> = toks.iter().map(|t| t.span.as_str(src)).collect();
+        assert_eq!(text, vec!["val", "x", "=", "List.map"]);
+
+        let err = tokenize(FileId::ANON, "x \"\\z\"").unwrap_err();
+        assert_eq!((err.span.start, err.span.end), (3, 5));
+        assert_eq!(err.to_string(), "invalid escape sequence \\z");
+    }
+
+    #[test]
+    fn display() {
+        for s in &["List.map", "''a", "~3", "0w7", "#\"c\"", "=>"] {
+            let toks = kinds(s);
+            assert_eq!(toks[0].to_string(), *s);
+        }
+    }
+}
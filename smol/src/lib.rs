@@ -1,3 +1,19 @@
+pub mod ast;
+pub mod basis;
+pub mod codegen;
+pub mod diagnostic;
+pub mod driver;
+pub mod eval;
+pub mod fixity;
+pub mod ir;
+pub mod lexer;
+pub mod lower;
+pub mod matching;
+pub mod mlb;
+pub mod repl;
+pub mod types;
+pub mod vm;
+
 use derive::Parser;
 
 #[derive(Parser)]
@@ -9,14 +25,207 @@ mod tests {
     use super::*;
     use parsegen::Parser;
 
-    // #[test]
-    // fn checking() {
-    //     let s = "val hello 123";
-    //     let toks: Vec<_> = SmlParser::parse(Rule::dec, s)
-    //         .unwrap()
-    //         .into_iter()
-    //         .collect();
+    /// Parse `s` with `rule`, returning the tokens if the whole input was
+    /// consumed.
+    fn parse(rule: Rule, s: &str) -> Option<Vec<parsegen::Token<Rule>>> {
+        let toks: Vec<_> = SmlParser::parse(rule, s).ok()?.into_iter().collect();
+        if toks[0].span.end == s.len() {
+            Some(toks)
+        } else {
+            None
+        }
+    }
+
+    fn assert_parses(rule: Rule, inputs: &[&str]) {
+        for input in inputs {
+            assert!(parse(rule, input).is_some(), "failed to parse {:?}", input);
+        }
+    }
+
+    fn assert_rejects(rule: Rule, inputs: &[&str]) {
+        for input in inputs {
+            assert!(parse(rule, input).is_none(), "parsed {:?}", input);
+        }
+    }
+
+    #[test]
+    fn checking() {
+        let s = "val hello = 123";
+        let toks = parse(Rule::dec, s).unwrap();
+        assert_eq!(toks[0].rule(), Rule::dec);
+        let vars: Vec<&str> = toks
+            .iter()
+            .filter(|t| t.rule() == Rule::pat_var)
+            .map(|t| t.as_str(s))
+            .collect();
+        assert_eq!(vars, vec!["hello"]);
+    }
+
+    #[test]
+    fn constants() {
+        assert_parses(
+            Rule::scon,
+            &[
+                "0",
+                "~12",
+                "0x1f",
+                "0w7",
+                "0wxFF",
+                "1.0",
+                "~1.5e~10",
+                "3E2",
+                "#\"c\"",
+                "\"\"",
+                "\"a\\\"b\"",
+                "\"\\^@\\255\\u00e9\"",
+            ],
+        );
+        assert_rejects(Rule::scon, &["1.", "0wx", "\"unterminated", "\"bad\\q\""]);
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_parses(
+            Rule::vid,
+            &["x", "x'", "camelCase", "snake_case", "::", ":=", "<>", "+"],
+        );
+        assert_parses(Rule::longvid, &["List.map", "A.B.c", "Int.+"]);
+        assert_rejects(Rule::vid, &["val", "andalso", "=", "=>", "|", ":", "_x"]);
+        // Keywords as a prefix are fine.
+        assert_parses(Rule::vid, &["value", "andalsoo", "infix_"]);
+    }
+
+    #[test]
+    fn expressions() {
+        assert_parses(
+            Rule::exp,
+            &[
+                "f x y",
+                "x + y * z",
+                "fn x => x",
+                "fn 0 => true | _ => false",
+                "case x of NONE => 0 | SOME y => y",
+                "if a then b else c",
+                "let val x = 1 in x; x end",
+                "(a; b; c)",
+                "(a, b, c)",
+                "()",
+                "[]",
+                "{a = 1, b = \"two\"}",
+                "#a r",
+                "raise Fail \"x\"",
+                "f x handle Overflow => 0",
+                "while x do y",
+                "x : int",
+                "a andalso b orelse c",
+                "op + (1, 2)",
+                "x = y",
+            ],
+        );
+        assert_rejects(
+            Rule::exp,
+            &["fn => x", "if a then b", "let in end", "(a, b"],
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        assert_parses(
+            Rule::pat,
+            &[
+                "_",
+                "x :: xs",
+                "(x, y)",
+                "SOME (x as (a, b))",
+                "{a, b = (c, _), ...}",
+                "[x, y]",
+                "x : int",
+                "~1",
+                "op ::",
+            ],
+        );
+        assert_rejects(Rule::pat, &["x = y", "fn"]);
+    }
+
+    #[test]
+    fn types() {
+        assert_parses(
+            Rule::ty,
+            &[
+                "int",
+                "'a",
+                "int -> int -> int",
+                "int * string -> unit",
+                "'a list list",
+                "('a, 'b) either",
+                "{x : int, y : real}",
+                "(int -> int) -> int",
+                "Foo.t",
+            ],
+        );
+    }
+
+    #[test]
+    fn declarations() {
+        assert_parses(
+            Rule::dec,
+            &[
+                "val x = 1",
+                "val 'a id = fn (x : 'a) => x",
+                "val rec f = fn x => f x",
+                "fun f 0 = 1 | f n = n * f (n - 1)",
+                "fun x ++ y = x + y",
+                "type t = int",
+                "datatype t = A | B of int",
+                "datatype u = datatype t",
+                "exception E of string",
+                "local val x = 1 in val y = x end",
+                "open A B.C",
+                "infix 4 ++",
+                "infixr ::",
+                "nonfix +",
+            ],
+        );
+        assert_rejects(
+            Rule::dec,
+            &["val = 1", "fun = 1", "datatype t", "infix 10 +"],
+        );
+    }
+
+    #[test]
+    fn modules() {
+        assert_parses(
+            Rule::topdec,
+            &[
+                "structure S = struct val x = 1 end",
+                "structure S : SIG = T and U :> SIG where type t = int = F (T)",
+                "structure S = F (val x = 1 type t = int)",
+                "structure S = let structure T = U in T.V end",
+                "local structure A = B in val x = A.x end",
+                "signature S = sig type t val x : t end",
+                "signature S = sig eqtype 'a t; datatype u = A | B of int end",
+                "signature T = sig include S structure A : S sharing type t = A.t end",
+                "signature U = S where type t = int and type 'a u = 'a list",
+                "functor F (X : S) = struct open X end",
+                "functor F (type t val x : t) :> S = struct type t = t val x = x end",
+            ],
+        );
+        assert_rejects(
+            Rule::topdec,
+            &[
+                "structure = struct end",
+                "signature S = struct end",
+                "functor F = struct end",
+            ],
+        );
+    }
 
-    //     println!("toks: {:?}", toks);
-    // }
+    #[test]
+    fn comments() {
+        assert_parses(
+            Rule::ws,
+            &["", "  \n\t", "(* a *)", "(* (* nested *) *)", "(***)"],
+        );
+        assert_rejects(Rule::ws, &["(* unterminated", "(* (* *)"]);
+    }
 }
@@ -0,0 +1,1441 @@
+//! Lowering from `SmlParser`'s parse tree to the AST.
+//!
+//! The grammar accepts some programs that the Definition doesn't, e.g.
+//! records with duplicate labels. Lowering reports these as diagnostics, and
+//! keeps going so that as many as possible are reported at once.
+
+use std::collections::HashSet;
+
+use parsegen::{FileId, Node, Parser, SourceMap, Span};
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{self, Lexer, TokenKind};
+use crate::{Rule, SmlParser};
+
+type Tree = Node<Rule>;
+
+/// Parse and lower a file from a source map.
+///
+/// # Examples
+///
+/// ```
+/// use parsegen::SourceMap;
+/// use smol::ast::{DecKind, StrDecKind, TopDec};
+///
+/// let mut sources = SourceMap::new();
+/// let file = sources.add("a.sml", "val x = 1; fun f y = y");
+/// let program = smol::lower::parse(&sources, file).unwrap();
+/// assert_eq!(program.items.len(), 2);
+/// match &program.items[1] {
+///     TopDec::Str(dec) => match &dec.kind {
+///         StrDecKind::Dec(dec) => assert!(matches!(dec.kind, DecKind::Fun { .. })),
+///         _ => unreachable!(),
+///     },
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn parse(sources: &SourceMap, file: FileId) -> Result<Program, Vec<Diagnostic>> {
+    let root = parse_tree(sources, file)?;
+    let mut lowerer = Lowerer::new(sources.source(file), file);
+    let program = lowerer.program(&root);
+    if lowerer.diagnostics.is_empty() {
+        Ok(program)
+    } else {
+        Err(lowerer.diagnostics)
+    }
+}
+
+/// Parse a file from a source map, giving the parse tree of the whole file.
+pub fn parse_tree(sources: &SourceMap, file: FileId) -> Result<Node<Rule>, Vec<Diagnostic>> {
+    let src = sources.source(file);
+    let root = SmlParser::parse(Rule::program, src)
+        .ok()
+        .map(|tree| tree.into_tree().swap_remove(0));
+    let end = match root {
+        Some(root) if root.span().end == src.len() => return Ok(root),
+        Some(root) => root.span().end,
+        None => 0,
+    };
+
+    // The parser can only say how far it got. The lexer gives better errors
+    // for malformed tokens, and otherwise names the token it stopped at, so
+    // only a file that doesn't parse is lexed.
+    let toks = lexer::tokenize(file, src)
+        .map_err(|err| vec![Diagnostic::error(err.span, err.to_string())])?;
+    let diag = match toks.iter().find(|tok| tok.span.start >= end) {
+        Some(tok) => Diagnostic::error(tok.span, format!("syntax error near `{}`", tok.kind)),
+        None => Diagnostic::error(Span::new(file, end, src.len()), "syntax error"),
+    };
+    Err(vec![diag])
+}
+
+/// A parse tree as text, a node a line, indented by depth. Whitespace and
+/// the nodes that only end words are left out, and identifiers and constants
+/// are leaves, showing their text. A node whose only child covers the same
+/// text shares its line, as `parent/child`.
+///
+/// # Examples
+///
+/// ```
+/// use parsegen::SourceMap;
+///
+/// let mut sources = SourceMap::new();
+/// let file = sources.add("a.sml", "val x = 1");
+/// let tree = smol::lower::parse_tree(&sources, file).unwrap();
+/// let text = smol::lower::show_tree(&tree, sources.source(file));
+/// assert!(text.starts_with("program/topdec/strdec/dec/val_dec 0..9\n"));
+/// assert!(text.contains("/pat_var/longvid 4..5 \"x\"\n"));
+/// ```
+pub fn show_tree(root: &Node<Rule>, src: &str) -> String {
+    fn is_lexical(node: &Tree) -> bool {
+        use Rule::*;
+        matches!(
+            node.rule(),
+            scon | vid
+                | longvid
+                | tycon
+                | longtycon
+                | strid
+                | longstrid
+                | sigid
+                | funid
+                | tyvar
+                | lab
+                | digit
+        )
+    }
+
+    fn show(mut node: &Tree, src: &str, depth: usize, out: &mut String) {
+        let span = node.span();
+        let mut names = vec![format!("{:?}", node.rule())];
+        loop {
+            let mut kids = children(node);
+            match (kids.next(), kids.next()) {
+                (Some(only), None) if only.span() == span && !is_lexical(node) => {
+                    names.push(format!("{:?}", only.rule()));
+                    node = only;
+                }
+                _ => break,
+            }
+        }
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(&format!("{} {}..{}", names.join("/"), span.start, span.end));
+        if is_lexical(node) || children(node).next().is_none() {
+            out.push_str(&format!(" {:?}\n", node.as_str(src)));
+            return;
+        }
+        out.push('\n');
+        for child in children(node) {
+            show(child, src, depth + 1, out);
+        }
+    }
+
+    let mut out = String::new();
+    show(root, src, 0, &mut out);
+    out
+}
+
+/// Whether a node only exists to separate or delimit tokens.
+fn is_trivia(node: &Tree) -> bool {
+    matches!(node.rule(), Rule::ws | Rule::word_end | Rule::sym_end)
+}
+
+/// The children of a node that carry meaning.
+fn children(node: &Tree) -> impl Iterator<Item = &Tree> {
+    node.children.iter().filter(|n| !is_trivia(n))
+}
+
+fn child(node: &Tree, rule: Rule) -> Option<&Tree> {
+    node.child(rule)
+}
+
+fn first(node: &Tree) -> &Tree {
+    children(node)
+        .next()
+        .unwrap_or_else(|| panic!("{:?} has no children", node.rule()))
+}
+
+struct Lowerer<'a> {
+    src: &'a str,
+    file: FileId,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Lowerer<'a> {
+    /// Create a lowerer for trees parsed from `src`, the contents of `file`.
+    fn new(src: &'a str, file: FileId) -> Self {
+        Lowerer {
+            src,
+            file,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn span(&self, node: &Tree) -> Span {
+        Span {
+            file: self.file,
+            ..node.span()
+        }
+    }
+
+    fn text(&self, node: &Tree) -> &'a str {
+        node.as_str(self.src)
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    /// Whether a node starts with a keyword, e.g. `op` or `rec`. Keywords
+    /// aren't tokens, but are always followed by `word_end`.
+    fn starts_with_keyword(&self, node: &Tree, keyword: &str) -> bool {
+        self.text(node).starts_with(keyword)
+            && node.children.first().is_some_and(|n| {
+                n.rule() == Rule::word_end && n.span().start == node.span().start + keyword.len()
+            })
+    }
+
+    fn program(&mut self, node: &Tree) -> Program {
+        let items = children(node)
+            .map(|n| match n.rule() {
+                Rule::topdec => self.topdec(n),
+                _ => TopDec::Exp(self.exp(n)),
+            })
+            .collect();
+        Program {
+            items,
+            span: self.span(node),
+        }
+    }
+
+    // Modules
+
+    fn topdec(&mut self, node: &Tree) -> TopDec {
+        let node = first(node);
+        match node.rule() {
+            Rule::strdec => TopDec::Str(self.strdec(node)),
+            Rule::sigdec => TopDec::Sig(
+                node.children_of(Rule::sigbind)
+                    .map(|n| SigBind {
+                        id: self.id(child(n, Rule::sigid).unwrap()),
+                        sig: self.sigexp(child(n, Rule::sigexp).unwrap()),
+                        span: self.span(n),
+                    })
+                    .collect(),
+            ),
+            Rule::fundec => TopDec::Functor(
+                node.children_of(Rule::funbind)
+                    .map(|n| self.funbind(n))
+                    .collect(),
+            ),
+            rule => unreachable!("unexpected top level declaration {:?}", rule),
+        }
+    }
+
+    fn strdecs(&mut self, node: &Tree) -> Vec<StrDec> {
+        children(node).map(|n| self.strdec(n)).collect()
+    }
+
+    fn strdec(&mut self, node: &Tree) -> StrDec {
+        let node = first(node);
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::structure_dec => StrDecKind::Structure(
+                node.children_of(Rule::strbind)
+                    .map(|n| StrBind {
+                        id: self.id(child(n, Rule::strid).unwrap()),
+                        sig: child(n, Rule::ascription).map(|a| self.ascription(a)),
+                        str: self.strexp(child(n, Rule::strexp).unwrap()),
+                        span: self.span(n),
+                    })
+                    .collect(),
+            ),
+            Rule::dec => StrDecKind::Dec(self.dec(node)),
+            Rule::local_strdec => {
+                let mut decs = node.children_of(Rule::strdecs);
+                let local = self.strdecs(decs.next().unwrap());
+                let body = self.strdecs(decs.next().unwrap());
+                StrDecKind::Local(local, body)
+            }
+            rule => unreachable!("unexpected structure declaration {:?}", rule),
+        };
+        StrDec { kind, span }
+    }
+
+    fn ascription(&mut self, node: &Tree) -> Ascription {
+        Ascription {
+            sig: self.sigexp(child(node, Rule::sigexp).unwrap()),
+            opaque: self.text(node).starts_with(":>"),
+        }
+    }
+
+    fn strexp(&mut self, node: &Tree) -> StrExp {
+        let mut exp = self.atstrexp(first(node));
+        for asc in node.children_of(Rule::ascription) {
+            let span = exp.span.to(self.span(asc));
+            exp = StrExp {
+                kind: StrExpKind::Ascribe(Box::new(exp), Box::new(self.ascription(asc))),
+                span,
+            };
+        }
+        exp
+    }
+
+    fn atstrexp(&mut self, node: &Tree) -> StrExp {
+        let node = first(node);
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::struct_exp => StrExpKind::Struct(self.strdecs(first(node))),
+            Rule::let_strexp => StrExpKind::Let(
+                self.strdecs(child(node, Rule::strdecs).unwrap()),
+                Box::new(self.strexp(child(node, Rule::strexp).unwrap())),
+            ),
+            Rule::functor_app => {
+                let id = self.id(child(node, Rule::funid).unwrap());
+                let arg = match child(node, Rule::strexp) {
+                    Some(arg) => self.strexp(arg),
+                    None => {
+                        let decs = child(node, Rule::strdecs).unwrap();
+                        StrExp {
+                            kind: StrExpKind::Struct(self.strdecs(decs)),
+                            span: self.span(decs),
+                        }
+                    }
+                };
+                StrExpKind::App(id, Box::new(arg))
+            }
+            Rule::longstrid => StrExpKind::Var(self.long_id(node)),
+            rule => unreachable!("unexpected structure expression {:?}", rule),
+        };
+        StrExp { kind, span }
+    }
+
+    fn sigexp(&mut self, node: &Tree) -> SigExp {
+        let atsig = first(first(node));
+        let mut sig = SigExp {
+            kind: match atsig.rule() {
+                Rule::sig_exp => SigExpKind::Sig(self.specs(first(atsig))),
+                Rule::sigid => SigExpKind::Var(self.id(atsig)),
+                rule => unreachable!("unexpected signature {:?}", rule),
+            },
+            span: self.span(atsig),
+        };
+        // `where type ... and type ...` refines the signature once for each
+        // type.
+        for refin in node
+            .children_of(Rule::where_type)
+            .flat_map(|n| n.children_of(Rule::typrefin))
+        {
+            let span = sig.span.to(self.span(refin));
+            sig = SigExp {
+                kind: SigExpKind::Where {
+                    sig: Box::new(sig),
+                    tyvars: self.tyvarseq(child(refin, Rule::tyvarseq)),
+                    tycon: self.long_id(child(refin, Rule::longtycon).unwrap()),
+                    ty: self.ty(child(refin, Rule::ty).unwrap()),
+                },
+                span,
+            };
+        }
+        sig
+    }
+
+    fn specs(&mut self, node: &Tree) -> Vec<Spec> {
+        children(node).flat_map(|n| self.spec(n)).collect()
+    }
+
+    /// Lower a specification. Type specifications and `include` with several
+    /// signatures are split into one specification each.
+    fn spec(&mut self, node: &Tree) -> Vec<Spec> {
+        let node = first(node);
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::val_spec => SpecKind::Val(
+                node.children_of(Rule::valdesc)
+                    .map(|n| {
+                        let id = self.id(child(n, Rule::vid).unwrap());
+                        (id, self.ty(child(n, Rule::ty).unwrap()))
+                    })
+                    .collect(),
+            ),
+            Rule::type_spec | Rule::eqtype_spec => {
+                let eq = node.rule() == Rule::eqtype_spec;
+                return node
+                    .children_of(Rule::typdesc)
+                    .map(|n| {
+                        let def = child(n, Rule::ty).map(|t| self.ty(t));
+                        if eq && def.is_some() {
+                            self.error(
+                                self.span(n),
+                                "an `eqtype` specification can't have a definition",
+                            );
+                        }
+                        Spec {
+                            kind: SpecKind::Type {
+                                tyvars: self.tyvarseq(child(n, Rule::tyvarseq)),
+                                tycon: self.id(child(n, Rule::tycon).unwrap()),
+                                eq,
+                                def,
+                            },
+                            span: self.span(n),
+                        }
+                    })
+                    .collect();
+            }
+            Rule::datatype_repl => SpecKind::DatatypeRepl(
+                self.id(child(node, Rule::tycon).unwrap()),
+                self.long_id(child(node, Rule::longtycon).unwrap()),
+            ),
+            Rule::datatype_spec => SpecKind::Datatype(self.datbinds(first(node))),
+            Rule::exception_spec => SpecKind::Exception(
+                node.children_of(Rule::exdesc)
+                    .map(|n| {
+                        let id = self.id(child(n, Rule::vid).unwrap());
+                        (id, child(n, Rule::ty).map(|t| self.ty(t)))
+                    })
+                    .collect(),
+            ),
+            Rule::structure_spec => SpecKind::Structure(
+                node.children_of(Rule::strdesc)
+                    .map(|n| {
+                        let id = self.id(child(n, Rule::strid).unwrap());
+                        (id, self.sigexp(child(n, Rule::sigexp).unwrap()))
+                    })
+                    .collect(),
+            ),
+            Rule::include_spec => {
+                let mut specs = vec![Spec {
+                    kind: SpecKind::Include(self.sigexp(child(node, Rule::sigexp).unwrap())),
+                    span,
+                }];
+                for sigid in node.children_of(Rule::sigid) {
+                    let span = self.span(sigid);
+                    specs.push(Spec {
+                        kind: SpecKind::Include(SigExp {
+                            kind: SigExpKind::Var(self.id(sigid)),
+                            span,
+                        }),
+                        span,
+                    });
+                }
+                return specs;
+            }
+            Rule::sharing_spec => {
+                if child(node, Rule::longtycon).is_some() {
+                    SpecKind::SharingType(
+                        node.children_of(Rule::longtycon)
+                            .map(|n| self.long_id(n))
+                            .collect(),
+                    )
+                } else {
+                    SpecKind::Sharing(
+                        node.children_of(Rule::longstrid)
+                            .map(|n| self.long_id(n))
+                            .collect(),
+                    )
+                }
+            }
+            rule => unreachable!("unexpected specification {:?}", rule),
+        };
+        vec![Spec { kind, span }]
+    }
+
+    fn funbind(&mut self, node: &Tree) -> FunctorBind {
+        let (param, param_sig) = match child(node, Rule::strid) {
+            Some(strid) => (
+                Some(self.id(strid)),
+                self.sigexp(child(node, Rule::sigexp).unwrap()),
+            ),
+            None => {
+                let specs = child(node, Rule::specs).unwrap();
+                let sig = SigExp {
+                    kind: SigExpKind::Sig(self.specs(specs)),
+                    span: self.span(specs),
+                };
+                (None, sig)
+            }
+        };
+        FunctorBind {
+            id: self.id(child(node, Rule::funid).unwrap()),
+            param,
+            param_sig,
+            sig: child(node, Rule::ascription).map(|n| self.ascription(n)),
+            body: self.strexp(child(node, Rule::strexp).unwrap()),
+            span: self.span(node),
+        }
+    }
+
+    // Identifiers
+
+    fn id(&self, node: &Tree) -> Id {
+        Id {
+            name: self.text(node).to_owned(),
+            span: self.span(node),
+        }
+    }
+
+    /// Lower `longvid`, `longtycon` and `longstrid`.
+    fn long_id(&self, node: &Tree) -> LongId {
+        let mut parts: Vec<String> = children(node).map(|n| self.text(n).to_owned()).collect();
+        let name = parts.pop().unwrap();
+        LongId {
+            path: parts,
+            name,
+            span: self.span(node),
+        }
+    }
+
+    fn lab(&self, node: &Tree) -> Lab {
+        let text = self.text(node);
+        match text.parse() {
+            Ok(n) => Lab::Num(n),
+            Err(_) => Lab::Id(text.to_owned()),
+        }
+    }
+
+    fn tyvar(&self, node: &Tree) -> TyVar {
+        let text = self.text(node);
+        let name = text.trim_start_matches('\'');
+        TyVar {
+            name: name.to_owned(),
+            eq: text.len() - name.len() > 1,
+            span: self.span(node),
+        }
+    }
+
+    fn tyvarseq(&mut self, node: Option<&Tree>) -> Vec<TyVar> {
+        let tyvars: Vec<_> = match node {
+            Some(node) => children(node).map(|n| self.tyvar(n)).collect(),
+            None => Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        for tyvar in &tyvars {
+            if !seen.insert(&tyvar.name) {
+                self.error(tyvar.span, format!("duplicate type variable {}", tyvar));
+            }
+        }
+        tyvars
+    }
+
+    /// Report labels that appear more than once in a record.
+    fn check_labels<'l>(&mut self, labs: impl Iterator<Item = (&'l Lab, Span)>) {
+        let mut seen = HashSet::new();
+        for (lab, span) in labs {
+            if !seen.insert(lab) {
+                self.error(span, format!("duplicate label {} in record", lab));
+            }
+        }
+    }
+
+    fn scon(&mut self, node: &Tree) -> Const {
+        let span = self.span(node);
+        // Only the constant's own text is lexed, for its value.
+        let tok = match Lexer::new(self.file, self.text(node)).next() {
+            Some(Ok(tok)) => Some(tok.kind),
+            Some(Err(err)) => {
+                self.error(span, err.to_string());
+                return Const::Int(0);
+            }
+            None => None,
+        };
+        match tok {
+            Some(TokenKind::Int(n)) if (MIN_INT..=MAX_INT).contains(&n) => Const::Int(n),
+            Some(TokenKind::Int(_)) => {
+                self.error(span, "int constant doesn't fit in 63 bits");
+                Const::Int(0)
+            }
+            Some(TokenKind::Word(n)) => Const::Word(n),
+            Some(TokenKind::Real(n)) => Const::Real(n),
+            Some(TokenKind::Char(c)) => Const::Char(c),
+            Some(TokenKind::String(s)) => Const::String(s),
+            _ => {
+                self.error(span, "invalid constant");
+                Const::Int(0)
+            }
+        }
+    }
+
+    // Declarations
+
+    fn decs(&mut self, node: &Tree) -> Vec<Dec> {
+        children(node).map(|n| self.dec(n)).collect()
+    }
+
+    fn dec(&mut self, node: &Tree) -> Dec {
+        let node = first(node);
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::val_dec => {
+                let tyvars = self.tyvarseq(child(node, Rule::tyvarseq));
+                let binds = node
+                    .children_of(Rule::valbind)
+                    .map(|n| self.valbind(n))
+                    .collect();
+                DecKind::Val { tyvars, binds }
+            }
+            Rule::fun_dec => {
+                let tyvars = self.tyvarseq(child(node, Rule::tyvarseq));
+                let binds = node
+                    .children_of(Rule::fvalbind)
+                    .map(|n| FunBind {
+                        clauses: children(n).map(|c| self.fclause(c)).collect(),
+                        span: self.span(n),
+                    })
+                    .collect();
+                DecKind::Fun { tyvars, binds }
+            }
+            Rule::type_dec => DecKind::Type(self.typbinds(node)),
+            Rule::datatype_repl => {
+                let tycon = self.id(child(node, Rule::tycon).unwrap());
+                let from = self.long_id(child(node, Rule::longtycon).unwrap());
+                DecKind::DatatypeRepl(tycon, from)
+            }
+            Rule::datatype_dec => DecKind::Datatype {
+                binds: self.datbinds(child(node, Rule::datbinds).unwrap()),
+                withtype: self.withtype(node),
+            },
+            Rule::abstype_dec => DecKind::Abstype {
+                binds: self.datbinds(child(node, Rule::datbinds).unwrap()),
+                withtype: self.withtype(node),
+                body: self.decs(child(node, Rule::decs).unwrap()),
+            },
+            Rule::exception_dec => {
+                DecKind::Exception(children(node).map(|n| self.exbind(n)).collect())
+            }
+            Rule::local_dec => {
+                let mut decs = node.children_of(Rule::decs);
+                let local = self.decs(decs.next().unwrap());
+                let body = self.decs(decs.next().unwrap());
+                DecKind::Local(local, body)
+            }
+            Rule::open_dec => DecKind::Open(children(node).map(|n| self.long_id(n)).collect()),
+            Rule::fixity_dec => {
+                let text = self.text(node);
+                let prec = child(node, Rule::digit)
+                    .map(|n| self.text(n).parse().unwrap())
+                    .unwrap_or(0);
+                let fixity = if text.starts_with("infixr") {
+                    Fixity::Infixr(prec)
+                } else if text.starts_with("infix") {
+                    Fixity::Infix(prec)
+                } else {
+                    Fixity::Nonfix
+                };
+                let ids = node.children_of(Rule::vid).map(|n| self.id(n)).collect();
+                DecKind::Fixity(fixity, ids)
+            }
+            rule => unreachable!("unexpected declaration {:?}", rule),
+        };
+        Dec { kind, span }
+    }
+
+    fn valbind(&mut self, node: &Tree) -> ValBind {
+        let rec = self.starts_with_keyword(node, "rec");
+        let pat = self.pat(child(node, Rule::pat).unwrap());
+        let exp = self.exp(child(node, Rule::exp).unwrap());
+        if rec {
+            let mut inner = &exp;
+            while let ExpKind::Typed(exp, _) = &inner.kind {
+                inner = exp;
+            }
+            if !matches!(inner.kind, ExpKind::Fn(_)) {
+                self.error(exp.span, "`val rec` must bind a `fn` expression");
+            }
+        }
+        ValBind {
+            rec,
+            pat,
+            exp,
+            span: self.span(node),
+        }
+    }
+
+    fn fclause(&mut self, node: &Tree) -> Clause {
+        Clause {
+            name: None,
+            args: node
+                .children_of(Rule::atpat)
+                .map(|n| self.atpat(n))
+                .collect(),
+            ty: child(node, Rule::ty).map(|n| self.ty(n)),
+            body: self.exp(child(node, Rule::exp).unwrap()),
+            span: self.span(node),
+        }
+    }
+
+    /// Lower the `typbind`s of a `type` declaration or `withtype`.
+    fn typbinds(&mut self, node: &Tree) -> Vec<TypBind> {
+        node.children_of(Rule::typbind)
+            .map(|n| TypBind {
+                tyvars: self.tyvarseq(child(n, Rule::tyvarseq)),
+                tycon: self.id(child(n, Rule::tycon).unwrap()),
+                ty: self.ty(child(n, Rule::ty).unwrap()),
+                span: self.span(n),
+            })
+            .collect()
+    }
+
+    fn withtype(&mut self, node: &Tree) -> Vec<TypBind> {
+        match child(node, Rule::withtype) {
+            Some(n) => self.typbinds(n),
+            None => Vec::new(),
+        }
+    }
+
+    fn datbinds(&mut self, node: &Tree) -> Vec<DatBind> {
+        children(node)
+            .map(|n| DatBind {
+                tyvars: self.tyvarseq(child(n, Rule::tyvarseq)),
+                tycon: self.id(child(n, Rule::tycon).unwrap()),
+                cons: n
+                    .children_of(Rule::conbind)
+                    .map(|c| ConBind {
+                        id: self.id(child(c, Rule::vid).unwrap()),
+                        arg: child(c, Rule::ty).map(|t| self.ty(t)),
+                        span: self.span(c),
+                    })
+                    .collect(),
+                span: self.span(n),
+            })
+            .collect()
+    }
+
+    fn exbind(&mut self, node: &Tree) -> ExBind {
+        let id = self.id(child(node, Rule::vid).unwrap());
+        let span = self.span(node);
+        match child(node, Rule::longvid) {
+            Some(from) => ExBind::Copy {
+                id,
+                from: self.long_id(from),
+                span,
+            },
+            None => ExBind::New {
+                id,
+                arg: child(node, Rule::ty).map(|n| self.ty(n)),
+                span,
+            },
+        }
+    }
+
+    // Expressions
+
+    fn exp(&mut self, node: &Tree) -> Exp {
+        let span = self.span(node);
+        let node = match node.rule() {
+            Rule::exp => first(node),
+            _ => node,
+        };
+        let kind = match node.rule() {
+            Rule::fn_exp => ExpKind::Fn(self.mrules(first(node))),
+            Rule::case_exp => ExpKind::Case(
+                Box::new(self.exp(first(node))),
+                self.mrules(child(node, Rule::mrules).unwrap()),
+            ),
+            Rule::if_exp => {
+                let mut exps = node.children_of(Rule::exp).map(|n| Box::new(self.exp(n)));
+                let (c, t, e) = (exps.next(), exps.next(), exps.next());
+                ExpKind::If(c.unwrap(), t.unwrap(), e.unwrap())
+            }
+            Rule::while_exp => {
+                let mut exps = node.children_of(Rule::exp).map(|n| Box::new(self.exp(n)));
+                let (c, body) = (exps.next(), exps.next());
+                ExpKind::While(c.unwrap(), body.unwrap())
+            }
+            Rule::raise_exp => ExpKind::Raise(Box::new(self.exp(first(node)))),
+            Rule::handle_exp => {
+                let exp = self.orelse_exp(first(node));
+                match child(node, Rule::mrules) {
+                    Some(mrules) => ExpKind::Handle(Box::new(exp), self.mrules(mrules)),
+                    None => return exp,
+                }
+            }
+            rule => unreachable!("unexpected expression {:?}", rule),
+        };
+        Exp { kind, span }
+    }
+
+    /// Fold a sequence of operands into left associated binary expressions.
+    fn fold_binary(
+        &mut self,
+        operands: Vec<Exp>,
+        f: impl Fn(Box<Exp>, Box<Exp>) -> ExpKind,
+    ) -> Exp {
+        let mut operands = operands.into_iter();
+        let mut exp = operands.next().unwrap();
+        for rhs in operands {
+            let span = exp.span.to(rhs.span);
+            exp = Exp {
+                kind: f(Box::new(exp), Box::new(rhs)),
+                span,
+            };
+        }
+        exp
+    }
+
+    fn orelse_exp(&mut self, node: &Tree) -> Exp {
+        let operands = children(node).map(|n| self.andalso_exp(n)).collect();
+        self.fold_binary(operands, ExpKind::Orelse)
+    }
+
+    fn andalso_exp(&mut self, node: &Tree) -> Exp {
+        let operands = children(node).map(|n| self.typed_exp(n)).collect();
+        self.fold_binary(operands, ExpKind::Andalso)
+    }
+
+    fn typed_exp(&mut self, node: &Tree) -> Exp {
+        let mut exp = self.app_exp(first(node));
+        for ty in node.children_of(Rule::ty) {
+            let ty = self.ty(ty);
+            exp = Exp {
+                span: exp.span.to(ty.span),
+                kind: ExpKind::Typed(Box::new(exp), ty),
+            };
+        }
+        exp
+    }
+
+    fn app_exp(&mut self, node: &Tree) -> Exp {
+        let mut exps: Vec<_> = children(node).map(|n| self.atexp(n)).collect();
+        if exps.len() == 1 {
+            exps.pop().unwrap()
+        } else {
+            Exp {
+                kind: ExpKind::Flat(exps),
+                span: self.span(node),
+            }
+        }
+    }
+
+    fn atexp(&mut self, node: &Tree) -> Exp {
+        let node = first(node);
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::scon => ExpKind::Const(self.scon(node)),
+            Rule::selector => ExpKind::Selector(self.lab(first(node))),
+            Rule::exp_var => {
+                let op = self.starts_with_keyword(node, "op");
+                let id = match child(node, Rule::longvid) {
+                    Some(n) => self.long_id(n),
+                    None => LongId {
+                        path: Vec::new(),
+                        name: "=".to_owned(),
+                        span: Span::new(self.file, span.end - 1, span.end),
+                    },
+                };
+                ExpKind::Var { op, id }
+            }
+            Rule::record_exp => {
+                let rows: Vec<_> = children(node)
+                    .map(|n| {
+                        let lab = self.lab(first(n));
+                        (lab, self.exp(child(n, Rule::exp).unwrap()))
+                    })
+                    .collect();
+                let spans: Vec<_> = node
+                    .children_of(Rule::exprow)
+                    .map(|n| self.span(n))
+                    .collect();
+                self.check_labels(rows.iter().map(|(lab, _)| lab).zip(spans));
+                ExpKind::Record(rows)
+            }
+            Rule::let_exp => {
+                let decs = self.decs(child(node, Rule::decs).unwrap());
+                let mut body: Vec<_> = node.children_of(Rule::exp).map(|n| self.exp(n)).collect();
+                let body = if body.len() == 1 {
+                    body.pop().unwrap()
+                } else {
+                    Exp {
+                        span: body[0].span.to(body[body.len() - 1].span),
+                        kind: ExpKind::Seq(body),
+                    }
+                };
+                ExpKind::Let(decs, Box::new(body))
+            }
+            Rule::paren_exp => {
+                let mut exps: Vec<_> = children(node).map(|n| self.exp(n)).collect();
+                if exps.len() == 1 {
+                    return exps.pop().unwrap();
+                }
+                let seps = self.separators(node);
+                if seps.contains(&',') && seps.contains(&';') {
+                    self.error(span, "can't mix `,` and `;` in parentheses");
+                }
+                if seps.contains(&';') {
+                    ExpKind::Seq(exps)
+                } else {
+                    ExpKind::Tuple(exps)
+                }
+            }
+            Rule::list_exp => ExpKind::List(children(node).map(|n| self.exp(n)).collect()),
+            rule => unreachable!("unexpected expression {:?}", rule),
+        };
+        Exp { kind, span }
+    }
+
+    /// The separators between the items of a parenthesized sequence. Each
+    /// separator directly follows whitespace.
+    fn separators(&self, node: &Tree) -> Vec<char> {
+        node.children_of(Rule::ws)
+            .filter_map(|ws| self.src[ws.span().end..].chars().next())
+            .filter(|c| *c == ',' || *c == ';')
+            .collect()
+    }
+
+    fn mrules(&mut self, node: &Tree) -> Vec<MRule> {
+        children(node)
+            .map(|n| MRule {
+                pat: self.pat(child(n, Rule::pat).unwrap()),
+                exp: self.exp(child(n, Rule::exp).unwrap()),
+                span: self.span(n),
+            })
+            .collect()
+    }
+
+    // Patterns
+
+    fn pat(&mut self, node: &Tree) -> Pat {
+        let node = first(node);
+        match node.rule() {
+            Rule::as_pat => Pat {
+                kind: PatKind::Layered {
+                    id: self.id(child(node, Rule::vid).unwrap()),
+                    ty: child(node, Rule::ty).map(|n| self.ty(n)),
+                    pat: Box::new(self.pat(child(node, Rule::pat).unwrap())),
+                },
+                span: self.span(node),
+            },
+            Rule::typed_pat => {
+                let mut pat = self.app_pat(first(node));
+                for ty in node.children_of(Rule::ty) {
+                    let ty = self.ty(ty);
+                    pat = Pat {
+                        span: pat.span.to(ty.span),
+                        kind: PatKind::Typed(Box::new(pat), ty),
+                    };
+                }
+                pat
+            }
+            rule => unreachable!("unexpected pattern {:?}", rule),
+        }
+    }
+
+    fn app_pat(&mut self, node: &Tree) -> Pat {
+        let mut pats: Vec<_> = children(node).map(|n| self.atpat(n)).collect();
+        if pats.len() == 1 {
+            pats.pop().unwrap()
+        } else {
+            Pat {
+                kind: PatKind::Flat(pats),
+                span: self.span(node),
+            }
+        }
+    }
+
+    fn atpat(&mut self, node: &Tree) -> Pat {
+        let node = first(node);
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::wildcard => PatKind::Wildcard,
+            Rule::scon => {
+                let c = self.scon(node);
+                if let Const::Real(_) = c {
+                    self.error(span, "real constants can't be used in patterns");
+                }
+                PatKind::Const(c)
+            }
+            Rule::pat_var => PatKind::Var {
+                op: self.starts_with_keyword(node, "op"),
+                id: self.long_id(child(node, Rule::longvid).unwrap()),
+            },
+            Rule::record_pat => {
+                let rows: Vec<&Tree> = children(node).collect();
+                let mut flexible = false;
+                let mut fields = Vec::new();
+                let mut spans = Vec::new();
+                for (i, row) in rows.iter().enumerate() {
+                    let row_span = self.span(row);
+                    if let Some(field) = self.patrow(row) {
+                        fields.push(field);
+                        spans.push(row_span);
+                    } else if i + 1 == rows.len() {
+                        flexible = true;
+                    } else {
+                        self.error(row_span, "`...` must come last in a record pattern");
+                    }
+                }
+                self.check_labels(fields.iter().map(|(lab, _)| lab).zip(spans));
+                PatKind::Record {
+                    rows: fields,
+                    flexible,
+                }
+            }
+            Rule::paren_pat => {
+                let mut pats: Vec<_> = children(node).map(|n| self.pat(n)).collect();
+                if pats.len() == 1 {
+                    return pats.pop().unwrap();
+                }
+                PatKind::Tuple(pats)
+            }
+            Rule::list_pat => PatKind::List(children(node).map(|n| self.pat(n)).collect()),
+            rule => unreachable!("unexpected pattern {:?}", rule),
+        };
+        Pat { kind, span }
+    }
+
+    /// Lower a row of a record pattern, or `None` for `...`.
+    fn patrow(&mut self, node: &Tree) -> Option<(Lab, Pat)> {
+        if let Some(lab) = child(node, Rule::lab) {
+            let lab = self.lab(lab);
+            return Some((lab, self.pat(child(node, Rule::pat).unwrap())));
+        }
+
+        // `vid [: ty] [as pat]` is short for `vid = vid [: ty] [as pat]`.
+        let id = self.id(child(node, Rule::vid)?);
+        let ty = child(node, Rule::ty).map(|n| self.ty(n));
+        let span = self.span(node);
+        let kind = match child(node, Rule::pat) {
+            Some(pat) => PatKind::Layered {
+                id: id.clone(),
+                ty,
+                pat: Box::new(self.pat(pat)),
+            },
+            None => {
+                let var = Pat {
+                    kind: PatKind::Var {
+                        op: false,
+                        id: id.clone().into(),
+                    },
+                    span: id.span,
+                };
+                match ty {
+                    Some(ty) => PatKind::Typed(Box::new(var), ty),
+                    None => return Some((Lab::Id(id.name), var)),
+                }
+            }
+        };
+        Some((Lab::Id(id.name), Pat { kind, span }))
+    }
+
+    // Types
+
+    fn ty(&mut self, node: &Tree) -> Ty {
+        let arg = self.tuple_ty(first(node));
+        match child(node, Rule::ty) {
+            Some(res) => {
+                let res = self.ty(res);
+                Ty {
+                    span: self.span(node),
+                    kind: TyKind::Arrow(Box::new(arg), Box::new(res)),
+                }
+            }
+            None => arg,
+        }
+    }
+
+    fn tuple_ty(&mut self, node: &Tree) -> Ty {
+        let mut tys: Vec<_> = children(node).map(|n| self.app_ty(n)).collect();
+        if tys.len() == 1 {
+            tys.pop().unwrap()
+        } else {
+            Ty {
+                kind: TyKind::Tuple(tys),
+                span: self.span(node),
+            }
+        }
+    }
+
+    fn app_ty(&mut self, node: &Tree) -> Ty {
+        let atty = first(first(node));
+        let start = self.span(atty);
+        // A parenthesized sequence of types is only allowed as the arguments
+        // of a type constructor.
+        let mut args: Vec<Ty> = match atty.rule() {
+            Rule::paren_ty => children(atty).map(|n| self.ty(n)).collect(),
+            _ => vec![self.atty(atty)],
+        };
+        for tycon in node.children_of(Rule::longtycon) {
+            let tycon = self.long_id(tycon);
+            let span = start.to(tycon.span);
+            args = vec![Ty {
+                kind: TyKind::Con(args, tycon),
+                span,
+            }];
+        }
+        if args.len() != 1 {
+            self.error(start, "expected a type constructor after type arguments");
+        }
+        args.swap_remove(0)
+    }
+
+    fn atty(&mut self, node: &Tree) -> Ty {
+        let span = self.span(node);
+        let kind = match node.rule() {
+            Rule::tyvar => TyKind::Var(self.tyvar(node)),
+            Rule::longtycon => TyKind::Con(Vec::new(), self.long_id(node)),
+            Rule::record_ty => {
+                let rows: Vec<_> = children(node)
+                    .map(|n| {
+                        let lab = self.lab(first(n));
+                        (lab, self.ty(child(n, Rule::ty).unwrap()))
+                    })
+                    .collect();
+                let spans: Vec<_> = node
+                    .children_of(Rule::tyrow)
+                    .map(|n| self.span(n))
+                    .collect();
+                self.check_labels(rows.iter().map(|(lab, _)| lab).zip(spans));
+                TyKind::Record(rows)
+            }
+            rule => unreachable!("unexpected type {:?}", rule),
+        };
+        Ty { kind, span }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower(src: &str) -> Result<Program, Vec<Diagnostic>> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        parse(&sources, file)
+    }
+
+    /// Lower a program, returning its only declaration.
+    fn dec(src: &str) -> DecKind {
+        let mut program = lower(src).unwrap();
+        assert_eq!(program.items.len(), 1);
+        match program.items.pop().unwrap() {
+            TopDec::Str(StrDec {
+                kind: StrDecKind::Dec(dec),
+                ..
+            }) => dec.kind,
+            item => panic!("expected a declaration, got {:?}", item),
+        }
+    }
+
+    /// Lower a top level expression.
+    fn exp(src: &str) -> ExpKind {
+        let mut program = lower(&format!("{};", src)).unwrap();
+        match program.items.pop().unwrap() {
+            TopDec::Exp(exp) => exp.kind,
+            item => panic!("expected an expression, got {:?}", item),
+        }
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        lower(src)
+            .unwrap_err()
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+    }
+
+    fn var(exp: &Exp) -> &str {
+        match &exp.kind {
+            ExpKind::Var { id, .. } => &id.name,
+            kind => panic!("expected a variable, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn val_dec() {
+        match dec("val 'a x = y") {
+            DecKind::Val { tyvars, binds } => {
+                assert_eq!(tyvars[0].to_string(), "'a");
+                assert!(!binds[0].rec);
+                assert!(matches!(&binds[0].pat.kind, PatKind::Var { id, .. } if id.name == "x"));
+                assert_eq!(var(&binds[0].exp), "y");
+            }
+            kind => panic!("{:?}", kind),
+        }
+        match dec("val rec f = fn x => x and g = fn y => y") {
+            DecKind::Val { binds, .. } => {
+                assert_eq!(binds.len(), 2);
+                assert!(binds[0].rec);
+            }
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn fun_dec() {
+        match dec("fun f 0 = 1 | f n : int = n * f (n - 1)") {
+            DecKind::Fun { binds, .. } => {
+                let clauses = &binds[0].clauses;
+                assert_eq!(clauses.len(), 2);
+                assert_eq!(clauses[0].name, None);
+                assert_eq!(clauses[0].args.len(), 2);
+                assert!(clauses[1].ty.is_some());
+                assert!(matches!(clauses[1].body.kind, ExpKind::Flat(ref e) if e.len() == 4));
+            }
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn datatypes_and_exceptions() {
+        match dec("datatype 'a t = A | B of 'a * int withtype u = int t") {
+            DecKind::Datatype { binds, withtype } => {
+                assert_eq!(binds[0].tycon.name, "t");
+                assert_eq!(binds[0].cons.len(), 2);
+                assert!(matches!(
+                    binds[0].cons[1].arg.as_ref().unwrap().kind,
+                    TyKind::Tuple(_)
+                ));
+                assert_eq!(withtype[0].tycon.name, "u");
+            }
+            kind => panic!("{:?}", kind),
+        }
+        match dec("exception E of string and F = Foo.E") {
+            DecKind::Exception(binds) => {
+                assert!(matches!(&binds[0], ExBind::New { arg: Some(_), .. }));
+                assert!(
+                    matches!(&binds[1], ExBind::Copy { from, .. } if from.to_string() == "Foo.E")
+                );
+            }
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn fixity() {
+        match dec("infixr 5 ++ @@") {
+            DecKind::Fixity(Fixity::Infixr(5), ids) => assert_eq!(ids.len(), 2),
+            kind => panic!("{:?}", kind),
+        }
+        assert!(matches!(
+            dec("infix +"),
+            DecKind::Fixity(Fixity::Infix(0), _)
+        ));
+        assert!(matches!(
+            dec("nonfix +"),
+            DecKind::Fixity(Fixity::Nonfix, _)
+        ));
+    }
+
+    #[test]
+    fn modules() {
+        let program = lower(
+            "structure S :> SIG where type t = int and type u = bool = F (val x = 1)
+             signature SIG = sig type t eqtype u val x : t include A B sharing type t = A.t end
+             functor F (type t) : sig end = struct end",
+        )
+        .unwrap();
+        match &program.items[0] {
+            TopDec::Str(StrDec {
+                kind: StrDecKind::Structure(binds),
+                ..
+            }) => {
+                let asc = binds[0].sig.as_ref().unwrap();
+                assert!(asc.opaque);
+                match &asc.sig.kind {
+                    SigExpKind::Where { sig, tycon, .. } => {
+                        assert_eq!(tycon.name, "u");
+                        assert!(matches!(sig.kind, SigExpKind::Where { .. }));
+                    }
+                    sig => panic!("expected `where type`, got {:?}", sig),
+                }
+                match &binds[0].str.kind {
+                    StrExpKind::App(id, arg) => {
+                        assert_eq!(id.name, "F");
+                        assert!(
+                            matches!(arg.kind, StrExpKind::Struct(ref decs) if decs.len() == 1)
+                        );
+                    }
+                    str => panic!("expected a functor application, got {:?}", str),
+                }
+            }
+            item => panic!("expected a structure, got {:?}", item),
+        }
+        match &program.items[1] {
+            TopDec::Sig(binds) => match &binds[0].sig.kind {
+                SigExpKind::Sig(specs) => {
+                    let kinds: Vec<_> = specs
+                        .iter()
+                        .map(|spec| match &spec.kind {
+                            SpecKind::Type { eq, .. } => {
+                                if *eq {
+                                    "eqtype"
+                                } else {
+                                    "type"
+                                }
+                            }
+                            SpecKind::Val(_) => "val",
+                            SpecKind::Include(_) => "include",
+                            SpecKind::SharingType(_) => "sharing type",
+                            _ => "other",
+                        })
+                        .collect();
+                    assert_eq!(
+                        kinds,
+                        vec![
+                            "type",
+                            "eqtype",
+                            "val",
+                            "include",
+                            "include",
+                            "sharing type"
+                        ]
+                    );
+                }
+                sig => panic!("expected `sig`, got {:?}", sig),
+            },
+            item => panic!("expected a signature, got {:?}", item),
+        }
+        match &program.items[2] {
+            TopDec::Functor(binds) => {
+                assert!(binds[0].param.is_none());
+                assert!(
+                    matches!(binds[0].param_sig.kind, SigExpKind::Sig(ref specs) if specs.len() == 1)
+                );
+                assert!(!binds[0].sig.as_ref().unwrap().opaque);
+            }
+            item => panic!("expected a functor, got {:?}", item),
+        }
+        assert_eq!(
+            errors("signature S = sig eqtype t = int end"),
+            vec!["an `eqtype` specification can't have a definition"]
+        );
+    }
+
+    #[test]
+    fn expressions() {
+        assert!(matches!(exp("()"), ExpKind::Tuple(ref e) if e.is_empty()));
+        assert!(matches!(exp("(a, b)"), ExpKind::Tuple(ref e) if e.len() == 2));
+        assert!(matches!(exp("(a; b; c)"), ExpKind::Seq(ref e) if e.len() == 3));
+        assert!(matches!(exp("(a)"), ExpKind::Var { .. }));
+        assert!(matches!(exp("op + (1, 2)"), ExpKind::Flat(ref e)
+            if matches!(e[0].kind, ExpKind::Var { op: true, .. })));
+        assert!(
+            matches!(exp("a orelse b andalso c"), ExpKind::Orelse(_, ref r)
+            if matches!(r.kind, ExpKind::Andalso(_, _)))
+        );
+        assert!(matches!(exp("f x handle E => 0"), ExpKind::Handle(_, ref r) if r.len() == 1));
+        assert!(matches!(exp("x = y"), ExpKind::Flat(ref e) if var(&e[1]) == "="));
+        assert!(matches!(exp("#2 p"), ExpKind::Flat(ref e)
+            if e[0].kind == ExpKind::Selector(Lab::Num(2))));
+        assert!(
+            matches!(exp("let val x = 1 in x; x end"), ExpKind::Let(ref d, ref b)
+            if d.len() == 1 && matches!(b.kind, ExpKind::Seq(_)))
+        );
+        assert_eq!(
+            exp("\"a\\tb\""),
+            ExpKind::Const(Const::String("a\tb".into()))
+        );
+        assert_eq!(exp("~0x10"), ExpKind::Const(Const::Int(-16)));
+        match exp("{b = 1, a = List.map}") {
+            ExpKind::Record(rows) => {
+                assert_eq!(rows[0].0, Lab::Id("b".into()));
+                assert_eq!(var(&rows[1].1), "map");
+            }
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let pat = |src: &str| match dec(&format!("val {} = x", src)) {
+            DecKind::Val { mut binds, .. } => binds.pop().unwrap().pat.kind,
+            kind => panic!("{:?}", kind),
+        };
+        assert_eq!(pat("_"), PatKind::Wildcard);
+        assert!(matches!(pat("x :: xs"), PatKind::Flat(ref p) if p.len() == 3));
+        assert!(matches!(pat("x as (a, b)"), PatKind::Layered { .. }));
+        match pat("{a, b = c, d : int, ...}") {
+            PatKind::Record { rows, flexible } => {
+                assert!(flexible);
+                assert_eq!(rows.len(), 3);
+                assert!(matches!(rows[0].1.kind, PatKind::Var { .. }));
+                assert!(matches!(rows[2].1.kind, PatKind::Typed(_, _)));
+            }
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn types() {
+        let ty = |src: &str| match dec(&format!("type t = {}", src)) {
+            DecKind::Type(mut binds) => binds.pop().unwrap().ty.kind,
+            kind => panic!("{:?}", kind),
+        };
+        assert!(matches!(ty("int -> int -> int"), TyKind::Arrow(_, ref r)
+            if matches!(r.kind, TyKind::Arrow(_, _))));
+        match ty("('a, int) either list") {
+            TyKind::Con(args, list) => {
+                assert_eq!(list.name, "list");
+                assert!(matches!(args[0].kind, TyKind::Con(ref a, _) if a.len() == 2));
+            }
+            kind => panic!("{:?}", kind),
+        }
+        assert!(matches!(ty("{x : int}"), TyKind::Record(ref r) if r.len() == 1));
+        assert!(matches!(ty("(int)"), TyKind::Con(ref a, _) if a.is_empty()));
+    }
+
+    #[test]
+    fn spans() {
+        let src = "val x = 1\nval y = f x";
+        let program = lower(src).unwrap();
+        let spans: Vec<&str> = program
+            .items
+            .iter()
+            .map(|item| match item {
+                TopDec::Str(dec) => &src[dec.span.start..dec.span.end],
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(spans, vec!["val x = 1", "val y = f x"]);
+    }
+
+    #[test]
+    fn diagnostics() {
+        assert_eq!(
+            errors("val x = {a = 1, a = 2}"),
+            vec!["duplicate label a in record"]
+        );
+        assert_eq!(
+            errors("val {..., a} = x"),
+            vec!["`...` must come last in a record pattern"]
+        );
+        assert_eq!(
+            errors("val rec f = 1"),
+            vec!["`val rec` must bind a `fn` expression"]
+        );
+        assert_eq!(
+            errors("fun f 1.0 = 1"),
+            vec!["real constants can't be used in patterns"]
+        );
+        assert_eq!(
+            errors("type ('a, 'a) t = int"),
+            vec!["duplicate type variable 'a"]
+        );
+        assert_eq!(
+            errors("type t = (int, int)"),
+            vec!["expected a type constructor after type arguments"]
+        );
+        assert_eq!(
+            errors("val x = (a, b; c)"),
+            vec!["can't mix `,` and `;` in parentheses"]
+        );
+        // Several problems are reported at once.
+        assert_eq!(errors("val x = {a = 1, a = 2}; val rec f = 1").len(), 2);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            errors("val x = 1\nval = 2"),
+            vec!["syntax error near `val`"]
+        );
+        assert_eq!(
+            errors("val x = \"\\q\""),
+            vec!["invalid escape sequence \\q"]
+        );
+        assert_eq!(
+            errors("val x = 99999999999999999999"),
+            vec!["constant is too large"]
+        );
+    }
+}
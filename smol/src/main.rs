@@ -0,0 +1,107 @@
+//! The `smol` top level.
+//!
+//! Usage: `smol [file.sml ...]`. Each file is run before the prompt appears.
+//!
+//! `smol --run file.sml ...` runs the files as a script instead: they're
+//! compiled together to bytecode, which runs on the virtual machine, with no
+//! prompt. The files can include `.mlb` and `.cm` projects. The bytecode is
+//! cached (see `vm::Cache`), so running the same script again starts
+//! straight away. A `.smbc` file, from `smolc --emit-bytecode`, runs as it
+//! is.
+
+use std::io::{self, BufRead, Write};
+use std::thread;
+
+use smol::eval::Interpreter;
+use smol::repl::{self, Response, Session};
+use smol::vm;
+
+/// Non-tail recursion in SML programs uses the Rust stack, so give the
+/// interpreter plenty.
+const STACK_SIZE: usize = 1 << 30;
+
+fn main() {
+    let mut files: Vec<String> = std::env::args().skip(1).collect();
+    if files.first().map(String::as_str) == Some("--run") {
+        files.remove(0);
+        let script = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || script(&files))
+            .expect("failed to start the script");
+        if let Err(err) = script.join().unwrap_or(Err(String::new())) {
+            eprint!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let top = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(&files))
+        .expect("failed to start the top level");
+    match top.join() {
+        // A closed pipe, as from `smol < script.sml | head`, isn't an error.
+        Ok(Err(err)) if err.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("error: can't print: {}", err);
+            std::process::exit(1);
+        }
+        Ok(_) => (),
+        Err(_) => std::process::exit(1),
+    }
+}
+
+/// Run files as a script on the virtual machine, failing with the errors or
+/// the uncaught exception.
+fn script(files: &[String]) -> Result<(), String> {
+    if files.is_empty() {
+        return Err("usage: smol --run file.sml ...\n".to_owned());
+    }
+    let module = vm::load(files, vm::Cache::from_env().as_ref())?;
+    let result = vm::Machine::new(&module).run();
+    result.map_err(|uncaught| format!("{}\n", uncaught))
+}
+
+/// Write to stdout, flushing so prompts appear.
+fn show(text: &str) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    out.write_all(text.as_bytes())?;
+    out.flush()
+}
+
+fn run(files: &[String]) -> io::Result<()> {
+    let mut interp = Interpreter::new();
+    interp.set_stack_size(STACK_SIZE);
+    let mut session = Session::new(interp);
+    for file in files {
+        show(&session.use_file(file))?;
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    loop {
+        show(if input.is_empty() { "- " } else { "= " })?;
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return show("\n"),
+        };
+
+        if input.is_empty() {
+            if let Some(command) = line.trim_start().strip_prefix(':') {
+                match session.command(command) {
+                    Response::Print(out) => show(&out)?,
+                    Response::Quit => return Ok(()),
+                }
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+        }
+        input += &line;
+        input.push('\n');
+        if repl::is_complete(&input) {
+            show(&session.input(&input))?;
+            input.clear();
+        }
+    }
+}
@@ -0,0 +1,926 @@
+//! Pattern match compilation.
+//!
+//! Each `case`, `fn`, `handle`, `fun` and `val` is compiled into a decision
+//! tree, which tests each part of the value at most once on any path. This is
+//! the usual clause matrix algorithm (Maranget, "Compiling Pattern Matching to
+//! Good Decision Trees"): pick a column the first row needs to test, split the
+//! rows on each constructor that appears in it, and recurse.
+//!
+//! The same walk finds matches that aren't exhaustive, along with an example
+//! of a value that isn't matched, and rules that can never match.
+//!
+//! This runs after type checking, which records which identifiers in patterns
+//! are constructors.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+use parsegen::Span;
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+use crate::types::{self, ConInfo, ConKind, Info, TyCon, TyConInfo};
+
+/// A path from the value being matched to one of its parts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Access {
+    /// Which of the values being matched, for `fun` clauses with several
+    /// arguments. Otherwise always 0.
+    pub root: usize,
+    pub path: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// A field of a record or tuple, and its position among the record's
+    /// fields in label order.
+    Field(Lab, usize),
+    /// The argument of a constructor, which must already have been tested.
+    ConArg,
+}
+
+impl Access {
+    pub fn root(root: usize) -> Self {
+        Access {
+            root,
+            path: Vec::new(),
+        }
+    }
+
+    fn then(&self, step: Step) -> Self {
+        let mut access = self.clone();
+        access.path.push(step);
+        access
+    }
+}
+
+/// What a decision tree node tests a value against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    Con(Rc<ConInfo>),
+    Const(Const),
+}
+
+impl Test {
+    fn matches(&self, other: &Test) -> bool {
+        match (self, other) {
+            (Test::Con(a), Test::Con(b)) => a.kind == b.kind,
+            (Test::Const(a), Test::Const(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// No rule matches, so `Match` or `Bind` is raised. For `handle`, the
+    /// exception is raised again.
+    Fail,
+    /// Run the given rule, with its variables bound to parts of the value.
+    Leaf {
+        rule: usize,
+        bindings: Vec<(String, Access)>,
+    },
+    /// Test part of the value. If no case matches, take the default.
+    Switch {
+        access: Access,
+        cases: Vec<(Test, Decision)>,
+        default: Option<Box<Decision>>,
+    },
+}
+
+/// A compiled match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub tree: Decision,
+    /// An example of a value that isn't matched, if there is one.
+    pub missing: Option<Witness>,
+    /// Indices of the rules that can never be reached.
+    pub redundant: Vec<usize>,
+}
+
+/// Compiled matches, keyed by the span of the `case`, `fn` or `handle`
+/// expression, `fun` binding or `val` binding they're from.
+pub type Matches = HashMap<Span, Match>;
+
+/// An example value, for messages about nonexhaustive matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Witness {
+    Any,
+    Con(String, Option<Box<Witness>>),
+    Const(Const),
+    Record(Vec<(Lab, Witness)>),
+    /// The arguments of a `fun` with several parameters.
+    Args(Vec<Witness>),
+}
+
+impl Witness {
+    /// Show the witness, parenthesized unless it's atomic if `atomic` is set.
+    fn show(&self, atomic: bool) -> String {
+        let (s, is_atomic) = match self {
+            Witness::Any => ("_".to_owned(), true),
+            Witness::Con(name, None) => (name.clone(), true),
+            Witness::Con(name, Some(arg)) => match &**arg {
+                Witness::Record(fields) if name == "::" && fields.len() == 2 => (
+                    format!("{} :: {}", fields[0].1.show(true), fields[1].1.show(false)),
+                    false,
+                ),
+                Witness::Any if name == "::" => ("_ :: _".to_owned(), false),
+                arg => (format!("{} {}", name, arg.show(true)), false),
+            },
+            Witness::Const(c) => (c.to_string(), true),
+            Witness::Record(fields) => {
+                let is_tuple = fields.len() != 1
+                    && fields
+                        .iter()
+                        .enumerate()
+                        .all(|(i, (lab, _))| *lab == Lab::Num(i as u32 + 1));
+                let s = if is_tuple {
+                    let items: Vec<_> = fields.iter().map(|(_, w)| w.show(false)).collect();
+                    format!("({})", items.join(", "))
+                } else {
+                    let rows: Vec<_> = fields
+                        .iter()
+                        .map(|(lab, w)| format!("{} = {}", lab, w.show(false)))
+                        .collect();
+                    format!("{{{}}}", rows.join(", "))
+                };
+                (s, true)
+            }
+            Witness::Args(args) => {
+                let args: Vec<_> = args.iter().map(|w| w.show(true)).collect();
+                (args.join(" "), false)
+            }
+        };
+        if atomic && !is_atomic {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+}
+
+impl Display for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.show(false))
+    }
+}
+
+/// Compile a match. Each row has a pattern for each of the values being
+/// matched.
+pub fn compile(rows: &[Vec<&Pat>], info: &Info, tycons: &[TyConInfo]) -> Match {
+    let width = rows.first().map_or(0, |row| row.len());
+    let mut compiler = Compiler {
+        tycons,
+        reached: vec![false; rows.len()],
+        missing: None,
+        known: Vec::new(),
+        width,
+    };
+    let rows = rows
+        .iter()
+        .enumerate()
+        .map(|(rule, pats)| Row {
+            pats: pats.iter().map(|pat| simplify(pat, info)).collect(),
+            bindings: Vec::new(),
+            rule,
+        })
+        .collect();
+    let cols = (0..width).map(Access::root).collect();
+    let tree = compiler.compile(rows, cols);
+    Match {
+        tree,
+        redundant: (0..compiler.reached.len())
+            .filter(|&i| !compiler.reached[i])
+            .collect(),
+        missing: compiler.missing,
+    }
+}
+
+/// Compile every match in a program, and warn about nonexhaustive matches
+/// and redundant rules.
+pub fn compile_program(
+    program: &Program,
+    info: &Info,
+    tycons: &[TyConInfo],
+) -> (Matches, Vec<Diagnostic>) {
+    let mut walker = Walker {
+        info,
+        tycons,
+        matches: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+    for item in &program.items {
+        match item {
+            TopDec::Str(dec) => walker.str_dec(dec),
+            TopDec::Sig(_) => (),
+            TopDec::Functor(binds) => {
+                for bind in binds {
+                    walker.str_exp(&bind.body);
+                }
+            }
+            TopDec::Exp(exp) => walker.exp(exp),
+        }
+    }
+    (walker.matches, walker.diagnostics)
+}
+
+// Patterns
+
+/// A pattern with only the parts that matter for matching.
+#[derive(Debug, Clone)]
+struct SPat {
+    /// Variables bound to the value at this point.
+    binds: Vec<String>,
+    kind: SPatKind,
+}
+
+#[derive(Debug, Clone)]
+enum SPatKind {
+    Any,
+    Test(Test, Option<Box<SPat>>),
+    Record(Vec<(Lab, SPat)>),
+}
+
+impl SPat {
+    fn any() -> Self {
+        SPat {
+            binds: Vec::new(),
+            kind: SPatKind::Any,
+        }
+    }
+}
+
+fn list_con(name: &str, tag: usize) -> Rc<ConInfo> {
+    Rc::new(ConInfo {
+        name: name.to_owned(),
+        kind: ConKind::Datatype {
+            tycon: TyCon {
+                id: types::LIST,
+                name: Rc::from("list"),
+            },
+            tag,
+        },
+        has_arg: tag == 1,
+    })
+}
+
+fn simplify(pat: &Pat, info: &Info) -> SPat {
+    let kind = match &pat.kind {
+        PatKind::Wildcard | PatKind::Flat(_) => SPatKind::Any,
+        PatKind::Const(c) => SPatKind::Test(Test::Const(c.clone()), None),
+        PatKind::Var { id, .. } => match info.cons.get(&id.span) {
+            Some(con) => SPatKind::Test(Test::Con(con.clone()), None),
+            None => {
+                return SPat {
+                    binds: vec![id.name.clone()],
+                    kind: SPatKind::Any,
+                }
+            }
+        },
+        PatKind::Record { rows, flexible } => {
+            let mut fields: Vec<_> = rows
+                .iter()
+                .map(|(lab, pat)| (lab.clone(), simplify(pat, info)))
+                .collect();
+            // The fields `...` stands for match anything, but fields are
+            // numbered by the whole record.
+            if *flexible {
+                if let Some(types::Type::Record(all)) = info.types.get(&pat.span) {
+                    for lab in all.keys() {
+                        if !fields.iter().any(|(l, _)| l == lab) {
+                            fields.push((lab.clone(), SPat::any()));
+                        }
+                    }
+                }
+            }
+            SPatKind::Record(fields)
+        }
+        PatKind::Tuple(pats) => SPatKind::Record(
+            pats.iter()
+                .enumerate()
+                .map(|(i, pat)| (Lab::Num(i as u32 + 1), simplify(pat, info)))
+                .collect(),
+        ),
+        PatKind::List(pats) => {
+            let nil = SPat {
+                binds: Vec::new(),
+                kind: SPatKind::Test(Test::Con(list_con("nil", 0)), None),
+            };
+            return pats.iter().rev().fold(nil, |tail, pat| {
+                let arg = SPat {
+                    binds: Vec::new(),
+                    kind: SPatKind::Record(vec![
+                        (Lab::Num(1), simplify(pat, info)),
+                        (Lab::Num(2), tail),
+                    ]),
+                };
+                SPat {
+                    binds: Vec::new(),
+                    kind: SPatKind::Test(Test::Con(list_con("::", 1)), Some(Box::new(arg))),
+                }
+            });
+        }
+        PatKind::Con(id, arg) => match info.cons.get(&id.span) {
+            Some(con) => {
+                SPatKind::Test(Test::Con(con.clone()), Some(Box::new(simplify(arg, info))))
+            }
+            None => SPatKind::Any,
+        },
+        PatKind::Typed(pat, _) => return simplify(pat, info),
+        PatKind::Layered { id, pat, .. } => {
+            let mut pat = simplify(pat, info);
+            pat.binds.insert(0, id.name.clone());
+            return pat;
+        }
+    };
+    SPat {
+        binds: Vec::new(),
+        kind,
+    }
+}
+
+// Compilation
+
+struct Row {
+    pats: Vec<SPat>,
+    bindings: Vec<(String, Access)>,
+    rule: usize,
+}
+
+impl Row {
+    /// Remove the pattern in column `col`, binding its variables.
+    fn take(&mut self, col: usize, access: &Access) -> SPat {
+        let pat = self.pats.remove(col);
+        for name in &pat.binds {
+            self.bindings.push((name.clone(), access.clone()));
+        }
+        pat
+    }
+}
+
+/// What the path to the current node says about part of the value.
+#[derive(Debug, Clone)]
+enum Known {
+    Test(Test),
+    Record(Vec<Lab>),
+    /// None of these tests matched.
+    Not(Vec<Test>),
+}
+
+struct Compiler<'a> {
+    tycons: &'a [TyConInfo],
+    reached: Vec<bool>,
+    missing: Option<Witness>,
+    known: Vec<(Access, Known)>,
+    width: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn compile(&mut self, mut rows: Vec<Row>, mut cols: Vec<Access>) -> Decision {
+        let first = match rows.first() {
+            Some(row) => row,
+            None => {
+                if self.missing.is_none() {
+                    self.missing = Some(self.witness());
+                }
+                return Decision::Fail;
+            }
+        };
+
+        let col = match first
+            .pats
+            .iter()
+            .position(|pat| !matches!(pat.kind, SPatKind::Any))
+        {
+            Some(col) => col,
+            None => {
+                let mut row = rows.swap_remove(0);
+                for access in &cols {
+                    row.take(0, access);
+                }
+                self.reached[row.rule] = true;
+                return Decision::Leaf {
+                    rule: row.rule,
+                    bindings: row.bindings,
+                };
+            }
+        };
+        let access = cols.remove(col);
+
+        // Records don't need testing, so expand them into their fields.
+        // Rows that leave out a field match anything there.
+        let is_record = rows
+            .iter()
+            .any(|row| matches!(row.pats[col].kind, SPatKind::Record(_)));
+        if is_record {
+            let labels: BTreeSet<Lab> = rows
+                .iter()
+                .flat_map(|row| match &row.pats[col].kind {
+                    SPatKind::Record(fields) => fields.iter().map(|(lab, _)| lab.clone()).collect(),
+                    _ => Vec::new(),
+                })
+                .collect();
+            for row in &mut rows {
+                let mut fields = match row.take(col, &access).kind {
+                    SPatKind::Record(fields) => fields,
+                    _ => Vec::new(),
+                };
+                for (i, lab) in labels.iter().enumerate() {
+                    let pat = match fields.iter().position(|(l, _)| l == lab) {
+                        Some(j) => fields.swap_remove(j).1,
+                        None => SPat::any(),
+                    };
+                    row.pats.insert(col + i, pat);
+                }
+            }
+            for (i, lab) in labels.iter().enumerate() {
+                cols.insert(col + i, access.then(Step::Field(lab.clone(), i)));
+            }
+            self.known
+                .push((access, Known::Record(labels.into_iter().collect())));
+            let tree = self.compile(rows, cols);
+            self.known.pop();
+            return tree;
+        }
+
+        let mut tests: Vec<Test> = Vec::new();
+        for row in &rows {
+            if let SPatKind::Test(test, _) = &row.pats[col].kind {
+                if !tests.iter().any(|t| t.matches(test)) {
+                    tests.push(test.clone());
+                }
+            }
+        }
+
+        let mut cases = Vec::new();
+        for test in &tests {
+            let has_arg = match test {
+                Test::Con(con) => con.has_arg,
+                Test::Const(_) => false,
+            };
+            let mut sub_rows = Vec::new();
+            for row in &rows {
+                let arg = match &row.pats[col].kind {
+                    SPatKind::Test(t, arg) if t.matches(test) => {
+                        arg.as_deref().cloned().unwrap_or_else(SPat::any)
+                    }
+                    SPatKind::Any => SPat::any(),
+                    _ => continue,
+                };
+                let mut row = Row {
+                    pats: row.pats.clone(),
+                    bindings: row.bindings.clone(),
+                    rule: row.rule,
+                };
+                row.take(col, &access);
+                if has_arg {
+                    row.pats.insert(col, arg);
+                }
+                sub_rows.push(row);
+            }
+            let mut sub_cols = cols.clone();
+            if has_arg {
+                sub_cols.insert(col, access.then(Step::ConArg));
+            }
+            self.known.push((access.clone(), Known::Test(test.clone())));
+            cases.push((test.clone(), self.compile(sub_rows, sub_cols)));
+            self.known.pop();
+        }
+
+        let default = if self.is_complete(&tests) {
+            None
+        } else {
+            let mut default_rows = Vec::new();
+            for mut row in rows {
+                if let SPatKind::Any = row.pats[col].kind {
+                    row.take(col, &access);
+                    default_rows.push(row);
+                }
+            }
+            self.known.push((access.clone(), Known::Not(tests)));
+            let tree = self.compile(default_rows, cols);
+            self.known.pop();
+            Some(Box::new(tree))
+        };
+        Decision::Switch {
+            access,
+            cases,
+            default,
+        }
+    }
+
+    /// The constructors of the datatype the tests are on, if they're
+    /// constructor tests.
+    fn datatype(&self, tests: &[Test]) -> Option<&'a TyConInfo> {
+        match tests.first()? {
+            Test::Con(con) => match &con.kind {
+                ConKind::Datatype { tycon, .. } => Some(&self.tycons[tycon.id as usize]),
+                ConKind::Exn(_) => None,
+            },
+            Test::Const(_) => None,
+        }
+    }
+
+    /// Whether the tests cover every possible value.
+    fn is_complete(&self, tests: &[Test]) -> bool {
+        self.datatype(tests)
+            .is_some_and(|info| tests.len() == info.cons.len())
+    }
+
+    /// An example of a value that reaches the current node.
+    fn witness(&self) -> Witness {
+        let args: Vec<_> = (0..self.width)
+            .map(|root| self.witness_at(&Access::root(root)))
+            .collect();
+        match args.len() {
+            1 => args.into_iter().next().unwrap(),
+            _ => Witness::Args(args),
+        }
+    }
+
+    fn witness_at(&self, access: &Access) -> Witness {
+        let known = self
+            .known
+            .iter()
+            .rev()
+            .find(|(a, _)| a == access)
+            .map(|(_, known)| known);
+        let con = |name: &str, has_arg: bool| {
+            let arg = has_arg.then(|| Box::new(self.witness_at(&access.then(Step::ConArg))));
+            Witness::Con(name.to_owned(), arg)
+        };
+        match known {
+            None => Witness::Any,
+            Some(Known::Test(Test::Con(info))) => con(&info.name, info.has_arg),
+            Some(Known::Test(Test::Const(c))) => Witness::Const(c.clone()),
+            Some(Known::Record(labels)) => Witness::Record(
+                labels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, lab)| {
+                        (
+                            lab.clone(),
+                            self.witness_at(&access.then(Step::Field(lab.clone(), i))),
+                        )
+                    })
+                    .collect(),
+            ),
+            Some(Known::Not(tests)) => {
+                let unmatched = self.datatype(tests).and_then(|info| {
+                    info.cons.iter().enumerate().find(|(tag, _)| {
+                        !tests.iter().any(|test| match test {
+                            Test::Con(con) => {
+                                matches!(con.kind, ConKind::Datatype { tag: t, .. } if t == *tag)
+                            }
+                            Test::Const(_) => false,
+                        })
+                    })
+                });
+                match unmatched {
+                    Some((_, (name, has_arg))) => {
+                        Witness::Con(name.clone(), has_arg.then(|| Box::new(Witness::Any)))
+                    }
+                    None => Witness::Any,
+                }
+            }
+        }
+    }
+}
+
+// Walking the program
+
+struct Walker<'a> {
+    info: &'a Info,
+    tycons: &'a [TyConInfo],
+    matches: Matches,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// What a match is part of, which decides the warnings.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Match,
+    Handle,
+    Binding,
+}
+
+impl<'a> Walker<'a> {
+    fn add(&mut self, span: Span, pats: Vec<Vec<&Pat>>, context: Context) {
+        let m = compile(&pats, self.info, self.tycons);
+        for &rule in &m.redundant {
+            let first = pats[rule][0].span;
+            let last = pats[rule][pats[rule].len() - 1].span;
+            let span = Span::new(first.file, first.start, last.end);
+            self.diagnostics
+                .push(Diagnostic::warning(span, "redundant match rule"));
+        }
+        if let Some(missing) = &m.missing {
+            let message = match context {
+                Context::Match => Some("match nonexhaustive"),
+                Context::Binding => Some("binding not exhaustive"),
+                // Unmatched exceptions are raised again.
+                Context::Handle => None,
+            };
+            if let Some(message) = message {
+                self.diagnostics.push(
+                    Diagnostic::warning(span, message)
+                        .with_note(format!("`{}` isn't matched", missing)),
+                );
+            }
+        }
+        self.matches.insert(span, m);
+    }
+
+    fn str_dec(&mut self, dec: &StrDec) {
+        match &dec.kind {
+            StrDecKind::Dec(dec) => self.dec(dec),
+            StrDecKind::Structure(binds) => {
+                for bind in binds {
+                    self.str_exp(&bind.str);
+                }
+            }
+            StrDecKind::Local(local, body) => {
+                for dec in local.iter().chain(body) {
+                    self.str_dec(dec);
+                }
+            }
+        }
+    }
+
+    fn str_exp(&mut self, exp: &StrExp) {
+        match &exp.kind {
+            StrExpKind::Struct(decs) => {
+                for dec in decs {
+                    self.str_dec(dec);
+                }
+            }
+            StrExpKind::Var(_) => (),
+            StrExpKind::Ascribe(exp, _) | StrExpKind::App(_, exp) => self.str_exp(exp),
+            StrExpKind::Let(decs, exp) => {
+                for dec in decs {
+                    self.str_dec(dec);
+                }
+                self.str_exp(exp);
+            }
+        }
+    }
+
+    fn dec(&mut self, dec: &Dec) {
+        match &dec.kind {
+            DecKind::Val { binds, .. } => {
+                for bind in binds {
+                    self.exp(&bind.exp);
+                    self.add(bind.span, vec![vec![&bind.pat]], Context::Binding);
+                }
+            }
+            DecKind::Fun { binds, .. } => {
+                for bind in binds {
+                    let pats = bind
+                        .clauses
+                        .iter()
+                        .map(|clause| clause.args.iter().collect())
+                        .collect();
+                    for clause in &bind.clauses {
+                        self.exp(&clause.body);
+                    }
+                    self.add(bind.span, pats, Context::Match);
+                }
+            }
+            DecKind::Abstype { body, .. } => {
+                for dec in body {
+                    self.dec(dec);
+                }
+            }
+            DecKind::Local(local, body) => {
+                for dec in local.iter().chain(body) {
+                    self.dec(dec);
+                }
+            }
+            DecKind::Type(_)
+            | DecKind::Datatype { .. }
+            | DecKind::DatatypeRepl(_, _)
+            | DecKind::Exception(_)
+            | DecKind::Open(_)
+            | DecKind::Fixity(_, _) => (),
+        }
+    }
+
+    fn rules(&mut self, span: Span, rules: &[MRule], context: Context) {
+        for rule in rules {
+            self.exp(&rule.exp);
+        }
+        let pats = rules.iter().map(|rule| vec![&rule.pat]).collect();
+        self.add(span, pats, context);
+    }
+
+    fn exp(&mut self, exp: &Exp) {
+        match &exp.kind {
+            ExpKind::Const(_) | ExpKind::Var { .. } | ExpKind::Selector(_) => (),
+            ExpKind::Record(rows) => rows.iter().for_each(|(_, e)| self.exp(e)),
+            ExpKind::Tuple(exps)
+            | ExpKind::List(exps)
+            | ExpKind::Seq(exps)
+            | ExpKind::Flat(exps) => exps.iter().for_each(|e| self.exp(e)),
+            ExpKind::Let(decs, body) => {
+                for dec in decs {
+                    self.dec(dec);
+                }
+                self.exp(body);
+            }
+            ExpKind::App(a, b)
+            | ExpKind::Andalso(a, b)
+            | ExpKind::Orelse(a, b)
+            | ExpKind::While(a, b) => {
+                self.exp(a);
+                self.exp(b);
+            }
+            ExpKind::Typed(exp, _) | ExpKind::Raise(exp) => self.exp(exp),
+            ExpKind::Handle(body, rules) => {
+                self.exp(body);
+                self.rules(exp.span, rules, Context::Handle);
+            }
+            ExpKind::If(a, b, c) => {
+                self.exp(a);
+                self.exp(b);
+                self.exp(c);
+            }
+            ExpKind::Case(scrutinee, rules) => {
+                self.exp(scrutinee);
+                self.rules(exp.span, rules, Context::Match);
+            }
+            ExpKind::Fn(rules) => self.rules(exp.span, rules, Context::Match),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Checker;
+    use parsegen::SourceMap;
+
+    fn program(src: &str) -> (Program, Checker) {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        crate::fixity::resolve(&mut program).unwrap();
+        let mut checker = Checker::new();
+        checker.check_program(&program).unwrap();
+        (program, checker)
+    }
+
+    /// The warnings for a program, with their notes.
+    fn warnings(src: &str) -> Vec<String> {
+        let (program, checker) = program(src);
+        let (_, diags) = compile_program(&program, &checker.info, &checker.tycons);
+        diags
+            .into_iter()
+            .map(|d| {
+                let mut s = d.message;
+                for note in d.notes {
+                    s += &format!(": {}", note);
+                }
+                s
+            })
+            .collect()
+    }
+
+    /// The tree of the last `fun` in a program.
+    fn tree(src: &str) -> Decision {
+        let (program, checker) = program(src);
+        let bind = match program.items.last() {
+            Some(TopDec::Str(StrDec {
+                kind: StrDecKind::Dec(dec),
+                ..
+            })) => match &dec.kind {
+                DecKind::Fun { binds, .. } => binds[0].clone(),
+                _ => panic!("not a fun"),
+            },
+            _ => panic!("not a dec"),
+        };
+        let (matches, _) = compile_program(&program, &checker.info, &checker.tycons);
+        matches[&bind.span].tree.clone()
+    }
+
+    #[test]
+    fn exhaustive() {
+        assert_eq!(
+            warnings(
+                "fun len [] = 0 | len (_ :: xs) = 1 + len xs
+                 fun zip (x :: xs, y :: ys) = (x, y) :: zip (xs, ys) | zip _ = []
+                 val (a, b) = (1, 2)
+                 fun f {x = SOME _, ...} = 1 | f {y = true, ...} = 2 | f {x = NONE, y = false} = 3
+                 val g = fn (ref 0) => 0 | ref n => n"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn nonexhaustive() {
+        assert_eq!(
+            warnings(
+                "fun hd (x :: _) = x
+                 fun both (true, false) = 1 | both (false, true) = 2
+                 val f = fn 0 => \"zero\" | 1 => \"one\"
+                 val SOME x = SOME 1
+                 fun g (SOME (x :: _)) [] = x
+                 val h = fn {a = LESS, b} => b"
+            ),
+            vec![
+                "match nonexhaustive: `nil` isn't matched",
+                "match nonexhaustive: `(true, true)` isn't matched",
+                "match nonexhaustive: `_` isn't matched",
+                "binding not exhaustive: `NONE` isn't matched",
+                "match nonexhaustive: `(SOME (_ :: _)) (_ :: _)` isn't matched",
+                "match nonexhaustive: `{a = EQUAL, b = _}` isn't matched",
+            ]
+        );
+    }
+
+    #[test]
+    fn list_witness() {
+        assert_eq!(
+            warnings("fun f [] = 0 | f [x] = 1 | f [x, y, z] = 3"),
+            vec!["match nonexhaustive: `_ :: _ :: _ :: _ :: _` isn't matched"]
+        );
+    }
+
+    #[test]
+    fn redundant() {
+        assert_eq!(
+            warnings(
+                "fun f (SOME x) = x | f NONE = 0 | f (SOME 1) = 1
+                 val g = fn x => x | 0 => 1
+                 exception E
+                 val h = fn E => 0 | Fail _ => 1 | E => 2 | _ => 3
+                 val y = 1 handle Div => 2 | Div => 3"
+            ),
+            vec![
+                "redundant match rule",
+                "redundant match rule",
+                "redundant match rule",
+                "redundant match rule",
+            ]
+        );
+    }
+
+    #[test]
+    fn decision_tree() {
+        let nil = || Test::Con(list_con("nil", 0));
+        let cons = || Test::Con(list_con("::", 1));
+        let arg = |field| Access {
+            root: 0,
+            path: vec![
+                Step::ConArg,
+                Step::Field(Lab::Num(field), field as usize - 1),
+            ],
+        };
+        assert_eq!(
+            tree("fun f [] = 0 | f (x :: xs) = x"),
+            Decision::Switch {
+                access: Access::root(0),
+                cases: vec![
+                    (
+                        nil(),
+                        Decision::Leaf {
+                            rule: 0,
+                            bindings: vec![],
+                        }
+                    ),
+                    (
+                        cons(),
+                        Decision::Leaf {
+                            rule: 1,
+                            bindings: vec![("x".to_owned(), arg(1)), ("xs".to_owned(), arg(2))],
+                        }
+                    ),
+                ],
+                default: None,
+            }
+        );
+        // The second argument is only tested if the first isn't enough.
+        assert_eq!(
+            tree("fun g 0 _ = 0 | g n m = n + m"),
+            Decision::Switch {
+                access: Access::root(0),
+                cases: vec![(
+                    Test::Const(Const::Int(0)),
+                    Decision::Leaf {
+                        rule: 0,
+                        bindings: vec![],
+                    }
+                )],
+                default: Some(Box::new(Decision::Leaf {
+                    rule: 1,
+                    bindings: vec![
+                        ("n".to_owned(), Access::root(0)),
+                        ("m".to_owned(), Access::root(1)),
+                    ],
+                })),
+            }
+        );
+    }
+}
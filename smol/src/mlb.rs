@@ -0,0 +1,502 @@
+//! ML Basis files, which say what files make up a program and how their
+//! top level bindings are scoped.
+//!
+//! An `.mlb` file is a sequence of basis declarations, as MLton and MLKit
+//! read them:
+//!
+//! ```text
+//! $(SML_LIB)/basis/basis.mlb
+//! local
+//!   util.sml
+//! in
+//!   structure Util
+//!   main.sml
+//! end
+//! ```
+//!
+//! Loading one elaborates its declarations to a list of [`Step`]s, which
+//! infix resolution, type checking and lowering each follow in turn: the
+//! files to check, and the bases to check them in. A basis is a scope, like
+//! the ones `local` makes in a program. One is entered, files are checked
+//! in it, and when it's left, what was bound in it is saved in a register,
+//! to be opened later. Basis identifiers are resolved while loading, so the
+//! steps only name registers.
+//!
+//! A file is elaborated the first time it's mentioned, and mentioning it
+//! again opens what it bound then, so the datatypes it declares keep their
+//! identity. Paths are relative to the file they're in, and `$(VAR)` is
+//! replaced with a path variable given to [`load`], or else an environment
+//! variable. The Basis Library is always in scope, so the libraries under
+//! `$(SML_LIB)/basis` bind nothing. Annotations are read, but ignored.
+//!
+//! SML/NJ's `.cm` files can be loaded too; see [`cm`].
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use parsegen::{FileId, SourceMap, Span};
+
+use crate::diagnostic::Diagnostic;
+
+pub mod cm;
+mod parse;
+
+pub use parse::parse;
+
+/// An identifier in a basis declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+}
+
+/// The kinds of module an `.mlb` file can rebind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    Structure,
+    Signature,
+    Functor,
+}
+
+impl Display for ModuleKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleKind::Structure => write!(f, "structure"),
+            ModuleKind::Signature => write!(f, "signature"),
+            ModuleKind::Functor => write!(f, "functor"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BasDec {
+    /// `basis b = basexp and ...`
+    Basis(Vec<(Name, BasExp)>),
+    /// `local basdecs in basdecs end`
+    Local(Vec<BasDec>, Vec<BasDec>),
+    /// `open b ...`
+    Open(Vec<Name>),
+    /// `structure a = b and ...`, and the same for signatures and functors.
+    /// `structure a` is short for `structure a = a`.
+    Alias(ModuleKind, Vec<(Name, Name)>),
+    /// A path, as written.
+    File(String, Span),
+    /// `ann "annotation" ... in basdecs end`
+    Ann(Vec<String>, Vec<BasDec>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BasExp {
+    /// `bas basdecs end`
+    Bas(Vec<BasDec>),
+    Var(Name),
+    /// `let basdecs in basexp end`
+    Let(Vec<BasDec>, Box<BasExp>),
+}
+
+/// One step of elaborating a program's bases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Check the nth file, in the current basis.
+    File(usize),
+    /// Start a basis, in which everything in the current one is visible.
+    Enter,
+    /// Go back to the basis before the last one entered, saving what was
+    /// bound in that one in the nth register. Registers are numbered in the
+    /// order they're saved in.
+    Leave(usize),
+    /// Bind what's saved in the nth register in the current basis.
+    Open(usize),
+    /// Bind modules to other names, all at once.
+    Alias(ModuleKind, Vec<(Name, Name)>),
+}
+
+/// The files of a program, and how to elaborate them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Project {
+    /// The SML files, in the order they're first mentioned.
+    pub files: Vec<FileId>,
+    pub steps: Vec<Step>,
+}
+
+/// The directory that holds the libraries MLton and MLKit ship.
+const SML_LIB: &str = "$(SML_LIB)";
+
+/// Load an `.mlb` or `.cm` file, and the files it mentions, adding them all
+/// to `sources`. The file's name in `sources` is its path.
+pub fn load(
+    sources: &mut SourceMap,
+    root: FileId,
+    vars: &HashMap<String, String>,
+) -> Result<Project, Vec<Diagnostic>> {
+    let path = PathBuf::from(sources.file(root).name());
+    let mut loader = Loader {
+        sources,
+        vars,
+        project: Project::default(),
+        done: HashMap::new(),
+        loading: Vec::new(),
+        scopes: vec![HashMap::new()],
+        registers: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    if let Ok(canonical) = fs::canonicalize(&path) {
+        loader.loading.push(canonical);
+    }
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    match loader.basis_file(root, &path, dir) {
+        Ok(decs) => loader.decs(&decs, dir),
+        Err(diags) => loader.diagnostics.extend(diags),
+    }
+    if loader.diagnostics.is_empty() {
+        Ok(loader.project)
+    } else {
+        Err(loader.diagnostics)
+    }
+}
+
+/// Whether a path names a file `load` reads.
+pub fn is_basis_file(path: &str) -> bool {
+    path.ends_with(".mlb") || path.ends_with(".cm")
+}
+
+/// Replace the `$(VAR)`s in a path, giving back the first variable that
+/// isn't set if there is one.
+fn expand(path: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = path;
+    while let Some(start) = rest.find("$(") {
+        let end = match rest[start..].find(')') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let var = &rest[start + 2..end];
+        let value = match vars.get(var) {
+            Some(value) => value.clone(),
+            None => env::var(var).map_err(|_| var.to_owned())?,
+        };
+        out += &rest[..start];
+        out += &value;
+        rest = &rest[end + 1..];
+    }
+    out += rest;
+    Ok(out)
+}
+
+struct Loader<'a> {
+    sources: &'a mut SourceMap,
+    vars: &'a HashMap<String, String>,
+    project: Project,
+    /// The files elaborated so far, by canonical path, and the registers
+    /// holding what they bound.
+    done: HashMap<PathBuf, usize>,
+    /// The basis files being elaborated, outermost first, to catch cycles.
+    loading: Vec<PathBuf>,
+    /// The basis identifiers in scope, innermost last, and the registers
+    /// they're saved in.
+    scopes: Vec<HashMap<String, usize>>,
+    /// The basis identifiers bound in each register.
+    registers: Vec<HashMap<String, usize>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Loader<'a> {
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    fn enter(&mut self) {
+        self.scopes.push(HashMap::new());
+        self.project.steps.push(Step::Enter);
+    }
+
+    fn leave(&mut self) -> usize {
+        let register = self.registers.len();
+        self.registers
+            .push(self.scopes.pop().expect("a basis was entered"));
+        self.project.steps.push(Step::Leave(register));
+        register
+    }
+
+    fn open(&mut self, register: usize) {
+        let bases = self.registers[register].clone();
+        self.scopes.last_mut().unwrap().extend(bases);
+        self.project.steps.push(Step::Open(register));
+    }
+
+    fn lookup(&mut self, name: &Name) -> usize {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.name).copied());
+        found.unwrap_or_else(|| {
+            self.error(name.span, format!("unbound basis `{}`", name.name));
+            self.enter();
+            self.leave()
+        })
+    }
+
+    fn decs(&mut self, decs: &[BasDec], dir: &Path) {
+        for dec in decs {
+            self.dec(dec, dir);
+        }
+    }
+
+    fn dec(&mut self, dec: &BasDec, dir: &Path) {
+        match dec {
+            BasDec::Basis(binds) => {
+                let bound: Vec<_> = binds
+                    .iter()
+                    .map(|(name, exp)| (name.name.clone(), self.exp(exp, dir)))
+                    .collect();
+                self.scopes.last_mut().unwrap().extend(bound);
+            }
+            BasDec::Local(local, body) => {
+                self.enter();
+                self.decs(local, dir);
+                self.enter();
+                self.decs(body, dir);
+                let body = self.leave();
+                self.leave();
+                self.open(body);
+            }
+            BasDec::Open(names) => {
+                for name in names {
+                    let register = self.lookup(name);
+                    self.open(register);
+                }
+            }
+            BasDec::Alias(kind, binds) => {
+                self.project.steps.push(Step::Alias(*kind, binds.clone()))
+            }
+            BasDec::File(path, span) => self.file(path, *span, dir),
+            BasDec::Ann(_, decs) => self.decs(decs, dir),
+        }
+    }
+
+    /// Elaborate a basis expression, giving the register it's saved in.
+    fn exp(&mut self, exp: &BasExp, dir: &Path) -> usize {
+        match exp {
+            BasExp::Bas(decs) => {
+                self.enter();
+                self.decs(decs, dir);
+                self.leave()
+            }
+            BasExp::Var(name) => self.lookup(name),
+            BasExp::Let(decs, exp) => {
+                self.enter();
+                self.decs(decs, dir);
+                let register = self.exp(exp, dir);
+                self.leave();
+                register
+            }
+        }
+    }
+
+    fn file(&mut self, written: &str, span: Span, dir: &Path) {
+        if let Some(lib) = written.strip_prefix(SML_LIB) {
+            if lib.starts_with("/basis/") {
+                return;
+            }
+        }
+        let path = match expand(written, self.vars) {
+            Ok(path) => dir.join(path),
+            Err(var) => return self.error(span, format!("unknown path variable `{}`", var)),
+        };
+        let canonical = match fs::canonicalize(&path) {
+            Ok(canonical) => canonical,
+            Err(err) => return self.error(span, format!("can't read {}: {}", path.display(), err)),
+        };
+        if let Some(&register) = self.done.get(&canonical) {
+            return self.open(register);
+        }
+        if self.loading.contains(&canonical) {
+            return self.error(span, format!("`{}` includes itself", written));
+        }
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(err) => return self.error(span, format!("can't read {}: {}", path.display(), err)),
+        };
+        let file = self.sources.add(&path.to_string_lossy(), &src);
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        self.enter();
+        match ext {
+            "sml" | "sig" | "fun" | "ML" => {
+                self.project
+                    .steps
+                    .push(Step::File(self.project.files.len()));
+                self.project.files.push(file);
+            }
+            "mlb" | "cm" => {
+                let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                match self.basis_file(file, &path, dir) {
+                    Ok(decs) => {
+                        self.loading.push(canonical.clone());
+                        self.decs(&decs, dir);
+                        self.loading.pop();
+                    }
+                    Err(diags) => self.diagnostics.extend(diags),
+                }
+            }
+            _ => self.error(
+                span,
+                format!("`{}` isn't an SML, `.mlb` or `.cm` file", written),
+            ),
+        }
+        let register = self.leave();
+        self.done.insert(canonical, register);
+        self.open(register);
+    }
+
+    /// Read the declarations of an `.mlb` or `.cm` file.
+    fn basis_file(
+        &mut self,
+        file: FileId,
+        path: &Path,
+        dir: &Path,
+    ) -> Result<Vec<BasDec>, Vec<Diagnostic>> {
+        let src = self.sources.source(file);
+        if path.extension().is_some_and(|ext| ext == "cm") {
+            cm::decs(file, src, dir)
+        } else {
+            parse(file, src).map_err(|diag| vec![diag])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for a test, with the given files.
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir()
+            .join(format!("smol-mlb-{}", std::process::id()))
+            .join(name);
+        for (path, src) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, src).unwrap();
+        }
+        dir
+    }
+
+    fn load_root(dir: &Path, root: &str) -> (SourceMap, Result<Project, Vec<Diagnostic>>) {
+        let path = dir.join(root);
+        let mut sources = SourceMap::new();
+        let file = sources.add(&path.to_string_lossy(), &fs::read_to_string(&path).unwrap());
+        let vars = HashMap::from([("LIB".to_owned(), "lib".to_owned())]);
+        let project = load(&mut sources, file, &vars);
+        (sources, project)
+    }
+
+    fn names(sources: &SourceMap, project: &Project) -> Vec<String> {
+        project
+            .files
+            .iter()
+            .map(|&file| {
+                let name = sources.file(file).name();
+                Path::new(name)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expanding() {
+        let vars = HashMap::from([("A".to_owned(), "x/y".to_owned())]);
+        assert_eq!(expand("$(A)/b.sml", &vars), Ok("x/y/b.sml".to_owned()));
+        assert_eq!(expand("a.sml", &vars), Ok("a.sml".to_owned()));
+        assert_eq!(
+            expand("$(SMOL_UNSET_VARIABLE)/a.sml", &vars),
+            Err("SMOL_UNSET_VARIABLE".to_owned())
+        );
+    }
+
+    #[test]
+    fn steps() {
+        let dir = project(
+            "steps",
+            &[
+                (
+                    "main.mlb",
+                    "$(SML_LIB)/basis/basis.mlb
+                     basis B = bas a.sml end
+                     local open B in structure S = A b.sml end
+                     $(LIB)/c.mlb
+                     a.sml",
+                ),
+                ("a.sml", "structure A = struct end"),
+                ("b.sml", "val b = S"),
+                ("lib/c.mlb", "ann \"warnUnused true\" in ../a.sml c.sml end"),
+                ("lib/c.sml", "val c = ()"),
+            ],
+        );
+        let (sources, project) = load_root(&dir, "main.mlb");
+        let project = project.unwrap();
+        assert_eq!(names(&sources, &project), ["a.sml", "b.sml", "c.sml"]);
+
+        use Step::*;
+        let steps: Vec<_> = project
+            .steps
+            .iter()
+            .map(|step| match step {
+                Alias(..) => "alias".to_owned(),
+                step => format!("{:?}", step),
+            })
+            .collect();
+        let expected = [
+            // basis B = bas a.sml end
+            "Enter", "Enter", "File(0)", "Leave(0)", "Open(0)", "Leave(1)",
+            // local open B in ... end
+            "Enter", "Open(1)", "Enter", "alias", "Enter", "File(1)", "Leave(2)", "Open(2)",
+            "Leave(3)", "Leave(4)", "Open(3)",
+            // $(LIB)/c.mlb, which opens a.sml again
+            "Enter", "Open(0)", "Enter", "File(2)", "Leave(5)", "Open(5)", "Leave(6)", "Open(6)",
+            // a.sml
+            "Open(0)",
+        ];
+        assert_eq!(steps, expected);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = project(
+            "errors",
+            &[
+                (
+                    "main.mlb",
+                    "open Missing\n$(SMOL_UNSET_VARIABLE)/a.sml\nmissing.sml\nself.mlb\nnotes.txt",
+                ),
+                ("self.mlb", "main.mlb"),
+                ("notes.txt", ""),
+            ],
+        );
+        let (sources, project) = load_root(&dir, "main.mlb");
+        let messages: Vec<_> = project
+            .unwrap_err()
+            .iter()
+            .map(|diag| (sources.slice(diag.span).to_owned(), diag.message.clone()))
+            .collect();
+        assert_eq!(messages.len(), 5, "{:?}", messages);
+        assert_eq!(messages[0].1, "unbound basis `Missing`");
+        assert_eq!(messages[1].1, "unknown path variable `SMOL_UNSET_VARIABLE`");
+        assert!(messages[2].1.starts_with("can't read"));
+        assert_eq!(messages[2].0, "missing.sml");
+        assert_eq!(
+            messages[3],
+            (
+                "main.mlb".to_owned(),
+                "`main.mlb` includes itself".to_owned()
+            )
+        );
+        assert_eq!(messages[4].0, "notes.txt");
+    }
+}
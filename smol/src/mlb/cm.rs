@@ -0,0 +1,363 @@
+//! Reading SML/NJ's `.cm` files as basis declarations.
+//!
+//! Only the old, simple form of CM description is read:
+//!
+//! ```text
+//! Library
+//!   structure Queue
+//!   signature QUEUE
+//! is
+//!   $/basis.cm
+//!   queue.sig
+//!   queue.sml
+//! ```
+//!
+//! Unlike an `.mlb` file, a `.cm` file doesn't order its members: CM works
+//! out which depend on which. This does the same, roughly, from the tokens
+//! of each SML member: a file that mentions a module name another file
+//! declares comes after it. Groups, the `.cm` members, come first, since
+//! everything they export is visible to the whole group. With an export
+//! list, only the modules listed are bound; without one, everything is.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use parsegen::{FileId, Span};
+
+use super::{BasDec, ModuleKind, Name};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{self, Keyword, TokenKind};
+
+/// A path a basis declaration names, for the Basis Library CM knows as
+/// `$/basis.cm`.
+const BASIS: &str = "$(SML_LIB)/basis/basis.mlb";
+
+/// Split a file into words and their spans, skipping whitespace and
+/// comments.
+fn words(file: FileId, src: &str) -> Result<Vec<(&str, Span)>, Diagnostic> {
+    let mut words = Vec::new();
+    let mut pos = 0;
+    while pos < src.len() {
+        let rest = &src[pos..];
+        if rest.starts_with("(*") {
+            let mut depth = 0;
+            let mut i = pos;
+            loop {
+                if src[i..].starts_with("(*") {
+                    depth += 1;
+                    i += 2;
+                } else if src[i..].starts_with("*)") {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else if i >= src.len() {
+                    let span = Span::new(file, pos, src.len());
+                    return Err(Diagnostic::error(span, "unterminated comment"));
+                } else {
+                    i += src[i..].chars().next().map_or(1, char::len_utf8);
+                }
+            }
+            pos = i;
+            continue;
+        }
+        let start = pos;
+        let len = rest.find(|c: char| c.is_whitespace()).unwrap_or(rest.len());
+        let len = rest[..len].find("(*").unwrap_or(len);
+        pos += len;
+        if len > 0 {
+            words.push((&src[start..pos], Span::new(file, start, pos)));
+        } else {
+            pos += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    Ok(words)
+}
+
+/// The module names a file declares, and the names it mentions.
+fn names(src: &str) -> (HashSet<String>, HashSet<String>) {
+    let mut declared = HashSet::new();
+    let mut used = HashSet::new();
+    let toks = match lexer::tokenize(FileId::ANON, src) {
+        Ok(toks) => toks,
+        // The file will be reported when it's checked.
+        Err(_) => return (declared, used),
+    };
+    // Whether the last declaration was of a module, so `and` declares one
+    // too, and whether the next identifier is its name.
+    let mut module = false;
+    let mut declaring = false;
+    for tok in toks {
+        match tok.kind {
+            TokenKind::Keyword(Keyword::Structure | Keyword::Signature | Keyword::Functor) => {
+                module = true;
+                declaring = true;
+            }
+            TokenKind::Keyword(Keyword::And) => declaring = module,
+            TokenKind::Keyword(
+                Keyword::Val
+                | Keyword::Fun
+                | Keyword::Type
+                | Keyword::Datatype
+                | Keyword::Exception
+                | Keyword::Eqtype
+                | Keyword::Include
+                | Keyword::Sharing,
+            ) => {
+                module = false;
+                declaring = false;
+            }
+            TokenKind::Id(id) if declaring => {
+                declared.insert(id);
+                declaring = false;
+            }
+            TokenKind::Id(id) => {
+                used.insert(id);
+            }
+            TokenKind::LongId(mut path, _) => {
+                used.insert(path.swap_remove(0));
+            }
+            _ => declaring = false,
+        }
+    }
+    (declared, used)
+}
+
+/// Order files so each comes after the ones it uses, keeping the order
+/// they're listed in otherwise.
+fn order(names: &[(HashSet<String>, HashSet<String>)]) -> Result<Vec<usize>, Vec<usize>> {
+    let mut after: Vec<Vec<usize>> = vec![Vec::new(); names.len()];
+    let mut waiting = vec![0; names.len()];
+    for (i, (declared, used)) in names.iter().enumerate() {
+        for (j, (other, _)) in names.iter().enumerate() {
+            if i != j
+                && used
+                    .iter()
+                    .any(|id| other.contains(id) && !declared.contains(id))
+            {
+                after[j].push(i);
+                waiting[i] += 1;
+            }
+        }
+    }
+    let mut order = Vec::new();
+    let mut done = vec![false; names.len()];
+    while let Some(next) = (0..names.len()).find(|&i| !done[i] && waiting[i] == 0) {
+        done[next] = true;
+        order.push(next);
+        for &i in &after[next] {
+            waiting[i] -= 1;
+        }
+    }
+    if order.len() == names.len() {
+        Ok(order)
+    } else {
+        Err((0..names.len()).filter(|&i| !done[i]).collect())
+    }
+}
+
+/// Read a `.cm` file as basis declarations. Its members are read too, to
+/// order them; paths are relative to `dir`.
+pub fn decs(file: FileId, src: &str, dir: &Path) -> Result<Vec<BasDec>, Vec<Diagnostic>> {
+    let words = words(file, src).map_err(|diag| vec![diag])?;
+    let end = Span::new(file, src.len(), src.len());
+    let mut words = words.into_iter().peekable();
+    match words.next() {
+        Some(("Group" | "Library" | "group" | "library", _)) => (),
+        Some((word, span)) => {
+            let message = format!("expected `Group` or `Library`, found `{}`", word);
+            return Err(vec![Diagnostic::error(span, message)]);
+        }
+        None => {
+            return Err(vec![Diagnostic::error(
+                end,
+                "expected `Group` or `Library`",
+            )])
+        }
+    }
+
+    let mut exports = Vec::new();
+    loop {
+        let kind = match words.next() {
+            Some(("is", _)) => break,
+            Some(("structure", _)) => ModuleKind::Structure,
+            Some(("signature", _)) => ModuleKind::Signature,
+            Some(("functor", _)) => ModuleKind::Functor,
+            Some((word, span)) => {
+                let message = format!("`{}` isn't an export this reader understands", word);
+                return Err(vec![Diagnostic::error(span, message)]);
+            }
+            None => return Err(vec![Diagnostic::error(end, "expected `is`")]),
+        };
+        match words.next() {
+            Some((name, span)) if name != "is" => {
+                let name = Name {
+                    name: name.to_owned(),
+                    span,
+                };
+                exports.push((kind, name));
+            }
+            _ => {
+                let span = words.peek().map_or(end, |(_, span)| *span);
+                return Err(vec![Diagnostic::error(span, "expected a module name")]);
+            }
+        }
+    }
+
+    let mut groups = Vec::new();
+    let mut sources = Vec::new();
+    let mut diags = Vec::new();
+    while let Some((word, span)) = words.next() {
+        // A member's class, like `: sml`, is left for its extension to say.
+        if word == ":" {
+            words.next();
+            continue;
+        }
+        if word.starts_with('#') {
+            diags.push(Diagnostic::error(span, "conditionals aren't supported"));
+            continue;
+        }
+        let path = match word {
+            "$/basis.cm" | "$SMLNJ-BASIS/basis.cm" => BASIS.to_owned(),
+            lib if lib.starts_with('$') => {
+                diags.push(Diagnostic::error(
+                    span,
+                    format!("the library `{}` isn't available", lib),
+                ));
+                continue;
+            }
+            path => path.to_owned(),
+        };
+        if path.ends_with(".cm") || path == BASIS {
+            groups.push(BasDec::File(path, span));
+        } else {
+            sources.push((path, span));
+        }
+    }
+    if !diags.is_empty() {
+        return Err(diags);
+    }
+
+    let names: Vec<_> = sources
+        .iter()
+        .map(|(path, _)| match fs::read_to_string(dir.join(path)) {
+            Ok(src) => names(&src),
+            // The file will be reported when it's loaded.
+            Err(_) => Default::default(),
+        })
+        .collect();
+    let order = order(&names).map_err(|cycle| {
+        let files: Vec<_> = cycle
+            .iter()
+            .map(|&i| format!("`{}`", sources[i].0))
+            .collect();
+        let message = format!("can't order {}, which use each other", files.join(", "));
+        vec![Diagnostic::error(sources[cycle[0]].1, message)]
+    })?;
+    let mut members = groups;
+    members.extend(
+        order
+            .into_iter()
+            .map(|i| BasDec::File(sources[i].0.clone(), sources[i].1)),
+    );
+
+    if exports.is_empty() {
+        return Ok(members);
+    }
+    let aliases = [
+        ModuleKind::Structure,
+        ModuleKind::Signature,
+        ModuleKind::Functor,
+    ]
+    .iter()
+    .filter_map(|&kind| {
+        let binds: Vec<_> = exports
+            .iter()
+            .filter(|(k, _)| *k == kind)
+            .map(|(_, name)| (name.clone(), name.clone()))
+            .collect();
+        (!binds.is_empty()).then_some(BasDec::Alias(kind, binds))
+    })
+    .collect();
+    Ok(vec![BasDec::Local(members, aliases)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(decs: &[BasDec]) -> Vec<String> {
+        decs.iter()
+            .flat_map(|dec| match dec {
+                BasDec::File(path, _) => vec![path.clone()],
+                BasDec::Local(members, _) => files(members),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ordering() {
+        let dir = std::env::temp_dir().join(format!("smol-cm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let members = [
+            ("main.sml", "val _ = Queue.push (Util.id 1)"),
+            (
+                "queue.sml",
+                "structure Queue :> QUEUE = struct fun push x = () end",
+            ),
+            (
+                "queue.sig",
+                "signature QUEUE = sig val push : int -> unit end",
+            ),
+            (
+                "util.sml",
+                "structure Util = struct fun id x = x end and Other = Util",
+            ),
+        ];
+        for (name, src) in members {
+            fs::write(dir.join(name), src).unwrap();
+        }
+
+        let src = "Group is (* the basis *) $/basis.cm main.sml queue.sml queue.sig : sml util.sml";
+        let read = decs(FileId::ANON, src, &dir).unwrap();
+        assert_eq!(
+            files(&read),
+            [BASIS, "queue.sig", "queue.sml", "util.sml", "main.sml"]
+        );
+
+        let src = "Library structure Queue signature QUEUE is queue.sig queue.sml";
+        let read = decs(FileId::ANON, src, &dir).unwrap();
+        match &read[..] {
+            [BasDec::Local(_, aliases)] => assert_eq!(aliases.len(), 2),
+            read => panic!("{:?}", read),
+        }
+
+        fs::write(dir.join("a.sml"), "structure A = B").unwrap();
+        fs::write(dir.join("b.sml"), "structure B = A").unwrap();
+        let message = |src: &str| {
+            decs(FileId::ANON, src, &dir).unwrap_err()[0]
+                .message
+                .clone()
+        };
+        assert_eq!(
+            message("Group is a.sml b.sml"),
+            "can't order `a.sml`, `b.sml`, which use each other"
+        );
+        assert_eq!(
+            message("Group is $/smlnj-lib.cm"),
+            "the library `$/smlnj-lib.cm` isn't available"
+        );
+        assert_eq!(
+            message("Library funsig F is"),
+            "`funsig` isn't an export this reader understands"
+        );
+        assert_eq!(message("Group"), "expected `is`");
+        assert_eq!(
+            message("a.sml"),
+            "expected `Group` or `Library`, found `a.sml`"
+        );
+    }
+}
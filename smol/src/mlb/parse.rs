@@ -0,0 +1,437 @@
+//! Parsing `.mlb` files.
+//!
+//! The grammar is small enough to parse by hand:
+//!
+//! ```text
+//! basdecs ::= (basdec ";"?)*
+//! basdec  ::= "basis" basid "=" basexp ("and" basid "=" basexp)*
+//!           | "local" basdecs "in" basdecs "end"
+//!           | "open" basid+
+//!           | ("structure" | "signature" | "functor") bind ("and" bind)*
+//!           | path | string
+//!           | "ann" string+ "in" basdecs "end"
+//! basexp  ::= "bas" basdecs "end" | basid | "let" basdecs "in" basexp "end"
+//! bind    ::= id ("=" id)?
+//! ```
+//!
+//! A path is a word with a `.` or `/` in it, like `src/main.sml`, and a
+//! string is a quoted path, for paths with spaces in.
+
+use parsegen::{FileId, Span};
+
+use super::{BasDec, BasExp, ModuleKind, Name};
+use crate::diagnostic::Diagnostic;
+
+const KEYWORDS: &[&str] = &[
+    "and",
+    "ann",
+    "bas",
+    "basis",
+    "end",
+    "functor",
+    "in",
+    "let",
+    "local",
+    "open",
+    "signature",
+    "structure",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Keyword(&'static str),
+    Id(String),
+    Path(String),
+    String(String),
+    Equals,
+    Semicolon,
+}
+
+impl Tok {
+    fn describe(&self) -> String {
+        match self {
+            Tok::Keyword(kw) => format!("`{}`", kw),
+            Tok::Id(id) => format!("`{}`", id),
+            Tok::Path(path) => format!("`{}`", path),
+            Tok::String(_) => "a string".to_owned(),
+            Tok::Equals => "`=`".to_owned(),
+            Tok::Semicolon => "`;`".to_owned(),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_'./$()-~+".contains(c)
+}
+
+/// Split a file into tokens, skipping whitespace and (nested) comments.
+fn tokenize(file: FileId, src: &str) -> Result<Vec<(Tok, Span)>, Diagnostic> {
+    let mut toks = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let span = |end| Span::new(file, start, end);
+        let tok = match c {
+            c if c.is_whitespace() => continue,
+            '(' if src[start..].starts_with("(*") => {
+                chars.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some((i, '(')) if src[i..].starts_with("(*") => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        Some((i, '*')) if src[i..].starts_with("*)") => {
+                            chars.next();
+                            depth -= 1;
+                        }
+                        Some(_) => (),
+                        None => {
+                            return Err(Diagnostic::error(span(src.len()), "unterminated comment"))
+                        }
+                    }
+                }
+                continue;
+            }
+            '=' => Tok::Equals,
+            ';' => Tok::Semicolon,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => s.push('\n'),
+                            Some((_, 't')) => s.push('\t'),
+                            Some((_, c)) => s.push(c),
+                            None => (),
+                        },
+                        Some((_, c)) => s.push(c),
+                        None => {
+                            return Err(Diagnostic::error(span(src.len()), "unterminated string"))
+                        }
+                    }
+                }
+                Tok::String(s)
+            }
+            c if is_word_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !is_word_char(c) || src[i..].starts_with("(*") {
+                        break;
+                    }
+                    chars.next();
+                    end = i + c.len_utf8();
+                }
+                let word = &src[start..end];
+                match KEYWORDS.iter().find(|kw| **kw == word) {
+                    Some(kw) => Tok::Keyword(kw),
+                    None if word.contains(['.', '/']) => Tok::Path(word.to_owned()),
+                    None => Tok::Id(word.to_owned()),
+                }
+            }
+            c => {
+                let end = start + c.len_utf8();
+                return Err(Diagnostic::error(
+                    span(end),
+                    format!("unexpected character {:?}", c),
+                ));
+            }
+        };
+        let end = chars.peek().map_or(src.len(), |&(i, _)| i);
+        toks.push((tok, span(end)));
+    }
+    Ok(toks)
+}
+
+/// Parse the basis declarations of an `.mlb` file.
+///
+/// # Examples
+///
+/// ```
+/// use parsegen::FileId;
+/// use smol::mlb::{parse, BasDec};
+///
+/// let decs = parse(FileId::ANON, "local lib.sml in structure Lib end main.sml").unwrap();
+/// assert!(matches!(&decs[0], BasDec::Local(..)));
+/// assert!(matches!(&decs[1], BasDec::File(path, _) if path == "main.sml"));
+/// ```
+pub fn parse(file: FileId, src: &str) -> Result<Vec<BasDec>, Diagnostic> {
+    let mut parser = Parser {
+        toks: tokenize(file, src)?,
+        pos: 0,
+        end: Span::new(file, src.len(), src.len()),
+    };
+    let decs = parser.decs()?;
+    match parser.peek() {
+        None => Ok(decs),
+        Some(_) => Err(parser.unexpected("a basis declaration")),
+    }
+}
+
+struct Parser {
+    toks: Vec<(Tok, Span)>,
+    pos: usize,
+    /// The empty span at the end of the file.
+    end: Span,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos).map(|(tok, _)| tok)
+    }
+
+    fn span(&self) -> Span {
+        self.toks.get(self.pos).map_or(self.end, |(_, span)| *span)
+    }
+
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        let found = self
+            .peek()
+            .map_or_else(|| "the end of the file".to_owned(), Tok::describe);
+        Diagnostic::error(
+            self.span(),
+            format!("expected {}, found {}", expected, found),
+        )
+    }
+
+    fn eat(&mut self, tok: &Tok) -> bool {
+        if self.peek() == Some(tok) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: Tok) -> Result<(), Diagnostic> {
+        if self.eat(&tok) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&tok.describe()))
+        }
+    }
+
+    fn name(&mut self) -> Result<Name, Diagnostic> {
+        match self.peek() {
+            Some(Tok::Id(name)) => {
+                let name = Name {
+                    name: name.clone(),
+                    span: self.span(),
+                };
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected("an identifier")),
+        }
+    }
+
+    fn decs(&mut self) -> Result<Vec<BasDec>, Diagnostic> {
+        let mut decs = Vec::new();
+        loop {
+            let dec = match self.peek() {
+                Some(Tok::Semicolon) => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(Tok::Keyword("basis")) => {
+                    self.pos += 1;
+                    let mut binds = Vec::new();
+                    loop {
+                        let name = self.name()?;
+                        self.expect(Tok::Equals)?;
+                        binds.push((name, self.exp()?));
+                        if !self.eat(&Tok::Keyword("and")) {
+                            break;
+                        }
+                    }
+                    BasDec::Basis(binds)
+                }
+                Some(Tok::Keyword("local")) => {
+                    self.pos += 1;
+                    let local = self.decs()?;
+                    self.expect(Tok::Keyword("in"))?;
+                    let body = self.decs()?;
+                    self.expect(Tok::Keyword("end"))?;
+                    BasDec::Local(local, body)
+                }
+                Some(Tok::Keyword("open")) => {
+                    self.pos += 1;
+                    let mut names = vec![self.name()?];
+                    while let Some(Tok::Id(_)) = self.peek() {
+                        names.push(self.name()?);
+                    }
+                    BasDec::Open(names)
+                }
+                Some(Tok::Keyword(kw @ ("structure" | "signature" | "functor"))) => {
+                    let kind = match *kw {
+                        "structure" => ModuleKind::Structure,
+                        "signature" => ModuleKind::Signature,
+                        _ => ModuleKind::Functor,
+                    };
+                    self.pos += 1;
+                    let mut binds = Vec::new();
+                    loop {
+                        let name = self.name()?;
+                        let old = if self.eat(&Tok::Equals) {
+                            self.name()?
+                        } else {
+                            name.clone()
+                        };
+                        binds.push((name, old));
+                        if !self.eat(&Tok::Keyword("and")) {
+                            break;
+                        }
+                    }
+                    BasDec::Alias(kind, binds)
+                }
+                Some(Tok::Path(path)) | Some(Tok::String(path)) => {
+                    let dec = BasDec::File(path.clone(), self.span());
+                    self.pos += 1;
+                    dec
+                }
+                Some(Tok::Keyword("ann")) => {
+                    self.pos += 1;
+                    let mut anns = Vec::new();
+                    while let Some(Tok::String(ann)) = self.peek() {
+                        anns.push(ann.clone());
+                        self.pos += 1;
+                    }
+                    if anns.is_empty() {
+                        return Err(self.unexpected("an annotation"));
+                    }
+                    self.expect(Tok::Keyword("in"))?;
+                    let decs = self.decs()?;
+                    self.expect(Tok::Keyword("end"))?;
+                    BasDec::Ann(anns, decs)
+                }
+                _ => return Ok(decs),
+            };
+            decs.push(dec);
+        }
+    }
+
+    fn exp(&mut self) -> Result<BasExp, Diagnostic> {
+        match self.peek() {
+            Some(Tok::Keyword("bas")) => {
+                self.pos += 1;
+                let decs = self.decs()?;
+                self.expect(Tok::Keyword("end"))?;
+                Ok(BasExp::Bas(decs))
+            }
+            Some(Tok::Keyword("let")) => {
+                self.pos += 1;
+                let decs = self.decs()?;
+                self.expect(Tok::Keyword("in"))?;
+                let exp = self.exp()?;
+                self.expect(Tok::Keyword("end"))?;
+                Ok(BasExp::Let(decs, Box::new(exp)))
+            }
+            Some(Tok::Id(_)) => Ok(BasExp::Var(self.name()?)),
+            _ => Err(self.unexpected("a basis expression")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(src: &str) -> Result<Vec<BasDec>, String> {
+        parse(FileId::ANON, src).map_err(|diag| diag.message)
+    }
+
+    /// The declarations, with the spans left out.
+    fn show(decs: &[BasDec]) -> String {
+        decs.iter()
+            .map(|dec| match dec {
+                BasDec::Basis(binds) => {
+                    let binds: Vec<_> = binds
+                        .iter()
+                        .map(|(n, exp)| format!("{} = {}", n.name, show_exp(exp)))
+                        .collect();
+                    format!("basis {}", binds.join(" and "))
+                }
+                BasDec::Local(a, b) => {
+                    format!("local {} in {} end", show(a), show(b))
+                }
+                BasDec::Open(names) => {
+                    let names: Vec<_> = names.iter().map(|n| n.name.clone()).collect();
+                    format!("open {}", names.join(" "))
+                }
+                BasDec::Alias(kind, binds) => {
+                    let binds: Vec<_> = binds
+                        .iter()
+                        .map(|(a, b)| format!("{} = {}", a.name, b.name))
+                        .collect();
+                    format!("{:?} {}", kind, binds.join(" and "))
+                }
+                BasDec::File(path, _) => path.clone(),
+                BasDec::Ann(anns, decs) => {
+                    format!("ann {:?} in {} end", anns, show(decs))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn show_exp(exp: &BasExp) -> String {
+        match exp {
+            BasExp::Bas(decs) => format!("bas {} end", show(decs)),
+            BasExp::Var(name) => name.name.clone(),
+            BasExp::Let(decs, exp) => format!("let {} in {} end", show(decs), show_exp(exp)),
+        }
+    }
+
+    #[test]
+    fn declarations() {
+        let cases = [
+            ("", ""),
+            ("a.sml b.sig; (* c.sml *) c/d.fun", "a.sml b.sig c/d.fun"),
+            ("\"with space.sml\"", "with space.sml"),
+            ("$(SML_LIB)/basis/basis.mlb", "$(SML_LIB)/basis/basis.mlb"),
+            (
+                "local a.sml in structure A structure B = A' and C = A end",
+                "local a.sml in Structure A = A Structure B = A' and C = A end",
+            ),
+            ("signature S functor F = G", "Signature S = S Functor F = G"),
+            (
+                "basis B = bas a.sml end and C = B open B C",
+                "basis B = bas a.sml end and C = B open B C",
+            ),
+            (
+                "basis B = let a.sml in bas b.sml end end",
+                "basis B = let a.sml in bas b.sml end end",
+            ),
+            (
+                "ann \"milletDiagnosticsIgnore true\" \"warnUnused false\" in a.sml end",
+                "ann [\"milletDiagnosticsIgnore true\", \"warnUnused false\"] in a.sml end",
+            ),
+            ("(* (* nested *) *) a.sml", "a.sml"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(show(&parse_str(src).unwrap()), expected, "{}", src);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            ("local a.sml end", "expected `in`, found `end`"),
+            ("basis = a.sml", "expected an identifier, found `=`"),
+            (
+                "basis B = a.sml",
+                "expected a basis expression, found `a.sml`",
+            ),
+            ("open", "expected an identifier, found the end of the file"),
+            ("ann in a.sml end", "expected an annotation, found `in`"),
+            ("a.sml end", "expected a basis declaration, found `end`"),
+            ("a.sml (* open", "unterminated comment"),
+            ("\"a.sml", "unterminated string"),
+            ("a.sml, b.sml", "unexpected character ','"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(parse_str(src), Err(expected.to_owned()), "{}", src);
+        }
+    }
+}
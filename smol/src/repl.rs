@@ -0,0 +1,466 @@
+//! An interactive top level, like SML/NJ's.
+//!
+//! Each input is a program ending in `;`, and goes through the whole
+//! pipeline: parsing, fixity, type checking, match compilation and
+//! evaluation. An input that fails at any stage leaves the environment as it
+//! was.
+//!
+//! Sessions start with the Basis loaded. Besides SML, the top level
+//! understands `use "file.sml";`, which runs a file, and commands starting
+//! with `:`. `:run` runs a file as a program of its own, compiled to bytecode
+//! for the virtual machine (see `vm`), rather than in the session.
+
+use std::fs;
+
+use parsegen::{FileId, SourceMap};
+
+use crate::ast::{Program, StrDecKind, TopDec};
+use crate::basis;
+use crate::diagnostic::Diagnostic;
+use crate::eval::{Interpreter, Raise};
+use crate::fixity;
+use crate::lexer::{ErrorKind, Keyword, Lexer, TokenKind};
+use crate::matching;
+use crate::types::{Checker, ConKind, IdStatus, Scheme};
+use crate::vm;
+
+pub const HELP: &str = "\
+Enter declarations or expressions, ending each with `;`.
+
+  use \"file.sml\";   run a file
+  :type <exp>       show the type of an expression without running it
+  :run <file>       run a file on its own, compiled to bytecode
+  :help             show this message
+  :quit             exit
+";
+
+/// What to do after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Print the text, and carry on.
+    Print(String),
+    Quit,
+}
+
+/// A program that ran, with its top level values and the rendered
+/// warnings.
+struct Ran {
+    program: Program,
+    bound: Vec<(String, Scheme)>,
+    warnings: String,
+}
+
+/// The state of a top level session.
+pub struct Session {
+    pub sources: SourceMap,
+    fixity: fixity::Env,
+    checker: Checker,
+    interp: Interpreter,
+    /// Where `:run` keeps bytecode.
+    cache: Option<vm::Cache>,
+}
+
+impl Session {
+    /// A session that runs programs with `interp`, with the Basis loaded.
+    pub fn new(interp: Interpreter) -> Self {
+        let mut session = Session {
+            sources: SourceMap::new(),
+            fixity: fixity::Env::basis(),
+            checker: Checker::new(),
+            interp,
+            cache: vm::Cache::from_env(),
+        };
+        session.load_basis();
+        session
+    }
+
+    /// Keep `:run`'s bytecode in `cache`, rather than the one the
+    /// environment names, or nowhere.
+    pub fn with_cache(mut self, cache: Option<vm::Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Load the Basis. It's part of the crate, so it failing to compile is
+    /// a bug.
+    fn load_basis(&mut self) {
+        self.load("prim.sml", basis::PRIM);
+        let prim = self.checker.sigs[basis::PRIM_SIG].env.clone();
+        self.checker.declare_structure("Prim", prim);
+        for (name, src) in basis::FILES {
+            self.load(name, src);
+        }
+    }
+
+    fn load(&mut self, name: &str, src: &str) {
+        let file = self.sources.add(&format!("<basis>/{}", name), src);
+        match self.exec(file) {
+            Ok(ran) if ran.warnings.is_empty() => (),
+            Ok(Ran {
+                warnings: errors, ..
+            })
+            | Err(errors) => panic!("the Basis doesn't compile:\n{}", errors),
+        }
+    }
+
+    /// Run an input, returning what to print: the new bindings, or the
+    /// errors.
+    pub fn input(&mut self, src: &str) -> String {
+        if let Some(path) = use_path(src) {
+            return self.use_file(&path);
+        }
+        let file = self.sources.add("stdin", src);
+        self.run(file)
+    }
+
+    /// Run a file.
+    pub fn use_file(&mut self, path: &str) -> String {
+        match fs::read_to_string(path) {
+            Ok(src) => {
+                let file = self.sources.add(path, &src);
+                self.run(file)
+            }
+            Err(err) => format!("error: can't read {}: {}\n", path, err),
+        }
+    }
+
+    /// Run a command, the text after a leading `:`.
+    pub fn command(&mut self, command: &str) -> Response {
+        let command = command.trim();
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "quit" | "q" => Response::Quit,
+            "help" | "h" => Response::Print(HELP.to_owned()),
+            "type" | "t" => Response::Print(self.type_of(arg)),
+            "run" | "r" => Response::Print(self.run_bytecode(arg)),
+            _ => Response::Print(format!("error: unknown command `:{}`, try `:help`\n", name)),
+        }
+    }
+
+    /// Run a file as a program of its own on the virtual machine, writing
+    /// to stdout, and give the errors or the uncaught exception. Its
+    /// bytecode is cached, so running it again, unchanged, skips compiling
+    /// it.
+    fn run_bytecode(&self, path: &str) -> String {
+        if path.is_empty() {
+            return "error: `:run` needs a file\n".to_owned();
+        }
+        let module = match vm::load(&[path.to_owned()], self.cache.as_ref()) {
+            Ok(module) => module,
+            Err(errors) => return errors,
+        };
+        match vm::Machine::new(&module).run() {
+            Ok(()) => String::new(),
+            Err(uncaught) => format!("{}\n", uncaught),
+        }
+    }
+
+    /// The type of an expression, without running it.
+    fn type_of(&mut self, exp: &str) -> String {
+        let src = format!("{};", exp.trim_end_matches(';'));
+        let file = self.sources.add("stdin", &src);
+        let mut checker = self.checker.clone();
+        let mut fixity = self.fixity.clone();
+        let res = self
+            .parse(file, &mut fixity)
+            .and_then(|program| checker.check_program(&program));
+        match res {
+            Ok(bound) => match bound.iter().find(|(name, _)| name == "it") {
+                Some((_, scheme)) => {
+                    format!("{} : {}\n", exp.trim(), checker.show_scheme(scheme))
+                }
+                None => "error: expected an expression\n".to_owned(),
+            },
+            Err(diags) => self.render(&diags),
+        }
+    }
+
+    fn parse(
+        &self,
+        file: FileId,
+        fixity: &mut fixity::Env,
+    ) -> Result<Program, Vec<crate::diagnostic::Diagnostic>> {
+        let mut program = crate::lower::parse(&self.sources, file)?;
+        fixity::resolve_with(fixity, &mut program)?;
+        Ok(program)
+    }
+
+    fn render(&self, diags: &[Diagnostic]) -> String {
+        diags.iter().map(|d| d.render(&self.sources)).collect()
+    }
+
+    /// Run a file, returning the rendered errors (and warnings) if it fails.
+    fn exec(&mut self, file: FileId) -> Result<Ran, String> {
+        let mut fixity = self.fixity.clone();
+        let program = self
+            .parse(file, &mut fixity)
+            .map_err(|diags| self.render(&diags))?;
+
+        let saved = self.checker.clone();
+        let bound = match self.checker.check_program(&program) {
+            Ok(bound) => bound,
+            Err(diags) => {
+                self.checker = saved;
+                return Err(self.render(&diags));
+            }
+        };
+        let (matches, warnings) =
+            matching::compile_program(&program, &self.checker.info, &self.checker.tycons);
+        let warnings = self.render(&warnings);
+
+        let env = self.interp.env.clone();
+        if let Err(Raise(exn)) = self.interp.run(&program, &self.checker.info, &matches) {
+            self.checker = saved;
+            self.interp.env = env;
+            return Err(format!("{}uncaught exception {}\n", warnings, exn));
+        }
+        self.fixity = fixity;
+        Ok(Ran {
+            program,
+            bound,
+            warnings,
+        })
+    }
+
+    fn run(&mut self, file: FileId) -> String {
+        let Ran {
+            program,
+            bound,
+            warnings: mut out,
+        } = match self.exec(file) {
+            Ok(ran) => ran,
+            Err(errors) => return errors,
+        };
+        for item in &program.items {
+            out += &show_modules(item);
+        }
+        for (name, scheme) in bound {
+            let ty = self.checker.show_scheme(&scheme);
+            let line = match self.checker.lookup_value(&name).map(|b| &b.status) {
+                Some(IdStatus::Con(info)) => match info.kind {
+                    ConKind::Datatype { .. } => format!("con {} : {}\n", name, ty),
+                    ConKind::Exn(_) => format!("exception {} : {}\n", name, ty),
+                },
+                // Values of abstract types are hidden, as their structure's
+                // signature hides what they are.
+                _ if self.checker.is_abstract(&scheme.ty) => format!("val {} = - : {}\n", name, ty),
+                _ => match self.interp.env.lookup_value(&name) {
+                    Some(value) => format!("val {} = {} : {}\n", name, value, ty),
+                    None => format!("val {} : {}\n", name, ty),
+                },
+            };
+            out += &line;
+        }
+        out
+    }
+}
+
+/// The structures, signatures and functors a top level declaration binds.
+pub(crate) fn show_modules(item: &TopDec) -> String {
+    let lines: Vec<String> = match item {
+        TopDec::Str(dec) => match &dec.kind {
+            StrDecKind::Structure(binds) => binds
+                .iter()
+                .map(|bind| format!("structure {}", bind.id))
+                .collect(),
+            _ => Vec::new(),
+        },
+        TopDec::Sig(binds) => binds
+            .iter()
+            .map(|bind| format!("signature {}", bind.id))
+            .collect(),
+        TopDec::Functor(binds) => binds
+            .iter()
+            .map(|bind| format!("functor {}", bind.id))
+            .collect(),
+        TopDec::Exp(_) => Vec::new(),
+    };
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// The file of a `use "file";` input.
+fn use_path(src: &str) -> Option<String> {
+    let toks: Vec<_> = Lexer::new(FileId::ANON, src)
+        .map(|tok| tok.ok().map(|tok| tok.kind))
+        .collect::<Option<_>>()?;
+    match toks.as_slice() {
+        [TokenKind::Id(id), TokenKind::String(path), TokenKind::Keyword(Keyword::Semicolon)]
+            if id == "use" =>
+        {
+            Some(path.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Whether an input is complete: it ends with `;`, outside any comment or
+/// string. Inputs with other lexical errors are complete, so the errors are
+/// reported.
+///
+/// # Examples
+///
+/// ```
+/// use smol::repl::is_complete;
+///
+/// assert!(is_complete("val x = 1;"));
+/// assert!(!is_complete("fun f x =\n  x"));
+/// assert!(!is_complete("val s = \"a;"));
+/// assert!(!is_complete("1; (* ; "));
+/// ```
+pub fn is_complete(src: &str) -> bool {
+    let mut last = None;
+    for tok in Lexer::new(FileId::ANON, src) {
+        match tok {
+            Ok(tok) => last = Some(tok.kind),
+            Err(err) => {
+                return !matches!(
+                    err.kind,
+                    ErrorKind::UnterminatedComment | ErrorKind::UnterminatedString
+                )
+            }
+        }
+    }
+    last == Some(TokenKind::Keyword(Keyword::Semicolon))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new(Interpreter::with_output(Box::new(std::io::sink())))
+    }
+
+    #[test]
+    fn bindings() {
+        let mut s = session();
+        assert_eq!(s.input("1 + 2;"), "val it = 3 : int\n");
+        assert_eq!(
+            s.input("fun f x = x; val y = f \"a\";"),
+            "val f = fn : 'a -> 'a\nval y = \"a\" : string\n"
+        );
+        assert_eq!(
+            s.input("datatype t = A | B of int; exception E;"),
+            "con A : t\ncon B : int -> t\nexception E : exn\n"
+        );
+        assert_eq!(
+            s.input("structure S = struct val z = [B 1, A] end; signature T = sig end;"),
+            "structure S\nsignature T\n"
+        );
+        assert_eq!(
+            s.input("(S.z, it);"),
+            "val it = ([B 1, A], 3) : t list * int\n"
+        );
+    }
+
+    #[test]
+    fn abstract_values() {
+        let mut s = session();
+        s.input(
+            "structure Set :> sig type set val empty : set val add : int * set -> set end =
+             struct type set = int list val empty = [] fun add (x, s) = x :: s end;",
+        );
+        assert_eq!(
+            s.input("val s = Set.add (2, Set.add (1, Set.empty));"),
+            "val s = - : Set.set\n"
+        );
+        assert_eq!(s.input("(s, 1);"), "val it = - : Set.set * int\n");
+        assert_eq!(
+            s.command("type Set.add"),
+            Response::Print("Set.add : int * Set.set -> Set.set\n".to_owned())
+        );
+        assert_eq!(
+            s.input("abstype t = T of int with val t = T 1 end;"),
+            "val t = - : t\n"
+        );
+        // Transparent ascription shows what the type is.
+        s.input(
+            "structure L : sig type t val x : t end = struct type t = int list val x = [1] end;",
+        );
+        assert_eq!(s.input("L.x;"), "val it = [1] : int list\n");
+    }
+
+    #[test]
+    fn failed_inputs_change_nothing() {
+        let mut s = session();
+        s.input("val x = 1;");
+        assert!(s
+            .input("val x = true; val y = x + 1;")
+            .contains("error: `+` is defined on int, word and real, not `bool`"));
+        assert!(s
+            .input("infix 5 ++; val x = \"s\"; val _ = raise Fail \"no\";")
+            .ends_with("uncaught exception Fail \"no\"\n"));
+        assert!(s.input("val z = 1 ++ 2;").contains("error"));
+        assert_eq!(s.input("x;"), "val it = 1 : int\n");
+    }
+
+    #[test]
+    fn fixity_persists() {
+        let mut s = session();
+        s.input("infixr 5 ++; fun [] ++ ys = ys | (x :: xs) ++ ys = x :: (xs ++ ys);");
+        assert_eq!(
+            s.input("[1] ++ [2] ++ [3];"),
+            "val it = [1, 2, 3] : int list\n"
+        );
+    }
+
+    #[test]
+    fn commands() {
+        let mut s = session();
+        s.input("val x = 1;");
+        assert_eq!(
+            s.command("type fn y => (x, y)"),
+            Response::Print("fn y => (x, y) : 'a -> int * 'a\n".to_owned())
+        );
+        // `:type` doesn't bind `it`.
+        assert!(s.input("it;").contains("unbound variable"));
+        assert_eq!(s.command("quit"), Response::Quit);
+        assert!(matches!(s.command("nope"), Response::Print(ref s) if s.contains("unknown")));
+    }
+
+    #[test]
+    fn use_file() {
+        let path = std::env::temp_dir().join(format!("smol-use-{}.sml", std::process::id()));
+        fs::write(&path, "fun double x = x * 2\nval four = double 2\n").unwrap();
+        let mut s = session();
+        let out = s.input(&format!("use {:?};", path.display().to_string()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(out, "val double = fn : int -> int\nval four = 4 : int\n");
+        assert_eq!(s.input("double four;"), "val it = 8 : int\n");
+        assert!(s
+            .input("use \"/nonexistent/file.sml\";")
+            .starts_with("error: can't read"));
+    }
+
+    #[test]
+    fn run_command() {
+        // Compiling the Basis for the virtual machine takes more stack than
+        // a test has.
+        let run = || {
+            let path = std::env::temp_dir().join(format!("smol-run-{}.sml", std::process::id()));
+            fs::write(&path, "val x = 1\nval _ = raise Fail \"no\"\n").unwrap();
+            let mut s = session().with_cache(None);
+            let out = s.command(&format!("run {}", path.display()));
+            fs::remove_file(&path).unwrap();
+            assert_eq!(out, Response::Print("uncaught exception Fail\n".to_owned()));
+            // The file ran on its own, so the session didn't get its bindings.
+            assert!(s.input("x;").contains("unbound variable"));
+            assert!(
+                matches!(s.command("run"), Response::Print(ref s) if s.contains("needs a file"))
+            );
+            assert!(matches!(
+                s.command("run /nonexistent/file.sml"),
+                Response::Print(ref s) if s.starts_with("error: can't read")
+            ));
+        };
+        std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
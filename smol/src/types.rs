@@ -0,0 +1,2803 @@
+//! Type inference for the core language.
+//!
+//! This is Algorithm J: type variables are mutable cells that are bound as
+//! unification goes, and let-polymorphism uses levels to decide which
+//! variables can be generalized. On top of plain Hindley-Milner, Standard ML
+//! needs:
+//!
+//! - The value restriction. Only non-expansive expressions (roughly, values)
+//!   are generalized.
+//! - Equality type variables (`''a`), which only unify with types that admit
+//!   equality.
+//! - Overloaded operators (`+`, `<`, ...). Their type variables range over a
+//!   fixed set of types, and default to `int` if nothing else decides them.
+//! - Flexible records (`{x, ...}` and `#x`), whose other fields must be known
+//!   by the end of the declaration.
+//!
+//! Fixity must be resolved before checking.
+//!
+//! Types print variables that are still being inferred as `'_a`, to tell
+//! them from the variables of a polymorphic type, which can be any type.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+use parsegen::Span;
+
+use crate::ast::*;
+use crate::diagnostic::Diagnostic;
+
+mod modules;
+
+pub use modules::{Basis, Functor, Sig};
+
+// Types
+
+/// A type constructor, e.g. `int` or `list`. Datatype declarations are
+/// generative, so each declaration gets a new id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TyCon {
+    pub id: u32,
+    pub name: Rc<str>,
+}
+
+// Ids of the built in type constructors.
+pub const INT: u32 = 0;
+pub const WORD: u32 = 1;
+pub const REAL: u32 = 2;
+pub const CHAR: u32 = 3;
+pub const STRING: u32 = 4;
+pub const BOOL: u32 = 5;
+pub const LIST: u32 = 6;
+pub const REF: u32 = 7;
+pub const EXN: u32 = 8;
+pub const OPTION: u32 = 9;
+pub const ORDER: u32 = 10;
+pub const ARRAY: u32 = 11;
+pub const VECTOR: u32 = 12;
+
+const BUILTIN_TYCONS: &[&str] = &[
+    "int", "word", "real", "char", "string", "bool", "list", "ref", "exn", "option", "order",
+    "array", "vector",
+];
+
+/// The built in exceptions. Each one's id is its index.
+pub const BUILTIN_EXNS: &[&str] = &[
+    "Bind",
+    "Match",
+    "Div",
+    "Overflow",
+    "Size",
+    "Subscript",
+    "Chr",
+    "Domain",
+    "Empty",
+    "Fail",
+    "Span",
+];
+
+#[derive(Debug, Clone)]
+pub enum Type {
+    Var(TypeVar),
+    /// A generalized type variable, standing for the nth parameter of a
+    /// scheme or type function.
+    Gen(u32),
+    Con(TyCon, Vec<Type>),
+    /// Records, including tuples (labelled `1` to `n`) and unit.
+    Record(BTreeMap<Lab, Type>),
+    Arrow(Box<Type>, Box<Type>),
+}
+
+impl Type {
+    pub fn con(id: u32, args: Vec<Type>) -> Type {
+        Type::Con(
+            TyCon {
+                id,
+                name: Rc::from(BUILTIN_TYCONS[id as usize]),
+            },
+            args,
+        )
+    }
+
+    pub fn int() -> Type {
+        Type::con(INT, Vec::new())
+    }
+
+    pub fn bool() -> Type {
+        Type::con(BOOL, Vec::new())
+    }
+
+    pub fn exn() -> Type {
+        Type::con(EXN, Vec::new())
+    }
+
+    pub fn unit() -> Type {
+        Type::Record(BTreeMap::new())
+    }
+
+    pub fn tuple(tys: Vec<Type>) -> Type {
+        Type::Record(
+            tys.into_iter()
+                .enumerate()
+                .map(|(i, ty)| (Lab::Num(i as u32 + 1), ty))
+                .collect(),
+        )
+    }
+
+    pub fn arrow(arg: Type, res: Type) -> Type {
+        Type::Arrow(Box::new(arg), Box::new(res))
+    }
+
+    /// Follow bound type variables until reaching a type that isn't one.
+    pub fn resolve(&self) -> Type {
+        match self {
+            Type::Var(var) => match &*var.0.borrow() {
+                VarState::Bound(ty) => ty.resolve(),
+                VarState::Unbound(_) => self.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Resolve all bound type variables in the type.
+    pub fn zonk(&self) -> Type {
+        match self.resolve() {
+            Type::Con(con, args) => Type::Con(con, args.iter().map(Type::zonk).collect()),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(lab, ty)| (lab.clone(), ty.zonk()))
+                    .collect(),
+            ),
+            Type::Arrow(a, b) => Type::arrow(a.zonk(), b.zonk()),
+            ty => ty,
+        }
+    }
+
+    /// Replace generalized variables with the given types.
+    fn subst(&self, args: &[Type]) -> Type {
+        match self {
+            Type::Gen(i) => args[*i as usize].clone(),
+            Type::Var(_) => self.clone(),
+            Type::Con(con, tys) => {
+                Type::Con(con.clone(), tys.iter().map(|t| t.subst(args)).collect())
+            }
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(lab, ty)| (lab.clone(), ty.subst(args)))
+                    .collect(),
+            ),
+            Type::Arrow(a, b) => Type::arrow(a.subst(args), b.subst(args)),
+        }
+    }
+
+    /// Replace the unbound type variables `subst` has.
+    pub fn apply(&self, subst: &Subst) -> Type {
+        if subst.0.is_empty() {
+            return self.clone();
+        }
+        match self.resolve() {
+            Type::Var(var) => match &*var.0.borrow() {
+                VarState::Unbound(u) => subst.0.get(&u.id).cloned(),
+                VarState::Bound(_) => unreachable!(),
+            }
+            .unwrap_or_else(|| Type::Var(var.clone())),
+            Type::Gen(i) => Type::Gen(i),
+            Type::Con(con, args) => Type::Con(con, args.iter().map(|t| t.apply(subst)).collect()),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(lab, ty)| (lab.clone(), ty.apply(subst)))
+                    .collect(),
+            ),
+            Type::Arrow(a, b) => Type::arrow(a.apply(subst), b.apply(subst)),
+        }
+    }
+
+    /// Extend `subst` so that it takes this type to `instance`, binding the
+    /// unbound variables of this type to the parts of `instance` in their
+    /// place. Variables already in `subst` keep their types.
+    pub fn match_instance(&self, instance: &Type, subst: &mut Subst) {
+        match (self.resolve(), instance.resolve()) {
+            (Type::Var(var), instance) => {
+                if let VarState::Unbound(u) = &*var.0.borrow() {
+                    subst.0.entry(u.id).or_insert(instance);
+                }
+            }
+            (Type::Con(_, args), Type::Con(_, instances)) => {
+                for (arg, instance) in args.iter().zip(&instances) {
+                    arg.match_instance(instance, subst);
+                }
+            }
+            (Type::Record(fields), Type::Record(instances)) => {
+                for (lab, ty) in &fields {
+                    if let Some(instance) = instances.get(lab) {
+                        ty.match_instance(instance, subst);
+                    }
+                }
+            }
+            (Type::Arrow(a, b), Type::Arrow(c, d)) => {
+                a.match_instance(&c, subst);
+                b.match_instance(&d, subst);
+            }
+            _ => (),
+        }
+    }
+
+    /// The tuple components of a record type, if it's a tuple.
+    fn as_tuple(fields: &BTreeMap<Lab, Type>) -> Option<Vec<&Type>> {
+        if fields.len() < 2 {
+            return None;
+        }
+        let tys: Vec<_> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, (lab, ty))| (*lab == Lab::Num(i as u32 + 1)).then_some(ty))
+            .collect::<Option<_>>()?;
+        Some(tys)
+    }
+}
+
+/// Types are equal if they're the same once bound variables are resolved.
+/// Unbound variables are only equal to themselves.
+impl PartialEq for Type {
+    fn eq(&self, other: &Type) -> bool {
+        match (self.resolve(), other.resolve()) {
+            (Type::Var(a), Type::Var(b)) => Rc::ptr_eq(&a.0, &b.0),
+            (Type::Gen(a), Type::Gen(b)) => a == b,
+            (Type::Con(a, args), Type::Con(b, others)) => a == b && args == others,
+            (Type::Record(a), Type::Record(b)) => a == b,
+            (Type::Arrow(a, b), Type::Arrow(c, d)) => a == c && b == d,
+            _ => false,
+        }
+    }
+}
+
+/// Types for unbound type variables, which specialise a polymorphic type
+/// to one of its instances.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subst(BTreeMap<u32, Type>);
+
+/// A unification variable.
+#[derive(Clone)]
+pub struct TypeVar(Rc<RefCell<VarState>>);
+
+impl fmt::Debug for TypeVar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &*self.0.borrow() {
+            VarState::Bound(ty) => write!(f, "{:?}", ty),
+            VarState::Unbound(var) => write!(f, "?{}", var.id),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum VarState {
+    Bound(Type),
+    Unbound(Unbound),
+}
+
+#[derive(Debug, Clone)]
+struct Unbound {
+    id: u32,
+    /// The let-nesting level the variable was created at, lowered when it's
+    /// unified with variables from outer levels. Only variables above the
+    /// current level are generalized.
+    level: u32,
+    eq: bool,
+    kind: VarKind,
+}
+
+#[derive(Debug, Clone)]
+enum VarKind {
+    Plain,
+    /// An explicit type variable from an annotation, which can't be unified
+    /// with anything but itself.
+    Rigid(String),
+    /// A variable of an overloaded operator, which can only be one of the
+    /// types in the set.
+    Overload(Overload),
+    /// A record with at least these fields.
+    Record(BTreeMap<Lab, Type>),
+}
+
+/// A set of types an overloaded type variable can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overload(u8);
+
+impl Overload {
+    const TYCONS: [u32; 5] = [INT, WORD, REAL, CHAR, STRING];
+
+    pub const INT: Overload = Overload(0b00011);
+    pub const REAL: Overload = Overload(0b00100);
+    pub const NUM: Overload = Overload(0b00111);
+    pub const NUM_TXT: Overload = Overload(0b11111);
+
+    fn contains(self, id: u32) -> bool {
+        Self::TYCONS
+            .iter()
+            .position(|&t| t == id)
+            .is_some_and(|i| self.0 & (1 << i) != 0)
+    }
+
+    fn intersect(self, other: Overload) -> Option<Overload> {
+        let set = self.0 & other.0;
+        (set != 0).then_some(Overload(set))
+    }
+
+    /// Remove types that don't admit equality.
+    fn with_eq(self) -> Option<Overload> {
+        self.intersect(Overload(!Overload::REAL.0))
+    }
+
+    /// The types in the set, e.g. "int, word and real".
+    fn describe(self) -> String {
+        const NAMES: [&str; 5] = ["int", "word", "real", "char", "string"];
+        let names: Vec<_> = (0..5)
+            .filter(|i| self.0 & (1 << i) != 0)
+            .map(|i| NAMES[i])
+            .collect();
+        match names.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+            _ => names.concat(),
+        }
+    }
+
+    /// The type used when nothing else decides. This is the first of `int`,
+    /// `word`, `real`, `char` and `string` in the set.
+    fn default(self) -> Type {
+        let i = (0..5).find(|i| self.0 & (1 << i) != 0).unwrap();
+        Type::con(Self::TYCONS[i], Vec::new())
+    }
+}
+
+/// A polymorphic type. `Gen(i)` in the type refers to `params[i]`.
+#[derive(Debug, Clone)]
+pub struct Scheme {
+    pub params: Vec<GenParam>,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GenParam {
+    pub eq: bool,
+    pub overload: Option<Overload>,
+}
+
+impl Scheme {
+    pub fn mono(ty: Type) -> Self {
+        Scheme {
+            params: Vec::new(),
+            ty,
+        }
+    }
+}
+
+impl Scheme {
+    /// Show the scheme, giving the type constructors in `names` those names
+    /// rather than their own.
+    pub fn show_with(&self, names: HashMap<u32, String>) -> String {
+        let mut namer = Namer {
+            params: self.params.clone(),
+            names,
+            ..Namer::default()
+        };
+        namer.show(&self.ty)
+    }
+}
+
+impl Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut namer = Namer {
+            params: self.params.clone(),
+            ..Namer::default()
+        };
+        write!(f, "{}", namer.show(&self.ty))
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Namer::default().show(self))
+    }
+}
+
+/// Names type variables `'a`, `'b`, ... in order of appearance, so that
+/// several types can be shown with consistent names.
+#[derive(Default)]
+struct Namer {
+    vars: HashMap<u32, String>,
+    params: Vec<GenParam>,
+    gens: HashMap<u32, String>,
+    used: usize,
+    /// Names of type constructors to use instead of their own.
+    names: HashMap<u32, String>,
+}
+
+impl Namer {
+    fn fresh(&mut self, eq: bool) -> String {
+        let n = self.used;
+        self.used += 1;
+        let letter = (b'a' + (n % 26) as u8) as char;
+        let suffix = if n >= 26 {
+            (n / 26).to_string()
+        } else {
+            String::new()
+        };
+        format!("{}{}{}", if eq { "''" } else { "'" }, letter, suffix)
+    }
+
+    /// A name for a variable that hasn't been generalised, e.g. `'_a`, so it
+    /// isn't taken for one that can be any type.
+    fn fresh_ungeneralised(&mut self, eq: bool) -> String {
+        let name = self.fresh(eq);
+        let quotes = if eq { 2 } else { 1 };
+        format!("{}_{}", &name[..quotes], &name[quotes..])
+    }
+
+    fn show(&mut self, ty: &Type) -> String {
+        self.show_prec(ty, 0)
+    }
+
+    /// Show a type, parenthesizing it if it binds looser than `prec`: 0 for
+    /// arrows, 1 for tuples, 2 for applications.
+    fn show_prec(&mut self, ty: &Type, prec: u8) -> String {
+        let (s, ty_prec) = match ty.resolve() {
+            Type::Var(var) => match &*var.0.borrow() {
+                VarState::Unbound(var) => match &var.kind {
+                    VarKind::Record(fields) => {
+                        let mut rows: Vec<String> = fields
+                            .iter()
+                            .map(|(lab, ty)| format!("{} : {}", lab, self.show(ty)))
+                            .collect();
+                        rows.push("...".to_owned());
+                        (format!("{{{}}}", rows.join(", ")), 2)
+                    }
+                    kind => {
+                        let name = match self.vars.get(&var.id) {
+                            Some(name) => name.clone(),
+                            None => {
+                                let name = match kind {
+                                    VarKind::Rigid(name) => {
+                                        format!("{}{}", if var.eq { "''" } else { "'" }, name)
+                                    }
+                                    _ => self.fresh_ungeneralised(var.eq),
+                                };
+                                self.vars.insert(var.id, name.clone());
+                                name
+                            }
+                        };
+                        (name, 2)
+                    }
+                },
+                VarState::Bound(_) => unreachable!(),
+            },
+            Type::Gen(i) => {
+                let name = match self.gens.get(&i) {
+                    Some(name) => name.clone(),
+                    None => {
+                        let eq = self.params.get(i as usize).is_some_and(|p| p.eq);
+                        let name = self.fresh(eq);
+                        self.gens.insert(i, name.clone());
+                        name
+                    }
+                };
+                (name, 2)
+            }
+            Type::Con(con, args) => {
+                let name = match self.names.get(&con.id) {
+                    Some(name) => name.clone(),
+                    None => con.name.to_string(),
+                };
+                let s = match args.len() {
+                    0 => name,
+                    1 => format!("{} {}", self.show_prec(&args[0], 2), name),
+                    _ => {
+                        let args: Vec<_> = args.iter().map(|t| self.show(t)).collect();
+                        format!("({}) {}", args.join(", "), name)
+                    }
+                };
+                (s, 2)
+            }
+            Type::Record(fields) if fields.is_empty() => ("unit".to_owned(), 2),
+            Type::Record(fields) => match Type::as_tuple(&fields) {
+                Some(tys) => {
+                    let tys: Vec<_> = tys.iter().map(|t| self.show_prec(t, 2)).collect();
+                    (tys.join(" * "), 1)
+                }
+                None => {
+                    let rows: Vec<_> = fields
+                        .iter()
+                        .map(|(lab, ty)| format!("{} : {}", lab, self.show(ty)))
+                        .collect();
+                    (format!("{{{}}}", rows.join(", ")), 2)
+                }
+            },
+            Type::Arrow(a, b) => {
+                let s = format!("{} -> {}", self.show_prec(&a, 1), self.show_prec(&b, 0));
+                (s, 0)
+            }
+        };
+        if ty_prec < prec {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+}
+
+// Environments
+
+/// What an identifier in the value environment is.
+#[derive(Debug, Clone)]
+pub enum IdStatus {
+    Var,
+    /// A datatype or exception constructor.
+    Con(Rc<ConInfo>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConInfo {
+    pub name: String,
+    pub kind: ConKind,
+    pub has_arg: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConKind {
+    /// The `tag`th constructor of a datatype.
+    Datatype { tycon: TyCon, tag: usize },
+    /// An exception constructor. Exception declarations are generative, so
+    /// each one gets a new id.
+    Exn(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct ValueBinding {
+    pub scheme: Scheme,
+    pub status: IdStatus,
+}
+
+/// A type function, mapping `arity` argument types to a type. `Gen(i)` in
+/// `ty` refers to the ith argument.
+#[derive(Debug, Clone)]
+pub struct TyFcn {
+    pub arity: usize,
+    pub ty: Type,
+}
+
+impl TyFcn {
+    fn apply(&self, args: &[Type]) -> Type {
+        self.ty.subst(args)
+    }
+}
+
+/// A type structure: a type function, and the constructors if it's a
+/// datatype.
+#[derive(Debug, Clone)]
+pub struct TyStr {
+    pub fcn: TyFcn,
+    pub cons: Vec<String>,
+}
+
+/// What the checker knows about a datatype.
+#[derive(Debug, Clone)]
+pub struct TyConInfo {
+    pub name: Rc<str>,
+    pub arity: usize,
+    pub eq: Equality,
+    /// Constructor names and whether they take an argument, in order of tag.
+    pub cons: Vec<(String, bool)>,
+    /// Whether the type is abstract, made by opaque ascription or `abstype`,
+    /// so its values can't be shown.
+    pub opaque: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Equality {
+    /// Admits equality whatever the arguments, like `ref`.
+    Always,
+    /// Admits equality if the arguments do.
+    IfArgs,
+    Never,
+}
+
+/// Identifiers in scope.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    pub values: HashMap<String, ValueBinding>,
+    pub types: HashMap<String, TyStr>,
+    pub structures: HashMap<String, Env>,
+}
+
+impl Env {
+    /// Add all bindings of `other`, shadowing existing ones.
+    pub fn extend(&mut self, other: Env) {
+        self.values.extend(other.values);
+        self.types.extend(other.types);
+        self.structures.extend(other.structures);
+    }
+}
+
+/// What was learnt about a program while checking it, keyed by the spans of
+/// identifiers.
+#[derive(Debug, Clone, Default)]
+pub struct Info {
+    /// The types of variables, at both binding and use sites. Use sites have
+    /// the instantiated type. Flexible record patterns and selectors have
+    /// their types recorded too, since their other fields aren't written.
+    pub types: HashMap<Span, Type>,
+    /// Identifiers that refer to constructors, in patterns and expressions.
+    pub cons: HashMap<Span, Rc<ConInfo>>,
+    /// The environment a structure has after matching a signature, keyed by
+    /// the span of the signature expression. Functor arguments are keyed by
+    /// the parameter's signature.
+    pub sigs: HashMap<Span, Env>,
+}
+
+// Unification
+
+enum UnifyError {
+    Mismatch(Type, Type),
+    Circular(Type, Type),
+    NotEquality(Type),
+    NotOverloaded(Type),
+    MissingField(Lab, Type),
+}
+
+/// Type check a program from the initial environment.
+///
+/// # Examples
+///
+/// ```
+/// use parsegen::SourceMap;
+///
+/// let mut sources = SourceMap::new();
+/// let file = sources.add("a.sml", "fun map f [] = [] | map f (x :: xs) = f x :: map f xs");
+/// let mut program = smol::lower::parse(&sources, file).unwrap();
+/// smol::fixity::resolve(&mut program).unwrap();
+///
+/// let mut checker = smol::types::Checker::new();
+/// let bindings = checker.check_program(&program).unwrap();
+/// assert_eq!(bindings[0].0, "map");
+/// assert_eq!(bindings[0].1.to_string(), "('a -> 'b) -> 'a list -> 'b list");
+/// ```
+pub fn check(program: &Program) -> Result<Info, Vec<Diagnostic>> {
+    let mut checker = Checker::new();
+    checker.check_program(program)?;
+    Ok(checker.info)
+}
+
+/// A type checker, holding the environment of everything checked so far.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    /// Scopes of the environment, innermost last.
+    scopes: Vec<Env>,
+    /// Explicit type variables in scope.
+    tyvars: Vec<HashMap<String, Type>>,
+    pub tycons: Vec<TyConInfo>,
+    pub sigs: HashMap<String, Rc<Sig>>,
+    pub functors: HashMap<String, Rc<Functor>>,
+    /// The signatures and functors in scope when each basis being checked
+    /// was entered, innermost last.
+    bases: Vec<modules::Modules>,
+    pub info: Info,
+    level: u32,
+    next_var: u32,
+    next_exn: u32,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new()
+    }
+}
+
+impl Checker {
+    /// A checker with the built in types and values.
+    pub fn new() -> Self {
+        let mut checker = Checker {
+            scopes: vec![Env::default()],
+            tyvars: Vec::new(),
+            tycons: Vec::new(),
+            sigs: HashMap::new(),
+            functors: HashMap::new(),
+            bases: Vec::new(),
+            info: Info::default(),
+            level: 0,
+            next_var: 0,
+            next_exn: 0,
+            diagnostics: Vec::new(),
+        };
+        checker.add_builtins();
+        checker
+    }
+
+    fn add_builtins(&mut self) {
+        // A constructor name and its argument type.
+        type Con<'a> = (&'a str, Option<Type>);
+        let gen = |eq| GenParam { eq, overload: None };
+        let a = || Type::Gen(0);
+        for (id, name) in BUILTIN_TYCONS.iter().enumerate() {
+            let (arity, eq) = match id as u32 {
+                REAL | EXN => (0, Equality::Never),
+                REF | ARRAY => (1, Equality::Always),
+                LIST | OPTION | VECTOR => (1, Equality::IfArgs),
+                _ => (0, Equality::IfArgs),
+            };
+            self.tycons.push(TyConInfo {
+                name: Rc::from(*name),
+                arity,
+                eq,
+                cons: Vec::new(),
+                opaque: false,
+            });
+            let args = (0..arity as u32).map(Type::Gen).collect();
+            self.scope().types.insert(
+                name.to_string(),
+                TyStr {
+                    fcn: TyFcn {
+                        arity,
+                        ty: Type::con(id as u32, args),
+                    },
+                    cons: Vec::new(),
+                },
+            );
+        }
+        self.scope().types.insert(
+            "unit".to_owned(),
+            TyStr {
+                fcn: TyFcn {
+                    arity: 0,
+                    ty: Type::unit(),
+                },
+                cons: Vec::new(),
+            },
+        );
+
+        let datatypes: &[(u32, &[Con])] = &[
+            (BOOL, &[("false", None), ("true", None)]),
+            (
+                LIST,
+                &[
+                    ("nil", None),
+                    (
+                        "::",
+                        Some(Type::tuple(vec![a(), Type::con(LIST, vec![a()])])),
+                    ),
+                ],
+            ),
+            (REF, &[("ref", Some(a()))]),
+            (OPTION, &[("NONE", None), ("SOME", Some(a()))]),
+            (ORDER, &[("LESS", None), ("EQUAL", None), ("GREATER", None)]),
+        ];
+        for (id, cons) in datatypes {
+            let arity = self.tycons[*id as usize].arity;
+            let res = Type::con(*id, (0..arity as u32).map(Type::Gen).collect());
+            let params = vec![gen(false); arity];
+            let names = cons.iter().map(|(name, _)| name.to_string()).collect();
+            self.scope()
+                .types
+                .get_mut(BUILTIN_TYCONS[*id as usize])
+                .unwrap()
+                .cons = names;
+            for (tag, (name, arg)) in cons.iter().enumerate() {
+                let ty = match arg {
+                    Some(arg) => Type::arrow(arg.clone(), res.clone()),
+                    None => res.clone(),
+                };
+                self.add_con(
+                    name,
+                    Scheme {
+                        params: params.clone(),
+                        ty,
+                    },
+                    ConKind::Datatype {
+                        tycon: TyCon {
+                            id: *id,
+                            name: Rc::from(BUILTIN_TYCONS[*id as usize]),
+                        },
+                        tag,
+                    },
+                    arg.is_some(),
+                );
+            }
+        }
+
+        let string = || Type::con(STRING, Vec::new());
+        for name in BUILTIN_EXNS {
+            let (ty, has_arg) = match *name {
+                "Fail" => (Type::arrow(string(), Type::exn()), true),
+                _ => (Type::exn(), false),
+            };
+            let id = self.fresh_exn();
+            self.add_con(name, Scheme::mono(ty), ConKind::Exn(id), has_arg);
+        }
+
+        let overloaded = |set| GenParam {
+            eq: false,
+            overload: Some(set),
+        };
+        let binary = |res: Type| Type::arrow(Type::tuple(vec![a(), a()]), res);
+        let values = vec![
+            ("+", vec![overloaded(Overload::NUM)], binary(a())),
+            ("-", vec![overloaded(Overload::NUM)], binary(a())),
+            ("*", vec![overloaded(Overload::NUM)], binary(a())),
+            ("/", vec![overloaded(Overload::REAL)], binary(a())),
+            ("div", vec![overloaded(Overload::INT)], binary(a())),
+            ("mod", vec![overloaded(Overload::INT)], binary(a())),
+            ("~", vec![overloaded(Overload::NUM)], Type::arrow(a(), a())),
+            (
+                "abs",
+                vec![overloaded(Overload::NUM)],
+                Type::arrow(a(), a()),
+            ),
+            (
+                "<",
+                vec![overloaded(Overload::NUM_TXT)],
+                binary(Type::bool()),
+            ),
+            (
+                ">",
+                vec![overloaded(Overload::NUM_TXT)],
+                binary(Type::bool()),
+            ),
+            (
+                "<=",
+                vec![overloaded(Overload::NUM_TXT)],
+                binary(Type::bool()),
+            ),
+            (
+                ">=",
+                vec![overloaded(Overload::NUM_TXT)],
+                binary(Type::bool()),
+            ),
+            ("=", vec![gen(true)], binary(Type::bool())),
+            ("<>", vec![gen(true)], binary(Type::bool())),
+            (
+                "!",
+                vec![gen(false)],
+                Type::arrow(Type::con(REF, vec![a()]), a()),
+            ),
+            (
+                ":=",
+                vec![gen(false)],
+                Type::arrow(
+                    Type::tuple(vec![Type::con(REF, vec![a()]), a()]),
+                    Type::unit(),
+                ),
+            ),
+            (
+                "^",
+                vec![],
+                Type::arrow(Type::tuple(vec![string(), string()]), string()),
+            ),
+            (
+                "o",
+                vec![gen(false); 3],
+                Type::arrow(
+                    Type::tuple(vec![
+                        Type::arrow(Type::Gen(1), Type::Gen(2)),
+                        Type::arrow(Type::Gen(0), Type::Gen(1)),
+                    ]),
+                    Type::arrow(Type::Gen(0), Type::Gen(2)),
+                ),
+            ),
+            (
+                "before",
+                vec![gen(false)],
+                Type::arrow(Type::tuple(vec![a(), Type::unit()]), a()),
+            ),
+            ("print", vec![], Type::arrow(string(), Type::unit())),
+        ];
+        for (name, params, ty) in values {
+            self.scope().values.insert(
+                name.to_owned(),
+                ValueBinding {
+                    scheme: Scheme { params, ty },
+                    status: IdStatus::Var,
+                },
+            );
+        }
+    }
+
+    fn add_con(&mut self, name: &str, scheme: Scheme, kind: ConKind, has_arg: bool) {
+        if let ConKind::Datatype { tycon, .. } = &kind {
+            self.tycons[tycon.id as usize]
+                .cons
+                .push((name.to_owned(), has_arg));
+        }
+        let info = ConInfo {
+            name: name.to_owned(),
+            kind,
+            has_arg,
+        };
+        self.scope().values.insert(
+            name.to_owned(),
+            ValueBinding {
+                scheme,
+                status: IdStatus::Con(Rc::new(info)),
+            },
+        );
+    }
+
+    /// Check a program, adding its declarations to the environment. Returns
+    /// the new top level values, in order.
+    pub fn check_program(
+        &mut self,
+        program: &Program,
+    ) -> Result<Vec<(String, Scheme)>, Vec<Diagnostic>> {
+        let mut bound = Vec::new();
+        for item in &program.items {
+            let names = match item {
+                TopDec::Str(dec) => self.str_dec(dec),
+                TopDec::Exp(exp) => {
+                    let span = exp.span;
+                    self.enter();
+                    let ty = self.exp(exp);
+                    self.leave();
+                    let scheme = if self.is_nonexpansive(exp) {
+                        self.generalize(&ty, span)
+                    } else {
+                        self.weaken(&ty, span);
+                        Scheme::mono(ty)
+                    };
+                    self.bind_value("it", scheme, IdStatus::Var);
+                    vec!["it".to_owned()]
+                }
+                TopDec::Sig(binds) => {
+                    self.sig_dec(binds);
+                    Vec::new()
+                }
+                TopDec::Functor(binds) => {
+                    self.functor_dec(binds);
+                    Vec::new()
+                }
+            };
+            for name in names {
+                let scheme = self.scope().values[&name].scheme.clone();
+                bound.retain(|(n, _): &(String, Scheme)| *n != name);
+                bound.push((name, scheme));
+            }
+        }
+
+        for ty in self.info.types.values_mut() {
+            *ty = ty.zonk();
+        }
+        if self.diagnostics.is_empty() {
+            Ok(bound)
+        } else {
+            Err(std::mem::take(&mut self.diagnostics))
+        }
+    }
+
+    /// Look up a value in the environment.
+    pub fn lookup_value(&self, name: &str) -> Option<&ValueBinding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|env| env.values.get(name))
+    }
+
+    /// Look up a type constructor in the environment.
+    pub fn lookup_type(&self, name: &str) -> Option<&TyStr> {
+        self.scopes.iter().rev().find_map(|env| env.types.get(name))
+    }
+
+    /// Whether `ty` mentions an abstract type, so its values can't be shown.
+    pub fn is_abstract(&self, ty: &Type) -> bool {
+        let mut ids = Vec::new();
+        self.abstract_tycons(ty, &mut ids);
+        !ids.is_empty()
+    }
+
+    /// Show a scheme, with the abstract types in it named by where they're
+    /// bound, like `Set.set`.
+    pub fn show_scheme(&self, scheme: &Scheme) -> String {
+        let mut ids = Vec::new();
+        self.abstract_tycons(&scheme.ty, &mut ids);
+        let names = ids
+            .into_iter()
+            .filter_map(|id| Some((id, self.abstract_name(id)?)))
+            .collect();
+        scheme.show_with(names)
+    }
+
+    fn abstract_tycons(&self, ty: &Type, ids: &mut Vec<u32>) {
+        match ty.resolve() {
+            Type::Con(con, args) => {
+                if self.tycons[con.id as usize].opaque {
+                    ids.push(con.id);
+                }
+                for ty in &args {
+                    self.abstract_tycons(ty, ids);
+                }
+            }
+            Type::Record(fields) => {
+                for ty in fields.values() {
+                    self.abstract_tycons(ty, ids);
+                }
+            }
+            Type::Arrow(a, b) => {
+                self.abstract_tycons(&a, ids);
+                self.abstract_tycons(&b, ids);
+            }
+            Type::Var(_) | Type::Gen(_) => (),
+        }
+    }
+
+    /// The long name of the structure component an abstract type is bound
+    /// to.
+    fn abstract_name(&self, id: u32) -> Option<String> {
+        let mut envs: Vec<(String, &Env)> = Vec::new();
+        for scope in self.scopes.iter().rev() {
+            envs.extend(
+                scope
+                    .structures
+                    .iter()
+                    .map(|(name, env)| (name.clone(), env)),
+            );
+        }
+        // Breadth first, so the shortest name is found.
+        let mut next = 0;
+        while let Some((path, env)) = envs.get(next).cloned() {
+            next += 1;
+            for (name, tystr) in &env.types {
+                if matches!(&tystr.fcn.ty, Type::Con(con, _) if con.id == id) {
+                    return Some(format!("{}.{}", path, name));
+                }
+            }
+            envs.extend(
+                env.structures
+                    .iter()
+                    .map(|(name, env)| (format!("{}.{}", path, name), env)),
+            );
+        }
+        None
+    }
+
+    /// Bind a structure whose contents are described by an environment,
+    /// for structures implemented outside SML, like the Basis' primitives.
+    pub fn declare_structure(&mut self, name: &str, env: Env) {
+        self.scope().structures.insert(name.to_owned(), env);
+    }
+
+    // Helpers
+
+    fn scope(&mut self) -> &mut Env {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(Env::default());
+    }
+
+    fn pop_scope(&mut self) -> Env {
+        self.scopes.pop().unwrap()
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(span, message));
+    }
+
+    fn enter(&mut self) {
+        self.level += 1;
+    }
+
+    fn leave(&mut self) {
+        self.level -= 1;
+    }
+
+    fn new_var(&mut self, eq: bool, kind: VarKind) -> Type {
+        let id = self.next_var;
+        self.next_var += 1;
+        Type::Var(TypeVar(Rc::new(RefCell::new(VarState::Unbound(Unbound {
+            id,
+            level: self.level,
+            eq,
+            kind,
+        })))))
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.new_var(false, VarKind::Plain)
+    }
+
+    fn fresh_exn(&mut self) -> u32 {
+        let id = self.next_exn;
+        self.next_exn += 1;
+        id
+    }
+
+    fn bind_value(&mut self, name: &str, scheme: Scheme, status: IdStatus) {
+        self.scope()
+            .values
+            .insert(name.to_owned(), ValueBinding { scheme, status });
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        if scheme.params.is_empty() {
+            return scheme.ty.clone();
+        }
+        let args: Vec<_> = scheme
+            .params
+            .iter()
+            .map(|p| match p.overload {
+                Some(set) => self.new_var(p.eq, VarKind::Overload(set)),
+                None => self.new_var(p.eq, VarKind::Plain),
+            })
+            .collect();
+        scheme.ty.subst(&args)
+    }
+
+    /// Generalize the variables of `ty` that were created inside the current
+    /// level. Overloaded variables are defaulted instead, and unresolved
+    /// flexible records are an error.
+    fn generalize(&mut self, ty: &Type, span: Span) -> Scheme {
+        let mut params = Vec::new();
+        let mut ids = HashMap::new();
+        let ty = self.generalize_type(ty, span, &mut params, &mut ids);
+        Scheme { params, ty }
+    }
+
+    fn generalize_type(
+        &mut self,
+        ty: &Type,
+        span: Span,
+        params: &mut Vec<GenParam>,
+        ids: &mut HashMap<u32, u32>,
+    ) -> Type {
+        match ty.resolve() {
+            Type::Var(var) => {
+                let unbound = match &*var.0.borrow() {
+                    VarState::Unbound(u) => u.clone(),
+                    VarState::Bound(_) => unreachable!(),
+                };
+                if unbound.level <= self.level {
+                    return Type::Var(var);
+                }
+                match unbound.kind {
+                    VarKind::Overload(set) => {
+                        let default = set.default();
+                        *var.0.borrow_mut() = VarState::Bound(default.clone());
+                        default
+                    }
+                    VarKind::Record(fields) => {
+                        let ty = Type::Var(var.clone());
+                        self.error(span, format!("unresolved flexible record type `{}`", ty));
+                        // Close the record so the error isn't repeated.
+                        *var.0.borrow_mut() = VarState::Bound(Type::Record(fields));
+                        self.generalize_type(&ty, span, params, ids)
+                    }
+                    VarKind::Plain | VarKind::Rigid(_) => {
+                        let idx = *ids.entry(unbound.id).or_insert_with(|| {
+                            params.push(GenParam {
+                                eq: unbound.eq,
+                                overload: None,
+                            });
+                            params.len() as u32 - 1
+                        });
+                        Type::Gen(idx)
+                    }
+                }
+            }
+            Type::Gen(i) => Type::Gen(i),
+            Type::Con(con, args) => Type::Con(
+                con,
+                args.iter()
+                    .map(|t| self.generalize_type(t, span, params, ids))
+                    .collect(),
+            ),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(lab, t)| (lab.clone(), self.generalize_type(t, span, params, ids)))
+                    .collect(),
+            ),
+            Type::Arrow(a, b) => Type::arrow(
+                self.generalize_type(&a, span, params, ids),
+                self.generalize_type(&b, span, params, ids),
+            ),
+        }
+    }
+
+    /// Move the variables of a type that isn't being generalized out to the
+    /// current level, so an enclosing declaration doesn't generalize them
+    /// either. Overloads and records are still resolved.
+    fn weaken(&mut self, ty: &Type, span: Span) {
+        match ty.resolve() {
+            Type::Var(var) => {
+                let kind = match &mut *var.0.borrow_mut() {
+                    VarState::Unbound(u) if u.level > self.level => {
+                        u.level = self.level;
+                        u.kind.clone()
+                    }
+                    _ => return,
+                };
+                match kind {
+                    VarKind::Overload(set) => {
+                        *var.0.borrow_mut() = VarState::Bound(set.default());
+                    }
+                    VarKind::Record(fields) => {
+                        let ty = Type::Var(var.clone());
+                        self.error(span, format!("unresolved flexible record type `{}`", ty));
+                        *var.0.borrow_mut() = VarState::Bound(Type::Record(fields));
+                        self.weaken(&ty, span);
+                    }
+                    _ => (),
+                }
+            }
+            Type::Gen(_) => (),
+            Type::Con(_, args) => args.iter().for_each(|t| self.weaken(t, span)),
+            Type::Record(fields) => fields.values().for_each(|t| self.weaken(t, span)),
+            Type::Arrow(a, b) => {
+                self.weaken(&a, span);
+                self.weaken(&b, span);
+            }
+        }
+    }
+
+    // Unification
+
+    /// Unify two types, reporting an error at `span` if they don't match.
+    fn unify_at(&mut self, span: Span, expected: &Type, found: &Type) {
+        self.unify_arg(span, expected, found, None);
+    }
+
+    /// Unify two types like `unify_at`, naming the overloaded identifier
+    /// `op` and the types it's defined on in the error if they're the type
+    /// of its argument.
+    fn unify_arg(
+        &mut self,
+        span: Span,
+        expected: &Type,
+        found: &Type,
+        op: Option<(&LongId, Overload)>,
+    ) {
+        if let Err(err) = self.unify(expected, found) {
+            let mut namer = Namer::default();
+            let expected_s = namer.show(expected);
+            let found_s = namer.show(found);
+            let message = match (&err, op) {
+                (UnifyError::Circular(var, ty), _) => format!(
+                    "circular type: `{}` would have to contain itself in `{}`",
+                    namer.show(var),
+                    namer.show(ty)
+                ),
+                (UnifyError::NotOverloaded(ty), Some((op, set))) => format!(
+                    "`{}` is defined on {}, not `{}`",
+                    op,
+                    set.describe(),
+                    namer.show(ty)
+                ),
+                (_, Some((op, _))) => format!(
+                    "type mismatch in the argument of overloaded `{}`: expected `{}`, found `{}`",
+                    op, expected_s, found_s
+                ),
+                (_, None) => format!(
+                    "type mismatch: expected `{}`, found `{}`",
+                    expected_s, found_s
+                ),
+            };
+            let mut diag = Diagnostic::error(span, message);
+            let note = match err {
+                UnifyError::Mismatch(a, b) => {
+                    let (a, b) = (namer.show(&a), namer.show(&b));
+                    if a != expected_s || b != found_s {
+                        Some(format!("`{}` doesn't match `{}`", a, b))
+                    } else {
+                        None
+                    }
+                }
+                UnifyError::Circular(var, ty) => {
+                    let (var, ty) = (namer.show(&var), namer.show(&ty));
+                    if var != expected_s || ty != found_s {
+                        Some(format!("expected `{}`, found `{}`", expected_s, found_s))
+                    } else {
+                        None
+                    }
+                }
+                UnifyError::NotEquality(ty) => {
+                    Some(format!("`{}` doesn't admit equality", namer.show(&ty)))
+                }
+                UnifyError::NotOverloaded(ty) => match op {
+                    Some(_) => None,
+                    None => Some(format!(
+                        "the operator isn't defined for `{}`",
+                        namer.show(&ty)
+                    )),
+                },
+                UnifyError::MissingField(lab, ty) => {
+                    Some(format!("`{}` has no field `{}`", namer.show(&ty), lab))
+                }
+            };
+            if let Some(note) = note {
+                diag = diag.with_note(note);
+            }
+            self.diagnostics.push(diag);
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), UnifyError> {
+        let (a, b) = (a.resolve(), b.resolve());
+        match (&a, &b) {
+            (Type::Var(x), Type::Var(y)) if Rc::ptr_eq(&x.0, &y.0) => Ok(()),
+            (Type::Var(x), Type::Var(y)) => self.unify_vars(x, y, &a, &b),
+            (Type::Var(x), _) => self.bind_var(x, &b),
+            (_, Type::Var(y)) => self.bind_var(y, &a),
+            (Type::Con(c1, a1), Type::Con(c2, a2)) if c1.id == c2.id => {
+                for (x, y) in a1.iter().zip(a2) {
+                    self.unify(x, y)?;
+                }
+                Ok(())
+            }
+            (Type::Record(f1), Type::Record(f2))
+                if f1.len() == f2.len() && f1.keys().zip(f2.keys()).all(|(x, y)| x == y) =>
+            {
+                for (x, y) in f1.values().zip(f2.values()) {
+                    self.unify(x, y)?;
+                }
+                Ok(())
+            }
+            (Type::Arrow(a1, r1), Type::Arrow(a2, r2)) => {
+                self.unify(a1, a2)?;
+                self.unify(r1, r2)
+            }
+            _ => Err(UnifyError::Mismatch(a.clone(), b.clone())),
+        }
+    }
+
+    fn unbound(var: &TypeVar) -> Unbound {
+        match &*var.0.borrow() {
+            VarState::Unbound(u) => u.clone(),
+            VarState::Bound(_) => unreachable!("variable is resolved before unifying"),
+        }
+    }
+
+    /// Unify two distinct unbound variables, by binding one to the other
+    /// with the constraints of both.
+    fn unify_vars(
+        &mut self,
+        x: &TypeVar,
+        y: &TypeVar,
+        a: &Type,
+        b: &Type,
+    ) -> Result<(), UnifyError> {
+        let (ux, uy) = (Self::unbound(x), Self::unbound(y));
+        let eq = ux.eq || uy.eq;
+        let level = ux.level.min(uy.level);
+        let mismatch = || Err(UnifyError::Mismatch(a.clone(), b.clone()));
+
+        // The variable that's kept, and its new kind.
+        let (keep, other, kind) = match (&ux.kind, &uy.kind) {
+            (VarKind::Rigid(_), VarKind::Rigid(_)) => return mismatch(),
+            (VarKind::Rigid(_), VarKind::Plain) => {
+                if eq && !ux.eq {
+                    return Err(UnifyError::NotEquality(a.clone()));
+                }
+                (x, y, ux.kind.clone())
+            }
+            (VarKind::Plain, VarKind::Rigid(_)) => {
+                if eq && !uy.eq {
+                    return Err(UnifyError::NotEquality(b.clone()));
+                }
+                (y, x, uy.kind.clone())
+            }
+            (VarKind::Rigid(_), _) => return Err(UnifyError::NotOverloaded(a.clone())),
+            (_, VarKind::Rigid(_)) => return Err(UnifyError::NotOverloaded(b.clone())),
+            (VarKind::Overload(s1), VarKind::Overload(s2)) => match s1.intersect(*s2) {
+                Some(set) => (x, y, VarKind::Overload(set)),
+                None => return mismatch(),
+            },
+            (VarKind::Overload(_), VarKind::Plain) => (x, y, ux.kind.clone()),
+            (VarKind::Plain, VarKind::Overload(_)) => (y, x, uy.kind.clone()),
+            (VarKind::Overload(_), VarKind::Record(_))
+            | (VarKind::Record(_), VarKind::Overload(_)) => return mismatch(),
+            (VarKind::Record(f1), VarKind::Record(f2)) => {
+                let mut fields = f1.clone();
+                for (lab, ty) in f2 {
+                    match f1.get(lab) {
+                        Some(other) => self.unify(other, ty)?,
+                        None => {
+                            fields.insert(lab.clone(), ty.clone());
+                        }
+                    }
+                }
+                (x, y, VarKind::Record(fields))
+            }
+            (VarKind::Record(_), VarKind::Plain) => (x, y, ux.kind.clone()),
+            (VarKind::Plain, VarKind::Record(_)) => (y, x, uy.kind.clone()),
+            (VarKind::Plain, VarKind::Plain) => (x, y, VarKind::Plain),
+        };
+
+        let kind = match kind {
+            VarKind::Overload(set) if eq => match set.with_eq() {
+                Some(set) => VarKind::Overload(set),
+                None => return Err(UnifyError::NotEquality(a.clone())),
+            },
+            VarKind::Record(fields) => {
+                for ty in fields.values() {
+                    self.adjust(ty, level, eq, keep)?;
+                }
+                VarKind::Record(fields)
+            }
+            kind => kind,
+        };
+        let id = Self::unbound(keep).id;
+        *keep.0.borrow_mut() = VarState::Unbound(Unbound {
+            id,
+            level,
+            eq,
+            kind,
+        });
+        *other.0.borrow_mut() = VarState::Bound(Type::Var(keep.clone()));
+        Ok(())
+    }
+
+    /// Bind an unbound variable to a type that isn't a variable.
+    fn bind_var(&mut self, var: &TypeVar, ty: &Type) -> Result<(), UnifyError> {
+        let u = Self::unbound(var);
+        let var_ty = Type::Var(var.clone());
+        match &u.kind {
+            VarKind::Plain => (),
+            VarKind::Rigid(_) => return Err(UnifyError::Mismatch(var_ty, ty.clone())),
+            VarKind::Overload(set) => match ty {
+                Type::Con(con, _) if set.contains(con.id) => (),
+                _ => return Err(UnifyError::NotOverloaded(ty.clone())),
+            },
+            VarKind::Record(fields) => match ty {
+                Type::Record(actual) => {
+                    for (lab, field) in fields {
+                        match actual.get(lab) {
+                            Some(actual) => self.unify(field, actual)?,
+                            None => return Err(UnifyError::MissingField(lab.clone(), ty.clone())),
+                        }
+                    }
+                }
+                _ => return Err(UnifyError::Mismatch(var_ty, ty.clone())),
+            },
+        }
+        self.adjust(ty, u.level, u.eq, var)
+            .map_err(|err| match err {
+                UnifyError::Circular(_, _) => UnifyError::Circular(var_ty.clone(), ty.clone()),
+                err => err,
+            })?;
+        *var.0.borrow_mut() = VarState::Bound(ty.clone());
+        Ok(())
+    }
+
+    /// Prepare `ty` for being bound to `var`: check `var` doesn't occur in
+    /// it, lower the levels of its variables to `level`, and if `eq` is set
+    /// make sure it admits equality.
+    fn adjust(&mut self, ty: &Type, level: u32, eq: bool, var: &TypeVar) -> Result<(), UnifyError> {
+        match ty.resolve() {
+            Type::Var(v) => {
+                if Rc::ptr_eq(&v.0, &var.0) {
+                    return Err(UnifyError::Circular(Type::Var(var.clone()), ty.clone()));
+                }
+                let mut state = v.0.borrow_mut();
+                let u = match &mut *state {
+                    VarState::Unbound(u) => u,
+                    VarState::Bound(_) => unreachable!(),
+                };
+                u.level = u.level.min(level);
+                if eq && !u.eq {
+                    match &mut u.kind {
+                        VarKind::Rigid(_) => return Err(UnifyError::NotEquality(ty.clone())),
+                        VarKind::Overload(set) => match set.with_eq() {
+                            Some(eq_set) => *set = eq_set,
+                            None => return Err(UnifyError::NotEquality(ty.clone())),
+                        },
+                        _ => (),
+                    }
+                    u.eq = true;
+                }
+                let fields = match &u.kind {
+                    VarKind::Record(fields) => fields.clone(),
+                    _ => BTreeMap::new(),
+                };
+                drop(state);
+                for field in fields.values() {
+                    self.adjust(field, level, eq, var)?;
+                }
+                Ok(())
+            }
+            Type::Gen(_) => Ok(()),
+            Type::Con(con, args) => {
+                let eq_args = match self.tycons[con.id as usize].eq {
+                    _ if !eq => false,
+                    Equality::Always => false,
+                    Equality::IfArgs => true,
+                    Equality::Never => return Err(UnifyError::NotEquality(ty.clone())),
+                };
+                for arg in &args {
+                    self.adjust(arg, level, eq_args, var)?;
+                }
+                Ok(())
+            }
+            Type::Record(fields) => {
+                for field in fields.values() {
+                    self.adjust(field, level, eq, var)?;
+                }
+                Ok(())
+            }
+            Type::Arrow(a, b) => {
+                if eq {
+                    return Err(UnifyError::NotEquality(ty.clone()));
+                }
+                self.adjust(&a, level, false, var)?;
+                self.adjust(&b, level, false, var)
+            }
+        }
+    }
+
+    // Identifiers
+
+    /// Find the environment a long identifier's structure path refers to.
+    fn structure_env(&mut self, id: &LongId) -> Option<&Env> {
+        let (first, rest) = id.path.split_first()?;
+        let mut env = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|env| env.structures.get(first));
+        for strid in rest {
+            env = env.and_then(|env| env.structures.get(strid));
+        }
+        env
+    }
+
+    fn lookup_long_value(&mut self, id: &LongId) -> Option<ValueBinding> {
+        if !id.is_qualified() {
+            return self.lookup_value(&id.name).cloned();
+        }
+        match self.structure_env(id) {
+            Some(env) => env.values.get(&id.name).cloned(),
+            None => {
+                let path = id.path.join(".");
+                self.error(id.span, format!("unbound structure `{}`", path));
+                None
+            }
+        }
+    }
+
+    /// The identifier `exp` is, and the types it's defined on, if it's bound
+    /// to an overloaded value.
+    fn overloaded<'e>(&mut self, exp: &'e Exp) -> Option<(&'e LongId, Overload)> {
+        let id = match &exp.kind {
+            ExpKind::Var { id, .. } => id,
+            _ => return None,
+        };
+        let binding = if id.is_qualified() {
+            self.structure_env(id)?.values.get(&id.name)
+        } else {
+            self.lookup_value(&id.name)
+        }?;
+        let set = binding.scheme.params.iter().find_map(|p| p.overload)?;
+        Some((id, set))
+    }
+
+    fn lookup_long_type(&mut self, id: &LongId) -> Option<TyStr> {
+        if !id.is_qualified() {
+            return self.lookup_type(&id.name).cloned();
+        }
+        match self.structure_env(id) {
+            Some(env) => env.types.get(&id.name).cloned(),
+            None => {
+                let path = id.path.join(".");
+                self.error(id.span, format!("unbound structure `{}`", path));
+                None
+            }
+        }
+    }
+
+    /// Look up a constructor for a pattern.
+    fn lookup_con(&mut self, id: &LongId) -> Option<(Scheme, Rc<ConInfo>)> {
+        match self.lookup_long_value(id) {
+            Some(ValueBinding {
+                scheme,
+                status: IdStatus::Con(info),
+            }) => Some((scheme, info)),
+            _ => None,
+        }
+    }
+
+    // Types
+
+    /// Convert a type from the syntax. Type variables must be in scope.
+    fn ty(&mut self, ty: &Ty) -> Type {
+        match &ty.kind {
+            TyKind::Var(tyvar) => {
+                let found = self
+                    .tyvars
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.get(&tyvar.to_string()).cloned());
+                match found {
+                    Some(ty) => ty,
+                    None => {
+                        self.error(tyvar.span, format!("unbound type variable `{}`", tyvar));
+                        self.fresh()
+                    }
+                }
+            }
+            TyKind::Record(rows) => Type::Record(
+                rows.iter()
+                    .map(|(lab, ty)| (lab.clone(), self.ty(ty)))
+                    .collect(),
+            ),
+            TyKind::Tuple(tys) => Type::tuple(tys.iter().map(|t| self.ty(t)).collect()),
+            TyKind::Con(args, tycon) => {
+                let args: Vec<_> = args.iter().map(|t| self.ty(t)).collect();
+                match self.lookup_long_type(tycon) {
+                    Some(tystr) if tystr.fcn.arity == args.len() => tystr.fcn.apply(&args),
+                    Some(tystr) => {
+                        self.error(
+                            ty.span,
+                            format!(
+                                "type constructor `{}` takes {} argument(s), but was given {}",
+                                tycon,
+                                tystr.fcn.arity,
+                                args.len()
+                            ),
+                        );
+                        self.fresh()
+                    }
+                    None => {
+                        if !tycon.is_qualified() || self.structure_env(tycon).is_some() {
+                            self.error(tycon.span, format!("unbound type constructor `{}`", tycon));
+                        }
+                        self.fresh()
+                    }
+                }
+            }
+            TyKind::Arrow(a, b) => {
+                let a = self.ty(a);
+                let b = self.ty(b);
+                Type::arrow(a, b)
+            }
+        }
+    }
+
+    /// Bring the type variables of a declaration into scope as rigid
+    /// variables: the explicit ones, plus any in annotations that aren't
+    /// already in scope.
+    fn scope_tyvars(&mut self, explicit: &[TyVar], dec: &Dec) {
+        let mut names: Vec<TyVar> = explicit.to_vec();
+        let mut found = Vec::new();
+        tyvars_in_dec(dec, &mut found);
+        for tyvar in found {
+            let name = tyvar.to_string();
+            let in_scope = self.tyvars.iter().any(|scope| scope.contains_key(&name));
+            if !in_scope && !names.iter().any(|t| t.to_string() == name) {
+                names.push(tyvar);
+            }
+        }
+        let mut scope = HashMap::new();
+        for tyvar in names {
+            let ty = self.new_var(tyvar.eq, VarKind::Rigid(tyvar.name.clone()));
+            scope.insert(tyvar.to_string(), ty);
+        }
+        self.tyvars.push(scope);
+    }
+
+    // Expressions
+
+    fn is_nonexpansive(&self, exp: &Exp) -> bool {
+        match &exp.kind {
+            ExpKind::Const(_) | ExpKind::Var { .. } | ExpKind::Selector(_) | ExpKind::Fn(_) => true,
+            ExpKind::Record(rows) => rows.iter().all(|(_, e)| self.is_nonexpansive(e)),
+            ExpKind::Tuple(exps) | ExpKind::List(exps) => {
+                exps.iter().all(|e| self.is_nonexpansive(e))
+            }
+            ExpKind::Typed(exp, _) => self.is_nonexpansive(exp),
+            ExpKind::App(f, arg) => {
+                let is_con = match &f.kind {
+                    ExpKind::Var { id, .. } => self
+                        .info
+                        .cons
+                        .get(&id.span)
+                        .is_some_and(|con| con.name != "ref"),
+                    _ => false,
+                };
+                is_con && self.is_nonexpansive(arg)
+            }
+            _ => false,
+        }
+    }
+
+    fn constant(&mut self, c: &Const) -> Type {
+        let id = match c {
+            Const::Int(_) => INT,
+            Const::Word(_) => WORD,
+            Const::Real(_) => REAL,
+            Const::Char(_) => CHAR,
+            Const::String(_) => STRING,
+        };
+        Type::con(id, Vec::new())
+    }
+
+    fn exp(&mut self, exp: &Exp) -> Type {
+        match &exp.kind {
+            ExpKind::Const(c) => self.constant(c),
+            ExpKind::Var { id, .. } => match self.lookup_long_value(id) {
+                Some(binding) => {
+                    let ty = self.instantiate(&binding.scheme);
+                    if let IdStatus::Con(info) = binding.status {
+                        self.info.cons.insert(id.span, info);
+                    }
+                    self.info.types.insert(id.span, ty.clone());
+                    ty
+                }
+                None => {
+                    if !id.is_qualified() || self.structure_env(id).is_some() {
+                        self.error(id.span, format!("unbound variable or constructor `{}`", id));
+                    }
+                    self.fresh()
+                }
+            },
+            ExpKind::Selector(lab) => {
+                let field = self.fresh();
+                let mut fields = BTreeMap::new();
+                fields.insert(lab.clone(), field.clone());
+                let record = self.new_var(false, VarKind::Record(fields));
+                let ty = Type::arrow(record, field);
+                self.info.types.insert(exp.span, ty.clone());
+                ty
+            }
+            ExpKind::Record(rows) => Type::Record(
+                rows.iter()
+                    .map(|(lab, exp)| (lab.clone(), self.exp(exp)))
+                    .collect(),
+            ),
+            ExpKind::Tuple(exps) => Type::tuple(exps.iter().map(|e| self.exp(e)).collect()),
+            ExpKind::List(exps) => {
+                let elem = self.fresh();
+                for exp in exps {
+                    let ty = self.exp(exp);
+                    self.unify_at(exp.span, &elem, &ty);
+                }
+                Type::con(LIST, vec![elem])
+            }
+            ExpKind::Seq(exps) => {
+                let mut ty = Type::unit();
+                for exp in exps {
+                    ty = self.exp(exp);
+                }
+                ty
+            }
+            ExpKind::Let(decs, body) => {
+                self.push_scope();
+                for dec in decs {
+                    self.dec(dec);
+                }
+                let ty = self.exp(body);
+                self.pop_scope();
+                ty
+            }
+            ExpKind::Flat(_) => {
+                self.error(exp.span, "infix operators haven't been resolved");
+                self.fresh()
+            }
+            ExpKind::App(f, arg) => {
+                let f_ty = self.exp(f);
+                let arg_ty = self.exp(arg);
+                match f_ty.resolve() {
+                    Type::Arrow(param, res) => {
+                        let op = self.overloaded(f);
+                        self.unify_arg(arg.span, &param, &arg_ty, op);
+                        *res
+                    }
+                    Type::Var(_) => {
+                        let res = self.fresh();
+                        self.unify_at(f.span, &f_ty, &Type::arrow(arg_ty, res.clone()));
+                        res
+                    }
+                    ty => {
+                        self.error(
+                            f.span,
+                            format!("expression of type `{}` isn't a function", ty),
+                        );
+                        self.fresh()
+                    }
+                }
+            }
+            ExpKind::Typed(inner, ty) => {
+                let expected = self.ty(ty);
+                let found = self.exp(inner);
+                self.unify_at(inner.span, &expected, &found);
+                expected
+            }
+            ExpKind::Andalso(a, b) | ExpKind::Orelse(a, b) => {
+                for exp in [a, b] {
+                    let ty = self.exp(exp);
+                    self.unify_at(exp.span, &Type::bool(), &ty);
+                }
+                Type::bool()
+            }
+            ExpKind::Handle(body, rules) => {
+                let ty = self.exp(body);
+                self.mrules(rules, &Type::exn(), &ty);
+                ty
+            }
+            ExpKind::Raise(exn) => {
+                let ty = self.exp(exn);
+                self.unify_at(exn.span, &Type::exn(), &ty);
+                self.fresh()
+            }
+            ExpKind::If(c, t, e) => {
+                let c_ty = self.exp(c);
+                self.unify_at(c.span, &Type::bool(), &c_ty);
+                let t_ty = self.exp(t);
+                let e_ty = self.exp(e);
+                self.unify_at(e.span, &t_ty, &e_ty);
+                t_ty
+            }
+            ExpKind::While(c, body) => {
+                let c_ty = self.exp(c);
+                self.unify_at(c.span, &Type::bool(), &c_ty);
+                self.exp(body);
+                Type::unit()
+            }
+            ExpKind::Case(scrutinee, rules) => {
+                let arg = self.exp(scrutinee);
+                let res = self.fresh();
+                self.mrules(rules, &arg, &res);
+                res
+            }
+            ExpKind::Fn(rules) => {
+                let arg = self.fresh();
+                let res = self.fresh();
+                self.mrules(rules, &arg, &res);
+                Type::arrow(arg, res)
+            }
+        }
+    }
+
+    fn mrules(&mut self, rules: &[MRule], arg: &Type, res: &Type) {
+        for rule in rules {
+            self.push_scope();
+            let mut bindings = Bindings::new();
+            let pat_ty = self.pat(&rule.pat, &mut bindings);
+            self.unify_at(rule.pat.span, arg, &pat_ty);
+            self.bind_pattern_vars(bindings, |_, ty| Scheme::mono(ty));
+            let exp_ty = self.exp(&rule.exp);
+            self.unify_at(rule.exp.span, res, &exp_ty);
+            self.pop_scope();
+        }
+    }
+
+    // Patterns
+
+    /// Add the variables bound by a pattern to the current scope.
+    fn bind_pattern_vars(
+        &mut self,
+        bindings: Bindings,
+        mut scheme: impl FnMut(&mut Self, Type) -> Scheme,
+    ) {
+        for (name, (ty, _)) in bindings.vars {
+            let scheme = scheme(self, ty);
+            self.bind_value(&name, scheme, IdStatus::Var);
+        }
+    }
+
+    fn bind_pat_var(&mut self, id: &Id, bindings: &mut Bindings) -> Type {
+        let ty = self.fresh();
+        if bindings.vars.iter().any(|(name, _)| *name == id.name) {
+            self.error(
+                id.span,
+                format!("`{}` is bound more than once in the pattern", id),
+            );
+        } else {
+            bindings.vars.push((id.name.clone(), (ty.clone(), id.span)));
+        }
+        self.info.types.insert(id.span, ty.clone());
+        ty
+    }
+
+    fn pat(&mut self, pat: &Pat, bindings: &mut Bindings) -> Type {
+        match &pat.kind {
+            PatKind::Wildcard => self.fresh(),
+            PatKind::Const(c) => {
+                let ty = self.constant(c);
+                // Constants are compared with `=`.
+                if let Const::Real(_) = c {
+                    self.error(pat.span, "real constants can't be used in patterns");
+                }
+                ty
+            }
+            PatKind::Var { id, .. } => {
+                let con = if id.is_qualified() {
+                    let con = self.lookup_con(id);
+                    if con.is_none() && self.structure_env(id).is_some() {
+                        self.error(id.span, format!("unbound constructor `{}`", id));
+                    }
+                    con
+                } else {
+                    self.lookup_con(id)
+                };
+                match con {
+                    Some((scheme, info)) => {
+                        if info.has_arg {
+                            self.error(
+                                id.span,
+                                format!("constructor `{}` needs an argument in a pattern", id),
+                            );
+                        }
+                        self.info.cons.insert(id.span, info);
+                        self.instantiate(&scheme)
+                    }
+                    None if id.is_qualified() => self.fresh(),
+                    None => self.bind_pat_var(
+                        &Id {
+                            name: id.name.clone(),
+                            span: id.span,
+                        },
+                        bindings,
+                    ),
+                }
+            }
+            PatKind::Record { rows, flexible } => {
+                let fields: BTreeMap<_, _> = rows
+                    .iter()
+                    .map(|(lab, pat)| (lab.clone(), self.pat(pat, bindings)))
+                    .collect();
+                if *flexible {
+                    let ty = self.new_var(false, VarKind::Record(fields));
+                    self.info.types.insert(pat.span, ty.clone());
+                    ty
+                } else {
+                    Type::Record(fields)
+                }
+            }
+            PatKind::Tuple(pats) => {
+                Type::tuple(pats.iter().map(|p| self.pat(p, bindings)).collect())
+            }
+            PatKind::List(pats) => {
+                let elem = self.fresh();
+                for pat in pats {
+                    let ty = self.pat(pat, bindings);
+                    self.unify_at(pat.span, &elem, &ty);
+                }
+                Type::con(LIST, vec![elem])
+            }
+            PatKind::Flat(_) => {
+                self.error(pat.span, "infix operators haven't been resolved");
+                self.fresh()
+            }
+            PatKind::Con(id, arg) => {
+                let arg_ty = self.pat(arg, bindings);
+                match self.lookup_con(id) {
+                    Some((scheme, info)) => {
+                        self.info.cons.insert(id.span, info.clone());
+                        let ty = self.instantiate(&scheme);
+                        match ty {
+                            Type::Arrow(param, res) if info.has_arg => {
+                                self.unify_at(arg.span, &param, &arg_ty);
+                                *res
+                            }
+                            ty => {
+                                self.error(
+                                    id.span,
+                                    format!("constructor `{}` doesn't take an argument", id),
+                                );
+                                ty
+                            }
+                        }
+                    }
+                    None => {
+                        self.error(id.span, format!("`{}` isn't a constructor", id));
+                        self.fresh()
+                    }
+                }
+            }
+            PatKind::Typed(inner, ty) => {
+                let expected = self.ty(ty);
+                let found = self.pat(inner, bindings);
+                self.unify_at(inner.span, &expected, &found);
+                expected
+            }
+            PatKind::Layered { id, ty, pat: inner } => {
+                if self.lookup_con(&id.clone().into()).is_some() {
+                    self.error(
+                        id.span,
+                        format!("constructor `{}` can't be bound with `as`", id),
+                    );
+                }
+                let var = self.bind_pat_var(id, bindings);
+                if let Some(ty) = ty {
+                    let expected = self.ty(ty);
+                    self.unify_at(id.span, &expected, &var);
+                }
+                let inner_ty = self.pat(inner, bindings);
+                self.unify_at(inner.span, &var, &inner_ty);
+                var
+            }
+        }
+    }
+
+    // Declarations
+
+    fn str_dec(&mut self, dec: &StrDec) -> Vec<String> {
+        match &dec.kind {
+            StrDecKind::Dec(dec) => self.dec(dec),
+            StrDecKind::Structure(binds) => {
+                self.structure_dec(binds);
+                Vec::new()
+            }
+            StrDecKind::Local(local, body) => {
+                self.push_scope();
+                for dec in local {
+                    self.str_dec(dec);
+                }
+                self.push_scope();
+                let mut names = Vec::new();
+                for dec in body {
+                    names.extend(self.str_dec(dec));
+                }
+                let body = self.pop_scope();
+                self.pop_scope();
+                self.scope().extend(body);
+                names
+            }
+        }
+    }
+
+    /// Check a declaration, adding its bindings to the current scope.
+    /// Returns the names of the values it binds.
+    fn dec(&mut self, dec: &Dec) -> Vec<String> {
+        match &dec.kind {
+            DecKind::Val { tyvars, binds } => {
+                self.scope_tyvars(tyvars, dec);
+                let names = self.val_dec(binds);
+                self.tyvars.pop();
+                names
+            }
+            DecKind::Fun { tyvars, binds } => {
+                self.scope_tyvars(tyvars, dec);
+                let names = self.fun_dec(binds);
+                self.tyvars.pop();
+                names
+            }
+            DecKind::Type(binds) => {
+                for bind in binds {
+                    let tystr = self.typbind(bind);
+                    self.scope().types.insert(bind.tycon.name.clone(), tystr);
+                }
+                Vec::new()
+            }
+            DecKind::Datatype { binds, withtype } => self.datatype_dec(binds, withtype),
+            DecKind::DatatypeRepl(tycon, from) => self.datatype_repl(tycon, from),
+            DecKind::Abstype {
+                binds,
+                withtype,
+                body,
+            } => {
+                self.push_scope();
+                self.datatype_dec(binds, withtype);
+                let types = self.scope().clone().types;
+                self.push_scope();
+                let mut names = Vec::new();
+                for dec in body {
+                    names.extend(self.dec(dec));
+                }
+                let body_env = self.pop_scope();
+                self.pop_scope();
+                // Outside, the types are abstract: no constructors, no
+                // equality, and no looking at values.
+                for bind in binds {
+                    let mut tystr = types[&bind.tycon.name].clone();
+                    tystr.cons.clear();
+                    if let Type::Con(con, _) = &tystr.fcn.ty {
+                        let info = &mut self.tycons[con.id as usize];
+                        info.eq = Equality::Never;
+                        info.opaque = true;
+                    }
+                    self.scope().types.insert(bind.tycon.name.clone(), tystr);
+                }
+                for bind in withtype {
+                    let tystr = types[&bind.tycon.name].clone();
+                    self.scope().types.insert(bind.tycon.name.clone(), tystr);
+                }
+                self.scope().extend(body_env);
+                names
+            }
+            DecKind::Exception(binds) => {
+                let mut names = Vec::new();
+                for bind in binds {
+                    match bind {
+                        ExBind::New { id, arg, .. } => {
+                            self.tyvars.push(HashMap::new());
+                            let (ty, has_arg) = match arg {
+                                Some(arg) => (Type::arrow(self.ty(arg), Type::exn()), true),
+                                None => (Type::exn(), false),
+                            };
+                            self.tyvars.pop();
+                            let exn = self.fresh_exn();
+                            self.add_con(&id.name, Scheme::mono(ty), ConKind::Exn(exn), has_arg);
+                        }
+                        ExBind::Copy { id, from, .. } => match self.lookup_long_value(from) {
+                            Some(
+                                binding @ ValueBinding {
+                                    status: IdStatus::Con(_),
+                                    ..
+                                },
+                            ) if matches!(&binding.status, IdStatus::Con(c) if matches!(c.kind, ConKind::Exn(_))) =>
+                            {
+                                self.scope().values.insert(id.name.clone(), binding);
+                            }
+                            _ => {
+                                self.error(from.span, format!("`{}` isn't an exception", from));
+                                continue;
+                            }
+                        },
+                    }
+                    let id = match bind {
+                        ExBind::New { id, .. } | ExBind::Copy { id, .. } => id,
+                    };
+                    names.push(id.name.clone());
+                }
+                names
+            }
+            DecKind::Local(local, body) => {
+                self.push_scope();
+                for dec in local {
+                    self.dec(dec);
+                }
+                self.push_scope();
+                let mut names = Vec::new();
+                for dec in body {
+                    names.extend(self.dec(dec));
+                }
+                let body = self.pop_scope();
+                self.pop_scope();
+                self.scope().extend(body);
+                names
+            }
+            DecKind::Open(strids) => {
+                let mut names = Vec::new();
+                for strid in strids {
+                    let mut path = strid.path.clone();
+                    path.push(strid.name.clone());
+                    let id = LongId {
+                        path,
+                        name: String::new(),
+                        span: strid.span,
+                    };
+                    match self.structure_env(&id).cloned() {
+                        Some(env) => {
+                            names.extend(env.values.keys().cloned());
+                            self.scope().extend(env);
+                        }
+                        None => self.error(strid.span, format!("unbound structure `{}`", strid)),
+                    }
+                }
+                names
+            }
+            DecKind::Fixity(_, _) => Vec::new(),
+        }
+    }
+
+    fn val_dec(&mut self, binds: &[ValBind]) -> Vec<String> {
+        // Everything after `rec` is recursive.
+        let rec_from = binds.iter().position(|b| b.rec).unwrap_or(binds.len());
+        let mut names = Vec::new();
+
+        self.enter();
+        let mut plain = Vec::new();
+        for bind in &binds[..rec_from] {
+            let exp_ty = self.exp(&bind.exp);
+            let mut bindings = Bindings::new();
+            let pat_ty = self.pat(&bind.pat, &mut bindings);
+            self.unify_at(bind.pat.span, &pat_ty, &exp_ty);
+            plain.push((bindings, self.is_nonexpansive(&bind.exp), bind.exp.span));
+        }
+
+        // Recursive bindings are all visible in each other's bodies, with
+        // monomorphic types.
+        let mut recursive = Vec::new();
+        if rec_from < binds.len() {
+            self.push_scope();
+            let mut pat_tys = Vec::new();
+            for bind in &binds[rec_from..] {
+                let mut bindings = Bindings::new();
+                let pat_ty = self.pat(&bind.pat, &mut bindings);
+                for (name, (ty, _)) in &bindings.vars {
+                    self.bind_value(name, Scheme::mono(ty.clone()), IdStatus::Var);
+                }
+                pat_tys.push(pat_ty);
+                recursive.push((bindings, true, bind.exp.span));
+            }
+            for (bind, pat_ty) in binds[rec_from..].iter().zip(&pat_tys) {
+                let exp_ty = self.exp(&bind.exp);
+                self.unify_at(bind.exp.span, pat_ty, &exp_ty);
+            }
+            self.pop_scope();
+        }
+        self.leave();
+
+        for (bindings, nonexpansive, span) in plain.into_iter().chain(recursive) {
+            names.extend(bindings.vars.iter().map(|(name, _)| name.clone()));
+            self.bind_pattern_vars(bindings, |checker, ty| {
+                if nonexpansive {
+                    checker.generalize(&ty, span)
+                } else {
+                    checker.weaken(&ty, span);
+                    Scheme::mono(ty)
+                }
+            });
+        }
+        names
+    }
+
+    fn fun_dec(&mut self, binds: &[FunBind]) -> Vec<String> {
+        let mut names = Vec::new();
+        self.enter();
+        self.push_scope();
+        let mut fn_tys = Vec::new();
+        for bind in binds {
+            let name = match &bind.clauses[0].name {
+                Some(name) => name.clone(),
+                None => {
+                    self.error(bind.span, "infix operators haven't been resolved");
+                    return names;
+                }
+            };
+            let ty = self.fresh();
+            self.info.types.insert(name.span, ty.clone());
+            self.bind_value(&name.name, Scheme::mono(ty.clone()), IdStatus::Var);
+            fn_tys.push((name, ty));
+        }
+
+        for (bind, (_, fn_ty)) in binds.iter().zip(&fn_tys) {
+            let arity = bind.clauses[0].args.len();
+            let params: Vec<_> = (0..arity).map(|_| self.fresh()).collect();
+            let res = self.fresh();
+            for clause in &bind.clauses {
+                self.push_scope();
+                let mut bindings = Bindings::new();
+                for (arg, param) in clause.args.iter().zip(&params) {
+                    let ty = self.pat(arg, &mut bindings);
+                    self.unify_at(arg.span, param, &ty);
+                }
+                self.bind_pattern_vars(bindings, |_, ty| Scheme::mono(ty));
+                if let Some(ty) = &clause.ty {
+                    let expected = self.ty(ty);
+                    self.unify_at(ty.span, &res, &expected);
+                }
+                let body = self.exp(&clause.body);
+                self.unify_at(clause.body.span, &res, &body);
+                self.pop_scope();
+            }
+            let ty = params
+                .into_iter()
+                .rev()
+                .fold(res, |res, param| Type::arrow(param, res));
+            self.unify_at(bind.span, fn_ty, &ty);
+        }
+        self.pop_scope();
+        self.leave();
+
+        for ((name, ty), bind) in fn_tys.into_iter().zip(binds) {
+            let scheme = self.generalize(&ty, bind.span);
+            self.bind_value(&name.name, scheme, IdStatus::Var);
+            names.push(name.name);
+        }
+        names
+    }
+
+    /// A type function from a binding with parameters, e.g. `'a t = 'a list`.
+    fn typbind(&mut self, bind: &TypBind) -> TyStr {
+        TyStr {
+            fcn: self.tyfcn(&bind.tyvars, &bind.ty),
+            cons: Vec::new(),
+        }
+    }
+
+    fn tyfcn(&mut self, tyvars: &[TyVar], ty: &Ty) -> TyFcn {
+        let scope = tyvars
+            .iter()
+            .enumerate()
+            .map(|(i, tyvar)| (tyvar.to_string(), Type::Gen(i as u32)))
+            .collect();
+        // Type variables from an enclosing declaration aren't in scope.
+        let saved = std::mem::replace(&mut self.tyvars, vec![scope]);
+        let ty = self.ty(ty);
+        self.tyvars = saved;
+        TyFcn {
+            arity: tyvars.len(),
+            ty,
+        }
+    }
+
+    /// `datatype tycon = datatype from`, which copies the type and its
+    /// constructors.
+    fn datatype_repl(&mut self, tycon: &Id, from: &LongId) -> Vec<String> {
+        match self.lookup_long_type(from) {
+            Some(tystr) => {
+                let mut names = Vec::new();
+                for con in &tystr.cons {
+                    let mut id = from.clone();
+                    id.name = con.clone();
+                    if let Some(binding) = self.lookup_long_value(&id) {
+                        self.scope().values.insert(con.clone(), binding);
+                        names.push(con.clone());
+                    }
+                }
+                self.scope().types.insert(tycon.name.clone(), tystr);
+                names
+            }
+            None => {
+                self.error(from.span, format!("unbound type constructor `{}`", from));
+                Vec::new()
+            }
+        }
+    }
+
+    fn datatype_dec(&mut self, binds: &[DatBind], withtype: &[TypBind]) -> Vec<String> {
+        // Add the new type constructors first, since they can be recursive.
+        let mut tycons = Vec::new();
+        for bind in binds {
+            let id = self.tycons.len() as u32;
+            let tycon = TyCon {
+                id,
+                name: Rc::from(bind.tycon.name.as_str()),
+            };
+            self.tycons.push(TyConInfo {
+                name: tycon.name.clone(),
+                arity: bind.tyvars.len(),
+                eq: Equality::IfArgs,
+                cons: Vec::new(),
+                opaque: false,
+            });
+            let args = (0..bind.tyvars.len() as u32).map(Type::Gen).collect();
+            self.scope().types.insert(
+                bind.tycon.name.clone(),
+                TyStr {
+                    fcn: TyFcn {
+                        arity: bind.tyvars.len(),
+                        ty: Type::Con(tycon.clone(), args),
+                    },
+                    cons: bind.cons.iter().map(|c| c.id.name.clone()).collect(),
+                },
+            );
+            tycons.push(tycon);
+        }
+        for bind in withtype {
+            let tystr = self.typbind(bind);
+            self.scope().types.insert(bind.tycon.name.clone(), tystr);
+        }
+
+        let mut names = Vec::new();
+        let mut con_args = Vec::new();
+        for (bind, tycon) in binds.iter().zip(&tycons) {
+            let scope = bind
+                .tyvars
+                .iter()
+                .enumerate()
+                .map(|(i, tyvar)| (tyvar.to_string(), Type::Gen(i as u32)))
+                .collect();
+            let saved = std::mem::replace(&mut self.tyvars, vec![scope]);
+            let res = Type::Con(
+                tycon.clone(),
+                (0..bind.tyvars.len() as u32).map(Type::Gen).collect(),
+            );
+            let params: Vec<_> = bind
+                .tyvars
+                .iter()
+                .map(|t| GenParam {
+                    eq: t.eq,
+                    overload: None,
+                })
+                .collect();
+            let mut seen = HashSet::new();
+            for (tag, con) in bind.cons.iter().enumerate() {
+                if !seen.insert(&con.id.name) {
+                    self.error(con.id.span, format!("duplicate constructor `{}`", con.id));
+                }
+                let arg = con.arg.as_ref().map(|ty| self.ty(ty));
+                let ty = match &arg {
+                    Some(arg) => Type::arrow(arg.clone(), res.clone()),
+                    None => res.clone(),
+                };
+                if let Some(arg) = arg {
+                    con_args.push((tycon.id, arg));
+                }
+                self.add_con(
+                    &con.id.name,
+                    Scheme {
+                        params: params.clone(),
+                        ty,
+                    },
+                    ConKind::Datatype {
+                        tycon: tycon.clone(),
+                        tag,
+                    },
+                    con.arg.is_some(),
+                );
+                names.push(con.id.name.clone());
+            }
+            self.tyvars = saved;
+        }
+
+        // A datatype admits equality if all its constructors' arguments do,
+        // assuming the datatypes being declared do. Remove datatypes until
+        // that holds.
+        loop {
+            let mut changed = false;
+            for (id, arg) in &con_args {
+                if self.tycons[*id as usize].eq != Equality::Never && !self.admits_eq(arg) {
+                    self.tycons[*id as usize].eq = Equality::Never;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        names
+    }
+
+    /// Whether a type from a datatype declaration admits equality, treating
+    /// its parameters as equality type variables.
+    fn admits_eq(&self, ty: &Type) -> bool {
+        match ty.resolve() {
+            Type::Gen(_) => true,
+            Type::Var(var) => match &*var.0.borrow() {
+                VarState::Unbound(u) => u.eq,
+                VarState::Bound(_) => unreachable!(),
+            },
+            Type::Con(con, args) => match self.tycons[con.id as usize].eq {
+                Equality::Always => true,
+                Equality::IfArgs => args.iter().all(|t| self.admits_eq(t)),
+                Equality::Never => false,
+            },
+            Type::Record(fields) => fields.values().all(|t| self.admits_eq(t)),
+            Type::Arrow(_, _) => false,
+        }
+    }
+}
+
+/// Variables bound by a pattern, in order.
+struct Bindings {
+    vars: Vec<(String, (Type, Span))>,
+}
+
+impl Bindings {
+    fn new() -> Self {
+        Bindings { vars: Vec::new() }
+    }
+}
+
+/// Collect the explicit type variables in annotations in a declaration.
+fn tyvars_in_dec(dec: &Dec, out: &mut Vec<TyVar>) {
+    match &dec.kind {
+        DecKind::Val { binds, .. } => {
+            for bind in binds {
+                tyvars_in_pat(&bind.pat, out);
+                tyvars_in_exp(&bind.exp, out);
+            }
+        }
+        DecKind::Fun { binds, .. } => {
+            for clause in binds.iter().flat_map(|b| &b.clauses) {
+                clause.args.iter().for_each(|p| tyvars_in_pat(p, out));
+                if let Some(ty) = &clause.ty {
+                    tyvars_in_ty(ty, out);
+                }
+                tyvars_in_exp(&clause.body, out);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn tyvars_in_ty(ty: &Ty, out: &mut Vec<TyVar>) {
+    match &ty.kind {
+        TyKind::Var(tyvar) => out.push(tyvar.clone()),
+        TyKind::Record(rows) => rows.iter().for_each(|(_, t)| tyvars_in_ty(t, out)),
+        TyKind::Tuple(tys) | TyKind::Con(tys, _) => tys.iter().for_each(|t| tyvars_in_ty(t, out)),
+        TyKind::Arrow(a, b) => {
+            tyvars_in_ty(a, out);
+            tyvars_in_ty(b, out);
+        }
+    }
+}
+
+fn tyvars_in_pat(pat: &Pat, out: &mut Vec<TyVar>) {
+    match &pat.kind {
+        PatKind::Record { rows, .. } => rows.iter().for_each(|(_, p)| tyvars_in_pat(p, out)),
+        PatKind::Tuple(pats) | PatKind::List(pats) | PatKind::Flat(pats) => {
+            pats.iter().for_each(|p| tyvars_in_pat(p, out))
+        }
+        PatKind::Con(_, pat) => tyvars_in_pat(pat, out),
+        PatKind::Typed(pat, ty) => {
+            tyvars_in_pat(pat, out);
+            tyvars_in_ty(ty, out);
+        }
+        PatKind::Layered { ty, pat, .. } => {
+            if let Some(ty) = ty {
+                tyvars_in_ty(ty, out);
+            }
+            tyvars_in_pat(pat, out);
+        }
+        PatKind::Wildcard | PatKind::Const(_) | PatKind::Var { .. } => (),
+    }
+}
+
+/// Type variables in nested value declarations are scoped there, so this
+/// doesn't look inside `let`.
+fn tyvars_in_exp(exp: &Exp, out: &mut Vec<TyVar>) {
+    match &exp.kind {
+        ExpKind::Record(rows) => rows.iter().for_each(|(_, e)| tyvars_in_exp(e, out)),
+        ExpKind::Tuple(exps) | ExpKind::List(exps) | ExpKind::Seq(exps) | ExpKind::Flat(exps) => {
+            exps.iter().for_each(|e| tyvars_in_exp(e, out))
+        }
+        ExpKind::App(a, b)
+        | ExpKind::Andalso(a, b)
+        | ExpKind::Orelse(a, b)
+        | ExpKind::While(a, b) => {
+            tyvars_in_exp(a, out);
+            tyvars_in_exp(b, out);
+        }
+        ExpKind::Typed(exp, ty) => {
+            tyvars_in_exp(exp, out);
+            tyvars_in_ty(ty, out);
+        }
+        ExpKind::Handle(exp, rules) | ExpKind::Case(exp, rules) => {
+            tyvars_in_exp(exp, out);
+            for rule in rules {
+                tyvars_in_pat(&rule.pat, out);
+                tyvars_in_exp(&rule.exp, out);
+            }
+        }
+        ExpKind::Fn(rules) => {
+            for rule in rules {
+                tyvars_in_pat(&rule.pat, out);
+                tyvars_in_exp(&rule.exp, out);
+            }
+        }
+        ExpKind::Raise(exp) => tyvars_in_exp(exp, out),
+        ExpKind::If(a, b, c) => {
+            tyvars_in_exp(a, out);
+            tyvars_in_exp(b, out);
+            tyvars_in_exp(c, out);
+        }
+        ExpKind::Const(_) | ExpKind::Var { .. } | ExpKind::Selector(_) | ExpKind::Let(_, _) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parsegen::SourceMap;
+
+    /// Check a program, returning the new bindings as `name : type`, or the
+    /// error messages.
+    fn infer(src: &str) -> Result<Vec<String>, Vec<String>> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        crate::fixity::resolve(&mut program).unwrap();
+        match Checker::new().check_program(&program) {
+            Ok(bindings) => Ok(bindings
+                .into_iter()
+                .map(|(name, scheme)| format!("{} : {}", name, scheme))
+                .collect()),
+            Err(diags) => Err(diags.into_iter().map(|d| d.message).collect()),
+        }
+    }
+
+    fn notes(src: &str) -> Vec<String> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        crate::fixity::resolve(&mut program).unwrap();
+        let diags = Checker::new().check_program(&program).unwrap_err();
+        diags.into_iter().flat_map(|d| d.notes).collect()
+    }
+
+    #[test]
+    fn let_polymorphism() {
+        assert_eq!(
+            infer(
+                "fun id x = x
+                 val pair = (id 1, id true)
+                 fun compose (f, g) x = f (g x)
+                 val it = let val k = fn x => fn _ => x in (k 1 2, k \"a\" 3.0) end"
+            ),
+            Ok(vec![
+                "id : 'a -> 'a".to_owned(),
+                "pair : int * bool".to_owned(),
+                "compose : ('a -> 'b) * ('c -> 'a) -> 'c -> 'b".to_owned(),
+                "it : int * string".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn value_restriction() {
+        assert_eq!(
+            infer("val f = (fn x => x) (fn y => y) val a = f 1"),
+            // `f` isn't generalized, so its use fixes its type.
+            Ok(vec!["f : int -> int".to_owned(), "a : int".to_owned()])
+        );
+        assert_eq!(
+            infer("val r = ref [] val _ = r := [1] val _ = r := [true]"),
+            Err(vec![
+                "type mismatch: expected `int list ref * int list`, found `int list ref * bool list`"
+                    .to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("val xs = [fn x => x] val ys = SOME []"),
+            Ok(vec![
+                "xs : ('a -> 'a) list".to_owned(),
+                "ys : 'a list option".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn equality_types() {
+        assert_eq!(
+            infer("fun member (x, []) = false | member (x, y :: ys) = x = y orelse member (x, ys)"),
+            Ok(vec!["member : ''a * ''a list -> bool".to_owned()])
+        );
+        assert_eq!(
+            infer("fun same (f : int -> int) = f = f"),
+            Err(vec![
+                "type mismatch: expected `''_a * ''_a`, found `(int -> int) * (int -> int)`"
+                    .to_owned()
+            ])
+        );
+        assert_eq!(
+            notes("val b = 1.0 = 2.0"),
+            vec!["`real` doesn't admit equality".to_owned()]
+        );
+        assert_eq!(
+            infer(
+                "datatype t = A of int | B of t list
+                 datatype u = F of int -> int
+                 val x = A 1 = B []"
+            ),
+            Ok(vec![
+                "A : int -> t".to_owned(),
+                "B : t list -> t".to_owned(),
+                "F : (int -> int) -> u".to_owned(),
+                "x : bool".to_owned()
+            ])
+        );
+        assert!(infer("datatype u = F of int -> int val x = F abs = F abs").is_err());
+    }
+
+    #[test]
+    fn overloading() {
+        assert_eq!(
+            infer(
+                "fun add (x, y) = x + y
+                 fun addr (x, y) = x + y : real
+                 fun less (a : string, b) = a < b
+                 val w = 0w1 + 0w2"
+            ),
+            Ok(vec![
+                "add : int * int -> int".to_owned(),
+                "addr : real * real -> real".to_owned(),
+                "less : string * string -> bool".to_owned(),
+                "w : word".to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("val s = \"a\" + \"b\""),
+            Err(vec![
+                "`+` is defined on int, word and real, not `string`".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn flexible_records() {
+        assert_eq!(
+            infer(
+                "fun getx ({x, ...} : {x : int, y : bool}) = x
+                 val one = #1 (1, true)
+                 val p = {x = 1, y = \"a\"}
+                 val y = let val {y, ...} = p in y end"
+            ),
+            Ok(vec![
+                "getx : {x : int, y : bool} -> int".to_owned(),
+                "one : int".to_owned(),
+                "p : {x : int, y : string}".to_owned(),
+                "y : string".to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("fun getx {x, ...} = x"),
+            Err(vec![
+                "unresolved flexible record type `{x : '_a, ...}`".to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("val f = #z : {x : int} -> int"),
+            Err(vec![
+                "type mismatch: expected `{x : int} -> int`, found `{z : '_a, ...} -> '_a`"
+                    .to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn datatypes_and_exceptions() {
+        assert_eq!(
+            infer(
+                "datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree
+                 fun size Leaf = 0 | size (Node (l, _, r)) = size l + 1 + size r
+                 exception Empty of string
+                 fun safe f x = f x handle Empty s => 0"
+            ),
+            Ok(vec![
+                "Leaf : 'a tree".to_owned(),
+                "Node : 'a tree * 'a * 'a tree -> 'a tree".to_owned(),
+                "size : 'a tree -> int".to_owned(),
+                "Empty : string -> exn".to_owned(),
+                "safe : ('a -> int) -> 'a -> int".to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("fun f (SOME x) = x | f NONE = raise Fail \"none\" type 'a pair = 'a * 'a val p : int pair = (1, 2)"),
+            Ok(vec!["f : 'a option -> 'a".to_owned(), "p : int * int".to_owned()])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            infer("val x = 1 + true"),
+            Err(vec![
+                "type mismatch in the argument of overloaded `+`: expected `int * int`, found `int * bool`"
+                    .to_owned()
+            ])
+        );
+        assert_eq!(
+            notes("val x = 1 + true"),
+            vec!["`int` doesn't match `bool`".to_owned()]
+        );
+        assert_eq!(
+            infer("val x = \"a\" < 1"),
+            Err(vec![
+                "type mismatch in the argument of overloaded `<`: expected `string * string`, found `string * int`"
+                    .to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("fun f (x : 'a) = x div x"),
+            Err(vec!["`div` is defined on int and word, not `'a`".to_owned()])
+        );
+        assert_eq!(
+            infer("fun f x = f"),
+            Err(vec![
+                "circular type: `'_a` would have to contain itself in `'_b -> '_a`".to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("fun f x = f x x"),
+            Err(vec![
+                "circular type: `'_b` would have to contain itself in `'_a -> '_b`".to_owned()
+            ])
+        );
+        assert_eq!(
+            infer("val f = fn (x : 'a) => (x : int)"),
+            Err(vec!["type mismatch: expected `int`, found `'a`".to_owned()])
+        );
+        assert_eq!(
+            infer("val x = y val z : t = 1"),
+            Err(vec![
+                "unbound variable or constructor `y`".to_owned(),
+                "unbound type constructor `t`".to_owned()
+            ])
+        );
+    }
+}
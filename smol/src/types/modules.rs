@@ -0,0 +1,1298 @@
+//! Structures, signatures and functors.
+//!
+//! A signature is elaborated to an environment in which each type
+//! specification without a definition is a new, *flexible* type constructor.
+//! Matching a structure against a signature first finds the structure's type
+//! for each flexible constructor (a realisation), then checks the structure
+//! has every component of the realised signature, with the same type or a
+//! more general one.
+//!
+//! Transparent ascription gives the structure the realised signature's
+//! environment. Opaque ascription keeps the flexible types abstract, except
+//! for datatypes, which keep their identity so their constructors can still
+//! be matched on.
+//!
+//! Functors are elaborated again at each application, in the environment
+//! they were declared in, so datatypes in the body are generative. The body is
+//! also checked once at the declaration, with the parameter's types abstract,
+//! and errors are only reported from there.
+//!
+//! For ML Basis files, a basis is a scope, entered and left around the files
+//! checked in it. Signatures and functors aren't otherwise scoped, so those
+//! in scope are saved on entering one, and put back on leaving.
+
+use std::collections::hash_map::Entry;
+
+use super::*;
+use crate::mlb::{ModuleKind, Name};
+
+/// An elaborated signature.
+#[derive(Debug, Clone, Default)]
+pub struct Sig {
+    pub env: Env,
+    /// Type constructors the signature leaves open. They're instantiated to
+    /// the structure's types when the signature is matched.
+    pub flexible: Vec<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Functor {
+    pub bind: FunctorBind,
+    pub param: Sig,
+    /// The environment the functor was declared in.
+    scopes: Vec<Env>,
+}
+
+/// What was bound in a basis.
+#[derive(Debug, Clone, Default)]
+pub struct Basis {
+    pub env: Env,
+    pub sigs: HashMap<String, Rc<Sig>>,
+    pub functors: HashMap<String, Rc<Functor>>,
+}
+
+/// Signatures and functors in scope.
+pub(super) type Modules = (HashMap<String, Rc<Sig>>, HashMap<String, Rc<Functor>>);
+
+/// The entries of `now` that aren't in `before`.
+fn changed<T>(
+    now: HashMap<String, Rc<T>>,
+    before: &HashMap<String, Rc<T>>,
+) -> HashMap<String, Rc<T>> {
+    now.into_iter()
+        .filter(|(name, x)| !before.get(name).is_some_and(|y| Rc::ptr_eq(x, y)))
+        .collect()
+}
+
+/// A mapping from flexible type constructors to the types they stand for.
+type Realisation = HashMap<u32, TyFcn>;
+
+fn realise(ty: &Type, map: &Realisation) -> Type {
+    match ty.resolve() {
+        Type::Con(con, args) => {
+            let args: Vec<_> = args.iter().map(|t| realise(t, map)).collect();
+            match map.get(&con.id) {
+                Some(fcn) => fcn.apply(&args),
+                None => Type::Con(con, args),
+            }
+        }
+        Type::Record(fields) => Type::Record(
+            fields
+                .iter()
+                .map(|(lab, ty)| (lab.clone(), realise(ty, map)))
+                .collect(),
+        ),
+        Type::Arrow(a, b) => Type::arrow(realise(&a, map), realise(&b, map)),
+        ty => ty,
+    }
+}
+
+fn realise_env(env: &mut Env, map: &Realisation) {
+    for binding in env.values.values_mut() {
+        binding.scheme.ty = realise(&binding.scheme.ty, map);
+        // Constructors of a datatype that's been renamed belong to the new
+        // datatype.
+        if let IdStatus::Con(info) = &binding.status {
+            if let ConKind::Datatype { tycon, tag } = &info.kind {
+                if let Some(Type::Con(new, _)) = map.get(&tycon.id).map(|fcn| &fcn.ty) {
+                    let info = ConInfo {
+                        kind: ConKind::Datatype {
+                            tycon: new.clone(),
+                            tag: *tag,
+                        },
+                        ..(**info).clone()
+                    };
+                    binding.status = IdStatus::Con(Rc::new(info));
+                }
+            }
+        }
+    }
+    for tystr in env.types.values_mut() {
+        tystr.fcn.ty = realise(&tystr.fcn.ty, map);
+    }
+    for env in env.structures.values_mut() {
+        realise_env(env, map);
+    }
+}
+
+/// The flexible type constructor a type structure is, if it's one.
+fn flexible_id(tystr: &TyStr, flexible: &[u32]) -> Option<u32> {
+    match &tystr.fcn.ty {
+        Type::Con(con, args)
+            if flexible.contains(&con.id)
+                && args
+                    .iter()
+                    .enumerate()
+                    .all(|(i, arg)| matches!(arg, Type::Gen(n) if *n as usize == i)) =>
+        {
+            Some(con.id)
+        }
+        _ => None,
+    }
+}
+
+/// Find a type in an environment by its path.
+fn lookup_type_in<'e>(env: &'e Env, path: &[String], name: &str) -> Option<&'e TyStr> {
+    let mut env = env;
+    for strid in path {
+        env = env.structures.get(strid)?;
+    }
+    env.types.get(name)
+}
+
+/// The paths of the flexible type constructors in a signature.
+fn flexible_paths(
+    env: &Env,
+    flexible: &[u32],
+    prefix: &mut Vec<String>,
+    out: &mut Vec<(u32, Vec<String>, String)>,
+) {
+    let mut names: Vec<_> = env.types.keys().collect();
+    names.sort();
+    for name in names {
+        if let Some(id) = flexible_id(&env.types[name], flexible) {
+            if !out.iter().any(|(other, _, _)| *other == id) {
+                out.push((id, prefix.clone(), name.clone()));
+            }
+        }
+    }
+    let mut strids: Vec<_> = env.structures.keys().collect();
+    strids.sort();
+    for strid in strids {
+        prefix.push(strid.clone());
+        flexible_paths(&env.structures[strid], flexible, prefix, out);
+        prefix.pop();
+    }
+}
+
+/// The paths of all types in an environment.
+fn type_paths(env: &Env, prefix: &mut Vec<String>, out: &mut Vec<(Vec<String>, String)>) {
+    let mut names: Vec<_> = env.types.keys().collect();
+    names.sort();
+    for name in names {
+        out.push((prefix.clone(), name.clone()));
+    }
+    let mut strids: Vec<_> = env.structures.keys().collect();
+    strids.sort();
+    for strid in strids {
+        prefix.push(strid.clone());
+        type_paths(&env.structures[strid], prefix, out);
+        prefix.pop();
+    }
+}
+
+fn show_path(path: &[String], name: &str) -> String {
+    let mut parts = path.to_vec();
+    parts.push(name.to_owned());
+    parts.join(".")
+}
+
+/// Use the structure's constructors in a signature's view of it, so that
+/// constructors keep their identity.
+fn with_statuses(mut view: Env, str: &Env) -> Env {
+    for (name, binding) in view.values.iter_mut() {
+        if let (IdStatus::Con(_), Some(actual)) = (&binding.status, str.values.get(name)) {
+            if let IdStatus::Con(_) = actual.status {
+                binding.status = actual.status.clone();
+            }
+        }
+    }
+    for (strid, env) in view.structures.iter_mut() {
+        if let Some(actual) = str.structures.get(strid) {
+            *env = with_statuses(std::mem::take(env), actual);
+        }
+    }
+    view
+}
+
+impl Checker {
+    fn new_tycon(&mut self, name: &str, arity: usize, eq: Equality) -> TyCon {
+        let id = self.tycons.len() as u32;
+        self.tycons.push(TyConInfo {
+            name: Rc::from(name),
+            arity,
+            eq,
+            cons: Vec::new(),
+            opaque: false,
+        });
+        TyCon {
+            id,
+            name: Rc::from(name),
+        }
+    }
+
+    /// Look up a structure by its long identifier.
+    fn lookup_structure(&mut self, id: &LongId) -> Option<Env> {
+        let mut path = id.path.clone();
+        path.push(id.name.clone());
+        let id = LongId {
+            path,
+            name: String::new(),
+            span: id.span,
+        };
+        self.structure_env(&id).cloned()
+    }
+
+    // Structures
+
+    pub(super) fn structure_dec(&mut self, binds: &[StrBind]) {
+        let mut envs = Vec::new();
+        for bind in binds {
+            let mut env = self.strexp(&bind.str);
+            if let Some(asc) = &bind.sig {
+                env = self.ascribe(env, asc, bind.str.span);
+            }
+            envs.push((bind.id.name.clone(), env));
+        }
+        self.scope().structures.extend(envs);
+    }
+
+    fn strexp(&mut self, exp: &StrExp) -> Env {
+        match &exp.kind {
+            StrExpKind::Struct(decs) => {
+                self.push_scope();
+                for dec in decs {
+                    self.str_dec(dec);
+                }
+                self.pop_scope()
+            }
+            StrExpKind::Var(id) => match self.lookup_structure(id) {
+                Some(env) => env,
+                None => {
+                    self.error(id.span, format!("unbound structure `{}`", id));
+                    Env::default()
+                }
+            },
+            StrExpKind::Ascribe(inner, asc) => {
+                let env = self.strexp(inner);
+                self.ascribe(env, asc, inner.span)
+            }
+            StrExpKind::App(funid, arg) => self.apply_functor(funid, arg),
+            StrExpKind::Let(decs, body) => {
+                self.push_scope();
+                for dec in decs {
+                    self.str_dec(dec);
+                }
+                let env = self.strexp(body);
+                self.pop_scope();
+                env
+            }
+        }
+    }
+
+    /// Constrain a structure by a signature. Errors are reported at `span`,
+    /// the structure being constrained.
+    fn ascribe(&mut self, env: Env, asc: &Ascription, span: Span) -> Env {
+        let sig = self.sigexp(&asc.sig);
+        let (view, map) = self.match_sig(&env, &sig, span);
+        if !asc.opaque {
+            self.info.sigs.insert(asc.sig.span, view.clone());
+            return view;
+        }
+
+        let mut abstracted = Realisation::new();
+        for &id in &sig.flexible {
+            let info = self.tycons[id as usize].clone();
+            if !info.cons.is_empty() {
+                if let Some(fcn) = map.get(&id) {
+                    abstracted.insert(id, fcn.clone());
+                }
+                continue;
+            }
+            let tycon = self.new_tycon(&info.name, info.arity, info.eq);
+            self.tycons[tycon.id as usize].opaque = true;
+            let args = (0..info.arity as u32).map(Type::Gen).collect();
+            abstracted.insert(
+                id,
+                TyFcn {
+                    arity: info.arity,
+                    ty: Type::Con(tycon, args),
+                },
+            );
+        }
+        let mut opaque = sig.env;
+        realise_env(&mut opaque, &abstracted);
+        let opaque = with_statuses(opaque, &env);
+        self.info.sigs.insert(asc.sig.span, opaque.clone());
+        opaque
+    }
+
+    // Signatures
+
+    pub(super) fn sig_dec(&mut self, binds: &[SigBind]) {
+        let sigs: Vec<_> = binds
+            .iter()
+            .map(|bind| (bind.id.name.clone(), Rc::new(self.sigexp(&bind.sig))))
+            .collect();
+        self.sigs.extend(sigs);
+    }
+
+    fn sigexp(&mut self, sig: &SigExp) -> Sig {
+        match &sig.kind {
+            SigExpKind::Var(id) => match self.sigs.get(&id.name).cloned() {
+                Some(sig) => self.fresh_sig(&sig),
+                None => {
+                    self.error(id.span, format!("unbound signature `{}`", id));
+                    Sig::default()
+                }
+            },
+            SigExpKind::Sig(specs) => {
+                self.push_scope();
+                let tyvars = std::mem::take(&mut self.tyvars);
+                let mut flexible = Vec::new();
+                for spec in specs {
+                    self.spec(spec, &mut flexible);
+                }
+                self.tyvars = tyvars;
+                Sig {
+                    env: self.pop_scope(),
+                    flexible,
+                }
+            }
+            SigExpKind::Where {
+                sig: inner,
+                tyvars,
+                tycon,
+                ty,
+            } => {
+                let mut sig = self.sigexp(inner);
+                let fcn = self.tyfcn(tyvars, ty);
+                let tystr = lookup_type_in(&sig.env, &tycon.path, &tycon.name).cloned();
+                let id = match tystr {
+                    Some(tystr) => match flexible_id(&tystr, &sig.flexible) {
+                        Some(id) => id,
+                        None => {
+                            self.error(
+                                tycon.span,
+                                format!(
+                                    "can't refine type `{}`, since it's already defined",
+                                    tycon
+                                ),
+                            );
+                            return sig;
+                        }
+                    },
+                    None => {
+                        self.error(tycon.span, format!("the signature has no type `{}`", tycon));
+                        return sig;
+                    }
+                };
+                let info = &self.tycons[id as usize];
+                if info.arity != fcn.arity {
+                    let message = format!(
+                        "type `{}` takes {} argument(s), but was given {}",
+                        tycon, info.arity, fcn.arity
+                    );
+                    self.error(tycon.span, message);
+                    return sig;
+                }
+                if info.eq != Equality::Never && !self.admits_eq(&fcn.ty) {
+                    self.error(
+                        ty.span,
+                        format!(
+                            "`{}` is an eqtype, but `{}` doesn't admit equality",
+                            tycon, fcn.ty
+                        ),
+                    );
+                }
+                sig.flexible.retain(|&f| f != id);
+                let mut map = Realisation::new();
+                map.insert(id, fcn);
+                realise_env(&mut sig.env, &map);
+                sig
+            }
+        }
+    }
+
+    /// Copy a signature with new flexible type constructors, so that
+    /// refining the copy doesn't affect other uses.
+    fn fresh_sig(&mut self, sig: &Sig) -> Sig {
+        let mut map = Realisation::new();
+        let mut flexible = Vec::new();
+        for &id in &sig.flexible {
+            let info = self.tycons[id as usize].clone();
+            let tycon = self.new_tycon(&info.name, info.arity, info.eq);
+            self.tycons[tycon.id as usize].cons = info.cons;
+            flexible.push(tycon.id);
+            let args = (0..info.arity as u32).map(Type::Gen).collect();
+            map.insert(
+                id,
+                TyFcn {
+                    arity: info.arity,
+                    ty: Type::Con(tycon, args),
+                },
+            );
+        }
+        let mut env = sig.env.clone();
+        realise_env(&mut env, &map);
+        Sig { env, flexible }
+    }
+
+    /// Add a specification to the current scope.
+    fn spec(&mut self, spec: &Spec, flexible: &mut Vec<u32>) {
+        match &spec.kind {
+            SpecKind::Val(descs) => {
+                for (id, ty) in descs {
+                    let scheme = self.spec_scheme(ty);
+                    self.bind_value(&id.name, scheme, IdStatus::Var);
+                }
+            }
+            SpecKind::Type {
+                tyvars,
+                tycon,
+                eq,
+                def,
+            } => {
+                let fcn = match def {
+                    Some(ty) => self.tyfcn(tyvars, ty),
+                    None => {
+                        let eq = if *eq {
+                            Equality::IfArgs
+                        } else {
+                            Equality::Never
+                        };
+                        let new = self.new_tycon(&tycon.name, tyvars.len(), eq);
+                        flexible.push(new.id);
+                        TyFcn {
+                            arity: tyvars.len(),
+                            ty: Type::Con(new, (0..tyvars.len() as u32).map(Type::Gen).collect()),
+                        }
+                    }
+                };
+                let tystr = TyStr {
+                    fcn,
+                    cons: Vec::new(),
+                };
+                self.scope().types.insert(tycon.name.clone(), tystr);
+            }
+            SpecKind::Datatype(binds) => {
+                let first = self.tycons.len() as u32;
+                self.datatype_dec(binds, &[]);
+                flexible.extend(first..self.tycons.len() as u32);
+            }
+            SpecKind::DatatypeRepl(tycon, from) => {
+                self.datatype_repl(tycon, from);
+            }
+            SpecKind::Exception(descs) => {
+                for (id, arg) in descs {
+                    self.tyvars.push(HashMap::new());
+                    let (ty, has_arg) = match arg {
+                        Some(arg) => (Type::arrow(self.ty(arg), Type::exn()), true),
+                        None => (Type::exn(), false),
+                    };
+                    self.tyvars.pop();
+                    let exn = self.fresh_exn();
+                    self.add_con(&id.name, Scheme::mono(ty), ConKind::Exn(exn), has_arg);
+                }
+            }
+            SpecKind::Structure(descs) => {
+                for (id, sigexp) in descs {
+                    let sig = self.sigexp(sigexp);
+                    flexible.extend(sig.flexible);
+                    self.scope().structures.insert(id.name.clone(), sig.env);
+                }
+            }
+            SpecKind::Include(sigexp) => {
+                let sig = self.sigexp(sigexp);
+                flexible.extend(sig.flexible);
+                self.scope().extend(sig.env);
+            }
+            SpecKind::SharingType(tycons) => self.share_types(tycons, flexible),
+            SpecKind::Sharing(strids) => {
+                // Structures share all the types they have in common.
+                let envs: Vec<_> = strids
+                    .iter()
+                    .map(|strid| self.lookup_structure(strid))
+                    .collect();
+                for (strid, env) in strids.iter().zip(&envs) {
+                    if env.is_none() {
+                        self.error(strid.span, format!("unbound structure `{}`", strid));
+                    }
+                }
+                let mut paths = Vec::new();
+                if let Some(Some(env)) = envs.first() {
+                    type_paths(env, &mut Vec::new(), &mut paths);
+                }
+                for (path, name) in paths {
+                    let tycons: Vec<_> = strids
+                        .iter()
+                        .zip(&envs)
+                        .filter(|(_, env)| {
+                            env.as_ref()
+                                .is_some_and(|env| lookup_type_in(env, &path, &name).is_some())
+                        })
+                        .map(|(strid, _)| {
+                            let mut full = strid.path.clone();
+                            full.push(strid.name.clone());
+                            full.extend(path.iter().cloned());
+                            LongId {
+                                path: full,
+                                name: name.clone(),
+                                span: strid.span,
+                            }
+                        })
+                        .collect();
+                    if tycons.len() > 1 {
+                        self.share_types(&tycons, flexible);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The type scheme of a value specification. Its type variables are
+    /// implicitly bound.
+    fn spec_scheme(&mut self, ty: &Ty) -> Scheme {
+        let mut found = Vec::new();
+        tyvars_in_ty(ty, &mut found);
+        let mut params: Vec<GenParam> = Vec::new();
+        let mut scope = HashMap::new();
+        for tyvar in found {
+            let name = tyvar.to_string();
+            if let Entry::Vacant(entry) = scope.entry(name) {
+                entry.insert(Type::Gen(params.len() as u32));
+                params.push(GenParam {
+                    eq: tyvar.eq,
+                    overload: None,
+                });
+            }
+        }
+        self.tyvars.push(scope);
+        let ty = self.ty(ty);
+        self.tyvars.pop();
+        Scheme { params, ty }
+    }
+
+    /// `sharing type t1 = ... = tn`: make the flexible types the same.
+    fn share_types(&mut self, tycons: &[LongId], flexible: &mut Vec<u32>) {
+        let mut found: Vec<(&LongId, u32)> = Vec::new();
+        for tycon in tycons {
+            match self.lookup_long_type(tycon) {
+                Some(tystr) => match flexible_id(&tystr, flexible) {
+                    Some(id) => found.push((tycon, id)),
+                    None => self.error(
+                        tycon.span,
+                        format!(
+                            "type `{}` can't be shared, since it's already defined",
+                            tycon
+                        ),
+                    ),
+                },
+                None => self.error(tycon.span, format!("unbound type constructor `{}`", tycon)),
+            }
+        }
+        let (_, rep) = match found.first() {
+            Some(first) => *first,
+            None => return,
+        };
+        let arity = self.tycons[rep as usize].arity;
+        let name = self.tycons[rep as usize].name.clone();
+        let mut map = Realisation::new();
+        for &(tycon, id) in &found[1..] {
+            if id == rep {
+                continue;
+            }
+            if self.tycons[id as usize].arity != arity {
+                self.error(
+                    tycon.span,
+                    "types in a sharing constraint must take the same number of arguments",
+                );
+                continue;
+            }
+            if self.tycons[id as usize].eq != Equality::Never {
+                self.tycons[rep as usize].eq = Equality::IfArgs;
+            }
+            map.insert(
+                id,
+                TyFcn {
+                    arity,
+                    ty: Type::Con(
+                        TyCon {
+                            id: rep,
+                            name: name.clone(),
+                        },
+                        (0..arity as u32).map(Type::Gen).collect(),
+                    ),
+                },
+            );
+        }
+        flexible.retain(|id| !map.contains_key(id));
+        realise_env(self.scope(), &map);
+    }
+
+    // Matching
+
+    /// Match a structure against a signature. Returns the signature's view of
+    /// the structure, and the realisation of the signature's flexible types.
+    fn match_sig(&mut self, env: &Env, sig: &Sig, span: Span) -> (Env, Realisation) {
+        let mut paths = Vec::new();
+        flexible_paths(&sig.env, &sig.flexible, &mut Vec::new(), &mut paths);
+        let mut map = Realisation::new();
+        for (id, path, name) in paths {
+            // Missing types are reported when checking the components.
+            let tystr = match lookup_type_in(env, &path, &name) {
+                Some(tystr) => tystr.clone(),
+                None => continue,
+            };
+            let full = show_path(&path, &name);
+            let info = self.tycons[id as usize].clone();
+            // Arity mismatches are reported when checking the components.
+            if tystr.fcn.arity != info.arity {
+                continue;
+            }
+            if info.eq != Equality::Never && !self.admits_eq(&tystr.fcn.ty) {
+                self.error(
+                    span,
+                    format!(
+                        "type `{}` must admit equality, as the signature requires",
+                        full
+                    ),
+                );
+            }
+            if !info.cons.is_empty() {
+                let matches = match &tystr.fcn.ty {
+                    Type::Con(con, _) => {
+                        let mut expected = info.cons.clone();
+                        let mut actual = self.tycons[con.id as usize].cons.clone();
+                        expected.sort();
+                        actual.sort();
+                        expected == actual
+                    }
+                    _ => false,
+                };
+                if !matches {
+                    let cons: Vec<_> = info.cons.iter().map(|(name, _)| name.as_str()).collect();
+                    self.error(
+                        span,
+                        format!(
+                            "type `{}` must be a datatype with constructors {}",
+                            full,
+                            cons.join(" | ")
+                        ),
+                    );
+                }
+            }
+            map.insert(id, tystr.fcn);
+        }
+
+        let mut view = sig.env.clone();
+        realise_env(&mut view, &map);
+        self.enrich(env, &view, &mut Vec::new(), span);
+        (with_statuses(view, env), map)
+    }
+
+    /// Check that a structure has every component of a signature, with a
+    /// type at least as general.
+    fn enrich(&mut self, str: &Env, sig: &Env, path: &mut Vec<String>, span: Span) {
+        let mut names: Vec<_> = sig.types.keys().collect();
+        names.sort();
+        for name in names {
+            let full = show_path(path, name);
+            let expected = &sig.types[name];
+            let actual = match str.types.get(name) {
+                Some(actual) => actual,
+                None => {
+                    self.error(
+                        span,
+                        format!(
+                            "structure is missing type `{}` required by the signature",
+                            full
+                        ),
+                    );
+                    continue;
+                }
+            };
+            let arity = expected.fcn.arity;
+            if actual.fcn.arity != arity {
+                self.error(
+                    span,
+                    format!(
+                        "type `{}` takes {} argument(s) in the signature, but {} in the structure",
+                        full, arity, actual.fcn.arity
+                    ),
+                );
+                continue;
+            }
+            let args: Vec<_> = (0..arity)
+                .map(|i| self.new_var(false, VarKind::Rigid(format!("t{}", i))))
+                .collect();
+            let (expected, actual) = (expected.fcn.apply(&args), actual.fcn.apply(&args));
+            if self.unify(&expected, &actual).is_err() {
+                let mut namer = Namer::default();
+                let message = format!(
+                    "type `{}` is `{}` in the structure, but the signature requires `{}`",
+                    full,
+                    namer.show(&actual),
+                    namer.show(&expected)
+                );
+                self.error(span, message);
+            }
+        }
+
+        let mut names: Vec<_> = sig.values.keys().collect();
+        names.sort();
+        for name in names {
+            let full = show_path(path, name);
+            let expected = &sig.values[name];
+            let what = match &expected.status {
+                IdStatus::Var => "value",
+                IdStatus::Con(info) => match info.kind {
+                    ConKind::Datatype { .. } => "constructor",
+                    ConKind::Exn(_) => "exception",
+                },
+            };
+            let actual = match str.values.get(name) {
+                Some(actual) => actual,
+                None => {
+                    self.error(
+                        span,
+                        format!(
+                            "structure is missing {} `{}` required by the signature",
+                            what, full
+                        ),
+                    );
+                    continue;
+                }
+            };
+            let status_matches = match (&expected.status, &actual.status) {
+                (IdStatus::Var, _) => true,
+                (IdStatus::Con(e), IdStatus::Con(a)) => {
+                    matches!(
+                        (&e.kind, &a.kind),
+                        (ConKind::Datatype { .. }, ConKind::Datatype { .. })
+                            | (ConKind::Exn(_), ConKind::Exn(_))
+                    )
+                }
+                (IdStatus::Con(_), IdStatus::Var) => false,
+            };
+            if !status_matches {
+                self.error(
+                    span,
+                    format!(
+                        "`{}` must be {} {}, as the signature requires",
+                        full,
+                        if what == "exception" { "an" } else { "a" },
+                        what
+                    ),
+                );
+                continue;
+            }
+
+            // The structure's type must be an instance of the signature's,
+            // with the signature's type variables held fixed.
+            let args: Vec<_> = expected
+                .scheme
+                .params
+                .iter()
+                .enumerate()
+                .map(|(i, param)| {
+                    let name = ((b'a' + (i % 26) as u8) as char).to_string();
+                    self.new_var(param.eq, VarKind::Rigid(name))
+                })
+                .collect();
+            let expected_ty = expected.scheme.ty.subst(&args);
+            self.enter();
+            let actual_ty = self.instantiate(&actual.scheme);
+            self.leave();
+            if self.unify(&actual_ty, &expected_ty).is_err() {
+                self.error(
+                    span,
+                    format!(
+                        "value `{}` has type `{}` in the structure, but the signature requires `{}`",
+                        full, actual.scheme, expected.scheme
+                    ),
+                );
+            }
+        }
+
+        let mut strids: Vec<_> = sig.structures.keys().collect();
+        strids.sort();
+        for strid in strids {
+            match str.structures.get(strid) {
+                Some(actual) => {
+                    path.push(strid.clone());
+                    self.enrich(actual, &sig.structures[strid], path, span);
+                    path.pop();
+                }
+                None => self.error(
+                    span,
+                    format!(
+                        "structure is missing structure `{}` required by the signature",
+                        show_path(path, strid)
+                    ),
+                ),
+            }
+        }
+    }
+
+    // Functors
+
+    pub(super) fn functor_dec(&mut self, binds: &[FunctorBind]) {
+        let mut functors = Vec::new();
+        for bind in binds {
+            let param = self.sigexp(&bind.param_sig);
+            self.push_scope();
+            self.bind_param(bind, param.env.clone());
+            let env = self.strexp(&bind.body);
+            if let Some(asc) = &bind.sig {
+                self.ascribe(env, asc, bind.body.span);
+            }
+            self.pop_scope();
+            let functor = Functor {
+                bind: bind.clone(),
+                param,
+                scopes: self.scopes.clone(),
+            };
+            functors.push((bind.id.name.clone(), Rc::new(functor)));
+        }
+        self.functors.extend(functors);
+    }
+
+    /// Bring a functor's parameter into scope.
+    fn bind_param(&mut self, bind: &FunctorBind, env: Env) {
+        match &bind.param {
+            Some(id) => {
+                self.scope().structures.insert(id.name.clone(), env);
+            }
+            None => self.scope().extend(env),
+        }
+    }
+
+    fn apply_functor(&mut self, funid: &Id, arg: &StrExp) -> Env {
+        let functor = match self.functors.get(&funid.name) {
+            Some(functor) => functor.clone(),
+            None => {
+                self.error(funid.span, format!("unbound functor `{}`", funid));
+                return Env::default();
+            }
+        };
+        let arg_env = self.strexp(arg);
+        let (view, _) = self.match_sig(&arg_env, &functor.param, arg.span);
+        self.info
+            .sigs
+            .insert(functor.bind.param_sig.span, view.clone());
+
+        let scopes = std::mem::replace(&mut self.scopes, functor.scopes.clone());
+        let errors = self.diagnostics.len();
+        self.push_scope();
+        self.bind_param(&functor.bind, view);
+        let mut env = self.strexp(&functor.bind.body);
+        if let Some(asc) = &functor.bind.sig {
+            env = self.ascribe(env, asc, functor.bind.body.span);
+        }
+        self.pop_scope();
+        // Errors in the body were reported at the declaration.
+        self.diagnostics.truncate(errors);
+        self.scopes = scopes;
+        env
+    }
+
+    // Bases
+
+    /// Start a basis, in which everything bound so far is still visible.
+    pub fn enter_basis(&mut self) {
+        self.push_scope();
+        self.bases.push((self.sigs.clone(), self.functors.clone()));
+    }
+
+    /// Go back to the basis before the last one entered, giving what was
+    /// bound in that one.
+    pub fn leave_basis(&mut self) -> Basis {
+        let (sigs, functors) = self.bases.pop().expect("a basis was entered");
+        let env = self.pop_scope();
+        let sigs = changed(std::mem::replace(&mut self.sigs, sigs), &self.sigs);
+        let functors = changed(
+            std::mem::replace(&mut self.functors, functors),
+            &self.functors,
+        );
+        Basis {
+            env,
+            sigs,
+            functors,
+        }
+    }
+
+    /// Bind what was bound in a basis.
+    pub fn open_basis(&mut self, basis: &Basis) {
+        self.scope().extend(basis.env.clone());
+        self.sigs.extend(basis.sigs.clone());
+        self.functors.extend(basis.functors.clone());
+    }
+
+    /// Bind modules to other names, all at once, as `structure a = b` in an
+    /// ML Basis file does.
+    pub fn alias(
+        &mut self,
+        kind: ModuleKind,
+        binds: &[(Name, Name)],
+    ) -> Result<(), Vec<Diagnostic>> {
+        let mut structures = Vec::new();
+        let mut sigs = Vec::new();
+        let mut functors = Vec::new();
+        for (new, old) in binds {
+            let name = new.name.clone();
+            let found = match kind {
+                ModuleKind::Structure => {
+                    let env = self
+                        .scopes
+                        .iter()
+                        .rev()
+                        .find_map(|env| env.structures.get(&old.name));
+                    env.map(|env| structures.push((name, env.clone())))
+                }
+                ModuleKind::Signature => {
+                    let sig = self.sigs.get(&old.name);
+                    sig.map(|sig| sigs.push((name, sig.clone())))
+                }
+                ModuleKind::Functor => {
+                    let functor = self.functors.get(&old.name);
+                    functor.map(|functor| functors.push((name, functor.clone())))
+                }
+            };
+            if found.is_none() {
+                self.error(old.span, format!("unbound {} `{}`", kind, old.name));
+            }
+        }
+        self.scope().structures.extend(structures);
+        self.sigs.extend(sigs);
+        self.functors.extend(functors);
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.diagnostics))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parsegen::{FileId, SourceMap};
+
+    /// Check a program, returning the value `name` as `name : type`, or the
+    /// error messages.
+    fn check(src: &str, names: &[&str]) -> Result<Vec<String>, Vec<String>> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        crate::fixity::resolve(&mut program).unwrap();
+        let mut checker = Checker::new();
+        if let Err(diags) = checker.check_program(&program) {
+            return Err(diags.into_iter().map(|d| d.message).collect());
+        }
+        Ok(names
+            .iter()
+            .map(|name| {
+                let mut path: Vec<_> = name.split('.').map(str::to_owned).collect();
+                let id = LongId {
+                    name: path.pop().unwrap(),
+                    path,
+                    span: Span::new(file, 0, 0),
+                };
+                let binding = checker.lookup_long_value(&id).unwrap();
+                format!("{} : {}", id, binding.scheme)
+            })
+            .collect())
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        check(src, &[]).unwrap_err()
+    }
+
+    #[test]
+    fn structures() {
+        assert_eq!(
+            check(
+                "structure S = struct
+                   datatype t = A | B of int
+                   fun f (B n) = n | f A = 0
+                   structure T = struct val x = f (B 1) end
+                 end
+                 structure U = S
+                 val y = U.f U.A + S.T.x",
+                &["S.f", "U.T.x", "y"]
+            ),
+            Ok(vec![
+                "S.f : t -> int".to_owned(),
+                "U.T.x : int".to_owned(),
+                "y : int".to_owned()
+            ])
+        );
+        assert_eq!(
+            errors("structure S = struct end val x = S.y structure T = V"),
+            vec![
+                "unbound variable or constructor `S.y`".to_owned(),
+                "unbound structure `V`".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn ascription() {
+        let src = "signature STACK = sig
+                     type 'a t
+                     val empty : 'a t
+                     val push : 'a * 'a t -> 'a t
+                   end
+                   structure L = struct
+                     type 'a t = 'a list
+                     val empty = []
+                     fun push (x, xs) = x :: xs
+                     fun extra x = x
+                   end";
+        // Transparent ascription keeps the type's definition, but hides
+        // anything the signature doesn't mention.
+        assert_eq!(
+            check(
+                &format!("{} structure S : STACK = L val s = S.push (1, [])", src),
+                &["S.push", "s"]
+            ),
+            Ok(vec![
+                "S.push : 'a * 'a list -> 'a list".to_owned(),
+                "s : int list".to_owned()
+            ])
+        );
+        assert_eq!(
+            errors(&format!("{} structure S : STACK = L val e = S.extra", src)),
+            vec!["unbound variable or constructor `S.extra`".to_owned()]
+        );
+        // Opaque ascription makes it abstract.
+        assert_eq!(
+            check(
+                &format!(
+                    "{} structure S :> STACK = L val s = S.push (1, S.empty)",
+                    src
+                ),
+                &["s"]
+            ),
+            Ok(vec!["s : int t".to_owned()])
+        );
+        assert_eq!(
+            errors(&format!(
+                "{} structure S :> STACK = L val s = S.push (1, [])",
+                src
+            )),
+            vec!["type mismatch: expected `int * int t`, found `int * '_a list`".to_owned()]
+        );
+        // `where type` exposes it again.
+        assert_eq!(
+            check(
+                &format!(
+                    "{} structure S :> STACK where type 'a t = 'a list = L val s = S.push (1, [])",
+                    src
+                ),
+                &["s"]
+            ),
+            Ok(vec!["s : int list".to_owned()])
+        );
+    }
+
+    #[test]
+    fn signature_matching_errors() {
+        assert_eq!(
+            errors(
+                "signature S = sig
+                   type t
+                   eqtype u
+                   val x : t
+                   val f : 'a -> 'a
+                   exception E
+                   structure A : sig val y : int end
+                 end
+                 structure T : S = struct
+                   type t = int
+                   type u = real
+                   val x = true
+                   fun f x = x + 1
+                   val E = 1
+                   structure A = struct end
+                 end"
+            ),
+            vec![
+                "type `u` must admit equality, as the signature requires".to_owned(),
+                "`E` must be an exception, as the signature requires".to_owned(),
+                "value `f` has type `int -> int` in the structure, but the signature requires `'a -> 'a`"
+                    .to_owned(),
+                "value `x` has type `bool` in the structure, but the signature requires `int`"
+                    .to_owned(),
+                "structure is missing value `A.y` required by the signature".to_owned()
+            ]
+        );
+        assert_eq!(
+            errors(
+                "signature S = sig type 'a t datatype d = A | B val v : int end
+                 structure T : S = struct type t = int datatype d = A end"
+            ),
+            vec![
+                "type `d` must be a datatype with constructors A | B".to_owned(),
+                "type `t` takes 1 argument(s) in the signature, but 0 in the structure".to_owned(),
+                "structure is missing constructor `B` required by the signature".to_owned(),
+                "structure is missing value `v` required by the signature".to_owned()
+            ]
+        );
+        assert_eq!(
+            errors("signature S = sig type t end signature T = S where type u = int structure A : U = struct end"),
+            vec![
+                "the signature has no type `u`".to_owned(),
+                "unbound signature `U`".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn opaque_datatypes() {
+        // Constructors of a datatype in an opaque signature can still be
+        // matched on.
+        assert_eq!(
+            check(
+                "structure S :> sig datatype t = A | B of int val get : t -> int end = struct
+                   datatype t = A | B of int
+                   fun get A = 0 | get (B n) = n
+                 end
+                 val n = S.get (S.B 1)
+                 fun f S.A = 1 | f (S.B n) = n",
+                &["n", "f"]
+            ),
+            Ok(vec!["n : int".to_owned(), "f : t -> int".to_owned()])
+        );
+    }
+
+    #[test]
+    fn include_and_sharing() {
+        assert_eq!(
+            check(
+                "signature ORD = sig type t val compare : t * t -> order end
+                 signature SET = sig
+                   include ORD
+                   structure Elem : ORD
+                   sharing type t = Elem.t
+                 end
+                 structure IntOrd = struct type t = int
+                   fun compare (a, b) = if a < b then LESS else if a > b then GREATER else EQUAL
+                 end
+                 structure S :> SET where type t = int = struct
+                   open IntOrd
+                   structure Elem = IntOrd
+                 end
+                 val c = S.Elem.compare (1, 2)",
+                &["c"]
+            ),
+            Ok(vec!["c : order".to_owned()])
+        );
+        assert_eq!(
+            errors("signature S = sig type t = int type u sharing type t = u end"),
+            vec!["type `t` can't be shared, since it's already defined".to_owned()]
+        );
+    }
+
+    #[test]
+    fn functors() {
+        let src = "signature ORD = sig type t val le : t * t -> bool end
+                   functor Sort (O : ORD) = struct
+                     datatype box = Box of O.t
+                     fun insert (x, []) = [x]
+                       | insert (x, y :: ys) = if O.le (x, y) then x :: y :: ys else y :: insert (x, ys)
+                     fun sort [] = [] | sort (x :: xs) = insert (x, sort xs)
+                   end
+                   structure IntSort = Sort (struct type t = int val le = op <= end)
+                   structure StrSort = Sort (type t = string fun le (a : string, b) = a <= b)";
+        assert_eq!(
+            check(
+                &format!(
+                    "{} val a = IntSort.sort [3, 1, 2] val b = StrSort.sort [\"b\"]",
+                    src
+                ),
+                &["a", "b", "IntSort.Box"]
+            ),
+            Ok(vec![
+                "a : int list".to_owned(),
+                "b : string list".to_owned(),
+                "IntSort.Box : int -> box".to_owned()
+            ])
+        );
+        // Datatypes are generative: each application makes a new one.
+        assert!(check(
+            &format!("{} val x = [IntSort.Box 1, StrSort.Box 1]", src),
+            &[]
+        )
+        .is_err());
+        assert_eq!(
+            errors(&format!(
+                "{} structure B = Sort (struct type t = int end) structure C = G (B)",
+                src
+            )),
+            vec![
+                "structure is missing value `le` required by the signature".to_owned(),
+                "unbound functor `G`".to_owned()
+            ]
+        );
+        // The body is checked with the parameter's types abstract.
+        assert_eq!(
+            errors("functor F (type t) = struct val x : t = 1 end"),
+            vec!["type mismatch: expected `t`, found `int`".to_owned()]
+        );
+    }
+
+    #[test]
+    fn bases() {
+        let mut sources = SourceMap::new();
+        let mut checker = Checker::new();
+        let mut check = |checker: &mut Checker, src: &str| {
+            let file = sources.add("test.sml", src);
+            let mut program = crate::lower::parse(&sources, file).unwrap();
+            crate::fixity::resolve(&mut program).unwrap();
+            match checker.check_program(&program) {
+                Ok(_) => Ok(()),
+                Err(diags) => Err(diags[0].message.clone()),
+            }
+        };
+        let name = |name: &str| Name {
+            name: name.to_owned(),
+            span: Span::new(FileId::ANON, 0, 0),
+        };
+
+        checker.enter_basis();
+        check(
+            &mut checker,
+            "signature S = sig val x : int end
+             structure A : S = struct val x = 1 end
+             functor F (X : S) = struct val y = X.x end
+             val hidden = 1",
+        )
+        .unwrap();
+        let basis = checker.leave_basis();
+        assert!(checker.lookup_value("hidden").is_none());
+        assert!(!checker.sigs.contains_key("S"));
+        assert_eq!(basis.sigs.len(), 1);
+        assert_eq!(basis.functors.len(), 1);
+
+        checker.enter_basis();
+        checker.open_basis(&basis);
+        let binds = [(name("B"), name("A"))];
+        checker.alias(ModuleKind::Structure, &binds).unwrap();
+        checker
+            .alias(ModuleKind::Functor, &[(name("G"), name("F"))])
+            .unwrap();
+        let exports = checker.leave_basis();
+        assert!(exports.env.structures.contains_key("B"));
+        // What was opened is bound in this basis too.
+        assert!(exports.env.structures.contains_key("A"));
+        assert_eq!(exports.sigs.len(), 1);
+        assert_eq!(exports.functors.len(), 2);
+
+        checker.open_basis(&exports);
+        check(
+            &mut checker,
+            "structure C = G (B) val z = C.y + B.x + hidden",
+        )
+        .unwrap();
+        assert_eq!(
+            check(&mut checker, "structure D : S = struct end"),
+            Err("structure is missing value `x` required by the signature".to_owned())
+        );
+        let diags = checker
+            .alias(ModuleKind::Signature, &[(name("T"), name("Missing"))])
+            .unwrap_err();
+        assert_eq!(diags[0].message, "unbound signature `Missing`");
+    }
+}
@@ -0,0 +1,560 @@
+//! A bytecode compiler and virtual machine, for running programs without
+//! compiling them to native code: scripts, and programs run again and again.
+//!
+//! Closure converted IR compiles to a `Module`, with a bytecode `Function` for
+//! each IR function. Bytecode is register based: each of a function's
+//! variables is a register of its frame, its parameters first, and its
+//! continuations are labels, the indices of instructions in its code. A jump
+//! to a continuation moves the arguments into its parameters' registers, and
+//! a call says where the result goes: into a register, going on at a label,
+//! or out of the function, if it returns to the function's own return
+//! continuation. Exceptions are the same, with the handler continuation, so
+//! raising unwinds frames until one has a handler. A call whose result and
+//! exception both go out of the function is a tail call, and reuses its
+//! frame, so loops written as recursion run in constant space.
+//!
+//! Values are ints (and everything represented as one: chars, bools, unit and
+//! constructors without arguments), words, reals, or references to objects
+//! on the machine's heap, which it collects when it's allocated enough (see
+//! `heap`).
+//!
+//! A module is a whole program, including the parts of the Basis it uses. It
+//! can be written to a compact binary form and read back (see `Module::write`
+//! and `Module::read`), so running a program again doesn't parse, type check
+//! or compile anything. `load` keeps the modules it compiles in a `Cache`
+//! on disk, so running the same files again doesn't either.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::ir::{FunId, PrimOp};
+
+mod cache;
+mod compile;
+pub mod heap;
+mod machine;
+mod natives;
+mod serial;
+
+pub use cache::{is_bytecode, load, Cache, EXTENSION};
+pub use compile::compile;
+pub use machine::{Machine, Uncaught};
+pub use natives::{Native, NATIVES};
+pub use serial::ReadError;
+
+/// A register of a function's frame.
+pub type Reg = u32;
+
+/// The index of an instruction in a function's code.
+pub type Label = u32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    /// The program's top level, which takes no arguments.
+    pub main: Function,
+    /// The functions `Callee::Direct` and closures refer to.
+    pub funs: Vec<Function>,
+    /// The constants `Instr::Const` loads.
+    pub constants: Vec<Constant>,
+    /// How many global variables the program has.
+    pub globals: u32,
+    /// The names of exceptions, by id, for `exnName`.
+    pub exns: BTreeMap<u32, String>,
+    /// The primitives the program calls, which `Callee::Native` indexes.
+    pub natives: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// How many parameters the function takes, in its first registers.
+    pub params: u32,
+    pub registers: u32,
+    pub code: Vec<Instr>,
+}
+
+/// Constants that aren't ints.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    String(String),
+    Word(u64),
+    Real(f64),
+}
+
+/// Where a value given to a continuation goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Out of the function: it returns the value, or raises it if it's an
+    /// exception.
+    Out,
+    /// Into a register, going on at a label.
+    Block(Reg, Label),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Callee {
+    /// A closure, which is given itself before its argument.
+    Closure(Reg),
+    /// A lambda lifted function.
+    Direct(FunId),
+    /// A primitive, by its index in `Module::natives`.
+    Native(u32),
+}
+
+/// A closure to make, capturing the values of registers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MakeClosure {
+    pub dst: Reg,
+    pub fun: FunId,
+    pub free: Vec<Reg>,
+}
+
+/// Instructions, whose first register is where their result goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Int(Reg, i64),
+    Const(Reg, u32),
+    Move(Reg, Reg),
+    Record(Reg, Vec<Reg>),
+    Select(Reg, u32, Reg),
+    /// A constructor with an argument.
+    Con(Reg, u32, Reg),
+    Tag(Reg, Reg),
+    ConArg(Reg, Reg),
+    Exn(Reg, u32, Option<Reg>),
+    ExnId(Reg, Reg),
+    ExnArg(Reg, Reg),
+    Prim(Reg, PrimOp, Vec<Reg>),
+    /// An operation that can raise, and where its exception goes.
+    Checked(Reg, PrimOp, Vec<Reg>, Target),
+    Free(Reg, u32, Reg),
+    Global(Reg, u32),
+    SetGlobal(u32, Reg),
+    /// Closures that can capture each other.
+    Closures(Vec<MakeClosure>),
+    Jump(Label),
+    /// Go to the first label if the bool is true, else the second.
+    If(Reg, Label, Label),
+    Switch(Reg, Vec<(i64, Label)>, Label),
+    Return(Reg),
+    Raise(Reg),
+    Call {
+        callee: Callee,
+        args: Vec<Reg>,
+        ret: Target,
+        handler: Target,
+    },
+    /// A call whose result and exception both go out of the function.
+    TailCall(Callee, Vec<Reg>),
+}
+
+impl Instr {
+    /// The registers the instruction uses and sets.
+    pub fn regs(&self) -> Vec<Reg> {
+        let target = |target: &Target| match target {
+            Target::Out => None,
+            Target::Block(reg, _) => Some(*reg),
+        };
+        let callee = |callee: &Callee| match callee {
+            Callee::Closure(reg) => Some(*reg),
+            Callee::Direct(_) | Callee::Native(_) => None,
+        };
+        match self {
+            Instr::Int(dst, _) | Instr::Const(dst, _) | Instr::Global(dst, _) => vec![*dst],
+            Instr::Move(dst, src)
+            | Instr::Select(dst, _, src)
+            | Instr::Con(dst, _, src)
+            | Instr::Tag(dst, src)
+            | Instr::ConArg(dst, src)
+            | Instr::ExnId(dst, src)
+            | Instr::ExnArg(dst, src)
+            | Instr::Free(dst, _, src) => vec![*dst, *src],
+            Instr::Exn(dst, _, arg) => std::iter::once(*dst).chain(*arg).collect(),
+            Instr::Record(dst, srcs) | Instr::Prim(dst, _, srcs) => {
+                std::iter::once(*dst).chain(srcs.iter().copied()).collect()
+            }
+            Instr::Checked(dst, _, srcs, handler) => std::iter::once(*dst)
+                .chain(srcs.iter().copied())
+                .chain(target(handler))
+                .collect(),
+            Instr::SetGlobal(_, src)
+            | Instr::If(src, _, _)
+            | Instr::Switch(src, _, _)
+            | Instr::Return(src)
+            | Instr::Raise(src) => vec![*src],
+            Instr::Closures(closures) => closures
+                .iter()
+                .flat_map(|closure| {
+                    std::iter::once(closure.dst).chain(closure.free.iter().copied())
+                })
+                .collect(),
+            Instr::Jump(_) => Vec::new(),
+            Instr::Call {
+                callee: f,
+                args,
+                ret,
+                handler,
+            } => callee(f)
+                .into_iter()
+                .chain(args.iter().copied())
+                .chain(target(ret))
+                .chain(target(handler))
+                .collect(),
+            Instr::TailCall(f, args) => callee(f).into_iter().chain(args.iter().copied()).collect(),
+        }
+    }
+
+    /// The labels the instruction can go to.
+    pub fn labels_mut(&mut self) -> Vec<&mut Label> {
+        fn target(target: &mut Target) -> Option<&mut Label> {
+            match target {
+                Target::Out => None,
+                Target::Block(_, label) => Some(label),
+            }
+        }
+        match self {
+            Instr::Checked(_, _, _, handler) => target(handler).into_iter().collect(),
+            Instr::Jump(label) => vec![label],
+            Instr::If(_, a, b) => vec![a, b],
+            Instr::Switch(_, cases, default) => cases
+                .iter_mut()
+                .map(|(_, label)| label)
+                .chain(std::iter::once(default))
+                .collect(),
+            Instr::Call { ret, handler, .. } => {
+                target(ret).into_iter().chain(target(handler)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, constant) in self.constants.iter().enumerate() {
+            match constant {
+                Constant::String(s) => writeln!(f, "const {} = {:?}", i, s)?,
+                Constant::Word(w) => writeln!(f, "const {} = 0wx{:x}", i, w)?,
+                Constant::Real(x) => writeln!(f, "const {} = {:?}", i, x)?,
+            }
+        }
+        write!(f, "{}", self.main)?;
+        for (id, fun) in self.funs.iter().enumerate() {
+            write!(f, "#{} {}", id, fun)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({} params, {} registers)",
+            self.name, self.params, self.registers
+        )?;
+        for (i, instr) in self.code.iter().enumerate() {
+            writeln!(f, "  {:4} {}", i, instr)?;
+        }
+        Ok(())
+    }
+}
+
+fn regs(regs: &[Reg]) -> String {
+    let regs: Vec<_> = regs.iter().map(|reg| format!("r{}", reg)).collect();
+    regs.join(", ")
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Target::Out => write!(f, "out"),
+            Target::Block(reg, label) => write!(f, "r{} @{}", reg, label),
+        }
+    }
+}
+
+impl Display for Callee {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Callee::Closure(reg) => write!(f, "r{}", reg),
+            Callee::Direct(id) => write!(f, "#{}", id),
+            Callee::Native(i) => write!(f, "native {}", i),
+        }
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Instr::Int(dst, n) => write!(f, "r{} = {}", dst, n),
+            Instr::Const(dst, i) => write!(f, "r{} = const {}", dst, i),
+            Instr::Move(dst, src) => write!(f, "r{} = r{}", dst, src),
+            Instr::Record(dst, fields) => write!(f, "r{} = {{{}}}", dst, regs(fields)),
+            Instr::Select(dst, i, src) => write!(f, "r{} = select {} r{}", dst, i, src),
+            Instr::Con(dst, tag, arg) => write!(f, "r{} = con {} r{}", dst, tag, arg),
+            Instr::Tag(dst, src) => write!(f, "r{} = tag r{}", dst, src),
+            Instr::ConArg(dst, src) => write!(f, "r{} = con_arg r{}", dst, src),
+            Instr::Exn(dst, id, None) => write!(f, "r{} = exn {}", dst, id),
+            Instr::Exn(dst, id, Some(arg)) => write!(f, "r{} = exn {} r{}", dst, id, arg),
+            Instr::ExnId(dst, src) => write!(f, "r{} = exn_id r{}", dst, src),
+            Instr::ExnArg(dst, src) => write!(f, "r{} = exn_arg r{}", dst, src),
+            Instr::Prim(dst, op, args) => write!(f, "r{} = {} {}", dst, op.name(), regs(args)),
+            Instr::Checked(dst, op, args, handler) => write!(
+                f,
+                "r{} = {} {} else {}",
+                dst,
+                op.name(),
+                regs(args),
+                handler
+            ),
+            Instr::Free(dst, i, closure) => write!(f, "r{} = free {} r{}", dst, i, closure),
+            Instr::Global(dst, global) => write!(f, "r{} = global {}", dst, global),
+            Instr::SetGlobal(global, src) => write!(f, "global {} = r{}", global, src),
+            Instr::Closures(closures) => {
+                let closures: Vec<_> = closures
+                    .iter()
+                    .map(|closure| {
+                        format!(
+                            "r{} = #{} [{}]",
+                            closure.dst,
+                            closure.fun,
+                            regs(&closure.free)
+                        )
+                    })
+                    .collect();
+                write!(f, "closures {}", closures.join(" and "))
+            }
+            Instr::Jump(label) => write!(f, "jump @{}", label),
+            Instr::If(cond, a, b) => write!(f, "if r{} then @{} else @{}", cond, a, b),
+            Instr::Switch(scrutinee, cases, default) => {
+                let cases: Vec<_> = cases
+                    .iter()
+                    .map(|(n, label)| format!("{} => @{}", n, label))
+                    .collect();
+                write!(
+                    f,
+                    "switch r{} {{ {}, _ => @{} }}",
+                    scrutinee,
+                    cases.join(", "),
+                    default
+                )
+            }
+            Instr::Return(src) => write!(f, "return r{}", src),
+            Instr::Raise(src) => write!(f, "raise r{}", src),
+            Instr::Call {
+                callee,
+                args,
+                ret,
+                handler,
+            } => write!(
+                f,
+                "call {} ({}) -> {}, {}",
+                callee,
+                regs(args),
+                ret,
+                handler
+            ),
+            Instr::TailCall(callee, args) => write!(f, "tail call {} ({})", callee, regs(args)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::ir::{self, closure};
+    use crate::types::Checker;
+    use crate::{fixity, matching};
+    use parsegen::SourceMap;
+
+    /// Compile a program, without the Basis.
+    fn compile_str(src: &str) -> Module {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.sml", src);
+        let mut program = crate::lower::parse(&sources, file).unwrap();
+        fixity::resolve(&mut program).unwrap();
+        let mut checker = Checker::new();
+        checker.check_program(&program).unwrap();
+        let (matches, _) = matching::compile_program(&program, &checker.info, &checker.tycons);
+        let mut ir = ir::lower(&[program], &checker.info, &matches).unwrap();
+        closure::convert(&mut ir);
+        compile(&ir)
+    }
+
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run a module with a small heap, giving what it printed, what it
+    /// raised, and how many collections there were.
+    fn run(module: &Module) -> (String, Result<(), Uncaught>, usize) {
+        let out = Capture::default();
+        let mut machine = Machine::with_output(module, Box::new(out.clone())).with_heap(64);
+        let result = machine.run();
+        let printed = String::from_utf8(out.0.borrow().clone()).unwrap();
+        (printed, result, machine.stats().collections)
+    }
+
+    #[test]
+    fn closures_and_exceptions() {
+        let module = compile_str(
+            "exception E of string
+             fun adder n = fn x => x + n
+             val add2 = adder 2
+             val _ = if add2 40 = 42 then print \"closure\\n\" else ()
+             fun f 0 = raise E \"zero\" | f n = n
+             val _ = f 0 handle E s => (print s; print \"\\n\"; 0)
+             val _ = (1 div 0; ()) handle Div => print \"div\\n\"
+             val r = ref [1, 2]
+             val _ = r := 3 :: !r
+             val _ = if !r = [3, 1, 2] then print \"equal\\n\" else ()
+             val _ = f 0",
+        );
+        let (printed, result, _) = run(&module);
+        assert_eq!(printed, "closure\nzero\ndiv\nequal\n");
+        assert_eq!(result, Err(Uncaught("E".to_owned())));
+        assert_eq!(result.unwrap_err().to_string(), "uncaught exception E");
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // The loop is a tail call, so it runs in one frame, and the sum isn't,
+        // but frames aren't on the machine's own stack.
+        let module = compile_str(
+            "fun loop (0, acc) = acc | loop (n, acc) = loop (n - 1, acc + 1)
+             fun sum 0 = 0 | sum n = n + sum (n - 1)
+             val _ = if loop (1000000, 0) = 1000000 then print \"loop\\n\" else ()
+             val _ = if sum 100000 = 5000050000 then print \"sum\\n\" else ()",
+        );
+        let (printed, result, _) = run(&module);
+        assert_eq!(printed, "loop\nsum\n");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn collecting() {
+        let module = compile_str(
+            "fun build (0, xs) = xs | build (n, xs) = build (n - 1, (n, [n]) :: xs)
+             fun len [] = 0 | len (_ :: xs) = 1 + len xs
+             val kept = build (100, [])
+             fun churn 0 = () | churn n = (build (50, []); churn (n - 1))
+             val _ = churn 100
+             val _ = if len kept = 100 andalso kept = build (100, []) then print \"kept\\n\" else ()",
+        );
+        let (printed, result, collections) = run(&module);
+        assert_eq!(printed, "kept\n");
+        assert_eq!(result, Ok(()));
+        assert!(collections > 10, "{} collections", collections);
+    }
+
+    #[test]
+    fn serialised() {
+        let module = compile_str(
+            "val s = \"h\\233llo\" ^ \"!\"
+             val _ = if s = \"h\\233llo!\" andalso 0w3 + 0w4 = 0w7 then print s else ()
+             val _ = print (if 1.5 < 2.0 then \"\\n\" else \"?\")",
+        );
+        let mut bytes = Vec::new();
+        module.write(&mut bytes).unwrap();
+        let read = Module::read(&mut &bytes[..]).unwrap();
+        assert_eq!(read, module);
+        let (printed, result, _) = run(&read);
+        assert_eq!(printed, "h\u{e9}llo!\n");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn cached() {
+        // Compiling the Basis takes more stack than a test has.
+        let test = || {
+            let dir = std::env::temp_dir().join(format!("smol-cache-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("hello.sml");
+            let cache = Cache::new(&dir);
+            let load = || load(&[path.display().to_string()], Some(&cache)).unwrap();
+
+            std::fs::write(&path, "val _ = print \"hello\\n\"").unwrap();
+            assert_eq!(run(&load()).0, "hello\n");
+            let cached: Vec<_> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+                .collect();
+            assert_eq!(cached.len(), 1, "{:?}", cached);
+
+            // The same file again is read from the cache, without compiling
+            // it, so a different module put there is what's loaded.
+            let other = compile_str("val _ = print \"other\\n\"");
+            let mut bytes = Vec::new();
+            other.write(&mut bytes).unwrap();
+            std::fs::write(&cached[0], bytes).unwrap();
+            assert_eq!(load(), other);
+
+            // A changed file isn't.
+            std::fs::write(&path, "val _ = print \"changed\\n\"").unwrap();
+            assert_eq!(run(&load()).0, "changed\n");
+            std::fs::remove_dir_all(&dir).unwrap();
+        };
+        std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn cached_projects() {
+        let test = || {
+            let dir = std::env::temp_dir().join(format!("smol-projects-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let cache = Cache::new(dir.join("cache"));
+            let project = dir.join("app.mlb");
+            let load = || load(&[project.display().to_string()], Some(&cache)).unwrap();
+
+            std::fs::write(&project, "util.sml main.sml").unwrap();
+            std::fs::write(dir.join("util.sml"), "val greeting = \"hello\\n\"").unwrap();
+            std::fs::write(dir.join("main.sml"), "val _ = print greeting").unwrap();
+            assert_eq!(run(&load()).0, "hello\n");
+            let cached = || -> Vec<_> {
+                let entries = std::fs::read_dir(dir.join("cache")).unwrap();
+                entries.map(|entry| entry.unwrap().path()).collect()
+            };
+            assert_eq!(cached().len(), 1);
+
+            // Loading it again doesn't compile it.
+            let other = compile_str("val _ = print \"other\\n\"");
+            let mut bytes = Vec::new();
+            other.write(&mut bytes).unwrap();
+            std::fs::write(&cached()[0], bytes).unwrap();
+            assert_eq!(load(), other);
+
+            // Changing a file the project mentions compiles it again.
+            std::fs::write(dir.join("util.sml"), "val greeting = \"changed\\n\"").unwrap();
+            assert_eq!(run(&load()).0, "changed\n");
+            assert_eq!(cached().len(), 2);
+            std::fs::remove_dir_all(&dir).unwrap();
+        };
+        std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
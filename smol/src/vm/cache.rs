@@ -0,0 +1,202 @@
+//! Keeping compiled modules on disk.
+//!
+//! `load` compiles a program's files after the Basis the first time it sees
+//! them, and writes the module to the cache, named after a hash of the
+//! build of smol running, the Basis and the files' contents. Running the
+//! same files again reads the module back, so neither they nor the Basis
+//! are parsed, checked or compiled again. A project's files are its `.mlb`
+//! or `.cm` files and the files they mention, so changing any of them
+//! compiles it again.
+//!
+//! The build is told apart by its version and its executable's path, size
+//! and modification time, which change whenever it's rebuilt, so a module
+//! compiled by one build of smol is never run by another. Where the
+//! executable can't be found, nothing is cached.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use parsegen::SourceMap;
+
+use super::{compile, serial, Module};
+use crate::basis;
+use crate::driver::{Compiler, Options};
+use crate::mlb;
+
+/// The extension of files holding a module.
+pub const EXTENSION: &str = "smbc";
+
+/// Whether a file holds a module rather than SML.
+pub fn is_bytecode(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == EXTENSION)
+}
+
+/// A directory of compiled modules.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Cache {
+        Cache { dir: dir.into() }
+    }
+
+    /// The cache in `$SMOL_CACHE`, or else `smol` in the user's cache
+    /// directory, `$XDG_CACHE_HOME` or `~/.cache`. An empty `$SMOL_CACHE`
+    /// turns caching off.
+    pub fn from_env() -> Option<Cache> {
+        if let Some(dir) = env::var_os("SMOL_CACHE") {
+            return (!dir.is_empty()).then(|| Cache::new(dir));
+        }
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(Cache::new(base.join("smol")))
+    }
+
+    fn path(&self, key: u128) -> PathBuf {
+        self.dir.join(format!("{:032x}.{}", key, EXTENSION))
+    }
+
+    /// The module with a key, if it's there and readable.
+    fn get(&self, key: u128) -> Option<Module> {
+        let mut file = fs::File::open(self.path(key)).ok()?;
+        Module::read(&mut file).ok()
+    }
+
+    /// Keep a module. It's written beside where it goes and then moved
+    /// there, so a reader never sees half of it.
+    fn put(&self, key: u128, module: &Module) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let partial = path.with_extension(format!("{}.{}", EXTENSION, process::id()));
+        let mut bytes = Vec::new();
+        module.write(&mut bytes)?;
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)
+    }
+}
+
+/// The module a program's files compile to, with the default options, or,
+/// for a single `.smbc` file, the module in it. The files can be SML, or
+/// `.mlb` or `.cm` projects, whose path variables come from the environment.
+/// Compiled modules are kept in `cache`, and taken from it when the files
+/// are the same. Fails with the rendered errors.
+pub fn load(paths: &[String], cache: Option<&Cache>) -> Result<Module, String> {
+    if let [path] = paths {
+        if is_bytecode(path) {
+            let mut file = fs::File::open(path)
+                .map_err(|err| format!("error: can't read {}: {}\n", path, err))?;
+            return Module::read(&mut file).map_err(|err| format!("error: {}: {}\n", path, err));
+        }
+    }
+    let vars = HashMap::new();
+    // Projects are loaded once without the Basis, which is quick, to find
+    // the files the key covers.
+    let mut sources = SourceMap::new();
+    let mut files = Vec::new();
+    for path in paths {
+        let src = fs::read_to_string(path)
+            .map_err(|err| format!("error: can't read {}: {}\n", path, err))?;
+        let file = sources.add(path, &src);
+        if mlb::is_basis_file(path) {
+            mlb::load(&mut sources, file, &vars).map_err(|diags| {
+                diags
+                    .iter()
+                    .map(|diag| diag.render(&sources))
+                    .collect::<String>()
+            })?;
+        }
+        files.push(src);
+    }
+    let read: Vec<&str> = sources.files().map(|(_, file)| file.src()).collect();
+    let key = build().map(|build| key(&build, &read));
+    if let Some(module) = cache.zip(key).and_then(|(cache, key)| cache.get(key)) {
+        return Ok(module);
+    }
+
+    let mut compiler = Compiler::new();
+    let basis = compiler.sources.files().count();
+    for (path, src) in paths.iter().zip(&files) {
+        let file = compiler.add(path, src);
+        if !mlb::is_basis_file(path) {
+            let checked = compiler
+                .parse(file)
+                .and_then(|program| compiler.check(program));
+            checked.map_err(|diags| compiler.render(&diags))?;
+            continue;
+        }
+        let project = compiler
+            .load_project(file, &vars)
+            .map_err(|diags| compiler.render(&diags))?;
+        for step in &project.steps {
+            let done = match step {
+                mlb::Step::File(i) => compiler
+                    .parse(project.files[*i])
+                    .and_then(|program| compiler.check(program))
+                    .map(|_| ()),
+                step => compiler.step(step),
+            };
+            done.map_err(|diags| compiler.render(&diags))?;
+        }
+    }
+    let program = compiler
+        .lower(Options::default())
+        .map_err(|diags| compiler.render(&diags))?;
+    let module = compile(&program);
+    // A project's files are read again to compile it, so it's only kept if
+    // they're what the key says.
+    if let (Some(cache), Some(key)) = (cache, key) {
+        let compiled = compiler.sources.files().skip(basis);
+        if compiled.map(|(_, file)| file.src()).eq(read) {
+            // A cache that can't be written to is just slower.
+            let _ = cache.put(key, &module);
+        }
+    }
+    Ok(module)
+}
+
+/// What tells this build of smol apart from others: its version, and the
+/// path, size and modification time of the executable running it.
+fn build() -> Option<String> {
+    let exe = env::current_exe().ok()?;
+    let metadata = fs::metadata(&exe).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "{} {} {} {}",
+        env!("CARGO_PKG_VERSION"),
+        exe.display(),
+        metadata.len(),
+        modified.as_nanos()
+    ))
+}
+
+/// A 128 bit FNV-1a hash of the build, the bytecode's version, the Basis
+/// and a program's files, each after its length, so that files can't run
+/// into each other.
+fn key(build: &str, files: &[&str]) -> u128 {
+    const OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+    let mut hash = OFFSET;
+    let mut add = |text: &str| {
+        let len = (text.len() as u64).to_le_bytes();
+        for &b in len.iter().chain(text.as_bytes()) {
+            hash = (hash ^ u128::from(b)).wrapping_mul(PRIME);
+        }
+    };
+    add(&format!("{} {}", build, serial::VERSION));
+    add(basis::PRIM);
+    for (_, src) in basis::FILES {
+        add(src);
+    }
+    files.iter().for_each(|file| add(file));
+    hash
+}
@@ -0,0 +1,382 @@
+//! Compiling closure converted IR to bytecode.
+
+use std::collections::HashMap;
+
+use super::*;
+use crate::ast::Const;
+use crate::ir::{self, Cont, ContDef, Exp, Program, Term, Var};
+
+/// Compile a closure converted program.
+pub fn compile(program: &Program) -> Module {
+    let mut compiler = Compiler {
+        constants: Vec::new(),
+        strings: HashMap::new(),
+        natives: Vec::new(),
+    };
+    let main = compiler.function(program, &program.main);
+    let funs = program
+        .funs
+        .iter()
+        .map(|fun| compiler.function(program, fun))
+        .collect();
+    Module {
+        main,
+        funs,
+        constants: compiler.constants,
+        globals: program.globals,
+        exns: program.exns.clone(),
+        natives: compiler.natives,
+    }
+}
+
+struct Compiler {
+    constants: Vec<Constant>,
+    strings: HashMap<String, u32>,
+    natives: Vec<String>,
+}
+
+impl Compiler {
+    fn constant(&mut self, constant: Constant) -> u32 {
+        if let Constant::String(s) = &constant {
+            if let Some(i) = self.strings.get(s) {
+                return *i;
+            }
+            self.strings.insert(s.clone(), self.constants.len() as u32);
+        }
+        self.constants.push(constant);
+        self.constants.len() as u32 - 1
+    }
+
+    fn native(&mut self, name: &str) -> u32 {
+        match self.natives.iter().position(|native| native == name) {
+            Some(i) => i as u32,
+            None => {
+                self.natives.push(name.to_owned());
+                self.natives.len() as u32 - 1
+            }
+        }
+    }
+
+    fn function(&mut self, program: &Program, fun: &ir::Fun) -> Function {
+        let mut f = FunctionCompiler {
+            compiler: self,
+            regs: HashMap::new(),
+            params: HashMap::new(),
+            labels: HashMap::new(),
+            ret: fun.ret,
+            handler: fun.handler,
+            registers: 0,
+            code: Vec::new(),
+            pending: Vec::new(),
+        };
+        for param in &fun.params {
+            f.bind(*param);
+        }
+        f.bind_term(&fun.body);
+        f.term(&fun.body);
+        while let Some(def) = f.pending.pop() {
+            f.labels.insert(def.cont, f.code.len() as Label);
+            f.term(&def.body);
+        }
+        // Jumps were compiled with continuations for labels.
+        let labels = f.labels;
+        for instr in &mut f.code {
+            for label in instr.labels_mut() {
+                *label = labels[&Cont(*label)];
+            }
+        }
+        Function {
+            name: program.name(fun.name).to_owned(),
+            params: fun.params.len() as u32,
+            registers: f.registers,
+            code: f.code,
+        }
+    }
+}
+
+struct FunctionCompiler<'c, 'p> {
+    compiler: &'c mut Compiler,
+    regs: HashMap<Var, Reg>,
+    /// The parameters of the function's continuations.
+    params: HashMap<Cont, Vec<Var>>,
+    labels: HashMap<Cont, Label>,
+    ret: Cont,
+    handler: Cont,
+    registers: u32,
+    code: Vec<Instr>,
+    /// Continuations whose code hasn't been compiled yet.
+    pending: Vec<&'p ContDef>,
+}
+
+impl<'c, 'p> FunctionCompiler<'c, 'p> {
+    fn fresh(&mut self) -> Reg {
+        self.registers += 1;
+        self.registers - 1
+    }
+
+    fn bind(&mut self, var: Var) {
+        if !self.regs.contains_key(&var) {
+            let reg = self.fresh();
+            self.regs.insert(var, reg);
+        }
+    }
+
+    /// Give registers to the variables a term binds.
+    fn bind_term(&mut self, mut term: &Term) {
+        loop {
+            match term {
+                Term::Let(var, _, body) | Term::Checked { var, body, .. } => {
+                    self.bind(*var);
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    for param in &def.params {
+                        self.bind(*param);
+                    }
+                    self.params.insert(def.cont, def.params.clone());
+                    self.bind_term(&def.body);
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    for closure in closures {
+                        self.bind(closure.var);
+                    }
+                    term = body;
+                }
+                Term::LetFun(_, _) => {
+                    panic!("functions should be closure converted before compiling to bytecode")
+                }
+                Term::Call { .. } | Term::Jump(_, _) | Term::If(_, _, _) | Term::Switch { .. } => {
+                    return
+                }
+            }
+        }
+    }
+
+    fn reg(&self, var: Var) -> Reg {
+        self.regs[&var]
+    }
+
+    fn regs(&self, vars: &[Var]) -> Vec<Reg> {
+        vars.iter().map(|var| self.reg(*var)).collect()
+    }
+
+    /// Where a value given to a continuation goes. Labels are continuations
+    /// until the function is compiled.
+    fn target(&self, cont: Cont, own: Cont) -> Target {
+        if cont == own {
+            Target::Out
+        } else {
+            Target::Block(self.reg(self.params[&cont][0]), cont.0)
+        }
+    }
+
+    /// Move values into registers, all at once: each register gets the
+    /// value the register it's given had before any of them changed.
+    fn moves(&mut self, dsts: Vec<Reg>, srcs: Vec<Reg>) {
+        let mut pending: Vec<(Reg, Reg)> = dsts
+            .into_iter()
+            .zip(srcs)
+            .filter(|(dst, src)| dst != src)
+            .collect();
+        while !pending.is_empty() {
+            // A move is safe once nothing still to be done reads what it
+            // overwrites.
+            let safe = pending
+                .iter()
+                .position(|(dst, _)| pending.iter().all(|(_, src)| src != dst));
+            match safe {
+                Some(i) => {
+                    let (dst, src) = pending.remove(i);
+                    self.code.push(Instr::Move(dst, src));
+                }
+                None => {
+                    // They're in a cycle, so save one of the values.
+                    let saved = pending[0].1;
+                    let scratch = self.fresh();
+                    self.code.push(Instr::Move(scratch, saved));
+                    for (_, src) in &mut pending {
+                        if *src == saved {
+                            *src = scratch;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn term(&mut self, mut term: &'p Term) {
+        loop {
+            match term {
+                Term::Let(var, exp, body) => {
+                    self.exp(*var, exp);
+                    term = body;
+                }
+                Term::Checked {
+                    var,
+                    op,
+                    args,
+                    handler,
+                    body,
+                } => {
+                    let handler = self.target(*handler, self.handler);
+                    self.code.push(Instr::Checked(
+                        self.reg(*var),
+                        *op,
+                        self.regs(args),
+                        handler,
+                    ));
+                    term = body;
+                }
+                Term::LetCont(def, body) => {
+                    self.pending.push(def);
+                    term = body;
+                }
+                Term::LetClosures(closures, body) => {
+                    let closures = closures
+                        .iter()
+                        .map(|closure| MakeClosure {
+                            dst: self.reg(closure.var),
+                            fun: closure.fun,
+                            free: self.regs(&closure.free),
+                        })
+                        .collect();
+                    self.code.push(Instr::Closures(closures));
+                    term = body;
+                }
+                Term::LetFun(_, _) => {
+                    panic!("functions should be closure converted before compiling to bytecode")
+                }
+                Term::Call {
+                    callee,
+                    args,
+                    ret,
+                    handler,
+                } => {
+                    let callee = match callee {
+                        ir::Callee::Closure(f) => Callee::Closure(self.reg(*f)),
+                        ir::Callee::Direct(id) => Callee::Direct(*id),
+                        ir::Callee::Native(name) => Callee::Native(self.compiler.native(name)),
+                    };
+                    let args = self.regs(args);
+                    let ret = self.target(*ret, self.ret);
+                    let handler = self.target(*handler, self.handler);
+                    self.code.push(match (ret, handler) {
+                        (Target::Out, Target::Out) => Instr::TailCall(callee, args),
+                        _ => Instr::Call {
+                            callee,
+                            args,
+                            ret,
+                            handler,
+                        },
+                    });
+                    return;
+                }
+                Term::Jump(cont, args) => {
+                    let args = self.regs(args);
+                    let instr = if *cont == self.ret {
+                        Instr::Return(args[0])
+                    } else if *cont == self.handler {
+                        Instr::Raise(args[0])
+                    } else {
+                        let params = self.regs(&self.params[cont].clone());
+                        self.moves(params, args);
+                        Instr::Jump(cont.0)
+                    };
+                    return self.code.push(instr);
+                }
+                Term::If(var, a, b) => {
+                    return self.code.push(Instr::If(self.reg(*var), a.0, b.0));
+                }
+                Term::Switch {
+                    scrutinee,
+                    cases,
+                    default,
+                } => {
+                    let (default, cases) = match default {
+                        Some(default) => (*default, &cases[..]),
+                        None => {
+                            let (last, rest) = cases.split_last().expect("a switch has cases");
+                            (last.1, rest)
+                        }
+                    };
+                    let cases = cases.iter().map(|(n, cont)| (*n, cont.0)).collect();
+                    return self
+                        .code
+                        .push(Instr::Switch(self.reg(*scrutinee), cases, default.0));
+                }
+            }
+        }
+    }
+
+    fn exp(&mut self, var: Var, exp: &Exp) {
+        let dst = self.reg(var);
+        let instr = match exp {
+            Exp::Const(Const::Int(n)) => Instr::Int(dst, *n),
+            Exp::Const(Const::Char(c)) => Instr::Int(dst, *c as i64),
+            Exp::Const(Const::String(s)) => {
+                Instr::Const(dst, self.compiler.constant(Constant::String(s.clone())))
+            }
+            Exp::Const(Const::Word(w)) => {
+                Instr::Const(dst, self.compiler.constant(Constant::Word(*w)))
+            }
+            Exp::Const(Const::Real(x)) => {
+                Instr::Const(dst, self.compiler.constant(Constant::Real(*x)))
+            }
+            Exp::Record(fields) if fields.is_empty() => Instr::Int(dst, 0),
+            Exp::Record(fields) => Instr::Record(dst, self.regs(fields)),
+            Exp::Select(i, record) => Instr::Select(dst, *i as u32, self.reg(*record)),
+            Exp::Con(tag, None) => Instr::Int(dst, *tag as i64),
+            Exp::Con(tag, Some(arg)) => Instr::Con(dst, *tag, self.reg(*arg)),
+            Exp::Tag(value) => Instr::Tag(dst, self.reg(*value)),
+            Exp::ConArg(value) => Instr::ConArg(dst, self.reg(*value)),
+            Exp::Exn(id, arg) => Instr::Exn(dst, *id, arg.map(|arg| self.reg(arg))),
+            Exp::ExnId(value) => Instr::ExnId(dst, self.reg(*value)),
+            Exp::ExnArg(value) => Instr::ExnArg(dst, self.reg(*value)),
+            Exp::Prim(op, args) => Instr::Prim(dst, *op, self.regs(args)),
+            Exp::Free(i, closure) => Instr::Free(dst, *i as u32, self.reg(*closure)),
+            Exp::Global(global) => Instr::Global(dst, *global),
+            Exp::SetGlobal(global, value) => {
+                self.code.push(Instr::SetGlobal(*global, self.reg(*value)));
+                Instr::Int(dst, 0)
+            }
+        };
+        self.code.push(instr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_moves() {
+        let mut compiler = Compiler {
+            constants: Vec::new(),
+            strings: HashMap::new(),
+            natives: Vec::new(),
+        };
+        let mut f = FunctionCompiler {
+            compiler: &mut compiler,
+            regs: HashMap::new(),
+            params: HashMap::new(),
+            labels: HashMap::new(),
+            ret: Cont(0),
+            handler: Cont(1),
+            registers: 4,
+            code: Vec::new(),
+            pending: Vec::new(),
+        };
+        // Swap r0 and r1, and copy r0 to r2 too, leaving r3 alone.
+        f.moves(vec![0, 1, 2, 3], vec![1, 0, 0, 3]);
+        let mut regs = [10, 11, 12, 13, 0];
+        for instr in &f.code {
+            match instr {
+                Instr::Move(dst, src) => regs[*dst as usize] = regs[*src as usize],
+                instr => panic!("expected a move, found {}", instr),
+            }
+        }
+        assert_eq!(regs[..4], [11, 10, 10, 13]);
+        assert_eq!(f.code.len(), 4);
+    }
+}
@@ -0,0 +1,207 @@
+//! The machine's heap, and its mark and sweep collector.
+//!
+//! Objects live in slots, and values refer to them by index, so they never
+//! move. The machine only collects between instructions, when every value it
+//! holds is in a register, a global or a constant, so primitives can allocate
+//! as much as they like without keeping track of what they hold. The heap
+//! collects once as many objects have been allocated since the last
+//! collection as were live after it, or the minimum, whichever is more.
+
+use crate::ir::FunId;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    /// Ints, chars, bools, unit and constructors without arguments.
+    Int(i64),
+    Word(u64),
+    Real(f64),
+    Object(u32),
+}
+
+pub const UNIT: Value = Value::Int(0);
+/// `nil` and `NONE`.
+pub const NIL: Value = Value::Int(0);
+
+impl Value {
+    pub fn bool(b: bool) -> Value {
+        Value::Int(b as i64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Record(Vec<Value>),
+    /// A constructor's tag and its argument.
+    Con(u32, Value),
+    /// An exception's id and its argument, or unit.
+    Exn(u32, Value),
+    Ref(Value),
+    Array(Vec<Value>),
+    Vector(Vec<Value>),
+    Closure(FunId, Vec<Value>),
+    String(String),
+}
+
+impl Object {
+    fn values(&self) -> &[Value] {
+        match self {
+            Object::Record(values)
+            | Object::Array(values)
+            | Object::Vector(values)
+            | Object::Closure(_, values) => values,
+            Object::Con(_, value) | Object::Exn(_, value) | Object::Ref(value) => {
+                std::slice::from_ref(value)
+            }
+            Object::String(_) => &[],
+        }
+    }
+}
+
+/// The fewest objects allocated between collections, by default.
+pub const MIN_OBJECTS: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub collections: usize,
+    pub allocated: usize,
+    pub max_live: usize,
+}
+
+#[derive(Debug)]
+pub struct Heap {
+    slots: Vec<Option<Object>>,
+    marks: Vec<bool>,
+    free: Vec<u32>,
+    /// Objects allocated since the last collection.
+    allocated: usize,
+    /// How many can be before the next.
+    limit: usize,
+    min: usize,
+    pub stats: Stats,
+}
+
+impl Heap {
+    pub fn new(min: usize) -> Heap {
+        Heap {
+            slots: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            allocated: 0,
+            limit: min,
+            min,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn alloc(&mut self, object: Object) -> Value {
+        self.allocated += 1;
+        self.stats.allocated += 1;
+        match self.free.pop() {
+            Some(i) => {
+                self.slots[i as usize] = Some(object);
+                Value::Object(i)
+            }
+            None => {
+                self.slots.push(Some(object));
+                self.marks.push(false);
+                Value::Object(self.slots.len() as u32 - 1)
+            }
+        }
+    }
+
+    /// The object a value refers to.
+    ///
+    /// # Panics
+    ///
+    /// If it isn't an object, or has been collected.
+    pub fn get(&self, value: Value) -> &Object {
+        match value {
+            Value::Object(i) => self.slots[i as usize].as_ref().expect("a live object"),
+            value => panic!("expected an object, found {:?}", value),
+        }
+    }
+
+    pub fn get_mut(&mut self, value: Value) -> &mut Object {
+        match value {
+            Value::Object(i) => self.slots[i as usize].as_mut().expect("a live object"),
+            value => panic!("expected an object, found {:?}", value),
+        }
+    }
+
+    /// How many objects are live, or haven't been collected yet.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether it's time to collect.
+    pub fn due(&self) -> bool {
+        self.allocated >= self.limit
+    }
+
+    /// Free every object the roots don't lead to.
+    pub fn collect<'a>(&mut self, roots: impl IntoIterator<Item = &'a Value>) {
+        let mut stack: Vec<u32> = Vec::new();
+        let mark = |value: &Value, marks: &mut Vec<bool>, stack: &mut Vec<u32>| {
+            if let Value::Object(i) = *value {
+                if !marks[i as usize] {
+                    marks[i as usize] = true;
+                    stack.push(i);
+                }
+            }
+        };
+        for root in roots {
+            mark(root, &mut self.marks, &mut stack);
+        }
+        while let Some(i) = stack.pop() {
+            let object = self.slots[i as usize].as_ref().expect("a live object");
+            for value in object.values() {
+                mark(value, &mut self.marks, &mut stack);
+            }
+        }
+
+        let mut live = 0;
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            if self.marks[i] {
+                self.marks[i] = false;
+                live += 1;
+            } else if slot.take().is_some() {
+                self.free.push(i as u32);
+            }
+        }
+        self.allocated = 0;
+        self.limit = live.max(self.min);
+        self.stats.collections += 1;
+        self.stats.max_live = self.stats.max_live.max(live);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collecting() {
+        let mut heap = Heap::new(4);
+        let s = heap.alloc(Object::String("kept".to_owned()));
+        let r = heap.alloc(Object::Ref(s));
+        let cycle = heap.alloc(Object::Ref(UNIT));
+        *heap.get_mut(cycle) = Object::Ref(cycle);
+        assert!(!heap.due());
+        heap.alloc(Object::Record(vec![Value::Int(1), cycle]));
+        assert!(heap.due());
+
+        heap.collect(&[r, Value::Word(3)]);
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.get(s), &Object::String("kept".to_owned()));
+        assert_eq!(heap.stats.collections, 1);
+
+        // Freed slots are reused.
+        let again = heap.alloc(Object::Vector(Vec::new()));
+        assert!(matches!(again, Value::Object(i) if i >= 2));
+        assert_eq!(heap.len(), 3);
+    }
+}
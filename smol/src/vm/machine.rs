@@ -0,0 +1,559 @@
+//! Running bytecode.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+
+use super::heap::{Heap, Object, Stats, Value, MIN_OBJECTS, UNIT};
+use super::natives::{Native, NATIVES};
+use super::*;
+use crate::ast::{MAX_INT, MIN_INT};
+use crate::eval::Streams;
+use crate::types::BUILTIN_EXNS;
+
+/// An exception the program didn't handle, by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uncaught(pub String);
+
+impl Display for Uncaught {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "uncaught exception {}", self.0)
+    }
+}
+
+impl std::error::Error for Uncaught {}
+
+/// A function's activation: its code, where it's up to, where its
+/// registers start, and where its result and exception go in its caller.
+struct Frame<'m> {
+    code: &'m [Instr],
+    pc: usize,
+    base: usize,
+    ret: Target,
+    handler: Target,
+}
+
+/// What an instruction did.
+enum Flow {
+    Next,
+    /// The function returned a value.
+    Return(Value),
+    /// The function raised an exception.
+    Raise(Value),
+}
+
+pub struct Machine<'m> {
+    module: &'m Module,
+    natives: Vec<&'static Native>,
+    pub(super) heap: Heap,
+    /// Every frame's registers, one after another.
+    regs: Vec<Value>,
+    frames: Vec<Frame<'m>>,
+    /// Where the current frame's registers start.
+    base: usize,
+    globals: Vec<Value>,
+    constants: Vec<Value>,
+    pub(super) streams: Streams,
+    /// The arguments of the call being made.
+    args: Vec<Value>,
+}
+
+impl<'m> Machine<'m> {
+    /// A machine to run a module, writing standard output to stdout.
+    ///
+    /// # Panics
+    ///
+    /// If the module calls a primitive the machine doesn't have, which
+    /// `compile` and `Module::read` never give.
+    pub fn new(module: &'m Module) -> Self {
+        Self::with_output(module, Box::new(io::stdout()))
+    }
+
+    pub fn with_output(module: &'m Module, out: Box<dyn Write>) -> Self {
+        let natives = module
+            .natives
+            .iter()
+            .map(|name| {
+                NATIVES
+                    .iter()
+                    .find(|native| native.name == name)
+                    .unwrap_or_else(|| panic!("no primitive {}", name))
+            })
+            .collect();
+        Machine {
+            module,
+            natives,
+            heap: Heap::new(MIN_OBJECTS),
+            regs: Vec::new(),
+            frames: Vec::new(),
+            base: 0,
+            globals: vec![UNIT; module.globals as usize],
+            constants: Vec::new(),
+            streams: Streams::new(out),
+            args: Vec::new(),
+        }
+    }
+
+    /// Collect once at least `min` objects have been allocated since the
+    /// last collection.
+    pub fn with_heap(mut self, min: usize) -> Self {
+        self.heap = Heap::new(min.max(1));
+        self
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.heap.stats
+    }
+
+    /// Run the program to the end.
+    pub fn run(&mut self) -> Result<(), Uncaught> {
+        self.constants = self
+            .module
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Constant::String(s) => self.heap.alloc(Object::String(s.clone())),
+                Constant::Word(w) => Value::Word(*w),
+                Constant::Real(x) => Value::Real(*x),
+            })
+            .collect();
+        self.enter(&self.module.main, Target::Out, Target::Out);
+        let result = loop {
+            if self.heap.due() {
+                self.collect();
+            }
+            let frame = self.frames.last_mut().expect("a frame");
+            let code = frame.code;
+            let instr = &code[frame.pc];
+            frame.pc += 1;
+            let done = match self.step(instr) {
+                Flow::Next => None,
+                Flow::Return(value) => self.leave(value, false),
+                Flow::Raise(exn) => self.leave(exn, true),
+            };
+            if let Some(result) = done {
+                break result;
+            }
+        };
+        self.streams.flush(1);
+        result
+    }
+
+    fn collect(&mut self) {
+        let roots = self.regs.iter().chain(&self.globals).chain(&self.constants);
+        self.heap.collect(roots);
+    }
+
+    fn get(&self, reg: Reg) -> Value {
+        self.regs[self.base + reg as usize]
+    }
+
+    fn set(&mut self, reg: Reg, value: Value) {
+        self.regs[self.base + reg as usize] = value;
+    }
+
+    /// Push a frame for a function, with `self.args` as its arguments.
+    fn enter(&mut self, fun: &'m Function, ret: Target, handler: Target) {
+        let base = self.regs.len();
+        self.regs.resize(base + fun.registers as usize, UNIT);
+        self.regs[base..base + self.args.len()].copy_from_slice(&self.args);
+        self.base = base;
+        self.frames.push(Frame {
+            code: &fun.code,
+            pc: 0,
+            base,
+            ret,
+            handler,
+        });
+    }
+
+    /// Give a value to a continuation of the current function.
+    fn give(&mut self, target: Target, value: Value, raise: bool) -> Flow {
+        match target {
+            Target::Block(reg, label) => {
+                self.set(reg, value);
+                self.frames.last_mut().expect("a frame").pc = label as usize;
+                Flow::Next
+            }
+            Target::Out if raise => Flow::Raise(value),
+            Target::Out => Flow::Return(value),
+        }
+    }
+
+    /// Return or raise from the current function, and from its callers for
+    /// as long as the value goes out of them too. Leaving the last frame
+    /// ends the program.
+    fn leave(&mut self, value: Value, raise: bool) -> Option<Result<(), Uncaught>> {
+        loop {
+            let frame = self.frames.pop().expect("a frame");
+            self.regs.truncate(frame.base);
+            let caller = match self.frames.last_mut() {
+                Some(caller) => caller,
+                None if raise => return Some(Err(Uncaught(self.exn_name(value).to_owned()))),
+                None => return Some(Ok(())),
+            };
+            self.base = caller.base;
+            match if raise { frame.handler } else { frame.ret } {
+                Target::Block(reg, label) => {
+                    caller.pc = label as usize;
+                    self.regs[self.base + reg as usize] = value;
+                    return None;
+                }
+                Target::Out => continue,
+            }
+        }
+    }
+
+    pub(super) fn exn_name(&self, exn: Value) -> &'m str {
+        match self.heap.get(exn) {
+            Object::Exn(id, _) => &self.module.exns[id],
+            object => panic!("expected an exception, found {:?}", object),
+        }
+    }
+
+    /// A built in exception, by name.
+    fn builtin(&mut self, name: &str) -> Value {
+        let id = BUILTIN_EXNS
+            .iter()
+            .position(|exn| *exn == name)
+            .expect("a built in exception");
+        self.heap.alloc(Object::Exn(id as u32, UNIT))
+    }
+
+    /// Set `self.args` to the arguments of a call, giving the function
+    /// called if it isn't a primitive.
+    fn callee(&mut self, callee: Callee, args: &[Reg]) -> Result<&'m Function, &'static Native> {
+        self.args.clear();
+        let fun = match callee {
+            Callee::Native(i) => {
+                self.args.push(self.get(args[0]));
+                return Err(self.natives[i as usize]);
+            }
+            Callee::Direct(id) => id,
+            Callee::Closure(reg) => {
+                let closure = self.get(reg);
+                self.args.push(closure);
+                match self.heap.get(closure) {
+                    Object::Closure(id, _) => *id,
+                    object => panic!("expected a closure, found {:?}", object),
+                }
+            }
+        };
+        for arg in args {
+            self.args.push(self.get(*arg));
+        }
+        Ok(&self.module.funs[fun])
+    }
+
+    fn step(&mut self, instr: &'m Instr) -> Flow {
+        match instr {
+            Instr::Int(dst, n) => self.set(*dst, Value::Int(*n)),
+            Instr::Const(dst, i) => self.set(*dst, self.constants[*i as usize]),
+            Instr::Move(dst, src) => self.set(*dst, self.get(*src)),
+            Instr::Record(dst, fields) => {
+                let fields = fields.iter().map(|field| self.get(*field)).collect();
+                let record = self.heap.alloc(Object::Record(fields));
+                self.set(*dst, record);
+            }
+            Instr::Select(dst, i, record) => {
+                let field = match self.heap.get(self.get(*record)) {
+                    Object::Record(fields) => fields[*i as usize],
+                    object => panic!("expected a record, found {:?}", object),
+                };
+                self.set(*dst, field);
+            }
+            Instr::Con(dst, tag, arg) => {
+                let value = self.heap.alloc(Object::Con(*tag, self.get(*arg)));
+                self.set(*dst, value);
+            }
+            Instr::Tag(dst, value) => {
+                let tag = match self.get(*value) {
+                    Value::Int(tag) => tag,
+                    value => match self.heap.get(value) {
+                        Object::Con(tag, _) => *tag as i64,
+                        object => panic!("expected a constructor, found {:?}", object),
+                    },
+                };
+                self.set(*dst, Value::Int(tag));
+            }
+            Instr::ConArg(dst, value) => {
+                let arg = match self.heap.get(self.get(*value)) {
+                    Object::Con(_, arg) => *arg,
+                    object => panic!("expected a constructor, found {:?}", object),
+                };
+                self.set(*dst, arg);
+            }
+            Instr::Exn(dst, id, arg) => {
+                let arg = arg.map_or(UNIT, |arg| self.get(arg));
+                let exn = self.heap.alloc(Object::Exn(*id, arg));
+                self.set(*dst, exn);
+            }
+            Instr::ExnId(dst, exn) | Instr::ExnArg(dst, exn) => {
+                let value = match self.heap.get(self.get(*exn)) {
+                    Object::Exn(id, _) if matches!(instr, Instr::ExnId(_, _)) => {
+                        Value::Int(*id as i64)
+                    }
+                    Object::Exn(_, arg) => *arg,
+                    object => panic!("expected an exception, found {:?}", object),
+                };
+                self.set(*dst, value);
+            }
+            Instr::Prim(dst, op, args) => {
+                let value = self.prim(*op, args).expect("an operation that can't raise");
+                self.set(*dst, value);
+            }
+            Instr::Checked(dst, op, args, handler) => match self.prim(*op, args) {
+                Ok(value) => self.set(*dst, value),
+                Err(name) => {
+                    let exn = self.builtin(name);
+                    return self.give(*handler, exn, true);
+                }
+            },
+            Instr::Free(dst, i, closure) => {
+                let value = match self.heap.get(self.get(*closure)) {
+                    Object::Closure(_, free) => free[*i as usize],
+                    object => panic!("expected a closure, found {:?}", object),
+                };
+                self.set(*dst, value);
+            }
+            Instr::Global(dst, global) => self.set(*dst, self.globals[*global as usize]),
+            Instr::SetGlobal(global, src) => self.globals[*global as usize] = self.get(*src),
+            Instr::Closures(closures) => {
+                // Make them all before capturing anything, since they can
+                // capture each other.
+                for closure in closures {
+                    let value = self.heap.alloc(Object::Closure(closure.fun, Vec::new()));
+                    self.set(closure.dst, value);
+                }
+                for closure in closures {
+                    let free = closure.free.iter().map(|reg| self.get(*reg)).collect();
+                    *self.heap.get_mut(self.get(closure.dst)) = Object::Closure(closure.fun, free);
+                }
+            }
+            Instr::Jump(label) => self.jump(*label),
+            Instr::If(cond, a, b) => {
+                let label = if self.get(*cond) == Value::Int(0) {
+                    b
+                } else {
+                    a
+                };
+                self.jump(*label);
+            }
+            Instr::Switch(scrutinee, cases, default) => {
+                let n = int(self.get(*scrutinee));
+                let label = cases
+                    .iter()
+                    .find(|(case, _)| *case == n)
+                    .map_or(default, |(_, label)| label);
+                self.jump(*label);
+            }
+            Instr::Return(src) => return Flow::Return(self.get(*src)),
+            Instr::Raise(src) => return Flow::Raise(self.get(*src)),
+            Instr::Call {
+                callee,
+                args,
+                ret,
+                handler,
+            } => match self.callee(*callee, args) {
+                Ok(fun) => self.enter(fun, *ret, *handler),
+                Err(native) => {
+                    return match (native.apply)(self, self.args[0]) {
+                        Ok(value) => self.give(*ret, value, false),
+                        Err(name) => {
+                            let exn = self.builtin(name);
+                            self.give(*handler, exn, true)
+                        }
+                    }
+                }
+            },
+            Instr::TailCall(callee, args) => match self.callee(*callee, args) {
+                Ok(fun) => {
+                    let frame = self.frames.pop().expect("a frame");
+                    self.regs.truncate(frame.base);
+                    self.enter(fun, frame.ret, frame.handler);
+                }
+                Err(native) => {
+                    return match (native.apply)(self, self.args[0]) {
+                        Ok(value) => Flow::Return(value),
+                        Err(name) => Flow::Raise(self.builtin(name)),
+                    }
+                }
+            },
+        }
+        Flow::Next
+    }
+
+    fn jump(&mut self, label: Label) {
+        self.frames.last_mut().expect("a frame").pc = label as usize;
+    }
+
+    /// Apply an operation, or give the name of the built in exception it
+    /// raises.
+    fn prim(&mut self, op: PrimOp, args: &[Reg]) -> Result<Value, &'static str> {
+        use PrimOp::*;
+        let mut values = [UNIT; 2];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = self.get(*arg);
+        }
+        let arg = |i: usize| values[i];
+        let ints = || (int(arg(0)), int(arg(1)));
+        let words = || (word(arg(0)), word(arg(1)));
+        let reals = || (real(arg(0)), real(arg(1)));
+        let value = match op {
+            IntAdd => checked_int(ints().0.checked_add(ints().1))?,
+            IntSub => checked_int(ints().0.checked_sub(ints().1))?,
+            IntMul => checked_int(ints().0.checked_mul(ints().1))?,
+            IntDiv | IntMod => {
+                let (x, y) = ints();
+                if y == 0 {
+                    return Err("Div");
+                }
+                let q = x.checked_div(y);
+                let r = x % y;
+                // Truncating division rounds towards zero, so adjust when
+                // the signs differ.
+                let floor = r != 0 && (r < 0) != (y < 0);
+                checked_int(match op {
+                    IntDiv if floor => q.map(|q| q - 1),
+                    IntDiv => q,
+                    _ if floor => Some(r + y),
+                    _ => Some(r),
+                })?
+            }
+            IntNeg => checked_int(int(arg(0)).checked_neg())?,
+            IntAbs => checked_int(int(arg(0)).checked_abs())?,
+            WordDiv | WordMod => {
+                let (x, y) = words();
+                if y == 0 {
+                    return Err("Div");
+                }
+                Value::Word(if op == WordDiv { x / y } else { x % y })
+            }
+            IntLt => Value::bool(ints().0 < ints().1),
+            IntLe => Value::bool(ints().0 <= ints().1),
+            IntGt => Value::bool(ints().0 > ints().1),
+            IntGe => Value::bool(ints().0 >= ints().1),
+            IntEq => Value::bool(ints().0 == ints().1),
+            WordAdd => Value::Word(words().0.wrapping_add(words().1)),
+            WordSub => Value::Word(words().0.wrapping_sub(words().1)),
+            WordMul => Value::Word(words().0.wrapping_mul(words().1)),
+            WordNeg => Value::Word(word(arg(0)).wrapping_neg()),
+            WordLt => Value::bool(words().0 < words().1),
+            WordLe => Value::bool(words().0 <= words().1),
+            WordGt => Value::bool(words().0 > words().1),
+            WordGe => Value::bool(words().0 >= words().1),
+            RealAdd => Value::Real(reals().0 + reals().1),
+            RealSub => Value::Real(reals().0 - reals().1),
+            RealMul => Value::Real(reals().0 * reals().1),
+            RealDiv => Value::Real(reals().0 / reals().1),
+            RealNeg => Value::Real(-real(arg(0))),
+            RealAbs => Value::Real(real(arg(0)).abs()),
+            RealLt => Value::bool(reals().0 < reals().1),
+            RealLe => Value::bool(reals().0 <= reals().1),
+            RealGt => Value::bool(reals().0 > reals().1),
+            RealGe => Value::bool(reals().0 >= reals().1),
+            StringLt | StringLe | StringGt | StringGe => {
+                let ordering = self.string(arg(0)).cmp(self.string(arg(1)));
+                Value::bool(match op {
+                    StringLt => ordering.is_lt(),
+                    StringLe => ordering.is_le(),
+                    StringGt => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                })
+            }
+            StringConcat => {
+                let s = format!("{}{}", self.string(arg(0)), self.string(arg(1)));
+                self.heap.alloc(Object::String(s))
+            }
+            Equal => Value::bool(self.equal(arg(0), arg(1))),
+            Not => Value::bool(arg(0) == Value::Int(0)),
+            Ref => {
+                let value = arg(0);
+                self.heap.alloc(Object::Ref(value))
+            }
+            Deref => match self.heap.get(arg(0)) {
+                Object::Ref(value) => *value,
+                object => panic!("expected a ref, found {:?}", object),
+            },
+            Assign => {
+                let value = arg(1);
+                *self.heap.get_mut(arg(0)) = Object::Ref(value);
+                UNIT
+            }
+            Print => {
+                // Output is best effort, as in the Basis' `print`.
+                let s = self.string(arg(0)).to_owned();
+                self.streams.output(1, &s);
+                self.streams.flush(1);
+                UNIT
+            }
+        };
+        Ok(value)
+    }
+
+    pub(super) fn string(&self, value: Value) -> &str {
+        match self.heap.get(value) {
+            Object::String(s) => s,
+            object => panic!("expected a string, found {:?}", object),
+        }
+    }
+
+    /// Structural equality, except for refs and arrays, which are only equal
+    /// to themselves.
+    fn equal(&self, a: Value, b: Value) -> bool {
+        let mut pairs = vec![(a, b)];
+        while let Some((a, b)) = pairs.pop() {
+            if a == b {
+                continue;
+            }
+            let (a, b) = match (a, b) {
+                (Value::Object(_), Value::Object(_)) => (self.heap.get(a), self.heap.get(b)),
+                _ => return false,
+            };
+            match (a, b) {
+                (Object::Record(xs), Object::Record(ys))
+                | (Object::Vector(xs), Object::Vector(ys))
+                    if xs.len() == ys.len() =>
+                {
+                    pairs.extend(xs.iter().copied().zip(ys.iter().copied()))
+                }
+                (Object::Con(t, x), Object::Con(u, y)) | (Object::Exn(t, x), Object::Exn(u, y))
+                    if t == u =>
+                {
+                    pairs.push((*x, *y))
+                }
+                (Object::String(s), Object::String(t)) if s == t => (),
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// An int, or `Overflow` if there isn't one or it doesn't fit in 63 bits.
+pub(super) fn checked_int(n: Option<i64>) -> Result<Value, &'static str> {
+    match n {
+        Some(n) if (MIN_INT..=MAX_INT).contains(&n) => Ok(Value::Int(n)),
+        _ => Err("Overflow"),
+    }
+}
+
+pub(super) fn int(value: Value) -> i64 {
+    match value {
+        Value::Int(n) => n,
+        value => panic!("expected an int, found {:?}", value),
+    }
+}
+
+pub(super) fn word(value: Value) -> u64 {
+    match value {
+        Value::Word(w) => w,
+        value => panic!("expected a word, found {:?}", value),
+    }
+}
+
+pub(super) fn real(value: Value) -> f64 {
+    match value {
+        Value::Real(x) => x,
+        value => panic!("expected a real, found {:?}", value),
+    }
+}
@@ -0,0 +1,378 @@
+//! The primitives of the Basis' structure `Prim`, as the interpreter has them
+//! (see `eval::NATIVES`), for the machine.
+//!
+//! Each takes its argument, a tuple if it has more than one, and gives its
+//! result, or the name of the built in exception it raises. Characters are
+//! Unicode scalar values, and string positions count characters.
+
+use std::convert::TryFrom;
+
+use super::heap::{Object, Value, NIL, UNIT};
+use super::machine::{checked_int, int, real, word};
+use super::Machine;
+use crate::ast::Const;
+use crate::eval::{scan_real, show_real, Input};
+
+/// A function implemented by the machine.
+pub struct Native {
+    pub name: &'static str,
+    pub(super) apply: fn(&mut Machine, Value) -> Result<Value, &'static str>,
+}
+
+macro_rules! native {
+    ($name:expr, |$m:pat_param, $arg:pat_param| $body:expr) => {
+        Native {
+            name: $name,
+            apply: |$m, $arg| Ok($body),
+        }
+    };
+}
+
+pub const NATIVES: &[Native] = &[
+    native!("exnName", |m, exn| {
+        let name = m.exn_name(exn);
+        string(m, name)
+    }),
+    // Int
+    native!("intToString", |m, n| string(
+        m,
+        &Const::Int(int(n)).to_string()
+    )),
+    native!("intQuot", |m, arg| {
+        let (a, b) = ints(m, arg);
+        if b == 0 {
+            return Err("Div");
+        }
+        checked_int(a.checked_div(b))?
+    }),
+    native!("intRem", |m, arg| {
+        let (a, b) = ints(m, arg);
+        if b == 0 {
+            return Err("Div");
+        }
+        Value::Int(a.wrapping_rem(b))
+    }),
+    // Word
+    native!("wordFromInt", |_, n| Value::Word(int(n) as u64)),
+    native!("wordToInt", |_, w| checked_int(
+        i64::try_from(word(w)).ok()
+    )?),
+    native!("wordToIntX", |_, w| checked_int(Some(word(w) as i64))?),
+    native!("wordAndb", |m, arg| words(m, arg, |a, b| a & b)),
+    native!("wordOrb", |m, arg| words(m, arg, |a, b| a | b)),
+    native!("wordXorb", |m, arg| words(m, arg, |a, b| a ^ b)),
+    native!("wordNotb", |_, w| Value::Word(!word(w))),
+    native!("wordShl", |m, arg| words(m, arg, |a, b| {
+        a.checked_shl(u32::try_from(b).unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    })),
+    native!("wordShr", |m, arg| words(m, arg, |a, b| {
+        a.checked_shr(u32::try_from(b).unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    })),
+    native!("wordAshr", |m, arg| words(m, arg, |a, b| {
+        // Shifting by the word size or more fills with the sign bit.
+        (a as i64 >> b.min(63)) as u64
+    })),
+    // Real
+    native!("realFromInt", |_, n| Value::Real(int(n) as f64)),
+    native!("realToString", |m, x| string(m, &show_real(real(x)))),
+    native!("realScan", |m, s| match scan_real(m.string(s)) {
+        Some((x, len)) => {
+            let pair = m
+                .heap
+                .alloc(Object::Record(vec![Value::Real(x), Value::Int(len as i64)]));
+            some(m, pair)
+        }
+        None => NIL,
+    }),
+    native!("realFloor", |_, x| to_int(real(x).floor())?),
+    native!("realCeil", |_, x| to_int(real(x).ceil())?),
+    native!("realRound", |_, x| to_int(real(x).round_ties_even())?),
+    native!("realTrunc", |_, x| to_int(real(x).trunc())?),
+    // Char
+    native!("charOrd", |_, c| c),
+    native!("charChr", |_, n| match u8::try_from(int(n)) {
+        Ok(c) => Value::Int(c as i64),
+        Err(_) => return Err("Chr"),
+    }),
+    // String
+    native!("stringSize", |m, s| Value::Int(size(m.string(s)) as i64)),
+    native!("stringSub", |m, arg| {
+        let fields = fields(m, arg);
+        let (s, i) = (m.string(fields[0]), int(fields[1]));
+        let c = usize::try_from(i).ok().and_then(|i| {
+            if s.is_ascii() {
+                s.as_bytes().get(i).map(|&b| b as char)
+            } else {
+                s.chars().nth(i)
+            }
+        });
+        Value::Int(c.ok_or("Subscript")? as i64)
+    }),
+    native!("stringExtract", |m, arg| {
+        let fields = fields(m, arg);
+        let (s, i, n) = (m.string(fields[0]), int(fields[1]), int(fields[2]));
+        let len = size(s) as i64;
+        if i < 0 || n < 0 || i > len - n {
+            return Err("Subscript");
+        }
+        let (i, n) = (i as usize, n as usize);
+        let extract = if s.is_ascii() {
+            s[i..i + n].to_owned()
+        } else {
+            s.chars().skip(i).take(n).collect()
+        };
+        m.heap.alloc(Object::String(extract))
+    }),
+    native!("stringConcat", |m, ss| {
+        let s: String = list(m, ss).into_iter().map(|s| m.string(s)).collect();
+        m.heap.alloc(Object::String(s))
+    }),
+    native!("stringImplode", |m, cs| {
+        let s = list(m, cs)
+            .into_iter()
+            .map(|c| char::from_u32(int(c) as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        m.heap.alloc(Object::String(s))
+    }),
+    native!("stringExplode", |m, s| {
+        let chars = m.string(s).chars().map(|c| Value::Int(c as i64)).collect();
+        make_list(m, chars)
+    }),
+    // Vector
+    native!("vectorFromList", |m, xs| {
+        let items = list(m, xs);
+        m.heap.alloc(Object::Vector(items))
+    }),
+    native!("vectorLength", |m, v| Value::Int(items(m, v).len() as i64)),
+    native!("vectorSub", |m, arg| {
+        let (v, i) = pair(m, arg);
+        index(items(m, v), int(i))?
+    }),
+    // Array
+    native!("arrayArray", |m, arg| {
+        let (n, x) = pair(m, arg);
+        let n = usize::try_from(int(n)).map_err(|_| "Size")?;
+        m.heap.alloc(Object::Array(vec![x; n]))
+    }),
+    native!("arrayFromList", |m, xs| {
+        let items = list(m, xs);
+        m.heap.alloc(Object::Array(items))
+    }),
+    native!("arrayLength", |m, a| Value::Int(items(m, a).len() as i64)),
+    native!("arraySub", |m, arg| {
+        let (a, i) = pair(m, arg);
+        index(items(m, a), int(i))?
+    }),
+    native!("arrayUpdate", |m, arg| {
+        let fields = fields(m, arg);
+        let items = match m.heap.get_mut(fields[0]) {
+            Object::Array(items) => items,
+            object => panic!("expected an array, found {:?}", object),
+        };
+        let slot = usize::try_from(int(fields[1]))
+            .ok()
+            .and_then(|i| items.get_mut(i))
+            .ok_or("Subscript")?;
+        *slot = fields[2];
+        UNIT
+    }),
+    native!("arrayVector", |m, a| {
+        let items = items(m, a).to_vec();
+        m.heap.alloc(Object::Vector(items))
+    }),
+    // TextIO
+    native!("ioOpenIn", |m, name| {
+        let name = m.string(name).to_owned();
+        let res = m.streams.open_in(&name);
+        opened(m, res)
+    }),
+    native!("ioOpenOut", |m, name| {
+        let name = m.string(name).to_owned();
+        let res = m.streams.open_out(&name, false);
+        opened(m, res)
+    }),
+    native!("ioOpenAppend", |m, name| {
+        let name = m.string(name).to_owned();
+        let res = m.streams.open_out(&name, true);
+        opened(m, res)
+    }),
+    native!("ioClose", |m, id| {
+        m.streams.close(int(id) as usize);
+        UNIT
+    }),
+    native!("ioOutput", |m, arg| {
+        let (id, s) = pair(m, arg);
+        let s = m.string(s).to_owned();
+        Value::bool(m.streams.output(int(id) as usize, &s))
+    }),
+    native!("ioFlush", |m, id| Value::bool(
+        m.streams.flush(int(id) as usize)
+    )),
+    native!("ioInput", |m, arg| {
+        let (id, n) = pair(m, arg);
+        let mut s = String::new();
+        if let Some(input) = m.streams.input(int(id) as usize) {
+            for _ in 0..int(n) {
+                match input.read_char() {
+                    Some(c) => s.push(c),
+                    None => break,
+                }
+            }
+        }
+        m.heap.alloc(Object::String(s))
+    }),
+    native!("ioPeek", |m, arg| {
+        let (id, n) = pair(m, arg);
+        let n = usize::try_from(int(n)).unwrap_or(usize::MAX);
+        match m
+            .streams
+            .input(int(id) as usize)
+            .and_then(|input| input.peek(n))
+        {
+            Some(c) => some(m, Value::Int(c as i64)),
+            None => NIL,
+        }
+    }),
+    native!("ioInputLine", |m, id| {
+        match m.streams.input(int(id) as usize).and_then(Input::read_line) {
+            Some(line) => {
+                let line = m.heap.alloc(Object::String(line));
+                some(m, line)
+            }
+            None => NIL,
+        }
+    }),
+    native!("ioInputAll", |m, id| {
+        let s = match m.streams.input(int(id) as usize) {
+            Some(input) => input.read_all(),
+            None => String::new(),
+        };
+        m.heap.alloc(Object::String(s))
+    }),
+    native!("ioEndOfStream", |m, id| Value::bool(
+        m.streams
+            .input(int(id) as usize)
+            .and_then(|input| input.peek(0))
+            .is_none()
+    )),
+];
+
+/// A stream's id and an empty error, or ~1 and the error opening it.
+fn opened(m: &mut Machine, res: std::io::Result<usize>) -> Value {
+    let (id, error) = match res {
+        Ok(id) => (id as i64, String::new()),
+        Err(err) => (-1, err.to_string()),
+    };
+    let error = m.heap.alloc(Object::String(error));
+    m.heap.alloc(Object::Record(vec![Value::Int(id), error]))
+}
+
+fn string(m: &mut Machine, s: &str) -> Value {
+    m.heap.alloc(Object::String(s.to_owned()))
+}
+
+fn some(m: &mut Machine, value: Value) -> Value {
+    m.heap.alloc(Object::Con(1, value))
+}
+
+/// The fields of a tuple argument.
+fn fields(m: &Machine, arg: Value) -> Vec<Value> {
+    match m.heap.get(arg) {
+        Object::Record(fields) => fields.clone(),
+        object => panic!("expected a tuple, found {:?}", object),
+    }
+}
+
+fn pair(m: &Machine, arg: Value) -> (Value, Value) {
+    match fields(m, arg)[..] {
+        [a, b] => (a, b),
+        ref fields => panic!("expected a pair, found {:?}", fields),
+    }
+}
+
+fn ints(m: &Machine, arg: Value) -> (i64, i64) {
+    let (a, b) = pair(m, arg);
+    (int(a), int(b))
+}
+
+fn words(m: &Machine, arg: Value, f: fn(u64, u64) -> u64) -> Value {
+    let (a, b) = pair(m, arg);
+    Value::Word(f(word(a), word(b)))
+}
+
+/// The elements of a vector or array.
+fn items<'a>(m: &'a Machine, value: Value) -> &'a [Value] {
+    match m.heap.get(value) {
+        Object::Vector(items) | Object::Array(items) => items,
+        object => panic!("expected a vector or array, found {:?}", object),
+    }
+}
+
+fn index(items: &[Value], i: i64) -> Result<Value, &'static str> {
+    usize::try_from(i)
+        .ok()
+        .and_then(|i| items.get(i))
+        .copied()
+        .ok_or("Subscript")
+}
+
+/// The elements of a list. A cons cell is constructor 1 of a pair, and
+/// `nil` is 0.
+fn list(m: &Machine, mut xs: Value) -> Vec<Value> {
+    let mut items = Vec::new();
+    while xs != NIL {
+        let cell = match m.heap.get(xs) {
+            Object::Con(1, cell) => *cell,
+            object => panic!("expected a list, found {:?}", object),
+        };
+        let (x, rest) = pair(m, cell);
+        items.push(x);
+        xs = rest;
+    }
+    items
+}
+
+fn make_list(m: &mut Machine, items: Vec<Value>) -> Value {
+    items.into_iter().rev().fold(NIL, |rest, x| {
+        let cell = m.heap.alloc(Object::Record(vec![x, rest]));
+        m.heap.alloc(Object::Con(1, cell))
+    })
+}
+
+fn to_int(x: f64) -> Result<Value, &'static str> {
+    if x.is_nan() {
+        Err("Domain")
+    } else if x < i64::MIN as f64 || x >= i64::MAX as f64 {
+        Err("Overflow")
+    } else {
+        checked_int(Some(x as i64))
+    }
+}
+
+/// The number of characters in a string.
+fn size(s: &str) -> usize {
+    if s.is_ascii() {
+        s.len()
+    } else {
+        s.chars().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_interpreters_natives() {
+        let mut names: Vec<_> = NATIVES.iter().map(|native| native.name).collect();
+        let mut prims: Vec<_> = crate::eval::NATIVES
+            .iter()
+            .map(|native| native.name)
+            .collect();
+        names.sort_unstable();
+        prims.sort_unstable();
+        assert_eq!(names, prims);
+    }
+}
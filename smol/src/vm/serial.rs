@@ -0,0 +1,730 @@
+//! Writing modules in a compact binary form, and reading them back.
+//!
+//! A module starts with `MAGIC` and the format's version. Unsigned numbers
+//! are LEB128, signed ones zigzag encoded first, reals their bits, strings
+//! their length in bytes and then UTF-8, and sequences their length and then
+//! their items. Each instruction is an opcode and then its operands, with
+//! operations as their index in `PrimOp::ALL`.
+//!
+//! Reading checks everything the machine relies on: registers are in their
+//! frame, labels, functions, constants, globals and primitives exist, and
+//! code can't run off its end.
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+
+use super::*;
+
+pub const MAGIC: &[u8; 4] = b"smol";
+pub const VERSION: u64 = 1;
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// It isn't a module.
+    Magic,
+    /// It's a module written by a different version of smol.
+    Version(u64),
+    Malformed(String),
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "{}", err),
+            ReadError::Magic => write!(f, "not a bytecode module"),
+            ReadError::Version(version) => write!(
+                f,
+                "bytecode version {} isn't supported (expected {})",
+                version, VERSION
+            ),
+            ReadError::Malformed(why) => write!(f, "malformed bytecode: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        ReadError::Io(err)
+    }
+}
+
+fn malformed<T>(why: impl Into<String>) -> Result<T, ReadError> {
+    Err(ReadError::Malformed(why.into()))
+}
+
+impl Module {
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(MAGIC);
+        w.uint(VERSION);
+        w.function(&self.main);
+        w.len(self.funs.len());
+        for fun in &self.funs {
+            w.function(fun);
+        }
+        w.len(self.constants.len());
+        for constant in &self.constants {
+            match constant {
+                Constant::String(s) => {
+                    w.byte(0);
+                    w.string(s);
+                }
+                Constant::Word(n) => {
+                    w.byte(1);
+                    w.uint(*n);
+                }
+                Constant::Real(x) => {
+                    w.byte(2);
+                    w.0.extend_from_slice(&x.to_bits().to_le_bytes());
+                }
+            }
+        }
+        w.uint(self.globals.into());
+        w.len(self.exns.len());
+        for (id, name) in &self.exns {
+            w.uint((*id).into());
+            w.string(name);
+        }
+        w.len(self.natives.len());
+        for name in &self.natives {
+            w.string(name);
+        }
+        out.write_all(&w.0)
+    }
+
+    pub fn read(input: &mut impl Read) -> Result<Module, ReadError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        if !bytes.starts_with(MAGIC) {
+            return Err(ReadError::Magic);
+        }
+        let mut r = Reader {
+            bytes: &bytes,
+            pos: MAGIC.len(),
+        };
+        let version = r.uint()?;
+        if version != VERSION {
+            return Err(ReadError::Version(version));
+        }
+        let main = r.function()?;
+        let funs = r.seq(Reader::function)?;
+        let constants = r.seq(|r| match r.byte()? {
+            0 => Ok(Constant::String(r.string()?)),
+            1 => Ok(Constant::Word(r.uint()?)),
+            2 => {
+                let bits = r.take(8)?;
+                Ok(Constant::Real(f64::from_bits(u64::from_le_bytes(
+                    bits.try_into().unwrap(),
+                ))))
+            }
+            kind => malformed(format!("constant kind {}", kind)),
+        })?;
+        let globals = r.u32()?;
+        let exns = r
+            .seq(|r| Ok((r.u32()?, r.string()?)))?
+            .into_iter()
+            .collect();
+        let natives = r.seq(Reader::string)?;
+        if r.pos != bytes.len() {
+            return malformed("bytes after the end");
+        }
+        let module = Module {
+            main,
+            funs,
+            constants,
+            globals,
+            exns,
+            natives,
+        };
+        module.validate()?;
+        Ok(module)
+    }
+
+    fn validate(&self) -> Result<(), ReadError> {
+        if self.main.params != 0 {
+            return malformed("the top level takes arguments");
+        }
+        for name in &self.natives {
+            if !NATIVES.iter().any(|native| native.name == name) {
+                return malformed(format!("no primitive {}", name));
+            }
+        }
+        for fun in std::iter::once(&self.main).chain(&self.funs) {
+            self.validate_function(fun)
+                .map_err(|why| ReadError::Malformed(format!("in {}: {}", fun.name, why)))?;
+        }
+        Ok(())
+    }
+
+    fn validate_function(&self, fun: &Function) -> Result<(), String> {
+        if fun.params > fun.registers {
+            return Err("more parameters than registers".to_owned());
+        }
+        // Closures are given themselves and their argument.
+        let fun_id = |id: FunId, params: usize| match self.funs.get(id) {
+            Some(fun) if fun.params as usize == params => Ok(()),
+            Some(_) => Err(format!("the wrong number of arguments for #{}", id)),
+            None => Err(format!("no function #{}", id)),
+        };
+        for (pc, instr) in fun.code.iter().enumerate() {
+            if let Some(reg) = instr.regs().into_iter().find(|reg| *reg >= fun.registers) {
+                return Err(format!("no register r{} at {}", reg, pc));
+            }
+            let mut instr = instr.clone();
+            if let Some(label) = instr
+                .labels_mut()
+                .into_iter()
+                .find(|label| **label as usize >= fun.code.len())
+            {
+                return Err(format!("no label @{} at {}", label, pc));
+            }
+            match &instr {
+                Instr::Const(_, i) if *i as usize >= self.constants.len() => {
+                    return Err(format!("no constant {} at {}", i, pc))
+                }
+                Instr::Global(_, global) | Instr::SetGlobal(global, _)
+                    if *global >= self.globals =>
+                {
+                    return Err(format!("no global {} at {}", global, pc))
+                }
+                Instr::Closures(closures) => {
+                    for closure in closures {
+                        fun_id(closure.fun, 2)?;
+                    }
+                }
+                Instr::Call { callee, args, .. } | Instr::TailCall(callee, args) => match callee {
+                    Callee::Direct(id) => fun_id(*id, args.len())?,
+                    Callee::Closure(_) if args.len() != 1 => {
+                        return Err(format!("a closure takes one argument at {}", pc))
+                    }
+                    Callee::Native(i) if *i as usize >= self.natives.len() => {
+                        return Err(format!("no primitive {} at {}", i, pc))
+                    }
+                    Callee::Native(_) if args.len() != 1 => {
+                        return Err(format!("a primitive takes one argument at {}", pc))
+                    }
+                    _ => {}
+                },
+                Instr::Prim(_, op, args) | Instr::Checked(_, op, args, _) => {
+                    let arity = match op {
+                        PrimOp::IntNeg
+                        | PrimOp::IntAbs
+                        | PrimOp::WordNeg
+                        | PrimOp::RealNeg
+                        | PrimOp::RealAbs
+                        | PrimOp::Not
+                        | PrimOp::Ref
+                        | PrimOp::Deref
+                        | PrimOp::Print => 1,
+                        _ => 2,
+                    };
+                    if args.len() != arity {
+                        return Err(format!("{} takes {} arguments at {}", op.name(), arity, pc));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let ends = matches!(
+            fun.code.last(),
+            Some(
+                Instr::Jump(_)
+                    | Instr::If(_, _, _)
+                    | Instr::Switch(_, _, _)
+                    | Instr::Return(_)
+                    | Instr::Raise(_)
+                    | Instr::Call { .. }
+                    | Instr::TailCall(_, _)
+            )
+        );
+        if !ends {
+            return Err("the code runs off its end".to_owned());
+        }
+        Ok(())
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+
+    fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                return self.byte(byte);
+            }
+            self.byte(byte | 0x80);
+        }
+    }
+
+    fn int(&mut self, n: i64) {
+        self.uint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn len(&mut self, len: usize) {
+        self.uint(len as u64);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.len(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn regs(&mut self, regs: &[Reg]) {
+        self.len(regs.len());
+        for reg in regs {
+            self.uint((*reg).into());
+        }
+    }
+
+    fn target(&mut self, target: Target) {
+        match target {
+            Target::Out => self.byte(0),
+            Target::Block(reg, label) => {
+                self.byte(1);
+                self.uint(reg.into());
+                self.uint(label.into());
+            }
+        }
+    }
+
+    fn callee(&mut self, callee: Callee) {
+        match callee {
+            Callee::Closure(reg) => {
+                self.byte(0);
+                self.uint(reg.into());
+            }
+            Callee::Direct(id) => {
+                self.byte(1);
+                self.uint(id as u64);
+            }
+            Callee::Native(i) => {
+                self.byte(2);
+                self.uint(i.into());
+            }
+        }
+    }
+
+    fn op(&mut self, op: PrimOp) {
+        let i = PrimOp::ALL.iter().position(|o| *o == op).unwrap();
+        self.byte(i as u8);
+    }
+
+    fn function(&mut self, fun: &Function) {
+        self.string(&fun.name);
+        self.uint(fun.params.into());
+        self.uint(fun.registers.into());
+        self.len(fun.code.len());
+        for instr in &fun.code {
+            self.instr(instr);
+        }
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        let uint = |w: &mut Self, n: u32| w.uint(n.into());
+        match instr {
+            Instr::Int(dst, n) => {
+                self.byte(0);
+                uint(self, *dst);
+                self.int(*n);
+            }
+            Instr::Const(dst, i) => {
+                self.byte(1);
+                uint(self, *dst);
+                uint(self, *i);
+            }
+            Instr::Move(dst, src) => {
+                self.byte(2);
+                uint(self, *dst);
+                uint(self, *src);
+            }
+            Instr::Record(dst, fields) => {
+                self.byte(3);
+                uint(self, *dst);
+                self.regs(fields);
+            }
+            Instr::Select(dst, i, src) => {
+                self.byte(4);
+                uint(self, *dst);
+                uint(self, *i);
+                uint(self, *src);
+            }
+            Instr::Con(dst, tag, arg) => {
+                self.byte(5);
+                uint(self, *dst);
+                uint(self, *tag);
+                uint(self, *arg);
+            }
+            Instr::Tag(dst, src) => {
+                self.byte(6);
+                uint(self, *dst);
+                uint(self, *src);
+            }
+            Instr::ConArg(dst, src) => {
+                self.byte(7);
+                uint(self, *dst);
+                uint(self, *src);
+            }
+            Instr::Exn(dst, id, arg) => {
+                self.byte(8);
+                uint(self, *dst);
+                uint(self, *id);
+                match arg {
+                    None => self.byte(0),
+                    Some(arg) => {
+                        self.byte(1);
+                        uint(self, *arg);
+                    }
+                }
+            }
+            Instr::ExnId(dst, src) => {
+                self.byte(9);
+                uint(self, *dst);
+                uint(self, *src);
+            }
+            Instr::ExnArg(dst, src) => {
+                self.byte(10);
+                uint(self, *dst);
+                uint(self, *src);
+            }
+            Instr::Prim(dst, op, args) => {
+                self.byte(11);
+                uint(self, *dst);
+                self.op(*op);
+                self.regs(args);
+            }
+            Instr::Checked(dst, op, args, handler) => {
+                self.byte(12);
+                uint(self, *dst);
+                self.op(*op);
+                self.regs(args);
+                self.target(*handler);
+            }
+            Instr::Free(dst, i, closure) => {
+                self.byte(13);
+                uint(self, *dst);
+                uint(self, *i);
+                uint(self, *closure);
+            }
+            Instr::Global(dst, global) => {
+                self.byte(14);
+                uint(self, *dst);
+                uint(self, *global);
+            }
+            Instr::SetGlobal(global, src) => {
+                self.byte(15);
+                uint(self, *global);
+                uint(self, *src);
+            }
+            Instr::Closures(closures) => {
+                self.byte(16);
+                self.len(closures.len());
+                for closure in closures {
+                    uint(self, closure.dst);
+                    self.uint(closure.fun as u64);
+                    self.regs(&closure.free);
+                }
+            }
+            Instr::Jump(label) => {
+                self.byte(17);
+                uint(self, *label);
+            }
+            Instr::If(cond, a, b) => {
+                self.byte(18);
+                uint(self, *cond);
+                uint(self, *a);
+                uint(self, *b);
+            }
+            Instr::Switch(scrutinee, cases, default) => {
+                self.byte(19);
+                uint(self, *scrutinee);
+                self.len(cases.len());
+                for (n, label) in cases {
+                    self.int(*n);
+                    uint(self, *label);
+                }
+                uint(self, *default);
+            }
+            Instr::Return(src) => {
+                self.byte(20);
+                uint(self, *src);
+            }
+            Instr::Raise(src) => {
+                self.byte(21);
+                uint(self, *src);
+            }
+            Instr::Call {
+                callee,
+                args,
+                ret,
+                handler,
+            } => {
+                self.byte(22);
+                self.callee(*callee);
+                self.regs(args);
+                self.target(*ret);
+                self.target(*handler);
+            }
+            Instr::TailCall(callee, args) => {
+                self.byte(23);
+                self.callee(*callee);
+                self.regs(args);
+            }
+        }
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, n: usize) -> Result<&'b [u8], ReadError> {
+        match self.bytes.get(self.pos..self.pos.saturating_add(n)) {
+            Some(bytes) => {
+                self.pos += n;
+                Ok(bytes)
+            }
+            None => malformed("it ends too soon"),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, ReadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, ReadError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        malformed("a number is too long")
+    }
+
+    fn int(&mut self) -> Result<i64, ReadError> {
+        let n = self.uint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn u32(&mut self) -> Result<u32, ReadError> {
+        let n = self.uint()?;
+        u32::try_from(n).or_else(|_| malformed(format!("{} is too big", n)))
+    }
+
+    /// The length of a sequence, which can't be more than the bytes left,
+    /// since every item takes at least one.
+    fn len(&mut self) -> Result<usize, ReadError> {
+        let len = self.uint()?;
+        if len > (self.bytes.len() - self.pos) as u64 {
+            return malformed("a sequence is longer than the module");
+        }
+        Ok(len as usize)
+    }
+
+    fn seq<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, ReadError>,
+    ) -> Result<Vec<T>, ReadError> {
+        let len = self.len()?;
+        (0..len).map(|_| item(self)).collect()
+    }
+
+    fn string(&mut self) -> Result<String, ReadError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).or_else(|_| malformed("a string isn't UTF-8"))
+    }
+
+    fn regs(&mut self) -> Result<Vec<Reg>, ReadError> {
+        self.seq(Reader::u32)
+    }
+
+    fn target(&mut self) -> Result<Target, ReadError> {
+        match self.byte()? {
+            0 => Ok(Target::Out),
+            1 => Ok(Target::Block(self.u32()?, self.u32()?)),
+            kind => malformed(format!("target kind {}", kind)),
+        }
+    }
+
+    fn callee(&mut self) -> Result<Callee, ReadError> {
+        match self.byte()? {
+            0 => Ok(Callee::Closure(self.u32()?)),
+            1 => Ok(Callee::Direct(self.uint()? as FunId)),
+            2 => Ok(Callee::Native(self.u32()?)),
+            kind => malformed(format!("callee kind {}", kind)),
+        }
+    }
+
+    fn op(&mut self) -> Result<PrimOp, ReadError> {
+        let i = self.byte()?;
+        match PrimOp::ALL.get(i as usize) {
+            Some(op) => Ok(*op),
+            None => malformed(format!("operation {}", i)),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, ReadError> {
+        Ok(Function {
+            name: self.string()?,
+            params: self.u32()?,
+            registers: self.u32()?,
+            code: self.seq(Reader::instr)?,
+        })
+    }
+
+    fn instr(&mut self) -> Result<Instr, ReadError> {
+        let instr = match self.byte()? {
+            0 => Instr::Int(self.u32()?, self.int()?),
+            1 => Instr::Const(self.u32()?, self.u32()?),
+            2 => Instr::Move(self.u32()?, self.u32()?),
+            3 => Instr::Record(self.u32()?, self.regs()?),
+            4 => Instr::Select(self.u32()?, self.u32()?, self.u32()?),
+            5 => Instr::Con(self.u32()?, self.u32()?, self.u32()?),
+            6 => Instr::Tag(self.u32()?, self.u32()?),
+            7 => Instr::ConArg(self.u32()?, self.u32()?),
+            8 => {
+                let (dst, id) = (self.u32()?, self.u32()?);
+                let arg = match self.byte()? {
+                    0 => None,
+                    1 => Some(self.u32()?),
+                    kind => return malformed(format!("exception kind {}", kind)),
+                };
+                Instr::Exn(dst, id, arg)
+            }
+            9 => Instr::ExnId(self.u32()?, self.u32()?),
+            10 => Instr::ExnArg(self.u32()?, self.u32()?),
+            11 => Instr::Prim(self.u32()?, self.op()?, self.regs()?),
+            12 => Instr::Checked(self.u32()?, self.op()?, self.regs()?, self.target()?),
+            13 => Instr::Free(self.u32()?, self.u32()?, self.u32()?),
+            14 => Instr::Global(self.u32()?, self.u32()?),
+            15 => Instr::SetGlobal(self.u32()?, self.u32()?),
+            16 => Instr::Closures(self.seq(|r| {
+                Ok(MakeClosure {
+                    dst: r.u32()?,
+                    fun: r.uint()? as FunId,
+                    free: r.regs()?,
+                })
+            })?),
+            17 => Instr::Jump(self.u32()?),
+            18 => Instr::If(self.u32()?, self.u32()?, self.u32()?),
+            19 => Instr::Switch(
+                self.u32()?,
+                self.seq(|r| Ok((r.int()?, r.u32()?)))?,
+                self.u32()?,
+            ),
+            20 => Instr::Return(self.u32()?),
+            21 => Instr::Raise(self.u32()?),
+            22 => Instr::Call {
+                callee: self.callee()?,
+                args: self.regs()?,
+                ret: self.target()?,
+                handler: self.target()?,
+            },
+            23 => Instr::TailCall(self.callee()?, self.regs()?),
+            opcode => return malformed(format!("opcode {}", opcode)),
+        };
+        Ok(instr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module() -> Module {
+        let main = Function {
+            name: "main".to_owned(),
+            params: 0,
+            registers: 3,
+            code: vec![
+                Instr::Const(0, 0),
+                Instr::Int(1, -5),
+                Instr::Checked(2, PrimOp::IntAdd, vec![1, 1], Target::Block(2, 4)),
+                Instr::Call {
+                    callee: Callee::Native(0),
+                    args: vec![2],
+                    ret: Target::Block(0, 5),
+                    handler: Target::Out,
+                },
+                Instr::Raise(2),
+                Instr::Switch(1, vec![(-5, 6), (i64::MAX, 4)], 4),
+                Instr::Return(0),
+            ],
+        };
+        Module {
+            main,
+            funs: Vec::new(),
+            constants: vec![
+                Constant::String("h\u{e9}llo".to_owned()),
+                Constant::Word(u64::MAX),
+                Constant::Real(-0.5),
+            ],
+            globals: 0,
+            exns: vec![(0, "Bind".to_owned())].into_iter().collect(),
+            natives: vec!["intToString".to_owned()],
+        }
+    }
+
+    fn bytes(module: &Module) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        module.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let module = module();
+        let bytes = bytes(&module);
+        assert_eq!(Module::read(&mut &bytes[..]).unwrap(), module);
+    }
+
+    #[test]
+    fn bad_modules() {
+        let module = module();
+        let mut bytes = bytes(&module);
+        for len in 0..bytes.len() {
+            assert!(Module::read(&mut &bytes[..len]).is_err());
+        }
+        assert!(matches!(
+            Module::read(&mut &b"not a module"[..]),
+            Err(ReadError::Magic)
+        ));
+        bytes[4] = 2;
+        assert!(matches!(
+            Module::read(&mut &bytes[..]),
+            Err(ReadError::Version(2))
+        ));
+
+        let mut broken = module.clone();
+        broken.main.code[0] = Instr::Const(3, 0);
+        let err = Module::read(&mut &self::bytes(&broken)[..]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed bytecode: in main: no register r3 at 0"
+        );
+        let mut broken = module.clone();
+        broken.main.code[5] = Instr::Jump(7);
+        assert!(Module::read(&mut &self::bytes(&broken)[..]).is_err());
+        let mut broken = module.clone();
+        broken.main.code.pop();
+        assert!(Module::read(&mut &self::bytes(&broken)[..]).is_err());
+        let mut broken = module;
+        broken.natives[0] = "launchMissiles".to_owned();
+        assert!(Module::read(&mut &self::bytes(&broken)[..]).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Lexes, parses, lowers and resolves fixity in every program in `tests/corpus`, checking that the whole
+//! file is consumed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use parsegen::{Parser, SourceMap};
+use smol::lexer::tokenize;
+use smol::{Rule, SmlParser};
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sml"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", dir.display());
+    paths
+}
+
+#[test]
+fn lex_corpus() {
+    let mut sources = SourceMap::new();
+    let mut failures = Vec::new();
+    for path in corpus() {
+        let src = fs::read_to_string(&path).unwrap();
+        let file = sources.add(&path.display().to_string(), &src);
+        if let Err(err) = tokenize(file, &src) {
+            failures.push(format!("{}: {}", sources.location(err.span), err));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn lower_corpus() {
+    let mut sources = SourceMap::new();
+    let mut failures = Vec::new();
+    for path in corpus() {
+        let src = fs::read_to_string(&path).unwrap();
+        let file = sources.add(&path.display().to_string(), &src);
+        let res = smol::lower::parse(&sources, file)
+            .and_then(|mut program| smol::fixity::resolve(&mut program));
+        if let Err(diags) = res {
+            failures.extend(diags.iter().map(|d| d.render(&sources)));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn parse_corpus() {
+    let paths = corpus();
+    let mut failures = Vec::new();
+    for path in &paths {
+        let src = fs::read_to_string(path).unwrap();
+        let parsed = SmlParser::parse(Rule::program, &src)
+            .ok()
+            .and_then(|toks| toks.into_iter().next())
+            .map(|root| root.span.end);
+        match parsed {
+            Some(end) if end == src.len() => (),
+            Some(end) => {
+                let line = src[..end].matches('\n').count() + 1;
+                failures.push(format!("{}: stopped at line {}", path.display(), line));
+            }
+            None => failures.push(format!("{}: failed to parse", path.display())),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
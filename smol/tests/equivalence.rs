@@ -0,0 +1,571 @@
+//! Runs each program in `tests/equivalence` in the interpreter, compiled to
+//! native code linked against `smol-runtime` through both LLVM and C, and
+//! compiled to bytecode for `vm::Machine`, and checks that they print the
+//! same things and raise the same uncaught exception.
+//!
+//! Programs are compiled with each representation (see
+//! `ir::Representation`), with and without optimising, checking the IR after
+//! every pass, and some with linked closures or without lambda lifting (see
+//! `ir::closure::Options`). Compiled programs run with a small heap, so the
+//! collector runs often, and the `gc_` programs are written to stress it.
+//! Compiling through LLVM needs `llc` and `cc`, and through C just `cc`;
+//! without them, only the runtime's layout and the virtual machine are
+//! checked. Bytecode goes through being written out and read back, and runs
+//! with a small heap too.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+
+use parsegen::SourceMap;
+use smol::eval::{Interpreter, NATIVES};
+use smol::ir::closure::{self, Closures};
+use smol::ir::Representation;
+use smol::repl::Session;
+use smol::types::{Checker, BUILTIN_EXNS};
+use smol::{basis, codegen, fixity, ir, matching, vm};
+use smol_runtime::value;
+
+/// The heap's first space, in words, which is small enough that every
+/// program collects.
+const HEAP_WORDS: &str = "2048";
+
+/// The fewest objects the virtual machine allocates between collections,
+/// which is few enough that every program collects.
+const VM_OBJECTS: usize = 4096;
+
+fn programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/equivalence");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sml"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", dir.display());
+    paths
+}
+
+/// What a program did: its output, and the name of the exception it didn't
+/// handle, if any.
+#[derive(Debug, Clone, PartialEq)]
+struct Outcome {
+    out: String,
+    uncaught: Option<String>,
+}
+
+/// The exception's name, from a report of it, which the interpreter follows
+/// with its argument.
+fn uncaught(report: &str) -> Option<String> {
+    let exn = report.split("uncaught exception ").nth(1)?;
+    exn.split_whitespace().next().map(str::to_owned)
+}
+
+/// The stack `big_stack` gives a thread, which is as much as the `smol` top
+/// level has, so that deep non-tail recursion in the interpreter fits.
+const STACK_SIZE: usize = 1 << 30;
+
+/// Run `f` on a thread with enough stack for the Basis.
+fn big_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn interpret(path: &Path) -> Outcome {
+    let path = path.display().to_string();
+    big_stack(move || {
+        let out = Capture::default();
+        let mut interp = Interpreter::with_output(Box::new(out.clone()));
+        interp.set_stack_size(STACK_SIZE);
+        let mut session = Session::new(interp);
+        let response = session.use_file(&path);
+        assert!(
+            !response.contains("error"),
+            "{} doesn't compile:\n{}",
+            path,
+            response
+        );
+        let out = String::from_utf8_lossy(&out.0.borrow()).into_owned();
+        Outcome {
+            out,
+            uncaught: uncaught(&response),
+        }
+    })
+}
+
+/// How a program is compiled.
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    representation: Representation,
+    optimise: bool,
+    closures: closure::Options,
+}
+
+const FLAT: closure::Options = closure::Options {
+    closures: Closures::Flat,
+    lift: true,
+};
+
+const CONFIGS: [Config; 6] = [
+    Config {
+        representation: Representation::Uniform,
+        optimise: false,
+        closures: FLAT,
+    },
+    Config {
+        representation: Representation::Specialised,
+        optimise: false,
+        closures: FLAT,
+    },
+    Config {
+        representation: Representation::Uniform,
+        optimise: true,
+        closures: FLAT,
+    },
+    Config {
+        representation: Representation::Specialised,
+        optimise: true,
+        closures: FLAT,
+    },
+    Config {
+        representation: Representation::Uniform,
+        optimise: true,
+        closures: closure::Options {
+            closures: Closures::Linked,
+            lift: true,
+        },
+    },
+    Config {
+        representation: Representation::Specialised,
+        optimise: false,
+        closures: closure::Options {
+            closures: Closures::Linked,
+            lift: false,
+        },
+    },
+];
+
+/// Lower a program after the Basis to closure converted IR, on a thread
+/// with enough stack.
+fn lower(path: &Path, config: Config) -> Result<ir::Program, String> {
+    let path = path.to_owned();
+    big_stack(move || {
+        let src = fs::read_to_string(&path).unwrap();
+        let mut sources = SourceMap::new();
+        let mut checker = Checker::new();
+        let mut fixity = fixity::Env::basis();
+        let mut programs = Vec::new();
+        let mut matches = matching::Matches::new();
+        let mut files = vec![("prim.sml", basis::PRIM)];
+        files.extend(basis::FILES.iter().copied());
+        let name = path.display().to_string();
+        files.push((&name, &src));
+        for (i, (name, src)) in files.into_iter().enumerate() {
+            let file = sources.add(name, src);
+            let render = |diags: Vec<smol::diagnostic::Diagnostic>| {
+                diags.iter().map(|d| d.render(&sources)).collect::<String>()
+            };
+            let mut program = smol::lower::parse(&sources, file).map_err(render)?;
+            fixity::resolve_with(&mut fixity, &mut program).map_err(render)?;
+            checker.check_program(&program).map_err(render)?;
+            if i == 0 {
+                let prim = checker.sigs[basis::PRIM_SIG].env.clone();
+                checker.declare_structure("Prim", prim);
+            }
+            let (compiled, _) = matching::compile_program(&program, &checker.info, &checker.tycons);
+            matches.extend(compiled);
+            programs.push(program);
+        }
+        let mut ir = ir::lower_with(&programs, &checker.info, &matches, config.representation)
+            .map_err(|diags| diags.iter().map(|d| d.render(&sources)).collect::<String>())?;
+        if config.optimise {
+            let options = ir::opt::Options {
+                check: true,
+                ..ir::opt::Options::default()
+            };
+            ir::opt::optimise_with(&mut ir, &options).map_err(|broken| broken.to_string())?;
+        }
+        closure::convert_with(&mut ir, config.closures);
+        if let Err(errors) = ir::check::check(&ir) {
+            let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            return Err(format!("malformed IR:\n{}", errors.join("\n")));
+        }
+        Ok(ir)
+    })
+}
+
+/// Compile a program after the Basis to LLVM IR.
+fn compile(path: &Path, config: Config) -> Result<String, String> {
+    let ir = lower(path, config)?;
+    Ok(big_stack(move || codegen::llvm::emit(&ir)))
+}
+
+/// Compile a program after the Basis to C.
+fn compile_c(path: &Path, config: Config) -> Result<String, String> {
+    let ir = lower(path, config)?;
+    Ok(big_stack(move || codegen::c::emit(&ir)))
+}
+
+/// Compile a program to bytecode, write it out and read it back, and run
+/// it with a small heap.
+fn bytecode(path: &Path, config: Config) -> Result<Outcome, String> {
+    let module = vm::compile(&lower(path, config)?);
+    let mut bytes = Vec::new();
+    module.write(&mut bytes).unwrap();
+    let module = vm::Module::read(&mut &bytes[..]).map_err(|err| err.to_string())?;
+    let out = Capture::default();
+    let mut machine =
+        vm::Machine::with_output(&module, Box::new(out.clone())).with_heap(VM_OBJECTS);
+    let uncaught = machine.run().err().map(|exn| exn.0);
+    let out = String::from_utf8_lossy(&out.0.borrow()).into_owned();
+    Ok(Outcome { out, uncaught })
+}
+
+fn run(command: &mut Command) -> Result<std::process::Output, String> {
+    let out = command
+        .output()
+        .map_err(|err| format!("{:?}: {}", command, err))?;
+    if out.status.success() {
+        Ok(out)
+    } else {
+        Err(format!(
+            "{:?} failed:\n{}",
+            command,
+            String::from_utf8_lossy(&out.stderr)
+        ))
+    }
+}
+
+fn have_cc() -> bool {
+    Command::new("cc").arg("--version").output().is_ok()
+}
+
+/// `llc`'s major version, if it and `cc` are installed.
+fn toolchain() -> Option<u32> {
+    if !have_cc() {
+        return None;
+    }
+    let out = Command::new("llc").arg("--version").output().ok()?;
+    let out = String::from_utf8_lossy(&out.stdout);
+    let version = out.split("version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/// Build the runtime as a static library, in a target directory of its own
+/// so as not to wait on the one running the tests.
+fn runtime(dir: &Path) -> Result<PathBuf, String> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let target = dir.join("target");
+    run(Command::new(cargo)
+        .current_dir(workspace)
+        .args(["build", "--release", "-p", "smol-runtime", "--target-dir"])
+        .arg(&target))?;
+    Ok(target.join("release/libsmol_runtime.a"))
+}
+
+/// A name for what a program compiles to with a config.
+fn name(path: &Path, config: Config) -> String {
+    format!(
+        "{}-{:?}{}{}{}",
+        path.file_stem().unwrap().to_string_lossy(),
+        config.representation,
+        if config.optimise { "-opt" } else { "" },
+        match config.closures.closures {
+            Closures::Flat => "",
+            Closures::Linked => "-linked",
+        },
+        if config.closures.lift {
+            ""
+        } else {
+            "-unlifted"
+        }
+    )
+}
+
+/// Compile through LLVM, link and run a program.
+fn native(
+    path: &Path,
+    config: Config,
+    dir: &Path,
+    runtime: &Path,
+    llc: u32,
+) -> Result<Outcome, String> {
+    let name = name(path, config);
+    let ll = dir.join(format!("{}.ll", name));
+    let object = dir.join(format!("{}.o", name));
+    let exe = dir.join(&name);
+    fs::write(&ll, compile(path, config)?).unwrap();
+
+    let mut command = Command::new("llc");
+    if llc < 15 {
+        command.arg("-opaque-pointers");
+    }
+    run(command
+        .args(["-filetype=obj", "-relocation-model=pic", "-o"])
+        .arg(&object)
+        .arg(&ll))?;
+    run(Command::new("cc")
+        .arg(&object)
+        .arg(runtime)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&exe))?;
+    execute(&exe)
+}
+
+/// Compile through C, link and run a program.
+fn native_c(path: &Path, config: Config, dir: &Path, runtime: &Path) -> Result<Outcome, String> {
+    let name = format!("{}-c", name(path, config));
+    let c = dir.join(format!("{}.c", name));
+    let exe = dir.join(&name);
+    fs::write(&c, compile_c(path, config)?).unwrap();
+    run(Command::new("cc")
+        .args(["-std=c99", "-O2"])
+        .arg(&c)
+        .arg(runtime)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&exe))?;
+    execute(&exe)
+}
+
+/// Run a compiled program with a small heap.
+fn execute(exe: &Path) -> Result<Outcome, String> {
+    let out = Command::new(exe)
+        .env("SMOL_HEAP_WORDS", HEAP_WORDS)
+        .output()
+        .map_err(|err| format!("{}: {}", exe.display(), err))?;
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let uncaught = match out.status.code() {
+        Some(0) => None,
+        Some(1) if uncaught(&stderr).is_some() => uncaught(&stderr),
+        _ => {
+            return Err(format!(
+                "{} failed ({}):\n{}",
+                exe.display(),
+                out.status,
+                stderr
+            ))
+        }
+    };
+    Ok(Outcome {
+        out: String::from_utf8_lossy(&out.stdout).into_owned(),
+        uncaught,
+    })
+}
+
+#[test]
+fn equivalence() {
+    let llc = match toolchain() {
+        Some(llc) => llc,
+        None => {
+            eprintln!("skipping: llc or cc isn't installed");
+            return;
+        }
+    };
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("equivalence");
+    fs::create_dir_all(&dir).unwrap();
+    let runtime = runtime(&dir).unwrap();
+    let mut failures = Vec::new();
+    for path in programs() {
+        let expected = interpret(&path);
+        for config in CONFIGS {
+            match native(&path, config, &dir, &runtime, llc) {
+                Ok(outcome) if outcome == expected => (),
+                Ok(outcome) => failures.push(format!(
+                    "{} ({:?}): compiled, it did\n{:#?}\nbut interpreted\n{:#?}",
+                    path.display(),
+                    config,
+                    outcome,
+                    expected
+                )),
+                Err(err) => failures.push(format!("{} ({:?}): {}", path.display(), config, err)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn c_backend() {
+    if !have_cc() {
+        eprintln!("skipping: cc isn't installed");
+        return;
+    }
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("equivalence-c");
+    fs::create_dir_all(&dir).unwrap();
+    let runtime = runtime(&dir).unwrap();
+    let mut failures = Vec::new();
+    for path in programs() {
+        let expected = interpret(&path);
+        for config in CONFIGS {
+            match native_c(&path, config, &dir, &runtime) {
+                Ok(outcome) if outcome == expected => (),
+                Ok(outcome) => failures.push(format!(
+                    "{} ({:?}): compiled through C, it did\n{:#?}\nbut interpreted\n{:#?}",
+                    path.display(),
+                    config,
+                    outcome,
+                    expected
+                )),
+                Err(err) => failures.push(format!("{} ({:?}): {}", path.display(), config, err)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn virtual_machine() {
+    let mut failures = Vec::new();
+    for path in programs() {
+        let expected = interpret(&path);
+        for config in CONFIGS {
+            match bytecode(&path, config) {
+                Ok(outcome) if outcome == expected => (),
+                Ok(outcome) => failures.push(format!(
+                    "{} ({:?}): as bytecode, it did\n{:#?}\nbut interpreted\n{:#?}",
+                    path.display(),
+                    config,
+                    outcome,
+                    expected
+                )),
+                Err(err) => failures.push(format!("{} ({:?}): {}", path.display(), config, err)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Int constants that don't fit in 63 bits are rejected before any backend
+/// sees them, rather than wrapping when they're tagged.
+#[test]
+fn int_literals() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("equivalence-ints");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("literal.sml");
+    fs::write(&path, "val _ = print (Int.toString 4611686018427387904)\n").unwrap();
+    let message = "int constant doesn't fit in 63 bits";
+    match lower(&path, CONFIGS[0]) {
+        Err(err) => assert!(err.contains(message), "{}", err),
+        Ok(_) => panic!("4611686018427387904 was compiled"),
+    }
+    let response = big_stack(move || {
+        let mut session = Session::new(Interpreter::with_output(Box::new(io::sink())));
+        session.use_file(&path.display().to_string())
+    });
+    assert!(response.contains(message), "{}", response);
+}
+
+/// Compiled code recurses a million deep, and raises `Overflow` rather than
+/// crashing when it runs out of stack, through either backend.
+#[test]
+fn deep_recursion() {
+    if !have_cc() {
+        eprintln!("skipping: cc isn't installed");
+        return;
+    }
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("equivalence-deep");
+    fs::create_dir_all(&dir).unwrap();
+    let runtime = runtime(&dir).unwrap();
+    let path = dir.join("deep.sml");
+    fs::write(
+        &path,
+        "fun count 0 = 0 | count n = 1 + count (n - 1)
+         val () = print (Int.toString (count 1000000) ^ \"\\n\")
+         val () = print (Int.toString (count 100000000) ^ \"\\n\")
+                  handle Overflow => print \"Overflow\\n\"
+         val _ = count 100000000\n",
+    )
+    .unwrap();
+    let expected = Outcome {
+        out: "1000000\nOverflow\n".to_owned(),
+        uncaught: Some("Overflow".to_owned()),
+    };
+    let config = CONFIGS[2];
+    assert_eq!(
+        native_c(&path, config, &dir, &runtime),
+        Ok(expected.clone())
+    );
+    if let Some(llc) = toolchain() {
+        assert_eq!(native(&path, config, &dir, &runtime, llc), Ok(expected));
+    }
+}
+
+/// The runtime agrees with the compiler about how values are laid out, and
+/// has every primitive.
+#[test]
+fn runtime_layout() {
+    use codegen::kind;
+    let kinds = [
+        (kind::RECORD, value::kind::RECORD),
+        (kind::CON, value::kind::CON),
+        (kind::EXN, value::kind::EXN),
+        (kind::REF, value::kind::REF),
+        (kind::ARRAY, value::kind::ARRAY),
+        (kind::VECTOR, value::kind::VECTOR),
+        (kind::CLOSURE, value::kind::CLOSURE),
+        (kind::STRING, value::kind::STRING),
+        (kind::REAL, value::kind::REAL),
+        (kind::WORD, value::kind::WORD),
+    ];
+    for (compiler, runtime) in kinds {
+        assert_eq!(compiler, runtime);
+    }
+    assert_eq!(
+        codegen::header(kind::CON, 5, 1),
+        value::header(kind::CON, 5, 1)
+    );
+    assert_eq!(
+        (codegen::UNIT, codegen::FALSE, codegen::TRUE),
+        (value::UNIT, value::FALSE, value::TRUE)
+    );
+    assert_eq!(
+        (smol::ast::MIN_INT, smol::ast::MAX_INT),
+        (value::MIN_INT, value::MAX_INT)
+    );
+
+    let exns = [
+        ("Bind", value::exn::BIND),
+        ("Match", value::exn::MATCH),
+        ("Div", value::exn::DIV),
+        ("Overflow", value::exn::OVERFLOW),
+        ("Size", value::exn::SIZE),
+        ("Subscript", value::exn::SUBSCRIPT),
+        ("Chr", value::exn::CHR),
+        ("Domain", value::exn::DOMAIN),
+        ("Empty", value::exn::EMPTY),
+        ("Fail", value::exn::FAIL),
+        ("Span", value::exn::SPAN),
+    ];
+    for (name, id) in exns {
+        assert_eq!(BUILTIN_EXNS[id as usize], name);
+    }
+
+    let mut natives: Vec<_> = NATIVES.iter().map(|native| native.name).collect();
+    let mut prims = smol_runtime::natives::NAMES.to_vec();
+    natives.sort_unstable();
+    prims.sort_unstable();
+    assert_eq!(natives, prims);
+}
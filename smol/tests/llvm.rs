@@ -0,0 +1,112 @@
+//! Compiles each program in `tests/llvm` to LLVM IR and compares it with the
+//! `.ll` file next to it. Set `UPDATE_GOLDEN=1` to rewrite them instead.
+//!
+//! If `llc` is installed, the IR is also checked by compiling it.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use parsegen::SourceMap;
+use smol::types::Checker;
+use smol::{codegen, fixity, ir, matching};
+
+fn programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/llvm");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sml"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", dir.display());
+    paths
+}
+
+/// Compile a program, without the Basis.
+fn compile(path: &Path) -> Result<String, String> {
+    let mut sources = SourceMap::new();
+    let src = fs::read_to_string(path).unwrap();
+    let file = sources.add(&path.display().to_string(), &src);
+    let render = |diags: Vec<smol::diagnostic::Diagnostic>| {
+        diags.iter().map(|d| d.render(&sources)).collect::<String>()
+    };
+    let mut program = smol::lower::parse(&sources, file).map_err(render)?;
+    fixity::resolve(&mut program).map_err(render)?;
+    let mut checker = Checker::new();
+    checker.check_program(&program).map_err(render)?;
+    let (matches, _) = matching::compile_program(&program, &checker.info, &checker.tycons);
+    let programs = [program];
+    let mut ir = ir::lower(&programs, &checker.info, &matches).map_err(render)?;
+    ir::closure::convert(&mut ir);
+    if let Err(errors) = ir::check::check(&ir) {
+        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        return Err(format!(
+            "{}: malformed IR:\n{}",
+            path.display(),
+            errors.join("\n")
+        ));
+    }
+    Ok(codegen::llvm::emit(&ir))
+}
+
+/// `llc`'s major version, if it's installed.
+fn llc_version() -> Option<u32> {
+    let out = Command::new("llc").arg("--version").output().ok()?;
+    let out = String::from_utf8_lossy(&out.stdout);
+    let version = out.split("version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+fn llc(path: &Path, version: u32) -> Result<(), String> {
+    let mut command = Command::new("llc");
+    // Older versions need asking for `ptr`.
+    if version < 15 {
+        command.arg("-opaque-pointers");
+    }
+    let out = command
+        .arg(path)
+        .arg("-o")
+        .arg("/dev/null")
+        .output()
+        .map_err(|err| err.to_string())?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&out.stderr).into_owned())
+    }
+}
+
+#[test]
+fn golden() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let llc = llc_version();
+    let mut failures = Vec::new();
+    for path in programs() {
+        let golden = path.with_extension("ll");
+        let module = match compile(&path) {
+            Ok(module) => module,
+            Err(err) => {
+                failures.push(err);
+                continue;
+            }
+        };
+        if update {
+            fs::write(&golden, &module).unwrap();
+        } else if fs::read_to_string(&golden).ok().as_deref() != Some(&module) {
+            failures.push(format!(
+                "{}: output differs from {}, rerun with UPDATE_GOLDEN=1 if it's right",
+                path.display(),
+                golden.display()
+            ));
+            continue;
+        }
+        if let Some(version) = llc {
+            if let Err(err) = self::llc(&golden, version) {
+                failures.push(format!("{}: llc failed:\n{}", golden.display(), err));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
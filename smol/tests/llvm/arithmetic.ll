@@ -0,0 +1,286 @@
+; Generated by smol.
+
+@smol_sp = external global ptr
+@smol_sp_limit = external global ptr
+@smol_exn = external global i64
+@smol_globals = internal global [0 x i64] zeroinitializer
+@exn.name.0 = private constant [5 x i8] c"Bind\00"
+@exn.name.1 = private constant [6 x i8] c"Match\00"
+@exn.name.2 = private constant [4 x i8] c"Div\00"
+@exn.name.3 = private constant [9 x i8] c"Overflow\00"
+@exn.name.4 = private constant [5 x i8] c"Size\00"
+@exn.name.5 = private constant [10 x i8] c"Subscript\00"
+@exn.name.6 = private constant [4 x i8] c"Chr\00"
+@exn.name.7 = private constant [7 x i8] c"Domain\00"
+@exn.name.8 = private constant [6 x i8] c"Empty\00"
+@exn.name.9 = private constant [5 x i8] c"Fail\00"
+@exn.name.10 = private constant [5 x i8] c"Span\00"
+@smol_exn_names = private constant [11 x ptr] [ptr @exn.name.0, ptr @exn.name.1, ptr @exn.name.2, ptr @exn.name.3, ptr @exn.name.4, ptr @exn.name.5, ptr @exn.name.6, ptr @exn.name.7, ptr @exn.name.8, ptr @exn.name.9, ptr @exn.name.10]
+@exn.2 = private constant { i64, i64, i64 } { i64 8589934594, i64 5, i64 1 }, align 8
+@exn.3 = private constant { i64, i64, i64 } { i64 8589934594, i64 7, i64 1 }, align 8
+@str.0 = private constant { i64, [4 x i8] } { i64 17179869191, [4 x i8] c"big\0A" }, align 8
+@str.1 = private constant { i64, [6 x i8] } { i64 25769803783, [6 x i8] c"small\0A" }, align 8
+
+declare ptr @smol_alloc(i64, i64)
+declare i64 @smol_equal(i64, i64)
+declare i64 @smol_string_compare(i64, i64)
+declare i64 @smol_string_concat(i64, i64)
+declare void @smol_print(i64)
+declare void @smol_init(i32, ptr, ptr, i64, ptr, i64)
+declare void @smol_run(ptr)
+declare i32 @smol_finish()
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+declare double @llvm.fabs.f64(double)
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define internal i64 @smol_main() {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 16
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 128, i1 false)
+  %s0.c = getelementptr i64, ptr %fp, i64 0
+  %s1.r = getelementptr i64, ptr %fp, i64 1
+  %s2.c = getelementptr i64, ptr %fp, i64 2
+  %s3.n = getelementptr i64, ptr %fp, i64 3
+  %s4.c = getelementptr i64, ptr %fp, i64 4
+  %s5.n = getelementptr i64, ptr %fp, i64 5
+  %s6.r = getelementptr i64, ptr %fp, i64 6
+  %s7.v = getelementptr i64, ptr %fp, i64 7
+  %s8.n = getelementptr i64, ptr %fp, i64 8
+  %s9.n = getelementptr i64, ptr %fp, i64 9
+  %s10.n = getelementptr i64, ptr %fp, i64 10
+  %s11.unit = getelementptr i64, ptr %fp, i64 11
+  %s12.c = getelementptr i64, ptr %fp, i64 12
+  %s13.v = getelementptr i64, ptr %fp, i64 13
+  %s14.c = getelementptr i64, ptr %fp, i64 14
+  %s15.c = getelementptr i64, ptr %fp, i64 15
+  store i64 21, ptr %s0.c
+  %t0 = load i64, ptr %s0.c
+  %t1 = call tailcc i64 @fact.0(i64 %t0)
+  %t2 = load i64, ptr @smol_exn
+  %t3 = icmp ne i64 %t2, 0
+  br i1 %t3, label %b2, label %b3
+b2:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  store i64 %t1, ptr %s1.r
+  br label %k7
+k7:
+  store i64 15, ptr %s2.c
+  %t4 = load i64, ptr %s1.r
+  %t5 = load i64, ptr %s2.c
+  %t6 = ashr i64 %t4, 1
+  %t7 = ashr i64 %t5, 1
+  %t8 = icmp eq i64 %t7, 0
+  br i1 %t8, label %b4, label %b5
+b4:
+  store i64 ptrtoint (ptr @exn.2 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b5:
+  %t9 = srem i64 %t6, %t7
+  %t10 = icmp ne i64 %t9, 0
+  %t11 = xor i64 %t9, %t7
+  %t12 = icmp slt i64 %t11, 0
+  %t13 = and i1 %t10, %t12
+  %t14 = sdiv i64 %t6, %t7
+  %t15 = zext i1 %t13 to i64
+  %t16 = sub i64 %t14, %t15
+  %t17 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t16, i64 %t16)
+  %t18 = extractvalue { i64, i1 } %t17, 0
+  %t19 = extractvalue { i64, i1 } %t17, 1
+  br i1 %t19, label %b6, label %b7
+b6:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b7:
+  %t20 = or i64 %t18, 1
+  store i64 %t20, ptr %s3.n
+  store i64 11, ptr %s4.c
+  %t21 = load i64, ptr %s3.n
+  %t22 = load i64, ptr %s4.c
+  %t23 = ashr i64 %t21, 1
+  %t24 = ashr i64 %t22, 1
+  %t25 = icmp eq i64 %t24, 0
+  br i1 %t25, label %b8, label %b9
+b8:
+  store i64 ptrtoint (ptr @exn.2 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b9:
+  %t26 = srem i64 %t23, %t24
+  %t27 = icmp ne i64 %t26, 0
+  %t28 = xor i64 %t26, %t24
+  %t29 = icmp slt i64 %t28, 0
+  %t30 = and i1 %t27, %t29
+  %t31 = add i64 %t26, %t24
+  %t32 = select i1 %t30, i64 %t31, i64 %t26
+  %t33 = shl i64 %t32, 1
+  %t34 = or i64 %t33, 1
+  store i64 %t34, ptr %s5.n
+  store i64 7, ptr %s12.c
+  %t35 = load i64, ptr %s5.n
+  %t36 = load i64, ptr %s12.c
+  %t37 = icmp slt i64 %t35, %t36
+  %t38 = select i1 %t37, i64 3, i64 1
+  store i64 %t38, ptr %s13.v
+  %t39 = load i64, ptr %s13.v
+  %t40 = icmp ne i64 %t39, 1
+  br i1 %t40, label %k9, label %k10
+k10:
+  store i64 ptrtoint (ptr @str.0 to i64), ptr %s15.c
+  %t41 = load i64, ptr %s15.c
+  store i64 %t41, ptr %s6.r
+  br label %k8
+k9:
+  store i64 ptrtoint (ptr @str.1 to i64), ptr %s14.c
+  %t42 = load i64, ptr %s14.c
+  store i64 %t42, ptr %s6.r
+  br label %k8
+k8:
+  %t43 = load i64, ptr %s6.r
+  call void @smol_print(i64 %t43)
+  store i64 1, ptr %s7.v
+  %t44 = load i64, ptr %s5.n
+  %t45 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 2, i64 %t44)
+  %t46 = extractvalue { i64, i1 } %t45, 0
+  %t47 = extractvalue { i64, i1 } %t45, 1
+  br i1 %t47, label %b10, label %b11
+b10:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b11:
+  store i64 %t46, ptr %s8.n
+  %t48 = load i64, ptr %s5.n
+  %t49 = icmp slt i64 %t48, 0
+  %t50 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 2, i64 %t48)
+  %t51 = extractvalue { i64, i1 } %t50, 0
+  %t52 = extractvalue { i64, i1 } %t50, 1
+  %t53 = and i1 %t49, %t52
+  br i1 %t53, label %b12, label %b13
+b12:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b13:
+  %t54 = select i1 %t49, i64 %t51, i64 %t48
+  store i64 %t54, ptr %s9.n
+  %t55 = load i64, ptr %s8.n
+  %t56 = load i64, ptr %s9.n
+  %t57 = sub i64 %t56, 1
+  %t58 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t55, i64 %t57)
+  %t59 = extractvalue { i64, i1 } %t58, 0
+  %t60 = extractvalue { i64, i1 } %t58, 1
+  br i1 %t60, label %b14, label %b15
+b14:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b15:
+  store i64 %t59, ptr %s10.n
+  store i64 1, ptr %s11.unit
+  %t61 = load i64, ptr %s11.unit
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t61
+}
+
+define internal tailcc i64 @fact.0(i64 %p0) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 6
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 48, i1 false)
+  %s0.x = getelementptr i64, ptr %fp, i64 0
+  %s1.c = getelementptr i64, ptr %fp, i64 1
+  %s2.c = getelementptr i64, ptr %fp, i64 2
+  %s3.n = getelementptr i64, ptr %fp, i64 3
+  %s4.r = getelementptr i64, ptr %fp, i64 4
+  %s5.n = getelementptr i64, ptr %fp, i64 5
+  store i64 %p0, ptr %s0.x
+  %t0 = load i64, ptr %s0.x
+  switch i64 %t0, label %k6 [ i64 1, label %k4 ]
+k6:
+  store i64 3, ptr %s2.c
+  %t1 = load i64, ptr %s0.x
+  %t2 = load i64, ptr %s2.c
+  %t3 = sub i64 %t2, 1
+  %t4 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 %t1, i64 %t3)
+  %t5 = extractvalue { i64, i1 } %t4, 0
+  %t6 = extractvalue { i64, i1 } %t4, 1
+  br i1 %t6, label %b2, label %b3
+b2:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  store i64 %t5, ptr %s3.n
+  %t7 = load i64, ptr %s3.n
+  %t8 = call tailcc i64 @fact.0(i64 %t7)
+  %t9 = load i64, ptr @smol_exn
+  %t10 = icmp ne i64 %t9, 0
+  br i1 %t10, label %b4, label %b5
+b4:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b5:
+  store i64 %t8, ptr %s4.r
+  br label %k5
+k5:
+  %t11 = load i64, ptr %s0.x
+  %t12 = load i64, ptr %s4.r
+  %t13 = ashr i64 %t11, 1
+  %t14 = sub i64 %t12, 1
+  %t15 = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %t13, i64 %t14)
+  %t16 = extractvalue { i64, i1 } %t15, 0
+  %t17 = extractvalue { i64, i1 } %t15, 1
+  br i1 %t17, label %b6, label %b7
+b6:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b7:
+  %t18 = or i64 %t16, 1
+  store i64 %t18, ptr %s5.n
+  %t19 = load i64, ptr %s5.n
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t19
+k4:
+  store i64 3, ptr %s1.c
+  %t20 = load i64, ptr %s1.c
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t20
+}
+
+define internal void @smol_start() {
+  call i64 @smol_main()
+  ret void
+}
+
+define i32 @main(i32 %argc, ptr %argv) {
+  call void @smol_init(i32 %argc, ptr %argv, ptr @smol_globals, i64 0, ptr @smol_exn_names, i64 11)
+  call void @smol_run(ptr @smol_start)
+  %status = call i32 @smol_finish()
+  ret i32 %status
+}
@@ -0,0 +1,442 @@
+; Generated by smol.
+
+@smol_sp = external global ptr
+@smol_sp_limit = external global ptr
+@smol_exn = external global i64
+@smol_globals = internal global [0 x i64] zeroinitializer
+@exn.name.0 = private constant [5 x i8] c"Bind\00"
+@exn.name.1 = private constant [6 x i8] c"Match\00"
+@exn.name.2 = private constant [4 x i8] c"Div\00"
+@exn.name.3 = private constant [9 x i8] c"Overflow\00"
+@exn.name.4 = private constant [5 x i8] c"Size\00"
+@exn.name.5 = private constant [10 x i8] c"Subscript\00"
+@exn.name.6 = private constant [4 x i8] c"Chr\00"
+@exn.name.7 = private constant [7 x i8] c"Domain\00"
+@exn.name.8 = private constant [6 x i8] c"Empty\00"
+@exn.name.9 = private constant [5 x i8] c"Fail\00"
+@exn.name.10 = private constant [5 x i8] c"Span\00"
+@smol_exn_names = private constant [11 x ptr] [ptr @exn.name.0, ptr @exn.name.1, ptr @exn.name.2, ptr @exn.name.3, ptr @exn.name.4, ptr @exn.name.5, ptr @exn.name.6, ptr @exn.name.7, ptr @exn.name.8, ptr @exn.name.9, ptr @exn.name.10]
+@exn.3 = private constant { i64, i64, i64 } { i64 8589934594, i64 7, i64 1 }, align 8
+@str.0 = private constant { i64, [6 x i8] } { i64 25769803783, [6 x i8] c"wrong\0A" }, align 8
+@str.1 = private constant { i64, [3 x i8] } { i64 12884901895, [3 x i8] c"ok\0A" }, align 8
+
+declare ptr @smol_alloc(i64, i64)
+declare i64 @smol_equal(i64, i64)
+declare i64 @smol_string_compare(i64, i64)
+declare i64 @smol_string_concat(i64, i64)
+declare void @smol_print(i64)
+declare void @smol_init(i32, ptr, ptr, i64, ptr, i64)
+declare void @smol_run(ptr)
+declare i32 @smol_finish()
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+declare double @llvm.fabs.f64(double)
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define internal i64 @smol_main() {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 30
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 240, i1 false)
+  %s0.c = getelementptr i64, ptr %fp, i64 0
+  %s1.r = getelementptr i64, ptr %fp, i64 1
+  %s2.unit = getelementptr i64, ptr %fp, i64 2
+  %s3.r = getelementptr i64, ptr %fp, i64 3
+  %s4.unit = getelementptr i64, ptr %fp, i64 4
+  %s5.r = getelementptr i64, ptr %fp, i64 5
+  %s6.c = getelementptr i64, ptr %fp, i64 6
+  %s7.ref = getelementptr i64, ptr %fp, i64 7
+  %s8.r = getelementptr i64, ptr %fp, i64 8
+  %s9.r = getelementptr i64, ptr %fp, i64 9
+  %s10.v = getelementptr i64, ptr %fp, i64 10
+  %s11.unit = getelementptr i64, ptr %fp, i64 11
+  %s12.r = getelementptr i64, ptr %fp, i64 12
+  %s13.c = getelementptr i64, ptr %fp, i64 13
+  %s14.c = getelementptr i64, ptr %fp, i64 14
+  %s15.unit = getelementptr i64, ptr %fp, i64 15
+  %s16.r = getelementptr i64, ptr %fp, i64 16
+  %s17.c = getelementptr i64, ptr %fp, i64 17
+  %s18.eq = getelementptr i64, ptr %fp, i64 18
+  %s19.v = getelementptr i64, ptr %fp, i64 19
+  %s20.c = getelementptr i64, ptr %fp, i64 20
+  %s21.eq = getelementptr i64, ptr %fp, i64 21
+  %s22.b = getelementptr i64, ptr %fp, i64 22
+  %s23.v = getelementptr i64, ptr %fp, i64 23
+  %s24.c = getelementptr i64, ptr %fp, i64 24
+  %s25.v = getelementptr i64, ptr %fp, i64 25
+  %s26.v = getelementptr i64, ptr %fp, i64 26
+  %s27.r = getelementptr i64, ptr %fp, i64 27
+  %s28.v = getelementptr i64, ptr %fp, i64 28
+  %s29.unit = getelementptr i64, ptr %fp, i64 29
+  store i64 3, ptr %s0.c
+  %t0 = load i64, ptr %s0.c
+  %t1 = call tailcc i64 @add.0(i64 %t0)
+  %t2 = load i64, ptr @smol_exn
+  %t3 = icmp ne i64 %t2, 0
+  br i1 %t3, label %b2, label %b3
+b2:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  store i64 %t1, ptr %s1.r
+  br label %k6
+k6:
+  store i64 1, ptr %s2.unit
+  %t4 = load i64, ptr %s2.unit
+  %t5 = call tailcc i64 @counter.2(i64 %t4)
+  %t6 = load i64, ptr @smol_exn
+  %t7 = icmp ne i64 %t6, 0
+  br i1 %t7, label %b4, label %b5
+b4:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b5:
+  store i64 %t5, ptr %s3.r
+  br label %k11
+k11:
+  store i64 1, ptr %s4.unit
+  %t8 = load i64, ptr %s3.r
+  %t9 = load i64, ptr %s4.unit
+  %t10 = inttoptr i64 %t8 to ptr
+  %t11 = getelementptr i64, ptr %t10, i64 1
+  %t12 = load ptr, ptr %t11
+  %t13 = call tailcc i64 %t12(i64 %t8, i64 %t9)
+  %t14 = load i64, ptr @smol_exn
+  %t15 = icmp ne i64 %t14, 0
+  br i1 %t15, label %b6, label %b7
+b6:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b7:
+  store i64 %t13, ptr %s5.r
+  br label %k12
+k12:
+  store i64 1, ptr %s6.c
+  %t16 = call ptr @smol_alloc(i64 1, i64 4294967299)
+  %t17 = load i64, ptr %s6.c
+  %t18 = getelementptr i64, ptr %t16, i64 1
+  store i64 %t17, ptr %t18
+  %t19 = ptrtoint ptr %t16 to i64
+  store i64 %t19, ptr %s7.ref
+  br label %k14
+k14:
+  %t20 = load i64, ptr %s7.ref
+  %t21 = inttoptr i64 %t20 to ptr
+  %t22 = getelementptr i64, ptr %t21, i64 1
+  %t23 = load i64, ptr %t22
+  store i64 %t23, ptr %s23.v
+  store i64 21, ptr %s24.c
+  %t24 = load i64, ptr %s23.v
+  %t25 = load i64, ptr %s24.c
+  %t26 = icmp slt i64 %t24, %t25
+  %t27 = select i1 %t26, i64 3, i64 1
+  store i64 %t27, ptr %s25.v
+  %t28 = load i64, ptr %s25.v
+  %t29 = icmp ne i64 %t28, 1
+  br i1 %t29, label %k16, label %k17
+k17:
+  store i64 1, ptr %s29.unit
+  %t30 = load i64, ptr %s29.unit
+  store i64 %t30, ptr %s8.r
+  br label %k13
+k16:
+  %t31 = load i64, ptr %s7.ref
+  %t32 = inttoptr i64 %t31 to ptr
+  %t33 = getelementptr i64, ptr %t32, i64 1
+  %t34 = load i64, ptr %t33
+  store i64 %t34, ptr %s26.v
+  %t35 = load i64, ptr %s1.r
+  %t36 = load i64, ptr %s26.v
+  %t37 = inttoptr i64 %t35 to ptr
+  %t38 = getelementptr i64, ptr %t37, i64 1
+  %t39 = load ptr, ptr %t38
+  %t40 = call tailcc i64 %t39(i64 %t35, i64 %t36)
+  %t41 = load i64, ptr @smol_exn
+  %t42 = icmp ne i64 %t41, 0
+  br i1 %t42, label %b8, label %b9
+b8:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b9:
+  store i64 %t40, ptr %s27.r
+  br label %k15
+k15:
+  %t43 = load i64, ptr %s7.ref
+  %t44 = load i64, ptr %s27.r
+  %t45 = inttoptr i64 %t43 to ptr
+  %t46 = getelementptr i64, ptr %t45, i64 1
+  store i64 %t44, ptr %t46
+  store i64 1, ptr %s28.v
+  br label %k14
+k13:
+  store i64 1, ptr %s15.unit
+  %t47 = load i64, ptr %s3.r
+  %t48 = load i64, ptr %s15.unit
+  %t49 = inttoptr i64 %t47 to ptr
+  %t50 = getelementptr i64, ptr %t49, i64 1
+  %t51 = load ptr, ptr %t50
+  %t52 = call tailcc i64 %t51(i64 %t47, i64 %t48)
+  %t53 = load i64, ptr @smol_exn
+  %t54 = icmp ne i64 %t53, 0
+  br i1 %t54, label %b10, label %b11
+b10:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b11:
+  store i64 %t52, ptr %s16.r
+  br label %k20
+k20:
+  store i64 5, ptr %s17.c
+  %t55 = load i64, ptr %s16.r
+  %t56 = load i64, ptr %s17.c
+  %t57 = icmp eq i64 %t55, %t56
+  %t58 = select i1 %t57, i64 3, i64 1
+  store i64 %t58, ptr %s18.eq
+  %t59 = load i64, ptr %s18.eq
+  %t60 = icmp ne i64 %t59, 1
+  br i1 %t60, label %k21, label %k22
+k22:
+  store i64 1, ptr %s22.b
+  %t61 = load i64, ptr %s22.b
+  store i64 %t61, ptr %s12.r
+  br label %k19
+k21:
+  %t62 = load i64, ptr %s7.ref
+  %t63 = inttoptr i64 %t62 to ptr
+  %t64 = getelementptr i64, ptr %t63, i64 1
+  %t65 = load i64, ptr %t64
+  store i64 %t65, ptr %s19.v
+  store i64 21, ptr %s20.c
+  %t66 = load i64, ptr %s19.v
+  %t67 = load i64, ptr %s20.c
+  %t68 = icmp eq i64 %t66, %t67
+  %t69 = select i1 %t68, i64 3, i64 1
+  store i64 %t69, ptr %s21.eq
+  %t70 = load i64, ptr %s21.eq
+  store i64 %t70, ptr %s12.r
+  br label %k19
+k19:
+  %t71 = load i64, ptr %s12.r
+  %t72 = icmp ne i64 %t71, 1
+  br i1 %t72, label %k23, label %k24
+k24:
+  store i64 ptrtoint (ptr @str.0 to i64), ptr %s14.c
+  %t73 = load i64, ptr %s14.c
+  store i64 %t73, ptr %s9.r
+  br label %k18
+k23:
+  store i64 ptrtoint (ptr @str.1 to i64), ptr %s13.c
+  %t74 = load i64, ptr %s13.c
+  store i64 %t74, ptr %s9.r
+  br label %k18
+k18:
+  %t75 = load i64, ptr %s9.r
+  call void @smol_print(i64 %t75)
+  store i64 1, ptr %s10.v
+  store i64 1, ptr %s11.unit
+  %t76 = load i64, ptr %s11.unit
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t76
+}
+
+define internal tailcc i64 @add.0(i64 %p0) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 2
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 16, i1 false)
+  %s0.x = getelementptr i64, ptr %fp, i64 0
+  %s1.add = getelementptr i64, ptr %fp, i64 1
+  store i64 %p0, ptr %s0.x
+  %t0 = call ptr @smol_alloc(i64 2, i64 8589934598)
+  %t1 = getelementptr i64, ptr %t0, i64 1
+  store ptr @add.1, ptr %t1
+  %t2 = ptrtoint ptr %t0 to i64
+  store i64 %t2, ptr %s1.add
+  %t3 = load i64, ptr %s1.add
+  %t4 = inttoptr i64 %t3 to ptr
+  %t5 = getelementptr i64, ptr %t4, i64 2
+  %t6 = load i64, ptr %s0.x
+  store i64 %t6, ptr %t5
+  %t7 = load i64, ptr %s1.add
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t7
+}
+
+define internal tailcc i64 @add.1(i64 %p0, i64 %p1) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 4
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 32, i1 false)
+  %s0.add = getelementptr i64, ptr %fp, i64 0
+  %s1.x = getelementptr i64, ptr %fp, i64 1
+  %s2.x = getelementptr i64, ptr %fp, i64 2
+  %s3.n = getelementptr i64, ptr %fp, i64 3
+  store i64 %p0, ptr %s0.add
+  store i64 %p1, ptr %s1.x
+  %t0 = load i64, ptr %s0.add
+  %t1 = inttoptr i64 %t0 to ptr
+  %t2 = getelementptr i64, ptr %t1, i64 2
+  %t3 = load i64, ptr %t2
+  store i64 %t3, ptr %s2.x
+  %t4 = load i64, ptr %s2.x
+  %t5 = load i64, ptr %s1.x
+  %t6 = sub i64 %t5, 1
+  %t7 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t4, i64 %t6)
+  %t8 = extractvalue { i64, i1 } %t7, 0
+  %t9 = extractvalue { i64, i1 } %t7, 1
+  br i1 %t9, label %b2, label %b3
+b2:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  store i64 %t8, ptr %s3.n
+  %t10 = load i64, ptr %s3.n
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t10
+}
+
+define internal tailcc i64 @counter.2(i64 %p0) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 4
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 32, i1 false)
+  %s0.x = getelementptr i64, ptr %fp, i64 0
+  %s1.c = getelementptr i64, ptr %fp, i64 1
+  %s2.ref = getelementptr i64, ptr %fp, i64 2
+  %s3.fn = getelementptr i64, ptr %fp, i64 3
+  store i64 %p0, ptr %s0.x
+  store i64 1, ptr %s1.c
+  %t0 = call ptr @smol_alloc(i64 1, i64 4294967299)
+  %t1 = load i64, ptr %s1.c
+  %t2 = getelementptr i64, ptr %t0, i64 1
+  store i64 %t1, ptr %t2
+  %t3 = ptrtoint ptr %t0 to i64
+  store i64 %t3, ptr %s2.ref
+  %t4 = call ptr @smol_alloc(i64 2, i64 8589934598)
+  %t5 = getelementptr i64, ptr %t4, i64 1
+  store ptr @fn.3, ptr %t5
+  %t6 = ptrtoint ptr %t4 to i64
+  store i64 %t6, ptr %s3.fn
+  %t7 = load i64, ptr %s3.fn
+  %t8 = inttoptr i64 %t7 to ptr
+  %t9 = getelementptr i64, ptr %t8, i64 2
+  %t10 = load i64, ptr %s2.ref
+  store i64 %t10, ptr %t9
+  %t11 = load i64, ptr %s3.fn
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t11
+}
+
+define internal tailcc i64 @fn.3(i64 %p0, i64 %p1) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 8
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 64, i1 false)
+  %s0.fn = getelementptr i64, ptr %fp, i64 0
+  %s1.x = getelementptr i64, ptr %fp, i64 1
+  %s2.ref = getelementptr i64, ptr %fp, i64 2
+  %s3.v = getelementptr i64, ptr %fp, i64 3
+  %s4.c = getelementptr i64, ptr %fp, i64 4
+  %s5.n = getelementptr i64, ptr %fp, i64 5
+  %s6.v = getelementptr i64, ptr %fp, i64 6
+  %s7.v = getelementptr i64, ptr %fp, i64 7
+  store i64 %p0, ptr %s0.fn
+  store i64 %p1, ptr %s1.x
+  %t0 = load i64, ptr %s0.fn
+  %t1 = inttoptr i64 %t0 to ptr
+  %t2 = getelementptr i64, ptr %t1, i64 2
+  %t3 = load i64, ptr %t2
+  store i64 %t3, ptr %s2.ref
+  %t4 = load i64, ptr %s2.ref
+  %t5 = inttoptr i64 %t4 to ptr
+  %t6 = getelementptr i64, ptr %t5, i64 1
+  %t7 = load i64, ptr %t6
+  store i64 %t7, ptr %s3.v
+  store i64 3, ptr %s4.c
+  %t8 = load i64, ptr %s3.v
+  %t9 = load i64, ptr %s4.c
+  %t10 = sub i64 %t9, 1
+  %t11 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t8, i64 %t10)
+  %t12 = extractvalue { i64, i1 } %t11, 0
+  %t13 = extractvalue { i64, i1 } %t11, 1
+  br i1 %t13, label %b2, label %b3
+b2:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  store i64 %t12, ptr %s5.n
+  %t14 = load i64, ptr %s2.ref
+  %t15 = load i64, ptr %s5.n
+  %t16 = inttoptr i64 %t14 to ptr
+  %t17 = getelementptr i64, ptr %t16, i64 1
+  store i64 %t15, ptr %t17
+  store i64 1, ptr %s6.v
+  %t18 = load i64, ptr %s2.ref
+  %t19 = inttoptr i64 %t18 to ptr
+  %t20 = getelementptr i64, ptr %t19, i64 1
+  %t21 = load i64, ptr %t20
+  store i64 %t21, ptr %s7.v
+  %t22 = load i64, ptr %s7.v
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t22
+}
+
+define internal void @smol_start() {
+  call i64 @smol_main()
+  ret void
+}
+
+define i32 @main(i32 %argc, ptr %argv) {
+  call void @smol_init(i32 %argc, ptr %argv, ptr @smol_globals, i64 0, ptr @smol_exn_names, i64 11)
+  call void @smol_run(ptr @smol_start)
+  %status = call i32 @smol_finish()
+  ret i32 %status
+}
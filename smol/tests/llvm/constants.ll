@@ -0,0 +1,235 @@
+; Generated by smol.
+
+@smol_sp = external global ptr
+@smol_sp_limit = external global ptr
+@smol_exn = external global i64
+@smol_globals = internal global [0 x i64] zeroinitializer
+@exn.name.0 = private constant [5 x i8] c"Bind\00"
+@exn.name.1 = private constant [6 x i8] c"Match\00"
+@exn.name.2 = private constant [4 x i8] c"Div\00"
+@exn.name.3 = private constant [9 x i8] c"Overflow\00"
+@exn.name.4 = private constant [5 x i8] c"Size\00"
+@exn.name.5 = private constant [10 x i8] c"Subscript\00"
+@exn.name.6 = private constant [4 x i8] c"Chr\00"
+@exn.name.7 = private constant [7 x i8] c"Domain\00"
+@exn.name.8 = private constant [6 x i8] c"Empty\00"
+@exn.name.9 = private constant [5 x i8] c"Fail\00"
+@exn.name.10 = private constant [5 x i8] c"Span\00"
+@smol_exn_names = private constant [11 x ptr] [ptr @exn.name.0, ptr @exn.name.1, ptr @exn.name.2, ptr @exn.name.3, ptr @exn.name.4, ptr @exn.name.5, ptr @exn.name.6, ptr @exn.name.7, ptr @exn.name.8, ptr @exn.name.9, ptr @exn.name.10]
+@exn.2 = private constant { i64, i64, i64 } { i64 8589934594, i64 5, i64 1 }, align 8
+@exn.3 = private constant { i64, i64, i64 } { i64 8589934594, i64 7, i64 1 }, align 8
+@str.0 = private constant { i64, [7 x i8] } { i64 30064771079, [7 x i8] c"hello, " }, align 8
+@str.1 = private constant { i64, [6 x i8] } { i64 25769803783, [6 x i8] c"world\0A" }, align 8
+@word.2 = private constant { i64, i64 } { i64 4294967305, i64 7 }, align 8
+@word.3 = private constant { i64, i64 } { i64 4294967305, i64 6 }, align 8
+@word.4 = private constant { i64, i64 } { i64 4294967305, i64 2 }, align 8
+@real.5 = private constant { i64, double } { i64 4294967304, double 0x3FF8000000000000 }, align 8
+@real.6 = private constant { i64, double } { i64 4294967304, double 0x4000000000000000 }, align 8
+@word.7 = private constant { i64, i64 } { i64 4294967305, i64 21 }, align 8
+@real.8 = private constant { i64, double } { i64 4294967304, double 0x4004000000000000 }, align 8
+@str.9 = private constant { i64, [0 x i8] } { i64 7, [0 x i8] c"" }, align 8
+@str.10 = private constant { i64, [5 x i8] } { i64 21474836487, [5 x i8] c"hello" }, align 8
+@str.11 = private constant { i64, [8 x i8] } { i64 34359738375, [8 x i8] c"matched\0A" }, align 8
+
+declare ptr @smol_alloc(i64, i64)
+declare i64 @smol_equal(i64, i64)
+declare i64 @smol_string_compare(i64, i64)
+declare i64 @smol_string_concat(i64, i64)
+declare void @smol_print(i64)
+declare void @smol_init(i32, ptr, ptr, i64, ptr, i64)
+declare void @smol_run(ptr)
+declare i32 @smol_finish()
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+declare double @llvm.fabs.f64(double)
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define internal i64 @smol_main() {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 27
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 216, i1 false)
+  %s0.c = getelementptr i64, ptr %fp, i64 0
+  %s1.c = getelementptr i64, ptr %fp, i64 1
+  %s2.v = getelementptr i64, ptr %fp, i64 2
+  %s3.c = getelementptr i64, ptr %fp, i64 3
+  %s4.c = getelementptr i64, ptr %fp, i64 4
+  %s5.v = getelementptr i64, ptr %fp, i64 5
+  %s6.c = getelementptr i64, ptr %fp, i64 6
+  %s7.n = getelementptr i64, ptr %fp, i64 7
+  %s8.c = getelementptr i64, ptr %fp, i64 8
+  %s9.c = getelementptr i64, ptr %fp, i64 9
+  %s10.v = getelementptr i64, ptr %fp, i64 10
+  %s11.r = getelementptr i64, ptr %fp, i64 11
+  %s12.v = getelementptr i64, ptr %fp, i64 12
+  %s13.r = getelementptr i64, ptr %fp, i64 13
+  %s14.unit = getelementptr i64, ptr %fp, i64 14
+  %s15.unit = getelementptr i64, ptr %fp, i64 15
+  %s16.c = getelementptr i64, ptr %fp, i64 16
+  %s17.v = getelementptr i64, ptr %fp, i64 17
+  %s18.c = getelementptr i64, ptr %fp, i64 18
+  %s19.eq = getelementptr i64, ptr %fp, i64 19
+  %s20.r = getelementptr i64, ptr %fp, i64 20
+  %s21.c = getelementptr i64, ptr %fp, i64 21
+  %s22.c = getelementptr i64, ptr %fp, i64 22
+  %s23.eq = getelementptr i64, ptr %fp, i64 23
+  %s24.c = getelementptr i64, ptr %fp, i64 24
+  %s25.v = getelementptr i64, ptr %fp, i64 25
+  %s26.b = getelementptr i64, ptr %fp, i64 26
+  store i64 ptrtoint (ptr @str.0 to i64), ptr %s0.c
+  store i64 ptrtoint (ptr @str.1 to i64), ptr %s1.c
+  %t0 = load i64, ptr %s0.c
+  %t1 = load i64, ptr %s1.c
+  %t2 = call i64 @smol_string_concat(i64 %t0, i64 %t1)
+  store i64 %t2, ptr %s2.v
+  store i64 ptrtoint (ptr @word.2 to i64), ptr %s3.c
+  store i64 ptrtoint (ptr @word.3 to i64), ptr %s4.c
+  %t3 = load i64, ptr %s3.c
+  %t4 = inttoptr i64 %t3 to ptr
+  %t5 = getelementptr i64, ptr %t4, i64 1
+  %t6 = load i64, ptr %t5
+  %t7 = load i64, ptr %s4.c
+  %t8 = inttoptr i64 %t7 to ptr
+  %t9 = getelementptr i64, ptr %t8, i64 1
+  %t10 = load i64, ptr %t9
+  %t11 = mul i64 %t6, %t10
+  %t12 = call ptr @smol_alloc(i64 1, i64 4294967305)
+  %t13 = getelementptr i64, ptr %t12, i64 1
+  store i64 %t11, ptr %t13
+  %t14 = ptrtoint ptr %t12 to i64
+  store i64 %t14, ptr %s5.v
+  store i64 ptrtoint (ptr @word.4 to i64), ptr %s6.c
+  %t15 = load i64, ptr %s5.v
+  %t16 = inttoptr i64 %t15 to ptr
+  %t17 = getelementptr i64, ptr %t16, i64 1
+  %t18 = load i64, ptr %t17
+  %t19 = load i64, ptr %s6.c
+  %t20 = inttoptr i64 %t19 to ptr
+  %t21 = getelementptr i64, ptr %t20, i64 1
+  %t22 = load i64, ptr %t21
+  %t23 = icmp eq i64 %t22, 0
+  br i1 %t23, label %b2, label %b3
+b2:
+  store i64 ptrtoint (ptr @exn.2 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  %t24 = udiv i64 %t18, %t22
+  %t25 = call ptr @smol_alloc(i64 1, i64 4294967305)
+  %t26 = getelementptr i64, ptr %t25, i64 1
+  store i64 %t24, ptr %t26
+  %t27 = ptrtoint ptr %t25 to i64
+  store i64 %t27, ptr %s7.n
+  store i64 ptrtoint (ptr @real.5 to i64), ptr %s8.c
+  store i64 ptrtoint (ptr @real.6 to i64), ptr %s9.c
+  %t28 = load i64, ptr %s8.c
+  %t29 = inttoptr i64 %t28 to ptr
+  %t30 = getelementptr i64, ptr %t29, i64 1
+  %t31 = load double, ptr %t30
+  %t32 = load i64, ptr %s9.c
+  %t33 = inttoptr i64 %t32 to ptr
+  %t34 = getelementptr i64, ptr %t33, i64 1
+  %t35 = load double, ptr %t34
+  %t36 = fmul double %t31, %t35
+  %t37 = call ptr @smol_alloc(i64 1, i64 4294967304)
+  %t38 = getelementptr i64, ptr %t37, i64 1
+  store double %t36, ptr %t38
+  %t39 = ptrtoint ptr %t37 to i64
+  store i64 %t39, ptr %s10.v
+  store i64 ptrtoint (ptr @word.7 to i64), ptr %s22.c
+  %t40 = load i64, ptr %s7.n
+  %t41 = load i64, ptr %s22.c
+  %t42 = call i64 @smol_equal(i64 %t40, i64 %t41)
+  store i64 %t42, ptr %s23.eq
+  %t43 = load i64, ptr %s23.eq
+  %t44 = icmp ne i64 %t43, 1
+  br i1 %t44, label %k4, label %k5
+k5:
+  store i64 1, ptr %s26.b
+  %t45 = load i64, ptr %s26.b
+  store i64 %t45, ptr %s20.r
+  br label %k3
+k4:
+  store i64 ptrtoint (ptr @real.8 to i64), ptr %s24.c
+  %t46 = load i64, ptr %s10.v
+  %t47 = inttoptr i64 %t46 to ptr
+  %t48 = getelementptr i64, ptr %t47, i64 1
+  %t49 = load double, ptr %t48
+  %t50 = load i64, ptr %s24.c
+  %t51 = inttoptr i64 %t50 to ptr
+  %t52 = getelementptr i64, ptr %t51, i64 1
+  %t53 = load double, ptr %t52
+  %t54 = fcmp ogt double %t49, %t53
+  %t55 = select i1 %t54, i64 3, i64 1
+  store i64 %t55, ptr %s25.v
+  %t56 = load i64, ptr %s25.v
+  store i64 %t56, ptr %s20.r
+  br label %k3
+k3:
+  %t57 = load i64, ptr %s20.r
+  %t58 = icmp ne i64 %t57, 1
+  br i1 %t58, label %k6, label %k7
+k7:
+  store i64 ptrtoint (ptr @str.9 to i64), ptr %s21.c
+  %t59 = load i64, ptr %s21.c
+  store i64 %t59, ptr %s11.r
+  br label %k2
+k6:
+  %t60 = load i64, ptr %s2.v
+  store i64 %t60, ptr %s11.r
+  br label %k2
+k2:
+  %t61 = load i64, ptr %s11.r
+  call void @smol_print(i64 %t61)
+  store i64 1, ptr %s12.v
+  store i64 ptrtoint (ptr @str.10 to i64), ptr %s18.c
+  %t62 = load i64, ptr %s2.v
+  %t63 = load i64, ptr %s18.c
+  %t64 = call i64 @smol_equal(i64 %t62, i64 %t63)
+  store i64 %t64, ptr %s19.eq
+  %t65 = load i64, ptr %s19.eq
+  %t66 = icmp ne i64 %t65, 1
+  br i1 %t66, label %k9, label %k11
+k11:
+  br label %k10
+k10:
+  store i64 ptrtoint (ptr @str.11 to i64), ptr %s16.c
+  %t67 = load i64, ptr %s16.c
+  call void @smol_print(i64 %t67)
+  store i64 1, ptr %s17.v
+  %t68 = load i64, ptr %s17.v
+  store i64 %t68, ptr %s13.r
+  br label %k8
+k9:
+  store i64 1, ptr %s15.unit
+  %t69 = load i64, ptr %s15.unit
+  store i64 %t69, ptr %s13.r
+  br label %k8
+k8:
+  store i64 1, ptr %s14.unit
+  %t70 = load i64, ptr %s14.unit
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t70
+}
+
+define internal void @smol_start() {
+  call i64 @smol_main()
+  ret void
+}
+
+define i32 @main(i32 %argc, ptr %argv) {
+  call void @smol_init(i32 %argc, ptr %argv, ptr @smol_globals, i64 0, ptr @smol_exn_names, i64 11)
+  call void @smol_run(ptr @smol_start)
+  %status = call i32 @smol_finish()
+  ret i32 %status
+}
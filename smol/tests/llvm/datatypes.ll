@@ -0,0 +1,623 @@
+; Generated by smol.
+
+@smol_sp = external global ptr
+@smol_sp_limit = external global ptr
+@smol_exn = external global i64
+@smol_globals = internal global [0 x i64] zeroinitializer
+@exn.name.0 = private constant [5 x i8] c"Bind\00"
+@exn.name.1 = private constant [6 x i8] c"Match\00"
+@exn.name.2 = private constant [4 x i8] c"Div\00"
+@exn.name.3 = private constant [9 x i8] c"Overflow\00"
+@exn.name.4 = private constant [5 x i8] c"Size\00"
+@exn.name.5 = private constant [10 x i8] c"Subscript\00"
+@exn.name.6 = private constant [4 x i8] c"Chr\00"
+@exn.name.7 = private constant [7 x i8] c"Domain\00"
+@exn.name.8 = private constant [6 x i8] c"Empty\00"
+@exn.name.9 = private constant [5 x i8] c"Fail\00"
+@exn.name.10 = private constant [5 x i8] c"Span\00"
+@smol_exn_names = private constant [11 x ptr] [ptr @exn.name.0, ptr @exn.name.1, ptr @exn.name.2, ptr @exn.name.3, ptr @exn.name.4, ptr @exn.name.5, ptr @exn.name.6, ptr @exn.name.7, ptr @exn.name.8, ptr @exn.name.9, ptr @exn.name.10]
+@exn.3 = private constant { i64, i64, i64 } { i64 8589934594, i64 7, i64 1 }, align 8
+@str.0 = private constant { i64, [6 x i8] } { i64 25769803783, [6 x i8] c"wrong\0A" }, align 8
+@str.1 = private constant { i64, [3 x i8] } { i64 12884901895, [3 x i8] c"ok\0A" }, align 8
+
+declare ptr @smol_alloc(i64, i64)
+declare i64 @smol_equal(i64, i64)
+declare i64 @smol_string_compare(i64, i64)
+declare i64 @smol_string_concat(i64, i64)
+declare void @smol_print(i64)
+declare void @smol_init(i32, ptr, ptr, i64, ptr, i64)
+declare void @smol_run(ptr)
+declare i32 @smol_finish()
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+declare double @llvm.fabs.f64(double)
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define internal i64 @smol_main() {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 26
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 208, i1 false)
+  %s0.area = getelementptr i64, ptr %fp, i64 0
+  %s1.r = getelementptr i64, ptr %fp, i64 1
+  %s2.Point = getelementptr i64, ptr %fp, i64 2
+  %s3.c = getelementptr i64, ptr %fp, i64 3
+  %s4.Circle = getelementptr i64, ptr %fp, i64 4
+  %s5.c = getelementptr i64, ptr %fp, i64 5
+  %s6.c = getelementptr i64, ptr %fp, i64 6
+  %s7.t = getelementptr i64, ptr %fp, i64 7
+  %s8.Rect = getelementptr i64, ptr %fp, i64 8
+  %s9.nil = getelementptr i64, ptr %fp, i64 9
+  %s10.t = getelementptr i64, ptr %fp, i64 10
+  %s11.list = getelementptr i64, ptr %fp, i64 11
+  %s12.t = getelementptr i64, ptr %fp, i64 12
+  %s13.list = getelementptr i64, ptr %fp, i64 13
+  %s14.t = getelementptr i64, ptr %fp, i64 14
+  %s15.list = getelementptr i64, ptr %fp, i64 15
+  %s16.r = getelementptr i64, ptr %fp, i64 16
+  %s17.r = getelementptr i64, ptr %fp, i64 17
+  %s18.r = getelementptr i64, ptr %fp, i64 18
+  %s19.unit = getelementptr i64, ptr %fp, i64 19
+  %s20.c = getelementptr i64, ptr %fp, i64 20
+  %s21.eq = getelementptr i64, ptr %fp, i64 21
+  %s22.c = getelementptr i64, ptr %fp, i64 22
+  %s23.v = getelementptr i64, ptr %fp, i64 23
+  %s24.c = getelementptr i64, ptr %fp, i64 24
+  %s25.v = getelementptr i64, ptr %fp, i64 25
+  %t0 = call ptr @smol_alloc(i64 1, i64 4294967302)
+  %t1 = getelementptr i64, ptr %t0, i64 1
+  store ptr @area.0, ptr %t1
+  %t2 = ptrtoint ptr %t0 to i64
+  store i64 %t2, ptr %s0.area
+  %t3 = load i64, ptr %s0.area
+  %t4 = call tailcc i64 @map.2(i64 %t3)
+  %t5 = load i64, ptr @smol_exn
+  %t6 = icmp ne i64 %t5, 0
+  br i1 %t6, label %b2, label %b3
+b2:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b3:
+  store i64 %t4, ptr %s1.r
+  br label %k21
+k21:
+  store i64 1, ptr %s2.Point
+  store i64 5, ptr %s3.c
+  %t7 = call ptr @smol_alloc(i64 1, i64 4294967553)
+  %t8 = load i64, ptr %s3.c
+  %t9 = getelementptr i64, ptr %t7, i64 1
+  store i64 %t8, ptr %t9
+  %t10 = ptrtoint ptr %t7 to i64
+  store i64 %t10, ptr %s4.Circle
+  store i64 7, ptr %s5.c
+  store i64 9, ptr %s6.c
+  %t11 = call ptr @smol_alloc(i64 2, i64 8589934592)
+  %t12 = load i64, ptr %s5.c
+  %t13 = getelementptr i64, ptr %t11, i64 1
+  store i64 %t12, ptr %t13
+  %t14 = load i64, ptr %s6.c
+  %t15 = getelementptr i64, ptr %t11, i64 2
+  store i64 %t14, ptr %t15
+  %t16 = ptrtoint ptr %t11 to i64
+  store i64 %t16, ptr %s7.t
+  %t17 = call ptr @smol_alloc(i64 1, i64 4294967809)
+  %t18 = load i64, ptr %s7.t
+  %t19 = getelementptr i64, ptr %t17, i64 1
+  store i64 %t18, ptr %t19
+  %t20 = ptrtoint ptr %t17 to i64
+  store i64 %t20, ptr %s8.Rect
+  store i64 1, ptr %s9.nil
+  %t21 = call ptr @smol_alloc(i64 2, i64 8589934592)
+  %t22 = load i64, ptr %s8.Rect
+  %t23 = getelementptr i64, ptr %t21, i64 1
+  store i64 %t22, ptr %t23
+  %t24 = load i64, ptr %s9.nil
+  %t25 = getelementptr i64, ptr %t21, i64 2
+  store i64 %t24, ptr %t25
+  %t26 = ptrtoint ptr %t21 to i64
+  store i64 %t26, ptr %s10.t
+  %t27 = call ptr @smol_alloc(i64 1, i64 4294967553)
+  %t28 = load i64, ptr %s10.t
+  %t29 = getelementptr i64, ptr %t27, i64 1
+  store i64 %t28, ptr %t29
+  %t30 = ptrtoint ptr %t27 to i64
+  store i64 %t30, ptr %s11.list
+  %t31 = call ptr @smol_alloc(i64 2, i64 8589934592)
+  %t32 = load i64, ptr %s4.Circle
+  %t33 = getelementptr i64, ptr %t31, i64 1
+  store i64 %t32, ptr %t33
+  %t34 = load i64, ptr %s11.list
+  %t35 = getelementptr i64, ptr %t31, i64 2
+  store i64 %t34, ptr %t35
+  %t36 = ptrtoint ptr %t31 to i64
+  store i64 %t36, ptr %s12.t
+  %t37 = call ptr @smol_alloc(i64 1, i64 4294967553)
+  %t38 = load i64, ptr %s12.t
+  %t39 = getelementptr i64, ptr %t37, i64 1
+  store i64 %t38, ptr %t39
+  %t40 = ptrtoint ptr %t37 to i64
+  store i64 %t40, ptr %s13.list
+  %t41 = call ptr @smol_alloc(i64 2, i64 8589934592)
+  %t42 = load i64, ptr %s2.Point
+  %t43 = getelementptr i64, ptr %t41, i64 1
+  store i64 %t42, ptr %t43
+  %t44 = load i64, ptr %s13.list
+  %t45 = getelementptr i64, ptr %t41, i64 2
+  store i64 %t44, ptr %t45
+  %t46 = ptrtoint ptr %t41 to i64
+  store i64 %t46, ptr %s14.t
+  %t47 = call ptr @smol_alloc(i64 1, i64 4294967553)
+  %t48 = load i64, ptr %s14.t
+  %t49 = getelementptr i64, ptr %t47, i64 1
+  store i64 %t48, ptr %t49
+  %t50 = ptrtoint ptr %t47 to i64
+  store i64 %t50, ptr %s15.list
+  %t51 = load i64, ptr %s1.r
+  %t52 = load i64, ptr %s15.list
+  %t53 = inttoptr i64 %t51 to ptr
+  %t54 = getelementptr i64, ptr %t53, i64 1
+  %t55 = load ptr, ptr %t54
+  %t56 = call tailcc i64 %t55(i64 %t51, i64 %t52)
+  %t57 = load i64, ptr @smol_exn
+  %t58 = icmp ne i64 %t57, 0
+  br i1 %t58, label %b4, label %b5
+b4:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b5:
+  store i64 %t56, ptr %s16.r
+  br label %k22
+k22:
+  %t59 = load i64, ptr %s16.r
+  %t60 = call tailcc i64 @sum.1(i64 %t59)
+  %t61 = load i64, ptr @smol_exn
+  %t62 = icmp ne i64 %t61, 0
+  br i1 %t62, label %b6, label %b7
+b6:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b7:
+  store i64 %t60, ptr %s17.r
+  br label %k23
+k23:
+  store i64 49, ptr %s20.c
+  %t63 = load i64, ptr %s17.r
+  %t64 = load i64, ptr %s20.c
+  %t65 = icmp eq i64 %t63, %t64
+  %t66 = select i1 %t65, i64 3, i64 1
+  store i64 %t66, ptr %s21.eq
+  %t67 = load i64, ptr %s21.eq
+  %t68 = icmp ne i64 %t67, 1
+  br i1 %t68, label %k25, label %k26
+k26:
+  store i64 ptrtoint (ptr @str.0 to i64), ptr %s24.c
+  %t69 = load i64, ptr %s24.c
+  call void @smol_print(i64 %t69)
+  store i64 1, ptr %s25.v
+  %t70 = load i64, ptr %s25.v
+  store i64 %t70, ptr %s18.r
+  br label %k24
+k25:
+  store i64 ptrtoint (ptr @str.1 to i64), ptr %s22.c
+  %t71 = load i64, ptr %s22.c
+  call void @smol_print(i64 %t71)
+  store i64 1, ptr %s23.v
+  %t72 = load i64, ptr %s23.v
+  store i64 %t72, ptr %s18.r
+  br label %k24
+k24:
+  store i64 1, ptr %s19.unit
+  %t73 = load i64, ptr %s19.unit
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t73
+}
+
+define internal tailcc i64 @area.0(i64 %p0, i64 %p1) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 12
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 96, i1 false)
+  %s0.area = getelementptr i64, ptr %fp, i64 0
+  %s1.x = getelementptr i64, ptr %fp, i64 1
+  %s2.tag = getelementptr i64, ptr %fp, i64 2
+  %s3.c = getelementptr i64, ptr %fp, i64 3
+  %s4.p = getelementptr i64, ptr %fp, i64 4
+  %s5.c = getelementptr i64, ptr %fp, i64 5
+  %s6.n = getelementptr i64, ptr %fp, i64 6
+  %s7.n = getelementptr i64, ptr %fp, i64 7
+  %s8.p = getelementptr i64, ptr %fp, i64 8
+  %s9.p = getelementptr i64, ptr %fp, i64 9
+  %s10.p = getelementptr i64, ptr %fp, i64 10
+  %s11.n = getelementptr i64, ptr %fp, i64 11
+  store i64 %p0, ptr %s0.area
+  store i64 %p1, ptr %s1.x
+  %t0 = load i64, ptr %s1.x
+  %t1 = and i64 %t0, 1
+  %t2 = icmp ne i64 %t1, 0
+  br i1 %t2, label %b2, label %b3
+b2:
+  store i64 %t0, ptr %s2.tag
+  br label %b4
+b3:
+  %t3 = inttoptr i64 %t0 to ptr
+  %t4 = load i64, ptr %t3
+  %t5 = lshr i64 %t4, 7
+  %t6 = and i64 %t5, 33554430
+  %t7 = or i64 %t6, 1
+  store i64 %t7, ptr %s2.tag
+  br label %b4
+b4:
+  %t8 = load i64, ptr %s2.tag
+  switch i64 %t8, label %k6 [ i64 1, label %k4 i64 3, label %k5 ]
+k6:
+  %t9 = load i64, ptr %s1.x
+  %t10 = inttoptr i64 %t9 to ptr
+  %t11 = getelementptr i64, ptr %t10, i64 1
+  %t12 = load i64, ptr %t11
+  store i64 %t12, ptr %s8.p
+  %t13 = load i64, ptr %s8.p
+  %t14 = inttoptr i64 %t13 to ptr
+  %t15 = getelementptr i64, ptr %t14, i64 1
+  %t16 = load i64, ptr %t15
+  store i64 %t16, ptr %s9.p
+  %t17 = load i64, ptr %s8.p
+  %t18 = inttoptr i64 %t17 to ptr
+  %t19 = getelementptr i64, ptr %t18, i64 2
+  %t20 = load i64, ptr %t19
+  store i64 %t20, ptr %s10.p
+  %t21 = load i64, ptr %s9.p
+  %t22 = load i64, ptr %s10.p
+  %t23 = ashr i64 %t21, 1
+  %t24 = sub i64 %t22, 1
+  %t25 = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %t23, i64 %t24)
+  %t26 = extractvalue { i64, i1 } %t25, 0
+  %t27 = extractvalue { i64, i1 } %t25, 1
+  br i1 %t27, label %b5, label %b6
+b5:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b6:
+  %t28 = or i64 %t26, 1
+  store i64 %t28, ptr %s11.n
+  %t29 = load i64, ptr %s11.n
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t29
+k5:
+  %t30 = load i64, ptr %s1.x
+  %t31 = inttoptr i64 %t30 to ptr
+  %t32 = getelementptr i64, ptr %t31, i64 1
+  %t33 = load i64, ptr %t32
+  store i64 %t33, ptr %s4.p
+  store i64 7, ptr %s5.c
+  %t34 = load i64, ptr %s5.c
+  %t35 = load i64, ptr %s4.p
+  %t36 = ashr i64 %t34, 1
+  %t37 = sub i64 %t35, 1
+  %t38 = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %t36, i64 %t37)
+  %t39 = extractvalue { i64, i1 } %t38, 0
+  %t40 = extractvalue { i64, i1 } %t38, 1
+  br i1 %t40, label %b7, label %b8
+b7:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b8:
+  %t41 = or i64 %t39, 1
+  store i64 %t41, ptr %s6.n
+  %t42 = load i64, ptr %s6.n
+  %t43 = load i64, ptr %s4.p
+  %t44 = ashr i64 %t42, 1
+  %t45 = sub i64 %t43, 1
+  %t46 = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %t44, i64 %t45)
+  %t47 = extractvalue { i64, i1 } %t46, 0
+  %t48 = extractvalue { i64, i1 } %t46, 1
+  br i1 %t48, label %b9, label %b10
+b9:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b10:
+  %t49 = or i64 %t47, 1
+  store i64 %t49, ptr %s7.n
+  %t50 = load i64, ptr %s7.n
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t50
+k4:
+  store i64 1, ptr %s3.c
+  %t51 = load i64, ptr %s3.c
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t51
+}
+
+define internal tailcc i64 @sum.1(i64 %p0) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 8
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 64, i1 false)
+  %s0.x = getelementptr i64, ptr %fp, i64 0
+  %s1.tag = getelementptr i64, ptr %fp, i64 1
+  %s2.c = getelementptr i64, ptr %fp, i64 2
+  %s3.p = getelementptr i64, ptr %fp, i64 3
+  %s4.p = getelementptr i64, ptr %fp, i64 4
+  %s5.p = getelementptr i64, ptr %fp, i64 5
+  %s6.r = getelementptr i64, ptr %fp, i64 6
+  %s7.n = getelementptr i64, ptr %fp, i64 7
+  store i64 %p0, ptr %s0.x
+  %t0 = load i64, ptr %s0.x
+  %t1 = and i64 %t0, 1
+  %t2 = icmp ne i64 %t1, 0
+  br i1 %t2, label %b2, label %b3
+b2:
+  store i64 %t0, ptr %s1.tag
+  br label %b4
+b3:
+  %t3 = inttoptr i64 %t0 to ptr
+  %t4 = load i64, ptr %t3
+  %t5 = lshr i64 %t4, 7
+  %t6 = and i64 %t5, 33554430
+  %t7 = or i64 %t6, 1
+  store i64 %t7, ptr %s1.tag
+  br label %b4
+b4:
+  %t8 = load i64, ptr %s1.tag
+  switch i64 %t8, label %k11 [ i64 1, label %k9 ]
+k11:
+  %t9 = load i64, ptr %s0.x
+  %t10 = inttoptr i64 %t9 to ptr
+  %t11 = getelementptr i64, ptr %t10, i64 1
+  %t12 = load i64, ptr %t11
+  store i64 %t12, ptr %s3.p
+  %t13 = load i64, ptr %s3.p
+  %t14 = inttoptr i64 %t13 to ptr
+  %t15 = getelementptr i64, ptr %t14, i64 1
+  %t16 = load i64, ptr %t15
+  store i64 %t16, ptr %s4.p
+  %t17 = load i64, ptr %s3.p
+  %t18 = inttoptr i64 %t17 to ptr
+  %t19 = getelementptr i64, ptr %t18, i64 2
+  %t20 = load i64, ptr %t19
+  store i64 %t20, ptr %s5.p
+  %t21 = load i64, ptr %s5.p
+  %t22 = call tailcc i64 @sum.1(i64 %t21)
+  %t23 = load i64, ptr @smol_exn
+  %t24 = icmp ne i64 %t23, 0
+  br i1 %t24, label %b5, label %b6
+b5:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b6:
+  store i64 %t22, ptr %s6.r
+  br label %k10
+k10:
+  %t25 = load i64, ptr %s4.p
+  %t26 = load i64, ptr %s6.r
+  %t27 = sub i64 %t26, 1
+  %t28 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t25, i64 %t27)
+  %t29 = extractvalue { i64, i1 } %t28, 0
+  %t30 = extractvalue { i64, i1 } %t28, 1
+  br i1 %t30, label %b7, label %b8
+b7:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b8:
+  store i64 %t29, ptr %s7.n
+  %t31 = load i64, ptr %s7.n
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t31
+k9:
+  store i64 1, ptr %s2.c
+  %t32 = load i64, ptr %s2.c
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t32
+}
+
+define internal tailcc i64 @map.2(i64 %p0) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 2
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 16, i1 false)
+  %s0.x = getelementptr i64, ptr %fp, i64 0
+  %s1.map = getelementptr i64, ptr %fp, i64 1
+  store i64 %p0, ptr %s0.x
+  %t0 = call ptr @smol_alloc(i64 2, i64 8589934598)
+  %t1 = getelementptr i64, ptr %t0, i64 1
+  store ptr @map.3, ptr %t1
+  %t2 = ptrtoint ptr %t0 to i64
+  store i64 %t2, ptr %s1.map
+  %t3 = load i64, ptr %s1.map
+  %t4 = inttoptr i64 %t3 to ptr
+  %t5 = getelementptr i64, ptr %t4, i64 2
+  %t6 = load i64, ptr %s0.x
+  store i64 %t6, ptr %t5
+  %t7 = load i64, ptr %s1.map
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t7
+}
+
+define internal tailcc i64 @map.3(i64 %p0, i64 %p1) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 13
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 104, i1 false)
+  %s0.map = getelementptr i64, ptr %fp, i64 0
+  %s1.x = getelementptr i64, ptr %fp, i64 1
+  %s2.x = getelementptr i64, ptr %fp, i64 2
+  %s3.tag = getelementptr i64, ptr %fp, i64 3
+  %s4.nil = getelementptr i64, ptr %fp, i64 4
+  %s5.p = getelementptr i64, ptr %fp, i64 5
+  %s6.p = getelementptr i64, ptr %fp, i64 6
+  %s7.p = getelementptr i64, ptr %fp, i64 7
+  %s8.r = getelementptr i64, ptr %fp, i64 8
+  %s9.r = getelementptr i64, ptr %fp, i64 9
+  %s10.r = getelementptr i64, ptr %fp, i64 10
+  %s11.t = getelementptr i64, ptr %fp, i64 11
+  %s12.__ = getelementptr i64, ptr %fp, i64 12
+  store i64 %p0, ptr %s0.map
+  store i64 %p1, ptr %s1.x
+  %t0 = load i64, ptr %s0.map
+  %t1 = inttoptr i64 %t0 to ptr
+  %t2 = getelementptr i64, ptr %t1, i64 2
+  %t3 = load i64, ptr %t2
+  store i64 %t3, ptr %s2.x
+  %t4 = load i64, ptr %s1.x
+  %t5 = and i64 %t4, 1
+  %t6 = icmp ne i64 %t5, 0
+  br i1 %t6, label %b2, label %b3
+b2:
+  store i64 %t4, ptr %s3.tag
+  br label %b4
+b3:
+  %t7 = inttoptr i64 %t4 to ptr
+  %t8 = load i64, ptr %t7
+  %t9 = lshr i64 %t8, 7
+  %t10 = and i64 %t9, 33554430
+  %t11 = or i64 %t10, 1
+  store i64 %t11, ptr %s3.tag
+  br label %b4
+b4:
+  %t12 = load i64, ptr %s3.tag
+  switch i64 %t12, label %k18 [ i64 1, label %k14 ]
+k18:
+  %t13 = load i64, ptr %s1.x
+  %t14 = inttoptr i64 %t13 to ptr
+  %t15 = getelementptr i64, ptr %t14, i64 1
+  %t16 = load i64, ptr %t15
+  store i64 %t16, ptr %s5.p
+  %t17 = load i64, ptr %s5.p
+  %t18 = inttoptr i64 %t17 to ptr
+  %t19 = getelementptr i64, ptr %t18, i64 1
+  %t20 = load i64, ptr %t19
+  store i64 %t20, ptr %s6.p
+  %t21 = load i64, ptr %s5.p
+  %t22 = inttoptr i64 %t21 to ptr
+  %t23 = getelementptr i64, ptr %t22, i64 2
+  %t24 = load i64, ptr %t23
+  store i64 %t24, ptr %s7.p
+  %t25 = load i64, ptr %s2.x
+  %t26 = load i64, ptr %s6.p
+  %t27 = inttoptr i64 %t25 to ptr
+  %t28 = getelementptr i64, ptr %t27, i64 1
+  %t29 = load ptr, ptr %t28
+  %t30 = call tailcc i64 %t29(i64 %t25, i64 %t26)
+  %t31 = load i64, ptr @smol_exn
+  %t32 = icmp ne i64 %t31, 0
+  br i1 %t32, label %b5, label %b6
+b5:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b6:
+  store i64 %t30, ptr %s8.r
+  br label %k15
+k15:
+  %t33 = load i64, ptr %s2.x
+  %t34 = call tailcc i64 @map.2(i64 %t33)
+  %t35 = load i64, ptr @smol_exn
+  %t36 = icmp ne i64 %t35, 0
+  br i1 %t36, label %b7, label %b8
+b7:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b8:
+  store i64 %t34, ptr %s9.r
+  br label %k16
+k16:
+  %t37 = load i64, ptr %s9.r
+  %t38 = load i64, ptr %s7.p
+  %t39 = inttoptr i64 %t37 to ptr
+  %t40 = getelementptr i64, ptr %t39, i64 1
+  %t41 = load ptr, ptr %t40
+  %t42 = call tailcc i64 %t41(i64 %t37, i64 %t38)
+  %t43 = load i64, ptr @smol_exn
+  %t44 = icmp ne i64 %t43, 0
+  br i1 %t44, label %b9, label %b10
+b9:
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b10:
+  store i64 %t42, ptr %s10.r
+  br label %k17
+k17:
+  %t45 = call ptr @smol_alloc(i64 2, i64 8589934592)
+  %t46 = load i64, ptr %s8.r
+  %t47 = getelementptr i64, ptr %t45, i64 1
+  store i64 %t46, ptr %t47
+  %t48 = load i64, ptr %s10.r
+  %t49 = getelementptr i64, ptr %t45, i64 2
+  store i64 %t48, ptr %t49
+  %t50 = ptrtoint ptr %t45 to i64
+  store i64 %t50, ptr %s11.t
+  %t51 = call ptr @smol_alloc(i64 1, i64 4294967553)
+  %t52 = load i64, ptr %s11.t
+  %t53 = getelementptr i64, ptr %t51, i64 1
+  store i64 %t52, ptr %t53
+  %t54 = ptrtoint ptr %t51 to i64
+  store i64 %t54, ptr %s12.__
+  %t55 = load i64, ptr %s12.__
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t55
+k14:
+  store i64 1, ptr %s4.nil
+  %t56 = load i64, ptr %s4.nil
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t56
+}
+
+define internal void @smol_start() {
+  call i64 @smol_main()
+  ret void
+}
+
+define i32 @main(i32 %argc, ptr %argv) {
+  call void @smol_init(i32 %argc, ptr %argv, ptr @smol_globals, i64 0, ptr @smol_exn_names, i64 11)
+  call void @smol_run(ptr @smol_start)
+  %status = call i32 @smol_finish()
+  ret i32 %status
+}
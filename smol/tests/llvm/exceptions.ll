@@ -0,0 +1,339 @@
+; Generated by smol.
+
+@smol_sp = external global ptr
+@smol_sp_limit = external global ptr
+@smol_exn = external global i64
+@smol_globals = internal global [0 x i64] zeroinitializer
+@exn.name.0 = private constant [5 x i8] c"Bind\00"
+@exn.name.1 = private constant [6 x i8] c"Match\00"
+@exn.name.2 = private constant [4 x i8] c"Div\00"
+@exn.name.3 = private constant [9 x i8] c"Overflow\00"
+@exn.name.4 = private constant [5 x i8] c"Size\00"
+@exn.name.5 = private constant [10 x i8] c"Subscript\00"
+@exn.name.6 = private constant [4 x i8] c"Chr\00"
+@exn.name.7 = private constant [7 x i8] c"Domain\00"
+@exn.name.8 = private constant [6 x i8] c"Empty\00"
+@exn.name.9 = private constant [5 x i8] c"Fail\00"
+@exn.name.10 = private constant [5 x i8] c"Span\00"
+@exn.name.11 = private constant [9 x i8] c"Negative\00"
+@smol_exn_names = private constant [12 x ptr] [ptr @exn.name.0, ptr @exn.name.1, ptr @exn.name.2, ptr @exn.name.3, ptr @exn.name.4, ptr @exn.name.5, ptr @exn.name.6, ptr @exn.name.7, ptr @exn.name.8, ptr @exn.name.9, ptr @exn.name.10, ptr @exn.name.11]
+@exn.2 = private constant { i64, i64, i64 } { i64 8589934594, i64 5, i64 1 }, align 8
+@exn.3 = private constant { i64, i64, i64 } { i64 8589934594, i64 7, i64 1 }, align 8
+@str.0 = private constant { i64, [6 x i8] } { i64 25769803783, [6 x i8] c"wrong\0A" }, align 8
+@str.1 = private constant { i64, [3 x i8] } { i64 12884901895, [3 x i8] c"ok\0A" }, align 8
+
+declare ptr @smol_alloc(i64, i64)
+declare i64 @smol_equal(i64, i64)
+declare i64 @smol_string_compare(i64, i64)
+declare i64 @smol_string_concat(i64, i64)
+declare void @smol_print(i64)
+declare void @smol_init(i32, ptr, ptr, i64, ptr, i64)
+declare void @smol_run(ptr)
+declare i32 @smol_finish()
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+declare double @llvm.fabs.f64(double)
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define internal i64 @smol_main() {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 28
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 224, i1 false)
+  %s0.r = getelementptr i64, ptr %fp, i64 0
+  %s1.r = getelementptr i64, ptr %fp, i64 1
+  %s2.r = getelementptr i64, ptr %fp, i64 2
+  %s3.r = getelementptr i64, ptr %fp, i64 3
+  %s4.v = getelementptr i64, ptr %fp, i64 4
+  %s5.unit = getelementptr i64, ptr %fp, i64 5
+  %s6.n = getelementptr i64, ptr %fp, i64 6
+  %s7.n = getelementptr i64, ptr %fp, i64 7
+  %s8.c = getelementptr i64, ptr %fp, i64 8
+  %s9.eq = getelementptr i64, ptr %fp, i64 9
+  %s10.c = getelementptr i64, ptr %fp, i64 10
+  %s11.c = getelementptr i64, ptr %fp, i64 11
+  %s12.exn = getelementptr i64, ptr %fp, i64 12
+  %s13.id = getelementptr i64, ptr %fp, i64 13
+  %s14.c = getelementptr i64, ptr %fp, i64 14
+  %s15.c = getelementptr i64, ptr %fp, i64 15
+  %s16.c = getelementptr i64, ptr %fp, i64 16
+  %s17.n = getelementptr i64, ptr %fp, i64 17
+  %s18.exn = getelementptr i64, ptr %fp, i64 18
+  %s19.id = getelementptr i64, ptr %fp, i64 19
+  %s20.p = getelementptr i64, ptr %fp, i64 20
+  %s21.n = getelementptr i64, ptr %fp, i64 21
+  %s22.c = getelementptr i64, ptr %fp, i64 22
+  %s23.exn = getelementptr i64, ptr %fp, i64 23
+  %s24.id = getelementptr i64, ptr %fp, i64 24
+  %s25.p = getelementptr i64, ptr %fp, i64 25
+  %s26.n = getelementptr i64, ptr %fp, i64 26
+  %s27.c = getelementptr i64, ptr %fp, i64 27
+  store i64 3, ptr %s27.c
+  %t0 = load i64, ptr %s27.c
+  %t1 = call tailcc i64 @check.0(i64 %t0)
+  %t2 = load i64, ptr @smol_exn
+  %t3 = icmp ne i64 %t2, 0
+  br i1 %t3, label %b2, label %b3
+b2:
+  store i64 0, ptr @smol_exn
+  store i64 %t2, ptr %s23.exn
+  br label %k7
+b3:
+  store i64 %t1, ptr %s0.r
+  br label %k6
+k7:
+  %t4 = load i64, ptr %s23.exn
+  %t5 = inttoptr i64 %t4 to ptr
+  %t6 = getelementptr i64, ptr %t5, i64 1
+  %t7 = load i64, ptr %t6
+  store i64 %t7, ptr %s24.id
+  %t8 = load i64, ptr %s24.id
+  switch i64 %t8, label %k9 [ i64 23, label %k8 ]
+k9:
+  %t9 = load i64, ptr %s23.exn
+  store i64 %t9, ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+k8:
+  %t10 = load i64, ptr %s23.exn
+  %t11 = inttoptr i64 %t10 to ptr
+  %t12 = getelementptr i64, ptr %t11, i64 2
+  %t13 = load i64, ptr %t12
+  store i64 %t13, ptr %s25.p
+  %t14 = load i64, ptr %s25.p
+  %t15 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 2, i64 %t14)
+  %t16 = extractvalue { i64, i1 } %t15, 0
+  %t17 = extractvalue { i64, i1 } %t15, 1
+  br i1 %t17, label %b4, label %b5
+b4:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b5:
+  store i64 %t16, ptr %s26.n
+  %t18 = load i64, ptr %s26.n
+  store i64 %t18, ptr %s0.r
+  br label %k6
+k6:
+  store i64 -3, ptr %s22.c
+  %t19 = load i64, ptr %s22.c
+  %t20 = call tailcc i64 @check.0(i64 %t19)
+  %t21 = load i64, ptr @smol_exn
+  %t22 = icmp ne i64 %t21, 0
+  br i1 %t22, label %b6, label %b7
+b6:
+  store i64 0, ptr @smol_exn
+  store i64 %t21, ptr %s18.exn
+  br label %k11
+b7:
+  store i64 %t20, ptr %s1.r
+  br label %k10
+k11:
+  %t23 = load i64, ptr %s18.exn
+  %t24 = inttoptr i64 %t23 to ptr
+  %t25 = getelementptr i64, ptr %t24, i64 1
+  %t26 = load i64, ptr %t25
+  store i64 %t26, ptr %s19.id
+  %t27 = load i64, ptr %s19.id
+  switch i64 %t27, label %k13 [ i64 23, label %k12 ]
+k13:
+  %t28 = load i64, ptr %s18.exn
+  store i64 %t28, ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+k12:
+  %t29 = load i64, ptr %s18.exn
+  %t30 = inttoptr i64 %t29 to ptr
+  %t31 = getelementptr i64, ptr %t30, i64 2
+  %t32 = load i64, ptr %t31
+  store i64 %t32, ptr %s20.p
+  %t33 = load i64, ptr %s20.p
+  %t34 = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 2, i64 %t33)
+  %t35 = extractvalue { i64, i1 } %t34, 0
+  %t36 = extractvalue { i64, i1 } %t34, 1
+  br i1 %t36, label %b8, label %b9
+b8:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b9:
+  store i64 %t35, ptr %s21.n
+  %t37 = load i64, ptr %s21.n
+  store i64 %t37, ptr %s1.r
+  br label %k10
+k10:
+  store i64 21, ptr %s15.c
+  store i64 1, ptr %s16.c
+  %t38 = load i64, ptr %s15.c
+  %t39 = load i64, ptr %s16.c
+  %t40 = ashr i64 %t38, 1
+  %t41 = ashr i64 %t39, 1
+  %t42 = icmp eq i64 %t41, 0
+  br i1 %t42, label %b10, label %b11
+b10:
+  store i64 ptrtoint (ptr @exn.2 to i64), ptr %s12.exn
+  br label %k15
+b11:
+  %t43 = srem i64 %t40, %t41
+  %t44 = icmp ne i64 %t43, 0
+  %t45 = xor i64 %t43, %t41
+  %t46 = icmp slt i64 %t45, 0
+  %t47 = and i1 %t44, %t46
+  %t48 = sdiv i64 %t40, %t41
+  %t49 = zext i1 %t47 to i64
+  %t50 = sub i64 %t48, %t49
+  %t51 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t50, i64 %t50)
+  %t52 = extractvalue { i64, i1 } %t51, 0
+  %t53 = extractvalue { i64, i1 } %t51, 1
+  br i1 %t53, label %b12, label %b13
+b12:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr %s12.exn
+  br label %k15
+b13:
+  %t54 = or i64 %t52, 1
+  store i64 %t54, ptr %s17.n
+  %t55 = load i64, ptr %s17.n
+  store i64 %t55, ptr %s2.r
+  br label %k14
+k15:
+  %t56 = load i64, ptr %s12.exn
+  %t57 = inttoptr i64 %t56 to ptr
+  %t58 = getelementptr i64, ptr %t57, i64 1
+  %t59 = load i64, ptr %t58
+  store i64 %t59, ptr %s13.id
+  %t60 = load i64, ptr %s13.id
+  switch i64 %t60, label %k17 [ i64 5, label %k16 ]
+k17:
+  %t61 = load i64, ptr %s12.exn
+  store i64 %t61, ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+k16:
+  store i64 1, ptr %s14.c
+  %t62 = load i64, ptr %s14.c
+  store i64 %t62, ptr %s2.r
+  br label %k14
+k14:
+  %t63 = load i64, ptr %s0.r
+  %t64 = load i64, ptr %s1.r
+  %t65 = sub i64 %t64, 1
+  %t66 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t63, i64 %t65)
+  %t67 = extractvalue { i64, i1 } %t66, 0
+  %t68 = extractvalue { i64, i1 } %t66, 1
+  br i1 %t68, label %b14, label %b15
+b14:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b15:
+  store i64 %t67, ptr %s6.n
+  %t69 = load i64, ptr %s6.n
+  %t70 = load i64, ptr %s2.r
+  %t71 = sub i64 %t70, 1
+  %t72 = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %t69, i64 %t71)
+  %t73 = extractvalue { i64, i1 } %t72, 0
+  %t74 = extractvalue { i64, i1 } %t72, 1
+  br i1 %t74, label %b16, label %b17
+b16:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b17:
+  store i64 %t73, ptr %s7.n
+  store i64 7, ptr %s8.c
+  %t75 = load i64, ptr %s7.n
+  %t76 = load i64, ptr %s8.c
+  %t77 = icmp eq i64 %t75, %t76
+  %t78 = select i1 %t77, i64 3, i64 1
+  store i64 %t78, ptr %s9.eq
+  %t79 = load i64, ptr %s9.eq
+  %t80 = icmp ne i64 %t79, 1
+  br i1 %t80, label %k19, label %k20
+k20:
+  store i64 ptrtoint (ptr @str.0 to i64), ptr %s11.c
+  %t81 = load i64, ptr %s11.c
+  store i64 %t81, ptr %s3.r
+  br label %k18
+k19:
+  store i64 ptrtoint (ptr @str.1 to i64), ptr %s10.c
+  %t82 = load i64, ptr %s10.c
+  store i64 %t82, ptr %s3.r
+  br label %k18
+k18:
+  %t83 = load i64, ptr %s3.r
+  call void @smol_print(i64 %t83)
+  store i64 1, ptr %s4.v
+  store i64 1, ptr %s5.unit
+  %t84 = load i64, ptr %s5.unit
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t84
+}
+
+define internal tailcc i64 @check.0(i64 %p0) {
+entry:
+  %fp = load ptr, ptr @smol_sp
+  %top = getelementptr i64, ptr %fp, i64 4
+  %limit = load ptr, ptr @smol_sp_limit
+  %overflow = icmp ugt ptr %top, %limit
+  br i1 %overflow, label %b0, label %b1
+b0:
+  store i64 ptrtoint (ptr @exn.3 to i64), ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+b1:
+  store ptr %top, ptr @smol_sp
+  call void @llvm.memset.p0.i64(ptr %fp, i8 0, i64 32, i1 false)
+  %s0.x = getelementptr i64, ptr %fp, i64 0
+  %s1.c = getelementptr i64, ptr %fp, i64 1
+  %s2.v = getelementptr i64, ptr %fp, i64 2
+  %s3.Negative = getelementptr i64, ptr %fp, i64 3
+  store i64 %p0, ptr %s0.x
+  store i64 1, ptr %s1.c
+  %t0 = load i64, ptr %s0.x
+  %t1 = load i64, ptr %s1.c
+  %t2 = icmp slt i64 %t0, %t1
+  %t3 = select i1 %t2, i64 3, i64 1
+  store i64 %t3, ptr %s2.v
+  %t4 = load i64, ptr %s2.v
+  %t5 = icmp ne i64 %t4, 1
+  br i1 %t5, label %k4, label %k5
+k5:
+  %t6 = load i64, ptr %s0.x
+  store ptr %fp, ptr @smol_sp
+  ret i64 %t6
+k4:
+  %t7 = call ptr @smol_alloc(i64 2, i64 8589934594)
+  %t8 = getelementptr i64, ptr %t7, i64 1
+  store i64 23, ptr %t8
+  %t9 = load i64, ptr %s0.x
+  %t10 = getelementptr i64, ptr %t7, i64 2
+  store i64 %t9, ptr %t10
+  %t11 = ptrtoint ptr %t7 to i64
+  store i64 %t11, ptr %s3.Negative
+  %t12 = load i64, ptr %s3.Negative
+  store i64 %t12, ptr @smol_exn
+  store ptr %fp, ptr @smol_sp
+  ret i64 0
+}
+
+define internal void @smol_start() {
+  call i64 @smol_main()
+  ret void
+}
+
+define i32 @main(i32 %argc, ptr %argv) {
+  call void @smol_init(i32 %argc, ptr %argv, ptr @smol_globals, i64 0, ptr @smol_exn_names, i64 12)
+  call void @smol_run(ptr @smol_start)
+  %status = call i32 @smol_finish()
+  ret i32 %status
+}
@@ -0,0 +1,306 @@
+//! Runs the `smolc` binary: its arguments, each stage it can stop after,
+//! projects described by ML Basis and `.cm` files, bytecode, which `smol
+//! --run` runs too, and, with `cc` installed, programs it compiles.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const LIB: &str = "\
+infix 6 ++
+fun a ++ b = a + b
+datatype shape = Circle of int | Square of int
+exception Negative of int
+";
+
+const MAIN: &str = "\
+structure Area = struct
+  fun area (Circle r) = 3 * r * r
+    | area (Square s) = s * s
+end
+val total = Area.area (Circle 2) ++ Area.area (Square 3)
+val _ = print (Int.toString total ^ \"\\n\")
+val _ = if total > 0 then raise Negative total else ()
+";
+
+/// `lib.sml` then `main.sml`, with `lib.sml`'s bindings only visible to
+/// `main.sml`.
+const MLB: &str = "\
+(* The Basis is always there. *)
+$(SML_LIB)/basis/basis.mlb
+basis Lib = bas $(SRC)/lib.sml end
+local
+  open Lib
+in
+  ann \"warnUnused true\" in main.sml end
+end
+";
+
+/// A directory of its own for a test, with `lib.sml`, `main.sml`, and
+/// `main.mlb`.
+fn dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("smolc")
+        .join(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.sml"), LIB).unwrap();
+    fs::write(dir.join("main.sml"), MAIN).unwrap();
+    fs::write(dir.join("main.mlb"), MLB).unwrap();
+    dir
+}
+
+fn smolc(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_smolc"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+#[test]
+fn arguments() {
+    let dir = dir("arguments");
+    let out = smolc(&dir, &["--help"]);
+    assert!(out.status.success());
+    assert!(stdout(&out).contains("--dump-parse-tree"));
+
+    for args in [
+        &[][..],
+        &["--frobnicate", "main.sml"],
+        &["--check", "--emit-c", "main.sml"],
+        &["main.sml", "-o"],
+    ] {
+        let out = smolc(&dir, args);
+        assert_eq!(out.status.code(), Some(2), "{:?}: {}", args, stderr(&out));
+        assert!(stderr(&out).contains("usage: smolc"), "{}", stderr(&out));
+    }
+
+    let out = smolc(&dir, &["--check", "missing.sml"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("can't read missing.sml"));
+}
+
+#[test]
+fn checking() {
+    let dir = dir("checking");
+    let out = smolc(&dir, &["--check", "lib.sml", "main.sml"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "");
+
+    // Without the first file, `++` isn't infix, or even bound.
+    let out = smolc(&dir, &["--check", "main.sml"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("main.sml:"), "{}", stderr(&out));
+}
+
+#[test]
+fn projects() {
+    let dir = dir("projects");
+    let files = [
+        ("hidden.mlb", "local lib.sml in end main.sml"),
+        (
+            "alias.mlb",
+            "local lib.sml main.sml in structure Shapes = Area end shapes.sml",
+        ),
+        ("shapes.sml", "val _ = Shapes.area"),
+        ("total.sml", "val _ = total"),
+        ("total.mlb", "alias.mlb total.sml"),
+        ("missing.mlb", "structure A = Missing"),
+        ("bad.mlb", "local lib.sml"),
+        (
+            "queue.cm",
+            "Library structure Queue is $/basis.cm use.sml queue.sml",
+        ),
+        ("queue.sml", "structure Queue = struct val empty = [] end"),
+        ("use.sml", "val _ = Queue.empty"),
+    ];
+    for (name, src) in files {
+        fs::write(dir.join(name), src).unwrap();
+    }
+
+    let check = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.push("--check");
+        smolc(&dir, &args)
+    };
+    for args in [
+        &["--path-var", "SRC=.", "main.mlb"][..],
+        &["alias.mlb"],
+        &["queue.cm"],
+    ] {
+        let out = check(args);
+        assert!(out.status.success(), "{:?}: {}", args, stderr(&out));
+    }
+
+    for (file, error) in [
+        // `++` isn't infix outside the `local`.
+        ("hidden.mlb", "main.sml:"),
+        ("total.mlb", "unbound variable or constructor `total`"),
+        ("missing.mlb", "unbound structure `Missing`"),
+        ("bad.mlb", "expected `in`, found the end of the file"),
+        ("main.mlb", "unknown path variable `SRC`"),
+    ] {
+        let out = check(&[file]);
+        assert_eq!(out.status.code(), Some(1), "{}", file);
+        assert!(stderr(&out).contains(error), "{}: {}", file, stderr(&out));
+    }
+
+    let out = smolc(&dir, &["--dump-types", "--path-var", "SRC=.", "main.mlb"]);
+    let types = stdout(&out);
+    assert!(types.contains("val ++ : int * int -> int\n"), "{}", types);
+    assert!(types.contains("val total : int\n"), "{}", types);
+    let out = smolc(&dir, &["--dump-ast", "--path-var", "SRC=.", "main.mlb"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = smolc(&dir, &["--dump-ir", "--path-var", "SRC=.", "main.mlb"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).starts_with("main "));
+}
+
+#[test]
+fn stages() {
+    let dir = dir("stages");
+    let files = ["lib.sml", "main.sml"];
+    let dump = |flag: &str| {
+        let mut args = vec![flag];
+        args.extend(files);
+        let out = smolc(&dir, &args);
+        assert!(out.status.success(), "{}: {}", flag, stderr(&out));
+        stdout(&out)
+    };
+
+    let tree = dump("--dump-parse-tree");
+    assert!(tree.starts_with("program "), "{}", tree);
+    assert!(tree.contains("fixity_dec"), "{}", tree);
+    assert!(tree.contains("structure_dec"), "{}", tree);
+
+    let ast = dump("--dump-ast");
+    assert!(
+        ast.starts_with("infix 6 ++\nfun ++ (a, b) = + (a, b)\n"),
+        "{}",
+        ast
+    );
+    assert!(ast.contains("structure Area =\n  struct\n"), "{}", ast);
+    assert!(
+        ast.contains("val total = ++ (Area.area (Circle 2), Area.area (Square 3))\n"),
+        "{}",
+        ast
+    );
+
+    let types = dump("--dump-types");
+    for line in [
+        "val ++ : int * int -> int",
+        "con Circle : int -> shape",
+        "exception Negative : int -> exn",
+        "structure Area",
+        "val total : int",
+    ] {
+        assert!(types.contains(&format!("{}\n", line)), "{}", types);
+    }
+
+    let ir = dump("--dump-ir");
+    assert!(ir.starts_with("main "), "{}", ir);
+    assert!(dump("--emit-llvm").contains("define i32 @main("));
+    assert!(dump("--emit-c").contains("int main(int argc, char **argv)"));
+
+    // Output can go to a file instead.
+    let out = smolc(
+        &dir,
+        &["--emit-c", "-O0", "-o", "main.c", "lib.sml", "main.sml"],
+    );
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "");
+    assert!(fs::read_to_string(dir.join("main.c"))
+        .unwrap()
+        .contains("smol_main"));
+}
+
+/// Build the runtime as a static library, in a target directory of its own
+/// so as not to wait on the one running the tests.
+fn runtime(dir: &Path) -> PathBuf {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let target = dir.join("target");
+    let status = Command::new(cargo)
+        .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(".."))
+        .args(["build", "--release", "-p", "smol-runtime", "--target-dir"])
+        .arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    target.join("release/libsmol_runtime.a")
+}
+
+#[test]
+fn executables() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("skipping: cc isn't installed");
+        return;
+    }
+    let dir = dir("executables");
+    let runtime = runtime(&dir);
+    let runtime = runtime.to_str().unwrap();
+    for args in [
+        &["lib.sml", "main.sml"][..],
+        &["--specialise", "-O0", "lib.sml", "main.sml"],
+        &["--linked-closures", "--no-lift", "lib.sml", "main.sml"],
+        &["--path-var", "SRC=.", "main.mlb"],
+    ] {
+        let mut args = args.to_vec();
+        args.extend(["--runtime", runtime, "-o", "area"]);
+        let out = smolc(&dir, &args);
+        assert!(out.status.success(), "{:?}: {}", args, stderr(&out));
+
+        let out = Command::new(dir.join("area")).output().unwrap();
+        assert_eq!(out.status.code(), Some(1));
+        assert_eq!(stdout(&out), "21\n");
+        assert!(stderr(&out).contains("uncaught exception Negative"));
+    }
+}
+
+#[test]
+fn bytecode() {
+    let dir = dir("bytecode");
+    let cache = dir.join("cache");
+    let _ = fs::remove_dir_all(&cache);
+    let check = |out: Output| {
+        assert_eq!(out.status.code(), Some(1), "{}", stderr(&out));
+        assert_eq!(stdout(&out), "21\n");
+        assert!(stderr(&out).contains("uncaught exception Negative"));
+    };
+
+    let out = smolc(&dir, &["--emit-bytecode", "lib.sml", "main.sml"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(dir.join("main.smbc").exists());
+    check(smolc(&dir, &["--run", "main.smbc"]));
+    check(smolc(&dir, &["--run", "--path-var", "SRC=.", "main.mlb"]));
+
+    // `smol --run` compiles the script once, and then runs it from the
+    // cache.
+    let smol = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_smol"))
+            .current_dir(&dir)
+            .env("SMOL_CACHE", &cache)
+            .arg("--run")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    check(smol(&["lib.sml", "main.sml"]));
+    assert_eq!(fs::read_dir(&cache).unwrap().count(), 1);
+    check(smol(&["lib.sml", "main.sml"]));
+    assert_eq!(fs::read_dir(&cache).unwrap().count(), 1);
+    check(smol(&["main.smbc"]));
+
+    let out = smol(&["main.sml"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("main.sml:"), "{}", stderr(&out));
+}